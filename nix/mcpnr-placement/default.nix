@@ -14,7 +14,8 @@ mcpnr-rust-platform.buildRustPackage rec {
   pname = "mcpnr-placement";
   version = "0.1.0";
   src = ../../pnr;
-  cargoSha256 = "sha256-odoJDJHNLi7vcOkDvaUhPh/wWAaFsDcPy5b6PKtwS9s=";
+  # Vendor straight from the lock file, so there is no dependency hash to keep in sync with it
+  cargoLock.lockFile = ../../pnr/Cargo.lock;
   doCheck = false;
 
   cargoBuildFlags = [ "-p" pname ];
@@ -0,0 +1,84 @@
+//! Benchmarks of writing and reading [`BlockStorage`] in each of the output formats.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use mcpnr_common::anvil::DEFAULT_DATA_VERSION;
+use mcpnr_common::block_storage::stream::{read_rle_json, write_rle_json};
+use mcpnr_common::block_storage::{Block, BlockStorage};
+use mcpnr_common::schematic::write_schematic;
+
+const SIZES: [u32; 3] = [64, 128, 256];
+const HEIGHT: u32 = 32;
+
+/// A `size` x [`HEIGHT`] x `size` storage with a mix of short and long runs of a handful of block
+/// types, loosely like a routed design: the lower half of every 4 layers holds diagonal stripes of
+/// wire and the rest is air.
+fn storage(size: u32) -> BlockStorage {
+    let mut storage = BlockStorage::new(size, HEIGHT, size);
+    let palette: Vec<_> = [
+        "minecraft:redstone_wire",
+        "minecraft:white_concrete",
+        "minecraft:glass",
+        "minecraft:repeater",
+        "minecraft:target",
+    ]
+    .into_iter()
+    .map(|name| storage.add_new_block_type(Block::new(name.into())))
+    .collect();
+
+    for y in (0..HEIGHT).filter(|y| y % 4 < 2) {
+        for z in 0..size {
+            for x in 0..size {
+                let stripe = (x / 3 + z / 5 + y) as usize % (palette.len() + 1);
+                if let Some(block) = palette.get(stripe) {
+                    *storage.get_block_mut(x, y, z).unwrap() = *block;
+                }
+            }
+        }
+    }
+
+    storage
+}
+
+fn serialization(c: &mut Criterion) {
+    let mut group = c.benchmark_group("block_storage");
+    group.sample_size(20);
+    for size in SIZES {
+        let storage = storage(size);
+        let mut rle = Vec::new();
+        write_rle_json(&storage, &mut rle).unwrap();
+
+        group.throughput(Throughput::Elements((size * HEIGHT * size) as u64));
+        group.bench_with_input(BenchmarkId::new("serde_json", size), &storage, |b, s| {
+            b.iter(|| serde_json::to_vec(s).unwrap())
+        });
+        group.bench_with_input(
+            BenchmarkId::new("write_rle_json", size),
+            &storage,
+            |b, s| {
+                b.iter(|| {
+                    let mut out = Vec::with_capacity(rle.len());
+                    write_rle_json(s, &mut out).unwrap();
+                    out
+                })
+            },
+        );
+        group.bench_with_input(BenchmarkId::new("read_rle_json", size), &rle, |b, r| {
+            b.iter(|| read_rle_json(r.as_slice()).unwrap())
+        });
+        group.bench_with_input(
+            BenchmarkId::new("write_schematic", size),
+            &storage,
+            |b, s| {
+                b.iter(|| {
+                    let mut out = Vec::new();
+                    write_schematic(s, &mut out, DEFAULT_DATA_VERSION).unwrap();
+                    out
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, serialization);
+criterion_main!(benches);
@@ -8,6 +8,15 @@ fn main() -> Result<(), std::io::Error> {
     prost_build::Config::new()
         .include_file("protos.rs")
         .file_descriptor_set_path(out_dir.join("file_descriptor_set.protobuf"))
+        // Placed designs can also be written as JSON for debugging
+        .type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]")
+        // JSON designs written before the version was recorded are version 0, and may be from
+        // before cell names were kept too
+        .field_attribute(".mcpnr.PlacedDesign.version", "#[serde(default)]")
+        .field_attribute(".mcpnr.PlacedDesign.Cell.name", "#[serde(default)]")
+        .field_attribute(".mcpnr.PlacedDesign.provenance", "#[serde(default)]")
+        .field_attribute(".mcpnr.PlacedDesignHeader.provenance", "#[serde(default)]")
+        .field_attribute(".mcpnr.PlacedDesignVersion.version", "#[serde(default)]")
         .compile_protos(&proto_files, &[PathBuf::from("./src/protos/")])?;
     Ok(())
 }
@@ -0,0 +1,105 @@
+//! Write a synthetic design to a file, as a Yosys design for the placer or a placed design for the
+//! router. See [`mcpnr_common::synthetic`].
+//!
+//! ```sh
+//! cargo run -p mcpnr-common --example synthetic_design -- cloud --cells 5000 cloud.yosys-design
+//! cargo run -p mcpnr-common --example synthetic_design -- adder --bits 16 --placed-row-width 128 adder.mcpnr-placement
+//! ```
+
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context, Result};
+use clap::{Args, Parser, Subcommand};
+use mcpnr_common::prost::Message;
+use mcpnr_common::synthetic::{place_in_rows, SyntheticDesign};
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "synthetic_design",
+    about = "Generate a synthetic design for testing and benchmarking the placer and router"
+)]
+struct Cli {
+    #[clap(subcommand)]
+    kind: Kind,
+}
+
+#[derive(Debug, Subcommand)]
+enum Kind {
+    /// Random gates and registers
+    Cloud {
+        /// Number of cells, not counting the I/O macros
+        #[clap(long)]
+        cells: usize,
+        /// Rent exponent, from 0 for very local nets to 1 for completely random ones
+        #[clap(long, default_value = "0.6")]
+        rent_exponent: f64,
+        #[clap(flatten)]
+        output: OutputArgs,
+    },
+    /// A ripple carry adder
+    Adder {
+        /// Width of each input
+        #[clap(long)]
+        bits: usize,
+        #[clap(flatten)]
+        output: OutputArgs,
+    },
+    /// A chain of registers on one clock
+    ShiftRegister {
+        /// Number of registers
+        #[clap(long)]
+        bits: usize,
+        #[clap(flatten)]
+        output: OutputArgs,
+    },
+}
+
+#[derive(Debug, Args)]
+struct OutputArgs {
+    /// Seed for the random parts of the design
+    #[clap(long, default_value = "0")]
+    seed: u64,
+
+    /// Write a placed design for the router instead of a Yosys design, with the cells lined up in
+    /// rows this many blocks wide
+    #[clap(long, value_name = "BLOCKS")]
+    placed_row_width: Option<u32>,
+
+    /// Output file
+    #[clap(value_name = "OUTPUT")]
+    output: PathBuf,
+}
+
+fn main() -> Result<()> {
+    let (design, args) = match Cli::parse().kind {
+        Kind::Cloud {
+            cells,
+            rent_exponent,
+            output,
+        } => (
+            SyntheticDesign::LogicCloud {
+                cells,
+                rent_exponent,
+            },
+            output,
+        ),
+        Kind::Adder { bits, output } => (SyntheticDesign::AdderChain { bits }, output),
+        Kind::ShiftRegister { bits, output } => (SyntheticDesign::ShiftRegister { bits }, output),
+    };
+
+    let design = design.generate(args.seed).context("Generate design")?;
+    let writer = std::fs::File::create(&args.output)
+        .with_context(|| anyhow!("Open output file {:?}", args.output))?;
+    let mut writer = std::io::BufWriter::new(writer);
+    match args.placed_row_width {
+        Some(row_width) => {
+            let placed = place_in_rows(&design, row_width)?;
+            std::io::Write::write_all(&mut writer, &placed.encode_to_vec())
+                .with_context(|| anyhow!("Write {:?}", args.output))?;
+        }
+        None => serde_json::to_writer(&mut writer, &design)
+            .with_context(|| anyhow!("Write {:?}", args.output))?,
+    }
+
+    Ok(())
+}
@@ -0,0 +1,58 @@
+/*
+ * C ABI of mcpnr-common, for loading Yosys designs and reading and writing placed designs from
+ * tools outside of Rust. Link against libmcpnr_common (built by `cargo build -p mcpnr-common`
+ * as both a shared and a static library).
+ *
+ * Cells are referred to by index, in the order of their instance names. Functions returning int
+ * return 0 on success and -1 on failure, and functions returning a pointer return NULL on failure.
+ * mcpnr_last_error describes the last failure on the calling thread. Strings returned are owned by
+ * the handle they came from, and live as long as it does.
+ */
+#ifndef MCPNR_H
+#define MCPNR_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Flattened module of a synthesized design */
+typedef struct McpnrDesign McpnrDesign;
+/* Placed design */
+typedef struct McpnrPlacement McpnrPlacement;
+
+/* Description of the last failure on this thread, or NULL if nothing has failed */
+const char *mcpnr_last_error(void);
+
+/* Load the Yosys JSON design at path and flatten module, or the "top" module if module is NULL */
+McpnrDesign *mcpnr_design_load(const char *path, const char *module);
+void mcpnr_design_free(McpnrDesign *design);
+size_t mcpnr_design_cell_count(const McpnrDesign *design);
+const char *mcpnr_design_cell_name(const McpnrDesign *design, size_t idx);
+const char *mcpnr_design_cell_type(const McpnrDesign *design, size_t idx);
+
+/* Start a placement of the design, with the same cells in the same order and none of them placed */
+McpnrPlacement *mcpnr_placement_new(const McpnrDesign *design);
+/* Load a placed design, as JSON if path ends in .json, in the streaming format if it ends in .pbs
+ * or .jsonl and as protobuf otherwise */
+McpnrPlacement *mcpnr_placement_load(const char *path);
+void mcpnr_placement_free(McpnrPlacement *placement);
+size_t mcpnr_placement_cell_count(const McpnrPlacement *placement);
+const char *mcpnr_placement_cell_name(const McpnrPlacement *placement, size_t idx);
+const char *mcpnr_placement_cell_type(const McpnrPlacement *placement, size_t idx);
+/* Minimum corner of a placed cell, in blocks */
+int mcpnr_placement_cell_position(const McpnrPlacement *placement, size_t idx, uint32_t *x,
+                                  uint32_t *y, uint32_t *z);
+int mcpnr_placement_set_position(McpnrPlacement *placement, size_t idx, uint32_t x, uint32_t y,
+                                 uint32_t z);
+/* Write the placement, in the format mcpnr_placement_load reads from path. Fails if any cell
+ * hasn't been placed. */
+int mcpnr_placement_write(const McpnrPlacement *placement, const char *path);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* MCPNR_H */
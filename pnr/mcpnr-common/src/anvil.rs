@@ -0,0 +1,439 @@
+//! Export of a [`BlockStorage`] straight in to the region files of a Minecraft world save, in the
+//! anvil format used since 1.18.
+//!
+//! Each chunk the design touches is replaced completely, so anything already in those chunks is
+//! lost. Every other chunk in the affected region files is kept as it was. The game relights the
+//! written chunks when it loads them.
+
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, ensure, Context, Result};
+use log::info;
+use quartz_nbt::{io::Flavor, NbtCompound, NbtList, NbtTag};
+
+use crate::block_storage::{Block, BlockStorage, BlockTypeIndex, Position, PropertyValue};
+use crate::minecraft_types::json_to_nbt;
+
+/// Lowest Y coordinate blocks can be placed at in the overworld
+pub const WORLD_MIN_Y: i32 = -64;
+
+/// One past the highest Y coordinate blocks can be placed at in the overworld
+pub const WORLD_MAX_Y: i32 = 320;
+
+/// Data version to tag chunks with if the caller doesn't know better (1.18.2)
+pub const DEFAULT_DATA_VERSION: i32 = 2975;
+
+const SECTOR_SIZE: usize = 4096;
+const CHUNKS_PER_REGION: i32 = 32;
+const CHUNK_SIZE: i32 = 16;
+const BLOCKS_PER_SECTION: usize = 16 * 16 * 16;
+const COMPRESSION_ZLIB: u8 = 2;
+
+/// Sections of a chunk, by section Y. `None` is air.
+type ChunkSections = BTreeMap<i32, Vec<Option<BlockTypeIndex>>>;
+
+/// Compressed chunks to write in to a region file, by index in the region
+type RegionChunks = Vec<(usize, Vec<u8>)>;
+
+/// What an export wrote
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ExportStats {
+    pub chunks: usize,
+    pub regions: usize,
+}
+
+/// Write `storage` in to the world save at `world`, with its origin at `offset` in world
+/// coordinates. `data_version` should match the Minecraft version the world is opened with.
+pub fn export_world(
+    storage: &BlockStorage,
+    world: &Path,
+    offset: Position,
+    data_version: i32,
+) -> Result<ExportStats> {
+    ensure!(
+        world.is_dir(),
+        "World save {} is not a directory",
+        world.display()
+    );
+    let sy = storage.extents()[1];
+    ensure!(
+        offset.y >= WORLD_MIN_Y && offset.y + sy as i32 <= WORLD_MAX_Y,
+        "Design spans Y {} to {}, outside the world's build height of {} to {}",
+        offset.y,
+        offset.y + sy as i32,
+        WORLD_MIN_Y,
+        WORLD_MAX_Y
+    );
+
+    let mut chunks: BTreeMap<(i32, i32), ChunkSections> = BTreeMap::new();
+    for ((x, y, z), block) in storage.iter_block_coords() {
+        let (wx, wy, wz) = (
+            offset.x + x as i32,
+            offset.y + y as i32,
+            offset.z + z as i32,
+        );
+        let sections = chunks
+            .entry((wx.div_euclid(CHUNK_SIZE), wz.div_euclid(CHUNK_SIZE)))
+            .or_default();
+        let section = sections
+            .entry(wy.div_euclid(CHUNK_SIZE))
+            .or_insert_with(|| vec![None; BLOCKS_PER_SECTION]);
+        // All air is the same to the game, whether or not it came from the design
+        let is_air = !matches!(
+            storage.info_for_index(block),
+            Some(b) if b.name != "minecraft:air" || b.properties.is_some()
+        );
+        section[section_index(wx, wy, wz)] = (!is_air).then_some(block);
+    }
+
+    let mut block_entities: BTreeMap<(i32, i32), Vec<NbtTag>> = BTreeMap::new();
+    for ([x, y, z], nbt) in storage.iter_block_entities() {
+        let (wx, wy, wz) = (
+            offset.x + *x as i32,
+            offset.y + *y as i32,
+            offset.z + *z as i32,
+        );
+        let mut entity = match json_to_nbt(nbt)? {
+            NbtTag::Compound(entity) => entity,
+            _ => {
+                return Err(anyhow!(
+                    "Block entity at {}, {}, {} is not an object",
+                    x,
+                    y,
+                    z
+                ))
+            }
+        };
+        entity.insert("x", NbtTag::Int(wx));
+        entity.insert("y", NbtTag::Int(wy));
+        entity.insert("z", NbtTag::Int(wz));
+        entity.insert("keepPacked", NbtTag::Byte(0));
+        block_entities
+            .entry((wx.div_euclid(CHUNK_SIZE), wz.div_euclid(CHUNK_SIZE)))
+            .or_default()
+            .push(NbtTag::Compound(entity));
+    }
+
+    let mut regions: BTreeMap<(i32, i32), RegionChunks> = BTreeMap::new();
+    for ((cx, cz), sections) in chunks.iter() {
+        let entities = block_entities.remove(&(*cx, *cz)).unwrap_or_default();
+        let chunk = chunk_nbt(storage, *cx, *cz, sections, entities, data_version)
+            .with_context(|| anyhow!("Build chunk {}, {}", cx, cz))?;
+        let mut data = Vec::new();
+        quartz_nbt::io::write_nbt(&mut data, None, &chunk, Flavor::ZlibCompressed)
+            .with_context(|| anyhow!("Compress chunk {}, {}", cx, cz))?;
+        regions
+            .entry((
+                cx.div_euclid(CHUNKS_PER_REGION),
+                cz.div_euclid(CHUNKS_PER_REGION),
+            ))
+            .or_default()
+            .push((region_index(*cx, *cz), data));
+    }
+
+    let region_directory = world.join("region");
+    std::fs::create_dir_all(&region_directory)
+        .with_context(|| anyhow!("Create {}", region_directory.display()))?;
+    for ((rx, rz), chunks) in regions.iter() {
+        let path = region_directory.join(format!("r.{}.{}.mca", rx, rz));
+        write_region(&path, chunks).with_context(|| anyhow!("Write {}", path.display()))?;
+    }
+
+    let stats = ExportStats {
+        chunks: chunks.len(),
+        regions: regions.len(),
+    };
+    info!(
+        "Exported {} chunks in {} region files to {}",
+        stats.chunks,
+        stats.regions,
+        world.display()
+    );
+    Ok(stats)
+}
+
+/// Index of a block within its section, in the order the game packs them
+fn section_index(x: i32, y: i32, z: i32) -> usize {
+    (y.rem_euclid(CHUNK_SIZE) * 256 + z.rem_euclid(CHUNK_SIZE) * 16 + x.rem_euclid(CHUNK_SIZE))
+        as usize
+}
+
+/// Index of a chunk's entry in the header of its region file
+fn region_index(cx: i32, cz: i32) -> usize {
+    (cx.rem_euclid(CHUNKS_PER_REGION) + cz.rem_euclid(CHUNKS_PER_REGION) * CHUNKS_PER_REGION)
+        as usize
+}
+
+fn chunk_nbt(
+    storage: &BlockStorage,
+    cx: i32,
+    cz: i32,
+    sections: &ChunkSections,
+    block_entities: Vec<NbtTag>,
+    data_version: i32,
+) -> Result<NbtCompound> {
+    let mut section_list = Vec::with_capacity(sections.len());
+    for (y, blocks) in sections.iter() {
+        let mut section = NbtCompound::new();
+        section.insert("Y", NbtTag::Byte(*y as i8));
+        section.insert("block_states", block_states(storage, blocks)?);
+
+        let mut biomes = NbtCompound::new();
+        biomes.insert(
+            "palette",
+            NbtList::from(vec![NbtTag::String("minecraft:plains".into())]),
+        );
+        section.insert("biomes", biomes);
+
+        section_list.push(NbtTag::Compound(section));
+    }
+
+    let mut chunk = NbtCompound::new();
+    chunk.insert("DataVersion", NbtTag::Int(data_version));
+    chunk.insert("xPos", NbtTag::Int(cx));
+    chunk.insert("zPos", NbtTag::Int(cz));
+    chunk.insert("yPos", NbtTag::Int(WORLD_MIN_Y / CHUNK_SIZE));
+    chunk.insert("Status", NbtTag::String("full".into()));
+    chunk.insert("LastUpdate", NbtTag::Long(0));
+    chunk.insert("InhabitedTime", NbtTag::Long(0));
+    chunk.insert("isLightOn", NbtTag::Byte(0));
+    chunk.insert("sections", NbtList::from(section_list));
+    chunk.insert("block_entities", NbtList::from(block_entities));
+    Ok(chunk)
+}
+
+/// Palette and packed block indices for one section
+fn block_states(storage: &BlockStorage, blocks: &[Option<BlockTypeIndex>]) -> Result<NbtCompound> {
+    let mut palette: Vec<Option<BlockTypeIndex>> = Vec::new();
+    let indices: Vec<u64> = blocks
+        .iter()
+        .map(|block| match palette.iter().position(|b| b == block) {
+            Some(idx) => idx as u64,
+            None => {
+                palette.push(*block);
+                (palette.len() - 1) as u64
+            }
+        })
+        .collect();
+
+    let mut palette_list = Vec::with_capacity(palette.len());
+    for block in palette.iter() {
+        let block = match block {
+            Some(idx) => storage
+                .info_for_index(*idx)
+                .ok_or_else(|| anyhow!("Block index {:?} is not in the palette", idx))?
+                .clone(),
+            None => Block::new("minecraft:air".into()),
+        };
+        palette_list.push(NbtTag::Compound(palette_entry(&block)));
+    }
+
+    let mut states = NbtCompound::new();
+    states.insert("palette", NbtList::from(palette_list));
+    // A section with only one kind of block doesn't need any data
+    if palette.len() > 1 {
+        states.insert(
+            "data",
+            NbtTag::LongArray(pack_indices(&indices, palette.len())),
+        );
+    }
+    Ok(states)
+}
+
+fn palette_entry(block: &Block) -> NbtCompound {
+    let mut entry = NbtCompound::new();
+    entry.insert("Name", NbtTag::String(block.name.clone()));
+    if let Some(ref properties) = block.properties {
+        let mut compound = NbtCompound::new();
+        for (name, value) in properties.iter() {
+            // Block states are always strings in chunks
+            let value = match value {
+                PropertyValue::String(s) => s.clone(),
+                PropertyValue::Byte(b) => b.to_string(),
+            };
+            compound.insert(name.clone(), NbtTag::String(value));
+        }
+        entry.insert("Properties", compound);
+    }
+    entry
+}
+
+/// Pack palette indices in to longs the way the game does since 1.16: at least 4 bits each, with
+/// no index split across two longs.
+fn pack_indices(indices: &[u64], palette_len: usize) -> Vec<i64> {
+    let bits = std::cmp::max(4, usize::BITS - (palette_len - 1).leading_zeros()) as usize;
+    let per_long = 64 / bits;
+    indices
+        .chunks(per_long)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .fold(0u64, |packed, (i, idx)| packed | (idx << (i * bits))) as i64
+        })
+        .collect()
+}
+
+/// Write compressed chunks in to a region file, keeping any chunks already there that aren't
+/// being replaced.
+fn write_region(path: &Path, chunks: &RegionChunks) -> Result<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as u32);
+
+    // Length prefixed chunk payloads and timestamps by index in the region
+    let mut payloads: Vec<Option<(Vec<u8>, u32)>> = vec![None; 1024];
+    if path.exists() {
+        let existing = std::fs::read(path).context("Read existing region")?;
+        ensure!(
+            existing.len() >= 2 * SECTOR_SIZE,
+            "Existing region file is too short"
+        );
+        for (idx, payload) in payloads.iter_mut().enumerate() {
+            let location = u32::from_be_bytes(existing[idx * 4..idx * 4 + 4].try_into()?);
+            let (sector, count) = ((location >> 8) as usize, (location & 0xff) as usize);
+            if sector == 0 || count == 0 {
+                continue;
+            }
+            let start = sector * SECTOR_SIZE;
+            ensure!(
+                start + 4 <= existing.len(),
+                "Chunk {} is past the end of the existing region file",
+                idx
+            );
+            let length = u32::from_be_bytes(existing[start..start + 4].try_into()?) as usize;
+            ensure!(
+                start + 4 + length <= existing.len(),
+                "Chunk {} is truncated in the existing region file",
+                idx
+            );
+            let timestamp_at = SECTOR_SIZE + idx * 4;
+            let timestamp =
+                u32::from_be_bytes(existing[timestamp_at..timestamp_at + 4].try_into()?);
+            *payload = Some((existing[start..start + 4 + length].to_vec(), timestamp));
+        }
+    }
+
+    for (idx, data) in chunks.iter() {
+        let mut payload = Vec::with_capacity(data.len() + 5);
+        payload.extend_from_slice(&(data.len() as u32 + 1).to_be_bytes());
+        payload.push(COMPRESSION_ZLIB);
+        payload.extend_from_slice(data);
+        payloads[*idx] = Some((payload, now));
+    }
+
+    let mut header = vec![0u8; 2 * SECTOR_SIZE];
+    let mut body = Vec::new();
+    for (idx, payload) in payloads.iter().enumerate() {
+        let (payload, timestamp) = match payload {
+            Some(payload) => payload,
+            None => continue,
+        };
+        let sectors = payload.len().div_ceil(SECTOR_SIZE);
+        ensure!(
+            sectors < 256,
+            "Chunk {} is too big for a region file ({} bytes)",
+            idx,
+            payload.len()
+        );
+        let sector = 2 + body.len() / SECTOR_SIZE;
+        let location = ((sector as u32) << 8) | sectors as u32;
+        header[idx * 4..idx * 4 + 4].copy_from_slice(&location.to_be_bytes());
+        header[SECTOR_SIZE + idx * 4..SECTOR_SIZE + idx * 4 + 4]
+            .copy_from_slice(&timestamp.to_be_bytes());
+        body.extend_from_slice(payload);
+        body.resize(sectors * SECTOR_SIZE + (sector - 2) * SECTOR_SIZE, 0);
+    }
+
+    let mut outf = std::fs::File::create(path).context("Create region file")?;
+    outf.write_all(&header)?;
+    outf.write_all(&body)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use quartz_nbt::{io::Flavor, NbtCompound, NbtList};
+
+    use super::{export_world, pack_indices, SECTOR_SIZE};
+    use crate::block_storage::{Block, BlockStorage, Position};
+
+    #[test]
+    fn packs_indices_without_splitting_them() {
+        let packed = pack_indices(&[1, 2, 3], 2);
+        assert_eq!(packed, vec![0x321]);
+
+        // 5 bits each leaves 4 spare bits at the top of each long
+        let indices: Vec<u64> = (0..13).map(|i| i % 17).collect();
+        let packed = pack_indices(&indices, 17);
+        assert_eq!(packed.len(), 2);
+        assert_eq!((packed[0] as u64) >> 55, 11);
+        assert_eq!(packed[1], 12);
+    }
+
+    #[test]
+    fn exports_chunks_in_to_regions() -> anyhow::Result<()> {
+        let world = std::env::temp_dir().join(format!("mcpnr-anvil-test-{}", std::process::id()));
+        std::fs::create_dir_all(world.join("region"))?;
+
+        // A chunk that's already in the world, which the export should leave alone
+        let mut existing = vec![0u8; 3 * SECTOR_SIZE];
+        existing[5 * 4..5 * 4 + 4].copy_from_slice(&((2u32 << 8) | 1).to_be_bytes());
+        existing[2 * SECTOR_SIZE..2 * SECTOR_SIZE + 8].copy_from_slice(&[0, 0, 0, 4, 2, 1, 2, 3]);
+        std::fs::write(world.join("region/r.0.0.mca"), &existing)?;
+
+        let mut storage = BlockStorage::new(17, 2, 1);
+        let stone = storage.add_new_block_type(Block::new("minecraft:stone".into()));
+        *storage.get_block_mut(16, 1, 0)? = stone;
+        storage.set_block_entity(16, 1, 0, serde_json::json!({ "id": "minecraft:sign" }))?;
+
+        // Straddles the chunks at X -1 and 0, and the sections at Y -1 and 0
+        let stats = export_world(&storage, &world, Position::new(-1, -1, 0), 2975)?;
+        assert_eq!((stats.chunks, stats.regions), (2, 2));
+
+        let region = std::fs::read(world.join("region/r.0.0.mca"))?;
+        let location =
+            |idx: usize| u32::from_be_bytes(region[idx * 4..idx * 4 + 4].try_into().unwrap());
+        assert_eq!(location(0), (2 << 8) | 1);
+        assert_eq!(location(5), (3 << 8) | 1);
+        assert_eq!(
+            &region[3 * SECTOR_SIZE..3 * SECTOR_SIZE + 8],
+            &[0, 0, 0, 4, 2, 1, 2, 3]
+        );
+
+        let start = 2 * SECTOR_SIZE;
+        let length = u32::from_be_bytes(region[start..start + 4].try_into()?) as usize;
+        assert_eq!(region[start + 4], 2);
+        let (chunk, _) = quartz_nbt::io::read_nbt(
+            &mut &region[start + 5..start + 4 + length],
+            Flavor::ZlibCompressed,
+        )?;
+
+        let sections: &NbtList = chunk.get("sections")?;
+        assert_eq!(sections.len(), 2);
+        let section: &NbtCompound = sections.get(1)?;
+        assert_eq!(section.get::<_, i8>("Y")?, 0);
+        let states: &NbtCompound = section.get("block_states")?;
+        let palette: &NbtList = states.get("palette")?;
+        let names = (0..palette.len())
+            .map(|idx| palette.get::<&NbtCompound>(idx)?.get::<_, &str>("Name"))
+            .collect::<Result<Vec<_>, _>>()?;
+        // Everything but the stone block at world X 15, Y 0 is air
+        assert_eq!(names, vec!["minecraft:air", "minecraft:stone"]);
+        let data: &[i64] = states.get("data")?;
+        assert_eq!(data[0], 1 << 60);
+
+        let entities: &NbtList = chunk.get("block_entities")?;
+        let entity: &NbtCompound = entities.get(0)?;
+        assert_eq!(entity.get::<_, i32>("x")?, 15);
+        assert_eq!(entity.get::<_, &str>("id")?, "minecraft:sign");
+
+        std::fs::remove_dir_all(&world)?;
+        Ok(())
+    }
+}
@@ -20,6 +20,32 @@ impl<'a> Iterator for BlockIndexIter<'a> {
     }
 }
 
+/// Iterates over runs of identical blocks in storage order, as `(block, run length)` pairs.
+pub struct BlockRunIter<'a> {
+    inner: std::iter::Peekable<std::slice::Iter<'a, u32>>,
+}
+
+impl<'a> BlockRunIter<'a> {
+    pub(super) fn new(parent: &'a BlockStorage) -> Self {
+        Self {
+            inner: parent.blocks.iter().peekable(),
+        }
+    }
+}
+
+impl<'a> Iterator for BlockRunIter<'a> {
+    type Item = (BlockTypeIndex, u32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let first = *self.inner.next()?;
+        let mut length = 1;
+        while self.inner.next_if(|&&v| v == first).is_some() {
+            length += 1;
+        }
+        Some((BlockTypeIndex(first), length))
+    }
+}
+
 pub struct BlockCoordIter<'a> {
     parent: &'a BlockStorage,
     inner: std::iter::Enumerate<std::slice::Iter<'a, u32>>,
@@ -3,20 +3,25 @@
 
 pub mod iter;
 mod serialization;
+pub mod stream;
 
 use anyhow::{anyhow, Result};
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Display;
 use std::vec::Vec;
 
+use crate::provenance::Provenance;
+
 // Should go down
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
 pub enum PropertyValue {
     String(String),
     Byte(i8),
 }
 
-#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
 pub struct Block {
     pub name: String,
     pub properties: Option<HashMap<String, PropertyValue>>,
@@ -49,7 +54,7 @@ impl Block {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct Position {
     pub x: i32,
     pub y: i32,
@@ -87,7 +92,8 @@ impl Position {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Direction {
     /// Z-
     North,
@@ -147,19 +153,45 @@ pub struct BlockStorage {
 
     pub(self) palette: Vec<Block>,
 
-    /// Only indexes into the palette for now, if tile entity support is
-    /// required then some sort of overlay for that will need to be added.
+    /// Only indexes into the palette, block entities live in `block_entities`.
     ///
     /// Stored in x - z - y order
     pub(self) blocks: Vec<u32>,
+
+    /// Block entity data (like the text on a sign) for the few blocks that have any, by position.
+    pub(self) block_entities: BTreeMap<[u32; 3], serde_json::Value>,
+
+    /// Every tool that had a hand in the blocks, in the order they ran
+    pub(self) provenance: Vec<Provenance>,
+
+    /// Blocks handed out by [`Self::get_block_mut`] or given new block entities since
+    /// [`Self::start_journal`], if a journal is being kept
+    pub(self) journal: Option<Vec<BlockChange>>,
+}
+
+/// A block as it was before being changed through [`BlockStorage::get_block_mut`], or before its
+/// block entity was set or removed, while a journal was kept
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockChange {
+    pub pos: [u32; 3],
+    pub previous: BlockTypeIndex,
+    /// Block entity data the block had, if any
+    pub previous_entity: Option<serde_json::Value>,
 }
 
 /// Represents a type index into the BlockStorage's palette.
 // Must be repr(transparent) as we transmut &'a mut u32 to &'a mut BlockTypeIndex.
 #[repr(transparent)]
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub struct BlockTypeIndex(u32);
 
+impl BlockTypeIndex {
+    /// Position of the block type in [`BlockStorage::palette`]
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
 impl BlockStorage {
     pub fn new(sx: u32, sy: u32, sz: u32) -> Self {
         let total_size = (sx * sy * sz) as usize;
@@ -179,9 +211,18 @@ impl BlockStorage {
                 properties: None,
             }],
             blocks,
+            block_entities: BTreeMap::new(),
+            provenance: Vec::new(),
+            journal: None,
         }
     }
 
+    /// Bytes [`Self::new`] allocates for the blocks of a storage this size. The palette and block
+    /// entities are small enough not to count.
+    pub fn allocation_size(sx: u32, sy: u32, sz: u32) -> u64 {
+        sx as u64 * sy as u64 * sz as u64 * std::mem::size_of::<u32>() as u64
+    }
+
     pub fn resize(&mut self, sx: u32, sy: u32, sz: u32) -> Result<()> {
         unimplemented!("Resizing BlockStorage {} {} {}", sx, sy, sz)
     }
@@ -190,6 +231,10 @@ impl BlockStorage {
         iter::BlockIndexIter::new(self)
     }
 
+    pub fn iter_block_runs(&self) -> iter::BlockRunIter<'_> {
+        iter::BlockRunIter::new(self)
+    }
+
     pub fn iter_block_coords(&self) -> iter::BlockCoordIter {
         iter::BlockCoordIter::new(self)
     }
@@ -212,10 +257,96 @@ impl BlockStorage {
         return BlockTypeIndex(iidx as u32);
     }
 
+    /// Attach block entity data, e.g. `{"id": "minecraft:sign", "Text1": ...}`, to the block at
+    /// a position. It's up to the caller to put a block there that the data makes sense for.
+    pub fn set_block_entity(
+        &mut self,
+        x: u32,
+        y: u32,
+        z: u32,
+        nbt: serde_json::Value,
+    ) -> Result<()> {
+        self.get_block(x, y, z)?;
+        self.journal_block(x, y, z);
+        self.block_entities.insert([x, y, z], nbt);
+        Ok(())
+    }
+
+    pub fn block_entity(&self, x: u32, y: u32, z: u32) -> Option<&serde_json::Value> {
+        self.block_entities.get(&[x, y, z])
+    }
+
+    /// Detach the block entity data from the block at a position, e.g. when the block it belonged
+    /// to is replaced, returning it if there was any.
+    pub fn remove_block_entity(&mut self, x: u32, y: u32, z: u32) -> Option<serde_json::Value> {
+        if self.block_entities.contains_key(&[x, y, z]) {
+            self.journal_block(x, y, z);
+        }
+        self.block_entities.remove(&[x, y, z])
+    }
+
+    /// Every block entity, ordered by position
+    pub fn iter_block_entities(&self) -> impl Iterator<Item = (&[u32; 3], &serde_json::Value)> {
+        self.block_entities.iter()
+    }
+
+    /// Every tool that had a hand in the blocks, in the order they ran
+    pub fn provenance(&self) -> &[Provenance] {
+        &self.provenance
+    }
+
+    pub fn set_provenance(&mut self, provenance: Vec<Provenance>) {
+        self.provenance = provenance;
+    }
+
+    /// Start recording every block handed out by [`Self::get_block_mut`], and every block entity
+    /// set or removed, so the changes can be undone with [`Self::undo`]. Blocks changed any other
+    /// way aren't recorded. Throws away any journal already being kept.
+    pub fn start_journal(&mut self) {
+        self.journal = Some(Vec::new());
+    }
+
+    /// Stop recording, returning the changes since [`Self::start_journal`], oldest first
+    pub fn finish_journal(&mut self) -> Vec<BlockChange> {
+        self.journal.take().unwrap_or_default()
+    }
+
+    /// Put back the blocks as they were before `changes`. Blocks changed again since then are
+    /// overwritten as well, so this is only exact for the newest changes to each block.
+    pub fn undo(&mut self, changes: &[BlockChange]) -> Result<()> {
+        for change in changes.iter().rev() {
+            let [x, y, z] = change.pos;
+            *self.get_block_mut(x, y, z)? = change.previous;
+            match change.previous_entity {
+                Some(ref nbt) => self.block_entities.insert(change.pos, nbt.clone()),
+                None => self.block_entities.remove(&change.pos),
+            };
+        }
+        Ok(())
+    }
+
+    /// Record the block at a position as it is now, if a journal is being kept. The position must
+    /// be in bounds.
+    fn journal_block(&mut self, x: u32, y: u32, z: u32) {
+        if let Some(ref mut journal) = self.journal {
+            let i = x + z * self.zsi + y * self.ysi;
+            journal.push(BlockChange {
+                pos: [x, y, z],
+                previous: BlockTypeIndex(self.blocks[i as usize]),
+                previous_entity: self.block_entities.get(&[x, y, z]).cloned(),
+            });
+        }
+    }
+
     pub fn extents(&self) -> &[u32; 3] {
         &self.extents
     }
 
+    /// Every block type in the storage, indexed by [`BlockTypeIndex::index`]
+    pub fn palette(&self) -> &[Block] {
+        &self.palette
+    }
+
     pub fn info_for_index(&self, index: BlockTypeIndex) -> Option<&Block> {
         self.palette.get(index.0 as usize)
     }
@@ -259,6 +390,7 @@ impl BlockStorage {
         debug_assert!(
             self.blocks.len() as u32 == self.extents[0] * self.extents[1] * self.extents[2]
         );
+        self.journal_block(x, y, z);
         let i = x + z * self.zsi + y * self.ysi;
         // Safety:
         //   index will be within self.blocks.len() due to the check against extents above
@@ -270,3 +402,58 @@ impl BlockStorage {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Block, BlockStorage};
+
+    #[test]
+    fn undo_puts_back_journaled_blocks() {
+        let mut storage = BlockStorage::new(2, 2, 2);
+        let stone = storage.add_new_block_type(Block::new("minecraft:stone".to_owned()));
+        let glass = storage.add_new_block_type(Block::new("minecraft:glass".to_owned()));
+        *storage.get_block_mut(0, 0, 0).unwrap() = stone;
+
+        storage.start_journal();
+        *storage.get_block_mut(0, 0, 0).unwrap() = glass;
+        *storage.get_block_mut(1, 1, 1).unwrap() = stone;
+        *storage.get_block_mut(1, 1, 1).unwrap() = glass;
+        let changes = storage.finish_journal();
+        assert_eq!(changes.len(), 3);
+
+        // Not journaled any more
+        *storage.get_block_mut(1, 0, 0).unwrap() = stone;
+        assert!(storage.finish_journal().is_empty());
+
+        storage.undo(&changes).unwrap();
+        assert_eq!(*storage.get_block(0, 0, 0).unwrap(), stone);
+        assert_eq!(storage.get_block(1, 1, 1).unwrap().index(), 0);
+        assert_eq!(*storage.get_block(1, 0, 0).unwrap(), stone);
+    }
+
+    #[test]
+    fn undo_puts_back_block_entities() {
+        let mut storage = BlockStorage::new(2, 1, 1);
+        let sign = storage.add_new_block_type(Block::new("minecraft:oak_sign".to_owned()));
+        let stone = storage.add_new_block_type(Block::new("minecraft:stone".to_owned()));
+        let text = serde_json::json!({ "id": "minecraft:sign", "Text1": "A" });
+        *storage.get_block_mut(0, 0, 0).unwrap() = sign;
+        storage.set_block_entity(0, 0, 0, text.clone()).unwrap();
+
+        // Replace one sign with stone and put a new one next to it
+        storage.start_journal();
+        *storage.get_block_mut(0, 0, 0).unwrap() = stone;
+        storage.remove_block_entity(0, 0, 0);
+        *storage.get_block_mut(1, 0, 0).unwrap() = sign;
+        storage
+            .set_block_entity(1, 0, 0, serde_json::json!({ "id": "minecraft:sign" }))
+            .unwrap();
+        let changes = storage.finish_journal();
+
+        storage.undo(&changes).unwrap();
+        assert_eq!(*storage.get_block(0, 0, 0).unwrap(), sign);
+        assert_eq!(storage.block_entity(0, 0, 0), Some(&text));
+        assert_eq!(storage.get_block(1, 0, 0).unwrap().index(), 0);
+        assert_eq!(storage.block_entity(1, 0, 0), None);
+    }
+}
@@ -1,7 +1,7 @@
 use serde::ser::{SerializeMap, SerializeSeq};
-use serde::{Serialize, Serializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-use super::{Block, BlockStorage, PropertyValue};
+use super::{stream::StorageVisitor, Block, BlockStorage, PropertyValue};
 
 impl Serialize for PropertyValue {
     fn serialize<S>(&self, s: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error>
@@ -36,18 +36,26 @@ impl Serialize for BlockStorage {
     where
         S: Serializer,
     {
-        let mut map = s.serialize_map(Some(3))?;
+        let has_entities = !self.block_entities.is_empty();
+        let has_provenance = !self.provenance.is_empty();
+        let mut map = s.serialize_map(Some(3 + has_entities as usize + has_provenance as usize))?;
 
         map.serialize_entry("extents", &ArrayAsExtentsMapWrapper(&self.extents))?;
         map.serialize_entry("palette", &self.palette)?;
-        map.serialize_entry("blocks", &BlockIndexSynth(&self.blocks))?;
+        map.serialize_entry("runs", &BlockRunsSynth(self))?;
+        if has_entities {
+            map.serialize_entry("block_entities", &BlockEntitiesSynth(self))?;
+        }
+        if has_provenance {
+            map.serialize_entry("provenance", &self.provenance)?;
+        }
 
         map.end()
     }
 }
 
 /// Cursed workaround to dump a 3-entry u32 slice as x/y/z map
-struct ArrayAsExtentsMapWrapper<'a>(&'a [u32; 3]);
+pub(super) struct ArrayAsExtentsMapWrapper<'a>(pub(super) &'a [u32; 3]);
 
 impl<'a> Serialize for ArrayAsExtentsMapWrapper<'a> {
     fn serialize<S>(&self, s: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error>
@@ -62,34 +70,55 @@ impl<'a> Serialize for ArrayAsExtentsMapWrapper<'a> {
     }
 }
 
-/// Cursed workaround to dump list of numbers as individual objects
-struct BlockIndexSynth<'a>(&'a [u32]);
+/// Dump the blocks as `[palette index, length]` runs, see [`super::stream`] for the format.
+struct BlockRunsSynth<'a>(&'a BlockStorage);
 
-impl<'a> Serialize for BlockIndexSynth<'a> {
+impl<'a> Serialize for BlockRunsSynth<'a> {
     fn serialize<S>(&self, s: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error>
     where
         S: Serializer,
     {
-        let mut seq = s.serialize_seq(Some(self.0.len()))?;
+        // Counting takes an extra pass, but formats without delimiters need the length up front
+        let mut seq = s.serialize_seq(Some(self.0.iter_block_runs().count()))?;
 
-        for item in self.0 {
-            seq.serialize_element(&BlockIndexEntrySynth(item))?;
+        for (block, length) in self.0.iter_block_runs() {
+            seq.serialize_element(&(block.0, length))?;
         }
 
         seq.end()
     }
 }
 
-/// Cursed workaround to dump individual numbers as objects
-struct BlockIndexEntrySynth<'a>(&'a u32);
+/// Dump the block entities as `{"pos": [x, y, z], "nbt": {...}}` objects
+pub(super) struct BlockEntitiesSynth<'a>(pub(super) &'a BlockStorage);
 
-impl<'a> Serialize for BlockIndexEntrySynth<'a> {
+impl<'a> Serialize for BlockEntitiesSynth<'a> {
     fn serialize<S>(&self, s: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error>
     where
         S: Serializer,
     {
-        let mut map = s.serialize_map(Some(1))?;
-        map.serialize_entry("pi", self.0)?;
-        map.end()
+        let mut seq = s.serialize_seq(Some(self.0.block_entities.len()))?;
+        for (pos, nbt) in self.0.block_entities.iter() {
+            seq.serialize_element(&BlockEntityEntry {
+                pos: *pos,
+                nbt: nbt.clone(),
+            })?;
+        }
+        seq.end()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub(super) struct BlockEntityEntry {
+    pub(super) pos: [u32; 3],
+    pub(super) nbt: serde_json::Value,
+}
+
+impl<'de> Deserialize<'de> for BlockStorage {
+    fn deserialize<D>(d: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        d.deserialize_map(StorageVisitor)
     }
 }
@@ -0,0 +1,450 @@
+//! Streaming JSON reader and writer for [`BlockStorage`].
+//!
+//! The format is the same JSON object produced by the serde serialization: the extents, the
+//! palette, and `runs`, a list of `[palette index, run length]` pairs in storage order. Storages
+//! with block entities also get a `block_entities` list of `{"pos": [x, y, z], "nbt": {...}}`
+//! objects. The writer
+//! emits the runs as it walks the storage and the reader fills the storage as the runs are
+//! parsed, so neither needs anything larger than the storage itself in memory.
+//!
+//! ```json
+//! {"extents":{"x":2,"y":1,"z":2},"palette":[{"name":"minecraft:air"}],"runs":[[0,4]]}
+//! ```
+//!
+//! Outputs also get a `provenance` list, as described in [`crate::provenance`], once a tool has
+//! recorded itself in them.
+//!
+//! Outputs written before the blocks were run-length encoded have a `blocks` list of `{"pi": index}`
+//! objects, one per block, instead of `runs`. Both readers still read those.
+//!
+//! Files written with [`write_rle_json_file`] are gzipped if they have a `.gz` extension, and
+//! [`read_rle_json_file`] decompresses gzipped files whatever their extension.
+
+use anyhow::{anyhow, Context, Result};
+use flate2::{bufread::MultiGzDecoder, write::GzEncoder, Compression};
+use serde::de::{
+    DeserializeSeed, Deserializer, Error as DeError, IgnoredAny, MapAccess, SeqAccess, Visitor,
+};
+use serde::Deserialize;
+use std::fmt::Formatter;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use super::{
+    serialization::{ArrayAsExtentsMapWrapper, BlockEntitiesSynth, BlockEntityEntry},
+    Block, BlockStorage,
+};
+
+/// Write `storage` to `w` in the run-length encoded JSON format.
+pub fn write_rle_json<W: Write>(storage: &BlockStorage, mut w: W) -> Result<()> {
+    write!(w, "{{\"extents\":")?;
+    serde_json::to_writer(&mut w, &ArrayAsExtentsMapWrapper(&storage.extents))
+        .context("Write extents")?;
+    write!(w, ",\"palette\":")?;
+    serde_json::to_writer(&mut w, &storage.palette).context("Write palette")?;
+    write!(w, ",\"runs\":[")?;
+    for (i, (block, length)) in storage.iter_block_runs().enumerate() {
+        if i != 0 {
+            write!(w, ",")?;
+        }
+        write!(w, "[{},{}]", block.0, length)?;
+    }
+    write!(w, "]")?;
+    if !storage.block_entities.is_empty() {
+        write!(w, ",\"block_entities\":")?;
+        serde_json::to_writer(&mut w, &BlockEntitiesSynth(storage))
+            .context("Write block entities")?;
+    }
+    if !storage.provenance.is_empty() {
+        write!(w, ",\"provenance\":")?;
+        serde_json::to_writer(&mut w, &storage.provenance).context("Write provenance")?;
+    }
+    write!(w, "}}")?;
+    w.flush()?;
+
+    Ok(())
+}
+
+/// Read a [`BlockStorage`] in the run-length encoded JSON format from `r`. `extents` must appear
+/// before `runs`, which is always the case for files produced by [`write_rle_json`].
+pub fn read_rle_json<R: Read>(r: R) -> Result<BlockStorage> {
+    let mut deserializer = serde_json::Deserializer::from_reader(r);
+    let storage = deserializer
+        .deserialize_map(StorageVisitor)
+        .context("Parse block storage")?;
+    deserializer
+        .end()
+        .context("Trailing data after block storage")?;
+
+    Ok(storage)
+}
+
+/// Extension of gzipped structure JSON, like `counter.json.gz`
+pub const GZIP_EXTENSION: &str = "gz";
+
+/// First bytes of every gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Write `storage` to the file at `path` in the run-length encoded JSON format, gzipped if `path`
+/// has a `.gz` extension.
+pub fn write_rle_json_file(storage: &BlockStorage, path: &Path) -> Result<()> {
+    let file = File::create(path).with_context(|| anyhow!("Create {:?}", path))?;
+    let w = BufWriter::new(file);
+    if path.extension() == Some(GZIP_EXTENSION.as_ref()) {
+        let mut encoder = GzEncoder::new(w, Compression::default());
+        write_rle_json(storage, &mut encoder)?;
+        encoder.finish()?.flush()?;
+        Ok(())
+    } else {
+        write_rle_json(storage, w)
+    }
+    .with_context(|| anyhow!("Write {:?}", path))
+}
+
+/// Read a [`BlockStorage`] in the run-length encoded JSON format from the file at `path`,
+/// decompressing it first if it's gzipped.
+pub fn read_rle_json_file(path: &Path) -> Result<BlockStorage> {
+    let file = File::open(path).with_context(|| anyhow!("Open {:?}", path))?;
+    let mut r = BufReader::new(file);
+    let gzipped = r.fill_buf()?.starts_with(&GZIP_MAGIC);
+    if gzipped {
+        read_rle_json(BufReader::new(MultiGzDecoder::new(r)))
+    } else {
+        read_rle_json(r)
+    }
+    .with_context(|| anyhow!("Read {:?}", path))
+}
+
+#[derive(Deserialize)]
+struct Extents {
+    x: u32,
+    y: u32,
+    z: u32,
+}
+
+/// Serde visitor for the run-length encoded format, shared with the [`serde::Deserialize`]
+/// implementation.
+pub(super) struct StorageVisitor;
+
+impl<'de> Visitor<'de> for StorageVisitor {
+    type Value = BlockStorage;
+
+    fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "a run-length encoded block storage")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut extents: Option<Extents> = None;
+        let mut palette: Option<Vec<Block>> = None;
+        let mut storage: Option<BlockStorage> = None;
+        let mut block_entities: Vec<BlockEntityEntry> = Vec::new();
+        let mut provenance = Vec::new();
+
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "extents" => extents = Some(map.next_value()?),
+                "palette" => palette = Some(map.next_value()?),
+                "runs" => {
+                    let extents = extents
+                        .as_ref()
+                        .ok_or_else(|| A::Error::custom("\"runs\" must come after \"extents\""))?;
+                    let mut s = BlockStorage::new(extents.x, extents.y, extents.z);
+                    map.next_value_seed(RunsSeed(&mut s.blocks))?;
+                    storage = Some(s);
+                }
+                "blocks" => {
+                    let extents = extents.as_ref().ok_or_else(|| {
+                        A::Error::custom("\"blocks\" must come after \"extents\"")
+                    })?;
+                    let mut s = BlockStorage::new(extents.x, extents.y, extents.z);
+                    map.next_value_seed(VerboseBlocksSeed(&mut s.blocks))?;
+                    storage = Some(s);
+                }
+                "block_entities" => block_entities = map.next_value()?,
+                "provenance" => provenance = map.next_value()?,
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+
+        let mut storage = storage.ok_or_else(|| A::Error::missing_field("runs"))?;
+        storage.palette = palette.ok_or_else(|| A::Error::missing_field("palette"))?;
+        storage.provenance = provenance;
+
+        let palette_size = storage.palette.len() as u32;
+        if palette_size == 0 {
+            return Err(A::Error::custom("Block storage palette is empty"));
+        }
+        if let Some(bad) = storage.blocks.iter().find(|&&b| b >= palette_size) {
+            return Err(A::Error::custom(format!(
+                "Palette index {} out of range for palette of size {}",
+                bad, palette_size
+            )));
+        }
+        for entry in block_entities {
+            let [x, y, z] = entry.pos;
+            storage
+                .set_block_entity(x, y, z, entry.nbt)
+                .map_err(|e| A::Error::custom(format!("Block entity: {}", e)))?;
+        }
+
+        Ok(storage)
+    }
+}
+
+/// Decodes runs directly into the block array of a [`BlockStorage`]
+struct RunsSeed<'a>(&'a mut Vec<u32>);
+
+impl<'de, 'a> DeserializeSeed<'de> for RunsSeed<'a> {
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for RunsSeed<'a> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "a list of [palette index, length] runs")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut position = 0;
+        while let Some((block, length)) = seq.next_element::<(u32, u32)>()? {
+            let end = position + length as usize;
+            if end > self.0.len() {
+                return Err(A::Error::custom(format!(
+                    "Runs cover more than the {} blocks in the storage",
+                    self.0.len()
+                )));
+            }
+            self.0[position..end].fill(block);
+            position = end;
+        }
+
+        if position != self.0.len() {
+            return Err(A::Error::custom(format!(
+                "Runs cover {} blocks, but the storage has {}",
+                position,
+                self.0.len()
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// One block of the verbose format
+#[derive(Deserialize)]
+struct VerboseBlock {
+    pi: u32,
+}
+
+/// Decodes the verbose format's `{"pi": index}` per block directly into the block array of a
+/// [`BlockStorage`]
+struct VerboseBlocksSeed<'a>(&'a mut Vec<u32>);
+
+impl<'de, 'a> DeserializeSeed<'de> for VerboseBlocksSeed<'a> {
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for VerboseBlocksSeed<'a> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "a list of {{\"pi\": palette index}} blocks")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut position = 0;
+        while let Some(block) = seq.next_element::<VerboseBlock>()? {
+            let slot = self.0.get_mut(position).ok_or_else(|| {
+                A::Error::custom(format!("More blocks than the {} in the storage", position))
+            })?;
+            *slot = block.pi;
+            position += 1;
+        }
+
+        if position != self.0.len() {
+            return Err(A::Error::custom(format!(
+                "{} blocks, but the storage has {}",
+                position,
+                self.0.len()
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{read_rle_json, read_rle_json_file, write_rle_json, write_rle_json_file};
+    use crate::block_storage::{Block, BlockStorage, PropertyValue};
+    use crate::provenance::Provenance;
+
+    #[test]
+    fn roundtrip() {
+        let mut storage = BlockStorage::new(3, 2, 4);
+        let stone = storage.add_new_block_type(Block::new("minecraft:stone".to_owned()));
+        let repeater = storage.add_new_block_type(Block {
+            name: "minecraft:repeater".to_owned(),
+            properties: Some(
+                [
+                    (
+                        "facing".to_owned(),
+                        PropertyValue::String("south".to_owned()),
+                    ),
+                    ("delay".to_owned(), PropertyValue::Byte(2)),
+                ]
+                .into_iter()
+                .collect(),
+            ),
+        });
+        *storage.get_block_mut(0, 0, 0).unwrap() = stone;
+        *storage.get_block_mut(1, 0, 0).unwrap() = stone;
+        *storage.get_block_mut(2, 1, 3).unwrap() = repeater;
+
+        let mut buffer = Vec::new();
+        write_rle_json(&storage, &mut buffer).unwrap();
+        let text = String::from_utf8(buffer.clone()).unwrap();
+        assert!(text.contains("\"runs\":[[1,2],[0,21],[2,1]]"), "{}", text);
+
+        let read = read_rle_json(&buffer[..]).unwrap();
+        assert_eq!(read.extents(), storage.extents());
+        assert_eq!(read.palette, storage.palette);
+        assert_eq!(read.blocks, storage.blocks);
+    }
+
+    #[test]
+    fn roundtrip_block_entities() {
+        let mut storage = BlockStorage::new(2, 2, 2);
+        let sign = storage.add_new_block_type(Block::new("minecraft:oak_sign".to_owned()));
+        *storage.get_block_mut(1, 1, 0).unwrap() = sign;
+        let nbt = serde_json::json!({"id": "minecraft:sign", "Text1": "{\"text\":\"clk\"}"});
+        storage.set_block_entity(1, 1, 0, nbt.clone()).unwrap();
+        assert!(storage.set_block_entity(2, 0, 0, nbt.clone()).is_err());
+
+        let mut buffer = Vec::new();
+        write_rle_json(&storage, &mut buffer).unwrap();
+        assert_eq!(
+            String::from_utf8(buffer.clone()).unwrap(),
+            serde_json::to_string(&storage).unwrap()
+        );
+
+        let read = read_rle_json(&buffer[..]).unwrap();
+        assert_eq!(read.block_entity(1, 1, 0), Some(&nbt));
+        assert_eq!(read.iter_block_entities().count(), 1);
+    }
+
+    #[test]
+    fn roundtrip_provenance() {
+        let mut storage = BlockStorage::new(2, 2, 2);
+        let provenance = vec![Provenance {
+            tool: "mcpnr-routing".to_owned(),
+            input_hashes: [("counter.pb".to_owned(), "0123456789abcdef".to_owned())]
+                .into_iter()
+                .collect(),
+            seeds: vec![3],
+            ..Default::default()
+        }];
+        storage.set_provenance(provenance.clone());
+
+        let mut buffer = Vec::new();
+        write_rle_json(&storage, &mut buffer).unwrap();
+        assert_eq!(
+            String::from_utf8(buffer.clone()).unwrap(),
+            serde_json::to_string(&storage).unwrap()
+        );
+
+        let read = read_rle_json(&buffer[..]).unwrap();
+        assert_eq!(read.provenance(), provenance);
+    }
+
+    #[test]
+    fn matches_serde() {
+        let mut storage = BlockStorage::new(4, 3, 2);
+        let stone = storage.add_new_block_type(Block::new("minecraft:stone".to_owned()));
+        *storage.get_block_mut(3, 1, 1).unwrap() = stone;
+
+        let mut buffer = Vec::new();
+        write_rle_json(&storage, &mut buffer).unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            serde_json::to_string(&storage).unwrap()
+        );
+
+        let read: BlockStorage =
+            serde_json::from_str(&serde_json::to_string(&storage).unwrap()).expect("Deserialize");
+        assert_eq!(read.blocks, storage.blocks);
+    }
+
+    #[test]
+    fn rejects_short_runs() {
+        let json = r#"{"extents":{"x":2,"y":1,"z":1},"palette":[{"name":"minecraft:air"}],"runs":[[0,1]]}"#;
+        assert!(read_rle_json(json.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_palette_index() {
+        let json = r#"{"extents":{"x":2,"y":1,"z":1},"palette":[{"name":"minecraft:air"}],"runs":[[1,2]]}"#;
+        assert!(read_rle_json(json.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn gzip_round_trips() {
+        let mut storage = BlockStorage::new(16, 8, 16);
+        let stone = storage.add_new_block_type(Block::new("minecraft:stone".to_owned()));
+        *storage.get_block_mut(3, 1, 1).unwrap() = stone;
+
+        let dir = std::env::temp_dir().join(format!("mcpnr-gzip-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let gzipped = dir.join("output.json.gz");
+        write_rle_json_file(&storage, &gzipped).unwrap();
+        let data = std::fs::read(&gzipped).unwrap();
+        assert_eq!(data[..2], [0x1f, 0x8b]);
+
+        // Detected by the contents, not the extension
+        let renamed = dir.join("output.json");
+        std::fs::rename(&gzipped, &renamed).unwrap();
+        let read = read_rle_json_file(&renamed);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(read.unwrap().blocks, storage.blocks);
+    }
+
+    #[test]
+    fn reads_verbose_blocks() {
+        let json = r#"{"extents":{"x":2,"y":1,"z":1},"palette":[{"name":"minecraft:air"},{"name":"minecraft:stone"}],"blocks":[{"pi":0},{"pi":1}]}"#;
+        let read = read_rle_json(json.as_bytes()).unwrap();
+        assert_eq!(read.blocks, [0, 1]);
+
+        let read: BlockStorage = serde_json::from_str(json).unwrap();
+        assert_eq!(read.blocks, [0, 1]);
+
+        let short = json.replace(r#",{"pi":1}"#, "");
+        assert!(read_rle_json(short.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,134 @@
+//! Sidecar file the placer can write next to its output, describing where the placed cells are in
+//! block coordinates. The router builds its blocked cell grid from this when given one, instead of
+//! working the blockages out from the names of the blocks it splatted.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::block_storage::{Direction, Position};
+use crate::blockers::Blockers;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockageMap {
+    /// Blocks occupied by each placed cell, plus the blocks around them wires have to avoid.
+    pub footprints: Vec<Footprint>,
+    /// Extra regions wires must not be routed through. The placer doesn't produce any of these,
+    /// they are for reserving space by hand.
+    #[serde(default)]
+    pub keep_outs: Vec<KeepOut>,
+    pub pins: Vec<PinLocation>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Footprint {
+    /// Index of the cell in the placed design.
+    pub cell: usize,
+    pub cell_type: String,
+    pub blocked: Vec<[u32; 3]>,
+}
+
+/// Box of blocks from `min` up to but not including `max`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeepOut {
+    pub min: [u32; 3],
+    pub max: [u32; 3],
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PinLocation {
+    pub pos: [u32; 3],
+    /// Direction wires connect to the pin from.
+    pub facing: Direction,
+}
+
+fn to_position([x, y, z]: [u32; 3]) -> Position {
+    Position::new(x as i32, y as i32, z as i32)
+}
+
+impl BlockageMap {
+    pub fn read(path: &Path) -> Result<Self> {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Failed to open blockage map {:?}", path))?;
+        serde_json::from_reader(std::io::BufReader::new(file))
+            .with_context(|| format!("Failed to parse blockage map {:?}", path))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("Failed to create blockage map {:?}", path))?;
+        serde_json::to_writer(std::io::BufWriter::new(file), self)
+            .with_context(|| format!("Failed to write blockage map {:?}", path))
+    }
+
+    /// Expand the footprints and keep-outs into individual blocked positions.
+    pub fn to_blockers(&self) -> Blockers {
+        let mut blocked: Vec<Position> = self
+            .footprints
+            .iter()
+            .flat_map(|footprint| footprint.blocked.iter().copied().map(to_position))
+            .collect();
+        for keep_out in self.keep_outs.iter() {
+            for x in keep_out.min[0]..keep_out.max[0] {
+                for y in keep_out.min[1]..keep_out.max[1] {
+                    for z in keep_out.min[2]..keep_out.max[2] {
+                        blocked.push(to_position([x, y, z]));
+                    }
+                }
+            }
+        }
+
+        Blockers {
+            blocked,
+            pins: self
+                .pins
+                .iter()
+                .map(|pin| (to_position(pin.pos), pin.facing))
+                .collect(),
+            unrecognized: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BlockageMap, Footprint, KeepOut, PinLocation};
+    use crate::block_storage::{Direction, Position};
+
+    #[test]
+    fn keep_outs_expand_to_every_block() {
+        let map = BlockageMap {
+            footprints: vec![Footprint {
+                cell: 0,
+                cell_type: "gate_not.nbt".to_owned(),
+                blocked: vec![[4, 0, 4]],
+            }],
+            keep_outs: vec![KeepOut {
+                min: [0, 0, 0],
+                max: [2, 1, 1],
+            }],
+            pins: vec![PinLocation {
+                pos: [4, 1, 5],
+                facing: Direction::North,
+            }],
+        };
+
+        let json = serde_json::to_string(&map).unwrap();
+        assert!(json.contains("\"facing\":\"north\""));
+        let blockers = serde_json::from_str::<BlockageMap>(&json)
+            .unwrap()
+            .to_blockers();
+        assert_eq!(
+            blockers.blocked,
+            vec![
+                Position::new(4, 0, 4),
+                Position::new(0, 0, 0),
+                Position::new(1, 0, 0)
+            ]
+        );
+        assert_eq!(
+            blockers.pins,
+            vec![(Position::new(4, 1, 5), Direction::North)]
+        );
+    }
+}
@@ -0,0 +1,463 @@
+//! Rules for working out which positions wires have to stay out of, and where the pins are, from
+//! the blocks that make up a design. These are shared between the router, which applies them to
+//! the splatted design when it isn't given a blockage map, and the placer, which applies them to
+//! each cell type to write one.
+//!
+//! The rules are data rather than code, so a techlib can teach the flow about new blocks with a
+//! `blockers.json` next to its structures. Its rules are tried before the built in ones in
+//! `default_blockers.json`, and the first rule whose pattern matches the block name wins.
+
+use anyhow::{anyhow, ensure, Context, Result};
+use log::warn;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::block_storage::{
+    Block, BlockStorage, Direction, Position, PropertyValue, ALL_DIRECTIONS, PLANAR_DIRECTIONS,
+};
+
+/// Name of the file in the techlib with extra blocker rules
+pub const BLOCKER_RULES_FILE: &str = "blockers.json";
+
+const DEFAULT_RULES: &str = include_str!("default_blockers.json");
+
+/// Positions around a block that are blocked along with it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Neighbour {
+    North,
+    South,
+    East,
+    West,
+    Up,
+    Down,
+    /// The four horizontal neighbours
+    Planar,
+    /// All six neighbours
+    All,
+    /// The neighbour the block's `facing` property points at
+    Front,
+    /// The neighbour opposite the one the block's `facing` property points at
+    Back,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BlockerAction {
+    /// Wires can go through the block
+    Free,
+    /// Wires can't go through the block, or the listed neighbours
+    Blocked {
+        #[serde(default)]
+        neighbours: Vec<Neighbour>,
+    },
+    /// A sign marking a cell pin, which wires connect to from the direction it faces
+    Pin,
+    /// A piston, which blocks the space it can push the block in front of it in to
+    Piston,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct BlockerRule {
+    /// Block name to match, with at most one `*` matching any sequence of characters
+    pub pattern: String,
+    /// Free form explanation of the rule, ignored
+    #[serde(default)]
+    pub comment: Option<String>,
+    #[serde(flatten)]
+    pub action: BlockerAction,
+}
+
+impl BlockerRule {
+    fn matches(&self, name: &str) -> bool {
+        match self.pattern.split_once('*') {
+            Some((prefix, suffix)) => {
+                name.len() >= prefix.len() + suffix.len()
+                    && name.starts_with(prefix)
+                    && name.ends_with(suffix)
+            }
+            None => self.pattern == name,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct BlockerRules {
+    pub rules: Vec<BlockerRule>,
+}
+
+impl BlockerRules {
+    /// Only the rules built in to MCPNR
+    pub fn builtin() -> Self {
+        serde_json::from_str(DEFAULT_RULES).expect("Built in blocker rules are invalid")
+    }
+
+    /// Load the rules in `path`, followed by the built in ones. A missing file isn't an error,
+    /// since most techlibs don't need any extra rules.
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut rules = match std::fs::File::open(path) {
+            Ok(file) => serde_json::from_reader::<_, Self>(std::io::BufReader::new(file))
+                .with_context(|| format!("Failed to parse blocker rules {:?}", path))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self { rules: Vec::new() },
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to open blocker rules {:?}", path))
+            }
+        };
+        rules.rules.extend(Self::builtin().rules);
+
+        Ok(rules)
+    }
+
+    /// First rule matching a block name, if any
+    pub fn find(&self, name: &str) -> Option<&BlockerAction> {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(name))
+            .map(|rule| &rule.action)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Blockers {
+    /// Positions wires can't be routed through. These may lie outside of the scanned storage, and
+    /// may repeat.
+    pub blocked: Vec<Position>,
+    /// Pin positions, with the direction wires connect to them from.
+    pub pins: Vec<(Position, Direction)>,
+    /// Positions of blocks none of the rules know about, along with the block name.
+    pub unrecognized: Vec<(Position, String)>,
+}
+
+pub fn block_facing(block: &Block) -> Option<Direction> {
+    block
+        .properties
+        .as_ref()
+        .and_then(|p| p.get("facing"))
+        .and_then(|f| match f {
+            PropertyValue::String(f) => match f.as_str() {
+                "north" => Some(Direction::North),
+                "south" => Some(Direction::South),
+                "east" => Some(Direction::East),
+                "west" => Some(Direction::West),
+                "up" => Some(Direction::Up),
+                "down" => Some(Direction::Down),
+                _ => None,
+            },
+            PropertyValue::Byte(_) => None,
+        })
+}
+
+/// Horizontal direction a standing sign with `rotation` faces, rounded to the nearest of the four.
+/// Rotations exactly between two of them (the diagonals) are ambiguous, as are out of range ones.
+pub fn rotation_direction(rotation: u8) -> Option<Direction> {
+    match rotation {
+        15 | 0 | 1 => Some(Direction::South),
+        3..=5 => Some(Direction::West),
+        7..=9 => Some(Direction::North),
+        11..=13 => Some(Direction::East),
+        _ => None,
+    }
+}
+
+/// Direction a wire has to approach the pin marked by the sign `block` from, which is the way its
+/// text faces. That's the `rotation` property of standing signs, and the `facing` property of wall
+/// signs. Other blocks a techlib marks pins with can face up or down too, with a `facing` of `up`
+/// or `down`, or a `face` of `floor` or `ceiling` like buttons have.
+pub fn pin_direction(block: &Block) -> Result<Direction> {
+    let face = block.properties.as_ref().and_then(|p| p.get("face"));
+    match face {
+        Some(PropertyValue::String(face)) if face == "floor" => return Ok(Direction::Up),
+        Some(PropertyValue::String(face)) if face == "ceiling" => return Ok(Direction::Down),
+        _ => {}
+    }
+
+    match block.properties.as_ref().and_then(|p| p.get("rotation")) {
+        Some(rotation) => {
+            let rotation = match rotation {
+                PropertyValue::String(s) => s
+                    .parse()
+                    .with_context(|| anyhow!("Failed to parse rotation {:?}", s))?,
+                PropertyValue::Byte(b) => *b as u8,
+            };
+            rotation_direction(rotation).ok_or_else(|| {
+                anyhow!(
+                    "Rotation {} doesn't face north, south, east or west",
+                    rotation
+                )
+            })
+        }
+        None => {
+            block_facing(block).ok_or_else(|| anyhow!("{} has no rotation or facing", block.name))
+        }
+    }
+}
+
+/// Apply the blocker rules to every block in `storage`.
+pub fn find_blockers(storage: &BlockStorage, rules: &BlockerRules) -> Result<Blockers> {
+    let mut blockers = Blockers::default();
+    let mut actions = HashMap::new();
+
+    for ((x, y, z), block_idx) in storage.iter_block_coords() {
+        let pos = Position::new(x as i32, y as i32, z as i32);
+        let block = storage.info_for_index(block_idx).ok_or_else(|| {
+            anyhow!(
+                "Failed to look up block info for {:?} while finding blockers",
+                block_idx
+            )
+        })?;
+        let action = *actions
+            .entry(block_idx)
+            .or_insert_with(|| rules.find(&block.name));
+        let blocked = &mut blockers.blocked;
+        match action {
+            Some(BlockerAction::Free) => {}
+            Some(BlockerAction::Blocked { neighbours }) => {
+                blocked.push(pos);
+                for neighbour in neighbours {
+                    let directions = match neighbour {
+                        Neighbour::North => vec![Direction::North],
+                        Neighbour::South => vec![Direction::South],
+                        Neighbour::East => vec![Direction::East],
+                        Neighbour::West => vec![Direction::West],
+                        Neighbour::Up => vec![Direction::Up],
+                        Neighbour::Down => vec![Direction::Down],
+                        Neighbour::Planar => PLANAR_DIRECTIONS.to_vec(),
+                        Neighbour::All => ALL_DIRECTIONS.to_vec(),
+                        Neighbour::Front | Neighbour::Back => match block_facing(block) {
+                            Some(d) if *neighbour == Neighbour::Front => vec![d],
+                            Some(d) => vec![d.mirror()],
+                            None => {
+                                warn!("{} at {} has no facing property", block.name, pos);
+                                vec![]
+                            }
+                        },
+                    };
+                    blocked.extend(directions.into_iter().map(|d| pos.offset(d)));
+                }
+            }
+            Some(BlockerAction::Pin) => {
+                let direction = pin_direction(block).unwrap_or_else(|e| {
+                    warn!(
+                        "Pin at {} has no usable direction, assuming South: {}",
+                        pos, e
+                    );
+                    Direction::South
+                });
+                blockers.pins.push((pos, direction));
+            }
+            Some(BlockerAction::Piston) => {
+                // Pistons are giga cursed, we need to mark everything remotely closed to them as
+                // occupied to avoid phantom powering problems
+                blocked.push(pos);
+
+                // We also need to find the blocks attached to the face of the piston and mark the
+                // spaces those can push in to as occupied, potentially recursively (since the
+                // piston may be moving a block of redstone for example)
+                if let Some(piston_direction) = block_facing(block) {
+                    let po = pos.offset(piston_direction);
+                    let is_sticky = storage
+                        .get_block(po.x as u32, po.y as u32, po.z as u32)
+                        .ok()
+                        .and_then(|b| Some(storage.info_for_index(*b)?.is_sticky()))
+                        .unwrap_or(false);
+
+                    // Punt on sticky block handling for now, none of our cells use it and handling
+                    // it properly seems hard
+                    ensure!(
+                        !is_sticky,
+                        "Sticky block propegation is currently unsupported"
+                    );
+
+                    // Mark the space that this block might get pushed into as blocked
+                    blocked.push(po.offset(piston_direction));
+                } else {
+                    warn!("Piston at {} is missing its facing property", pos);
+                }
+            }
+            None => {
+                blockers.unrecognized.push((pos, block.name.clone()));
+            }
+        }
+    }
+
+    Ok(blockers)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{find_blockers, pin_direction, rotation_direction, BlockerRules};
+    use crate::block_storage::{Block, BlockStorage, Direction, Position, PropertyValue};
+
+    #[test]
+    fn signs_are_pins_and_unknown_blocks_are_reported() {
+        let mut storage = BlockStorage::new(3, 1, 3);
+        let sign = storage.add_new_block_type(Block {
+            name: "minecraft:oak_sign".to_owned(),
+            properties: Some(
+                [("rotation".to_owned(), PropertyValue::Byte(8))]
+                    .into_iter()
+                    .collect(),
+            ),
+        });
+        let calcite = storage.add_new_block_type(Block::new("minecraft:calcite".to_owned()));
+        let mystery = storage.add_new_block_type(Block::new("minecraft:mystery".to_owned()));
+        *storage.get_block_mut(0, 0, 0).unwrap() = sign;
+        *storage.get_block_mut(1, 0, 1).unwrap() = calcite;
+        *storage.get_block_mut(2, 0, 2).unwrap() = mystery;
+
+        let blockers = find_blockers(&storage, &BlockerRules::builtin()).unwrap();
+        assert_eq!(
+            blockers.pins,
+            vec![(Position::new(0, 0, 0), Direction::North)]
+        );
+        assert_eq!(blockers.blocked, vec![Position::new(1, 0, 1)]);
+        assert_eq!(
+            blockers.unrecognized,
+            vec![(Position::new(2, 0, 2), "minecraft:mystery".to_owned())]
+        );
+    }
+
+    #[test]
+    fn extra_rules_come_before_builtin_ones() {
+        let mut rules: BlockerRules = serde_json::from_str(
+            r#"{"rules": [
+                {"pattern": "minecraft:white_wool", "kind": "free"},
+                {"pattern": "minecraft:obs*", "kind": "blocked", "neighbours": ["front"]}
+            ]}"#,
+        )
+        .unwrap();
+        rules.rules.extend(BlockerRules::builtin().rules);
+
+        let mut storage = BlockStorage::new(1, 1, 2);
+        let wool = storage.add_new_block_type(Block::new("minecraft:white_wool".to_owned()));
+        let observer = storage.add_new_block_type(Block {
+            name: "minecraft:observer".to_owned(),
+            properties: Some(
+                [(
+                    "facing".to_owned(),
+                    PropertyValue::String("south".to_owned()),
+                )]
+                .into_iter()
+                .collect(),
+            ),
+        });
+        *storage.get_block_mut(0, 0, 0).unwrap() = wool;
+        *storage.get_block_mut(0, 0, 1).unwrap() = observer;
+
+        let blockers = find_blockers(&storage, &rules).unwrap();
+        assert_eq!(
+            blockers.blocked,
+            vec![Position::new(0, 0, 1), Position::new(0, 0, 2)]
+        );
+        assert!(blockers.unrecognized.is_empty());
+    }
+
+    #[test]
+    fn comparators_and_observers_block_by_facing() {
+        let facing = |name: &str, facing: &str| Block {
+            name: name.to_owned(),
+            properties: Some(
+                [(
+                    "facing".to_owned(),
+                    PropertyValue::String(facing.to_owned()),
+                )]
+                .into_iter()
+                .collect(),
+            ),
+        };
+        let mut storage = BlockStorage::new(1, 1, 1);
+        let comparator = storage.add_new_block_type(facing("minecraft:comparator", "east"));
+        let observer = storage.add_new_block_type(facing("minecraft:observer", "east"));
+        let rules = BlockerRules::builtin();
+
+        *storage.get_block_mut(0, 0, 0).unwrap() = comparator;
+        let mut blocked = find_blockers(&storage, &rules).unwrap().blocked;
+        blocked.sort_by_key(|p| (p.x, p.y, p.z));
+        assert_eq!(
+            blocked,
+            vec![
+                Position::new(-1, 0, 0),
+                Position::new(0, 0, -1),
+                Position::new(0, 0, 0),
+                Position::new(0, 0, 1),
+                Position::new(1, 0, 0),
+            ]
+        );
+
+        *storage.get_block_mut(0, 0, 0).unwrap() = observer;
+        let mut blocked = find_blockers(&storage, &rules).unwrap().blocked;
+        blocked.sort_by_key(|p| (p.x, p.y, p.z));
+        assert_eq!(
+            blocked,
+            vec![
+                Position::new(-1, 0, 0),
+                Position::new(0, 0, 0),
+                Position::new(1, 0, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn every_rotation_rounds_to_the_nearest_direction() {
+        let directions: Vec<_> = (0..16).map(rotation_direction).collect();
+        let (s, w, n, e) = (
+            Some(Direction::South),
+            Some(Direction::West),
+            Some(Direction::North),
+            Some(Direction::East),
+        );
+        assert_eq!(
+            directions,
+            vec![s, s, None, w, w, w, None, n, n, n, None, e, e, e, None, s]
+        );
+        assert_eq!(rotation_direction(16), None);
+    }
+
+    #[test]
+    fn pin_direction_reads_rotation_or_facing() {
+        let sign = |name: &str, property: &str, value: PropertyValue| Block {
+            name: name.to_owned(),
+            properties: Some([(property.to_owned(), value)].into_iter().collect()),
+        };
+
+        let standing = sign("minecraft:oak_sign", "rotation", PropertyValue::Byte(12));
+        assert_eq!(pin_direction(&standing).unwrap(), Direction::East);
+        let standing = sign(
+            "minecraft:oak_sign",
+            "rotation",
+            PropertyValue::String("4".to_owned()),
+        );
+        assert_eq!(pin_direction(&standing).unwrap(), Direction::West);
+        let diagonal = sign("minecraft:oak_sign", "rotation", PropertyValue::Byte(6));
+        assert!(pin_direction(&diagonal).is_err());
+
+        let wall = sign(
+            "minecraft:oak_wall_sign",
+            "facing",
+            PropertyValue::String("east".to_owned()),
+        );
+        assert_eq!(pin_direction(&wall).unwrap(), Direction::East);
+        assert!(pin_direction(&Block::new("minecraft:oak_sign".to_owned())).is_err());
+
+        let top = sign(
+            "minecraft:lightning_rod",
+            "facing",
+            PropertyValue::String("up".to_owned()),
+        );
+        assert_eq!(pin_direction(&top).unwrap(), Direction::Up);
+        let button = Block {
+            name: "minecraft:stone_button".to_owned(),
+            properties: Some(
+                [
+                    ("face", PropertyValue::String("ceiling".to_owned())),
+                    ("facing", PropertyValue::String("north".to_owned())),
+                ]
+                .into_iter()
+                .map(|(k, v)| (k.to_owned(), v))
+                .collect(),
+            ),
+        };
+        assert_eq!(pin_direction(&button).unwrap(), Direction::Down);
+    }
+}
@@ -0,0 +1,354 @@
+//! Environment and configuration checks shared by the `doctor` subcommands of the MCPNR tools.
+//!
+//! Checks record their results in a [`Report`] instead of bailing out on the first problem, so a
+//! user can fix everything that's wrong with their setup in one go.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::blockers::{BlockerRules, BLOCKER_RULES_FILE};
+use crate::minecraft_types::{is_structure_file, load_structure, Structure};
+
+/// How bad a finding is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Ok,
+    Warning,
+    Error,
+}
+
+/// The result of a single check
+#[derive(Debug)]
+pub struct Finding {
+    pub severity: Severity,
+    /// Short name of what was checked
+    pub check: String,
+    pub message: String,
+    /// What the user should do about it, if anything
+    pub fix: Option<String>,
+}
+
+/// Collection of findings from a doctor run
+#[derive(Debug, Default)]
+pub struct Report {
+    findings: Vec<Finding>,
+}
+
+impl Report {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn ok(&mut self, check: impl Into<String>, message: impl Into<String>) {
+        self.findings.push(Finding {
+            severity: Severity::Ok,
+            check: check.into(),
+            message: message.into(),
+            fix: None,
+        });
+    }
+
+    pub fn warning(
+        &mut self,
+        check: impl Into<String>,
+        message: impl Into<String>,
+        fix: impl Into<String>,
+    ) {
+        self.findings.push(Finding {
+            severity: Severity::Warning,
+            check: check.into(),
+            message: message.into(),
+            fix: Some(fix.into()),
+        });
+    }
+
+    pub fn error(
+        &mut self,
+        check: impl Into<String>,
+        message: impl Into<String>,
+        fix: impl Into<String>,
+    ) {
+        self.findings.push(Finding {
+            severity: Severity::Error,
+            check: check.into(),
+            message: message.into(),
+            fix: Some(fix.into()),
+        });
+    }
+
+    /// Record `ok_message` if `condition` holds, otherwise an error
+    pub fn require(
+        &mut self,
+        condition: bool,
+        check: impl Into<String>,
+        ok_message: impl Into<String>,
+        error_message: impl Into<String>,
+        fix: impl Into<String>,
+    ) {
+        if condition {
+            self.ok(check, ok_message);
+        } else {
+            self.error(check, error_message, fix);
+        }
+    }
+
+    pub fn findings(&self) -> &[Finding] {
+        &self.findings
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        self.findings
+            .iter()
+            .filter(|f| f.severity == severity)
+            .count()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.count(Severity::Error) > 0
+    }
+
+    /// Print the report in a human-readable form
+    pub fn print<W: Write>(&self, mut w: W) -> std::io::Result<()> {
+        for finding in self.findings.iter() {
+            let tag = match finding.severity {
+                Severity::Ok => "ok",
+                Severity::Warning => "warning",
+                Severity::Error => "error",
+            };
+            writeln!(w, "[{}] {}: {}", tag, finding.check, finding.message)?;
+            if let Some(ref fix) = finding.fix {
+                writeln!(w, "    fix: {}", fix)?;
+            }
+        }
+        writeln!(
+            w,
+            "{} errors, {} warnings",
+            self.count(Severity::Error),
+            self.count(Severity::Warning)
+        )
+    }
+}
+
+/// Technology library contents gathered while checking it, for use by later checks
+#[derive(Debug, Default)]
+pub struct TechlibInfo {
+    /// Structures that parsed successfully, by file name
+    pub structures: BTreeMap<String, Structure>,
+}
+
+/// Check that the technology library at `techlib` is readable and that its structures are valid
+/// and consistent with each other.
+pub fn check_techlib(report: &mut Report, techlib: &Path) -> TechlibInfo {
+    let mut info = TechlibInfo::default();
+
+    if !techlib.is_dir() {
+        report.error(
+            "techlib",
+            format!("{} is not a directory", techlib.display()),
+            "Pass --techlib the path to yosys-synth_mc/techlib in the MCPNR checkout",
+        );
+        return info;
+    }
+    report.ok("techlib", format!("found {}", techlib.display()));
+
+    for file in ["cells_sim.v", "minecraft.lib"] {
+        if !techlib.join(file).is_file() {
+            report.warning(
+                "techlib",
+                format!("{} is missing {}", techlib.display(), file),
+                "Synthesis needs this file, make sure the techlib directory is complete",
+            );
+        }
+    }
+
+    let rules_file = techlib.join(BLOCKER_RULES_FILE);
+    let rules = match BlockerRules::load(&rules_file) {
+        Ok(rules) => {
+            if rules_file.is_file() {
+                report.ok(
+                    "blocker rules",
+                    format!("{} rules including the built in ones", rules.rules.len()),
+                );
+            }
+            rules
+        }
+        Err(e) => {
+            report.error(
+                "blocker rules",
+                format!("{:#}", e),
+                format!(
+                    "Fix {}, or remove it to use only the built in rules",
+                    rules_file.display()
+                ),
+            );
+            BlockerRules::builtin()
+        }
+    };
+
+    let structure_directory = techlib.join("structures");
+    let entries = match std::fs::read_dir(&structure_directory) {
+        Ok(entries) => entries,
+        Err(e) => {
+            report.error(
+                "structures",
+                format!("failed to read {}: {}", structure_directory.display(), e),
+                "The techlib must contain a \"structures\" folder with an NBT file per cell",
+            );
+            return info;
+        }
+    };
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| is_structure_file(&path.to_string_lossy()))
+        .collect();
+    paths.sort();
+
+    if paths.is_empty() {
+        report.error(
+            "structures",
+            format!("no .nbt or .schem files in {}", structure_directory.display()),
+            "Export the standard cells from Minecraft with structure blocks or WorldEdit in to this folder",
+        );
+        return info;
+    }
+
+    for path in paths {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        match load_structure(&path) {
+            Ok(structure) => {
+                info.structures.insert(name, structure);
+            }
+            Err(e) => report.error(
+                "structures",
+                format!("failed to load {}: {:#}", name, e),
+                "Re-export the structure from Minecraft, it should be a gzipped NBT file or schematic",
+            ),
+        }
+    }
+    report.ok(
+        "structures",
+        format!("loaded {} structures", info.structures.len()),
+    );
+
+    let mut unrecognized: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for (name, structure) in info.structures.iter() {
+        for block in structure.palette.iter() {
+            if rules.find(&block.name).is_none() {
+                unrecognized.entry(&block.name).or_default().push(name);
+            }
+        }
+    }
+    if unrecognized.is_empty() {
+        report.ok("blocker rules", "every block in the structures has a rule");
+    } else {
+        report.warning(
+            "blocker rules",
+            format!(
+                "no rule for {}, the router will route wires through them",
+                join_display(unrecognized.iter().map(|(block, names)| format!(
+                    "{} ({})",
+                    block,
+                    names.join(", ")
+                )))
+            ),
+            format!("Add rules for these blocks to {}", rules_file.display()),
+        );
+    }
+
+    let mut bad_pins = Vec::new();
+    for (name, structure) in info.structures.iter() {
+        let pins = match structure.pins(&rules) {
+            Ok(pins) => pins,
+            Err(e) => {
+                bad_pins.push(format!("{}: {:#}", name, e));
+                continue;
+            }
+        };
+        for pin in pins.iter().filter(|pin| pin.facing.is_none()) {
+            let [x, y, z] = pin.pos;
+            bad_pins.push(format!(
+                "{} at {} {} {} doesn't face a direction",
+                name, x, y, z
+            ));
+        }
+    }
+    if bad_pins.is_empty() {
+        report.ok("pins", "every pin faces north, south, east or west");
+    } else {
+        report.error(
+            "pins",
+            format!(
+                "the router can't use these pins: {}",
+                join_display(bad_pins.iter())
+            ),
+            "Fix the pin markers, and turn them to face straight north, south, east or west",
+        );
+    }
+
+    let mut versions: BTreeMap<i32, Vec<&str>> = BTreeMap::new();
+    for (name, structure) in info.structures.iter() {
+        versions
+            .entry(structure.data_version)
+            .or_default()
+            .push(name);
+    }
+    match versions.len() {
+        0 => {}
+        1 => report.ok(
+            "data versions",
+            format!(
+                "all structures use DataVersion {}",
+                versions.keys().next().unwrap()
+            ),
+        ),
+        _ => report.warning(
+            "data versions",
+            format!(
+                "structures were saved by different Minecraft versions: {}",
+                join_display(versions.iter().map(|(version, names)| format!(
+                    "{} ({})",
+                    version,
+                    names.join(", ")
+                )))
+            ),
+            "Re-export the outdated structures from the same Minecraft version",
+        ),
+    }
+
+    info
+}
+
+fn join_display<T: Display>(items: impl Iterator<Item = T>) -> String {
+    items.map(|i| i.to_string()).collect::<Vec<_>>().join("; ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check_techlib, Report, Severity};
+
+    #[test]
+    fn missing_techlib_is_an_error() {
+        let mut report = Report::new();
+        check_techlib(&mut report, "/nonexistent/techlib".as_ref());
+        assert!(report.has_errors());
+        assert_eq!(report.findings()[0].severity, Severity::Error);
+    }
+
+    #[test]
+    fn shipped_techlib_is_healthy() {
+        let techlib =
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../../yosys-synth_mc/techlib");
+        let mut report = Report::new();
+        let info = check_techlib(&mut report, &techlib);
+        assert!(!report.has_errors(), "{:?}", report);
+        assert!(info.structures.contains_key("gate_not.nbt"));
+    }
+}
@@ -0,0 +1,507 @@
+//! C ABI for loading designs and writing placements, so tools outside of Rust can hand designs to
+//! and take them from the MCPNR flow without reimplementing the Yosys JSON and placed design
+//! schemas. The declarations are in `include/mcpnr.h`.
+//!
+//! Designs and placements are opaque handles, freed with `mcpnr_design_free` and
+//! `mcpnr_placement_free`. Cells are referred to by index, in the order of their instance names.
+//! Functions returning `int` return 0 on success and -1 on failure, and functions returning a
+//! pointer return null on failure. `mcpnr_last_error` describes the last failure on the calling
+//! thread.
+//!
+//! Strings returned by these functions are owned by the handle they came from, and live as long
+//! as it does.
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+
+use crate::protos::mcpnr::{placed_design, PlacedDesign, Position};
+use crate::protos::{read_placed_design, write_placed_design, PLACED_DESIGN_VERSION};
+use crate::synthetic::{placed_cell, placed_nets};
+use crate::yosys::{self, Module};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Run `f`, recording its error or panic for `mcpnr_last_error`
+fn ffi_call<T>(f: impl FnOnce() -> Result<T>) -> Option<T> {
+    let result = catch_unwind(AssertUnwindSafe(f))
+        .unwrap_or_else(|_| Err(anyhow!("Panicked, this is a bug in mcpnr")));
+    match result {
+        Ok(value) => Some(value),
+        Err(e) => {
+            // Error messages come from Rust strings, which can't be converted only if they have
+            // an interior nul
+            let message =
+                CString::new(format!("{:?}", e).replace('\0', "")).expect("Nul bytes were removed");
+            LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+            None
+        }
+    }
+}
+
+fn status(result: Option<()>) -> c_int {
+    match result {
+        Some(()) => 0,
+        None => -1,
+    }
+}
+
+unsafe fn str_arg<'a>(s: *const c_char, what: &str) -> Result<&'a str> {
+    ensure!(!s.is_null(), "{} is null", what);
+    CStr::from_ptr(s)
+        .to_str()
+        .with_context(|| anyhow!("{} is not UTF-8", what))
+}
+
+unsafe fn handle<'a, T>(handle: *const T, what: &str) -> Result<&'a T> {
+    handle.as_ref().ok_or_else(|| anyhow!("{} is null", what))
+}
+
+unsafe fn handle_mut<'a, T>(handle: *mut T, what: &str) -> Result<&'a mut T> {
+    handle.as_mut().ok_or_else(|| anyhow!("{} is null", what))
+}
+
+/// Interior nul bytes can't be put in a C string, so names and types with them are cut short
+fn c_string(s: &str) -> CString {
+    let end = s.find('\0').unwrap_or(s.len());
+    CString::new(&s[..end]).expect("Cut before the first nul")
+}
+
+/// Instance name and type of a cell, as C strings
+struct CellStrings {
+    name: CString,
+    ty: CString,
+}
+
+impl CellStrings {
+    fn new(name: &str, ty: &str) -> Self {
+        Self {
+            name: c_string(name),
+            ty: c_string(ty),
+        }
+    }
+}
+
+/// Flattened module of a synthesized design
+pub struct McpnrDesign {
+    creator: String,
+    module: Module,
+    /// Instance names of the cells of `module`, sorted
+    names: Vec<String>,
+    strings: Vec<CellStrings>,
+}
+
+impl McpnrDesign {
+    fn cell(&self, idx: usize) -> Result<&CellStrings> {
+        self.strings.get(idx).ok_or_else(|| {
+            anyhow!(
+                "Cell {} is out of range, the design has {} cells",
+                idx,
+                self.strings.len()
+            )
+        })
+    }
+}
+
+/// Placed design, and the C strings of its cells
+pub struct McpnrPlacement {
+    design: PlacedDesign,
+    strings: Vec<CellStrings>,
+}
+
+impl McpnrPlacement {
+    fn new(design: PlacedDesign) -> Self {
+        let strings = design
+            .cells
+            .iter()
+            .map(|cell| CellStrings::new(&cell.name, &cell.r#type))
+            .collect();
+        Self { design, strings }
+    }
+
+    fn cell(&self, idx: usize) -> Result<&placed_design::Cell> {
+        self.design.cells.get(idx).ok_or_else(|| {
+            anyhow!(
+                "Cell {} is out of range, the placement has {} cells",
+                idx,
+                self.design.cells.len()
+            )
+        })
+    }
+
+    fn cell_mut(&mut self, idx: usize) -> Result<&mut placed_design::Cell> {
+        let len = self.design.cells.len();
+        self.design.cells.get_mut(idx).ok_or_else(|| {
+            anyhow!(
+                "Cell {} is out of range, the placement has {} cells",
+                idx,
+                len
+            )
+        })
+    }
+}
+
+fn load_design(path: &Path, module: &str) -> Result<McpnrDesign> {
+    let file = std::fs::File::open(path).with_context(|| anyhow!("Open design {:?}", path))?;
+    let design: yosys::Design = serde_json::from_reader(std::io::BufReader::new(file))
+        .with_context(|| anyhow!("Parse design {:?}", path))?;
+    let top = design
+        .modules
+        .get(module)
+        .ok_or_else(|| anyhow!("No module named {:?}", module))?;
+    let module = design
+        .flatten(top)
+        .with_context(|| anyhow!("Flatten {:?}", module))?;
+
+    let mut names: Vec<String> = module.cells.keys().cloned().collect();
+    names.sort();
+    let strings = names
+        .iter()
+        .map(|name| CellStrings::new(name, &module.cells[name].ty))
+        .collect();
+    Ok(McpnrDesign {
+        creator: design.creator,
+        module,
+        names,
+        strings,
+    })
+}
+
+/// Description of the last failure on this thread, or null if nothing has failed. The string is
+/// valid until the next failure on this thread.
+#[no_mangle]
+pub extern "C" fn mcpnr_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Load the Yosys JSON design at `path` and flatten `module`, or the `top` module if `module` is
+/// null.
+///
+/// # Safety
+///
+/// `path` must be a nul terminated string, and `module` one or null.
+#[no_mangle]
+pub unsafe extern "C" fn mcpnr_design_load(
+    path: *const c_char,
+    module: *const c_char,
+) -> *mut McpnrDesign {
+    ffi_call(|| {
+        let path = str_arg(path, "path")?;
+        let module = if module.is_null() {
+            "top"
+        } else {
+            str_arg(module, "module")?
+        };
+        load_design(path.as_ref(), module)
+    })
+    .map_or(std::ptr::null_mut(), |design| {
+        Box::into_raw(Box::new(design))
+    })
+}
+
+/// Free a design from `mcpnr_design_load`, which may be null
+///
+/// # Safety
+///
+/// `design` must be null or come from `mcpnr_design_load`, and not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn mcpnr_design_free(design: *mut McpnrDesign) {
+    if !design.is_null() {
+        drop(Box::from_raw(design));
+    }
+}
+
+/// Number of cells in the design, or 0 if `design` is null
+///
+/// # Safety
+///
+/// `design` must be null or a live design.
+#[no_mangle]
+pub unsafe extern "C" fn mcpnr_design_cell_count(design: *const McpnrDesign) -> usize {
+    design.as_ref().map_or(0, |design| design.strings.len())
+}
+
+/// Instance name of cell `idx` of the design
+///
+/// # Safety
+///
+/// `design` must be null or a live design.
+#[no_mangle]
+pub unsafe extern "C" fn mcpnr_design_cell_name(
+    design: *const McpnrDesign,
+    idx: usize,
+) -> *const c_char {
+    ffi_call(|| Ok(handle(design, "design")?.cell(idx)?.name.as_ptr())).unwrap_or(std::ptr::null())
+}
+
+/// Type of cell `idx` of the design, its structure file or an I/O macro like `MCPNR_SWITCHES`
+///
+/// # Safety
+///
+/// `design` must be null or a live design.
+#[no_mangle]
+pub unsafe extern "C" fn mcpnr_design_cell_type(
+    design: *const McpnrDesign,
+    idx: usize,
+) -> *const c_char {
+    ffi_call(|| Ok(handle(design, "design")?.cell(idx)?.ty.as_ptr())).unwrap_or(std::ptr::null())
+}
+
+/// Start a placement of the design, with the same cells in the same order and none of them placed
+///
+/// # Safety
+///
+/// `design` must be null or a live design.
+#[no_mangle]
+pub unsafe extern "C" fn mcpnr_placement_new(design: *const McpnrDesign) -> *mut McpnrPlacement {
+    ffi_call(|| {
+        let design = handle(design, "design")?;
+        let cells = design
+            .names
+            .iter()
+            .map(|name| {
+                placed_cell(name, &design.module.cells[name], None)
+                    .with_context(|| anyhow!("Convert cell {:?}", name))
+            })
+            .collect::<Result<_>>()?;
+        Ok(McpnrPlacement::new(PlacedDesign {
+            creator: design.creator.clone(),
+            cells,
+            nets: placed_nets(&design.module),
+            version: PLACED_DESIGN_VERSION,
+            provenance: Vec::new(),
+        }))
+    })
+    .map_or(std::ptr::null_mut(), |p| Box::into_raw(Box::new(p)))
+}
+
+/// Load a placed design, as JSON if `path` ends in `.json`, in the streaming format if it ends in
+/// `.pbs` or `.jsonl` and as protobuf otherwise
+///
+/// # Safety
+///
+/// `path` must be a nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn mcpnr_placement_load(path: *const c_char) -> *mut McpnrPlacement {
+    ffi_call(|| {
+        let path = str_arg(path, "path")?;
+        Ok(McpnrPlacement::new(read_placed_design(path.as_ref())?))
+    })
+    .map_or(std::ptr::null_mut(), |p| Box::into_raw(Box::new(p)))
+}
+
+/// Free a placement from `mcpnr_placement_new` or `mcpnr_placement_load`, which may be null
+///
+/// # Safety
+///
+/// `placement` must be null or come from one of those, and not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn mcpnr_placement_free(placement: *mut McpnrPlacement) {
+    if !placement.is_null() {
+        drop(Box::from_raw(placement));
+    }
+}
+
+/// Number of cells in the placement, or 0 if `placement` is null
+///
+/// # Safety
+///
+/// `placement` must be null or a live placement.
+#[no_mangle]
+pub unsafe extern "C" fn mcpnr_placement_cell_count(placement: *const McpnrPlacement) -> usize {
+    placement.as_ref().map_or(0, |p| p.design.cells.len())
+}
+
+/// Instance name of cell `idx` of the placement, empty for designs placed before names were kept
+///
+/// # Safety
+///
+/// `placement` must be null or a live placement.
+#[no_mangle]
+pub unsafe extern "C" fn mcpnr_placement_cell_name(
+    placement: *const McpnrPlacement,
+    idx: usize,
+) -> *const c_char {
+    ffi_call(|| {
+        let placement = handle(placement, "placement")?;
+        placement.cell(idx)?;
+        Ok(placement.strings[idx].name.as_ptr())
+    })
+    .unwrap_or(std::ptr::null())
+}
+
+/// Type of cell `idx` of the placement
+///
+/// # Safety
+///
+/// `placement` must be null or a live placement.
+#[no_mangle]
+pub unsafe extern "C" fn mcpnr_placement_cell_type(
+    placement: *const McpnrPlacement,
+    idx: usize,
+) -> *const c_char {
+    ffi_call(|| {
+        let placement = handle(placement, "placement")?;
+        placement.cell(idx)?;
+        Ok(placement.strings[idx].ty.as_ptr())
+    })
+    .unwrap_or(std::ptr::null())
+}
+
+/// Minimum corner of cell `idx`, in blocks. Fails if the cell hasn't been placed.
+///
+/// # Safety
+///
+/// `placement` must be null or a live placement, and `x`, `y` and `z` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn mcpnr_placement_cell_position(
+    placement: *const McpnrPlacement,
+    idx: usize,
+    x: *mut u32,
+    y: *mut u32,
+    z: *mut u32,
+) -> c_int {
+    status(ffi_call(|| {
+        ensure!(
+            !x.is_null() && !y.is_null() && !z.is_null(),
+            "Position outputs are null"
+        );
+        let cell = handle(placement, "placement")?.cell(idx)?;
+        let pos = cell
+            .pos
+            .as_ref()
+            .ok_or_else(|| anyhow!("Cell {} is not placed", cell.describe(idx)))?;
+        *x = pos.x;
+        *y = pos.y;
+        *z = pos.z;
+        Ok(())
+    }))
+}
+
+/// Place the minimum corner of cell `idx` at (`x`, `y`, `z`), in blocks
+///
+/// # Safety
+///
+/// `placement` must be null or a live placement.
+#[no_mangle]
+pub unsafe extern "C" fn mcpnr_placement_set_position(
+    placement: *mut McpnrPlacement,
+    idx: usize,
+    x: u32,
+    y: u32,
+    z: u32,
+) -> c_int {
+    status(ffi_call(|| {
+        handle_mut(placement, "placement")?.cell_mut(idx)?.pos = Some(Position { x, y, z });
+        Ok(())
+    }))
+}
+
+/// Write the placement for the router, in the format `mcpnr_placement_load` reads from `path`.
+/// Fails if any cell hasn't been placed.
+///
+/// # Safety
+///
+/// `placement` must be null or a live placement, and `path` a nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn mcpnr_placement_write(
+    placement: *const McpnrPlacement,
+    path: *const c_char,
+) -> c_int {
+    status(ffi_call(|| {
+        let placement = handle(placement, "placement")?;
+        let path = str_arg(path, "path")?;
+        let mut cells = placement.design.cells.iter().enumerate();
+        if let Some((idx, cell)) = cells.find(|(_, cell)| cell.pos.is_none()) {
+            bail!("Cell {} is not placed", cell.describe(idx));
+        }
+        write_placed_design(&placement.design, path.as_ref())
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::{CStr, CString};
+
+    use super::*;
+    use crate::synthetic::SyntheticDesign;
+
+    fn path_arg(path: &Path) -> CString {
+        CString::new(path.to_str().expect("Temporary paths are UTF-8")).unwrap()
+    }
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(mcpnr_last_error()) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn placement_round_trips() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("mcpnr-ffi-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let design_path = dir.join("design.json");
+        let design = SyntheticDesign::AdderChain { bits: 4 }.generate(0)?;
+        std::fs::write(&design_path, serde_json::to_vec(&design)?)?;
+
+        unsafe {
+            let design = mcpnr_design_load(path_arg(&design_path).as_ptr(), std::ptr::null());
+            assert!(!design.is_null(), "{}", last_error());
+            let count = mcpnr_design_cell_count(design);
+            assert_eq!(count, design.as_ref().unwrap().module.cells.len());
+            assert!(mcpnr_design_cell_name(design, count).is_null());
+
+            let placement = mcpnr_placement_new(design);
+            assert!(!placement.is_null(), "{}", last_error());
+            assert_eq!(mcpnr_placement_cell_count(placement), count);
+
+            let output = dir.join("placed.pb");
+            let output_arg = path_arg(&output);
+            assert_eq!(mcpnr_placement_write(placement, output_arg.as_ptr()), -1);
+            assert!(last_error().contains("is not placed"));
+
+            for idx in 0..count {
+                let x = idx as u32 * 4;
+                assert_eq!(mcpnr_placement_set_position(placement, idx, x, 0, 8), 0);
+            }
+            assert_eq!(mcpnr_placement_write(placement, output_arg.as_ptr()), 0);
+
+            let read = mcpnr_placement_load(output_arg.as_ptr());
+            assert!(!read.is_null(), "{}", last_error());
+            assert_eq!((*read).design, (*placement).design);
+            for idx in 0..count {
+                assert_eq!(
+                    CStr::from_ptr(mcpnr_placement_cell_name(read, idx)),
+                    CStr::from_ptr(mcpnr_design_cell_name(design, idx))
+                );
+                assert_eq!(
+                    CStr::from_ptr(mcpnr_placement_cell_type(read, idx)),
+                    CStr::from_ptr(mcpnr_design_cell_type(design, idx))
+                );
+                let (mut x, mut y, mut z) = (0, 0, 0);
+                assert_eq!(
+                    mcpnr_placement_cell_position(read, idx, &mut x, &mut y, &mut z),
+                    0
+                );
+                assert_eq!((x, y, z), (idx as u32 * 4, 0, 8));
+            }
+
+            mcpnr_placement_free(read);
+            mcpnr_placement_free(placement);
+            mcpnr_design_free(design);
+        }
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+//! Block layouts for the MCPNR_LIGHTS and MCPNR_SWITCHES I/O macros. These are built in code
+//! rather than loaded from the techlib, so the router (which splats them) and the placer (which
+//! needs their footprints) share the layout from here.
+
+use anyhow::Result;
+
+use crate::block_storage::{Block, BlockStorage, PropertyValue};
+
+/// Position of the pin for bit `bit` of a light or switch macro, relative to its minimum corner.
+/// Both macros have their pins on the sign at the Z+ end of each light or switch.
+pub fn pin_offset(bit: u32) -> [u32; 3] {
+    [bit * 2, 1, 2]
+}
+
+fn block_with_properties(name: &str, properties: &[(&str, PropertyValue)]) -> Block {
+    Block {
+        name: name.to_owned(),
+        properties: Some(
+            properties
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.clone()))
+                .collect(),
+        ),
+    }
+}
+
+/// Draw `nlights` lights with their minimum corner at `base`. Each light is 2x2x3 blocks, with the
+/// input pin on the Z+ side.
+pub fn splat_lights(o: &mut BlockStorage, base: (u32, u32, u32), nlights: i64) -> Result<()> {
+    let (base_x, base_y, base_z) = base;
+
+    let b_air = o.add_new_block_type(Block::new("minecraft:air".to_owned()));
+    let b_calcite = o.add_new_block_type(Block::new("minecraft:calcite".to_owned()));
+    let b_light = o.add_new_block_type(Block::new("minecraft:redstone_lamp".to_owned()));
+    let z_repeater = o.add_new_block_type(block_with_properties(
+        "minecraft:repeater",
+        &[("facing", PropertyValue::String("south".to_owned()))],
+    ));
+    let z_sign = o.add_new_block_type(block_with_properties(
+        "minecraft:oak_sign",
+        &[("rotation", PropertyValue::Byte(0))],
+    ));
+
+    for light in 0..nlights {
+        let light_x = (light * 2) as u32 + base_x;
+
+        *(o.get_block_mut(light_x, base_y, base_z)?) = b_calcite;
+        *(o.get_block_mut(light_x, base_y + 1, base_z)?) = b_light;
+        *(o.get_block_mut(light_x + 1, base_y, base_z)?) = b_calcite;
+        *(o.get_block_mut(light_x + 1, base_y + 1, base_z)?) = b_calcite;
+
+        *(o.get_block_mut(light_x, base_y, base_z + 1)?) = b_calcite;
+        *(o.get_block_mut(light_x, base_y + 1, base_z + 1)?) = z_repeater;
+        *(o.get_block_mut(light_x + 1, base_y, base_z + 1)?) = b_air;
+        *(o.get_block_mut(light_x + 1, base_y + 1, base_z + 1)?) = b_air;
+
+        *(o.get_block_mut(light_x, base_y + 1, base_z + 2)?) = z_sign;
+        *(o.get_block_mut(light_x, base_y, base_z + 2)?) = b_calcite;
+    }
+
+    Ok(())
+}
+
+/// Draw `nswitches` switches with their minimum corner at `base`. Each switch is 2x2x3 blocks, with
+/// the output pin on the Z+ side.
+pub fn splat_switches(o: &mut BlockStorage, base: (u32, u32, u32), nswitches: i64) -> Result<()> {
+    let (base_x, base_y, base_z) = base;
+
+    let b_air = o.add_new_block_type(Block::new("minecraft:air".to_owned()));
+    let b_calcite = o.add_new_block_type(Block::new("minecraft:calcite".to_owned()));
+    let b_switch = o.add_new_block_type(block_with_properties(
+        "minecraft:lever",
+        &[
+            ("face", PropertyValue::String("wall".to_owned())),
+            ("facing", PropertyValue::String("north".to_owned())),
+        ],
+    ));
+    let z_sign = o.add_new_block_type(block_with_properties(
+        "minecraft:oak_sign",
+        &[("rotation", PropertyValue::Byte(8))],
+    ));
+
+    for switch in 0..nswitches {
+        let switch_x = (switch * 2) as u32 + base_x;
+
+        *(o.get_block_mut(switch_x, base_y, base_z)?) = b_air;
+        *(o.get_block_mut(switch_x, base_y + 1, base_z)?) = b_switch;
+        *(o.get_block_mut(switch_x + 1, base_y, base_z)?) = b_air;
+        *(o.get_block_mut(switch_x + 1, base_y + 1, base_z)?) = b_air;
+
+        *(o.get_block_mut(switch_x, base_y, base_z + 1)?) = b_calcite;
+        *(o.get_block_mut(switch_x, base_y + 1, base_z + 1)?) = b_calcite;
+        *(o.get_block_mut(switch_x + 1, base_y, base_z + 1)?) = b_calcite;
+        *(o.get_block_mut(switch_x + 1, base_y + 1, base_z + 1)?) = b_calcite;
+
+        *(o.get_block_mut(switch_x, base_y + 1, base_z + 2)?) = z_sign;
+        *(o.get_block_mut(switch_x, base_y, base_z + 2)?) = b_calcite;
+    }
+
+    Ok(())
+}
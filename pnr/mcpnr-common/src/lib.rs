@@ -1,6 +1,20 @@
+pub mod anvil;
 pub mod block_storage;
+pub mod blockage_map;
+pub mod blockers;
+pub mod doctor;
+pub mod ffi;
+pub mod io_macros;
+pub mod memory;
 pub mod minecraft_types;
+pub mod net_weights;
 pub mod protos;
+pub mod provenance;
+pub mod results;
+pub mod schematic;
+pub mod synthetic;
+pub mod timing_constraints;
+pub mod xorshift;
 pub mod yosys;
 
 pub use prost;
@@ -15,6 +29,10 @@ pub const BLOCKS_PER_Z_ROW: u32 = 8;
 /// for routing. The cell layer is 8 blocks high, and each metal layer is 2 blocks high
 pub const BLOCKS_PER_TIER: u32 = 16;
 
+/// Maximum number of tiers in a design. This many tiers fill the 384 block build height of a
+/// Minecraft 1.18+ world.
+pub const MAX_TIERS: u32 = 384 / BLOCKS_PER_TIER;
+
 /// Error generated when cell attribute retrieval fails
 #[derive(Debug)]
 pub enum CellGetAttribError {
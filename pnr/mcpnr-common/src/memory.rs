@@ -0,0 +1,131 @@
+//! Accounting for the big allocations of a run. Each of them is reserved against an optional
+//! limit before it's made, so a design that won't fit in memory fails with a clear error up front
+//! instead of being killed part way through.
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use log::info;
+
+const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+/// Running total of the memory reserved by a run, checked against a limit if there is one
+#[derive(Clone, Debug, Default)]
+pub struct MemoryBudget {
+    limit: Option<u64>,
+    used: u64,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: Option<u64>) -> Self {
+        Self { limit, used: 0 }
+    }
+
+    /// Reserve `bytes` for `what`, or fail if that would go over the limit. `hint` says how to
+    /// make the run need less, and ends up in the error.
+    pub fn reserve(&mut self, what: &str, bytes: u64, hint: &str) -> Result<()> {
+        info!("{} needs {}", what, format_bytes(bytes));
+
+        let used = self.used.saturating_add(bytes);
+        if let Some(limit) = self.limit {
+            if used > limit {
+                bail!(
+                    "{} would need {}, which is over the {} memory limit with the {} already \
+                     reserved; {}",
+                    what,
+                    format_bytes(bytes),
+                    format_bytes(limit),
+                    format_bytes(self.used),
+                    hint
+                );
+            }
+        }
+        self.used = used;
+
+        Ok(())
+    }
+
+    /// Total memory reserved so far, in bytes
+    pub fn used(&self) -> u64 {
+        self.used
+    }
+}
+
+/// Format a number of bytes in the largest binary unit it has at least one of, like `12 GiB`
+pub fn format_bytes(bytes: u64) -> String {
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 || value.fract() == 0.0 {
+        format!("{} {}", value, UNITS[unit])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Parse a memory size given on the command line, like `512M`, `1.5GiB` or a plain number of
+/// bytes. The units are binary whether or not they have the `i`.
+pub fn parse_size(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number
+        .parse()
+        .with_context(|| anyhow!("Invalid memory size {:?}", s))?;
+
+    let unit = unit.trim().to_ascii_lowercase();
+    let unit = unit.trim_end_matches('b').trim_end_matches('i');
+    let scale = match unit {
+        "" => 1u64,
+        "k" => 1 << 10,
+        "m" => 1 << 20,
+        "g" => 1 << 30,
+        "t" => 1 << 40,
+        _ => bail!("Unknown unit in memory size {:?}", s),
+    };
+
+    let bytes = number * scale as f64;
+    ensure!(bytes < u64::MAX as f64, "Memory size {:?} is too large", s);
+    Ok(bytes as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{format_bytes, parse_size, MemoryBudget};
+
+    #[test]
+    fn sizes_round_trip() -> anyhow::Result<()> {
+        assert_eq!(parse_size("1024")?, 1024);
+        assert_eq!(parse_size("512M")?, 512 << 20);
+        assert_eq!(parse_size("1.5GiB")?, 3 << 29);
+        assert_eq!(parse_size("2 gb")?, 2 << 30);
+        assert!(parse_size("12 parsecs").is_err());
+
+        assert_eq!(format_bytes(100), "100 B");
+        assert_eq!(format_bytes(12 << 30), "12 GiB");
+        assert_eq!(format_bytes(3 << 29), "1.5 GiB");
+
+        Ok(())
+    }
+
+    #[test]
+    fn reserve_fails_over_the_limit() {
+        let mut budget = MemoryBudget::new(Some(1 << 20));
+        assert!(budget.reserve("Output", 512 << 10, "use less").is_ok());
+        let error = budget
+            .reserve("Routing grid", 768 << 10, "reduce --tiers")
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("Routing grid would need 768 KiB"));
+        assert!(error.to_string().ends_with("reduce --tiers"));
+        assert_eq!(budget.used(), 512 << 10);
+
+        let mut unlimited = MemoryBudget::new(None);
+        assert!(unlimited.reserve("Output", u64::MAX, "").is_ok());
+    }
+}
@@ -1,5 +1,46 @@
-use quartz_nbt::NbtCompound;
+use anyhow::{anyhow, Context, Result};
+use quartz_nbt::{NbtCompound, NbtList, NbtTag};
 use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::path::Path;
+
+use crate::block_storage::{Block, BlockStorage, Direction, PropertyValue};
+use crate::blockers::{pin_direction, BlockerAction, BlockerRules};
+use crate::schematic;
+
+/// File extensions standard cells can be loaded from: vanilla structures, and WorldEdit
+/// schematics
+pub const STRUCTURE_EXTENSIONS: [&str; 2] = ["nbt", schematic::SCHEMATIC_EXTENSION];
+
+/// Whether a cell type names a structure file, rather than being a special cell like the I/O
+/// macros
+pub fn is_structure_file(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .is_some_and(|ext| STRUCTURE_EXTENSIONS.iter().any(|e| ext == *e))
+}
+
+/// Load a standard cell from a structure file, going by its extension to tell vanilla structures
+/// from WorldEdit schematics.
+pub fn load_structure(path: &Path) -> Result<Structure> {
+    let mut file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open structure file {:?} for reading", path))?;
+    read_structure(&mut file, path)
+}
+
+/// Read a standard cell from `r`, going by the extension of `name` to tell vanilla structures from
+/// WorldEdit schematics. For structures that don't come from a file, like the ones bundled in to
+/// the web build of the placer.
+pub fn read_structure<R: Read>(r: &mut R, name: &Path) -> Result<Structure> {
+    if name.extension() == Some(schematic::SCHEMATIC_EXTENSION.as_ref()) {
+        schematic::read_structure(r)
+    } else {
+        let (structure, _) =
+            quartz_nbt::serde::deserialize_from(r, quartz_nbt::io::Flavor::GzCompressed)?;
+        Ok(structure)
+    }
+    .with_context(|| format!("Failed to parse structure {:?}", name))
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
@@ -8,6 +49,56 @@ pub struct PaletteBlock {
     pub properties: Option<NbtCompound>,
 }
 
+impl PaletteBlock {
+    /// Convert to a block storage block, which only supports byte and string properties.
+    pub fn to_block(&self) -> Result<Block> {
+        let properties = match self.properties.as_ref() {
+            Some(c) => Some(
+                c.inner()
+                    .iter()
+                    .map(|(k, v)| {
+                        let v = match v {
+                            quartz_nbt::NbtTag::Byte(ref v) => PropertyValue::Byte(*v),
+                            quartz_nbt::NbtTag::String(ref s) => {
+                                PropertyValue::String(s.to_owned())
+                            }
+                            _ => {
+                                return Err(anyhow!("Unsupported property tag in mapping {:?}", v))
+                            }
+                        };
+                        Ok((k.to_owned(), v))
+                    })
+                    .collect::<Result<_>>()
+                    .with_context(|| format!("While mapping block {:?}", self))?,
+            ),
+            None => None,
+        };
+
+        Ok(Block {
+            name: self.name.clone(),
+            properties,
+        })
+    }
+
+    /// Redstone ticks a signal spends passing through this block. Repeaters take their delay
+    /// setting, comparators and torches a single tick, and everything else passes signals on
+    /// instantly.
+    pub fn delay_ticks(&self) -> u32 {
+        match self.name.as_str() {
+            "minecraft:repeater" => self
+                .properties
+                .as_ref()
+                .and_then(|p| p.get::<_, &str>("delay").ok())
+                .and_then(|delay| delay.parse().ok())
+                .unwrap_or(1),
+            "minecraft:comparator"
+            | "minecraft:redstone_torch"
+            | "minecraft:redstone_wall_torch" => 1,
+            _ => 0,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct StructureBlock {
     pub state: i32,
@@ -15,6 +106,131 @@ pub struct StructureBlock {
     pub nbt: Option<NbtCompound>,
 }
 
+/// Direction of a cell pin, from the point of view of the cell
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PinDirection {
+    Input,
+    Output,
+}
+
+/// A pin of a standard cell, marked in its structure with the port name, the direction (`INPUT`
+/// or `OUTPUT`) and optionally a derating like `SIG-2`. The usual marker is a sign with those on
+/// its lines, but a techlib can also use an item frame holding an item named with them, separated
+/// by spaces.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StructurePin {
+    pub port: String,
+    /// Position of the marker in the structure
+    pub pos: [i32; 3],
+    pub direction: PinDirection,
+    pub sig_derating: u32,
+    /// Direction wires connect to the pin from, if the marker faces one. Markers on a cell's side
+    /// walls face horizontally, and ones on its top or underneath face up or down.
+    pub facing: Option<Direction>,
+}
+
+/// Read the text of one line of a sign
+fn sign_text(nbt: &NbtCompound, element: &str) -> Result<String> {
+    let content = nbt.get::<_, &str>(element).context("Get NBT tag")?;
+    let content: serde_json::Value = serde_json::from_str(content).context("JSON parse")?;
+    let content = content
+        .as_object()
+        .ok_or_else(|| anyhow!("JSON content root was not object, got {:?}", content))?;
+    let content = content
+        .get("text")
+        .ok_or_else(|| anyhow!("Text object was missing 'text' attribute: {:?}", content))?;
+    let content = content
+        .as_str()
+        .ok_or_else(|| anyhow!("Text object was not text, was {}", content))?;
+
+    Ok(content.to_owned())
+}
+
+impl StructurePin {
+    /// Parse the pin marked by a sign with block entity `nbt` at `pos`
+    fn from_sign(pos: [i32; 3], facing: Option<Direction>, nbt: &NbtCompound) -> Result<Self> {
+        let text1 = sign_text(nbt, "Text1").context("Extract Text1")?;
+        let text2 = sign_text(nbt, "Text2").context("Extract Text2")?;
+        let text3 = sign_text(nbt, "Text3").context("Extract Text3")?;
+
+        Self::from_text(pos, facing, &text1, &text2, &text3)
+    }
+
+    /// Parse the pin marked by an item frame entity `nbt` at `pos`, from the name of the item in
+    /// it
+    fn from_item_frame(pos: [i32; 3], nbt: &NbtCompound) -> Result<Self> {
+        let item = nbt
+            .get::<_, &NbtCompound>("Item")
+            .context("Item frame is empty")?;
+        let display = item
+            .get::<_, &NbtCompound>("tag")
+            .and_then(|tag| tag.get::<_, &NbtCompound>("display"))
+            .context("Item in frame has no name")?;
+        let name = sign_text(display, "Name").context("Extract item name")?;
+        let mut words = name.split_whitespace();
+        let port = words
+            .next()
+            .ok_or_else(|| anyhow!("Item name {:?} has no port name", name))?;
+        let direction = words.next().unwrap_or_default();
+        let derating = words.next().unwrap_or_default();
+
+        // Item frames face away from the block they hang on, like wall signs. Ones lying on a
+        // floor face up, and ones on a ceiling face down.
+        let facing = match nbt.get::<_, i8>("Facing").ok() {
+            Some(0) => Some(Direction::Down),
+            Some(1) => Some(Direction::Up),
+            Some(2) => Some(Direction::North),
+            Some(3) => Some(Direction::South),
+            Some(4) => Some(Direction::West),
+            Some(5) => Some(Direction::East),
+            _ => None,
+        };
+
+        Self::from_text(pos, facing, port, direction, derating)
+    }
+
+    fn from_text(
+        pos: [i32; 3],
+        facing: Option<Direction>,
+        port: &str,
+        direction: &str,
+        derating: &str,
+    ) -> Result<Self> {
+        let direction = match direction {
+            "INPUT" => PinDirection::Input,
+            "OUTPUT" => PinDirection::Output,
+            _ => return Err(anyhow!("Unknown pin direction {}", direction)),
+        };
+
+        let sig_derating = derating
+            .split_once('-')
+            .map(|(_, derating)| {
+                derating
+                    .parse::<u32>()
+                    .with_context(|| anyhow!("Convert integer {:?}", derating))
+            })
+            .unwrap_or(Ok(0))
+            .with_context(|| anyhow!("Parse derating from {:?}", derating))?;
+
+        Ok(Self {
+            port: port.to_owned(),
+            pos,
+            direction,
+            sig_derating,
+            facing,
+        })
+    }
+}
+
+/// An entity saved with a structure, like an item frame
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StructureEntity {
+    /// Position of the block the entity is in
+    #[serde(rename = "blockPos")]
+    pub block_pos: [i32; 3],
+    pub nbt: NbtCompound,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Structure {
     #[serde(rename = "DataVersion")]
@@ -22,4 +238,195 @@ pub struct Structure {
     pub size: [i32; 3],
     pub palette: Vec<PaletteBlock>,
     pub blocks: Vec<StructureBlock>,
+    #[serde(default)]
+    pub entities: Vec<StructureEntity>,
+}
+
+impl Structure {
+    /// Copy the blocks of this structure in to a block storage of the same size.
+    pub fn to_block_storage(&self) -> Result<BlockStorage> {
+        let [sx, sy, sz] = self.size.map(|s| s.max(0) as u32);
+        let mut storage = BlockStorage::new(sx, sy, sz);
+        let palette = self
+            .palette
+            .iter()
+            .map(|block| Ok(storage.add_new_block_type(block.to_block()?)))
+            .collect::<Result<Vec<_>>>()?;
+
+        for block in self.blocks.iter() {
+            let [x, y, z] = block.pos;
+            *storage.get_block_mut(x.try_into()?, y.try_into()?, z.try_into()?)? = *palette
+                .get(block.state as usize)
+                .with_context(|| format!("Invalid block state index {:?}", block.state))?;
+        }
+
+        Ok(storage)
+    }
+
+    /// Pins of the cell: first the blocks `rules` mark as pins, which carry the pin like a sign
+    /// does, then the entities `rules` mark as pins, which carry it like an item frame does. Each
+    /// kind is in the order it appears in the structure. A port may have more than one pin.
+    pub fn pins(&self, rules: &BlockerRules) -> Result<Vec<StructurePin>> {
+        let is_pin = |name: &str| rules.find(name) == Some(&BlockerAction::Pin);
+        let mut pins = Vec::new();
+
+        for block in self.blocks.iter() {
+            let palette_block = match self.palette.get(block.state as usize) {
+                Some(palette_block) if is_pin(&palette_block.name) => palette_block,
+                _ => continue,
+            };
+            let nbt = block.nbt.as_ref().ok_or_else(|| {
+                anyhow!("Pin {} at {:?} has no text", palette_block.name, block.pos)
+            })?;
+            let facing = palette_block
+                .to_block()
+                .and_then(|block| pin_direction(&block))
+                .ok();
+            pins.push(
+                StructurePin::from_sign(block.pos, facing, nbt)
+                    .with_context(|| anyhow!("Reading pin at {:?}", block.pos))?,
+            );
+        }
+
+        for entity in self.entities.iter() {
+            if !entity.nbt.get::<_, &str>("id").is_ok_and(is_pin) {
+                continue;
+            }
+            pins.push(
+                StructurePin::from_item_frame(entity.block_pos, &entity.nbt)
+                    .with_context(|| anyhow!("Reading pin at {:?}", entity.block_pos))?,
+            );
+        }
+
+        Ok(pins)
+    }
+
+    /// Delay through the structure, counting every delaying block once. This is pessimistic for
+    /// cells where no signal passes through all of them, but never underestimates.
+    pub fn delay_ticks(&self) -> u32 {
+        self.blocks
+            .iter()
+            .filter_map(|block| self.palette.get(block.state as usize))
+            .map(PaletteBlock::delay_ticks)
+            .sum()
+    }
+}
+
+/// Convert block entity data from the JSON form [`BlockStorage`] keeps it in to NBT.
+pub fn json_to_nbt(value: &serde_json::Value) -> Result<NbtTag> {
+    use serde_json::Value;
+    Ok(match value {
+        Value::Bool(b) => NbtTag::Byte(*b as i8),
+        Value::Number(n) => match (n.as_i64(), n.as_f64()) {
+            (Some(i), _) => match i32::try_from(i) {
+                Ok(i) => NbtTag::Int(i),
+                Err(_) => NbtTag::Long(i),
+            },
+            (None, Some(f)) => NbtTag::Double(f),
+            _ => return Err(anyhow!("Unsupported number {} in block entity", n)),
+        },
+        Value::String(s) => NbtTag::String(s.clone()),
+        Value::Array(values) => NbtTag::List(NbtList::from(
+            values.iter().map(json_to_nbt).collect::<Result<Vec<_>>>()?,
+        )),
+        Value::Object(fields) => {
+            let mut compound = NbtCompound::new();
+            for (name, value) in fields.iter() {
+                compound.insert(name.clone(), json_to_nbt(value)?);
+            }
+            NbtTag::Compound(compound)
+        }
+        Value::Null => return Err(anyhow!("Block entities can't contain null")),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use quartz_nbt::{compound, NbtCompound};
+
+    use super::{PaletteBlock, PinDirection, Structure, StructureBlock, StructureEntity};
+    use crate::block_storage::Direction;
+    use crate::blockers::BlockerRules;
+
+    fn text(text: &str) -> String {
+        format!("{{\"text\":\"{}\"}}", text)
+    }
+
+    /// A wall sign pin and an item frame pin hanging off a target block
+    fn marked_structure() -> Structure {
+        let palette_block = |name: &str, properties: Option<NbtCompound>| PaletteBlock {
+            name: name.to_owned(),
+            properties,
+        };
+        Structure {
+            data_version: 0,
+            size: [3, 1, 1],
+            palette: vec![
+                palette_block(
+                    "minecraft:birch_wall_sign",
+                    Some(compound! { "facing": "east" }),
+                ),
+                palette_block("minecraft:target", None),
+            ],
+            blocks: vec![
+                StructureBlock {
+                    state: 0,
+                    pos: [0, 0, 0],
+                    nbt: Some(compound! {
+                        "id": "minecraft:sign",
+                        "Text1": text("A"),
+                        "Text2": text("INPUT"),
+                        "Text3": text(""),
+                    }),
+                },
+                StructureBlock {
+                    state: 1,
+                    pos: [1, 0, 0],
+                    nbt: None,
+                },
+            ],
+            entities: vec![StructureEntity {
+                block_pos: [2, 0, 0],
+                nbt: compound! {
+                    "id": "minecraft:item_frame",
+                    "Facing": 5i8,
+                    "Item": {
+                        "id": "minecraft:stick",
+                        "Count": 1i8,
+                        "tag": { "display": { "Name": text("Y OUTPUT SIG-2") } },
+                    },
+                },
+            }],
+        }
+    }
+
+    #[test]
+    fn pins_come_from_any_sign_and_configured_entities() -> anyhow::Result<()> {
+        let structure = marked_structure();
+
+        let pins = structure.pins(&BlockerRules::builtin())?;
+        assert_eq!(pins.len(), 1);
+        assert_eq!(pins[0].port, "A");
+        assert_eq!(pins[0].direction, PinDirection::Input);
+        assert_eq!(pins[0].facing, Some(Direction::East));
+
+        let mut rules: BlockerRules = serde_json::from_str(
+            r#"{"rules": [{"pattern": "minecraft:*item_frame", "kind": "pin"}]}"#,
+        )?;
+        rules.rules.extend(BlockerRules::builtin().rules);
+        let pins = structure.pins(&rules)?;
+        assert_eq!(pins.len(), 2);
+        assert_eq!(pins[1].port, "Y");
+        assert_eq!(pins[1].pos, [2, 0, 0]);
+        assert_eq!(pins[1].direction, PinDirection::Output);
+        assert_eq!(pins[1].sig_derating, 2);
+        assert_eq!(pins[1].facing, Some(Direction::East));
+
+        // Lying on top of the cell instead
+        let mut structure = structure;
+        structure.entities[0].nbt.insert("Facing", 1i8);
+        assert_eq!(structure.pins(&rules)?[1].facing, Some(Direction::Up));
+
+        Ok(())
+    }
 }
@@ -0,0 +1,79 @@
+//! Net weights fed back from a routing attempt to the next placement. The router writes one of
+//! these for the nets it had trouble with, the nets it couldn't route at all or that fought
+//! other nets for space, and the placer multiplies the wirelength weight of each net by it so
+//! those nets come out shorter next time.
+//!
+//! ```json
+//! {"nets": {"12": 4.0, "37": 1.5}, "unrouted": 1}
+//! ```
+//!
+//! Nets are keyed by their signal number, which the placer and router share. Nets that aren't
+//! listed have a weight of 1.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct NetWeights {
+    /// Weight of each net by signal number, for the nets whose weight isn't 1
+    pub nets: BTreeMap<i64, f32>,
+    /// Number of nets the routing attempt couldn't route, not counting the ones with several
+    /// drivers or none that no placement would help
+    #[serde(default)]
+    pub unrouted: usize,
+}
+
+impl NetWeights {
+    pub fn read(path: &Path) -> Result<Self> {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Failed to open net weights {:?}", path))?;
+        serde_json::from_reader(std::io::BufReader::new(file))
+            .with_context(|| format!("Failed to parse net weights {:?}", path))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("Failed to create net weights {:?}", path))?;
+        serde_json::to_writer(std::io::BufWriter::new(file), self)
+            .with_context(|| format!("Failed to write net weights {:?}", path))
+    }
+
+    /// Weight of the net with signal number `net`
+    pub fn weight(&self, net: i64) -> f32 {
+        self.nets.get(&net).copied().unwrap_or(1.0)
+    }
+
+    /// Scale these weights by the ones from a later routing attempt, so nets that keep having
+    /// trouble weigh more every time. The unrouted count is the later attempt's.
+    pub fn accumulate(&mut self, later: &NetWeights) {
+        for (net, weight) in later.nets.iter() {
+            *self.nets.entry(*net).or_insert(1.0) *= weight;
+        }
+        self.unrouted = later.unrouted;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::NetWeights;
+
+    #[test]
+    fn weights_compound_over_attempts() {
+        let mut weights: NetWeights =
+            serde_json::from_str(r#"{"nets": {"12": 4.0, "37": 1.5}, "unrouted": 1}"#).unwrap();
+        assert_eq!(weights.weight(12), 4.0);
+        assert_eq!(weights.weight(5), 1.0);
+
+        let later = NetWeights {
+            nets: [(37, 2.0), (5, 1.5)].into(),
+            unrouted: 0,
+        };
+        weights.accumulate(&later);
+        assert_eq!(weights.weight(12), 4.0);
+        assert_eq!(weights.weight(37), 3.0);
+        assert_eq!(weights.weight(5), 1.5);
+        assert_eq!(weights.unrouted, 0);
+    }
+}
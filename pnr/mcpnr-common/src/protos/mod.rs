@@ -1,9 +1,121 @@
+pub mod stream;
+
+use std::path::Path;
 use std::result::Result;
 
+use anyhow::Context;
+use prost::Message;
+
 use crate::{CellExt, CellGetAttribError};
 
 include!(concat!(env!("OUT_DIR"), "/protos.rs"));
 
+/// Extension of placed designs written as JSON instead of protobuf
+pub const JSON_EXTENSION: &str = "json";
+
+/// Version of the placed design format written by this build. Bump it with any change to
+/// `placed_design.proto` older readers would misread, and teach [`upgrade_placed_design`] to bring
+/// designs of the previous version up to date.
+pub const PLACED_DESIGN_VERSION: u32 = 1;
+
+/// Read a placed design, as JSON if the file has a `.json` extension, in the streaming format of
+/// [`stream`] if it has a `.pbs` or `.jsonl` extension and as protobuf otherwise. Designs of older
+/// versions are upgraded to [`PLACED_DESIGN_VERSION`], and designs of newer
+/// versions are rejected.
+pub fn read_placed_design(path: &Path) -> anyhow::Result<mcpnr::PlacedDesign> {
+    if stream::is_stream(path) {
+        return stream::open_placed_design_stream(path)?
+            .into_design()
+            .with_context(|| format!("Failed to read placed design {:?}", path));
+    }
+    let data =
+        std::fs::read(path).with_context(|| format!("Failed to open placed design {:?}", path))?;
+    let design = if path.extension() == Some(JSON_EXTENSION.as_ref()) {
+        let value: serde_json::Value = serde_json::from_slice(&data)
+            .with_context(|| format!("Failed to parse placed design {:?}", path))?;
+        let version: mcpnr::PlacedDesignVersion = serde_json::from_value(value.clone())
+            .with_context(|| format!("Failed to read the version of placed design {:?}", path))?;
+        check_version(version.version).with_context(|| format!("Placed design {:?}", path))?;
+        serde_json::from_value(value)
+            .with_context(|| format!("Failed to parse placed design {:?}", path))?
+    } else {
+        let version = mcpnr::PlacedDesignVersion::decode(&data[..])
+            .with_context(|| format!("Failed to decode placed design {:?}", path))?;
+        check_version(version.version).with_context(|| format!("Placed design {:?}", path))?;
+        mcpnr::PlacedDesign::decode(&data[..])
+            .with_context(|| format!("Failed to decode placed design {:?}", path))?
+    };
+    upgrade_placed_design(design)
+        .with_context(|| format!("Failed to upgrade placed design {:?}", path))
+}
+
+pub(crate) fn check_version(version: u32) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        version <= PLACED_DESIGN_VERSION,
+        "Written by a newer MCPNR as version {}, but this one reads up to version {}. Update \
+         MCPNR to read it.",
+        version,
+        PLACED_DESIGN_VERSION
+    );
+    Ok(())
+}
+
+/// Bring a placed design of any version up to [`PLACED_DESIGN_VERSION`], one version at a time
+pub fn upgrade_placed_design(
+    mut design: mcpnr::PlacedDesign,
+) -> anyhow::Result<mcpnr::PlacedDesign> {
+    check_version(design.version)?;
+    while design.version < PLACED_DESIGN_VERSION {
+        match design.version {
+            // Version 0 is every design written before the version was recorded, which only
+            // differs from version 1 in not having it
+            0 => design.version = 1,
+            v => unreachable!("No upgrade from placed design version {}", v),
+        }
+    }
+    Ok(design)
+}
+
+/// Write a placed design as pretty-printed JSON, for diffing and editing by hand
+pub fn write_placed_design_json(design: &mcpnr::PlacedDesign, path: &Path) -> anyhow::Result<()> {
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("Failed to create placed design {:?}", path))?;
+    serde_json::to_writer_pretty(std::io::BufWriter::new(file), design)
+        .with_context(|| format!("Failed to write placed design {:?}", path))
+}
+
+/// Write a placed design in the format [`read_placed_design`] reads from `path`
+pub fn write_placed_design(design: &mcpnr::PlacedDesign, path: &Path) -> anyhow::Result<()> {
+    if path.extension() == Some(JSON_EXTENSION.as_ref()) {
+        return write_placed_design_json(design, path);
+    }
+    if stream::is_stream(path) {
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("Failed to create placed design {:?}", path))?;
+        let w = std::io::BufWriter::new(file);
+        return if path.extension() == Some(stream::JSON_STREAM_EXTENSION.as_ref()) {
+            stream::write_json_stream(design, w)
+        } else {
+            stream::write_protobuf_stream(design, w)
+        }
+        .with_context(|| format!("Failed to write placed design {:?}", path));
+    }
+    std::fs::write(path, design.encode_to_vec())
+        .with_context(|| format!("Failed to write placed design {:?}", path))
+}
+
+impl mcpnr::placed_design::Cell {
+    /// How to refer to the cell at `idx` in messages: its instance name and type, or its index and
+    /// type if it has no name
+    pub fn describe(&self, idx: usize) -> String {
+        if self.name.is_empty() {
+            format!("#{} ({})", idx, self.r#type)
+        } else {
+            format!("{} ({})", self.name, self.r#type)
+        }
+    }
+}
+
 impl CellExt for mcpnr::placed_design::Cell {
     fn get_param_i64(&self, name: &str) -> Result<i64, CellGetAttribError> {
         use mcpnr::parameter::Value;
@@ -18,3 +130,115 @@ impl CellExt for mcpnr::placed_design::Cell {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use prost::Message;
+
+    use super::mcpnr::{placed_design::Cell, signal::Type, BitVector, PlacedDesign, Position};
+    use super::{read_placed_design, write_placed_design_json, PLACED_DESIGN_VERSION};
+
+    #[test]
+    fn json_round_trips() -> anyhow::Result<()> {
+        let design = PlacedDesign {
+            creator: "test".to_owned(),
+            cells: vec![Cell {
+                r#type: "gate_not.nbt".to_owned(),
+                pos: Some(Position { x: 1, y: 16, z: 3 }),
+                connection: [(
+                    "A".to_owned(),
+                    BitVector {
+                        signal: vec![super::mcpnr::Signal {
+                            r#type: Some(Type::Id(2)),
+                        }],
+                    },
+                )]
+                .into_iter()
+                .collect(),
+                ..Default::default()
+            }],
+            version: PLACED_DESIGN_VERSION,
+            ..Default::default()
+        };
+
+        let path = std::env::temp_dir().join(format!("mcpnr-placed-{}.json", std::process::id()));
+        write_placed_design_json(&design, &path)?;
+        let read = read_placed_design(&path);
+        std::fs::remove_file(&path)?;
+        assert_eq!(read?, design);
+
+        Ok(())
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("mcpnr-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn unversioned_designs_are_upgraded() -> anyhow::Result<()> {
+        let design = PlacedDesign {
+            creator: "test".to_owned(),
+            cells: vec![Cell {
+                r#type: "gate_not.nbt".to_owned(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let path = temp_path("unversioned.pb");
+        std::fs::write(&path, design.encode_to_vec())?;
+        let read = read_placed_design(&path);
+        std::fs::remove_file(&path)?;
+        assert_eq!(
+            read?,
+            PlacedDesign {
+                version: PLACED_DESIGN_VERSION,
+                ..design
+            }
+        );
+
+        // JSON written before versions, or cell names, were recorded
+        let path = temp_path("unversioned.json");
+        std::fs::write(
+            &path,
+            r#"{"creator": "test", "cells": [{"type": "gate_not.nbt", "pos": null,
+                "parameter": {}, "attribute": {}, "connection": {}}], "nets": {}}"#,
+        )?;
+        let read = read_placed_design(&path);
+        std::fs::remove_file(&path)?;
+        assert_eq!(read?.version, PLACED_DESIGN_VERSION);
+
+        Ok(())
+    }
+
+    #[test]
+    fn newer_designs_are_rejected() -> anyhow::Result<()> {
+        let design = PlacedDesign {
+            version: PLACED_DESIGN_VERSION + 1,
+            ..Default::default()
+        };
+
+        let path = temp_path("newer.pb");
+        std::fs::write(&path, design.encode_to_vec())?;
+        let read = read_placed_design(&path);
+        std::fs::remove_file(&path)?;
+        let error = format!("{:?}", read.expect_err("Newer version read"));
+        assert!(error.contains("newer MCPNR"), "{}", error);
+
+        // Newer JSON is rejected before the rest of it is parsed, which may have changed
+        let path = temp_path("newer.json");
+        std::fs::write(
+            &path,
+            format!(
+                r#"{{"version": {}, "cells": 3}}"#,
+                PLACED_DESIGN_VERSION + 1
+            ),
+        )?;
+        let read = read_placed_design(&path);
+        std::fs::remove_file(&path)?;
+        let error = format!("{:?}", read.expect_err("Newer version read"));
+        assert!(error.contains("newer MCPNR"), "{}", error);
+
+        Ok(())
+    }
+}
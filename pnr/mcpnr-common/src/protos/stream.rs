@@ -0,0 +1,294 @@
+//! Streaming reader and writer for placed designs, so designs of millions of cells can be loaded
+//! without first reading the whole file in to memory, and consumers that only need a cell at a
+//! time don't need the whole design in memory at all.
+//!
+//! A stream starts with a [`PlacedDesignHeader`], with everything of the design but its cells,
+//! followed by the cells one at a time. As protobuf (`.pbs`) each message is prefixed by its
+//! length as a varint, as [`prost::Message::encode_length_delimited`] writes them. As JSON
+//! (`.jsonl`) each message is a JSON object on its own line:
+//!
+//! ```json
+//! {"creator":"Placed by MCPNR","nets":{},"version":1}
+//! {"type":"gate_not.nbt","pos":{"x":0,"y":0,"z":8},"parameter":{},"attribute":{},"connection":{},"name":"$1"}
+//! ```
+
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, ensure, Context, Result};
+use prost::Message;
+
+use super::mcpnr::{placed_design::Cell, PlacedDesign, PlacedDesignHeader, PlacedDesignVersion};
+use super::{check_version, upgrade_placed_design};
+
+/// Extension of placed designs written in the streaming format as protobuf
+pub const PROTOBUF_STREAM_EXTENSION: &str = "pbs";
+
+/// Extension of placed designs written in the streaming format as JSON
+pub const JSON_STREAM_EXTENSION: &str = "jsonl";
+
+/// Whether the placed design at `path` is in the streaming format, going by its extension
+pub fn is_stream(path: &Path) -> bool {
+    path.extension() == Some(PROTOBUF_STREAM_EXTENSION.as_ref())
+        || path.extension() == Some(JSON_STREAM_EXTENSION.as_ref())
+}
+
+/// Placed design in the streaming format, iterating over its cells as they're read
+pub struct PlacedDesignStream<'a> {
+    header: PlacedDesignHeader,
+    cells: Box<dyn Iterator<Item = Result<Cell>> + 'a>,
+}
+
+impl<'a> PlacedDesignStream<'a> {
+    /// Start reading a stream written by [`write_protobuf_stream`]
+    pub fn from_protobuf<R: Read + 'a>(mut r: R) -> Result<Self> {
+        let mut buf = Vec::new();
+        ensure!(
+            read_delimited(&mut r, &mut buf).context("Read header")?,
+            "Stream is empty"
+        );
+        let version = PlacedDesignVersion::decode(&buf[..]).context("Decode header")?;
+        check_version(version.version)?;
+        let header = PlacedDesignHeader::decode(&buf[..]).context("Decode header")?;
+
+        let mut done = false;
+        let mut index = 0;
+        let cells = std::iter::from_fn(move || {
+            if done {
+                return None;
+            }
+            let cell = read_delimited(&mut r, &mut buf)
+                .and_then(|more| {
+                    if !more {
+                        return Ok(None);
+                    }
+                    Ok(Some(Cell::decode(&buf[..])?))
+                })
+                .with_context(|| anyhow!("Read cell {}", index));
+            index += 1;
+            // Nothing after an error can be trusted to be where a message starts
+            done = !matches!(cell, Ok(Some(_)));
+            cell.transpose()
+        });
+
+        Ok(Self {
+            header,
+            cells: Box::new(cells),
+        })
+    }
+
+    /// Start reading a stream written by [`write_json_stream`]
+    pub fn from_json<R: Read + 'a>(r: R) -> Result<Self> {
+        let mut deserializer = serde_json::Deserializer::from_reader(r);
+        // Parsed in to a value first so the version can be checked before anything else, which a
+        // newer version might have changed
+        let header: serde_json::Value =
+            serde::Deserialize::deserialize(&mut deserializer).context("Parse header")?;
+        let version: PlacedDesignVersion =
+            serde_json::from_value(header.clone()).context("Parse header version")?;
+        check_version(version.version)?;
+        let header = serde_json::from_value(header).context("Parse header")?;
+
+        let cells = deserializer
+            .into_iter::<Cell>()
+            .enumerate()
+            .map(|(index, cell)| cell.with_context(|| anyhow!("Parse cell {}", index)));
+
+        Ok(Self {
+            header,
+            cells: Box::new(cells),
+        })
+    }
+
+    /// Everything of the design but its cells
+    pub fn header(&self) -> &PlacedDesignHeader {
+        &self.header
+    }
+
+    /// Read the rest of the cells, and bring the design up to the current version
+    pub fn into_design(self) -> Result<PlacedDesign> {
+        let PlacedDesignHeader {
+            creator,
+            nets,
+            version,
+            provenance,
+        } = self.header;
+        let cells = self.cells.collect::<Result<_>>()?;
+        upgrade_placed_design(PlacedDesign {
+            creator,
+            cells,
+            nets,
+            version,
+            provenance,
+        })
+    }
+}
+
+impl<'a> Iterator for PlacedDesignStream<'a> {
+    type Item = Result<Cell>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.cells.next()
+    }
+}
+
+/// Open the placed design stream at `path`, as JSON if it has a `.jsonl` extension and as
+/// protobuf otherwise
+pub fn open_placed_design_stream(path: &Path) -> Result<PlacedDesignStream<'static>> {
+    let file = std::fs::File::open(path)
+        .with_context(|| anyhow!("Failed to open placed design {:?}", path))?;
+    let r = BufReader::new(file);
+    if path.extension() == Some(JSON_STREAM_EXTENSION.as_ref()) {
+        PlacedDesignStream::from_json(r)
+    } else {
+        PlacedDesignStream::from_protobuf(r)
+    }
+    .with_context(|| anyhow!("Failed to read placed design {:?}", path))
+}
+
+/// Read the next length-delimited message in to `buf`, returning false at the end of the stream
+fn read_delimited<R: Read>(r: &mut R, buf: &mut Vec<u8>) -> Result<bool> {
+    let mut length = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        match r.read_exact(&mut byte) {
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof && shift == 0 => return Ok(false),
+            result => result.context("Read message length")?,
+        }
+        length |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            buf.clear();
+            // Read through `take` so a corrupt length fails at the end of the file, instead of
+            // allocating however much it says
+            r.take(length).read_to_end(buf).context("Read message")?;
+            ensure!(
+                buf.len() as u64 == length,
+                "Stream ends {} bytes in to a {} byte message",
+                buf.len(),
+                length
+            );
+            return Ok(true);
+        }
+    }
+    Err(anyhow!("Message length is longer than 64 bits"))
+}
+
+fn header(design: &PlacedDesign) -> PlacedDesignHeader {
+    PlacedDesignHeader {
+        creator: design.creator.clone(),
+        nets: design.nets.clone(),
+        version: design.version,
+        provenance: design.provenance.clone(),
+    }
+}
+
+/// Write `design` to `w` in the streaming format as protobuf
+pub fn write_protobuf_stream<W: Write>(design: &PlacedDesign, mut w: W) -> Result<()> {
+    let mut buf = header(design).encode_length_delimited_to_vec();
+    w.write_all(&buf).context("Write header")?;
+    for cell in design.cells.iter() {
+        buf.clear();
+        cell.encode_length_delimited(&mut buf)?;
+        w.write_all(&buf).context("Write cell")?;
+    }
+    w.flush()?;
+
+    Ok(())
+}
+
+/// Write `design` to `w` in the streaming format as JSON
+pub fn write_json_stream<W: Write>(design: &PlacedDesign, mut w: W) -> Result<()> {
+    serde_json::to_writer(&mut w, &header(design)).context("Write header")?;
+    writeln!(w)?;
+    for cell in design.cells.iter() {
+        serde_json::to_writer(&mut w, cell).context("Write cell")?;
+        writeln!(w)?;
+    }
+    w.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{write_json_stream, write_protobuf_stream, PlacedDesignStream};
+    use crate::protos::mcpnr::{placed_design::Cell, PlacedDesign, Position};
+    use crate::protos::PLACED_DESIGN_VERSION;
+    use crate::synthetic::{place_in_rows, SyntheticDesign};
+
+    fn design() -> anyhow::Result<PlacedDesign> {
+        let design = SyntheticDesign::AdderChain { bits: 4 }.generate(0)?;
+        place_in_rows(&design, 64)
+    }
+
+    #[test]
+    fn protobuf_round_trips() -> anyhow::Result<()> {
+        let design = design()?;
+        let mut buf = Vec::new();
+        write_protobuf_stream(&design, &mut buf)?;
+
+        let stream = PlacedDesignStream::from_protobuf(&buf[..])?;
+        assert_eq!(stream.header().nets, design.nets);
+        assert_eq!(stream.into_design()?, design);
+
+        Ok(())
+    }
+
+    #[test]
+    fn json_round_trips() -> anyhow::Result<()> {
+        let design = design()?;
+        let mut buf = Vec::new();
+        write_json_stream(&design, &mut buf)?;
+        assert_eq!(
+            buf.iter().filter(|&&b| b == b'\n').count(),
+            design.cells.len() + 1
+        );
+
+        let stream = PlacedDesignStream::from_json(&buf[..])?;
+        assert_eq!(stream.into_design()?, design);
+
+        Ok(())
+    }
+
+    #[test]
+    fn truncated_streams_fail() -> anyhow::Result<()> {
+        let design = PlacedDesign {
+            cells: vec![
+                Cell {
+                    r#type: "gate_not.nbt".to_owned(),
+                    pos: Some(Position { x: 1, y: 0, z: 8 }),
+                    ..Default::default()
+                };
+                2
+            ],
+            version: PLACED_DESIGN_VERSION,
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        write_protobuf_stream(&design, &mut buf)?;
+
+        let cells: Vec<_> = PlacedDesignStream::from_protobuf(&buf[..buf.len() - 1])?.collect();
+        assert_eq!(cells.len(), 2);
+        assert!(cells[0].is_ok());
+        let error = format!("{:?}", cells[1].as_ref().expect_err("Truncated cell read"));
+        assert!(error.contains("Read cell 1"), "{}", error);
+
+        Ok(())
+    }
+
+    #[test]
+    fn newer_streams_are_rejected() -> anyhow::Result<()> {
+        let design = PlacedDesign {
+            version: PLACED_DESIGN_VERSION + 1,
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        write_protobuf_stream(&design, &mut buf)?;
+        assert!(PlacedDesignStream::from_protobuf(&buf[..]).is_err());
+
+        let header = format!(r#"{{"version": {}}}"#, PLACED_DESIGN_VERSION + 1);
+        assert!(PlacedDesignStream::from_json(header.as_bytes()).is_err());
+
+        Ok(())
+    }
+}
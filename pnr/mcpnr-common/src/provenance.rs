@@ -0,0 +1,110 @@
+//! Provenance of the outputs of the flow. Placed designs and routed outputs record which tool
+//! wrote them, with what options and from which inputs, so an output handed over on its own can
+//! be traced back to what produced it. Each tool appends its own [`Provenance`] to those of its
+//! input, so a routed output lists both the placer and the router.
+
+use std::io::Read;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Result};
+
+pub use crate::protos::mcpnr::Provenance;
+use crate::results::{fnv1a, FNV_OFFSET_BASIS};
+
+/// Hash of the contents of the file at `path`, as 16 hex digits. Like
+/// [`crate::results::config_hash`] it's the same in every build, but it isn't cryptographic:
+/// it tells which file an output came from, not whether it was tampered with.
+pub fn file_hash(path: &Path) -> Result<String> {
+    let mut file =
+        std::fs::File::open(path).with_context(|| anyhow!("Open {:?} to hash it", path))?;
+    let mut hash = FNV_OFFSET_BASIS;
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let read = file
+            .read(&mut buf)
+            .with_context(|| anyhow!("Read {:?} to hash it", path))?;
+        if read == 0 {
+            break;
+        }
+        hash = fnv1a(hash, &buf[..read]);
+    }
+    Ok(format!("{:016x}", hash))
+}
+
+impl Provenance {
+    /// Provenance of an output `tool` is writing now, hashing each of its `inputs`
+    pub fn new(
+        tool: &str,
+        tool_version: &str,
+        config_hash: String,
+        inputs: &[&Path],
+        seeds: Vec<u64>,
+    ) -> Result<Self> {
+        Ok(Self {
+            tool: tool.to_owned(),
+            tool_version: tool_version.to_owned(),
+            config_hash,
+            input_hashes: inputs
+                .iter()
+                .map(|path| Ok((path.to_string_lossy().into_owned(), file_hash(path)?)))
+                .collect::<Result<_>>()?,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            seeds,
+        })
+    }
+
+    /// One line description, like `mcpnr-placement 0.1.0 (config 0123456789abcdef) at 1700000000
+    /// from counter.json (fedcba9876543210), seeds 1, 2`
+    pub fn describe(&self) -> String {
+        let mut inputs: Vec<_> = self.input_hashes.iter().collect();
+        inputs.sort();
+        let mut description = format!(
+            "{} {} (config {}) at {}",
+            self.tool, self.tool_version, self.config_hash, self.timestamp
+        );
+        if !inputs.is_empty() {
+            let inputs: Vec<_> = inputs
+                .into_iter()
+                .map(|(path, hash)| format!("{} ({})", path, hash))
+                .collect();
+            description += &format!(" from {}", inputs.join(", "));
+        }
+        if !self.seeds.is_empty() {
+            let seeds: Vec<_> = self.seeds.iter().map(u64::to_string).collect();
+            description += &format!(", seeds {}", seeds.join(", "));
+        }
+        description
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{file_hash, Provenance};
+
+    #[test]
+    fn hashes_inputs() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("mcpnr-provenance-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let (a, b) = (dir.join("a.json"), dir.join("b.json"));
+        std::fs::write(&a, "{}")?;
+        std::fs::write(&b, "{ }")?;
+
+        let provenance = Provenance::new("test", "0.1.0", "0".repeat(16), &[&a, &b], vec![7]);
+        let hashes = (file_hash(&a), file_hash(&b));
+        std::fs::remove_dir_all(&dir)?;
+
+        let provenance = provenance?;
+        let (a_hash, b_hash) = (hashes.0?, hashes.1?);
+        assert_ne!(a_hash, b_hash);
+        assert_eq!(
+            provenance.input_hashes[&a.to_string_lossy().into_owned()],
+            a_hash
+        );
+        assert!(provenance.describe().ends_with(", seeds 7"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,386 @@
+//! Log of the results of runs of the flow, one CSV row per run, so the quality of results can be
+//! followed across parameter sweeps and changes to the tools instead of being copied in to
+//! spreadsheets by hand. The placer and router both append to the same file.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, ensure, Context, Result};
+
+/// First line of every results file
+pub const RESULTS_HEADER: &str =
+    "timestamp,tool,design,config_hash,hpwl,routed_wirelength,failures,runtime_s";
+
+/// Results of one run of the placer or router
+#[derive(Clone, Debug, PartialEq)]
+pub struct RunRecord {
+    /// Seconds since the Unix epoch when the run finished
+    pub timestamp: u64,
+    /// Tool that made the run, `placement` or `routing`
+    pub tool: String,
+    /// Name of the design, the stem of the input file
+    pub design: String,
+    /// Hash of the options that affect the results, see [`config_hash`]
+    pub config_hash: String,
+    /// Half-perimeter wirelength of the legalized placement, for placement runs
+    pub hpwl: Option<f64>,
+    /// Blocks of wire splatted by the router, for routing runs
+    pub routed_wirelength: Option<u64>,
+    /// Nets left unrouted, for routing runs
+    pub failures: Option<u64>,
+    /// Wall-clock time of the run, in seconds
+    pub runtime: f64,
+}
+
+impl RunRecord {
+    /// Start a record of a run of `tool` on the design in `input_file` finishing now, without any
+    /// results yet
+    pub fn new(tool: &str, input_file: &Path, config_hash: String, runtime: Duration) -> Self {
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            tool: tool.to_owned(),
+            design: input_file
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            config_hash,
+            hpwl: None,
+            routed_wirelength: None,
+            failures: None,
+            runtime: runtime.as_secs_f64(),
+        }
+    }
+
+    fn to_csv(&self) -> String {
+        fn optional<T: ToString>(value: Option<T>) -> String {
+            value.map(|v| v.to_string()).unwrap_or_default()
+        }
+
+        [
+            self.timestamp.to_string(),
+            quote(&self.tool),
+            quote(&self.design),
+            quote(&self.config_hash),
+            optional(self.hpwl),
+            optional(self.routed_wirelength),
+            optional(self.failures),
+            format!("{:.3}", self.runtime),
+        ]
+        .join(",")
+    }
+
+    fn from_csv(line: &str) -> Result<Self> {
+        fn optional<T: std::str::FromStr>(field: &str) -> Result<Option<T>>
+        where
+            T::Err: std::error::Error + Send + Sync + 'static,
+        {
+            if field.is_empty() {
+                Ok(None)
+            } else {
+                Ok(Some(field.parse()?))
+            }
+        }
+
+        let fields = split_csv(line);
+        ensure!(
+            fields.len() == 8,
+            "Expected 8 fields but found {}",
+            fields.len()
+        );
+        Ok(Self {
+            timestamp: fields[0].parse().context("Parse timestamp")?,
+            tool: fields[1].clone(),
+            design: fields[2].clone(),
+            config_hash: fields[3].clone(),
+            hpwl: optional(&fields[4]).context("Parse hpwl")?,
+            routed_wirelength: optional(&fields[5]).context("Parse routed_wirelength")?,
+            failures: optional(&fields[6]).context("Parse failures")?,
+            runtime: fields[7].parse().context("Parse runtime_s")?,
+        })
+    }
+}
+
+/// Quote a CSV field if it needs it
+fn quote(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// Split a line of CSV in to its fields, undoing [`quote`]
+fn split_csv(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+/// Hash of the `Debug` representation of a configuration, as 16 hex digits. Unlike the standard
+/// library's hasher, FNV-1a gives the same hash in every build, so runs logged by different builds
+/// of the tools can be grouped by it. Paths to the input and output should be left out of
+/// `config`, or every design will have its own configurations.
+pub fn config_hash(config: &impl Debug) -> String {
+    let hash = fnv1a(FNV_OFFSET_BASIS, format!("{:?}", config).as_bytes());
+    format!("{:016x}", hash)
+}
+
+/// Starting state of an FNV-1a hash
+pub(crate) const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+
+/// Continue the FNV-1a hash `hash` over `bytes`
+pub(crate) fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Append `record` to the results file at `path`, creating it if it doesn't exist yet
+pub fn append_run(path: &Path, record: &RunRecord) -> Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| anyhow!("Open results file {:?}", path))?;
+    let empty = file
+        .metadata()
+        .with_context(|| anyhow!("Stat results file {:?}", path))?
+        .len()
+        == 0;
+
+    // Written in one go, so runs logging to the same file at once don't interleave their rows
+    let mut rows = String::new();
+    if empty {
+        rows.push_str(RESULTS_HEADER);
+        rows.push('\n');
+    }
+    rows.push_str(&record.to_csv());
+    rows.push('\n');
+    file.write_all(rows.as_bytes())
+        .with_context(|| anyhow!("Write to results file {:?}", path))
+}
+
+/// Read every run logged to the results file at `path`, oldest first
+pub fn read_runs(path: &Path) -> Result<Vec<RunRecord>> {
+    let file =
+        std::fs::File::open(path).with_context(|| anyhow!("Open results file {:?}", path))?;
+    let mut lines = std::io::BufReader::new(file).lines();
+
+    let header = lines
+        .next()
+        .transpose()
+        .with_context(|| anyhow!("Read results file {:?}", path))?;
+    ensure!(
+        header.as_deref().map(str::trim_end) == Some(RESULTS_HEADER),
+        "{:?} is not a results file, its first line should be {:?}",
+        path,
+        RESULTS_HEADER
+    );
+
+    let mut runs = Vec::new();
+    for (idx, line) in lines.enumerate() {
+        let line = line.with_context(|| anyhow!("Read results file {:?}", path))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        runs.push(
+            RunRecord::from_csv(line.trim_end())
+                .with_context(|| anyhow!("Parse line {} of {:?}", idx + 2, path))?,
+        );
+    }
+    Ok(runs)
+}
+
+/// Print how one metric went over `values`, which are in run order with the config of each run
+fn write_trend<W: Write>(
+    w: &mut W,
+    name: &str,
+    values: &[(f64, &str)],
+    precision: usize,
+) -> std::io::Result<()> {
+    let (first, last) = match (values.first(), values.last()) {
+        (Some(first), Some(last)) => (first.0, last.0),
+        _ => return Ok(()),
+    };
+    let (best, best_config) = values
+        .iter()
+        .copied()
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .unwrap();
+
+    let change = if first != 0.0 {
+        format!(" ({:+.1}%)", 100.0 * (last - first) / first)
+    } else {
+        String::new()
+    };
+    writeln!(
+        w,
+        "  {}: {:.p$} -> {:.p$}{} over {} runs, best {:.p$} with config {}",
+        name,
+        first,
+        last,
+        change,
+        values.len(),
+        best,
+        best_config,
+        p = precision
+    )
+}
+
+/// Print the runs in `runs` grouped by design, the `last` most recent of each in a table, followed
+/// by how each metric changed from the first run to the latest and the best run of each.
+pub fn write_report<W: Write>(w: &mut W, runs: &[RunRecord], last: usize) -> std::io::Result<()> {
+    if runs.is_empty() {
+        return writeln!(w, "No runs logged yet");
+    }
+
+    let mut designs: BTreeMap<&str, Vec<(usize, &RunRecord)>> = BTreeMap::new();
+    for (idx, run) in runs.iter().enumerate() {
+        designs
+            .entry(run.design.as_str())
+            .or_default()
+            .push((idx + 1, run));
+    }
+
+    for (design_idx, (design, runs)) in designs.iter().enumerate() {
+        if design_idx > 0 {
+            writeln!(w)?;
+        }
+        writeln!(w, "{} ({} runs)", design, runs.len())?;
+        writeln!(
+            w,
+            "  {:>5} {:<10} {:<16} {:>10} {:>10} {:>8} {:>9}",
+            "run", "tool", "config", "hpwl", "wire", "failures", "runtime"
+        )?;
+        let skip = runs.len().saturating_sub(last);
+        if skip > 0 {
+            writeln!(w, "  ... {} earlier runs", skip)?;
+        }
+        for (run_idx, run) in runs.iter().skip(skip) {
+            let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_owned());
+            writeln!(
+                w,
+                "  {:>5} {:<10} {:<16} {:>10} {:>10} {:>8} {:>8.1}s",
+                run_idx,
+                run.tool,
+                run.config_hash,
+                optional(run.hpwl.map(|hpwl| format!("{:.0}", hpwl))),
+                optional(run.routed_wirelength.map(|wire| wire.to_string())),
+                optional(run.failures.map(|failures| failures.to_string())),
+                run.runtime
+            )?;
+        }
+
+        let metric = |value: fn(&RunRecord) -> Option<f64>| {
+            runs.iter()
+                .filter_map(|(_, run)| Some((value(run)?, run.config_hash.as_str())))
+                .collect::<Vec<_>>()
+        };
+        write_trend(w, "hpwl", &metric(|run| run.hpwl), 0)?;
+        write_trend(
+            w,
+            "routed wirelength",
+            &metric(|run| run.routed_wirelength.map(|wire| wire as f64)),
+            0,
+        )?;
+        write_trend(
+            w,
+            "failures",
+            &metric(|run| run.failures.map(|failures| failures as f64)),
+            0,
+        )?;
+        for tool in ["placement", "routing"] {
+            let runtimes = runs
+                .iter()
+                .filter(|(_, run)| run.tool == tool)
+                .map(|(_, run)| (run.runtime, run.config_hash.as_str()))
+                .collect::<Vec<_>>();
+            write_trend(w, &format!("{} runtime (s)", tool), &runtimes, 1)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{config_hash, split_csv, write_report, RunRecord};
+
+    fn run(design: &str, tool: &str, hpwl: Option<f64>, failures: Option<u64>) -> RunRecord {
+        RunRecord {
+            timestamp: 1_700_000_000,
+            tool: tool.to_owned(),
+            design: design.to_owned(),
+            config_hash: config_hash(&(tool, hpwl.is_some())),
+            hpwl,
+            routed_wirelength: failures.map(|f| 100 + f),
+            failures,
+            runtime: 1.5,
+        }
+    }
+
+    #[test]
+    fn records_round_trip() -> anyhow::Result<()> {
+        let record = run("adder, 8 bit \"fast\"", "placement", Some(1234.5), None);
+        assert_eq!(RunRecord::from_csv(&record.to_csv())?, record);
+
+        let record = run("counter", "routing", None, Some(3));
+        assert_eq!(
+            split_csv(&record.to_csv())[4..],
+            ["", "103", "3", "1.500"].map(String::from)
+        );
+        assert_eq!(RunRecord::from_csv(&record.to_csv())?, record);
+
+        assert!(RunRecord::from_csv("1,placement,counter").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn config_hash_is_stable() {
+        // Changing this hash would split the runs of every existing results file
+        assert_eq!(config_hash(&(1, "a")), "d8cdc980731a0bca");
+        assert_ne!(config_hash(&(1, "a")), config_hash(&(2, "a")));
+    }
+
+    #[test]
+    fn report_shows_trends() -> anyhow::Result<()> {
+        let runs = [
+            run("counter", "placement", Some(200.0), None),
+            run("adder", "placement", Some(50.0), None),
+            run("counter", "routing", None, Some(4)),
+            run("counter", "placement", Some(150.0), None),
+            run("counter", "routing", None, Some(0)),
+        ];
+        let mut report = Vec::new();
+        write_report(&mut report, &runs, 3)?;
+        let report = String::from_utf8(report)?;
+
+        assert!(report.starts_with("adder (1 runs)"));
+        assert!(report.contains("counter (4 runs)\n"));
+        assert!(report.contains("  ... 1 earlier runs\n"));
+        assert!(report.contains("  hpwl: 200 -> 150 (-25.0%) over 2 runs, best 150"));
+        assert!(report.contains("  failures: 4 -> 0 (-100.0%) over 2 runs, best 0"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,439 @@
+//! WorldEdit schematics, in the Sponge schematic format (`.schem`).
+//!
+//! Many servers only let players paste designs in with WorldEdit, so routed designs can be written
+//! as schematics as well as structure block JSON. Standard cells can be read from schematics too,
+//! so cell libraries built with WorldEdit don't need converting to structures first. Version 2 is
+//! written, and versions 1 to 3 can be read.
+
+use std::io::{Read, Write};
+
+use anyhow::{anyhow, ensure, Context, Result};
+use quartz_nbt::{io::Flavor, NbtCompound, NbtList, NbtTag};
+
+use crate::block_storage::{Block, BlockStorage, PropertyValue};
+use crate::minecraft_types::{json_to_nbt, PaletteBlock, Structure, StructureBlock};
+
+/// Version of the Sponge schematic format that's written
+pub const SCHEMATIC_VERSION: i32 = 2;
+
+/// File extension WorldEdit uses for Sponge schematics
+pub const SCHEMATIC_EXTENSION: &str = "schem";
+
+/// Block state string for a block, as used in schematic palettes, e.g.
+/// `minecraft:repeater[delay=1,facing=north]`.
+pub fn block_state(block: &Block) -> String {
+    let mut state = block.name.clone();
+    if let Some(ref properties) = block.properties {
+        let mut properties: Vec<_> = properties
+            .iter()
+            .map(|(name, value)| match value {
+                PropertyValue::String(s) => format!("{}={}", name, s),
+                PropertyValue::Byte(b) => format!("{}={}", name, b),
+            })
+            .collect();
+        if !properties.is_empty() {
+            properties.sort();
+            state.push('[');
+            state.push_str(&properties.join(","));
+            state.push(']');
+        }
+    }
+    state
+}
+
+fn write_var_int(data: &mut Vec<i8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            data.push(byte as i8);
+            return;
+        }
+        data.push((byte | 0x80) as i8);
+    }
+}
+
+/// Write `storage` as a gzipped Sponge schematic. `data_version` should match the Minecraft
+/// version the schematic is pasted in to.
+pub fn write_schematic<W: Write>(
+    storage: &BlockStorage,
+    mut w: W,
+    data_version: i32,
+) -> Result<()> {
+    let [sx, sy, sz] = *storage.extents();
+    ensure!(
+        [sx, sy, sz].iter().all(|s| *s <= u16::MAX as u32),
+        "Design is too big for a schematic ({}x{}x{})",
+        sx,
+        sy,
+        sz
+    );
+
+    // Several storage palette entries can map to the same block state, so give each state string
+    // its own index
+    let mut states: Vec<String> = Vec::new();
+    let mut palette_map = Vec::with_capacity(storage.palette().len());
+    for block in storage.palette() {
+        let state = block_state(block);
+        let idx = match states.iter().position(|s| *s == state) {
+            Some(idx) => idx,
+            None => {
+                states.push(state);
+                states.len() - 1
+            }
+        };
+        palette_map.push(idx as u32);
+    }
+
+    // Storage order already matches the schematic's, X fastest then Z then Y
+    let mut block_data = Vec::with_capacity((sx * sy * sz) as usize);
+    for block in storage.iter_block_indicies() {
+        let idx = palette_map
+            .get(block.index())
+            .ok_or_else(|| anyhow!("Block index {:?} is not in the palette", block))?;
+        write_var_int(&mut block_data, *idx);
+    }
+
+    let mut palette = NbtCompound::new();
+    for (idx, state) in states.iter().enumerate() {
+        palette.insert(state.clone(), NbtTag::Int(idx as i32));
+    }
+
+    let mut block_entities = Vec::new();
+    for ([x, y, z], nbt) in storage.iter_block_entities() {
+        let mut entity = match json_to_nbt(nbt)? {
+            NbtTag::Compound(entity) => entity,
+            _ => {
+                return Err(anyhow!(
+                    "Block entity at {}, {}, {} is not an object",
+                    x,
+                    y,
+                    z
+                ))
+            }
+        };
+        // Schematics keep the ID and position under different names to the game
+        let id = match entity.inner_mut().remove("id") {
+            Some(NbtTag::String(id)) => id,
+            _ => return Err(anyhow!("Block entity at {}, {}, {} has no ID", x, y, z)),
+        };
+        entity.insert("Id", NbtTag::String(id));
+        entity.insert(
+            "Pos",
+            NbtTag::IntArray(vec![*x as i32, *y as i32, *z as i32]),
+        );
+        block_entities.push(NbtTag::Compound(entity));
+    }
+
+    let mut schematic = NbtCompound::new();
+    schematic.insert("Version", NbtTag::Int(SCHEMATIC_VERSION));
+    schematic.insert("DataVersion", NbtTag::Int(data_version));
+    // Sizes are unsigned, even though NBT only has signed shorts
+    schematic.insert("Width", NbtTag::Short(sx as u16 as i16));
+    schematic.insert("Height", NbtTag::Short(sy as u16 as i16));
+    schematic.insert("Length", NbtTag::Short(sz as u16 as i16));
+    schematic.insert("Offset", NbtTag::IntArray(vec![0, 0, 0]));
+    schematic.insert("PaletteMax", NbtTag::Int(states.len() as i32));
+    schematic.insert("Palette", palette);
+    schematic.insert("BlockData", NbtTag::ByteArray(block_data));
+    schematic.insert("BlockEntities", NbtList::from(block_entities));
+
+    quartz_nbt::io::write_nbt(&mut w, Some("Schematic"), &schematic, Flavor::GzCompressed)
+        .context("Write schematic NBT")?;
+    Ok(())
+}
+
+/// Parse a block state string from a schematic palette, the reverse of [`block_state`].
+fn parse_block_state(state: &str) -> Result<PaletteBlock> {
+    let (name, properties) = match state.split_once('[') {
+        Some((name, properties)) => {
+            let properties = properties
+                .strip_suffix(']')
+                .ok_or_else(|| anyhow!("Unterminated properties in block state {:?}", state))?;
+            let mut compound = NbtCompound::new();
+            for property in properties.split(',').filter(|p| !p.is_empty()) {
+                let (key, value) = property.split_once('=').ok_or_else(|| {
+                    anyhow!("Bad property {:?} in block state {:?}", property, state)
+                })?;
+                compound.insert(key, NbtTag::String(value.to_owned()));
+            }
+            (name, Some(compound))
+        }
+        None => (state, None),
+    };
+    Ok(PaletteBlock {
+        name: name.to_owned(),
+        properties,
+    })
+}
+
+fn read_var_int(data: &mut impl Iterator<Item = i8>) -> Result<Option<u32>> {
+    let mut value = 0u32;
+    for shift in (0..35).step_by(7) {
+        let byte = match data.next() {
+            Some(byte) => byte as u8,
+            None if shift == 0 => return Ok(None),
+            None => return Err(anyhow!("Block data ends part way through a block")),
+        };
+        value |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+    }
+    Err(anyhow!("Block data has an index longer than 32 bits"))
+}
+
+/// Read a gzipped Sponge schematic as a structure, like the ones saved by structure blocks.
+pub fn read_structure<R: Read>(r: &mut R) -> Result<Structure> {
+    let (root, _) =
+        quartz_nbt::io::read_nbt(r, Flavor::GzCompressed).context("Read schematic NBT")?;
+    // Version 3 wraps everything in a "Schematic" compound, and moves the blocks in to their own
+    let schematic: &NbtCompound = root.get("Schematic").unwrap_or(&root);
+    let version: i32 = schematic.get("Version").context("Schematic version")?;
+    let blocks: &NbtCompound = match version {
+        1 | 2 => schematic,
+        3 => schematic.get("Blocks").context("Schematic blocks")?,
+        _ => return Err(anyhow!("Unsupported schematic version {}", version)),
+    };
+
+    // Sizes are unsigned, even though NBT only has signed shorts
+    let size = |key: &str| -> Result<i32> {
+        let size: i16 = schematic
+            .get(key)
+            .with_context(|| anyhow!("Schematic {}", key))?;
+        Ok(size as u16 as i32)
+    };
+    let [sx, sy, sz] = [size("Width")?, size("Height")?, size("Length")?];
+    ensure!(
+        sx > 0 && sy > 0 && sz > 0,
+        "Schematic is {}x{}x{}, it has to be at least a block in every direction",
+        sx,
+        sy,
+        sz
+    );
+
+    let palette_tag: &NbtCompound = blocks.get("Palette").context("Schematic palette")?;
+    let mut palette = Vec::with_capacity(palette_tag.len());
+    let mut palette_map = vec![usize::MAX; palette_tag.len()];
+    for (state, idx) in palette_tag.inner().iter() {
+        let idx = match idx {
+            NbtTag::Int(idx) => *idx as usize,
+            _ => return Err(anyhow!("Palette index for {:?} is not an int", state)),
+        };
+        ensure!(
+            idx < palette_map.len(),
+            "Palette index {} for {:?} is out of range",
+            idx,
+            state
+        );
+        palette_map[idx] = palette.len();
+        palette.push(parse_block_state(state)?);
+    }
+
+    let data: &[i8] = blocks
+        .get(if version == 3 { "Data" } else { "BlockData" })
+        .context("Schematic block data")?;
+    let mut data = data.iter().copied();
+    let mut structure_blocks = Vec::with_capacity((sx * sy * sz) as usize);
+    while let Some(idx) = read_var_int(&mut data)? {
+        let i = structure_blocks.len() as i32;
+        let state = *palette_map
+            .get(idx as usize)
+            .filter(|state| **state != usize::MAX)
+            .ok_or_else(|| anyhow!("Block data uses palette index {} which isn't defined", idx))?;
+        structure_blocks.push(StructureBlock {
+            state: state as i32,
+            pos: [i % sx, i / (sx * sz), (i / sx) % sz],
+            nbt: None,
+        });
+    }
+    ensure!(
+        structure_blocks.len() == (sx * sy * sz) as usize,
+        "Schematic has {} blocks but is {}x{}x{}",
+        structure_blocks.len(),
+        sx,
+        sy,
+        sz
+    );
+
+    let entities = blocks
+        .get::<_, &NbtList>("BlockEntities")
+        .or_else(|_| blocks.get::<_, &NbtList>("TileEntities"));
+    if let Ok(entities) = entities {
+        for idx in 0..entities.len() {
+            let entity: &NbtCompound = entities.get(idx)?;
+            let pos: &[i32] = entity.get("Pos").context("Block entity position")?;
+            let id: &str = entity.get("Id").context("Block entity ID")?;
+            ensure!(pos.len() == 3, "Block entity position {:?} is not 3D", pos);
+            let i = pos[0] + pos[2] * sx + pos[1] * sx * sz;
+            let block = usize::try_from(i)
+                .ok()
+                .and_then(|i| structure_blocks.get_mut(i))
+                .ok_or_else(|| anyhow!("Block entity at {:?} is outside the schematic", pos))?;
+
+            // Structures keep the data alongside the ID, version 3 schematics keep it separately
+            let mut nbt = match entity.get::<_, &NbtCompound>("Data") {
+                Ok(data) => data.clone(),
+                Err(_) => {
+                    let mut data = entity.clone();
+                    data.inner_mut().remove("Pos");
+                    data.inner_mut().remove("Id");
+                    data
+                }
+            };
+            nbt.insert("id", NbtTag::String(id.to_owned()));
+            block.nbt = Some(nbt);
+        }
+    }
+
+    Ok(Structure {
+        data_version: schematic.get("DataVersion").unwrap_or(0),
+        size: [sx, sy, sz],
+        palette,
+        blocks: structure_blocks,
+        entities: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use quartz_nbt::{io::Flavor, NbtCompound, NbtList, NbtTag};
+
+    use super::{block_state, read_structure, write_schematic};
+    use crate::block_storage::{Block, BlockStorage, PropertyValue};
+
+    #[test]
+    fn writes_palette_blocks_and_entities() -> anyhow::Result<()> {
+        let mut storage = BlockStorage::new(200, 1, 2);
+        let repeater = Block {
+            name: "minecraft:repeater".into(),
+            properties: Some(
+                [
+                    ("facing", PropertyValue::String("north".into())),
+                    ("delay", PropertyValue::String("1".into())),
+                ]
+                .into_iter()
+                .map(|(k, v)| (k.to_owned(), v))
+                .collect(),
+            ),
+        };
+        assert_eq!(
+            block_state(&repeater),
+            "minecraft:repeater[delay=1,facing=north]"
+        );
+        let repeater = storage.add_new_block_type(repeater);
+        *storage.get_block_mut(199, 0, 1)? = repeater;
+        storage.set_block_entity(0, 0, 1, serde_json::json!({ "id": "minecraft:sign" }))?;
+
+        let mut data = Vec::new();
+        write_schematic(&storage, &mut data, 2975)?;
+        let (schematic, name) = quartz_nbt::io::read_nbt(&mut &data[..], Flavor::GzCompressed)?;
+        assert_eq!(name, "Schematic");
+        assert_eq!(schematic.get::<_, i16>("Width")?, 200);
+        assert_eq!(schematic.get::<_, i16>("Length")?, 2);
+
+        let palette: &NbtCompound = schematic.get("Palette")?;
+        assert_eq!(palette.get::<_, i32>("minecraft:air")?, 0);
+        assert_eq!(
+            palette.get::<_, i32>("minecraft:repeater[delay=1,facing=north]")?,
+            1
+        );
+
+        // The repeater is the last block, after 399 single byte entries for air
+        let blocks: &[i8] = schematic.get("BlockData")?;
+        assert_eq!(blocks.len(), 400);
+        assert_eq!(blocks[399], 1);
+
+        let entities: &NbtList = schematic.get("BlockEntities")?;
+        let entity: &NbtCompound = entities.get(0)?;
+        assert_eq!(entity.get::<_, &str>("Id")?, "minecraft:sign");
+        assert_eq!(entity.get::<_, &[i32]>("Pos")?, &[0, 0, 1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reads_cells_from_schematics() -> anyhow::Result<()> {
+        let mut storage = BlockStorage::new(2, 2, 1);
+        let torch = storage.add_new_block_type(Block {
+            name: "minecraft:redstone_wall_torch".into(),
+            properties: Some(
+                [("facing".to_owned(), PropertyValue::String("east".into()))]
+                    .into_iter()
+                    .collect(),
+            ),
+        });
+        *storage.get_block_mut(1, 1, 0)? = torch;
+        storage.set_block_entity(
+            0,
+            1,
+            0,
+            serde_json::json!({ "id": "minecraft:sign", "Text1": "{\"text\":\"A\"}" }),
+        )?;
+        let mut data = Vec::new();
+        write_schematic(&storage, &mut data, 2975)?;
+
+        let structure = read_structure(&mut &data[..])?;
+        assert_eq!(structure.size, [2, 2, 1]);
+        assert_eq!(structure.data_version, 2975);
+        let torch = &structure.blocks[3];
+        assert_eq!(torch.pos, [1, 1, 0]);
+        assert_eq!(
+            block_state(&structure.palette[torch.state as usize].to_block()?),
+            "minecraft:redstone_wall_torch[facing=east]"
+        );
+        let sign = structure.blocks[2].nbt.as_ref().unwrap();
+        assert_eq!(sign.get::<_, &str>("id")?, "minecraft:sign");
+        assert_eq!(sign.get::<_, &str>("Text1")?, "{\"text\":\"A\"}");
+
+        // Version 3 moves the blocks in to their own compound, and block entity data under "Data"
+        let mut palette = NbtCompound::new();
+        palette.insert("minecraft:stone", NbtTag::Int(0));
+        let mut entity_data = NbtCompound::new();
+        entity_data.insert("Text1", NbtTag::String("B".into()));
+        let mut entity = NbtCompound::new();
+        entity.insert("Id", NbtTag::String("minecraft:sign".into()));
+        entity.insert("Pos", NbtTag::IntArray(vec![0, 0, 0]));
+        entity.insert("Data", entity_data);
+        let mut blocks = NbtCompound::new();
+        blocks.insert("Palette", palette);
+        blocks.insert("Data", NbtTag::ByteArray(vec![0]));
+        blocks.insert(
+            "BlockEntities",
+            NbtList::from(vec![NbtTag::Compound(entity)]),
+        );
+        let mut schematic = NbtCompound::new();
+        schematic.insert("Version", NbtTag::Int(3));
+        for key in ["Width", "Height", "Length"] {
+            schematic.insert(key, NbtTag::Short(1));
+        }
+        schematic.insert("Blocks", blocks);
+        let mut root = NbtCompound::new();
+        root.insert("Schematic", schematic);
+        let mut data = Vec::new();
+        quartz_nbt::io::write_nbt(&mut data, None, &root, Flavor::GzCompressed)?;
+
+        let structure = read_structure(&mut &data[..])?;
+        assert_eq!(structure.palette[0].name, "minecraft:stone");
+        let sign = structure.blocks[0].nbt.as_ref().unwrap();
+        assert_eq!(sign.get::<_, &str>("Text1")?, "B");
+
+        Ok(())
+    }
+
+    #[test]
+    fn empty_schematics_are_an_error() -> anyhow::Result<()> {
+        let mut schematic = NbtCompound::new();
+        schematic.insert("Version", NbtTag::Int(2));
+        schematic.insert("Width", NbtTag::Short(0));
+        schematic.insert("Height", NbtTag::Short(1));
+        schematic.insert("Length", NbtTag::Short(1));
+        let mut data = Vec::new();
+        quartz_nbt::io::write_nbt(&mut data, None, &schematic, Flavor::GzCompressed)?;
+
+        let e = read_structure(&mut &data[..]).unwrap_err();
+        assert!(e.to_string().contains("0x1x1"), "{}", e);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,800 @@
+//! Synthetic designs, so the placer and router can be tested and benchmarked at any size without a
+//! Yosys run for each one. Designs are built from the standard cells of the techlib, with their
+//! inputs on a `MCPNR_SWITCHES` macro and outputs on a `MCPNR_LIGHTS` macro like a real top level
+//! design.
+//!
+//! [`SyntheticDesign::generate`] gives a Yosys design, the input to the placer, and
+//! [`place_in_rows`] lines the cells of one up in rows to give a placed design for the router.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, ensure, Context, Result};
+
+use crate::protos::mcpnr::{
+    parameter::Value, placed_design, BitVector, NetMetadata, Parameter, PlacedDesign, Position,
+};
+use crate::protos::PLACED_DESIGN_VERSION;
+use crate::xorshift::XorShift;
+use crate::yosys::{Cell, ConstOrSignal, Design, Module, NetName, PortDirection};
+use crate::BLOCKS_PER_Z_ROW;
+
+const GATE_NOT: &str = "gate_not.nbt";
+const GATES_2_INPUT: [&str; 3] = ["gate_nand_i2.nbt", "gate_nor_i2.nbt", "gate_xor.nbt"];
+const DFF: &str = "dff.nbt";
+
+/// Fraction of the cells in a logic cloud that are registers
+const REGISTER_FRACTION: f32 = 0.125;
+/// Fraction of the gates in a logic cloud with a single input
+const SINGLE_INPUT_FRACTION: f32 = 0.2;
+/// Average number of pins per cell in a logic cloud, the `t` in Rent's rule
+const PINS_PER_CELL: f64 = 3.0;
+/// Number of drivers tried for a gate input before giving up and using a primary input, see
+/// [`LogicCloud::pick_driver`]
+const DRIVER_ATTEMPTS: usize = 8;
+
+/// Distance between the minimum corners of neighbouring cells in [`place_in_rows`]. This is more
+/// than the footprint of any standard cell, and leaves some room for pins.
+const CELL_PITCH: u32 = 8;
+
+/// The kinds of design that can be generated
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SyntheticDesign {
+    /// Random gates and registers with the locality of their connections set by a Rent exponent,
+    /// from 0 (every net stays close to home) to 1 (nets go anywhere). Real logic is usually
+    /// between 0.5 and 0.75.
+    LogicCloud { cells: usize, rent_exponent: f64 },
+    /// A ripple carry adder adding two `bits` wide inputs, built from 5 gates per full adder. Every
+    /// stage depends on the one before, so it's one long critical path.
+    AdderChain { bits: usize },
+    /// A chain of `bits` registers sharing one clock, with a light on each. The clock has a fanout
+    /// of `bits`.
+    ShiftRegister { bits: usize },
+}
+
+impl SyntheticDesign {
+    /// Build the design as the `top` module of a Yosys design. The same `seed` always gives the
+    /// same design.
+    pub fn generate(&self, seed: u64) -> Result<Design> {
+        let module = match *self {
+            Self::LogicCloud {
+                cells,
+                rent_exponent,
+            } => {
+                ensure!(cells >= 2, "A logic cloud needs at least 2 cells");
+                ensure!(
+                    (0.0..=1.0).contains(&rent_exponent),
+                    "Rent exponent must be between 0 and 1, got {}",
+                    rent_exponent
+                );
+                LogicCloud::new(cells, rent_exponent, seed).build()
+            }
+            Self::AdderChain { bits } => {
+                ensure!(bits >= 1, "An adder needs at least 1 bit");
+                adder_chain(bits)
+            }
+            Self::ShiftRegister { bits } => {
+                ensure!(bits >= 1, "A shift register needs at least 1 bit");
+                shift_register(bits)
+            }
+        };
+
+        Ok(Design {
+            creator: format!("MCPNR synthetic design {:?}, seed {}", self, seed),
+            modules: [("top".to_owned(), module)].into_iter().collect(),
+        })
+    }
+}
+
+/// Accumulates the cells and nets of a module
+struct ModuleBuilder {
+    module: Module,
+    next_signal: i64,
+}
+
+impl ModuleBuilder {
+    fn new() -> Self {
+        Self {
+            module: Module {
+                attributes: HashMap::new(),
+                parameter_default_values: None,
+                ports: HashMap::new(),
+                cells: HashMap::new(),
+                netnames: HashMap::new(),
+            },
+            // Yosys starts numbering at 2, leaving room for the constants
+            next_signal: 2,
+        }
+    }
+
+    fn signal(&mut self) -> i64 {
+        self.next_signal += 1;
+        self.next_signal - 1
+    }
+
+    fn signals(&mut self, count: usize) -> Vec<i64> {
+        (0..count).map(|_| self.signal()).collect()
+    }
+
+    fn cell(&mut self, name: String, ty: &str, ports: &[(&str, PortDirection, &[i64])]) {
+        self.cell_with_parameters(name, ty, ports, HashMap::new())
+    }
+
+    fn cell_with_parameters(
+        &mut self,
+        name: String,
+        ty: &str,
+        ports: &[(&str, PortDirection, &[i64])],
+        parameters: HashMap<String, String>,
+    ) {
+        self.module.cells.insert(
+            name,
+            Cell {
+                hide_name: 0,
+                ty: ty.to_owned(),
+                parameters,
+                attributes: HashMap::new(),
+                port_directions: ports
+                    .iter()
+                    .map(|(port, direction, _)| (port.to_string(), *direction))
+                    .collect(),
+                connections: ports
+                    .iter()
+                    .map(|(port, _, bits)| (port.to_string(), bits_of(bits)))
+                    .collect(),
+            },
+        );
+    }
+
+    fn switches(&mut self, bits: &[i64]) {
+        self.cell_with_parameters(
+            "switches".to_owned(),
+            "MCPNR_SWITCHES",
+            &[("O", PortDirection::Output, bits)],
+            [("NSWITCH".to_owned(), format!("{:032b}", bits.len()))]
+                .into_iter()
+                .collect(),
+        )
+    }
+
+    fn lights(&mut self, bits: &[i64]) {
+        self.cell_with_parameters(
+            "lights".to_owned(),
+            "MCPNR_LIGHTS",
+            &[("I", PortDirection::Input, bits)],
+            [("NLIGHT".to_owned(), format!("{:032b}", bits.len()))]
+                .into_iter()
+                .collect(),
+        )
+    }
+
+    fn net_name(&mut self, name: &str, bits: &[i64]) {
+        self.module.netnames.insert(
+            name.to_owned(),
+            NetName {
+                hide_name: 0,
+                bits: bits_of(bits),
+                attributes: HashMap::new(),
+            },
+        );
+    }
+}
+
+fn bits_of(signals: &[i64]) -> Vec<ConstOrSignal> {
+    signals.iter().map(|s| ConstOrSignal::Signal(*s)).collect()
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum CloudCell {
+    Not,
+    Gate2(&'static str),
+    Register,
+}
+
+impl CloudCell {
+    fn inputs(self) -> usize {
+        match self {
+            Self::Not | Self::Register => 1,
+            Self::Gate2(_) => 2,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Driver {
+    Cell(usize),
+    Input(usize),
+}
+
+/// Generator for [`SyntheticDesign::LogicCloud`].
+///
+/// The cells are leaves of a binary tree, in index order. Each input picks a driver by walking up
+/// from its cell, going up another level with probability `2^(p - 1)` each time, and then picks a
+/// cell from the other half of the subtree it stopped at. Going off the top of the tree picks a
+/// primary input. By Rent's rule a subtree of `2^level` cells should have `t * 2^(level * p)`
+/// terminals out of its `t * 2^level` pins, and `2^((p - 1) * level)` is the chance of an input
+/// getting out of it.
+///
+/// Gates are only driven by cells before them or registers, so there are no combinational loops.
+/// Inputs that can't find such a driver after a few tries fall back to primary inputs, which bumps
+/// the Rent exponent of small designs up a bit.
+struct LogicCloud {
+    rng: XorShift,
+    rent_exponent: f64,
+    levels: u32,
+    kinds: Vec<CloudCell>,
+    inputs: usize,
+    outputs: usize,
+}
+
+impl LogicCloud {
+    fn new(cells: usize, rent_exponent: f64, seed: u64) -> Self {
+        let mut rng = XorShift::new(seed);
+        let kinds = (0..cells)
+            .map(|_| {
+                if rng.unit() < REGISTER_FRACTION {
+                    CloudCell::Register
+                } else if rng.unit() < SINGLE_INPUT_FRACTION {
+                    CloudCell::Not
+                } else {
+                    CloudCell::Gate2(GATES_2_INPUT[rng.below(GATES_2_INPUT.len())])
+                }
+            })
+            .collect();
+        let terminals = (PINS_PER_CELL * (cells as f64).powf(rent_exponent)).round() as usize;
+        let inputs = (terminals / 2).max(1);
+
+        Self {
+            rng,
+            rent_exponent,
+            levels: usize::BITS - (cells - 1).leading_zeros(),
+            kinds,
+            inputs,
+            outputs: terminals.saturating_sub(inputs).max(1),
+        }
+    }
+
+    /// Level of the smallest subtree holding both ends of a new connection, or `levels + 1` for
+    /// one from outside the design
+    fn pick_level(&mut self) -> u32 {
+        let up = 2f64.powf(self.rent_exponent - 1.0);
+        let mut level = 1;
+        while level <= self.levels && (self.rng.unit() as f64) < up {
+            level += 1;
+        }
+        level
+    }
+
+    fn pick_driver(&mut self, sink: usize) -> Driver {
+        for _ in 0..DRIVER_ATTEMPTS {
+            let level = self.pick_level();
+            if level > self.levels {
+                break;
+            }
+
+            let half = 1 << (level - 1);
+            let sibling = (sink ^ half) & !(half - 1);
+            if sibling >= self.kinds.len() {
+                continue;
+            }
+            let driver = sibling + self.rng.below(half.min(self.kinds.len() - sibling));
+            if driver < sink
+                || self.kinds[sink] == CloudCell::Register
+                || self.kinds[driver] == CloudCell::Register
+            {
+                return Driver::Cell(driver);
+            }
+        }
+
+        Driver::Input(self.rng.below(self.inputs))
+    }
+
+    fn build(mut self) -> Module {
+        let mut builder = ModuleBuilder::new();
+        let inputs = builder.signals(self.inputs);
+        let clock = builder.signal();
+        let outputs = builder.signals(self.kinds.len());
+
+        let mut fanout = vec![0; self.kinds.len()];
+        for sink in 0..self.kinds.len() {
+            let kind = self.kinds[sink];
+            let drivers: Vec<_> = (0..kind.inputs())
+                .map(|_| match self.pick_driver(sink) {
+                    Driver::Cell(driver) => {
+                        fanout[driver] += 1;
+                        outputs[driver]
+                    }
+                    Driver::Input(input) => inputs[input],
+                })
+                .collect();
+
+            let name = format!("cell{}", sink);
+            let y = &[outputs[sink]];
+            match kind {
+                CloudCell::Not => builder.cell(
+                    name,
+                    GATE_NOT,
+                    &[
+                        ("A", PortDirection::Input, &drivers[..1]),
+                        ("Y", PortDirection::Output, y),
+                    ],
+                ),
+                CloudCell::Gate2(ty) => builder.cell(
+                    name,
+                    ty,
+                    &[
+                        ("A", PortDirection::Input, &drivers[..1]),
+                        ("B", PortDirection::Input, &drivers[1..]),
+                        ("Y", PortDirection::Output, y),
+                    ],
+                ),
+                CloudCell::Register => builder.cell(
+                    name,
+                    DFF,
+                    &[
+                        ("CLK", PortDirection::Input, &[clock]),
+                        ("D", PortDirection::Input, &drivers[..1]),
+                        ("Q", PortDirection::Output, y),
+                    ],
+                ),
+            }
+        }
+
+        // Lights go on the cells nothing else listens to first, then anywhere
+        let mut observed: Vec<_> = (0..self.kinds.len()).filter(|c| fanout[*c] == 0).collect();
+        observed.truncate(self.outputs);
+        while observed.len() < self.outputs {
+            observed.push(self.rng.below(self.kinds.len()));
+        }
+        let lights: Vec<_> = observed.iter().map(|c| outputs[*c]).collect();
+
+        let mut switches = inputs;
+        switches.push(clock);
+        builder.switches(&switches);
+        builder.lights(&lights);
+        builder.net_name("in", &switches[..switches.len() - 1]);
+        builder.net_name("clk", &[clock]);
+        builder.net_name("out", &lights);
+
+        builder.module
+    }
+}
+
+fn adder_chain(bits: usize) -> Module {
+    let mut builder = ModuleBuilder::new();
+    let a = builder.signals(bits);
+    let b = builder.signals(bits);
+    let sum = builder.signals(bits);
+    let mut carry = Vec::with_capacity(bits);
+
+    for bit in 0..bits {
+        let (a, b) = (&a[bit..=bit], &b[bit..=bit]);
+        let nand_ab = builder.signal();
+        let cout = builder.signal();
+        builder.cell(
+            format!("fa{}.nand_ab", bit),
+            "gate_nand_i2.nbt",
+            &[
+                ("A", PortDirection::Input, a),
+                ("B", PortDirection::Input, b),
+                ("Y", PortDirection::Output, &[nand_ab]),
+            ],
+        );
+
+        let cin = match carry.last() {
+            Some(&cin) => cin,
+            None => {
+                // The first bit is a half adder, there's nothing to carry in
+                builder.cell(
+                    format!("fa{}.xor_ab", bit),
+                    "gate_xor.nbt",
+                    &[
+                        ("A", PortDirection::Input, a),
+                        ("B", PortDirection::Input, b),
+                        ("Y", PortDirection::Output, &[sum[bit]]),
+                    ],
+                );
+                builder.cell(
+                    format!("fa{}.cout", bit),
+                    GATE_NOT,
+                    &[
+                        ("A", PortDirection::Input, &[nand_ab]),
+                        ("Y", PortDirection::Output, &[cout]),
+                    ],
+                );
+                carry.push(cout);
+                continue;
+            }
+        };
+
+        let xor_ab = builder.signal();
+        let nand_carry = builder.signal();
+        builder.cell(
+            format!("fa{}.xor_ab", bit),
+            "gate_xor.nbt",
+            &[
+                ("A", PortDirection::Input, a),
+                ("B", PortDirection::Input, b),
+                ("Y", PortDirection::Output, &[xor_ab]),
+            ],
+        );
+        builder.cell(
+            format!("fa{}.sum", bit),
+            "gate_xor.nbt",
+            &[
+                ("A", PortDirection::Input, &[xor_ab]),
+                ("B", PortDirection::Input, &[cin]),
+                ("Y", PortDirection::Output, &[sum[bit]]),
+            ],
+        );
+        builder.cell(
+            format!("fa{}.nand_carry", bit),
+            "gate_nand_i2.nbt",
+            &[
+                ("A", PortDirection::Input, &[xor_ab]),
+                ("B", PortDirection::Input, &[cin]),
+                ("Y", PortDirection::Output, &[nand_carry]),
+            ],
+        );
+        builder.cell(
+            format!("fa{}.cout", bit),
+            "gate_nand_i2.nbt",
+            &[
+                ("A", PortDirection::Input, &[nand_ab]),
+                ("B", PortDirection::Input, &[nand_carry]),
+                ("Y", PortDirection::Output, &[cout]),
+            ],
+        );
+        carry.push(cout);
+    }
+
+    let switches: Vec<_> = a.iter().chain(b.iter()).copied().collect();
+    let lights: Vec<_> = sum.iter().chain(carry.last()).copied().collect();
+    builder.switches(&switches);
+    builder.lights(&lights);
+    builder.net_name("a", &a);
+    builder.net_name("b", &b);
+    builder.net_name("sum", &sum);
+    builder.net_name("carry", &carry);
+
+    builder.module
+}
+
+fn shift_register(bits: usize) -> Module {
+    let mut builder = ModuleBuilder::new();
+    let d = builder.signal();
+    let clock = builder.signal();
+    let q = builder.signals(bits);
+
+    for bit in 0..bits {
+        let input = if bit == 0 { d } else { q[bit - 1] };
+        builder.cell(
+            format!("stage{}", bit),
+            DFF,
+            &[
+                ("CLK", PortDirection::Input, &[clock]),
+                ("D", PortDirection::Input, &[input]),
+                ("Q", PortDirection::Output, &[q[bit]]),
+            ],
+        );
+    }
+
+    builder.switches(&[d, clock]);
+    builder.lights(&q);
+    builder.net_name("d", &[d]);
+    builder.net_name("clk", &[clock]);
+    builder.net_name("q", &q);
+
+    builder.module
+}
+
+/// Turn the `top` module of `design` in to a placed design, without any actual placement: the
+/// switches go along the Z- edge, the lights along the Z+ edge, and the rest of the cells in rows
+/// `row_width` blocks wide in between, in name order. Every cell is on the first tier.
+///
+/// The result is nowhere near a good placement, but it's legal so it can go straight in to the
+/// router.
+pub fn place_in_rows(design: &Design, row_width: u32) -> Result<PlacedDesign> {
+    ensure!(
+        row_width >= CELL_PITCH,
+        "Rows must be at least {} blocks wide",
+        CELL_PITCH
+    );
+    let top = design
+        .modules
+        .get("top")
+        .ok_or_else(|| anyhow!("Failed to locate top module"))?;
+
+    let mut names: Vec<_> = top.cells.keys().collect();
+    names.sort();
+    let (io, logic): (Vec<_>, Vec<_>) = names.into_iter().partition(|name| {
+        matches!(
+            top.cells[*name].ty.as_str(),
+            "MCPNR_SWITCHES" | "MCPNR_LIGHTS"
+        )
+    });
+
+    let mut cells = Vec::with_capacity(top.cells.len());
+    let mut place = |name: &str, x: u32, z: u32| -> Result<()> {
+        cells.push(
+            placed_cell(name, &top.cells[name], Some(Position { x, y: 0, z }))
+                .with_context(|| anyhow!("Place cell {:?}", name))?,
+        );
+        Ok(())
+    };
+
+    // The I/O macros are 2 blocks wide per bit, and no more than a Z row deep
+    let logic_z = BLOCKS_PER_Z_ROW;
+    let rows = logic.len().div_ceil((row_width / CELL_PITCH) as usize) as u32;
+    let lights_z = logic_z + rows * BLOCKS_PER_Z_ROW;
+    let (mut switches_x, mut lights_x) = (0, 0);
+    for name in io {
+        let cell = &top.cells[name];
+        let width = cell
+            .connections
+            .values()
+            .map(|bits| bits.len() as u32)
+            .sum::<u32>()
+            * 2;
+        if cell.ty == "MCPNR_SWITCHES" {
+            place(name, switches_x, 0)?;
+            switches_x += width + 2;
+        } else {
+            place(name, lights_x, lights_z)?;
+            lights_x += width + 2;
+        }
+    }
+
+    let per_row = row_width / CELL_PITCH;
+    for (i, name) in logic.into_iter().enumerate() {
+        let i = i as u32;
+        place(
+            name,
+            (i % per_row) * CELL_PITCH,
+            logic_z + (i / per_row) * BLOCKS_PER_Z_ROW,
+        )?;
+    }
+
+    Ok(PlacedDesign {
+        creator: design.creator.clone(),
+        cells,
+        nets: placed_nets(top),
+        version: PLACED_DESIGN_VERSION,
+        provenance: Vec::new(),
+    })
+}
+
+/// Nets of a placed design, from the nets of the placed module
+pub(crate) fn placed_nets(module: &Module) -> HashMap<String, NetMetadata> {
+    module
+        .netnames
+        .iter()
+        .map(|(name, net)| {
+            (
+                name.clone(),
+                NetMetadata {
+                    hide_name: net.hide_name != 0,
+                    bits: Some(bit_vector(&net.bits)),
+                    attributes: string_parameters(&net.attributes),
+                },
+            )
+        })
+        .collect()
+}
+
+/// Cell of a placed design at `pos`, from the Yosys cell `name`
+pub(crate) fn placed_cell(
+    name: &str,
+    cell: &Cell,
+    pos: Option<Position>,
+) -> Result<placed_design::Cell> {
+    Ok(placed_design::Cell {
+        name: name.to_owned(),
+        r#type: cell.ty.clone(),
+        pos,
+        // Yosys writes integer parameters as 32 bit binary strings, but the router reads string
+        // parameters as decimal
+        parameter: cell
+            .parameters
+            .iter()
+            .map(|(name, value)| {
+                let value = i64::from_str_radix(value, 2)
+                    .with_context(|| anyhow!("Parameter {:?} = {:?}", name, value))?;
+                Ok((
+                    name.clone(),
+                    Parameter {
+                        value: Some(Value::Int(value)),
+                    },
+                ))
+            })
+            .collect::<Result<_>>()?,
+        attribute: string_parameters(&cell.attributes),
+        connection: cell
+            .connections
+            .iter()
+            .map(|(port, bits)| (port.clone(), bit_vector(bits)))
+            .collect(),
+    })
+}
+
+fn bit_vector(bits: &[ConstOrSignal]) -> BitVector {
+    BitVector {
+        signal: bits
+            .iter()
+            .map(|bit| crate::protos::mcpnr::Signal {
+                r#type: Some(bit.to_type()),
+            })
+            .collect(),
+    }
+}
+
+fn string_parameters(values: &HashMap<String, String>) -> HashMap<String, Parameter> {
+    values
+        .iter()
+        .map(|(k, v)| {
+            (
+                k.clone(),
+                Parameter {
+                    value: Some(Value::Str(v.clone())),
+                },
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{place_in_rows, SyntheticDesign};
+    use crate::protos::mcpnr::parameter::Value;
+    use crate::yosys::{ConstOrSignal, Module, PortDirection};
+
+    const DESIGNS: [SyntheticDesign; 3] = [
+        SyntheticDesign::LogicCloud {
+            cells: 500,
+            rent_exponent: 0.6,
+        },
+        SyntheticDesign::AdderChain { bits: 8 },
+        SyntheticDesign::ShiftRegister { bits: 8 },
+    ];
+
+    #[derive(Default)]
+    struct Connections<'a> {
+        drivers: Vec<&'a str>,
+        sinks: Vec<&'a str>,
+    }
+
+    /// Cells driving and listening to each signal
+    fn connectivity(module: &Module) -> HashMap<i64, Connections<'_>> {
+        let mut signals: HashMap<i64, Connections> = HashMap::new();
+        for (name, cell) in module.cells.iter() {
+            for (port, bits) in cell.connections.iter() {
+                for bit in bits {
+                    let signal = match bit {
+                        ConstOrSignal::Signal(s) => *s,
+                        ConstOrSignal::Const(c) => panic!("Constant {} on {}.{}", c, name, port),
+                    };
+                    let entry = signals.entry(signal).or_default();
+                    match cell.port_directions[port] {
+                        PortDirection::Output => entry.drivers.push(name),
+                        PortDirection::Input => entry.sinks.push(name),
+                    }
+                }
+            }
+        }
+        signals
+    }
+
+    #[test]
+    fn every_signal_has_one_driver() -> anyhow::Result<()> {
+        for design in DESIGNS {
+            let design = design.generate(1)?;
+            for (signal, connections) in connectivity(&design.modules["top"]) {
+                assert_eq!(
+                    connections.drivers.len(),
+                    1,
+                    "Signal {} has drivers {:?}",
+                    signal,
+                    connections.drivers
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn logic_cloud_has_no_combinational_loops() -> anyhow::Result<()> {
+        let design = DESIGNS[0].generate(2)?;
+        let top = &design.modules["top"];
+        let index = |name: &str| {
+            name.strip_prefix("cell")
+                .map(|i| i.parse::<usize>().unwrap())
+        };
+
+        for connections in connectivity(top).values() {
+            let driver = match index(connections.drivers[0]) {
+                Some(driver) if top.cells[connections.drivers[0]].ty != "dff.nbt" => driver,
+                _ => continue,
+            };
+            for sink in connections.sinks.iter() {
+                if let Some(sink) = index(sink) {
+                    assert!(
+                        driver < sink || top.cells[&format!("cell{}", sink)].ty == "dff.nbt",
+                        "Gate {} drives gate {}",
+                        driver,
+                        sink
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn rent_exponent_sets_locality() -> anyhow::Result<()> {
+        // Nets crossing between the two halves of the cell list
+        let cut = |rent_exponent| -> anyhow::Result<usize> {
+            let cells = 4096;
+            let design = SyntheticDesign::LogicCloud {
+                cells,
+                rent_exponent,
+            }
+            .generate(3)?;
+            let half = |name: &str| -> Option<bool> {
+                name.strip_prefix("cell")
+                    .map(|i| i.parse::<usize>().unwrap() < cells / 2)
+            };
+
+            Ok(connectivity(&design.modules["top"])
+                .values()
+                .filter(|connections| {
+                    let driver = half(connections.drivers[0]);
+                    connections.sinks.iter().any(|sink| {
+                        driver.is_some() && half(sink).is_some() && half(sink) != driver
+                    })
+                })
+                .count())
+        };
+
+        let local = cut(0.3)?;
+        let global = cut(0.9)?;
+        assert!(
+            local * 4 < global,
+            "Cut of {} at p = 0.3 vs {} at p = 0.9",
+            local,
+            global
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn place_in_rows_does_not_overlap() -> anyhow::Result<()> {
+        let design = SyntheticDesign::AdderChain { bits: 8 }.generate(0)?;
+        let placed = place_in_rows(&design, 64)?;
+
+        assert_eq!(placed.cells.len(), design.modules["top"].cells.len());
+        let mut positions: Vec<_> = placed
+            .cells
+            .iter()
+            .map(|cell| {
+                let pos = cell.pos.as_ref().unwrap();
+                (pos.x, pos.z)
+            })
+            .collect();
+        positions.sort();
+        positions.dedup();
+        assert_eq!(positions.len(), placed.cells.len());
+
+        let switches = placed
+            .cells
+            .iter()
+            .find(|cell| cell.r#type == "MCPNR_SWITCHES")
+            .unwrap();
+        assert_eq!(switches.parameter["NSWITCH"].value, Some(Value::Int(16)));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+//! How timing critical each net is, so the placer and router can favour the nets on slow paths.
+//!
+//! Criticality runs from 0 (plenty of slack) to 1 (on the slowest path). It comes from the
+//! [`CRITICALITY_ATTRIBUTE`] attribute on a net, e.g. `(* mcpnr_criticality = "0.9" *) wire sum;`,
+//! or from a constraints file with lines like
+//!
+//! ```text
+//! # Carry chain of the adder
+//! set_criticality 0.9 carry* sum
+//! ```
+//!
+//! Net names may use `*` to match any sequence of characters. When several lines match a net the
+//! last one wins, and a matching line takes priority over the attribute. The placer writes the
+//! result back in to the attribute of each net, so constraints given to the placer carry through
+//! to the router.
+
+use anyhow::{anyhow, ensure, Context, Result};
+use std::path::Path;
+
+use crate::yosys::parse_numeric_attribute;
+
+/// Net attribute giving the criticality of a net
+pub const CRITICALITY_ATTRIBUTE: &str = "mcpnr_criticality";
+
+/// Parse a criticality attribute, see [`parse_numeric_attribute`]
+pub fn parse_criticality(value: &str) -> Result<f32> {
+    let criticality = parse_numeric_attribute(value, "criticality")?;
+
+    ensure!(
+        (0.0..=1.0).contains(&criticality),
+        "Criticality must be between 0 and 1, got {}",
+        criticality
+    );
+
+    Ok(criticality)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct CriticalityRule {
+    /// Net name to match, with `*` matching any sequence of characters
+    pattern: String,
+    criticality: f32,
+}
+
+impl CriticalityRule {
+    fn matches(&self, name: &str) -> bool {
+        let mut parts = self.pattern.split('*');
+        let first = parts.next().unwrap_or_default();
+        let mut rest = match name.strip_prefix(first) {
+            Some(rest) => rest,
+            None => return false,
+        };
+
+        let parts: Vec<&str> = parts.collect();
+        match parts.split_last() {
+            // No wildcards, the whole name has to match
+            None => rest.is_empty(),
+            Some((last, middle)) => {
+                for part in middle {
+                    match rest.find(part) {
+                        Some(idx) => rest = &rest[idx + part.len()..],
+                        None => return false,
+                    }
+                }
+                rest.ends_with(last)
+            }
+        }
+    }
+}
+
+/// Criticality of nets, read from a constraints file
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TimingConstraints {
+    rules: Vec<CriticalityRule>,
+}
+
+impl TimingConstraints {
+    /// Parse the text of a constraints file
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut rules = Vec::new();
+        for (line_idx, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut words = line.split_whitespace();
+            let command = match words.next() {
+                Some(command) => command,
+                None => continue,
+            };
+            let line_rules = || -> Result<Vec<CriticalityRule>> {
+                ensure!(
+                    command == "set_criticality",
+                    "Unknown command {:?}",
+                    command
+                );
+                let criticality = words
+                    .next()
+                    .ok_or_else(|| anyhow!("Missing criticality"))
+                    .and_then(parse_criticality)?;
+                let rules: Vec<_> = words
+                    .map(|pattern| CriticalityRule {
+                        pattern: pattern.to_owned(),
+                        criticality,
+                    })
+                    .collect();
+                ensure!(!rules.is_empty(), "No nets given");
+                Ok(rules)
+            };
+            rules.extend(line_rules().with_context(|| anyhow!("Line {}", line_idx + 1))?);
+        }
+
+        Ok(Self { rules })
+    }
+
+    /// Load a constraints file
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| anyhow!("Failed to read timing constraints {:?}", path))?;
+        Self::parse(&text).with_context(|| anyhow!("Failed to parse timing constraints {:?}", path))
+    }
+
+    /// Number of net patterns with a criticality
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Criticality the constraints give the net called `name`, if any of them match it
+    pub fn criticality(&self, name: &str) -> Option<f32> {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matches(name))
+            .map(|rule| rule.criticality)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_criticality, TimingConstraints};
+
+    #[test]
+    fn last_matching_rule_wins() -> anyhow::Result<()> {
+        let constraints = TimingConstraints::parse(
+            "
+            # Whole adder, then the carry chain on top
+            set_criticality 0.5 adder_*
+            set_criticality 1 adder_c*y  sum
+            ",
+        )?;
+
+        assert_eq!(constraints.criticality("adder_a"), Some(0.5));
+        assert_eq!(constraints.criticality("adder_carry"), Some(1.0));
+        assert_eq!(constraints.criticality("sum"), Some(1.0));
+        assert_eq!(constraints.criticality("sum2"), None);
+        assert_eq!(constraints.criticality("my_adder_a"), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_bad_lines() {
+        assert!(TimingConstraints::parse("set_criticality 2 sum").is_err());
+        assert!(TimingConstraints::parse("set_criticality 0.5").is_err());
+        assert!(TimingConstraints::parse("set_max_delay 5 sum").is_err());
+    }
+
+    #[test]
+    fn parses_yosys_attributes() -> anyhow::Result<()> {
+        assert_eq!(parse_criticality("00000000000000000000000000000001")?, 1.0);
+        assert_eq!(parse_criticality("0.25")?, 0.25);
+        assert!(parse_criticality("00000000000000000000000000000010").is_err());
+
+        Ok(())
+    }
+}
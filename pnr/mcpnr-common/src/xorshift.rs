@@ -0,0 +1,56 @@
+//! A small pseudo-random generator for the parts of the flow that need one, from placement steps
+//! to synthetic test designs and benchmark netlists. Every use is seeded, so the results are the
+//! same from run to run.
+
+/// Mixed in to seeds, so small seeds still start from a state with plenty of bits set
+const SEED_MIX: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// Xorshift generator, which is plenty random enough to pick annealing moves, jitter cells and
+/// vary generated designs
+pub struct XorShift(u64);
+
+impl XorShift {
+    /// Generator seeded from any `seed`. Xorshift gets stuck at 0, so the one seed that would mix
+    /// to it starts from a different state instead.
+    pub fn new(seed: u64) -> Self {
+        match seed ^ SEED_MIX {
+            0 => Self(SEED_MIX),
+            state => Self(state),
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Uniform in `0..n`
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// Uniform in `0.0..1.0`
+    pub fn unit(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Uniform in `-1.0..1.0`
+    pub fn signed_unit(&mut self) -> f32 {
+        self.unit() * 2.0 - 1.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{XorShift, SEED_MIX};
+
+    #[test]
+    fn every_seed_gets_going() {
+        for seed in [0, 1, SEED_MIX, u64::MAX] {
+            let mut rng = XorShift::new(seed);
+            assert!((0..4).all(|_| rng.next_u64() != 0), "seed {:#x}", seed);
+        }
+    }
+}
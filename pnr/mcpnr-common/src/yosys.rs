@@ -2,6 +2,7 @@
 
 use std::collections::HashMap;
 
+use anyhow::{anyhow, ensure, Context, Result};
 use serde::{Deserialize, Serialize};
 
 use crate::{CellExt, CellGetAttribError, protos::mcpnr::signal::ConstantDriver};
@@ -12,6 +13,95 @@ pub struct Design {
     pub modules: HashMap<String, Module>,
 }
 
+/// Separator between the instance name and the inner name of cells and nets from a flattened
+/// sub-module
+pub const HIERARCHY_SEPARATOR: &str = ".";
+
+impl Design {
+    /// The module instantiated by cells of type `ty`, if it's a module of the design rather than
+    /// a techlib cell. Blackbox modules are only there to describe techlib cells, so they don't
+    /// count.
+    pub fn sub_module(&self, ty: &str) -> Option<&Module> {
+        self.modules
+            .get(ty)
+            .filter(|module| !module.attributes.contains_key("blackbox"))
+    }
+
+    /// Replace every instance of a sub-module in `module` with the cells of the sub-module,
+    /// recursively, the same way Yosys' `flatten` pass does. Cells and nets from an instance are
+    /// named `<instance>.<name>`. Modules without sub-modules come back unchanged.
+    pub fn flatten(&self, module: &Module) -> Result<Module> {
+        let mut flat = Module {
+            cells: HashMap::with_capacity(module.cells.len()),
+            ..module.clone()
+        };
+        let mut next_bit = module.max_bit() + 1;
+
+        for (name, cell) in module.cells.iter() {
+            let sub_module = match self.sub_module(&cell.ty) {
+                Some(sub_module) => self
+                    .flatten(sub_module)
+                    .with_context(|| anyhow!("Flatten {:?}", cell.ty))?,
+                None => {
+                    flat.cells.insert(name.clone(), cell.clone());
+                    continue;
+                }
+            };
+
+            // Bits on the ports of the sub-module become whatever the instance connects to them,
+            // and the rest are new nets
+            let mut bits: HashMap<i64, ConstOrSignal> = HashMap::new();
+            for (port_name, port) in sub_module.ports.iter() {
+                let connection = cell.connections.get(port_name).ok_or_else(|| {
+                    anyhow!("Instance {:?} doesn't connect port {:?}", name, port_name)
+                })?;
+                ensure!(
+                    connection.len() == port.bits.len(),
+                    "Instance {:?} connects {} bits to the {} bit port {:?}",
+                    name,
+                    connection.len(),
+                    port.bits.len(),
+                    port_name
+                );
+                for (inner, outer) in port.bits.iter().zip(connection.iter()) {
+                    if let ConstOrSignal::Signal(inner) = inner {
+                        bits.insert(*inner, outer.clone());
+                    }
+                }
+            }
+            let mut map_bit = |bit: &ConstOrSignal| match bit {
+                ConstOrSignal::Const(_) => bit.clone(),
+                ConstOrSignal::Signal(inner) => bits
+                    .entry(*inner)
+                    .or_insert_with(|| {
+                        next_bit += 1;
+                        ConstOrSignal::Signal(next_bit - 1)
+                    })
+                    .clone(),
+            };
+
+            for (inner_name, mut inner_cell) in sub_module.cells {
+                for connection in inner_cell.connections.values_mut() {
+                    *connection = connection.iter().map(&mut map_bit).collect();
+                }
+                flat.cells.insert(
+                    format!("{}{}{}", name, HIERARCHY_SEPARATOR, inner_name),
+                    inner_cell,
+                );
+            }
+            for (inner_name, mut net) in sub_module.netnames {
+                net.bits = net.bits.iter().map(&mut map_bit).collect();
+                flat.netnames.insert(
+                    format!("{}{}{}", name, HIERARCHY_SEPARATOR, inner_name),
+                    net,
+                );
+            }
+        }
+
+        Ok(flat)
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Module {
     pub attributes: HashMap<String, String>,
@@ -21,6 +111,27 @@ pub struct Module {
     pub netnames: HashMap<String, NetName>,
 }
 
+impl Module {
+    /// Largest signal number used anywhere in the module, or 0 if there are none
+    fn max_bit(&self) -> i64 {
+        let ports = self.ports.values().flat_map(|port| port.bits.iter());
+        let cells = self
+            .cells
+            .values()
+            .flat_map(|cell| cell.connections.values().flatten());
+        let nets = self.netnames.values().flat_map(|net| net.bits.iter());
+        ports
+            .chain(cells)
+            .chain(nets)
+            .filter_map(|bit| match bit {
+                ConstOrSignal::Signal(s) => Some(*s),
+                ConstOrSignal::Const(_) => None,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 #[serde(untagged)]
 pub enum ConstOrSignal {
@@ -42,12 +153,30 @@ impl ConstOrSignal {
             Self::Signal(s) => Type::Id((*s).try_into().unwrap()),
         }
     }
+
+    /// Inverse of [`ConstOrSignal::to_type`]. Constants Yosys has no name for come out as `x`.
+    pub fn from_type(ty: &crate::protos::mcpnr::signal::Type) -> Self {
+        use crate::protos::mcpnr::signal::Type;
+
+        match ty {
+            Type::Id(s) => Self::Signal(*s),
+            Type::Constant(c) => Self::Const(
+                match ConstantDriver::from_i32(*c) {
+                    Some(ConstantDriver::Low) => "0",
+                    Some(ConstantDriver::High) => "1",
+                    Some(ConstantDriver::Z) => "z",
+                    _ => "x",
+                }
+                .to_owned(),
+            ),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Port {
-    direction: PortDirection,
-    bits: Vec<ConstOrSignal>,
+    pub direction: PortDirection,
+    pub bits: Vec<ConstOrSignal>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -78,6 +207,20 @@ pub struct NetName {
     pub attributes: HashMap<String, String>,
 }
 
+/// Parse a numeric attribute, naming it `what` in the error if it isn't a number. Yosys writes
+/// integer attributes as 32 bit binary strings and string attributes verbatim, so accept both
+/// forms.
+pub fn parse_numeric_attribute(value: &str, what: &str) -> Result<f32> {
+    if value.len() == 32 && value.chars().all(|c| c == '0' || c == '1') {
+        Ok(u32::from_str_radix(value, 2)? as f32)
+    } else {
+        value
+            .trim()
+            .parse()
+            .with_context(|| anyhow!("Invalid {} {:?}", what, value))
+    }
+}
+
 impl CellExt for Cell {
     fn get_param_i64(&self, name: &str) -> Result<i64, crate::CellGetAttribError> {
         self.parameters
@@ -86,3 +229,92 @@ impl CellExt for Cell {
             .and_then(|v| { i64::from_str_radix(v, 2) }.map_err(CellGetAttribError::ParseFailed))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::{ConstOrSignal, Design};
+
+    fn bits(signals: &[ConstOrSignal]) -> Vec<String> {
+        signals
+            .iter()
+            .map(|bit| match bit {
+                ConstOrSignal::Signal(s) => s.to_string(),
+                ConstOrSignal::Const(c) => c.clone(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn flatten_connects_instance_ports() -> anyhow::Result<()> {
+        let cell = |ty: &str, a: serde_json::Value, y: serde_json::Value| {
+            json!({
+                "hide_name": 0,
+                "type": ty,
+                "parameters": {},
+                "attributes": {},
+                "port_directions": { "A": "input", "Y": "output" },
+                "connections": { "A": a, "Y": y },
+            })
+        };
+        let design: Design = serde_json::from_value(json!({
+            "creator": "test",
+            "modules": {
+                "top": {
+                    "attributes": {},
+                    "ports": {},
+                    "cells": {
+                        "u0": cell("buf", json!([2]), json!([3])),
+                        "u1": cell("buf", json!(["1"]), json!([4])),
+                    },
+                    "netnames": {},
+                },
+                "buf": {
+                    "attributes": {},
+                    "ports": {
+                        "A": { "direction": "input", "bits": [2] },
+                        "Y": { "direction": "output", "bits": [3] },
+                    },
+                    "cells": {
+                        "a": cell("gate_not.nbt", json!([2]), json!([5])),
+                        "b": cell("gate_not.nbt", json!([5]), json!([3])),
+                    },
+                    "netnames": {
+                        "mid": { "hide_name": 0, "bits": [5], "attributes": {} },
+                    },
+                },
+            },
+        }))?;
+
+        let flat = design.flatten(&design.modules["top"])?;
+        let connection = |cell: &str, port: &str| bits(&flat.cells[cell].connections[port]);
+
+        assert_eq!(flat.cells.len(), 4);
+        assert_eq!(connection("u0.a", "A"), ["2"]);
+        assert_eq!(connection("u0.b", "Y"), ["3"]);
+        assert_eq!(connection("u1.a", "A"), ["1"]);
+        assert_eq!(connection("u1.b", "Y"), ["4"]);
+        // Each instance gets its own copy of the internal net
+        assert_eq!(connection("u0.a", "Y"), connection("u0.b", "A"));
+        assert_eq!(bits(&flat.netnames["u1.mid"].bits), connection("u1.b", "A"));
+        assert_ne!(connection("u0.a", "Y"), connection("u1.a", "Y"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn signal_types_round_trip() {
+        let signals = [
+            ConstOrSignal::Signal(7),
+            ConstOrSignal::Const("0".to_owned()),
+            ConstOrSignal::Const("1".to_owned()),
+            ConstOrSignal::Const("x".to_owned()),
+        ];
+        let round_trip: Vec<_> = signals
+            .iter()
+            .map(|s| ConstOrSignal::from_type(&s.to_type()))
+            .collect();
+        assert_eq!(bits(&round_trip), bits(&signals));
+    }
+}
@@ -0,0 +1,105 @@
+//! Benchmarks of a single analytical solve on synthetic netlists of increasing size. The smaller
+//! sizes go through the dense solver and the larger ones through the sparse solver, see
+//! [`mcpnr_placement::placer::analytical::SPARSE_SOLVER_THRESHOLD`].
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use mcpnr_common::xorshift::XorShift;
+use mcpnr_placement::{
+    core::{NetlistHypergraph, Signal},
+    placement_cell::PlacementCell,
+    placer::analytical::{
+        Bound2Bound, Clique, DecompositionStrategy, MoveableStar, ThresholdCrossover,
+    },
+};
+use nalgebra::Vector3;
+
+const SIZES: [usize; 4] = [256, 1024, 4096, 16384];
+const REGION: f32 = 256.0;
+/// Same as the default schedule's unconstrained step.
+const CLIQUE_THRESHOLD: usize = 2;
+/// Nets connect cells at most this far apart in the cell list, which keeps the netlist local the
+/// way a real design is.
+const NET_WINDOW: usize = 16;
+const SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+/// `cells` gate sized mobile cells, all starting in the middle of the region, and one fixed pad
+/// along the edge of the region for every 32 of them. Every mobile cell drives a net of up to 4
+/// pins to cells shortly after it, and every pad drives a single mobile cell. Uses a fixed seed
+/// so every run benchmarks the same netlist.
+fn netlist(cells: usize) -> NetlistHypergraph {
+    let mut rng = XorShift::new(SEED);
+    let mut next = |limit: usize| rng.below(limit);
+
+    let pads = std::cmp::max(4, cells / 32);
+    let mut placement_cells: Vec<_> = (0..cells)
+        .map(|_| PlacementCell {
+            x: REGION / 2.0,
+            tier_y: 0.0,
+            z: REGION / 2.0,
+            sx: 1.0,
+            s_tier_y: 1.0,
+            sz: 2.0,
+            pos_locked: false,
+        })
+        .collect();
+    placement_cells.extend((0..pads).map(|pad| PlacementCell {
+        x: 0.0,
+        tier_y: 0.0,
+        z: (pad as f32 + 0.5) * REGION / pads as f32,
+        sx: 1.0,
+        s_tier_y: 1.0,
+        sz: 2.0,
+        pos_locked: true,
+    }));
+
+    let signal = |connected_cells: Vec<usize>, moveable_cells: usize| Signal {
+        pin_offsets: vec![Vector3::zeros(); connected_cells.len()],
+        connected_cells,
+        moveable_cells,
+        id: 0,
+        weight: 1.0,
+    };
+    let mut signals: Vec<_> = (0..cells)
+        .map(|driver| {
+            let mut connected_cells = vec![driver];
+            for _ in 0..1 + next(3) {
+                let sink = (driver + 1 + next(NET_WINDOW)) % cells;
+                if !connected_cells.contains(&sink) {
+                    connected_cells.push(sink);
+                }
+            }
+            let moveable_cells = connected_cells.len();
+            signal(connected_cells, moveable_cells)
+        })
+        .collect();
+    signals.extend((0..pads).map(|pad| signal(vec![cells + pad, next(cells)], 1)));
+
+    NetlistHypergraph::test_new(placement_cells, cells, signals)
+}
+
+fn solve(c: &mut Criterion, name: &str, mut strategy: impl DecompositionStrategy) {
+    let mut group = c.benchmark_group(name);
+    group.sample_size(10);
+    for cells in SIZES {
+        group.bench_with_input(BenchmarkId::from_parameter(cells), &cells, |b, &cells| {
+            b.iter_batched(
+                || netlist(cells),
+                |mut net| strategy.execute(&mut net).unwrap(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn analytical(c: &mut Criterion) {
+    solve(
+        c,
+        "unconstrained",
+        ThresholdCrossover::new(CLIQUE_THRESHOLD, Clique::new(), MoveableStar::new()),
+    );
+    solve(c, "bound2bound", Bound2Bound::new());
+}
+
+criterion_group!(benches, analytical);
+criterion_main!(benches);
@@ -0,0 +1,118 @@
+//! Benchmarks of the diffusion placer's inner loop on a large design with small regions, comparing
+//! the parallel and serial paths where there are both.
+
+use std::path::PathBuf;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use mcpnr_common::xorshift::XorShift;
+use mcpnr_placement::{
+    config::{
+        Config, DiffusionConfig, DiffusionMode, GeometryConfig, HierarchyConfig, IOConfig,
+        IOPlanConfig, LegalizerConfig, ObjectiveConfig, PlacementSchedule,
+    },
+    core::NetlistHypergraph,
+    placement_cell::PlacementCell,
+    placer::diffusion::DiffusionPlacer,
+};
+
+const SIZE_X: u32 = 512;
+const SIZE_Y: u32 = 4;
+const SIZE_Z: u32 = 512;
+const CELLS: usize = 100_000;
+const SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+fn config() -> Config {
+    Config {
+        io: IOConfig {
+            input_file: PathBuf::new(),
+            output_file: PathBuf::new(),
+            structure_directory: PathBuf::new(),
+            blocker_rules_file: PathBuf::new(),
+            blockage_map_file: None,
+            placed_json_file: None,
+            timing_constraints_file: None,
+            net_weights_file: None,
+            results_file: None,
+        },
+        io_plan: IOPlanConfig {
+            switch_edge: None,
+            light_edge: None,
+            constraints_file: None,
+        },
+        geometry: GeometryConfig {
+            size_x: SIZE_X,
+            size_y: SIZE_Y,
+            size_z: SIZE_Z,
+            target_fill: 0.8,
+            density_map: Vec::new(),
+        },
+        schedule: PlacementSchedule { schedule: vec![] },
+        objective: ObjectiveConfig::WIRELENGTH_ONLY,
+        legalizer: LegalizerConfig::Abacus,
+        hierarchy: HierarchyConfig {
+            soft_macros: false,
+            macro_aspect_ratio: 1.0,
+            refine_schedule: None,
+        },
+        auto_size: None,
+        max_memory: None,
+    }
+}
+
+/// Gate sized cells scattered over the placement region. Uses a fixed seed so every run
+/// benchmarks the same placement.
+fn netlist() -> NetlistHypergraph {
+    let mut rng = XorShift::new(SEED);
+    let mut next = |limit: u32| rng.below(limit as usize) as f32;
+
+    let cells = (0..CELLS)
+        .map(|_| PlacementCell {
+            x: next(SIZE_X - 1),
+            tier_y: next(SIZE_Y),
+            z: next(SIZE_Z - 2),
+            sx: 1.0,
+            s_tier_y: 1.0,
+            sz: 2.0,
+            pos_locked: false,
+        })
+        .collect();
+
+    NetlistHypergraph::test_new(cells, CELLS, vec![])
+}
+
+fn diffusion(c: &mut Criterion) {
+    let config = config();
+    let diffusion_config = DiffusionConfig {
+        region_size: 2,
+        mode: DiffusionMode::Volume,
+        iterations: 1,
+        delta_t: 0.1,
+    };
+
+    let mut group = c.benchmark_group("diffusion");
+    group.sample_size(20);
+    for parallel in [false, true] {
+        let label = if parallel { "parallel" } else { "serial" };
+        let mut net = netlist();
+        let mut placer = DiffusionPlacer::new(&config, &diffusion_config);
+        placer.parallel = parallel;
+        placer.splat(&net);
+
+        group.bench_function(BenchmarkId::new("compute_velocities", label), |b| {
+            b.iter(|| placer.compute_velocities())
+        });
+        group.bench_function(BenchmarkId::new("move_cells", label), |b| {
+            b.iter(|| placer.move_cells(&mut net, diffusion_config.delta_t))
+        });
+    }
+
+    let mut placer = DiffusionPlacer::new(&config, &diffusion_config);
+    placer.splat(&netlist());
+    group.bench_function("step_time", |b| {
+        b.iter(|| placer.step_time(diffusion_config.delta_t))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, diffusion);
+criterion_main!(benches);
@@ -0,0 +1,138 @@
+//! Builds the blockage map sidecar for a placed design, so the router can mark the blocks each cell
+//! occupies without having to recognize every block the techlib uses.
+
+use anyhow::{anyhow, Context, Result};
+use mcpnr_common::block_storage::{BlockStorage, Position};
+use mcpnr_common::blockage_map::{BlockageMap, Footprint, PinLocation};
+use mcpnr_common::blockers::{find_blockers, BlockerRules, Blockers};
+use mcpnr_common::io_macros;
+use mcpnr_common::protos::mcpnr::placed_design::Cell;
+use mcpnr_common::protos::mcpnr::PlacedDesign;
+use mcpnr_common::CellExt;
+use std::collections::HashMap;
+
+use crate::placement_cell::CellFactory;
+
+/// Find the blockers for a cell type, relative to the cell's minimum corner.
+fn cell_blockers(
+    cell_factory: &CellFactory,
+    rules: &BlockerRules,
+    cell: &Cell,
+) -> Result<Blockers> {
+    let storage = match cell.r#type.as_str() {
+        "MCPNR_SWITCHES" => {
+            let nswitches = cell.get_param_i64_with_default("NSWITCH", 1)?;
+            let mut storage = BlockStorage::new(2 * nswitches.max(0) as u32, 2, 3);
+            io_macros::splat_switches(&mut storage, (0, 0, 0), nswitches)?;
+            storage
+        }
+        "MCPNR_LIGHTS" => {
+            let nlights = cell.get_param_i64_with_default("NLIGHT", 1)?;
+            let mut storage = BlockStorage::new(2 * nlights.max(0) as u32, 2, 3);
+            io_macros::splat_lights(&mut storage, (0, 0, 0), nlights)?;
+            storage
+        }
+        ty => cell_factory.read_structure(ty)?.to_block_storage()?,
+    };
+
+    let mut blockers = find_blockers(&storage, rules)?;
+    if !blockers.unrecognized.is_empty() {
+        // Better to keep wires away from a block we know nothing about than to route through it
+        let mut names: Vec<&str> = blockers
+            .unrecognized
+            .iter()
+            .map(|(_, name)| name.as_str())
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        tracing::warn!(
+            cell_type = cell.r#type.as_str(),
+            "Treating unrecognized blocks as blocked: {}",
+            names.join(", ")
+        );
+        let unrecognized = std::mem::take(&mut blockers.unrecognized);
+        blockers
+            .blocked
+            .extend(unrecognized.into_iter().map(|(pos, _)| pos));
+    }
+
+    Ok(blockers)
+}
+
+/// Offset a position relative to a cell to world block coordinates, if it's inside the world.
+fn to_world(pos: Position, base: [u32; 3]) -> Option<[u32; 3]> {
+    Some([
+        u32::try_from(pos.x + base[0] as i32).ok()?,
+        u32::try_from(pos.y + base[1] as i32).ok()?,
+        u32::try_from(pos.z + base[2] as i32).ok()?,
+    ])
+}
+
+/// Work out the footprint and pins of every cell in a placed design.
+pub fn build_blockage_map(
+    cell_factory: &CellFactory,
+    rules: &BlockerRules,
+    design: &PlacedDesign,
+) -> Result<BlockageMap> {
+    let _span = tracing::info_span!("build_blockage_map").entered();
+
+    // Cells are keyed by type plus their I/O macro width, since that's all that changes their
+    // blockers
+    let mut cache: HashMap<(String, i64), Blockers> = HashMap::new();
+    let mut map = BlockageMap::default();
+
+    for (cell_idx, cell) in design.cells.iter().enumerate() {
+        let width = match cell.r#type.as_str() {
+            "MCPNR_SWITCHES" => cell.get_param_i64_with_default("NSWITCH", 1)?,
+            "MCPNR_LIGHTS" => cell.get_param_i64_with_default("NLIGHT", 1)?,
+            _ => 0,
+        };
+        let key = (cell.r#type.clone(), width);
+        if !cache.contains_key(&key) {
+            let blockers = cell_blockers(cell_factory, rules, cell)
+                .with_context(|| anyhow!("Find blockers for {}", cell.r#type))?;
+            cache.insert(key.clone(), blockers);
+        }
+        let blockers = &cache[&key];
+
+        let base = cell
+            .pos
+            .as_ref()
+            .map(|p| [p.x, p.y, p.z])
+            .ok_or_else(|| anyhow!("Cell {} has no position", cell.describe(cell_idx)))?;
+
+        let mut blocked: Vec<[u32; 3]> = blockers
+            .blocked
+            .iter()
+            .filter_map(|pos| to_world(*pos, base))
+            .collect();
+        blocked.sort_unstable();
+        blocked.dedup();
+        map.footprints.push(Footprint {
+            cell: cell_idx,
+            cell_type: cell.r#type.clone(),
+            blocked,
+        });
+
+        for (pos, facing) in blockers.pins.iter() {
+            let pos = to_world(*pos, base).ok_or_else(|| {
+                anyhow!(
+                    "Pin of cell {} is outside of the world",
+                    cell.describe(cell_idx)
+                )
+            })?;
+            map.pins.push(PinLocation {
+                pos,
+                facing: *facing,
+            });
+        }
+    }
+
+    tracing::info!(
+        footprints = map.footprints.len(),
+        pins = map.pins.len(),
+        "Built blockage map"
+    );
+
+    Ok(map)
+}
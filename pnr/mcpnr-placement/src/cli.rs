@@ -0,0 +1,283 @@
+//! Command line options of the `mcpnr-placement` binary, which [`Config::from_args`] reads. The
+//! Python bindings and the `sweep` subcommand parse the options of the `place` subcommand with
+//! them too.
+//!
+//! [`Config::from_args`]: crate::config::Config::from_args
+
+use clap::{Arg, Command};
+
+/// Options shared by the subcommands that place a design
+pub fn add_common_args<'help>(command: Command<'help>) -> Command<'help> {
+    command
+        .arg(
+            Arg::new("TECHLIB")
+                .long("techlib")
+                .value_name("TECHLIB")
+                .allow_invalid_utf8(true)
+                .required(true)
+                .help("Specify the path to the technology library")
+                .long_help("
+The technology library is expected to be a folder, containing a folder named \"structures\" with a minecraft NBT structure format for each standard cell.
+"),
+        )
+        .arg(
+            Arg::new("SIZE_X")
+                .long("size-x")
+                .value_name("SIZE_X")
+                .default_value("192"),
+        )
+        .arg(
+            Arg::new("SIZE_Y")
+                .long("size-y")
+                .value_name("SIZE_Y")
+                .default_value("4"),
+        )
+        .arg(
+            Arg::new("SIZE_Z")
+                .long("size-z")
+                .value_name("SIZE_Z")
+                .default_value("192"),
+        )
+        .arg(
+            Arg::new("AUTO_SIZE")
+                .long("auto-size")
+                .help("Size the placement region to fit the design instead of using --size-x and --size-z")
+                .long_help("
+Size the placement region to fit the design, from the total footprint of its cells and the target fill, instead of using --size-x and --size-z. The region is as close to square as it can be, with X rounded up to the routing grid and Z to whole cell rows. A --size-x or --size-z given as well is kept, and only the other one is picked. The number of tiers is always taken from --size-y.
+"),
+        )
+        .arg(
+            Arg::new("TARGET_FILL")
+                .long("target-fill")
+                .value_name("FILL")
+                .default_value("0.8")
+                .help("Fraction of the placement region the cells should cover, from 0 to 1"),
+        )
+        .arg(
+            Arg::new("DENSITY_MAP")
+                .long("density-map")
+                .value_name("FILE")
+                .allow_invalid_utf8(true)
+                .help("JSON file giving parts of the placement region their own target fill")
+                .long_help("
+JSON file giving parts of the placement region a target fill of their own, as a list of boxes like `{\"x\": 0, \"z\": 0, \"size_x\": 192, \"size_z\": 16, \"target_fill\": 0.5}`, with an optional `tier`. Diffusion spreads cells out more in boxes with a lower fill than --target-fill, leaving room for routing in areas known to be congested.
+"),
+        )
+        .arg(
+            Arg::new("SWITCH_EDGE")
+                .long("switch-edge")
+                .value_name("EDGE")
+                .possible_values(["north", "south", "east", "west"])
+                .help("Distribute the switch macros along this edge of the placement region"),
+        )
+        .arg(
+            Arg::new("LIGHT_EDGE")
+                .long("light-edge")
+                .value_name("EDGE")
+                .possible_values(["north", "south", "east", "west"])
+                .help("Distribute the light macros along this edge of the placement region"),
+        )
+        .arg(
+            Arg::new("IO_CONSTRAINTS")
+                .long("io-constraints")
+                .value_name("FILE")
+                .allow_invalid_utf8(true)
+                .help("JSON file mapping switch/light cell names to fixed positions"),
+        )
+        .arg(
+            Arg::new("TIMING_CONSTRAINTS")
+                .long("timing-constraints")
+                .value_name("FILE")
+                .allow_invalid_utf8(true)
+                .help("Constraints file giving the timing criticality of nets")
+                .long_help("
+Constraints file giving the timing criticality of nets, from 0 to 1, with lines like `set_criticality 0.9 carry*`. Critical nets are kept short during placement, and the criticality is passed on to the router in the placed design.
+"),
+        )
+        .arg(
+            Arg::new("NET_WEIGHTS")
+                .long("net-weights")
+                .value_name("FILE")
+                .allow_invalid_utf8(true)
+                .help("Net weights from an earlier routing attempt, see the router's --net-feedback")
+                .long_help("
+Net weights from an earlier routing attempt, as written by the router's --net-feedback. The wirelength weight of each net is multiplied by its weight in the file, so the nets routing had trouble with are kept shorter this time.
+"),
+        )
+        .arg(
+            Arg::new("SOFT_MACROS")
+                .long("soft-macros")
+                .help("Place each sub-module of a hierarchical design on its own, as a single block")
+                .long_help("
+Place each sub-module of a hierarchical design on its own, and then place it as a single block (a soft macro) in the module above it. This keeps the cells of each sub-module together and is quicker for large designs. Without this, hierarchical designs are flattened before placement.
+"),
+        )
+        .arg(
+            Arg::new("MACRO_ASPECT_RATIO")
+                .long("macro-aspect-ratio")
+                .value_name("RATIO")
+                .default_value("1")
+                .help("Width (X) over depth (Z) to aim for when sizing soft macros"),
+        )
+        .arg(
+            Arg::new("FLATTEN_MACROS")
+                .long("flatten-macros")
+                .requires("SOFT_MACROS")
+                .help("Once the soft macros are placed, flatten the design and refine the placement of every cell, letting cells move out of their macros"),
+        )
+        .arg(
+            Arg::new("HARD_MACRO_THRESHOLD")
+                .long("hard-macro-threshold")
+                .value_name("BLOCKS")
+                .help("Place cells with a footprint of at least this many blocks first, as hard macros")
+                .long_help("
+Place cells with a footprint (X times Z) of at least this many blocks first, as hard macros. After the initial wirelength optimization the hard macros are placed by simulated annealing and locked in place, and the standard cells are then placed around them. Useful for designs with a few very large cells, like RAM banks or displays.
+"),
+        )
+        .arg(
+            Arg::new("DIFFUSION_MODE")
+                .long("diffusion-mode")
+                .value_name("MODE")
+                .possible_values(["auto", "3d", "per-tier"])
+                .default_value("auto")
+                .help("Whether diffusion spreads cells between tiers (3d) or only within them (per-tier)")
+                .long_help("
+Whether diffusion spreads cells between tiers as well as within them (3d), or diffuses each tier on its own (per-tier). Per tier is quicker and keeps cells on the tier the analytical placement put them on. The default, auto, is per tier for single tier designs and 3d otherwise.
+"),
+        )
+        .arg(
+            Arg::new("INITIAL_PLACEMENT")
+                .long("initial-placement")
+                .value_name("METHOD")
+                .possible_values(["analytical", "spectral"])
+                .default_value("analytical")
+                .help("How to place the cells before diffusion spreads them out")
+                .long_help("
+How to place the cells before diffusion spreads them out. analytical minimizes the wirelength, pulling the cells towards the I/O. spectral spreads the cells along the lowest eigenvectors of the netlist's graph Laplacian instead, which keeps connected cells together without collapsing the whole design in to a small blob, for designs with few I/O connections.
+"),
+        )
+        .arg(
+            Arg::new("CLIQUE_THRESHOLD")
+                .long("clique-threshold")
+                .value_name("PINS")
+                .default_value("2")
+                .help("Model nets with more pins than this as stars instead of cliques in the analytical placement"),
+        )
+        .arg(
+            Arg::new("DELTA_T")
+                .long("delta-t")
+                .value_name("TIME")
+                .default_value("0.1")
+                .help("Time step of the main diffusion steps")
+                .long_help("
+Time step of the main diffusion steps. Larger steps spread the cells out in fewer iterations, but move them further from where the wirelength wants them. The final diffusion step uses half of this.
+"),
+        )
+        .arg(
+            Arg::new("JITTER")
+                .long("jitter")
+                .value_name("BLOCKS")
+                .help("Move every cell by a random offset of up to this many blocks before diffusion")
+                .long_help("
+Move every cell by a pseudo-random offset of up to this many blocks along X and Z before diffusion. Analytical placement of symmetric circuits can put many cells at exactly the same position, which diffusion can't pull apart. The offsets are drawn from --jitter-seed, which is recorded in the placed design.
+"),
+        )
+        .arg(
+            Arg::new("JITTER_SEED")
+                .long("jitter-seed")
+                .value_name("SEED")
+                .default_value("0")
+                .help("Seed for the offsets of --jitter"),
+        )
+        .arg(
+            Arg::new("TIER_CROSSING_WEIGHT")
+                .long("tier-crossing-weight")
+                .value_name("WEIGHT")
+                .default_value("1.0")
+                .help("Extra weight for each tier a net spans in the analytical placement")
+                .long_help("
+Extra weight for each tier a net spans in the analytical placement, relative to the weight of the net. Every tier crossing needs an inter-tier via, so nets spanning several tiers are pulled together along Y harder than along X and Z. This is on by default, so placements differ from those made before the option was added; 0 treats all three axes the same, as those did.
+"),
+        )
+        .arg(
+            Arg::new("LEGALIZER")
+                .long("legalizer")
+                .value_name("LEGALIZER")
+                .possible_values(["tetris", "abacus"])
+                .default_value("tetris")
+                .help("Legalization algorithm to use"),
+        )
+        .arg(
+            Arg::new("MAX_MEMORY")
+                .long("max-memory")
+                .value_name("SIZE")
+                .help("Fail up front if placement would need more than this much memory, like 8G or 512MiB"),
+        )
+        .arg(
+            Arg::new("PROFILE")
+                .long("profile")
+                .value_name("FILE")
+                .allow_invalid_utf8(true)
+                .help("Write a Chrome trace of the placement schedule to this file")
+                .long_help("
+Write a trace of every span the placer enters, from whole schedule steps down to single iterations, to this file in the Chrome trace format. Open it in chrome://tracing or Perfetto to see which steps take the most time. The trace is recorded whatever RUST_LOG is set to.
+"),
+        )
+}
+
+/// Input and output files, which are only `required` by the subcommands that place a design
+pub fn add_io_args<'help>(command: Command<'help>, required: bool) -> Command<'help> {
+    command
+        .arg(
+            Arg::new("INPUT")
+                .help("Input design, as the output of a Yosys write_protobuf command")
+                .index(1)
+                .allow_invalid_utf8(true)
+                .required(required),
+        )
+        .arg(
+            Arg::new("OUTPUT")
+                .help("Output file location")
+                .long_help("
+Output file location. The placed design is written as JSON if it ends in .json, in the streaming format if it ends in .pbs (protobuf) or .jsonl (JSON lines), which the router can load without reading the whole file in to memory first, and as protobuf otherwise.
+")
+                .index(2)
+                .allow_invalid_utf8(true)
+                .required(required),
+        )
+        .arg(
+            Arg::new("BLOCKAGE_MAP")
+                .long("blockage-map")
+                .help("Also write the blocks occupied by each cell to this file, for the router")
+                .takes_value(true)
+                .allow_invalid_utf8(true),
+        )
+        .arg(
+            Arg::new("OUTPUT_JSON")
+                .long("output-json")
+                .value_name("FILE")
+                .help("Also write the placed design to this file as pretty-printed JSON")
+                .long_help("
+Also write the placed design to this file as pretty-printed JSON, for diffing and editing by hand. The router reads placed designs from files ending in .json as JSON, so an edited copy can be routed directly.
+")
+                .allow_invalid_utf8(true),
+        )
+        .arg(
+            Arg::new("RESULTS")
+                .long("results")
+                .value_name("FILE")
+                .help("Append a row with the results of placement to this CSV file")
+                .long_help("
+Append a row with the results of placement (the design, a hash of the options, the HPWL of the legalized placement and the runtime) to this CSV file. The router's --results can log to the same file. The report subcommand prints how the results changed between runs.
+")
+                .allow_invalid_utf8(true),
+        )
+}
+
+/// The `place` subcommand
+pub fn place_command() -> Command<'static> {
+    add_io_args(
+        add_common_args(Command::new("place").before_help("Run the placer in headless mode")),
+        true,
+    )
+}
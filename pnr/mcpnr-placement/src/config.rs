@@ -1,8 +1,12 @@
 //! Global registry for configuration of the various placement stages.
 //!
 
-use anyhow::{Context, Result};
-use std::path::PathBuf;
+use anyhow::{anyhow, Context, Result};
+use mcpnr_common::{blockers::BLOCKER_RULES_FILE, memory::parse_size, BLOCKS_PER_Z_ROW};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+use crate::io_planner::IoEdge;
 
 /// Configuration variables related to input/output operations
 #[derive(Clone, Debug)]
@@ -13,6 +17,31 @@ pub struct IOConfig {
     pub output_file: PathBuf,
     /// Directory of the structure database, derviced from the path to the technology library.
     pub structure_directory: PathBuf,
+    /// Extra rules for which blocks wires must avoid, derived from the path to the technology
+    /// library. The file doesn't have to exist.
+    pub blocker_rules_file: PathBuf,
+    /// Where to write the blockage map for the router, if anywhere
+    pub blockage_map_file: Option<PathBuf>,
+    /// Where to write the placed design as JSON as well, if anywhere
+    pub placed_json_file: Option<PathBuf>,
+    /// Constraints file giving the timing criticality of nets, if any
+    pub timing_constraints_file: Option<PathBuf>,
+    /// Net weights fed back from an earlier routing attempt, if any
+    pub net_weights_file: Option<PathBuf>,
+    /// CSV file to log the results of placement to, if any
+    pub results_file: Option<PathBuf>,
+}
+
+/// Configuration of the I/O planner
+#[derive(Clone, Debug)]
+pub struct IOPlanConfig {
+    /// Edge to distribute the switch macros along, if any
+    pub switch_edge: Option<IoEdge>,
+    /// Edge to distribute the light macros along, if any
+    pub light_edge: Option<IoEdge>,
+    /// JSON file with explicit positions for individual I/O macros, which take priority over the
+    /// edge assignments
+    pub constraints_file: Option<PathBuf>,
 }
 
 /// Geometry of the placement region
@@ -26,19 +55,163 @@ pub struct GeometryConfig {
     pub size_z: u32,
     /// Desired overall normalized density of the placement, in the range 0-1
     pub target_fill: f32,
+    /// Parts of the region with a target fill of their own, like a sparser strip along the I/O
+    /// edge to leave the router room. Where they overlap, the last one wins.
+    pub density_map: Vec<DensityRegion>,
+}
+
+/// A box of the placement region with its own target fill. Coordinates are in blocks, except for
+/// `tier`.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct DensityRegion {
+    pub x: u32,
+    pub z: u32,
+    pub size_x: u32,
+    pub size_z: u32,
+    /// Tier the box is on, or every tier if not given
+    #[serde(default)]
+    pub tier: Option<u32>,
+    /// Fill to aim for inside the box instead of the overall target fill, from 0 to 1
+    pub target_fill: f32,
+}
+
+impl DensityRegion {
+    /// Whether the point at `x`, `z` in blocks on tier `tier_y` is inside the box
+    pub fn contains(&self, x: f32, tier_y: f32, z: f32) -> bool {
+        x >= self.x as f32
+            && x < (self.x + self.size_x) as f32
+            && z >= self.z as f32
+            && z < (self.z + self.size_z) as f32
+            && !matches!(
+                self.tier,
+                Some(tier) if tier_y < tier as f32 || tier_y >= (tier + 1) as f32
+            )
+    }
+}
+
+/// Load a density map, which is a JSON list of [`DensityRegion`]s:
+///
+/// ```json
+/// [{ "x": 0, "z": 0, "size_x": 192, "size_z": 16, "target_fill": 0.5 }]
+/// ```
+pub fn load_density_map(path: &Path) -> Result<Vec<DensityRegion>> {
+    let reader =
+        std::fs::File::open(path).with_context(|| anyhow!("Open density map {:?}", path))?;
+    serde_json::from_reader(std::io::BufReader::new(reader))
+        .with_context(|| anyhow!("Parse density map {:?}", path))
+}
+
+/// Sizing the placement region to fit the design, instead of taking its size from the command
+/// line. Only the X and Z sizes are picked, the number of tiers is always given.
+#[derive(Clone, Debug)]
+pub struct AutoSizeConfig {
+    /// X size given on the command line, which is kept instead of being picked
+    pub size_x: Option<u32>,
+    /// Z size given on the command line, which is kept instead of being picked
+    pub size_z: Option<u32>,
+}
+
+impl AutoSizeConfig {
+    /// Region with room for cells of the given footprints, `[x, tiers, z]`, at the target fill of
+    /// `geometry`. It's as close to square as the sizes given on the command line allow, with X
+    /// rounded up to the 2 block routing grid and Z to whole cell rows.
+    pub fn size(
+        &self,
+        geometry: &GeometryConfig,
+        footprints: impl IntoIterator<Item = [u32; 3]>,
+    ) -> GeometryConfig {
+        let (mut area, mut widest, mut deepest) = (0u64, 0, 0);
+        for [sx, tiers, sz] in footprints {
+            // Cells are aligned to the routing grid, so odd sizes waste the block after them
+            area += sx.next_multiple_of(2) as u64 * sz as u64 * tiers.max(1) as u64;
+            widest = widest.max(sx);
+            deepest = deepest.max(sz);
+        }
+        let area = area as f32 / (geometry.target_fill * geometry.size_y.max(1) as f32);
+
+        let size_x = self.size_x.unwrap_or_else(|| {
+            (area.sqrt().ceil() as u32)
+                .max(widest)
+                .max(2)
+                .next_multiple_of(2)
+        });
+        let size_z = self.size_z.unwrap_or_else(|| {
+            ((area / size_x as f32).ceil() as u32)
+                .max(deepest)
+                .max(1)
+                .next_multiple_of(BLOCKS_PER_Z_ROW)
+        });
+
+        GeometryConfig {
+            size_x,
+            size_z,
+            ..geometry.clone()
+        }
+    }
+}
+
+/// How the diffusion placer treats the tiers of the placement region
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiffusionMode {
+    /// Diffuse in 3D, so cells spread between the tiers as well as within them. Regions are
+    /// `region_size` tiers tall.
+    Volume,
+    /// Diffuse each tier in 2D on its own, with regions 1 tier tall. Cells stay on their tier, and
+    /// it's quicker than the 3D diffusion, which has nowhere to go along Y in single tier designs.
+    PerTier,
+}
+
+impl DiffusionMode {
+    /// Mode to use when none is given: per tier for single tier designs, 3D otherwise
+    pub fn for_geometry(geometry: &GeometryConfig) -> Self {
+        if geometry.size_y <= 1 {
+            DiffusionMode::PerTier
+        } else {
+            DiffusionMode::Volume
+        }
+    }
 }
 
 /// Configuration of the diffusion placer
 #[derive(Clone, Debug)]
 pub struct DiffusionConfig {
-    /// Number of blocks per region.
+    /// Number of blocks per region along X and Z, and tiers per region along Y in
+    /// [`DiffusionMode::Volume`]. Regions at the far edges of the placement region are cut short
+    /// if this doesn't divide its size.
     pub region_size: u32,
+    /// Whether to diffuse in 3D or in each tier on its own
+    pub mode: DiffusionMode,
     /// Total amount of internal timesteps for 1 diffusion step
     pub iterations: u32,
     /// How much virtual time we should elapse per internal timestep
     pub delta_t: f32,
 }
 
+/// Configuration of the hard macro placer
+#[derive(Clone, Debug)]
+pub struct HardMacroConfig {
+    /// Cells with a footprint (X times Z) of at least this many blocks are placed as hard macros
+    pub footprint_threshold: u32,
+    /// Number of moves to make while annealing
+    pub iterations: u32,
+}
+
+/// Terms of the analytical placement objective on top of the quadratic wirelength
+#[derive(Clone, Debug)]
+pub struct ObjectiveConfig {
+    /// Extra weight for each tier a net spans, relative to the weight of the net. Every tier
+    /// crossing needs an inter-tier via, which costs far more than the same distance along X or Z,
+    /// so nets spanning tiers are pulled together along Y this much harder.
+    pub tier_crossing_weight: f32,
+}
+
+impl ObjectiveConfig {
+    /// Plain wirelength, treating Y like X and Z
+    pub const WIRELENGTH_ONLY: ObjectiveConfig = ObjectiveConfig {
+        tier_crossing_weight: 0.0,
+    };
+}
+
 /// Overall schedule for the placement strategy
 ///
 /// TODO: we probably want some sort of dynamic scheduling, where the system pays attention to
@@ -49,12 +222,29 @@ pub struct PlacementSchedule {
     pub schedule: Vec<PlacementStep>,
 }
 
+impl PlacementSchedule {
+    /// Seeds of the [`PlacementStep::Jitter`] steps, in schedule order
+    pub fn jitter_seeds(&self) -> Vec<u64> {
+        self.schedule
+            .iter()
+            .filter_map(|step| match step {
+                PlacementStep::Jitter { seed, .. } => Some(*seed),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
 /// An individual step in the placement schedule
 #[derive(Clone, Debug)]
 pub enum PlacementStep {
     /// Center all moveable cells in the placement region. This helps avoid the IO cells clumping
     /// everything in to one of the edges.
     CenterCells,
+    /// Spread the cells out along the low eigenvectors of the netlist's graph Laplacian, see
+    /// [`crate::placer::spectral`]. An alternative to the unconstrained wirelength optimization
+    /// for designs where that collapses everything in to a small blob.
+    Spectral,
     /// Basic unconstrained wirelength optimization
     UnconstrainedAnalytical {
         /// The threshold at which we switch from a clique model to a moveable star model in the
@@ -72,97 +262,344 @@ pub enum PlacementStep {
         /// Number of iterations to run
         iterations: usize,
     },
+    /// Analytical wirelength optimization with the bound-to-bound net model. The model is rebuilt
+    /// from the previous solution each iteration.
+    Bound2BoundAnalytical {
+        /// Number of iterations to run
+        iterations: usize,
+    },
+    /// Move every mobile cell by a small pseudo-random offset, so diffusion can pull apart cells
+    /// the analytical placement put at exactly the same coordinates
+    Jitter {
+        /// Largest offset along X and Z, in blocks
+        magnitude: f32,
+        /// Seed for the offsets. Recorded in the placed design, so the placement can be reproduced.
+        seed: u64,
+    },
+    /// Place the cells with the largest footprints by simulated annealing and lock them in place,
+    /// leaving the later steps to place the standard cells around them
+    HardMacros(HardMacroConfig),
+}
+
+/// Configuration for the legalizer, selecting which algorithm to use
+#[derive(Clone, Debug)]
+pub enum LegalizerConfig {
+    /// The TETRIS legalizer. Fast, but can move cells a long way in dense regions.
+    Tetris {
+        /// Left-hand limit (how far left of the original X position we're allowed to place a
+        /// given cell.)
+        left_limit: u32,
+    },
+    /// The Abacus legalizer, which minimizes the displacement of cells from their global
+    /// placement.
+    Abacus,
 }
 
-/// Configuration for the legalizer.
-/// Currenetly directly the configuration for the TETRIS legalizer, but in principle this could be
-/// made an enumeration of configs for different legalizer types
+/// Configuration of hierarchical placement
 #[derive(Clone, Debug)]
-pub struct LegalizerConfig {
-    /// Left-hand limit (how far left of the original X position we're allowed to place a given
-    /// cell.)
-    pub left_limit: u32,
+pub struct HierarchyConfig {
+    /// Place each sub-module on its own as a soft macro, instead of flattening the design before
+    /// placement
+    pub soft_macros: bool,
+    /// Width (X) over depth (Z) to aim for when sizing the region for a soft macro
+    pub macro_aspect_ratio: f32,
+    /// Schedule to refine the placement of the flattened design with once the soft macros are
+    /// placed, if any
+    pub refine_schedule: Option<PlacementSchedule>,
 }
 
 /// Overall placement configuration
 #[derive(Clone, Debug)]
 pub struct Config {
     pub io: IOConfig,
+    pub io_plan: IOPlanConfig,
     pub geometry: GeometryConfig,
     pub schedule: PlacementSchedule,
+    pub objective: ObjectiveConfig,
     pub legalizer: LegalizerConfig,
+    pub hierarchy: HierarchyConfig,
+    /// How to size the placement region to fit the design, if it should be
+    pub auto_size: Option<AutoSizeConfig>,
+    /// Memory the solvers may use, in bytes, if limited
+    pub max_memory: Option<u64>,
 }
 
 impl Config {
     /// Construct a baseline configuration from the clap argument matches
     pub fn from_args(matches: &clap::ArgMatches) -> Result<Self> {
         let techlib_directory = PathBuf::from(matches.value_of_os("TECHLIB").unwrap());
-        let clique_threshold = 2;
+        let clique_threshold = matches
+            .value_of("CLIQUE_THRESHOLD")
+            .unwrap()
+            .parse()
+            .context("Parse CLIQUE_THRESHOLD")?;
+        let delta_t: f32 = matches
+            .value_of("DELTA_T")
+            .unwrap()
+            .parse()
+            .context("Parse DELTA_T")?;
+        let geometry = GeometryConfig {
+            size_x: matches
+                .value_of("SIZE_X")
+                .unwrap()
+                .parse()
+                .context("Parse SIZE_X")?,
+            size_y: matches
+                .value_of("SIZE_Y")
+                .unwrap()
+                .parse()
+                .context("Parse SIZE_Y")?,
+            size_z: matches
+                .value_of("SIZE_Z")
+                .unwrap()
+                .parse()
+                .context("Parse SIZE_Z")?,
+            target_fill: matches
+                .value_of("TARGET_FILL")
+                .unwrap()
+                .parse()
+                .context("Parse TARGET_FILL")?,
+            density_map: match matches.value_of_os("DENSITY_MAP") {
+                Some(path) => load_density_map(Path::new(path))?,
+                None => Vec::new(),
+            },
+        };
+        // Sizes given explicitly win over the ones auto-sizing would pick
+        let given = |name: &str, size: u32| (matches.occurrences_of(name) > 0).then_some(size);
+        let auto_size = matches.is_present("AUTO_SIZE").then(|| AutoSizeConfig {
+            size_x: given("SIZE_X", geometry.size_x),
+            size_z: given("SIZE_Z", geometry.size_z),
+        });
+        let diffusion_mode = match matches.value_of("DIFFUSION_MODE") {
+            Some("3d") => DiffusionMode::Volume,
+            Some("per-tier") => DiffusionMode::PerTier,
+            _ => DiffusionMode::for_geometry(&geometry),
+        };
         let diffusion_config = DiffusionConfig {
             region_size: 2,
+            mode: diffusion_mode,
             iterations: 512,
-            delta_t: 0.1,
+            delta_t,
         };
 
+        let hard_macro_threshold = matches
+            .value_of("HARD_MACRO_THRESHOLD")
+            .map(str::parse)
+            .transpose()
+            .context("Parse HARD_MACRO_THRESHOLD")?;
+
+        let mut schedule = match matches.value_of("INITIAL_PLACEMENT") {
+            // Spread the cells along the netlist's low eigenvectors
+            Some("spectral") => vec![PlacementStep::Spectral],
+            _ => vec![
+                // Initial unconstrained placement
+                PlacementStep::UnconstrainedAnalytical { clique_threshold },
+                // Refine the clique/star solution towards minimum HPWL
+                PlacementStep::Bound2BoundAnalytical { iterations: 4 },
+            ],
+        };
+        if let Some(footprint_threshold) = hard_macro_threshold {
+            // Fix the big cells where the wirelength wants them, before diffusion spreads the
+            // standard cells out around them
+            schedule.push(PlacementStep::HardMacros(HardMacroConfig {
+                footprint_threshold,
+                iterations: 20000,
+            }));
+        }
+        // Center cells as setup for diffusion
+        schedule.push(PlacementStep::CenterCells);
+        if let Some(magnitude) = matches
+            .value_of("JITTER")
+            .map(str::parse)
+            .transpose()
+            .context("Parse JITTER")?
+        {
+            // Break up cells stacked on top of each other before diffusion sees them
+            schedule.push(PlacementStep::Jitter {
+                magnitude,
+                seed: matches
+                    .value_of("JITTER_SEED")
+                    .unwrap()
+                    .parse()
+                    .context("Parse JITTER_SEED")?,
+            });
+        }
+        schedule.extend([
+            // Main diffusion steps
+            PlacementStep::Diffusion(diffusion_config.clone()),
+            PlacementStep::ConstrainedAnalytical {
+                clique_threshold,
+                iterations: 2,
+            },
+            PlacementStep::Diffusion(diffusion_config.clone()),
+            PlacementStep::ConstrainedAnalytical {
+                clique_threshold,
+                iterations: 2,
+            },
+            PlacementStep::Diffusion(diffusion_config.clone()),
+            PlacementStep::ConstrainedAnalytical {
+                clique_threshold,
+                iterations: 2,
+            },
+            PlacementStep::Diffusion(diffusion_config.clone()),
+            PlacementStep::ConstrainedAnalytical {
+                clique_threshold,
+                iterations: 1,
+            },
+            PlacementStep::Diffusion(DiffusionConfig {
+                region_size: 2,
+                mode: diffusion_mode,
+                iterations: 64,
+                delta_t: delta_t / 2.0,
+            }),
+        ]);
+
         Ok(Config {
             io: IOConfig {
-                input_file: PathBuf::from(matches.value_of_os("INPUT").unwrap()),
-                output_file: PathBuf::from(matches.value_of_os("OUTPUT").unwrap()),
+                // Only optional for the doctor, which leaves them empty when not given
+                input_file: matches
+                    .value_of_os("INPUT")
+                    .map(PathBuf::from)
+                    .unwrap_or_default(),
+                output_file: matches
+                    .value_of_os("OUTPUT")
+                    .map(PathBuf::from)
+                    .unwrap_or_default(),
                 structure_directory: techlib_directory.join("structures"),
+                blocker_rules_file: techlib_directory.join(BLOCKER_RULES_FILE),
+                blockage_map_file: matches.value_of_os("BLOCKAGE_MAP").map(PathBuf::from),
+                placed_json_file: matches.value_of_os("OUTPUT_JSON").map(PathBuf::from),
+                timing_constraints_file: matches
+                    .value_of_os("TIMING_CONSTRAINTS")
+                    .map(PathBuf::from),
+                net_weights_file: matches.value_of_os("NET_WEIGHTS").map(PathBuf::from),
+                results_file: matches.value_of_os("RESULTS").map(PathBuf::from),
             },
-            geometry: GeometryConfig {
-                size_x: matches
-                    .value_of("SIZE_X")
-                    .unwrap()
-                    .parse()
-                    .context("Parse SIZE_X")?,
-                size_y: matches
-                    .value_of("SIZE_Y")
+            io_plan: IOPlanConfig {
+                switch_edge: matches
+                    .value_of("SWITCH_EDGE")
+                    .map(str::parse)
+                    .transpose()
+                    .context("Parse SWITCH_EDGE")?,
+                light_edge: matches
+                    .value_of("LIGHT_EDGE")
+                    .map(str::parse)
+                    .transpose()
+                    .context("Parse LIGHT_EDGE")?,
+                constraints_file: matches.value_of_os("IO_CONSTRAINTS").map(PathBuf::from),
+            },
+            geometry,
+            schedule: PlacementSchedule { schedule },
+            objective: ObjectiveConfig {
+                tier_crossing_weight: matches
+                    .value_of("TIER_CROSSING_WEIGHT")
                     .unwrap()
                     .parse()
-                    .context("Parse SIZE_Y")?,
-                size_z: matches
-                    .value_of("SIZE_Z")
+                    .context("Parse TIER_CROSSING_WEIGHT")?,
+            },
+            legalizer: match matches.value_of("LEGALIZER") {
+                Some("abacus") => LegalizerConfig::Abacus,
+                _ => LegalizerConfig::Tetris { left_limit: 8 },
+            },
+            hierarchy: HierarchyConfig {
+                soft_macros: matches.is_present("SOFT_MACROS"),
+                macro_aspect_ratio: matches
+                    .value_of("MACRO_ASPECT_RATIO")
                     .unwrap()
                     .parse()
-                    .context("Parse SIZE_Z")?,
-                target_fill: 0.8,
-            },
-            schedule: PlacementSchedule {
-                schedule: vec![
-                    // Initial unconstrained placement
-                    PlacementStep::UnconstrainedAnalytical { clique_threshold },
-                    // Center cells as setup for diffusion
-                    PlacementStep::CenterCells,
-                    // Main diffusion steps
-                    PlacementStep::Diffusion(diffusion_config.clone()),
-                    PlacementStep::ConstrainedAnalytical {
-                        clique_threshold,
-                        iterations: 2,
-                    },
-                    PlacementStep::Diffusion(diffusion_config.clone()),
-                    PlacementStep::ConstrainedAnalytical {
-                        clique_threshold,
-                        iterations: 2,
-                    },
-                    PlacementStep::Diffusion(diffusion_config.clone()),
-                    PlacementStep::ConstrainedAnalytical {
-                        clique_threshold,
-                        iterations: 2,
-                    },
-                    PlacementStep::Diffusion(diffusion_config.clone()),
-                    PlacementStep::ConstrainedAnalytical {
-                        clique_threshold,
-                        iterations: 1,
-                    },
-                    PlacementStep::Diffusion(DiffusionConfig {
-                        region_size: 2,
-                        iterations: 64,
-                        delta_t: 0.05,
-                    }),
-                ],
+                    .context("Parse MACRO_ASPECT_RATIO")?,
+                refine_schedule: matches.is_present("FLATTEN_MACROS").then(|| {
+                    PlacementSchedule {
+                        schedule: vec![
+                            // Let cells out of their macros, but keep them near where they were
+                            PlacementStep::ConstrainedAnalytical {
+                                clique_threshold,
+                                iterations: 1,
+                            },
+                            PlacementStep::Diffusion(DiffusionConfig {
+                                region_size: 2,
+                                mode: diffusion_mode,
+                                iterations: 64,
+                                delta_t: 0.05,
+                            }),
+                        ],
+                    }
+                }),
             },
-            legalizer: LegalizerConfig { left_limit: 8 },
+            auto_size,
+            max_memory: matches
+                .value_of("MAX_MEMORY")
+                .map(parse_size)
+                .transpose()
+                .context("Parse MAX_MEMORY")?,
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{AutoSizeConfig, GeometryConfig};
+
+    fn geometry(size_y: u32, target_fill: f32) -> GeometryConfig {
+        GeometryConfig {
+            size_x: 0,
+            size_y,
+            size_z: 0,
+            target_fill,
+            density_map: Vec::new(),
+        }
+    }
+
+    fn size(
+        auto_size: AutoSizeConfig,
+        geometry: &GeometryConfig,
+        cells: &[[u32; 3]],
+    ) -> (u32, u32) {
+        let sized = auto_size.size(geometry, cells.iter().copied());
+        assert_eq!(sized.size_y, geometry.size_y);
+        assert_eq!(sized.target_fill, geometry.target_fill);
+        (sized.size_x, sized.size_z)
+    }
+
+    const PICK_BOTH: AutoSizeConfig = AutoSizeConfig {
+        size_x: None,
+        size_z: None,
+    };
+
+    #[test]
+    fn square_region_at_the_target_fill() {
+        // 240 blocks of cells at half full need 480, so 22 along X and 22 rounded up to a row
+        // along Z
+        let cells = [[4, 1, 6]; 10];
+        assert_eq!(size(PICK_BOTH, &geometry(1, 0.5), &cells), (22, 24));
+
+        // Spread over two tiers each tier only needs half the area
+        assert_eq!(size(PICK_BOTH, &geometry(2, 0.5), &cells), (16, 16));
+
+        // Odd widths take up the rest of the routing grid cell after them
+        let odd = [[3, 1, 6]; 10];
+        assert_eq!(size(PICK_BOTH, &geometry(1, 0.5), &odd), (22, 24));
+    }
+
+    #[test]
+    fn sizes_given_on_the_command_line_are_kept() {
+        let cells = [[4, 1, 6]; 10];
+        let auto_size = AutoSizeConfig {
+            size_x: Some(8),
+            size_z: None,
+        };
+        assert_eq!(size(auto_size, &geometry(1, 0.5), &cells), (8, 64));
+
+        let auto_size = AutoSizeConfig {
+            size_x: Some(30),
+            size_z: Some(10),
+        };
+        assert_eq!(size(auto_size, &geometry(1, 0.5), &cells), (30, 10));
+    }
+
+    #[test]
+    fn region_fits_the_largest_cell() {
+        assert_eq!(size(PICK_BOTH, &geometry(1, 1.0), &[[40, 1, 2]]), (40, 8));
+        assert_eq!(size(PICK_BOTH, &geometry(1, 1.0), &[[2, 1, 20]]), (8, 24));
+    }
+}
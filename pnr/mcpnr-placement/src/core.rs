@@ -1,17 +1,32 @@
 use std::collections::HashMap;
 
 use anyhow::{anyhow, Context, Result};
+use itertools::Itertools;
 use mcpnr_common::{
     protos::mcpnr::{
-        parameter::Value, placed_design::Cell, BitVector, NetMetadata, Parameter,
+        parameter::Value, placed_design::Cell, signal::Type, BitVector, NetMetadata, Parameter,
         PlacedDesign, Position,
     },
-    yosys::{ConstOrSignal, Module}, BLOCKS_PER_TIER,
+    protos::PLACED_DESIGN_VERSION,
+    timing_constraints::{parse_criticality, CRITICALITY_ATTRIBUTE},
+    yosys::{self, parse_numeric_attribute, ConstOrSignal, Module, NetName},
+    BLOCKS_PER_TIER,
 };
+use nalgebra::Vector3;
 
 use crate::placement_cell::{CellFactory, LegalizedCell, PlacementCell};
 
+/// Net attribute used to set the weight of a net in wirelength optimization, e.g.
+/// `(* mcpnr_weight = "5" *) wire critical;`
+pub const NET_WEIGHT_ATTRIBUTE: &str = "mcpnr_weight";
+
+/// Extra weight given to a net for being timing critical. A net with a criticality of 1 weighs
+/// this many times more than it would otherwise, on top of one times its own weight.
+pub const CRITICALITY_WEIGHT: f32 = 4.0;
+
 pub struct CellMetadata {
+    /// Name of the cell in the Yosys netlist
+    pub name: String,
     /// Map from attribute name to value
     pub attributes: HashMap<String, Parameter>,
     /// Map from port name to direction
@@ -27,8 +42,20 @@ pub struct Signal {
     /// Vector of indicies into `PlaceableCells::cells`
     pub connected_cells: Vec<usize>,
 
+    /// Offset of the pin from the center of the cell, for each entry in
+    /// [`Signal::connected_cells`].
+    pub pin_offsets: Vec<Vector3<f32>>,
+
     /// Number of cells in [`Signal::connected_cells`] that are moveable.
     pub moveable_cells: usize,
+
+    /// Yosys signal number of this net, see [`NetlistHypergraph::signal_name`]
+    pub id: u64,
+
+    /// Relative weight of this signal in wirelength optimization, taken from the
+    /// [`NET_WEIGHT_ATTRIBUTE`] attribute and scaled up by the criticality of the net (see
+    /// [`CRITICALITY_WEIGHT`]). Defaults to 1.0.
+    pub weight: f32,
 }
 
 impl Signal {
@@ -41,6 +68,11 @@ impl Signal {
             .filter(|idx| !net.cells[**idx].pos_locked)
             .map(|x| *x)
     }
+
+    /// Position of the `pin`th pin of this signal, given the current position of its cell.
+    pub fn pin_pos(&self, net: &NetlistHypergraph, pin: usize) -> Vector3<f32> {
+        net.cells[self.connected_cells[pin]].center_pos() + self.pin_offsets[pin]
+    }
 }
 
 /// Represents the netlist as a hypergraph. [`NetlistHypergraph::cells`] are the nodes,
@@ -79,9 +111,40 @@ impl NetlistHypergraph {
 
     /// Construct a placement cell from a Yosys module
     pub fn from_module(m: Module, cell_factory: &mut CellFactory) -> Result<Self> {
+        // Collect weights first, if a signal has several names with weights the largest wins
+        let mut signal_weights: HashMap<u64, f32> = HashMap::new();
+        // Likewise the most critical name wins
+        let mut signal_criticality: HashMap<u64, f32> = HashMap::new();
+        for (name, net) in m.netnames.iter() {
+            let weight = net
+                .attributes
+                .get(NET_WEIGHT_ATTRIBUTE)
+                .map(|weight| parse_net_weight(weight))
+                .transpose()
+                .with_context(|| anyhow!("Weight for net {:?}", name))?;
+            let criticality = net
+                .attributes
+                .get(CRITICALITY_ATTRIBUTE)
+                .map(|criticality| parse_criticality(criticality))
+                .transpose()
+                .with_context(|| anyhow!("Criticality for net {:?}", name))?;
+            for bit in net.bits.iter() {
+                if let ConstOrSignal::Signal(s) = bit {
+                    if let Some(weight) = weight {
+                        let entry = signal_weights.entry(*s as u64).or_insert(weight);
+                        *entry = entry.max(weight);
+                    }
+                    if let Some(criticality) = criticality {
+                        let entry = signal_criticality.entry(*s as u64).or_insert(criticality);
+                        *entry = entry.max(criticality);
+                    }
+                }
+            }
+        }
+
         let mut cells = Vec::with_capacity(m.cells.len());
         let mut metadata = Vec::with_capacity(m.cells.len());
-        let mut signals: HashMap<u64, Vec<usize>> = HashMap::new();
+        let mut signals: HashMap<u64, Vec<(usize, Vector3<f32>)>> = HashMap::new();
 
         // For each cell in the module,
         for (key, cell) in m.cells {
@@ -92,21 +155,29 @@ impl NetlistHypergraph {
                     .with_context(|| anyhow!("Pushing cell {:?}", key))?,
             );
 
-            for (_, bits) in &cell.connections {
-                for signal in bits.iter() {
+            for (port, bits) in &cell.connections {
+                for (bit, signal) in bits.iter().enumerate() {
                     match signal {
                         ConstOrSignal::Const(_c) => {
                             // log::warn!("Connection to a constant wire {c}")
                         }
-                        ConstOrSignal::Signal(s) => signals
-                            .entry(*s as u64)
-                            .or_insert_with(|| Vec::new())
-                            .push(cell_idx),
+                        ConstOrSignal::Signal(s) => {
+                            let offset = cell_factory
+                                .pin_offset(&cell, &cells[cell_idx], port, bit)
+                                .with_context(|| {
+                                    anyhow!("Pin {}[{}] of cell {:?}", port, bit, key)
+                                })?;
+                            signals
+                                .entry(*s as u64)
+                                .or_default()
+                                .push((cell_idx, offset))
+                        }
                     }
                 }
             }
 
             metadata.push(CellMetadata {
+                name: key,
                 attributes: cell
                     .attributes
                     .into_iter()
@@ -150,9 +221,21 @@ impl NetlistHypergraph {
 
         let mut signals: Vec<_> = signals
             .into_iter()
-            .map(|(_, v)| Signal {
-                moveable_cells: v.iter().filter(|idx| !cells[**idx].pos_locked).count(),
-                connected_cells: v,
+            .map(|(k, v)| {
+                let (connected_cells, pin_offsets): (Vec<_>, Vec<_>) = v.into_iter().unzip();
+                Signal {
+                    moveable_cells: connected_cells
+                        .iter()
+                        .filter(|idx| !cells[**idx].pos_locked)
+                        .count(),
+                    connected_cells,
+                    pin_offsets,
+                    id: k,
+                    weight: signal_weights.get(&k).copied().unwrap_or(1.0)
+                        * (1.0
+                            + CRITICALITY_WEIGHT
+                                * signal_criticality.get(&k).copied().unwrap_or(0.0)),
+                }
             })
             .collect();
 
@@ -242,15 +325,197 @@ impl NetlistHypergraph {
         })
     }
 
+    /// Rebuild the hypergraph of a design that has already been placed, with each cell where the
+    /// placement put it. The placement is returned too, as legalized cells in the same order as
+    /// [`NetlistHypergraph::cells`].
+    pub fn from_placed_design(
+        design: &PlacedDesign,
+        cell_factory: &mut CellFactory,
+    ) -> Result<(Self, Vec<LegalizedCell>)> {
+        // Cells placed before names were kept are named after their index
+        let names: Vec<String> = design
+            .cells
+            .iter()
+            .enumerate()
+            .map(|(idx, cell)| {
+                if cell.name.is_empty() {
+                    format!("$mcpnr_cell${}", idx)
+                } else {
+                    cell.name.clone()
+                }
+            })
+            .collect();
+        let mut cell_indices = HashMap::new();
+        for (idx, name) in names.iter().enumerate() {
+            if cell_indices.insert(name.as_str(), idx).is_some() {
+                return Err(anyhow!("More than one cell is named {:?}", name));
+            }
+        }
+
+        let module = Module {
+            attributes: HashMap::new(),
+            parameter_default_values: None,
+            ports: HashMap::new(),
+            cells: design
+                .cells
+                .iter()
+                .zip(names.iter())
+                .map(|(cell, name)| {
+                    let cell = yosys::Cell {
+                        hide_name: 0,
+                        ty: cell.r#type.clone(),
+                        parameters: parameter_strings(&cell.parameter),
+                        attributes: parameter_strings(&cell.attribute),
+                        port_directions: HashMap::new(),
+                        connections: cell
+                            .connection
+                            .iter()
+                            .map(|(port, bits)| (port.clone(), yosys_bits(bits)))
+                            .collect(),
+                    };
+                    (name.clone(), cell)
+                })
+                .collect(),
+            netnames: design
+                .nets
+                .iter()
+                .map(|(name, net)| {
+                    let net = NetName {
+                        hide_name: net.hide_name as usize,
+                        bits: net.bits.as_ref().map(yosys_bits).unwrap_or_default(),
+                        attributes: parameter_strings(&net.attributes),
+                    };
+                    (name.clone(), net)
+                })
+                .collect(),
+        };
+
+        let mut netlist = Self::from_module(module, cell_factory)?;
+        let legalized = netlist
+            .cells
+            .iter_mut()
+            .zip(netlist.metadata.iter())
+            .map(|(cell, metadata)| {
+                let idx = cell_indices[metadata.name.as_str()];
+                let pos = design.cells[idx]
+                    .pos
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("Cell {:?} has no position", metadata.name))?;
+                cell.x = pos.x as f32;
+                cell.tier_y = (pos.y / BLOCKS_PER_TIER) as f32;
+                cell.z = pos.z as f32;
+                Ok(LegalizedCell::from_placement(cell))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok((netlist, legalized))
+    }
+
+    /// Half-perimeter wirelength of the legalized placement `legalized`, weighted by net weight, in
+    /// blocks. The cells are taken in the same order as [`NetlistHypergraph::cells`].
+    pub fn legalized_wirelength(&self, legalized: &[LegalizedCell]) -> f32 {
+        self.signals
+            .iter()
+            .filter(|signal| !signal.connected_cells.is_empty())
+            .map(|signal| {
+                let mut min = [f32::INFINITY; 3];
+                let mut max = [f32::NEG_INFINITY; 3];
+                for &cell in signal.connected_cells.iter() {
+                    let cell = &legalized[cell];
+                    let center = [
+                        cell.x as f32 + cell.sx as f32 / 2.0,
+                        (cell.tier_y * BLOCKS_PER_TIER) as f32,
+                        cell.z as f32 + cell.sz as f32 / 2.0,
+                    ];
+                    for ((min, max), c) in min.iter_mut().zip(max.iter_mut()).zip(center) {
+                        *min = min.min(c);
+                        *max = max.max(c);
+                    }
+                }
+                min.iter()
+                    .zip(max.iter())
+                    .map(|(min, max)| max - min)
+                    .sum::<f32>()
+                    * signal.weight
+            })
+            .sum()
+    }
+
+    /// Lock the cells at `indices` where they are. The cells are reordered so the mobile cells
+    /// stay at the front of [`NetlistHypergraph::cells`], with their metadata and the signals
+    /// connected to them updated to match, so any cell indices held before this are invalid.
+    pub fn lock_cells(&mut self, indices: &[usize]) {
+        for &idx in indices {
+            self.cells[idx].pos_locked = true;
+        }
+
+        // Stable partition, mobile cells first and then the locked ones
+        let order: Vec<usize> = (0..self.cells.len())
+            .filter(|&idx| !self.cells[idx].pos_locked)
+            .chain((0..self.cells.len()).filter(|&idx| self.cells[idx].pos_locked))
+            .collect();
+        let mut new_index = vec![0; order.len()];
+        for (new, &old) in order.iter().enumerate() {
+            new_index[old] = new;
+        }
+
+        fn permute<T>(items: &mut Vec<T>, order: &[usize]) {
+            let mut old: Vec<Option<T>> = std::mem::take(items).into_iter().map(Some).collect();
+            *items = order.iter().map(|&idx| old[idx].take().unwrap()).collect();
+        }
+        permute(&mut self.cells, &order);
+        // Test netlists have no metadata
+        if !self.metadata.is_empty() {
+            permute(&mut self.metadata, &order);
+        }
+
+        for signal in self.signals.iter_mut() {
+            for cell_idx in signal.connected_cells.iter_mut() {
+                *cell_idx = new_index[*cell_idx];
+            }
+            signal.moveable_cells = signal
+                .connected_cells
+                .iter()
+                .filter(|idx| !self.cells[**idx].pos_locked)
+                .count();
+        }
+
+        self.mobile_cell_count = self.cells.iter().filter(|cell| !cell.pos_locked).count();
+    }
+
+    /// Name of the net `signal` is part of, preferring names Yosys didn't make up. Of several
+    /// names the first in sorted order is picked, so reports are the same from run to run.
+    pub fn signal_name(&self, signal: &Signal) -> Option<&str> {
+        let id = Some(Type::Id(signal.id as i64));
+        self.net_names
+            .iter()
+            .filter(|(_, net)| {
+                net.bits
+                    .as_ref()
+                    .map_or(false, |bits| bits.signal.iter().any(|bit| bit.r#type == id))
+            })
+            .min_by_key(|(name, net)| (net.hide_name, name.as_str()))
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Build the placed design from the legalized cells. The seeds of any jitter steps are recorded
+    /// in the creator string, so the placement can be reproduced.
     pub fn build_output(
         self,
         legalized_cells: Vec<LegalizedCell>,
         creator: String,
+        jitter_seeds: &[u64],
     ) -> PlacedDesign {
+        let seeds = if jitter_seeds.is_empty() {
+            String::new()
+        } else {
+            format!(" (jitter seed {})", jitter_seeds.iter().join(", "))
+        };
         PlacedDesign {
             creator: format!(
-                "Placed by MCPNR {}, Synth: {}",
+                "Placed by MCPNR {}{}, Synth: {}",
                 env!("CARGO_PKG_VERSION"),
+                seeds,
                 creator,
             ),
             nets: self.net_names,
@@ -267,8 +532,47 @@ impl NetlistHypergraph {
                     parameter: meta.parameter,
                     attribute: meta.attributes,
                     connection: meta.connection,
+                    name: meta.name,
                 })
                 .collect(),
+            version: PLACED_DESIGN_VERSION,
+            provenance: Vec::new(),
         }
     }
 }
+
+/// Parameters and attributes as Yosys writes them, with integers as binary strings
+fn parameter_strings(parameters: &HashMap<String, Parameter>) -> HashMap<String, String> {
+    parameters
+        .iter()
+        .map(|(name, parameter)| {
+            let value = match parameter.value {
+                Some(Value::Str(ref s)) => s.clone(),
+                Some(Value::Int(i)) => format!("{:032b}", i),
+                None => String::new(),
+            };
+            (name.clone(), value)
+        })
+        .collect()
+}
+
+fn yosys_bits(bits: &BitVector) -> Vec<ConstOrSignal> {
+    bits.signal
+        .iter()
+        .map(|bit| match bit.r#type {
+            Some(ref ty) => ConstOrSignal::from_type(ty),
+            None => ConstOrSignal::Const("x".to_owned()),
+        })
+        .collect()
+}
+
+/// Parse a net weight attribute, see [`parse_numeric_attribute`]
+fn parse_net_weight(value: &str) -> Result<f32> {
+    let weight = parse_numeric_attribute(value, "net weight")?;
+
+    if !(weight.is_finite() && weight > 0.0) {
+        return Err(anyhow!("Net weight must be positive, got {}", weight));
+    }
+
+    Ok(weight)
+}
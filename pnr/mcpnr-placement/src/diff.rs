@@ -0,0 +1,352 @@
+//! Implementation of the `diff` subcommand, which compares two placed designs of the same netlist
+//! to show what a change to the schedule or its parameters actually did to the placement.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use itertools::Itertools;
+use mcpnr_common::blockers::{BlockerRules, BLOCKER_RULES_FILE};
+use mcpnr_common::protos::read_placed_design;
+use mcpnr_common::BLOCKS_PER_TIER;
+
+use crate::core::NetlistHypergraph;
+use crate::placement_cell::{CellFactory, LegalizedCell};
+
+/// Arguments of the `diff` subcommand
+pub struct DiffArgs {
+    /// Placed design to compare against
+    pub before: PathBuf,
+    /// Placed design to compare
+    pub after: PathBuf,
+    /// Technology library both designs were placed with
+    pub techlib: PathBuf,
+    /// Number of cells listed in the table of moved cells
+    pub top: usize,
+}
+
+/// A cell placed in both designs
+struct CellMove {
+    name: String,
+    ty: String,
+    /// (x, tier, z) position in the first design
+    before: (u32, u32, u32),
+    /// (x, tier, z) position in the second design
+    after: (u32, u32, u32),
+    /// Manhattan distance between the two positions, in blocks
+    distance: u32,
+}
+
+/// Load a placed design, with its cells legalized where they were placed
+fn load(
+    cell_factory: &mut CellFactory,
+    path: &Path,
+) -> Result<(NetlistHypergraph, Vec<LegalizedCell>)> {
+    let design = read_placed_design(path)?;
+    NetlistHypergraph::from_placed_design(&design, cell_factory)
+        .with_context(|| anyhow!("Rebuild netlist of {:?}", path))
+}
+
+/// Match up the cells of the two designs by name, returning the cells in both (most moved first)
+/// and the number of cells only in the first and only in the second design.
+fn match_cells(
+    before: (&NetlistHypergraph, &[LegalizedCell]),
+    after: (&NetlistHypergraph, &[LegalizedCell]),
+) -> (Vec<CellMove>, usize, usize) {
+    let before_indices: HashMap<&str, usize> = before
+        .0
+        .metadata
+        .iter()
+        .enumerate()
+        .map(|(idx, metadata)| (metadata.name.as_str(), idx))
+        .collect();
+
+    let mut only_after = 0;
+    let mut moves = Vec::new();
+    for (idx, metadata) in after.0.metadata.iter().enumerate() {
+        let before_idx = match before_indices.get(metadata.name.as_str()) {
+            Some(&before_idx) => before_idx,
+            None => {
+                only_after += 1;
+                continue;
+            }
+        };
+
+        let a = &before.1[before_idx];
+        let b = &after.1[idx];
+        let distance =
+            a.x.abs_diff(b.x) + a.tier_y.abs_diff(b.tier_y) * BLOCKS_PER_TIER + a.z.abs_diff(b.z);
+        moves.push(CellMove {
+            name: metadata.name.clone(),
+            ty: metadata.ty.clone(),
+            before: (a.x, a.tier_y, a.z),
+            after: (b.x, b.tier_y, b.z),
+            distance,
+        });
+    }
+    let only_before = before.0.metadata.len() - moves.len();
+
+    moves.sort_by(|a, b| {
+        b.distance
+            .cmp(&a.distance)
+            .then_with(|| a.name.cmp(&b.name))
+    });
+
+    (moves, only_before, only_after)
+}
+
+/// Print the wirelength change, a summary of how far cells moved and the `top` most moved cells.
+fn print_diff<W: Write>(
+    mut w: W,
+    args: &DiffArgs,
+    before: (&NetlistHypergraph, &[LegalizedCell]),
+    after: (&NetlistHypergraph, &[LegalizedCell]),
+) -> std::io::Result<()> {
+    let hpwl_before = before.0.legalized_wirelength(before.1);
+    let hpwl_after = after.0.legalized_wirelength(after.1);
+    let delta = hpwl_after - hpwl_before;
+    writeln!(
+        w,
+        "HPWL: {:.0} -> {:.0} ({:+.0}, {:+.1}%)",
+        hpwl_before,
+        hpwl_after,
+        delta,
+        if hpwl_before > 0.0 {
+            100.0 * delta / hpwl_before
+        } else {
+            0.0
+        }
+    )?;
+
+    let (moves, only_before, only_after) = match_cells(before, after);
+    if only_before > 0 {
+        writeln!(w, "{} cells only in {:?}", only_before, args.before)?;
+    }
+    if only_after > 0 {
+        writeln!(w, "{} cells only in {:?}", only_after, args.after)?;
+    }
+
+    let moved = moves.iter().filter(|m| m.distance > 0).count();
+    let total: u64 = moves.iter().map(|m| m.distance as u64).sum();
+    writeln!(
+        w,
+        "{} of {} cells moved, mean displacement {:.2} blocks, max {} blocks",
+        moved,
+        moves.len(),
+        if moves.is_empty() {
+            0.0
+        } else {
+            total as f64 / moves.len() as f64
+        },
+        moves.first().map_or(0, |m| m.distance),
+    )?;
+
+    let shown = moves
+        .iter()
+        .take_while(|m| m.distance > 0)
+        .take(args.top)
+        .collect_vec();
+    if shown.is_empty() {
+        return Ok(());
+    }
+
+    let position = |(x, tier, z): (u32, u32, u32)| format!("({}, {}, {})", x, tier, z);
+    let rows = shown
+        .iter()
+        .map(|m| {
+            [
+                m.name.clone(),
+                m.ty.clone(),
+                position(m.before),
+                position(m.after),
+                m.distance.to_string(),
+            ]
+        })
+        .collect_vec();
+    let header = ["cell", "type", "before (x, tier, z)", "after", "moved"];
+    let widths: Vec<usize> = (0..header.len())
+        .map(|col| {
+            rows.iter()
+                .map(|row| row[col].len())
+                .chain(std::iter::once(header[col].len()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    writeln!(w)?;
+    for row in std::iter::once(header.map(String::from)).chain(rows) {
+        let line = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, &width)| format!("{:<width$}", cell, width = width))
+            .join("  ");
+        writeln!(w, "{}", line.trim_end())?;
+    }
+    if moved > shown.len() {
+        writeln!(w, "... and {} more moved cells", moved - shown.len())?;
+    }
+
+    Ok(())
+}
+
+/// Compare the placed designs in `args` and print the differences to stdout
+pub fn run_diff(args: &DiffArgs) -> Result<()> {
+    let rules = BlockerRules::load(&args.techlib.join(BLOCKER_RULES_FILE))?;
+    let mut cell_factory = CellFactory::new(args.techlib.join("structures"), rules);
+
+    let (before, before_legalized) = load(&mut cell_factory, &args.before)?;
+    let (after, after_legalized) = load(&mut cell_factory, &args.after)?;
+
+    print_diff(
+        std::io::stdout().lock(),
+        args,
+        (&before, &before_legalized),
+        (&after, &after_legalized),
+    )
+    .context("Write placement diff")
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use nalgebra::Vector3;
+
+    use super::{match_cells, print_diff, DiffArgs};
+
+    use crate::core::{CellMetadata, NetlistHypergraph, Signal};
+    use crate::placement_cell::{LegalizedCell, PlacementCell};
+
+    /// A design of 4x1x6 cells at the given (x, tier, z) positions, all on one net
+    fn design(cells: &[(&str, (u32, u32, u32))]) -> (NetlistHypergraph, Vec<LegalizedCell>) {
+        let placement_cells = cells
+            .iter()
+            .map(|&(_, (x, tier_y, z))| PlacementCell {
+                x: x as f32,
+                tier_y: tier_y as f32,
+                z: z as f32,
+                sx: 4.0,
+                s_tier_y: 1.0,
+                sz: 6.0,
+                pos_locked: false,
+            })
+            .collect();
+        let signal = Signal {
+            connected_cells: (0..cells.len()).collect(),
+            moveable_cells: cells.len(),
+            pin_offsets: vec![Vector3::zeros(); cells.len()],
+            id: 0,
+            weight: 1.0,
+        };
+        let mut net = NetlistHypergraph::test_new(placement_cells, cells.len(), vec![signal]);
+        net.metadata = cells
+            .iter()
+            .map(|&(name, _)| CellMetadata {
+                name: name.to_owned(),
+                attributes: Default::default(),
+                connection: Default::default(),
+                parameter: Default::default(),
+                ty: "gate".to_owned(),
+            })
+            .collect();
+
+        let legalized = cells
+            .iter()
+            .map(|&(_, (x, tier_y, z))| LegalizedCell {
+                x,
+                tier_y,
+                z,
+                sx: 4,
+                s_tier_y: 1,
+                sz: 6,
+            })
+            .collect();
+        (net, legalized)
+    }
+
+    fn args() -> DiffArgs {
+        DiffArgs {
+            before: PathBuf::from("before.pb"),
+            after: PathBuf::from("after.pb"),
+            techlib: PathBuf::from("techlib"),
+            top: 10,
+        }
+    }
+
+    #[test]
+    fn cells_are_matched_by_name() {
+        let before = design(&[("a", (0, 0, 0)), ("b", (4, 0, 0)), ("c", (0, 0, 8))]);
+        let after = design(&[("b", (4, 0, 0)), ("d", (8, 0, 8)), ("a", (2, 1, 0))]);
+
+        let (moves, only_before, only_after) =
+            match_cells((&before.0, &before.1), (&after.0, &after.1));
+
+        assert_eq!((only_before, only_after), (1, 1));
+        let moves: Vec<_> = moves
+            .iter()
+            .map(|m| (m.name.as_str(), m.before, m.after, m.distance))
+            .collect();
+        // A tier is 16 blocks, and the most moved cell comes first
+        assert_eq!(
+            moves,
+            vec![
+                ("a", (0, 0, 0), (2, 1, 0), 18),
+                ("b", (4, 0, 0), (4, 0, 0), 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_lists_added_removed_and_moved_cells() {
+        let before = design(&[("a", (0, 0, 0)), ("b", (4, 0, 0)), ("c", (0, 0, 8))]);
+        let after = design(&[("b", (4, 0, 0)), ("d", (8, 0, 8)), ("a", (2, 1, 0))]);
+
+        let mut out = Vec::new();
+        print_diff(
+            &mut out,
+            &args(),
+            (&before.0, &before.1),
+            (&after.0, &after.1),
+        )
+        .unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        let lines: Vec<_> = out.lines().collect();
+        assert!(lines[0].starts_with("HPWL: "), "{}", out);
+        assert_eq!(
+            &lines[1..],
+            &[
+                "1 cells only in \"before.pb\"",
+                "1 cells only in \"after.pb\"",
+                "1 of 2 cells moved, mean displacement 9.00 blocks, max 18 blocks",
+                "",
+                "cell  type  before (x, tier, z)  after      moved",
+                "a     gate  (0, 0, 0)            (2, 1, 0)  18",
+            ]
+        );
+    }
+
+    #[test]
+    fn identical_designs_have_no_table() {
+        let before = design(&[("a", (0, 0, 0)), ("b", (4, 0, 0))]);
+        let after = design(&[("a", (0, 0, 0)), ("b", (4, 0, 0))]);
+
+        let mut out = Vec::new();
+        print_diff(
+            &mut out,
+            &args(),
+            (&before.0, &before.1),
+            (&after.0, &after.1),
+        )
+        .unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert_eq!(
+            out,
+            "HPWL: 4 -> 4 (+0, +0.0%)\n\
+             0 of 2 cells moved, mean displacement 0.00 blocks, max 0 blocks\n"
+        );
+    }
+}
@@ -0,0 +1,386 @@
+//! Implementation of the `doctor` subcommand, which checks the techlib, the placement
+//! configuration and the input design for problems before they turn in to a panic halfway
+//! through placement.
+
+use anyhow::{anyhow, bail, Result};
+use mcpnr_common::doctor::{check_techlib, Report, Severity, TechlibInfo};
+use mcpnr_common::timing_constraints::TimingConstraints;
+use mcpnr_common::yosys::Design;
+use mcpnr_common::{BLOCKS_PER_TIER, BLOCKS_PER_Z_ROW, MAX_TIERS};
+
+use crate::config::{Config, DiffusionMode, PlacementStep};
+use crate::io_planner;
+
+/// Run every check, print the findings and fail if any of them were errors.
+pub fn run_doctor(config: &Config) -> Result<()> {
+    let mut report = Report::new();
+
+    let techlib = match config.io.structure_directory.parent() {
+        Some(techlib) => check_techlib(&mut report, techlib),
+        None => TechlibInfo::default(),
+    };
+    check_config(&mut report, config);
+    if config.io.input_file.as_os_str().is_empty() {
+        report.ok("input", "no input design given, skipping design checks");
+    } else {
+        check_design(&mut report, config, &techlib);
+    }
+
+    report.print(std::io::stdout().lock())?;
+    if report.has_errors() {
+        bail!("Doctor found {} errors", report.count(Severity::Error));
+    }
+
+    Ok(())
+}
+
+/// Check the configuration only, failing with every problem found instead of the first.
+pub fn ensure_config(config: &Config) -> Result<()> {
+    let mut report = Report::new();
+    check_config(&mut report, config);
+
+    let errors: Vec<String> = report
+        .findings()
+        .iter()
+        .filter(|finding| finding.severity == Severity::Error)
+        .map(|finding| {
+            format!(
+                "{}: {} ({})",
+                finding.check,
+                finding.message,
+                finding.fix.as_deref().unwrap_or_default()
+            )
+        })
+        .collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!("Invalid configuration:\n  {}", errors.join("\n  ")))
+    }
+}
+
+/// Check that the geometry and placement schedule agree with each other and with the limits of
+/// the rest of the flow.
+pub fn check_config(report: &mut Report, config: &Config) {
+    let geometry = &config.geometry;
+
+    for (name, flag, size) in [
+        ("size_x", "--size-x", geometry.size_x),
+        ("size_z", "--size-z", geometry.size_z),
+    ] {
+        report.require(
+            size > 0 && size % 2 == 0,
+            "geometry",
+            format!("{} is {}", name, size),
+            format!("{} is {}, but must be a positive even number", name, size),
+            format!(
+                "Pass an even {}, cells are aligned to the 2 block routing grid",
+                flag
+            ),
+        );
+    }
+    if geometry.size_z % BLOCKS_PER_Z_ROW != 0 {
+        report.warning(
+            "geometry",
+            format!(
+                "size_z {} is not a multiple of the {} block cell row depth",
+                geometry.size_z, BLOCKS_PER_Z_ROW
+            ),
+            format!(
+                "Pass --size-z {}, the remainder can't hold any cells",
+                geometry.size_z - geometry.size_z % BLOCKS_PER_Z_ROW
+            ),
+        );
+    }
+    report.require(
+        (1..=MAX_TIERS).contains(&geometry.size_y),
+        "geometry",
+        format!("{} tiers", geometry.size_y),
+        format!("{} tiers is outside of 1..={}", geometry.size_y, MAX_TIERS),
+        format!(
+            "Pass --size-y between 1 and {}, more tiers don't fit in the world height",
+            MAX_TIERS
+        ),
+    );
+    report.require(
+        geometry.target_fill > 0.0 && geometry.target_fill <= 1.0,
+        "geometry",
+        format!("target fill is {}", geometry.target_fill),
+        format!(
+            "target fill is {}, but must be in (0, 1]",
+            geometry.target_fill
+        ),
+        "Pass a --target-fill between 0 and 1",
+    );
+    for (region_idx, region) in geometry.density_map.iter().enumerate() {
+        report.require(
+            (0.0..=1.0).contains(&region.target_fill),
+            "geometry",
+            format!(
+                "density map box {} has a target fill of {}",
+                region_idx, region.target_fill
+            ),
+            format!(
+                "density map box {} has a target fill of {}, but must be in [0, 1]",
+                region_idx, region.target_fill
+            ),
+            "Give every box of the --density-map a target_fill between 0 and 1",
+        );
+        // Auto-sizing picks the size later, so only a given size can be checked against
+        let outside = region.x >= geometry.size_x
+            || region.z >= geometry.size_z
+            || region.tier.is_some_and(|tier| tier >= geometry.size_y);
+        if outside && config.auto_size.is_none() {
+            report.warning(
+                "geometry",
+                format!(
+                    "density map box {} at ({}, {}) is outside of the placement region",
+                    region_idx, region.x, region.z
+                ),
+                "Remove the box from the --density-map, it has no effect",
+            );
+        }
+    }
+
+    for (step_idx, step) in config.schedule.schedule.iter().enumerate() {
+        if let PlacementStep::Diffusion(diffusion) = step {
+            report.require(
+                diffusion.region_size > 0,
+                "schedule",
+                format!(
+                    "diffusion step {} has {} block regions",
+                    step_idx, diffusion.region_size
+                ),
+                format!("diffusion step {} has a region size of 0", step_idx),
+                "Give every diffusion step a positive region size",
+            );
+            report.require(
+                diffusion.delta_t.is_finite() && diffusion.delta_t > 0.0,
+                "schedule",
+                format!(
+                    "diffusion step {} has a time step of {}",
+                    step_idx, diffusion.delta_t
+                ),
+                format!(
+                    "diffusion step {} has a time step of {}, but must be a positive number",
+                    step_idx, diffusion.delta_t
+                ),
+                "Pass a positive --delta-t",
+            );
+        }
+    }
+
+    let diffuses_3d = config.schedule.schedule.iter().any(|step| match step {
+        PlacementStep::Diffusion(diffusion) => diffusion.mode == DiffusionMode::Volume,
+        _ => false,
+    });
+    if diffuses_3d && geometry.size_y == 1 {
+        report.warning(
+            "schedule",
+            "3D diffusion on a single tier design, which only costs time",
+            "Leave --diffusion-mode on auto or pass per-tier",
+        );
+    }
+
+    let hard_macros = config.schedule.schedule.iter().find_map(|step| match step {
+        PlacementStep::HardMacros(hard_macros) => Some(hard_macros),
+        _ => None,
+    });
+    if hard_macros.is_some_and(|hard_macros| hard_macros.footprint_threshold == 0) {
+        report.warning(
+            "hard macros",
+            "the hard macro threshold is zero, every cell will be placed as a hard macro",
+            "Raise --hard-macro-threshold to the footprint of the largest standard cell or more",
+        );
+    }
+
+    if let Some(ref path) = config.io_plan.constraints_file {
+        match io_planner::load_constraints(path) {
+            Ok(constraints) => report.ok(
+                "io constraints",
+                format!("{} constrained macros", constraints.len()),
+            ),
+            Err(e) => report.error(
+                "io constraints",
+                format!("{:#}", e),
+                "Fix --io-constraints, it must be a JSON object mapping cell names to positions",
+            ),
+        }
+    }
+
+    let aspect_ratio = config.hierarchy.macro_aspect_ratio;
+    report.require(
+        aspect_ratio.is_finite() && aspect_ratio > 0.0,
+        "hierarchy",
+        format!("soft macro aspect ratio is {}", aspect_ratio),
+        format!(
+            "soft macro aspect ratio is {}, but must be a positive number",
+            aspect_ratio
+        ),
+        "Pass a positive --macro-aspect-ratio",
+    );
+
+    if let Some(ref path) = config.io.timing_constraints_file {
+        match TimingConstraints::load(path) {
+            Ok(constraints) => report.ok(
+                "timing constraints",
+                format!("{} constrained net patterns", constraints.len()),
+            ),
+            Err(e) => report.error(
+                "timing constraints",
+                format!("{:#}", e),
+                "Fix --timing-constraints, each line must be `set_criticality VALUE NET...`",
+            ),
+        }
+    }
+}
+
+/// Check that the input design can be placed with this techlib in the configured region.
+fn check_design(report: &mut Report, config: &Config, techlib: &TechlibInfo) {
+    let design: Design = match std::fs::File::open(&config.io.input_file)
+        .map_err(anyhow::Error::from)
+        .and_then(|f| Ok(serde_json::from_reader(std::io::BufReader::new(f))?))
+    {
+        Ok(design) => design,
+        Err(e) => {
+            report.error(
+                "input",
+                format!("failed to load {}: {:#}", config.io.input_file.display(), e),
+                "Pass the output of the Yosys synth_mc script as INPUT",
+            );
+            return;
+        }
+    };
+    let top = match design.modules.get("top") {
+        Some(top) => top,
+        None => {
+            report.error(
+                "input",
+                "the design has no \"top\" module",
+                "Name the top level module of the design \"top\"",
+            );
+            return;
+        }
+    };
+    // Sub-modules are made of techlib cells too, so check the cells they're made of
+    let top = match design.flatten(top) {
+        Ok(top) => top,
+        Err(e) => {
+            report.error(
+                "input",
+                format!("failed to flatten the design: {:#}", e),
+                "Connect every port of each sub-module instance",
+            );
+            return;
+        }
+    };
+    report.ok("input", format!("{} cells in top", top.cells.len()));
+
+    let hard_macro_threshold = config.schedule.schedule.iter().find_map(|step| match step {
+        PlacementStep::HardMacros(hard_macros) => Some(hard_macros.footprint_threshold as u64),
+        _ => None,
+    });
+    let mut missing = Vec::new();
+    // Size of every cell but the I/O macros in blocks along X and Z and in tiers along Y, by type
+    let mut footprints = Vec::new();
+    for cell in top.cells.values() {
+        if cell.ty == "MCPNR_SWITCHES" || cell.ty == "MCPNR_LIGHTS" {
+            continue;
+        }
+        match techlib.structures.get(&cell.ty) {
+            Some(structure) => {
+                let [sx, sy, sz] = structure.size.map(|s| s.max(0) as u32);
+                let tiers = sy.div_ceil(BLOCKS_PER_TIER).max(1);
+                footprints.push((cell.ty.as_str(), [sx, tiers, sz]));
+            }
+            None => missing.push(cell.ty.as_str()),
+        }
+    }
+    let geometry = match config.auto_size {
+        Some(ref auto_size) => {
+            let geometry =
+                auto_size.size(&config.geometry, footprints.iter().map(|(_, size)| *size));
+            report.ok(
+                "geometry",
+                format!(
+                    "auto-sized to {}x{} blocks over {} tiers",
+                    geometry.size_x, geometry.size_z, geometry.size_y
+                ),
+            );
+            geometry
+        }
+        None => config.geometry.clone(),
+    };
+
+    let mut hard_macros = 0;
+    let mut oversized = Vec::new();
+    let mut area = 0u64;
+    for (ty, [sx, tiers, sz]) in footprints {
+        let (sx, tiers, sz) = (sx as u64, tiers as u64, sz as u64);
+        area += (sx + sx % 2) * (sz + sz % 2) * tiers;
+        if hard_macro_threshold.is_some_and(|threshold| sx * sz >= threshold) {
+            hard_macros += 1;
+            if sx > geometry.size_x as u64
+                || tiers > geometry.size_y as u64
+                || sz > geometry.size_z as u64
+            {
+                oversized.push(ty);
+            }
+        }
+    }
+    missing.sort_unstable();
+    missing.dedup();
+    if techlib.structures.is_empty() {
+        return;
+    }
+    report.require(
+        missing.is_empty(),
+        "cell types",
+        "every cell type has a structure",
+        format!("no structure for {}", missing.join(", ")),
+        "Synthesize the design against this techlib, or add the missing structures to it",
+    );
+
+    if hard_macro_threshold.is_some() {
+        oversized.sort_unstable();
+        oversized.dedup();
+        report.require(
+            oversized.is_empty(),
+            "hard macros",
+            format!("{} hard macros, all fit in the region", hard_macros),
+            format!(
+                "hard macros {} don't fit in the region",
+                oversized.join(", ")
+            ),
+            "Increase --size-x, --size-y or --size-z",
+        );
+    }
+
+    let capacity = geometry.size_x as u64 * geometry.size_z as u64 * geometry.size_y as u64;
+    let fill = area as f64 / capacity.max(1) as f64;
+    if fill > 1.0 {
+        report.error(
+            "utilization",
+            format!(
+                "cells cover {} blocks but the region only has {}",
+                area, capacity
+            ),
+            "Increase --size-x, --size-y or --size-z",
+        );
+    } else if fill > geometry.target_fill as f64 {
+        report.warning(
+            "utilization",
+            format!(
+                "cells fill {:.0}% of the region, above the {:.0}% target",
+                fill * 100.0,
+                geometry.target_fill * 100.0
+            ),
+            "Increase the region size to give the legalizer some room",
+        );
+    } else {
+        report.ok(
+            "utilization",
+            format!("cells fill {:.0}% of the region", fill * 100.0),
+        );
+    }
+}
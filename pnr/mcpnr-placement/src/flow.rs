@@ -0,0 +1,488 @@
+//! The placement flow, from a synthesized design to a placed one, as the `place` and `replace`
+//! subcommands run it.
+
+use anyhow::{anyhow, ensure, Context, Result};
+use mcpnr_common::blockers::BlockerRules;
+use mcpnr_common::memory::MemoryBudget;
+use mcpnr_common::net_weights::NetWeights;
+use mcpnr_common::protos::{
+    mcpnr::PlacedDesign, read_placed_design, write_placed_design, write_placed_design_json,
+};
+use mcpnr_common::provenance::Provenance;
+use mcpnr_common::results::{self, RunRecord};
+use mcpnr_common::timing_constraints::{TimingConstraints, CRITICALITY_ATTRIBUTE};
+use mcpnr_common::yosys::{Design, Module};
+use nalgebra::Vector3;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tracing::{debug_span, info, info_span};
+
+use crate::config::{Config, PlacementStep};
+use crate::core::NetlistHypergraph;
+use crate::legalizer::incremental::legalize_in_region;
+use crate::placement_cell::{CellFactory, LegalizedCell};
+use crate::placer::analytical::{
+    AnalyticWirelengthProblem, AnchoredByNet, Bound2Bound, Clique, DecompositionStrategy,
+    MoveableStar, ThresholdCrossover,
+};
+use crate::placer::clusters::{
+    find_clusters, report_clusters, CLUSTER_TOLERANCE, MIN_CLUSTER_SIZE,
+};
+use crate::placer::diffusion::DiffusionPlacer;
+use crate::placer::region::Region;
+use crate::{blockage_map, hierarchy, io_planner, legalizer, placer};
+
+/// Read the synthesized design from the input file
+pub fn load_design(config: &Config) -> Result<Design> {
+    let reader = std::fs::File::open(&config.io.input_file)
+        .with_context(|| anyhow!("Open input file {:?}", config.io.input_file))?;
+    let reader = std::io::BufReader::new(reader);
+    serde_json::from_reader(reader).with_context(|| anyhow!("Failed to parse reader"))
+}
+
+/// Write the timing constraints in to the net attributes of `module`, so they end up in the placed
+/// design too
+pub(crate) fn apply_timing_constraints(config: &Config, module: &mut Module) -> Result<()> {
+    if let Some(ref path) = config.io.timing_constraints_file {
+        let constraints = TimingConstraints::load(path)?;
+        for (name, net) in module.netnames.iter_mut() {
+            if let Some(criticality) = constraints.criticality(name) {
+                net.attributes
+                    .insert(CRITICALITY_ATTRIBUTE.to_owned(), criticality.to_string());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Read the net weights routing fed back, if any
+pub(crate) fn read_net_weights(config: &Config) -> Result<Option<NetWeights>> {
+    let path = match config.io.net_weights_file {
+        Some(ref path) => path,
+        None => return Ok(None),
+    };
+    let weights = NetWeights::read(path)?;
+    info!(
+        "Weighting {} nets from {:?}, where routing left {} nets unrouted",
+        weights.nets.len(),
+        path,
+        weights.unrouted
+    );
+
+    Ok(Some(weights))
+}
+
+/// Scale the weights of the signals of `cells` by the net weights routing fed back, if any
+fn apply_net_weights(config: &Config, cells: &mut NetlistHypergraph) -> Result<()> {
+    if let Some(weights) = read_net_weights(config)? {
+        for signal in cells.signals.iter_mut() {
+            signal.weight *= weights.weight(signal.id as i64);
+        }
+    }
+
+    Ok(())
+}
+
+/// Size the placement region to fit `cells`, if auto-sizing is on
+pub(crate) fn auto_size(config: &mut Config, cells: &NetlistHypergraph) {
+    if let Some(ref auto_size) = config.auto_size {
+        let footprints = cells
+            .cells
+            .iter()
+            .map(|cell| [cell.sx as u32, cell.s_tier_y as u32, cell.sz as u32]);
+        config.geometry = auto_size.size(&config.geometry, footprints);
+        info!(
+            "Auto-sized the placement region to {}x{} blocks over {} tiers, for {:.0}% fill",
+            config.geometry.size_x,
+            config.geometry.size_z,
+            config.geometry.size_y,
+            config.geometry.target_fill * 100.0
+        );
+    }
+}
+
+/// Load the cells of the flattened design, sizing the region for them if auto-sizing is on
+pub fn load_cells(
+    config: &mut Config,
+    design: Design,
+    cell_factory: &mut CellFactory,
+) -> Result<(NetlistHypergraph, String)> {
+    let top_module = design
+        .modules
+        .get("top")
+        .ok_or_else(|| anyhow!("Failed to locate top module"))?;
+    let mut top_module = design.flatten(top_module).context("Flatten design")?;
+    apply_timing_constraints(config, &mut top_module)?;
+
+    let mut cells = NetlistHypergraph::from_module(top_module, cell_factory)
+        .with_context(|| "Extract cells")?;
+    apply_net_weights(config, &mut cells)?;
+
+    auto_size(config, &cells);
+    io_planner::plan_io(&config.io_plan, &config.geometry, &mut cells).context("Plan I/O")?;
+
+    Ok((cells, design.creator))
+}
+
+fn min_f32(a: f32, b: f32) -> f32 {
+    if a < b {
+        a
+    } else {
+        b
+    }
+}
+
+fn max_f32(a: f32, b: f32) -> f32 {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+/// Move the moveable cells so they're centered in the placement region
+pub fn center_all_moveable_cells(config: &Config, cells: &mut NetlistHypergraph) {
+    // Set our initial guess for the minimum position to the maximum
+    let mut current_min = Vector3::new(
+        config.geometry.size_x as f32,
+        config.geometry.size_y as f32,
+        config.geometry.size_z as f32,
+    );
+    // and use that handy value to compute the desired center
+    let desired_center = current_min / 2.0;
+    let mut current_max = Vector3::zeros();
+
+    for cell in cells.cells.iter_mut() {
+        if cell.pos_locked {
+            continue;
+        }
+        current_max.x = max_f32(current_max.x, cell.x + cell.sx);
+        current_max.y = max_f32(current_max.y, cell.tier_y + cell.s_tier_y);
+        current_max.z = max_f32(current_max.z, cell.z + cell.sz);
+
+        current_min.x = min_f32(current_min.x, cell.x);
+        current_min.y = min_f32(current_min.y, cell.tier_y);
+        current_min.z = min_f32(current_min.z, cell.z);
+    }
+
+    let delta = ((current_max - current_min) / 2.0) + current_min - desired_center;
+
+    for cell in cells.cells.iter_mut() {
+        if cell.pos_locked {
+            continue;
+        }
+        cell.x -= delta.x;
+        cell.tier_y -= delta.y;
+        cell.z -= delta.z;
+    }
+}
+
+/// Warn about cells stacked on top of each other by the analytical step named `after`
+fn warn_stacked_cells(cells: &NetlistHypergraph, after: &str) {
+    let clusters = find_clusters(cells, CLUSTER_TOLERANCE, MIN_CLUSTER_SIZE);
+    report_clusters(cells, &clusters, after);
+}
+
+/// Run the placement schedule on `cells`
+pub fn place_algorithm(config: &Config, cells: &mut NetlistHypergraph) -> Result<()> {
+    let _span = info_span!("overall_place").entered();
+
+    // The net models add at most one entry per signal to the moveable cells
+    let problem_size = cells.mobile_cell_count + cells.signals.len();
+    if let Some(bytes) = AnalyticWirelengthProblem::dense_allocation_size(problem_size) {
+        MemoryBudget::new(config.max_memory).reserve(
+            "Dense hessian",
+            bytes,
+            "raise --max-memory, designs this small are always solved densely",
+        )?;
+    }
+
+    for step in &config.schedule.schedule {
+        match step {
+            PlacementStep::CenterCells => {
+                let _span = info_span!("center_cells").entered();
+                center_all_moveable_cells(config, cells);
+            }
+            PlacementStep::Spectral => {
+                let _span = info_span!("spectral").entered();
+                MemoryBudget::new(config.max_memory).reserve(
+                    "Lanczos basis",
+                    placer::spectral::basis_size(cells.mobile_cell_count),
+                    "raise --max-memory or use --initial-placement analytical",
+                )?;
+                placer::spectral::place_spectral(&config.geometry, cells)
+                    .context("Spectral placement")?;
+                warn_stacked_cells(cells, "spectral placement");
+            }
+            PlacementStep::UnconstrainedAnalytical { clique_threshold } => {
+                let _span = info_span!("unconstrained").entered();
+                let mut strategy =
+                    ThresholdCrossover::new(*clique_threshold, Clique::new(), MoveableStar::new());
+                strategy.execute_with(cells, &config.objective)?;
+                warn_stacked_cells(cells, "unconstrained analytical placement");
+            }
+            PlacementStep::Diffusion(diffusion_config) => {
+                let _span = info_span!(
+                    "diffusion",
+                    iterations = diffusion_config.iterations,
+                    region_size = diffusion_config.region_size,
+                    delta_t = diffusion_config.delta_t
+                )
+                .entered();
+                // Iterate between diffusion and some light analytic recover
+                let mut density = DiffusionPlacer::new(config, diffusion_config);
+
+                density.splat(cells);
+
+                // Diffusion simulation
+                for narrow_iteration in 0..diffusion_config.iterations {
+                    let _span =
+                        debug_span!("narrow_iteration", narrow_iteration = narrow_iteration)
+                            .entered();
+                    density.compute_velocities();
+                    density.move_cells(cells, diffusion_config.delta_t);
+                    density.step_time(diffusion_config.delta_t);
+                }
+            }
+            PlacementStep::ConstrainedAnalytical {
+                clique_threshold,
+                iterations,
+            } => {
+                let _span = info_span!(
+                    "analytical",
+                    iterations = iterations,
+                    clique_threshold = clique_threshold
+                )
+                .entered();
+                for _ in 0..*iterations {
+                    // Analytic wirelength recovery phase
+                    let mut strategy = ThresholdCrossover::new(
+                        *clique_threshold,
+                        Clique::new(),
+                        AnchoredByNet::new(),
+                    );
+
+                    strategy.execute_with(cells, &config.objective)?;
+                }
+                warn_stacked_cells(cells, "constrained analytical placement");
+            }
+            PlacementStep::Bound2BoundAnalytical { iterations } => {
+                let _span = info_span!("bound2bound", iterations = iterations).entered();
+                let mut strategy = Bound2Bound::new();
+                for _ in 0..*iterations {
+                    strategy.execute_with(cells, &config.objective)?;
+                }
+                warn_stacked_cells(cells, "bound-to-bound analytical placement");
+            }
+            PlacementStep::Jitter { magnitude, seed } => {
+                let _span = info_span!("jitter", magnitude, seed).entered();
+                placer::jitter::jitter_cells(&config.geometry, cells, *magnitude, *seed);
+            }
+            PlacementStep::HardMacros(hard_macro_config) => {
+                let _span = info_span!(
+                    "hard_macros",
+                    footprint_threshold = hard_macro_config.footprint_threshold
+                )
+                .entered();
+                placer::hard_macros::place_hard_macros(hard_macro_config, &config.geometry, cells)
+                    .context("Place hard macros")?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn legalize_algorithm(
+    config: &Config,
+    netlist: &NetlistHypergraph,
+) -> Result<Vec<LegalizedCell>> {
+    legalizer::from_config(&config.legalizer)
+        .legalize(&config.geometry, &netlist.cells)
+        .context("Legalize")
+}
+
+fn place(config: &Config, design: Design) -> Result<PlacedDesign> {
+    if config.hierarchy.soft_macros {
+        return place_hierarchical(config, design);
+    }
+
+    let mut config = config.clone();
+    let rules = BlockerRules::load(&config.io.blocker_rules_file)?;
+    let mut cell_factory = CellFactory::new(config.io.structure_directory.clone(), rules);
+    let (mut cells, creator) = load_cells(&mut config, design, &mut cell_factory)
+        .with_context(|| anyhow!("Load cells"))?;
+
+    place_algorithm(&config, &mut cells)
+        .with_context(|| anyhow!("Initial analytical placement"))?;
+
+    let legalized_cells = legalize_algorithm(&config, &cells)?;
+
+    Ok(cells.build_output(legalized_cells, creator, &config.schedule.jitter_seeds()))
+}
+
+fn place_hierarchical(config: &Config, design: Design) -> Result<PlacedDesign> {
+    let mut config = config.clone();
+    let rules = BlockerRules::load(&config.io.blocker_rules_file)?;
+    let mut cell_factory = CellFactory::new(config.io.structure_directory.clone(), rules);
+    let mut cells = hierarchy::place_soft_macros(&mut config, &design, &mut cell_factory)
+        .context("Place soft macros")?;
+
+    if let Some(ref schedule) = config.hierarchy.refine_schedule {
+        let refine_config = Config {
+            schedule: schedule.clone(),
+            ..config.clone()
+        };
+        place_algorithm(&refine_config, &mut cells).context("Refine flattened placement")?;
+    }
+
+    let legalized_cells = legalize_algorithm(&config, &cells)?;
+
+    Ok(cells.build_output(
+        legalized_cells,
+        design.creator,
+        &config.schedule.jitter_seeds(),
+    ))
+}
+
+/// Place the input design and write it to the output file, as the `place` subcommand does
+pub fn run_placement(config: &Config) -> Result<()> {
+    let start = Instant::now();
+    let design = load_design(config).with_context(|| anyhow!("Load design"))?;
+
+    let mut placed_design = place(config, design)
+        .with_context(|| anyhow!("Place design from {:?}", config.io.input_file))?;
+    placed_design
+        .provenance
+        .push(provenance(config).context("Record provenance")?);
+
+    write_placement(config, &placed_design, start)
+}
+
+/// Place the cells of `design` inside `region` again, with every other cell fixed where it is,
+/// and legalize them inside the region. Fails if they don't fit in the room the fixed cells leave.
+pub fn replace(config: &Config, design: &PlacedDesign, region: &Region) -> Result<PlacedDesign> {
+    let rules = BlockerRules::load(&config.io.blocker_rules_file)?;
+    let mut cell_factory = CellFactory::new(config.io.structure_directory.clone(), rules);
+    let (mut cells, _) = NetlistHypergraph::from_placed_design(design, &mut cell_factory)
+        .context("Rebuild netlist")?;
+    apply_net_weights(config, &mut cells)?;
+
+    let mobile = placer::region::lock_outside(&mut cells, region);
+    ensure!(
+        mobile > 0,
+        "No movable cells are inside the region {:?}",
+        region
+    );
+    info!(
+        "Placing the {} cells inside {:?} again, around {} fixed cells",
+        mobile,
+        region,
+        cells.cells.len() - mobile
+    );
+
+    place_algorithm(config, &mut cells).context("Place region")?;
+    placer::region::clamp_inside(&mut cells, region);
+    let legalized_cells =
+        legalize_in_region(&config.geometry, &cells.cells, region).context("Legalize region")?;
+
+    let mut placed_design = cells.build_output(
+        legalized_cells,
+        design.creator.clone(),
+        &config.schedule.jitter_seeds(),
+    );
+    placed_design.provenance = design.provenance.clone();
+
+    Ok(placed_design)
+}
+
+/// Place the cells of the placed input design inside `region` again and write it to the output
+/// file, as the `replace` subcommand does
+pub fn run_replace(config: &Config, region: &Region) -> Result<()> {
+    let start = Instant::now();
+    let design = read_placed_design(&config.io.input_file)?;
+
+    let mut placed_design = replace(config, &design, region)
+        .with_context(|| anyhow!("Place region of {:?} again", config.io.input_file))?;
+    placed_design
+        .provenance
+        .push(provenance(config).context("Record provenance")?);
+
+    write_placement(config, &placed_design, start)
+}
+
+/// Write the placed design to the output file and any of the extra outputs asked for
+fn write_placement(config: &Config, placed_design: &PlacedDesign, start: Instant) -> Result<()> {
+    write_placed_design(placed_design, &config.io.output_file)?;
+
+    if let Some(ref path) = config.io.placed_json_file {
+        write_placed_design_json(placed_design, path)?;
+    }
+
+    if let Some(ref path) = config.io.blockage_map_file {
+        let rules = BlockerRules::load(&config.io.blocker_rules_file)?;
+        let cell_factory = CellFactory::new(config.io.structure_directory.clone(), rules.clone());
+        blockage_map::build_blockage_map(&cell_factory, &rules, placed_design)
+            .context("Build blockage map")?
+            .write(path)?;
+    }
+
+    if let Some(ref path) = config.io.results_file {
+        log_results(config, path, placed_design, start.elapsed())
+            .with_context(|| anyhow!("Log results to {:?}", path))?;
+    }
+
+    Ok(())
+}
+
+/// Hash of the options that affect the placement, see [`results::config_hash`]
+fn config_hash(config: &Config) -> String {
+    // Everything but the input and output paths, so runs of the same options hash the same
+    let options = (
+        &config.io_plan,
+        &config.geometry,
+        &config.schedule,
+        &config.objective,
+        &config.legalizer,
+        &config.hierarchy,
+        &config.auto_size,
+    );
+    results::config_hash(&options)
+}
+
+/// Provenance of the placed design, recording the options and inputs it was placed from
+fn provenance(config: &Config) -> Result<Provenance> {
+    let mut inputs = vec![config.io.input_file.as_path()];
+    inputs.extend(config.io.timing_constraints_file.as_deref());
+    inputs.extend(config.io.net_weights_file.as_deref());
+    Provenance::new(
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        config_hash(config),
+        &inputs,
+        config.schedule.jitter_seeds(),
+    )
+}
+
+/// Append the wirelength of the legalized placement to the results file at `path`
+fn log_results(
+    config: &Config,
+    path: &Path,
+    placed_design: &PlacedDesign,
+    runtime: Duration,
+) -> Result<()> {
+    let rules = BlockerRules::load(&config.io.blocker_rules_file)?;
+    let mut cell_factory = CellFactory::new(config.io.structure_directory.clone(), rules);
+    let (netlist, legalized_cells) =
+        NetlistHypergraph::from_placed_design(placed_design, &mut cell_factory)?;
+
+    let mut record = RunRecord::new(
+        "placement",
+        &config.io.input_file,
+        config_hash(config),
+        runtime,
+    );
+    record.hpwl = Some(netlist.legalized_wirelength(&legalized_cells) as f64);
+    results::append_run(path, &record)
+}
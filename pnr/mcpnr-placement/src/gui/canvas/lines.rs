@@ -206,7 +206,7 @@ impl Canvas {
         let id = self.id;
 
         let cb = egui_wgpu::CallbackFn::new()
-            .prepare(move |device, queue, paint_callback_resources| {
+            .prepare(move |device, queue, _encoder, paint_callback_resources| {
                 let global_resources: &mut CanvasGlobalResources =
                     paint_callback_resources.get_mut().unwrap();
 
@@ -241,6 +241,8 @@ impl Canvas {
                     0,
                     bytemuck::cast_slice(&[uniforms]),
                 );
+
+                Vec::new()
             })
             .paint(move |_info, rpass, paint_callback_resources| {
                 let global_resources: &CanvasGlobalResources =
@@ -1,14 +1,25 @@
-use std::{collections::HashMap, sync::atomic::AtomicU64};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    sync::atomic::AtomicU64,
+};
 
-use egui::{Key, Vec2, Widget, WidgetInfo};
+use egui::{Key, Pos2, Vec2, Widget, WidgetInfo};
+use egui_wgpu::RenderState;
 use itertools::Itertools;
+use mcpnr_common::BLOCKS_PER_Z_ROW;
 use nalgebra as na;
 
 use crate::{
-    core::NetlistHypergraph, placement_cell::LegalizedCell, placer::diffusion::DiffusionPlacer,
+    config::GeometryConfig,
+    core::NetlistHypergraph,
+    placement_cell::LegalizedCell,
+    placer::{clusters::CellCluster, diffusion::DiffusionPlacer},
 };
 
 mod lines;
+mod pick;
+mod quads;
 mod rectangles;
 mod shader;
 
@@ -18,12 +29,67 @@ pub struct CanvasGlobalResources {
     rectangle: rectangles::GlobalResources,
     /// Global resources for rendering lines
     line: lines::GlobalResources,
+    /// Global resources for rendering filled quads
+    quad: quads::GlobalResources,
     /// Storage for per-canvas resources
     canvases: HashMap<CanvasId, CanvasRenderResources>,
 }
 
 const RECT_IDX_CELLS: usize = 0;
 const RECT_IDX_LEGAL: usize = 1;
+const RECT_IDX_CLUSTERS: usize = 2;
+
+/// Zoom limits, in pixels per internal unit
+const MIN_PIXELS_PER_UNIT: f32 = 1.0;
+const MAX_PIXELS_PER_UNIT: f32 = 128.0;
+
+/// Fraction of the canvas the design fills after fitting the view to it
+const FIT_MARGIN: f32 = 0.95;
+
+/// Length of the longer side of the minimap, and its distance from the corner of the canvas, in
+/// points
+const MINIMAP_SIZE: f32 = 160.0;
+const MINIMAP_MARGIN: f32 = 8.0;
+
+/// Outline colors of cells when they're not colored by type
+const GLOBAL_CELL_COLOR: egui::Color32 = egui::Color32::from_rgb(255, 0, 255);
+const LEGAL_CELL_COLOR: egui::Color32 = egui::Color32::from_rgb(0, 255, 255);
+const CLUSTER_CELL_COLOR: egui::Color32 = egui::Color32::from_rgb(255, 128, 0);
+
+/// Colors given to cell types, picked by hashing the type name. Chosen to stand out against the
+/// dark canvas and from each other.
+const CELL_TYPE_PALETTE: [egui::Color32; 12] = [
+    egui::Color32::from_rgb(230, 25, 75),
+    egui::Color32::from_rgb(60, 180, 75),
+    egui::Color32::from_rgb(255, 225, 25),
+    egui::Color32::from_rgb(67, 99, 216),
+    egui::Color32::from_rgb(245, 130, 49),
+    egui::Color32::from_rgb(145, 30, 180),
+    egui::Color32::from_rgb(66, 212, 244),
+    egui::Color32::from_rgb(240, 50, 230),
+    egui::Color32::from_rgb(191, 239, 69),
+    egui::Color32::from_rgb(250, 190, 212),
+    egui::Color32::from_rgb(70, 153, 144),
+    egui::Color32::from_rgb(220, 190, 255),
+];
+
+/// Most cell types listed in the legend, the rest are summed up in one line
+const LEGEND_ENTRIES: usize = 16;
+
+/// How close the cursor has to be to a net line to show its tooltip, in points
+const HOVER_TOLERANCE: f32 = 4.0;
+
+/// Smallest bucket size of the index used to find what's under the cursor, in internal units
+const HOVER_BUCKET_SIZE: f32 = 8.0;
+
+/// Color ramp for the density heatmap, from the least to the most dense region
+const DENSITY_RAMP: [(u8, u8, u8); 5] = [
+    (48, 18, 59),
+    (40, 120, 240),
+    (30, 200, 120),
+    (240, 220, 40),
+    (220, 30, 20),
+];
 
 /// Per-canvas render resources
 struct CanvasRenderResources {
@@ -34,6 +100,9 @@ struct CanvasRenderResources {
     /// Line resources.
     /// TODO: make this optional, so we can have more specialized canvases and they're cheaper
     line: lines::RenderResources,
+
+    /// Filled quad resources, used for the density heatmap
+    quads: quads::RenderResources,
 }
 
 #[repr(transparent)]
@@ -58,6 +127,45 @@ pub struct Canvas {
 
     /// Selected layer
     selected_layer: usize,
+
+    /// Whether to draw the cells where global placement put them
+    pub show_global: bool,
+
+    /// Whether to draw the legalized cells, if there are any
+    pub show_legalized: bool,
+
+    /// Whether to fill the diffusion regions with a color showing their density
+    pub show_heatmap: bool,
+
+    /// Opacity of the density heatmap
+    pub heatmap_alpha: f32,
+
+    /// Whether to color cells by their type instead of all the same
+    pub color_by_type: bool,
+
+    /// Whether to show which color is which cell type, when coloring by type
+    pub show_legend: bool,
+
+    /// Whether to fit the view to the design on the next render, when the size of the canvas is
+    /// known
+    fit_requested: bool,
+
+    /// Legalized cell currently being dragged around, if any
+    cell_drag: Option<CellDrag>,
+
+    /// Legalized cell that was dropped since the last call to [`Canvas::take_dropped_cell`]
+    dropped_cell: Option<CellDrag>,
+}
+
+/// A legalized cell being moved by hand
+#[derive(Clone, Copy, Debug)]
+pub struct CellDrag {
+    /// Index of the cell
+    pub cell: usize,
+    /// (x, tier, z) position of the cell before the drag started
+    pub original: (u32, u32, u32),
+    /// Offset from the cell origin to where it was grabbed, in internal units
+    grab_offset: Vec2,
 }
 
 /// Ephermeral state, for use with `egui::Ui::add`
@@ -65,17 +173,17 @@ pub struct CanvasWidget<'a> {
     canvas: &'a mut Canvas,
     cells: &'a NetlistHypergraph,
     diffusion: Option<&'a DiffusionPlacer>,
-    legalized_cells: Option<&'a [LegalizedCell]>,
+    legalized_cells: Option<&'a mut [LegalizedCell]>,
+    clusters: &'a [CellCluster],
+    geometry: &'a GeometryConfig,
 }
 
 impl CanvasGlobalResources {
-    pub fn register(cc: &eframe::CreationContext) {
-        let render_state = cc.wgpu_render_state.as_ref().expect("WGPU enabled");
-
+    pub fn register(render_state: &RenderState) {
         let device = &render_state.device;
 
         render_state
-            .egui_rpass
+            .renderer
             .write()
             .paint_callback_resources
             .insert(Self {
@@ -84,18 +192,18 @@ impl CanvasGlobalResources {
                     render_state.target_format.into(),
                 ),
                 line: lines::GlobalResources::new(device, render_state.target_format.into()),
+                quad: quads::GlobalResources::new(device, render_state.target_format),
                 canvases: Default::default(),
             });
     }
 }
 
 impl Canvas {
-    pub fn new(cc: &eframe::CreationContext) -> Self {
-        let render_state = cc.wgpu_render_state.as_ref().expect("WGPU enabled");
-        let mut rpass = render_state.egui_rpass.write();
+    pub fn new(render_state: &RenderState) -> Self {
+        let mut renderer = render_state.renderer.write();
 
         let global_resources: &mut CanvasGlobalResources =
-            rpass.paint_callback_resources.get_mut().unwrap();
+            renderer.paint_callback_resources.get_mut().unwrap();
 
         let device = &render_state.device;
 
@@ -105,8 +213,10 @@ impl Canvas {
             rectangle_resources: vec![
                 global_resources.rectangle.create_local(device),
                 global_resources.rectangle.create_local(device),
+                global_resources.rectangle.create_local(device),
             ],
             line: global_resources.line.create_local(device),
+            quads: global_resources.quad.create_local(device),
         };
 
         global_resources.canvases.insert(id, render_resources);
@@ -118,15 +228,68 @@ impl Canvas {
             density_max: 0.0,
             density_min: 0.0,
             selected_layer: 0,
+            show_global: true,
+            show_legalized: true,
+            show_heatmap: true,
+            heatmap_alpha: 0.5,
+            color_by_type: true,
+            show_legend: true,
+            fit_requested: false,
+            cell_drag: None,
+            dropped_cell: None,
         }
     }
 
+    /// Take the legalized cell that was most recently dropped after being dragged. Its position
+    /// has already been updated, but nothing was done to resolve any overlaps it introduced.
+    pub fn take_dropped_cell(&mut self) -> Option<CellDrag> {
+        self.dropped_cell.take()
+    }
+
+    /// Center and scale of the view, see [`Canvas::set_viewport`]
+    pub fn viewport(&self) -> (Vec2, f32) {
+        (self.center, self.pixels_per_unit)
+    }
+
+    /// Move the view, e.g. to keep it in line with another canvas
+    pub fn set_viewport(&mut self, (center, pixels_per_unit): (Vec2, f32)) {
+        self.center = center;
+        self.pixels_per_unit = pixels_per_unit;
+    }
+
+    /// Convert a position on the screen to internal units
+    fn screen_to_units(&self, render_rect: egui::Rect, pixels_per_point: f32, pos: Pos2) -> Vec2 {
+        // The X axis is mirrored by the projection (see `render_canvas`), the Y axis is flipped
+        // between screen space and clip space.
+        let offset = (pos - render_rect.center()) * pixels_per_point / self.pixels_per_unit;
+        Vec2::new(self.center.x - offset.x, self.center.y - offset.y)
+    }
+
+    /// Find the legalized cell on the selected layer under `pos`, ignoring position-locked cells
+    fn pick_cell(
+        &self,
+        cells: &NetlistHypergraph,
+        legalized_cells: &[LegalizedCell],
+        pos: Vec2,
+    ) -> Option<usize> {
+        legalized_cells.iter().enumerate().position(|(i, cell)| {
+            !cells.cells[i].pos_locked
+                && cell.tier_y as usize == self.selected_layer
+                && (cell.x as f32) <= pos.x
+                && pos.x < (cell.x + cell.sx) as f32
+                && (cell.z as f32) <= pos.y
+                && pos.y < (cell.z + cell.sz) as f32
+        })
+    }
+
     fn render_canvas(
         &mut self,
         ui: &mut egui::Ui,
         cells: &NetlistHypergraph,
         diffusion: Option<&DiffusionPlacer>,
-        legalized_cells: Option<&[LegalizedCell]>,
+        mut legalized_cells: Option<&mut [LegalizedCell]>,
+        clusters: &[CellCluster],
+        geometry: &GeometryConfig,
     ) -> egui::Response {
         let (render_rect, response) =
             ui.allocate_at_least(ui.available_size(), egui::Sense::click_and_drag());
@@ -169,7 +332,8 @@ impl Canvas {
                 1.0
             };
 
-            self.pixels_per_unit = f32::min(128.0, f32::max(1.0, self.pixels_per_unit * factor));
+            self.pixels_per_unit =
+                (self.pixels_per_unit * factor).clamp(MIN_PIXELS_PER_UNIT, MAX_PIXELS_PER_UNIT);
 
             // Keyboard controls
             const KEY_SCROLL: f32 = 64.0;
@@ -187,14 +351,77 @@ impl Canvas {
             }
         }
 
+        // Clicking the minimap jumps there
+        let die = egui::Rect::from_min_max(
+            Pos2::ZERO,
+            (geometry.size_x as f32, geometry.size_z as f32).into(),
+        );
+        let minimap = Minimap::new(render_rect, design_extent(cells, die), die);
+        if response.clicked() {
+            if let Some(pos) = response
+                .interact_pointer_pos()
+                .filter(|pos| minimap.rect.contains(*pos))
+            {
+                self.center = minimap.to_units(pos);
+            }
+        }
+
+        let pixels_per_point = ui.ctx().pixels_per_point();
+        let pointer = response
+            .interact_pointer_pos()
+            .map(|pos| self.screen_to_units(render_rect, pixels_per_point, pos));
+
+        // Dragging a legalized cell moves it, dragging anywhere else moves the view
+        if response.drag_started() {
+            self.cell_drag = match (pointer, legalized_cells.as_deref()) {
+                (Some(pointer), Some(legalized)) => {
+                    self.pick_cell(cells, legalized, pointer).map(|i| {
+                        let cell = &legalized[i];
+                        CellDrag {
+                            cell: i,
+                            original: (cell.x, cell.tier_y, cell.z),
+                            grab_offset: pointer - Vec2::new(cell.x as f32, cell.z as f32),
+                        }
+                    })
+                }
+                _ => None,
+            };
+        }
+
         if response.dragged() {
-            self.center += response.drag_delta() / self.pixels_per_unit;
+            match (self.cell_drag, pointer, legalized_cells.as_deref_mut()) {
+                (Some(drag), Some(pointer), Some(legalized)) => {
+                    // Snap to the legal grid: even x coordinates, and the start of a Z row
+                    let origin = pointer - drag.grab_offset;
+                    let cell = &mut legalized[drag.cell];
+                    cell.x = ((origin.x / 2.0).round().max(0.0) as u32) * 2;
+                    cell.z = ((origin.y / BLOCKS_PER_Z_ROW as f32).round().max(0.0) as u32)
+                        * BLOCKS_PER_Z_ROW;
+                }
+                _ => self.center += response.drag_delta() / self.pixels_per_unit,
+            }
+        }
+
+        if response.drag_released() {
+            if let Some(drag) = self.cell_drag.take() {
+                self.dropped_cell = Some(drag);
+            }
         }
 
         // Compute the size in pixels
         let pixel_width = render_rect.width() * ui.ctx().pixels_per_point();
         let pixel_height = render_rect.height() * ui.ctx().pixels_per_point();
 
+        if std::mem::take(&mut self.fit_requested) {
+            self.center = minimap.extent.center().to_vec2();
+            let scale = f32::min(
+                pixel_width / minimap.extent.width().max(1.0),
+                pixel_height / minimap.extent.height().max(1.0),
+            );
+            self.pixels_per_unit =
+                (scale * FIT_MARGIN).clamp(MIN_PIXELS_PER_UNIT, MAX_PIXELS_PER_UNIT);
+        }
+
         //
         // Extract the rectangles we should render
         //
@@ -244,6 +471,63 @@ impl Canvas {
 
         let selected_layer = self.selected_layer;
 
+        if let Some(diffusion) = diffusion.filter(|_| self.show_heatmap) {
+            let scale = diffusion.region_size as f32;
+            let range = density_max - density_min;
+            let alpha = self.heatmap_alpha;
+            // Region (x, z) of the density array covers [x - 1, x) * region_size, the first and
+            // last regions along each axis are the border around the placement region
+            self.render_quads(
+                ui,
+                projection_view,
+                render_rect,
+                clip_rect,
+                diffusion
+                    .density
+                    .indexed_iter()
+                    .filter(|((_, y, _), _)| *y == selected_layer)
+                    .map(|((x, _, z), &density)| {
+                        let x = x as f32 - 1.0;
+                        let z = z as f32 - 1.0;
+                        let level = if range > 0.0 {
+                            (density - density_min) / range
+                        } else {
+                            0.0
+                        };
+                        (
+                            egui::Rect {
+                                min: (x * scale, z * scale).into(),
+                                max: ((x + 1.0) * scale, (z + 1.0) * scale).into(),
+                            },
+                            density_color(level, alpha),
+                        )
+                    }),
+            );
+        }
+
+        // Nets are drawn between the legalized cells once there are any, so they follow cells
+        // being dragged around
+        let legalized_cells = legalized_cells.as_deref();
+        let cell_centers = match legalized_cells {
+            Some(legalized) => legalized
+                .iter()
+                .map(|cell| {
+                    (
+                        cell.x as f32 + cell.sx as f32 / 2.0,
+                        cell.z as f32 + cell.sz as f32 / 2.0,
+                    )
+                })
+                .collect_vec(),
+            None => cells
+                .cells
+                .iter()
+                .map(|cell| {
+                    let center = cell.center_pos();
+                    (center.x, center.z)
+                })
+                .collect_vec(),
+        };
+
         self.render_lines(
             ui,
             projection_view,
@@ -257,12 +541,9 @@ impl Canvas {
                     signal
                         .connected_cells
                         .iter()
-                        .map(|cell| {
-                            let center = &cells.cells[*cell].center_pos();
-                            lines::Vertex {
-                                color: egui::Color32::RED,
-                                position: (center.x, center.z),
-                            }
+                        .map(|cell| lines::Vertex {
+                            color: egui::Color32::RED,
+                            position: cell_centers[*cell],
                         })
                         .tuple_windows()
                 })
@@ -325,64 +606,79 @@ impl Canvas {
                                 },
                             )
                         }))
-                        .chain((0..(size_x + 2) * (size_y + 2)).map(move |xy| {
-                            let x = xy / (size_y + 2);
-                            let y = xy % (size_y + 2);
-                            let fill_level =
-                                (diffusion.density.get((x, selected_layer, y)).unwrap()
-                                    - density_min)
-                                    / (density_max - density_min);
-
-                            let x = ((x as f32) - 0.5) * scale;
-                            let y = ((y as f32) - 0.9) * scale;
-
-                            (
-                                lines::Vertex {
-                                    color: egui::Color32::GREEN,
-                                    position: (x, y),
-                                },
-                                lines::Vertex {
-                                    color: egui::Color32::GOLD,
-                                    position: (x, y + fill_level),
-                                },
-                            )
-                        }))
                 })),
         );
 
+        let cell_types = self.color_by_type.then(|| CellTypes::new(cells));
+        let cell_color = |i: usize, default: egui::Color32| match &cell_types {
+            Some(cell_types) => cell_types.color(i),
+            None => default,
+        };
+
+        if self.show_global {
+            self.render_rectangles(
+                ui,
+                projection_view,
+                render_rect,
+                clip_rect,
+                // Cell rendering
+                RECT_IDX_CELLS,
+                cells.cells.iter().enumerate().map(|(i, cell)| {
+                    (
+                        egui::Rect {
+                            min: (cell.x, cell.z).into(),
+                            max: (cell.x + cell.sx, cell.z + cell.sz).into(),
+                        },
+                        cell_color(i, GLOBAL_CELL_COLOR),
+                    )
+                }),
+            );
+        }
+
+        // Stacked cells, drawn a little larger so they show around the cells on top of them
         self.render_rectangles(
             ui,
             projection_view,
             render_rect,
             clip_rect,
-            // Cell rendering
-            egui::Color32::from_rgba_unmultiplied(255, 0, 255, 255),
-            RECT_IDX_CELLS,
-            cells.cells.iter().map(|cell| egui::Rect {
-                min: (cell.x, cell.z).into(),
-                max: (cell.x + cell.sx, cell.z + cell.sz).into(),
-            }),
+            RECT_IDX_CLUSTERS,
+            clusters
+                .iter()
+                .flat_map(|cluster| cluster.cells.iter())
+                .map(|&i| {
+                    const OUTSET: f32 = 0.25;
+                    let cell = &cells.cells[i];
+                    (
+                        egui::Rect {
+                            min: (cell.x - OUTSET, cell.z - OUTSET).into(),
+                            max: (cell.x + cell.sx + OUTSET, cell.z + cell.sz + OUTSET).into(),
+                        },
+                        CLUSTER_CELL_COLOR,
+                    )
+                }),
         );
 
-        if let Some(cells) = legalized_cells {
+        if let Some(legalized) = legalized_cells.filter(|_| self.show_legalized) {
             self.render_rectangles(
                 ui,
                 projection_view,
                 render_rect,
                 clip_rect,
-                egui::Color32::from_rgba_unmultiplied(0, 255, 255, 255),
                 RECT_IDX_LEGAL,
-                cells.iter().filter_map(|cell| {
+                legalized.iter().enumerate().filter_map(|(i, cell)| {
                     if cell.tier_y as usize == self.selected_layer {
                         const INSET: f32 = 0.05;
-                        Some(egui::Rect {
-                            min: (cell.x as f32 + INSET, cell.z as f32 + INSET).into(),
-                            max: (
-                                (cell.x + cell.sx) as f32 - INSET,
-                                (cell.z + cell.sz) as f32 - INSET,
-                            )
-                                .into(),
-                        })
+                        Some((
+                            egui::Rect {
+                                min: (cell.x as f32 + INSET, cell.z as f32 + INSET).into(),
+                                max: (
+                                    (cell.x + cell.sx) as f32 - INSET,
+                                    (cell.z + cell.sz) as f32 - INSET,
+                                )
+                                    .into(),
+                            },
+                            cell_color(i, LEGAL_CELL_COLOR),
+                        ))
                     } else {
                         None
                     }
@@ -390,7 +686,366 @@ impl Canvas {
             )
         }
 
-        response
+        let painter = ui.painter_at(render_rect);
+        if let Some(cell_types) = cell_types.as_ref().filter(|_| self.show_legend) {
+            cell_types.paint_legend(&painter, render_rect.min + Vec2::splat(MINIMAP_MARGIN));
+        }
+        minimap.paint(
+            &painter,
+            cells.cells.iter().enumerate().map(|(i, cell)| {
+                (
+                    egui::Rect::from_min_max(
+                        (cell.x, cell.z).into(),
+                        (cell.x + cell.sx, cell.z + cell.sz).into(),
+                    ),
+                    cell_color(i, GLOBAL_CELL_COLOR),
+                )
+            }),
+            clip_rect,
+        );
+
+        // Tooltip for whatever is under the cursor, looked up in an index of what was drawn
+        let hovered = response
+            .hover_pos()
+            .filter(|pos| self.cell_drag.is_none() && !minimap.rect.contains(*pos))
+            .and_then(|pos| {
+                let pixels_per_point = ui.ctx().pixels_per_point();
+                let units = self.screen_to_units(render_rect, pixels_per_point, pos);
+                let tolerance = HOVER_TOLERANCE * pixels_per_point / self.pixels_per_unit;
+                self.hover_index(cells, legalized_cells, &cell_centers, tolerance)
+                    .query(units.to_pos2(), tolerance)
+            });
+        match hovered {
+            Some(item) => {
+                response.on_hover_ui_at_pointer(|ui| hover_ui(ui, cells, legalized_cells, item))
+            }
+            None => response,
+        }
+    }
+
+    /// Index the cells and nets drawn this frame, so nets are found within `tolerance` of the
+    /// cursor
+    fn hover_index(
+        &self,
+        cells: &NetlistHypergraph,
+        legalized_cells: Option<&[LegalizedCell]>,
+        cell_centers: &[(f32, f32)],
+        tolerance: f32,
+    ) -> pick::SpatialIndex {
+        let mut index = pick::SpatialIndex::new(HOVER_BUCKET_SIZE.max(2.0 * tolerance));
+
+        for (i, signal) in cells.signals.iter().enumerate() {
+            for (&a, &b) in signal.connected_cells.iter().tuple_windows() {
+                index.insert_segment(
+                    pick::Item::Signal(i),
+                    cell_centers[a].into(),
+                    cell_centers[b].into(),
+                );
+            }
+        }
+
+        if self.show_global {
+            for (i, cell) in cells.cells.iter().enumerate() {
+                index.insert_rect(
+                    pick::Item::Cell(i),
+                    egui::Rect {
+                        min: (cell.x, cell.z).into(),
+                        max: (cell.x + cell.sx, cell.z + cell.sz).into(),
+                    },
+                );
+            }
+        }
+
+        if let Some(legalized) = legalized_cells.filter(|_| self.show_legalized) {
+            for (i, cell) in legalized.iter().enumerate() {
+                if cell.tier_y as usize == self.selected_layer {
+                    index.insert_rect(
+                        pick::Item::Cell(i),
+                        egui::Rect {
+                            min: (cell.x as f32, cell.z as f32).into(),
+                            max: ((cell.x + cell.sx) as f32, (cell.z + cell.sz) as f32).into(),
+                        },
+                    );
+                }
+            }
+        }
+
+        index
+    }
+}
+
+/// Contents of the tooltip for a hovered cell or net
+fn hover_ui(
+    ui: &mut egui::Ui,
+    cells: &NetlistHypergraph,
+    legalized_cells: Option<&[LegalizedCell]>,
+    item: pick::Item,
+) {
+    match item {
+        pick::Item::Cell(i) => {
+            match cells.metadata.get(i) {
+                Some(metadata) => {
+                    ui.strong(&metadata.name);
+                    ui.label(format!("Type: {}", metadata.ty));
+                }
+                None => {
+                    ui.strong(format!("Cell #{}", i));
+                }
+            }
+            match legalized_cells.and_then(|legalized| legalized.get(i)) {
+                Some(cell) => {
+                    ui.label(format!(
+                        "Size: {} x {} x {}",
+                        cell.sx, cell.s_tier_y, cell.sz
+                    ));
+                    ui.label(format!(
+                        "Position: ({}, {}, {})",
+                        cell.x, cell.tier_y, cell.z
+                    ));
+                }
+                None => {
+                    let cell = &cells.cells[i];
+                    ui.label(format!(
+                        "Size: {} x {} x {}",
+                        cell.sx, cell.s_tier_y, cell.sz
+                    ));
+                    ui.label(format!(
+                        "Position: ({:.2}, {:.2}, {:.2})",
+                        cell.x, cell.tier_y, cell.z
+                    ));
+                }
+            }
+            if cells.cells[i].pos_locked {
+                ui.label("Position locked");
+            }
+        }
+        pick::Item::Signal(i) => {
+            let signal = &cells.signals[i];
+            match cells.signal_name(signal) {
+                Some(name) => ui.strong(name),
+                None => ui.strong(format!("${}", signal.id)),
+            };
+            ui.label(format!(
+                "Pins: {} ({} on mobile cells)",
+                signal.connected_cells.len(),
+                signal.moveable_cells
+            ));
+        }
+    }
+}
+
+/// Extent of the design in internal units: the bounding box of the `die` and every cell, some of
+/// which may have strayed off it
+fn design_extent(cells: &NetlistHypergraph, die: egui::Rect) -> egui::Rect {
+    cells.cells.iter().fold(die, |extent, cell| {
+        extent.union(egui::Rect::from_min_max(
+            (cell.x, cell.z).into(),
+            (cell.x + cell.sx, cell.z + cell.sz).into(),
+        ))
+    })
+}
+
+/// Color of a region of the density heatmap, `level` going from 0 for the least dense region to 1
+/// for the most dense one
+fn density_color(level: f32, alpha: f32) -> egui::Color32 {
+    let position = level.clamp(0.0, 1.0) * (DENSITY_RAMP.len() - 1) as f32;
+    let idx = (position.floor() as usize).min(DENSITY_RAMP.len() - 2);
+    let t = position - idx as f32;
+    let (r0, g0, b0) = DENSITY_RAMP[idx];
+    let (r1, g1, b1) = DENSITY_RAMP[idx + 1];
+    let lerp = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+    egui::Color32::from_rgba_unmultiplied(
+        lerp(r0, r1),
+        lerp(g0, g1),
+        lerp(b0, b1),
+        (alpha.clamp(0.0, 1.0) * 255.0).round() as u8,
+    )
+}
+
+/// Palette color for the cell type `ty`
+fn cell_type_color(ty: &str) -> egui::Color32 {
+    let mut hasher = DefaultHasher::new();
+    ty.hash(&mut hasher);
+    CELL_TYPE_PALETTE[(hasher.finish() % CELL_TYPE_PALETTE.len() as u64) as usize]
+}
+
+/// Colors of the cells of a design by their type, and how many cells there are of each type
+struct CellTypes<'a> {
+    /// Color of each cell, in the same order as [`NetlistHypergraph::cells`]
+    colors: Vec<egui::Color32>,
+    /// Every cell type with its color and number of cells, most common first
+    types: Vec<(&'a str, egui::Color32, usize)>,
+}
+
+impl<'a> CellTypes<'a> {
+    fn new(cells: &'a NetlistHypergraph) -> Self {
+        let mut types: HashMap<&str, (egui::Color32, usize)> = HashMap::new();
+        let colors = (0..cells.cells.len())
+            .map(|i| match cells.metadata.get(i) {
+                Some(metadata) => {
+                    let entry = types
+                        .entry(metadata.ty.as_str())
+                        .or_insert_with(|| (cell_type_color(&metadata.ty), 0));
+                    entry.1 += 1;
+                    entry.0
+                }
+                // Cells without metadata have no type
+                None => GLOBAL_CELL_COLOR,
+            })
+            .collect();
+        let types = types
+            .into_iter()
+            .map(|(ty, (color, count))| (ty, color, count))
+            .sorted_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(b.0)))
+            .collect();
+
+        Self { colors, types }
+    }
+
+    fn color(&self, cell: usize) -> egui::Color32 {
+        self.colors[cell]
+    }
+
+    /// Draw a swatch, the name and the cell count of each type in a box with its top left corner at
+    /// `pos`. The palette is small, so several types may share a color.
+    fn paint_legend(&self, painter: &egui::Painter, pos: Pos2) {
+        if self.types.is_empty() {
+            return;
+        }
+
+        const PADDING: f32 = 4.0;
+        const SWATCH: f32 = 10.0;
+        let font_id = egui::FontId::proportional(12.0);
+
+        let mut rows = self
+            .types
+            .iter()
+            .take(LEGEND_ENTRIES)
+            .map(|&(ty, color, count)| {
+                let galley = painter.layout_no_wrap(
+                    format!("{} ({})", ty, count),
+                    font_id.clone(),
+                    egui::Color32::WHITE,
+                );
+                (Some(color), galley)
+            })
+            .collect_vec();
+        if self.types.len() > LEGEND_ENTRIES {
+            let rest: usize = self.types[LEGEND_ENTRIES..].iter().map(|t| t.2).sum();
+            let galley = painter.layout_no_wrap(
+                format!(
+                    "{} more types ({})",
+                    self.types.len() - LEGEND_ENTRIES,
+                    rest
+                ),
+                font_id,
+                egui::Color32::GRAY,
+            );
+            rows.push((None, galley));
+        }
+
+        let width = rows
+            .iter()
+            .map(|(_, galley)| galley.size().x)
+            .fold(0.0, f32::max);
+        let height: f32 = rows.iter().map(|(_, galley)| galley.size().y).sum();
+        let rect = egui::Rect::from_min_size(
+            pos,
+            Vec2::new(width + SWATCH + 3.0 * PADDING, height + 2.0 * PADDING),
+        );
+        painter.rect_filled(rect, 2.0, egui::Color32::from_black_alpha(192));
+        painter.rect_stroke(rect, 2.0, (1.0, egui::Color32::GRAY));
+
+        let mut row_pos = pos + Vec2::splat(PADDING);
+        for (color, galley) in rows {
+            let row_height = galley.size().y;
+            if let Some(color) = color {
+                let swatch = egui::Rect::from_center_size(
+                    row_pos + Vec2::new(SWATCH / 2.0, row_height / 2.0),
+                    Vec2::splat(SWATCH),
+                );
+                painter.rect_filled(swatch, 0.0, color);
+            }
+            painter.galley(row_pos + Vec2::new(SWATCH + PADDING, 0.0), galley);
+            row_pos.y += row_height;
+        }
+    }
+}
+
+/// Overview of the whole design in the corner of a canvas, with the part of it that's in view
+struct Minimap {
+    /// Where the minimap is on the screen, in points
+    rect: egui::Rect,
+    /// Extent of the design, in internal units
+    extent: egui::Rect,
+    /// Placement region, in internal units
+    die: egui::Rect,
+    /// Points per internal unit
+    scale: f32,
+}
+
+impl Minimap {
+    /// Minimap of `extent`, with the placement region `die` in it, in the bottom right corner of
+    /// `render_rect`
+    fn new(render_rect: egui::Rect, extent: egui::Rect, die: egui::Rect) -> Self {
+        let scale = MINIMAP_SIZE / extent.width().max(extent.height()).max(1.0);
+        let max = render_rect.max - Vec2::splat(MINIMAP_MARGIN);
+        Self {
+            rect: egui::Rect::from_min_max(max - extent.size() * scale, max),
+            extent,
+            die,
+            scale,
+        }
+    }
+
+    /// Rectangle on the screen covering `rect` in internal units
+    fn rect_to_screen(&self, rect: egui::Rect) -> egui::Rect {
+        egui::Rect::from_two_pos(self.to_screen(rect.min), self.to_screen(rect.max))
+    }
+
+    /// Position on the screen of a point in internal units. Like the canvas itself X is mirrored
+    /// and Z goes up the screen.
+    fn to_screen(&self, pos: Pos2) -> Pos2 {
+        Pos2::new(
+            self.rect.max.x - (pos.x - self.extent.min.x) * self.scale,
+            self.rect.max.y - (pos.y - self.extent.min.y) * self.scale,
+        )
+    }
+
+    /// Position in internal units of a point on the minimap
+    fn to_units(&self, pos: Pos2) -> Vec2 {
+        Vec2::new(
+            self.extent.min.x + (self.rect.max.x - pos.x) / self.scale,
+            self.extent.min.y + (self.rect.max.y - pos.y) / self.scale,
+        )
+    }
+
+    /// Draw the minimap with `cells` and the outline of the die on it, outlining `view`. Both the
+    /// cells and the view are in internal units. Cells are drawn at least a point across, so even
+    /// small ones show up on large designs.
+    fn paint(
+        &self,
+        painter: &egui::Painter,
+        cells: impl Iterator<Item = (egui::Rect, egui::Color32)>,
+        view: egui::Rect,
+    ) {
+        painter.rect_filled(self.rect, 2.0, egui::Color32::from_black_alpha(192));
+        painter.rect_stroke(self.rect, 2.0, (1.0, egui::Color32::GRAY));
+
+        for (cell, color) in cells {
+            let cell = self.rect_to_screen(cell);
+            let cell =
+                egui::Rect::from_center_size(cell.center(), cell.size().max(Vec2::splat(1.0)));
+            painter.rect_filled(cell, 0.0, color);
+        }
+        painter.rect_stroke(
+            self.rect_to_screen(self.die),
+            0.0,
+            (1.0, egui::Color32::LIGHT_GREEN),
+        );
+
+        let view = self.rect_to_screen(view).intersect(self.rect);
+        painter.rect_stroke(view, 0.0, (1.0, egui::Color32::WHITE));
     }
 }
 
@@ -409,13 +1064,17 @@ impl<'a> CanvasWidget<'a> {
         canvas: &'a mut Canvas,
         cells: &'a NetlistHypergraph,
         diffusion: Option<&'a DiffusionPlacer>,
-        legalized_cells: Option<&'a [LegalizedCell]>,
+        legalized_cells: Option<&'a mut [LegalizedCell]>,
+        clusters: &'a [CellCluster],
+        geometry: &'a GeometryConfig,
     ) -> Self {
         Self {
             canvas,
             cells,
             diffusion,
             legalized_cells,
+            clusters,
+            geometry,
         }
     }
 }
@@ -439,6 +1098,12 @@ impl<'a> Widget for CanvasWidget<'a> {
 
                 ui.horizontal(|ui| match self.diffusion.map(|m| m.density.shape()) {
                     Some(diffusion_shape) => {
+                        ui.checkbox(&mut self.canvas.show_heatmap, "Density heatmap");
+                        ui.add_enabled(
+                            self.canvas.show_heatmap,
+                            egui::Slider::new(&mut self.canvas.heatmap_alpha, 0.0..=1.0)
+                                .text("alpha"),
+                        );
                         if ui.small_button("+").clicked() {
                             if self.canvas.selected_layer + 1 < diffusion_shape[1] {
                                 self.canvas.selected_layer += 1;
@@ -458,6 +1123,19 @@ impl<'a> Widget for CanvasWidget<'a> {
                     None => {}
                 });
 
+                ui.horizontal(|ui| {
+                    if ui.button("Fit view").clicked() {
+                        self.canvas.fit_requested = true;
+                    }
+                    ui.checkbox(&mut self.canvas.show_global, "Global placement");
+                    ui.checkbox(&mut self.canvas.show_legalized, "Legalized");
+                    ui.checkbox(&mut self.canvas.color_by_type, "Color by type");
+                    ui.add_enabled(
+                        self.canvas.color_by_type,
+                        egui::Checkbox::new(&mut self.canvas.show_legend, "Legend"),
+                    );
+                });
+
                 egui::Frame::canvas(ui.style())
                     .show(ui, |ui| {
                         self.canvas.render_canvas(
@@ -465,6 +1143,8 @@ impl<'a> Widget for CanvasWidget<'a> {
                             self.cells,
                             self.diffusion,
                             self.legalized_cells,
+                            self.clusters,
+                            self.geometry,
                         )
                     })
                     .inner
@@ -0,0 +1,167 @@
+//! CPU-side index of what the canvas drew, so the element under the cursor can be found without
+//! reading anything back from the GPU.
+
+use std::collections::HashMap;
+
+use egui::{Pos2, Rect, Vec2};
+
+/// Something drawn on the canvas
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Item {
+    /// The cell with this index in [`crate::core::NetlistHypergraph::cells`]
+    Cell(usize),
+    /// The signal with this index in [`crate::core::NetlistHypergraph::signals`]
+    Signal(usize),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Shape {
+    Rect(Rect),
+    Segment(Pos2, Pos2),
+}
+
+/// Uniform grid over the rectangles and line segments drawn in a frame, in internal units
+pub(super) struct SpatialIndex {
+    /// Side length of each grid bucket
+    bucket_size: f32,
+    /// Indices in to `entries` of everything that may overlap each bucket
+    buckets: HashMap<(i32, i32), Vec<usize>>,
+    /// Everything inserted, in drawing order
+    entries: Vec<(Item, Shape)>,
+}
+
+impl SpatialIndex {
+    /// Create an empty index. Line segments are only found within half of `bucket_size` of the
+    /// query position.
+    pub(super) fn new(bucket_size: f32) -> Self {
+        Self {
+            bucket_size,
+            buckets: HashMap::new(),
+            entries: Vec::new(),
+        }
+    }
+
+    fn bucket(&self, pos: Pos2) -> (i32, i32) {
+        (
+            (pos.x / self.bucket_size).floor() as i32,
+            (pos.y / self.bucket_size).floor() as i32,
+        )
+    }
+
+    pub(super) fn insert_rect(&mut self, item: Item, rect: Rect) {
+        let idx = self.entries.len();
+        self.entries.push((item, Shape::Rect(rect)));
+
+        let (min_x, min_y) = self.bucket(rect.min);
+        let (max_x, max_y) = self.bucket(rect.max);
+        for x in min_x..=max_x {
+            for y in min_y..=max_y {
+                self.buckets.entry((x, y)).or_default().push(idx);
+            }
+        }
+    }
+
+    /// Insert a line segment in to the buckets it passes through. Those are found by stepping along
+    /// the segment half a bucket at a time, which may miss buckets the segment only clips the
+    /// corner of, but [`SpatialIndex::query`] also looks in the neighbouring buckets.
+    pub(super) fn insert_segment(&mut self, item: Item, start: Pos2, end: Pos2) {
+        let idx = self.entries.len();
+        self.entries.push((item, Shape::Segment(start, end)));
+
+        let steps = ((end - start).length() / (self.bucket_size / 2.0)).ceil() as usize;
+        let mut last = None;
+        for step in 0..=steps {
+            let t = if steps == 0 {
+                0.0
+            } else {
+                step as f32 / steps as f32
+            };
+            let bucket = self.bucket(start + (end - start) * t);
+            if last != Some(bucket) {
+                self.buckets.entry(bucket).or_default().push(idx);
+                last = Some(bucket);
+            }
+        }
+    }
+
+    /// Find what's under `pos`: the last drawn rectangle containing it if there is one, otherwise
+    /// the closest line segment no further than `tolerance` away.
+    pub(super) fn query(&self, pos: Pos2, tolerance: f32) -> Option<Item> {
+        let (bx, by) = self.bucket(pos);
+        let candidates = (bx - 1..=bx + 1)
+            .flat_map(|x| (by - 1..=by + 1).map(move |y| (x, y)))
+            .filter_map(|bucket| self.buckets.get(&bucket))
+            .flatten()
+            .copied();
+
+        let mut rect: Option<usize> = None;
+        let mut segment: Option<(usize, f32)> = None;
+        for idx in candidates {
+            match self.entries[idx].1 {
+                Shape::Rect(r) => {
+                    if r.contains(pos) {
+                        rect = rect.max(Some(idx));
+                    }
+                }
+                Shape::Segment(start, end) => {
+                    let distance = segment_distance(pos, start, end);
+                    let closer = !matches!(segment, Some((_, best)) if best <= distance);
+                    if distance <= tolerance && closer {
+                        segment = Some((idx, distance));
+                    }
+                }
+            }
+        }
+
+        rect.or(segment.map(|(idx, _)| idx))
+            .map(|idx| self.entries[idx].0)
+    }
+}
+
+/// Distance from `pos` to the closest point of the segment from `start` to `end`
+fn segment_distance(pos: Pos2, start: Pos2, end: Pos2) -> f32 {
+    let direction: Vec2 = end - start;
+    let length_sq = direction.length_sq();
+    let t = if length_sq > 0.0 {
+        ((pos - start).dot(direction) / length_sq).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    (start + direction * t).distance(pos)
+}
+
+#[cfg(test)]
+mod test {
+    use egui::{Pos2, Rect};
+
+    use super::{Item, SpatialIndex};
+
+    #[test]
+    fn finds_rectangles_before_segments() {
+        let mut index = SpatialIndex::new(4.0);
+        index.insert_segment(Item::Signal(0), Pos2::new(0.0, 0.0), Pos2::new(40.0, 40.0));
+        index.insert_segment(Item::Signal(1), Pos2::new(0.0, 10.0), Pos2::new(40.0, 10.0));
+        index.insert_rect(
+            Item::Cell(0),
+            Rect::from_min_max(Pos2::new(8.0, 8.0), Pos2::new(12.0, 12.0)),
+        );
+        index.insert_rect(
+            Item::Cell(1),
+            Rect::from_min_max(Pos2::new(9.0, 9.0), Pos2::new(11.0, 11.0)),
+        );
+
+        // The last drawn rectangle is on top
+        assert_eq!(index.query(Pos2::new(10.0, 10.0), 1.0), Some(Item::Cell(1)));
+        assert_eq!(index.query(Pos2::new(8.5, 11.5), 1.0), Some(Item::Cell(0)));
+        // Closest segment within the tolerance
+        assert_eq!(
+            index.query(Pos2::new(30.0, 10.5), 1.0),
+            Some(Item::Signal(1))
+        );
+        assert_eq!(
+            index.query(Pos2::new(30.0, 29.5), 1.0),
+            Some(Item::Signal(0))
+        );
+        assert_eq!(index.query(Pos2::new(30.0, 20.0), 1.0), None);
+    }
+}
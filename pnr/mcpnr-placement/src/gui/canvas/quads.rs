@@ -0,0 +1,264 @@
+use bytemuck::{Pod, Zeroable};
+use eframe::wgpu::{self, Device};
+use nalgebra as na;
+use std::sync::Arc;
+
+use crate::gui::canvas::CanvasGlobalResources;
+
+use super::Canvas;
+
+/********************************************************************************
+ * Rendering types and constants
+********************************************************************************/
+
+/// 2 triangles per quad, without sharing verticies
+const VERTEX_PER_QUAD: u64 = 6;
+
+/// Uniform buffer layout for the quad renderer
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
+pub struct Uniforms {
+    pub projection_view: [f32; 16],
+}
+
+/// Vertex type for the quad renderer
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Pod, Zeroable)]
+pub struct Vertex {
+    pub pos: egui::Vec2,
+    pub color: egui::Color32,
+}
+
+/********************************************************************************
+ * Implementation
+********************************************************************************/
+
+/// wgpu resources shared by all filled quad renderers
+pub struct GlobalResources {
+    /// Pipeline used to render the quads
+    pipeline: wgpu::RenderPipeline,
+    /// Bind group layout for quad pipeline
+    bind_group_layout: wgpu::BindGroupLayout,
+}
+
+impl GlobalResources {
+    /// Allocate all the globally shareable render resources. Quads are blended with what's under
+    /// them, so the colors passed to [`Canvas::render_quads`] may be translucent.
+    pub fn new(device: &Device, rs_target_format: wgpu::TextureFormat) -> Self {
+        // The vertex layout is the same as for the rectangle outlines, only the topology differs
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("canvas.quads.shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("./rectangles.wgsl").into()),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("canvas.quads.bind_group_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("canvas.quads.pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("canvas.quads.pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![
+                        0 => Float32x2,
+                        1 => Unorm8x4,
+                    ],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                // egui colors are premultiplied
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: rs_target_format,
+                    blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                ..wgpu::PrimitiveState::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        Self {
+            pipeline,
+            bind_group_layout,
+        }
+    }
+
+    /// Allocate storage for an individual quad renderer based on this suite of global resources.
+    pub fn create_local(&self, device: &Device) -> RenderResources {
+        const INITIAL_COUNT: u64 = 16;
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("canvas.quads.uniforms"),
+            size: std::mem::size_of::<Uniforms>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("canvas.quads.bind_group"),
+            layout: &self.bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+
+        RenderResources {
+            uniform_buffer,
+            bind_group,
+            vertex_buffer: alloc_buffer(device, INITIAL_COUNT),
+            count: INITIAL_COUNT,
+        }
+    }
+}
+
+/// Collection of resources used to render filled quads, per instance.
+pub struct RenderResources {
+    /// Buffer for uniforms
+    uniform_buffer: wgpu::Buffer,
+    /// Bind group
+    bind_group: wgpu::BindGroup,
+
+    /// Buffer for verticies
+    vertex_buffer: wgpu::Buffer,
+    /// Number of quads we have allocated in `Self::vertex_buffer`
+    count: u64,
+}
+
+impl Canvas {
+    /// Set the filled quads to be rendered for this canvas this frame, each in its own color.
+    pub fn render_quads(
+        &self,
+        ui: &mut egui::Ui,
+        projection_view: na::Matrix4<f32>,
+        render_rect: egui::Rect,
+        clip_rect: egui::Rect,
+        quads: impl Iterator<Item = (egui::Rect, egui::Color32)>,
+    ) {
+        let mut count: u64 = 0;
+        let mut verticies: Vec<Vertex> = Vec::new();
+
+        for (rect, color) in quads {
+            if rect.intersects(clip_rect) {
+                let corner = |x: f32, y: f32| Vertex {
+                    pos: egui::Vec2::new(x, y),
+                    color,
+                };
+                verticies.extend([
+                    corner(rect.min.x, rect.min.y),
+                    corner(rect.min.x, rect.max.y),
+                    corner(rect.max.x, rect.max.y),
+                    corner(rect.min.x, rect.min.y),
+                    corner(rect.max.x, rect.max.y),
+                    corner(rect.max.x, rect.min.y),
+                ]);
+
+                count += 1;
+            }
+        }
+
+        if count == 0 {
+            return;
+        }
+
+        let mut uniforms = Uniforms {
+            projection_view: [0.0; 16],
+        };
+
+        assert_eq!(projection_view.as_slice().len(), 16);
+        for (i, f) in projection_view.as_slice().iter().enumerate() {
+            uniforms.projection_view[i] = *f;
+        }
+
+        let id = self.id;
+
+        let cb = egui_wgpu::CallbackFn::new()
+            .prepare(move |device, queue, _encoder, paint_callback_resources| {
+                let global_resources: &mut CanvasGlobalResources =
+                    paint_callback_resources.get_mut().unwrap();
+
+                let local_resources = &mut global_resources.canvases.get_mut(&id).unwrap().quads;
+
+                if count > local_resources.count {
+                    let new_count = count + 16;
+
+                    local_resources.vertex_buffer = alloc_buffer(device, new_count);
+                    local_resources.count = new_count;
+                }
+
+                queue.write_buffer(
+                    &local_resources.vertex_buffer,
+                    0,
+                    bytemuck::cast_slice(&verticies),
+                );
+
+                queue.write_buffer(
+                    &local_resources.uniform_buffer,
+                    0,
+                    bytemuck::cast_slice(&[uniforms]),
+                );
+
+                Vec::new()
+            })
+            .paint(move |_info, rpass, paint_callback_resources| {
+                let global_resources: &CanvasGlobalResources =
+                    paint_callback_resources.get().unwrap();
+                let local_resources = global_resources.canvases.get(&id).unwrap();
+
+                let global_resources = &global_resources.quad;
+                let local_resources = &local_resources.quads;
+
+                rpass.set_pipeline(&global_resources.pipeline);
+                rpass.set_bind_group(0, &local_resources.bind_group, &[]);
+                rpass.set_vertex_buffer(
+                    0,
+                    local_resources
+                        .vertex_buffer
+                        .slice(..(count * VERTEX_PER_QUAD * std::mem::size_of::<Vertex>() as u64)),
+                );
+                rpass.draw(0..((count * VERTEX_PER_QUAD) as u32), 0..1);
+            });
+
+        ui.painter().add(egui::PaintCallback {
+            rect: render_rect,
+            callback: Arc::new(cb),
+        });
+    }
+}
+
+fn alloc_buffer(device: &wgpu::Device, count: u64) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("canvas.quads.vertex_buffer"),
+        size: count * VERTEX_PER_QUAD * std::mem::size_of::<Vertex>() as u64,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
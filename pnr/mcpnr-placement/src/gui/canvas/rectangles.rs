@@ -27,7 +27,6 @@ const INDEX_PER_RECT: u64 = 6;
 #[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
 pub struct Uniforms {
     pub projection_view: [f32; 16],
-    pub color: [f32; 4],
 }
 
 /// Vertex type for the rectangle renderer
@@ -35,6 +34,7 @@ pub struct Uniforms {
 #[derive(Clone, Copy, Debug, PartialEq, Pod, Zeroable)]
 pub struct Vertex {
     pub pos: egui::Vec2,
+    pub color: egui::Color32,
 }
 
 /********************************************************************************
@@ -87,7 +87,8 @@ impl GlobalResources {
                     array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
                     step_mode: wgpu::VertexStepMode::Vertex,
                     attributes: &wgpu::vertex_attr_array![
-                        0 => Float32x2
+                        0 => Float32x2,
+                        1 => Unorm8x4,
                     ],
                 }],
             },
@@ -161,35 +162,38 @@ pub struct RenderResources {
 }
 
 impl Canvas {
-    /// Set the rectangles to be rendered for this canvas this frame.
+    /// Set the rectangles to be rendered for this canvas this frame, each outlined in its own color.
     pub fn render_rectangles(
         &self,
         ui: &mut egui::Ui,
         projection_view: na::Matrix4<f32>,
         render_rect: egui::Rect,
         clip_rect: egui::Rect,
-        color: egui::Color32,
         rect_idx: usize,
-        rectangles: impl Iterator<Item = egui::Rect>,
+        rectangles: impl Iterator<Item = (egui::Rect, egui::Color32)>,
     ) {
         let mut count: IndexType = 0;
         let mut verticies: Vec<Vertex> = Vec::new();
         let mut indicies: Vec<IndexType> = Vec::new();
 
-        for rect in rectangles {
+        for (rect, color) in rectangles {
             if rect.intersects(clip_rect) {
                 let base_idx: u16 = verticies.len().try_into().unwrap();
                 verticies.push(Vertex {
                     pos: egui::Vec2::new(rect.min.x, rect.min.y),
+                    color,
                 });
                 verticies.push(Vertex {
                     pos: egui::Vec2::new(rect.min.x, rect.max.y),
+                    color,
                 });
                 verticies.push(Vertex {
                     pos: egui::Vec2::new(rect.max.x, rect.max.y),
+                    color,
                 });
                 verticies.push(Vertex {
                     pos: egui::Vec2::new(rect.max.x, rect.min.y),
+                    color,
                 });
 
                 indicies.push(base_idx + 0);
@@ -209,12 +213,6 @@ impl Canvas {
 
         let mut uniforms = Uniforms {
             projection_view: [0.0; 16],
-            color: [
-                color.r() as f32 / 255.0,
-                color.g() as f32 / 255.0,
-                color.b() as f32 / 255.0,
-                color.a() as f32 / 255.0,
-            ],
         };
 
         assert_eq!(projection_view.as_slice().len(), 16);
@@ -225,7 +223,7 @@ impl Canvas {
         let id = self.id;
 
         let cb = egui_wgpu::CallbackFn::new()
-            .prepare(move |device, queue, paint_callback_resources| {
+            .prepare(move |device, queue, _encoder, paint_callback_resources| {
                 let global_resources = &mut paint_callback_resources
                     .get_mut::<CanvasGlobalResources>()
                     .unwrap();
@@ -261,6 +259,8 @@ impl Canvas {
                     0,
                     bytemuck::cast_slice(&[uniforms]),
                 );
+
+                Vec::new()
             })
             .paint(move |_info, rpass, paint_callback_resources| {
                 let global_resources = paint_callback_resources
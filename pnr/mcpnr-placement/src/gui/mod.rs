@@ -1,26 +1,35 @@
 use crate::{
-    center_all_moveable_cells,
-    config::DiffusionConfig,
+    config::{DiffusionConfig, DiffusionMode, PlacementSchedule},
     core::NetlistHypergraph,
-    legalizer::{tetris::TetrisLegalizer, Legalizer},
-    load_cells, load_design, place_algorithm,
-    placement_cell::LegalizedCell,
+    flow::{center_all_moveable_cells, load_cells, load_design, place_algorithm},
+    legalizer::{self, incremental::legalize_incremental},
+    placement_cell::{CellFactory, LegalizedCell},
     placer::{
         analytical::{
-            AnchoredByNet, Clique, DecompositionStrategy, MoveableStar, ThresholdCrossover,
+            AnchoredByNet, Bound2Bound, Clique, DecompositionStrategy, MoveableStar,
+            ThresholdCrossover,
         },
+        clusters::{find_clusters, CellCluster, CLUSTER_TOLERANCE, MIN_CLUSTER_SIZE},
         diffusion::DiffusionPlacer,
     },
     Config,
 };
-use anyhow::Result;
-use eframe::{App, CreationContext};
+use anyhow::{anyhow, Context, Result};
+use eframe::App;
 use egui::Ui;
+use egui_wgpu::RenderState;
+use mcpnr_common::blockers::BlockerRules;
+use mcpnr_common::protos::read_placed_design;
+use std::path::Path;
 use tracing::info_span;
 
 use self::canvas::{Canvas, CanvasGlobalResources, CanvasWidget};
+use self::schedule::{schedule_ui, ScheduleAction};
 
 mod canvas;
+mod schedule;
+#[cfg(target_arch = "wasm32")]
+pub(crate) mod web;
 
 struct DiffusionUIState {
     diffusion_config: DiffusionConfig,
@@ -41,6 +50,11 @@ struct UIState {
 
     // Legalized cells, if that pass has been run
     legalized_cells: Option<Vec<LegalizedCell>>,
+    // Number of cells moved out of the way after the last cell drag
+    last_drag_displaced: Option<usize>,
+
+    // Clusters of stacked cells found by the last check, highlighted on the canvas
+    clusters: Vec<CellCluster>,
 
     // Net list properties
     cells: NetlistHypergraph,
@@ -49,6 +63,12 @@ struct UIState {
     // UI state
     do_debug_render: bool,
     primary_canvas: Canvas,
+    // Second canvas shown next to the first in split view, e.g. for another tier or for the
+    // legalized placement
+    secondary_canvas: Canvas,
+    split_view: bool,
+    // Whether panning or zooming either canvas in split view moves the other one too
+    lock_viewports: bool,
 }
 
 impl DiffusionUIState {
@@ -103,19 +123,26 @@ impl UIState {
     fn new(
         config: Config,
         cells: NetlistHypergraph,
+        legalized_cells: Option<Vec<LegalizedCell>>,
         creator: String,
-        cc: &CreationContext,
+        render_state: &RenderState,
     ) -> Self {
-        CanvasGlobalResources::register(cc);
+        CanvasGlobalResources::register(render_state);
 
         let diffusion_config = DiffusionConfig {
             region_size: 2,
+            mode: DiffusionMode::for_geometry(&config.geometry),
             iterations: 128,
             delta_t: 0.1,
         };
 
         let diffusion_placer = DiffusionPlacer::new(&config, &diffusion_config);
 
+        // The second canvas starts out showing only the legalized placement, to compare with the
+        // global placement in the first
+        let mut secondary_canvas = Canvas::new(render_state);
+        secondary_canvas.show_global = false;
+
         Self {
             config,
 
@@ -127,17 +154,24 @@ impl UIState {
                 diffusion_placer,
             }),
 
-            legalized_cells: None,
+            legalized_cells,
+            last_drag_displaced: None,
+            clusters: vec![],
 
             cells,
             creator,
             do_debug_render: false,
-            primary_canvas: Canvas::new(cc),
+            primary_canvas: Canvas::new(render_state),
+            secondary_canvas,
+            split_view: false,
+            lock_viewports: true,
         }
     }
 }
 
 impl App for UIState {
+    // The frame is only needed to quit, which the web build can't
+    #[cfg_attr(target_arch = "wasm32", allow(unused_variables))]
     fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
         egui::TopBottomPanel::top("title_panel").show(ctx, |ui| {
             ui.label(&self.creator);
@@ -151,6 +185,21 @@ impl App for UIState {
                 };
             }
 
+            ui.collapsing("Schedule", |ui| {
+                let action = schedule_ui(ui, &mut self.config.schedule, &self.config.geometry);
+                if let ScheduleAction::RunFrom(start) = action {
+                    let config = Config {
+                        schedule: PlacementSchedule {
+                            schedule: self.config.schedule.schedule[start..].to_vec(),
+                        },
+                        ..self.config.clone()
+                    };
+                    if let Err(e) = place_algorithm(&config, &mut self.cells) {
+                        log::error!("Placement failure: {:?}", e);
+                    }
+                }
+            });
+
             if ui.button("Center Cells").clicked() {
                 center_all_moveable_cells(&self.config, &mut self.cells);
             }
@@ -161,7 +210,7 @@ impl App for UIState {
                 if ui.button("Run").clicked() {
                     let mut strategy =
                         ThresholdCrossover::new(self.unconstrained_num_clique, Clique::new(), MoveableStar::new());
-                    match strategy.execute(&mut self.cells) {
+                    match strategy.execute_with(&mut self.cells, &self.config.objective) {
                         Ok(_) => {}
                         Err(e) => log::error!("Unconstrained analytical failure: {:?}", e),
                     };
@@ -174,13 +223,24 @@ impl App for UIState {
                 if ui.button("Run").clicked() {
                     let mut strategy =
                         ThresholdCrossover::new(2, Clique::new(), AnchoredByNet::new());
-                    match strategy.execute(&mut self.cells) {
+                    match strategy.execute_with(&mut self.cells, &self.config.objective) {
                         Ok(_) => {}
                         Err(e) => log::error!("Constrained analytical failure: {:?}", e),
                     };
                 }
             });
 
+            ui.group(|ui| {
+                ui.heading("Bound2Bound Analytical");
+                if ui.button("Run").clicked() {
+                    let mut strategy = Bound2Bound::new();
+                    match strategy.execute_with(&mut self.cells, &self.config.objective) {
+                        Ok(_) => {}
+                        Err(e) => log::error!("Bound2Bound analytical failure: {:?}", e),
+                    };
+                }
+            });
+
             ui.group(|ui| {
                 let mut checked = self.diffusion_state.is_some();
                 ui.checkbox(&mut checked, "Diffusion Placer");
@@ -189,6 +249,7 @@ impl App for UIState {
                     if self.diffusion_state.is_none() {
                         let diffusion_config = DiffusionConfig {
                             region_size: 2,
+                            mode: DiffusionMode::for_geometry(&self.config.geometry),
                             iterations: 128,
                             delta_t: 0.1,
                         };
@@ -211,14 +272,65 @@ impl App for UIState {
                 }
             });
 
+            ui.group(|ui| {
+                ui.heading("Stacked cells");
+
+                if ui.button("Find stacked cells").clicked() {
+                    self.clusters = find_clusters(&self.cells, CLUSTER_TOLERANCE, MIN_CLUSTER_SIZE);
+                }
+                if ui.button("Clear").clicked() {
+                    self.clusters.clear();
+                }
+
+                let stacked: usize = self.clusters.iter().map(|c| c.cells.len()).sum();
+                ui.label(format!(
+                    "{} cells in {} clusters",
+                    stacked,
+                    self.clusters.len()
+                ));
+                for cluster in self.clusters.iter().take(5) {
+                    ui.label(format!(
+                        "{} cells at ({:.1}, {:.1}, {:.1}), {} nets",
+                        cluster.cells.len(),
+                        cluster.center.x,
+                        cluster.center.y,
+                        cluster.center.z,
+                        cluster.signals.len()
+                    ));
+                }
+            });
+
             ui.group(|ui| {
                 ui.heading("Legalization");
 
                 if ui.button("Legalize!").clicked() {
-                    let legalizer = TetrisLegalizer::new(self.config.legalizer.left_limit);
-                    self.legalized_cells =
-                        Some(legalizer.legalize(&self.config.geometry, &self.cells.cells));
+                    let legalizer = legalizer::from_config(&self.config.legalizer);
+                    match legalizer.legalize(&self.config.geometry, &self.cells.cells) {
+                        Ok(legalized) => self.legalized_cells = Some(legalized),
+                        Err(e) => log::error!("Legalization failure: {:?}", e),
+                    }
+                    self.last_drag_displaced = None;
                 }
+
+                if let Some(ref legalized) = self.legalized_cells {
+                    ui.label("Drag cells on the canvas to move them");
+                    ui.label(format!(
+                        "HPWL: {:.0}",
+                        self.cells.legalized_wirelength(legalized)
+                    ));
+                    if let Some(displaced) = self.last_drag_displaced {
+                        ui.label(format!("Last drag moved {} other cells", displaced));
+                    }
+                }
+            });
+
+            ui.group(|ui| {
+                ui.heading("View");
+                ui.checkbox(&mut self.split_view, "Split view");
+                ui.add_enabled(
+                    self.split_view,
+                    egui::Checkbox::new(&mut self.lock_viewports, "Lock viewports"),
+                );
             });
 
             ui.collapsing("EGUI inspection", |ui| {
@@ -227,33 +339,133 @@ impl App for UIState {
                 ctx.inspection_ui(ui);
             });
 
+            // There's no quitting a web page
+            #[cfg(not(target_arch = "wasm32"))]
             ui.with_layout(egui::Layout::bottom_up(egui::Align::Max), |ui| {
                 if ui.button("quit").clicked() {
                     frame.close();
                 }
-            })
+            });
         });
 
         egui::CentralPanel::default().show(ctx, |ui| {
-            ui.add(CanvasWidget::new(
-                &mut self.primary_canvas,
-                &self.cells,
-                self.diffusion_state.as_ref().map(|x| &x.diffusion_placer),
-                self.legalized_cells.as_ref().map(Vec::as_slice),
-            ));
+            let diffusion = self.diffusion_state.as_ref().map(|x| &x.diffusion_placer);
+            if !self.split_view {
+                ui.add(CanvasWidget::new(
+                    &mut self.primary_canvas,
+                    &self.cells,
+                    diffusion,
+                    self.legalized_cells.as_deref_mut(),
+                    &self.clusters,
+                    &self.config.geometry,
+                ));
+                return;
+            }
+
+            let primary_viewport = self.primary_canvas.viewport();
+            ui.columns(2, |columns| {
+                columns[0].add(CanvasWidget::new(
+                    &mut self.primary_canvas,
+                    &self.cells,
+                    diffusion,
+                    self.legalized_cells.as_deref_mut(),
+                    &self.clusters,
+                    &self.config.geometry,
+                ));
+                columns[1].add(CanvasWidget::new(
+                    &mut self.secondary_canvas,
+                    &self.cells,
+                    diffusion,
+                    self.legalized_cells.as_deref_mut(),
+                    &self.clusters,
+                    &self.config.geometry,
+                ));
+            });
+
+            if self.lock_viewports {
+                // Follow whichever canvas moved this frame
+                if self.primary_canvas.viewport() != primary_viewport {
+                    self.secondary_canvas
+                        .set_viewport(self.primary_canvas.viewport());
+                } else {
+                    self.primary_canvas
+                        .set_viewport(self.secondary_canvas.viewport());
+                }
+            }
         });
+
+        let dropped_cell = self
+            .primary_canvas
+            .take_dropped_cell()
+            .or_else(|| self.secondary_canvas.take_dropped_cell());
+        if let (Some(drag), Some(legalized)) = (dropped_cell, self.legalized_cells.as_mut()) {
+            match legalize_incremental(
+                &self.config.geometry,
+                &self.cells.cells,
+                legalized,
+                drag.cell,
+            ) {
+                Ok(displaced) => self.last_drag_displaced = Some(displaced.len()),
+                Err(e) => {
+                    log::warn!(
+                        "Can't move cell {:?} there: {:?}",
+                        self.cells.metadata[drag.cell].name,
+                        e
+                    );
+                    let cell = &mut legalized[drag.cell];
+                    (cell.x, cell.tier_y, cell.z) = drag.original;
+                }
+            }
+        }
     }
 }
 
-pub(crate) fn run_gui(config: &Config) -> Result<()> {
-    let config = config.clone();
-    let design = load_design(&config)?;
-    let (cells, creator) = load_cells(&config, design)?;
+/// Load a design written by the placer, with its cells legalized where they were placed
+fn load_placed(
+    config: &Config,
+    path: &Path,
+) -> Result<(NetlistHypergraph, Vec<LegalizedCell>, String)> {
+    let design = read_placed_design(path)?;
+
+    let rules = BlockerRules::load(&config.io.blocker_rules_file)?;
+    let mut cell_factory = CellFactory::new(config.io.structure_directory.clone(), rules);
+    let (cells, legalized) = NetlistHypergraph::from_placed_design(&design, &mut cell_factory)
+        .with_context(|| anyhow!("Rebuild netlist of {:?}", path))?;
+
+    Ok((cells, legalized, design.creator))
+}
+
+/// Run the GUI on the input design, or on the placed design at `from_placed` if there is one
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn run_gui(config: &Config, from_placed: Option<&Path>) -> Result<()> {
+    let mut config = config.clone();
+    let (cells, legalized_cells, creator) = match from_placed {
+        Some(path) => {
+            let (cells, legalized, creator) = load_placed(&config, path)?;
+            (cells, Some(legalized), creator)
+        }
+        None => {
+            let design = load_design(&config)?;
+            let rules = BlockerRules::load(&config.io.blocker_rules_file)?;
+            let mut cell_factory = CellFactory::new(config.io.structure_directory.clone(), rules);
+            let (cells, creator) = load_cells(&mut config, design, &mut cell_factory)?;
+            (cells, None, creator)
+        }
+    };
 
     eframe::run_native(
         "mcpnr placement",
         eframe::NativeOptions::default(),
-        Box::new(|cc| Box::new(UIState::new(config, cells, creator, cc))),
+        Box::new(|cc| {
+            let render_state = cc.wgpu_render_state.as_ref().expect("WGPU enabled");
+            Box::new(UIState::new(
+                config,
+                cells,
+                legalized_cells,
+                creator,
+                render_state,
+            ))
+        }),
     );
 
     Ok(())
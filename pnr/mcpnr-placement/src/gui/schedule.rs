@@ -0,0 +1,168 @@
+//! Editor for the placement schedule, so steps can be tuned and rerun without restarting the GUI
+
+use egui::Ui;
+
+use crate::config::{
+    DiffusionConfig, DiffusionMode, GeometryConfig, HardMacroConfig, PlacementSchedule,
+    PlacementStep,
+};
+
+/// What the user asked for in the schedule editor this frame
+pub(super) enum ScheduleAction {
+    /// Nothing to do
+    None,
+    /// Run the schedule starting at this step
+    RunFrom(usize),
+}
+
+/// Short human-readable name of a step
+fn step_name(step: &PlacementStep) -> &'static str {
+    match step {
+        PlacementStep::CenterCells => "Center cells",
+        PlacementStep::Spectral => "Spectral",
+        PlacementStep::UnconstrainedAnalytical { .. } => "Unconstrained analytical",
+        PlacementStep::Diffusion(_) => "Diffusion",
+        PlacementStep::ConstrainedAnalytical { .. } => "Constrained analytical",
+        PlacementStep::Bound2BoundAnalytical { .. } => "Bound2Bound analytical",
+        PlacementStep::Jitter { .. } => "Jitter",
+        PlacementStep::HardMacros(_) => "Hard macros",
+    }
+}
+
+/// One of each kind of step, with the parameters the default schedule uses
+fn step_templates(geometry: &GeometryConfig) -> Vec<PlacementStep> {
+    vec![
+        PlacementStep::CenterCells,
+        PlacementStep::Spectral,
+        PlacementStep::UnconstrainedAnalytical {
+            clique_threshold: 2,
+        },
+        PlacementStep::Diffusion(DiffusionConfig {
+            region_size: 2,
+            mode: DiffusionMode::for_geometry(geometry),
+            iterations: 64,
+            delta_t: 0.1,
+        }),
+        PlacementStep::ConstrainedAnalytical {
+            clique_threshold: 2,
+            iterations: 2,
+        },
+        PlacementStep::Bound2BoundAnalytical { iterations: 4 },
+        PlacementStep::Jitter {
+            magnitude: 1.0,
+            seed: 0,
+        },
+        PlacementStep::HardMacros(HardMacroConfig {
+            footprint_threshold: 64,
+            iterations: 20000,
+        }),
+    ]
+}
+
+/// Widgets for the parameters of a single step
+fn step_parameters_ui(ui: &mut Ui, step: &mut PlacementStep) {
+    match step {
+        PlacementStep::CenterCells | PlacementStep::Spectral => {}
+        PlacementStep::UnconstrainedAnalytical { clique_threshold } => {
+            ui.add(egui::Slider::new(clique_threshold, 1..=8).text("clique threshold"));
+        }
+        PlacementStep::Diffusion(diffusion) => {
+            ui.add(egui::Slider::new(&mut diffusion.region_size, 1..=16).text("region size"));
+            ui.add(egui::Slider::new(&mut diffusion.iterations, 1..=1024).text("iterations"));
+            ui.add(
+                egui::Slider::new(&mut diffusion.delta_t, 0.01..=0.5)
+                    .logarithmic(true)
+                    .text("delta t"),
+            );
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut diffusion.mode, DiffusionMode::Volume, "3D");
+                ui.selectable_value(&mut diffusion.mode, DiffusionMode::PerTier, "Per tier");
+            });
+        }
+        PlacementStep::ConstrainedAnalytical {
+            clique_threshold,
+            iterations,
+        } => {
+            ui.add(egui::Slider::new(clique_threshold, 1..=8).text("clique threshold"));
+            ui.add(egui::Slider::new(iterations, 1..=16).text("iterations"));
+        }
+        PlacementStep::Bound2BoundAnalytical { iterations } => {
+            ui.add(egui::Slider::new(iterations, 1..=16).text("iterations"));
+        }
+        PlacementStep::Jitter { magnitude, seed } => {
+            ui.add(egui::Slider::new(magnitude, 0.0..=8.0).text("magnitude"));
+            ui.horizontal(|ui| {
+                ui.label("seed");
+                ui.add(egui::DragValue::new(seed));
+            });
+        }
+        PlacementStep::HardMacros(hard_macros) => {
+            ui.horizontal(|ui| {
+                ui.label("footprint threshold");
+                ui.add(egui::DragValue::new(&mut hard_macros.footprint_threshold));
+            });
+            ui.add(
+                egui::Slider::new(&mut hard_macros.iterations, 1000..=100000)
+                    .logarithmic(true)
+                    .text("iterations"),
+            );
+        }
+    }
+}
+
+/// Show the schedule as an editable list of steps: each can be edited, moved, removed or run from,
+/// and new steps are added at the end.
+pub(super) fn schedule_ui(
+    ui: &mut Ui,
+    schedule: &mut PlacementSchedule,
+    geometry: &GeometryConfig,
+) -> ScheduleAction {
+    let mut action = ScheduleAction::None;
+    let mut move_up = None;
+    let mut remove = None;
+
+    let steps = &mut schedule.schedule;
+    let step_count = steps.len();
+    for (idx, step) in steps.iter_mut().enumerate() {
+        ui.push_id(idx, |ui| {
+            ui.collapsing(format!("{}. {}", idx, step_name(step)), |ui| {
+                step_parameters_ui(ui, step);
+            });
+            ui.horizontal(|ui| {
+                if ui.add_enabled(idx > 0, egui::Button::new("Up")).clicked() {
+                    move_up = Some(idx);
+                }
+                if ui
+                    .add_enabled(idx + 1 < step_count, egui::Button::new("Down"))
+                    .clicked()
+                {
+                    move_up = Some(idx + 1);
+                }
+                if ui.button("Remove").clicked() {
+                    remove = Some(idx);
+                }
+                if ui.button("Run schedule from here").clicked() {
+                    action = ScheduleAction::RunFrom(idx);
+                }
+            });
+        });
+    }
+
+    if let Some(idx) = move_up {
+        steps.swap(idx - 1, idx);
+    }
+    if let Some(idx) = remove {
+        steps.remove(idx);
+    }
+
+    ui.menu_button("Add step", |ui| {
+        for template in step_templates(geometry) {
+            if ui.button(step_name(&template)).clicked() {
+                steps.push(template);
+                ui.close_menu();
+            }
+        }
+    });
+
+    action
+}
@@ -0,0 +1,225 @@
+//! The web build of the GUI, built and served with `trunk serve` from the `mcpnr-placement`
+//! directory. There's no file system in the browser, so the design is picked with a file dialog
+//! and placed with the techlib of the `synth_mc` Yosys pass, which is bundled in to the binary.
+//! Designs synthesized against other techlibs can be placed by picking their structures as well.
+
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use anyhow::{anyhow, Context, Result};
+use eframe::App;
+use egui_wgpu::RenderState;
+use mcpnr_common::blockers::BlockerRules;
+use mcpnr_common::minecraft_types::STRUCTURE_EXTENSIONS;
+use mcpnr_common::yosys::Design;
+use mcpnr_placement::cli::place_command;
+
+use super::UIState;
+use crate::flow::load_cells;
+use crate::placement_cell::CellFactory;
+use crate::Config;
+
+/// Structures of the techlib the `synth_mc` pass maps designs to
+const BUNDLED_STRUCTURES: [(&str, &[u8]); 5] = [
+    (
+        "dff.nbt",
+        include_bytes!("../../../../yosys-synth_mc/techlib/structures/dff.nbt"),
+    ),
+    (
+        "gate_nand_i2.nbt",
+        include_bytes!("../../../../yosys-synth_mc/techlib/structures/gate_nand_i2.nbt"),
+    ),
+    (
+        "gate_nor_i2.nbt",
+        include_bytes!("../../../../yosys-synth_mc/techlib/structures/gate_nor_i2.nbt"),
+    ),
+    (
+        "gate_not.nbt",
+        include_bytes!("../../../../yosys-synth_mc/techlib/structures/gate_not.nbt"),
+    ),
+    (
+        "gate_xor.nbt",
+        include_bytes!("../../../../yosys-synth_mc/techlib/structures/gate_xor.nbt"),
+    ),
+];
+
+/// A file picked in the browser
+struct PickedFile {
+    name: String,
+    data: Vec<u8>,
+}
+
+enum Picked {
+    Design(PickedFile),
+    Structures(Vec<PickedFile>),
+}
+
+/// Start screen picking the design to place, which hands over to the placer GUI once the design
+/// is loaded
+struct WebApp {
+    render_state: RenderState,
+    /// Contents of the structure files by name, the bundled ones and any picked since
+    structures: HashMap<String, Vec<u8>>,
+    /// The file dialogs finish some frames after they're opened, and send what was picked here
+    picked_sender: Sender<Picked>,
+    picked: Receiver<Picked>,
+    /// Why the last design picked failed to load
+    error: Option<String>,
+    placer: Option<UIState>,
+}
+
+impl WebApp {
+    fn new(render_state: RenderState) -> Self {
+        let (picked_sender, picked) = channel();
+        Self {
+            render_state,
+            structures: BUNDLED_STRUCTURES
+                .iter()
+                .map(|(name, data)| (name.to_string(), data.to_vec()))
+                .collect(),
+            picked_sender,
+            picked,
+            error: None,
+            placer: None,
+        }
+    }
+
+    fn load_design(&self, file: &PickedFile) -> Result<UIState> {
+        let design: Design = serde_json::from_slice(&file.data)
+            .with_context(|| anyhow!("Failed to parse design {:?}", file.name))?;
+
+        // The defaults of the place subcommand, sizing the region to fit whatever was picked
+        let matches = place_command()
+            .try_get_matches_from([
+                "place",
+                "--techlib",
+                "",
+                "--auto-size",
+                file.name.as_str(),
+                "",
+            ])
+            .context("Build default arguments")?;
+        let mut config = Config::from_args(&matches).context("Building config from args")?;
+
+        let mut cell_factory =
+            CellFactory::from_memory(self.structures.clone(), BlockerRules::builtin());
+        let (cells, creator) = load_cells(&mut config, design, &mut cell_factory)
+            .with_context(|| anyhow!("Load cells of {:?}", file.name))?;
+
+        Ok(UIState::new(
+            config,
+            cells,
+            None,
+            creator,
+            &self.render_state,
+        ))
+    }
+
+    fn receive_picked(&mut self) {
+        while let Ok(picked) = self.picked.try_recv() {
+            match picked {
+                Picked::Design(file) => match self.load_design(&file) {
+                    Ok(placer) => self.placer = Some(placer),
+                    Err(e) => self.error = Some(format!("{:?}", e)),
+                },
+                Picked::Structures(files) => {
+                    for file in files {
+                        self.structures.insert(file.name, file.data);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl App for WebApp {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        self.receive_picked();
+        if let Some(ref mut placer) = self.placer {
+            placer.update(ctx, frame);
+            return;
+        }
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading("mcpnr placement");
+            ui.label(
+                "Pick a design synthesized with the synth_mc Yosys pass and written with \
+                 write_json. The structures of its techlib are built in, add the structures of \
+                 any other techlib the design was synthesized against first.",
+            );
+
+            ui.horizontal(|ui| {
+                if ui.button("Open design…").clicked() {
+                    pick_design(ctx, self.picked_sender.clone());
+                }
+                if ui.button("Add structures…").clicked() {
+                    pick_structures(ctx, self.picked_sender.clone());
+                }
+            });
+
+            ui.collapsing(format!("{} structures", self.structures.len()), |ui| {
+                let mut names: Vec<&String> = self.structures.keys().collect();
+                names.sort();
+                for name in names {
+                    ui.label(name);
+                }
+            });
+
+            if let Some(ref error) = self.error {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+        });
+    }
+}
+
+/// Open a file dialog for the design JSON, sending the file once it's picked and read
+fn pick_design(ctx: &egui::Context, sender: Sender<Picked>) {
+    let ctx = ctx.clone();
+    wasm_bindgen_futures::spawn_local(async move {
+        let dialog = rfd::AsyncFileDialog::new().add_filter("Yosys JSON", &["json"]);
+        if let Some(file) = dialog.pick_file().await {
+            let picked = PickedFile {
+                name: file.file_name(),
+                data: file.read().await,
+            };
+            // The receiver lives as long as the page, so this can't fail
+            let _ = sender.send(Picked::Design(picked));
+            ctx.request_repaint();
+        }
+    });
+}
+
+/// Open a file dialog for techlib structures, sending the files once they're picked and read
+fn pick_structures(ctx: &egui::Context, sender: Sender<Picked>) {
+    let ctx = ctx.clone();
+    wasm_bindgen_futures::spawn_local(async move {
+        let dialog = rfd::AsyncFileDialog::new().add_filter("Structures", &STRUCTURE_EXTENSIONS);
+        if let Some(files) = dialog.pick_files().await {
+            let mut picked = Vec::with_capacity(files.len());
+            for file in files {
+                picked.push(PickedFile {
+                    name: file.file_name(),
+                    data: file.read().await,
+                });
+            }
+            let _ = sender.send(Picked::Structures(picked));
+            ctx.request_repaint();
+        }
+    });
+}
+
+/// Run the GUI on the canvas element with the id `canvas_id`
+pub(crate) fn start(canvas_id: &'static str) {
+    wasm_bindgen_futures::spawn_local(async move {
+        eframe::start_web(
+            canvas_id,
+            eframe::WebOptions::default(),
+            Box::new(|cc| {
+                let render_state = cc.wgpu_render_state.clone().expect("WGPU enabled");
+                Box::new(WebApp::new(render_state))
+            }),
+        )
+        .await
+        .expect("Failed to start eframe");
+    });
+}
@@ -0,0 +1,372 @@
+//! Hierarchical placement. Each sub-module of the design is placed on its own, in a region sized
+//! for its cells, and then stands in for a single block (a soft macro) in the module above it.
+//! Once the top module is placed the design is flattened, with every cell where its macro put it.
+//!
+//! This keeps the cells of each sub-module together, and each placement problem is much smaller
+//! than the flattened design.
+
+use anyhow::{anyhow, ensure, Context, Result};
+use mcpnr_common::{
+    io_macros,
+    net_weights::NetWeights,
+    yosys::{Cell, ConstOrSignal, Design, Module, PortDirection, HIERARCHY_SEPARATOR},
+    BLOCKS_PER_TIER, BLOCKS_PER_Z_ROW,
+};
+use std::collections::HashMap;
+
+use crate::{
+    config::{Config, GeometryConfig, IOPlanConfig},
+    core::NetlistHypergraph,
+    flow::{self, legalize_algorithm, place_algorithm},
+    io_planner::{self, IoEdge},
+    placement_cell::{CellFactory, LegalizedCell},
+};
+
+/// Blocks along Z taken up by the rows of switches and lights standing in for the ports of a soft
+/// macro.
+const PORT_ROWS_DEPTH: u32 = 6;
+
+/// Position of a cell in blocks along X and Z, and in tiers along Y
+type CellPosition = (u32, u32, u32);
+
+/// Positions of the cells of a placed soft macro, by their flattened names, relative to the origin
+/// of the macro
+type MacroCells = HashMap<String, CellPosition>;
+
+/// Net weights routing fed back for the nets of each module, by the module's own signal numbers
+type ModuleNetWeights = HashMap<String, HashMap<i64, f32>>;
+
+/// Place every sub-module of `design` as a soft macro, then the top module, and return the
+/// flattened design with every cell in place. With auto-sizing on, the region is sized for the
+/// top module's cells and macros.
+pub fn place_soft_macros(
+    config: &mut Config,
+    design: &Design,
+    cell_factory: &mut CellFactory,
+) -> Result<NetlistHypergraph> {
+    let top = design
+        .modules
+        .get("top")
+        .ok_or_else(|| anyhow!("Failed to locate top module"))?;
+
+    let mut flat = design.flatten(top).context("Flatten design")?;
+    let weights = flow::read_net_weights(config)?;
+    let module_weights = match weights {
+        Some(ref weights) => module_net_weights(design, top, &flat, weights),
+        None => HashMap::new(),
+    };
+
+    let mut macros = HashMap::new();
+    for cell in top.cells.values() {
+        place_macro(
+            config,
+            design,
+            &cell.ty,
+            cell_factory,
+            &module_weights,
+            &mut macros,
+        )?;
+    }
+
+    let _span = tracing::info_span!("place_top").entered();
+    let mut net = NetlistHypergraph::from_module(top.clone(), cell_factory)
+        .context("Extract top level cells")?;
+    weigh_signals(&mut net, module_weights.get("top"));
+    flow::auto_size(config, &net);
+    io_planner::plan_io(&config.io_plan, &config.geometry, &mut net).context("Plan I/O")?;
+    place_algorithm(config, &mut net).context("Place top level")?;
+    let legalized = legalize_algorithm(config, &net)?;
+    let (positions, _) = record_positions(&net, &legalized, &macros, &HashMap::new());
+
+    flow::apply_timing_constraints(config, &mut flat)?;
+    let mut net =
+        NetlistHypergraph::from_module(flat, cell_factory).context("Extract flattened cells")?;
+    if let Some(ref weights) = weights {
+        for signal in net.signals.iter_mut() {
+            signal.weight *= weights.weight(signal.id as i64);
+        }
+    }
+    for (cell, metadata) in net.cells.iter_mut().zip(net.metadata.iter()) {
+        let (x, tier_y, z) = positions
+            .get(&metadata.name)
+            .ok_or_else(|| anyhow!("No position for flattened cell {:?}", metadata.name))?;
+        cell.x = *x as f32;
+        cell.tier_y = *tier_y as f32;
+        cell.z = *z as f32;
+    }
+
+    Ok(net)
+}
+
+/// Place the module `name` as a soft macro, after the sub-modules it instantiates, and teach
+/// `cell_factory` to build cells for it. Does nothing for techlib cells and modules that are
+/// already placed.
+fn place_macro(
+    config: &Config,
+    design: &Design,
+    name: &str,
+    cell_factory: &mut CellFactory,
+    module_weights: &ModuleNetWeights,
+    macros: &mut HashMap<String, MacroCells>,
+) -> Result<()> {
+    let module = match design.sub_module(name) {
+        Some(module) if !macros.contains_key(name) => module,
+        _ => return Ok(()),
+    };
+    ensure!(
+        !module.ports.is_empty(),
+        "Module {:?} has no ports, so there is nothing to place it against",
+        name
+    );
+
+    // Children first, so they can be placed as blocks in this module
+    for cell in module.cells.values() {
+        place_macro(
+            config,
+            design,
+            &cell.ty,
+            cell_factory,
+            module_weights,
+            macros,
+        )?;
+    }
+
+    let _span = tracing::info_span!("place_macro", name).entered();
+    let mut module = module.clone();
+    let port_cells = add_port_cells(&mut module);
+    let mut net = NetlistHypergraph::from_module(module, cell_factory)
+        .with_context(|| anyhow!("Extract cells of {:?}", name))?;
+    weigh_signals(&mut net, module_weights.get(name));
+
+    let macro_config = Config {
+        geometry: macro_geometry(config, &net),
+        io_plan: IOPlanConfig {
+            switch_edge: Some(IoEdge::North),
+            light_edge: Some(IoEdge::South),
+            constraints_file: None,
+        },
+        ..config.clone()
+    };
+    let geometry = &macro_config.geometry;
+    tracing::info!(
+        "Placing {} in {}x{}x{}",
+        name,
+        geometry.size_x,
+        geometry.size_y,
+        geometry.size_z
+    );
+    io_planner::plan_io(&macro_config.io_plan, geometry, &mut net)
+        .with_context(|| anyhow!("Plan ports of {:?}", name))?;
+    place_algorithm(&macro_config, &mut net).with_context(|| anyhow!("Place {:?}", name))?;
+    let legalized =
+        legalize_algorithm(&macro_config, &net).with_context(|| anyhow!("Legalize {:?}", name))?;
+
+    let (cells, pins) = record_positions(&net, &legalized, macros, &port_cells);
+    cell_factory.add_soft_macro(
+        name,
+        [
+            geometry.size_x,
+            geometry.size_y * BLOCKS_PER_TIER,
+            geometry.size_z,
+        ],
+        pins,
+    );
+    macros.insert(name.to_owned(), cells);
+
+    Ok(())
+}
+
+/// Weights of the nets of every module of `design` under `top`, from `weights` for the nets of
+/// `flat`, the design flattened from `top`. Routing only knows the flattened nets, and every
+/// instance of a module shares one placement, so each net of a module takes the heaviest weight
+/// of any instance of it.
+fn module_net_weights(
+    design: &Design,
+    top: &Module,
+    flat: &Module,
+    weights: &NetWeights,
+) -> ModuleNetWeights {
+    // Path of each instance of each module in the flattened names, the top module's being empty
+    let mut instances: HashMap<String, Vec<String>> = HashMap::new();
+    let mut stack = vec![(top, String::new())];
+    while let Some((module, path)) = stack.pop() {
+        for (name, cell) in module.cells.iter() {
+            if let Some(sub_module) = design.sub_module(&cell.ty) {
+                let path = match path.as_str() {
+                    "" => name.clone(),
+                    _ => format!("{}{}{}", path, HIERARCHY_SEPARATOR, name),
+                };
+                instances
+                    .entry(cell.ty.clone())
+                    .or_default()
+                    .push(path.clone());
+                stack.push((sub_module, path));
+            }
+        }
+    }
+    instances.insert("top".to_owned(), vec![String::new()]);
+
+    let mut module_weights = ModuleNetWeights::new();
+    for (ty, paths) in instances.iter() {
+        let module = match ty.as_str() {
+            "top" => top,
+            _ => match design.sub_module(ty) {
+                Some(module) => module,
+                None => continue,
+            },
+        };
+        let local = module_weights.entry(ty.clone()).or_default();
+        for (net_name, net) in module.netnames.iter() {
+            for path in paths.iter() {
+                let flat_name = match path.as_str() {
+                    "" => net_name.clone(),
+                    _ => format!("{}{}{}", path, HIERARCHY_SEPARATOR, net_name),
+                };
+                let flat_bits = match flat.netnames.get(&flat_name) {
+                    Some(flat_net) => &flat_net.bits,
+                    None => continue,
+                };
+                for (bit, flat_bit) in net.bits.iter().zip(flat_bits.iter()) {
+                    if let (ConstOrSignal::Signal(bit), ConstOrSignal::Signal(flat_bit)) =
+                        (bit, flat_bit)
+                    {
+                        let weight = local.entry(*bit).or_insert(1.0);
+                        *weight = weight.max(weights.weight(*flat_bit));
+                    }
+                }
+            }
+        }
+    }
+
+    module_weights
+}
+
+/// Scale the weights of the signals of `net` by `weights`, if there are any for its module
+fn weigh_signals(net: &mut NetlistHypergraph, weights: Option<&HashMap<i64, f32>>) {
+    if let Some(weights) = weights {
+        for signal in net.signals.iter_mut() {
+            if let Some(weight) = weights.get(&(signal.id as i64)) {
+                signal.weight *= weight;
+            }
+        }
+    }
+}
+
+/// Stand in a switch macro for each input port of `module` and a light macro for each output, so
+/// the ports are fixed along the edges of the region like the I/O of a top level design. Returns
+/// the port each new cell stands in for, by cell name.
+fn add_port_cells(module: &mut Module) -> HashMap<String, String> {
+    let mut port_cells = HashMap::new();
+    for (port_name, port) in module.ports.iter() {
+        let (ty, count_parameter, cell_port, cell_direction) = match port.direction {
+            PortDirection::Input => ("MCPNR_SWITCHES", "NSWITCH", "O", PortDirection::Output),
+            PortDirection::Output => ("MCPNR_LIGHTS", "NLIGHT", "I", PortDirection::Input),
+        };
+        let cell_name = format!("$mcpnr_port${}", port_name);
+        module.cells.insert(
+            cell_name.clone(),
+            Cell {
+                hide_name: 1,
+                ty: ty.to_owned(),
+                parameters: [(
+                    count_parameter.to_owned(),
+                    format!("{:032b}", port.bits.len()),
+                )]
+                .into_iter()
+                .collect(),
+                attributes: HashMap::new(),
+                port_directions: [(cell_port.to_owned(), cell_direction)]
+                    .into_iter()
+                    .collect(),
+                connections: [(cell_port.to_owned(), port.bits.clone())]
+                    .into_iter()
+                    .collect(),
+            },
+        );
+        port_cells.insert(cell_name, port_name.clone());
+    }
+
+    port_cells
+}
+
+/// Region to place a soft macro in. It's as tall as the tallest cell, with enough area at the
+/// configured fill for the cells in between the rows of ports, and as close to the configured
+/// aspect ratio as the ports allow.
+fn macro_geometry(config: &Config, net: &NetlistHypergraph) -> GeometryConfig {
+    let target_fill = config.geometry.target_fill;
+    let tiers = net
+        .cells
+        .iter()
+        .map(|cell| cell.s_tier_y as u32)
+        .max()
+        .unwrap_or(1)
+        .max(1);
+    let area = net.cells[..net.mobile_cell_count]
+        .iter()
+        .map(|cell| cell.sx * cell.sz * cell.s_tier_y)
+        .sum::<f32>()
+        / (target_fill * tiers as f32);
+
+    // Leave a gap after each port, so the I/O planner has room to spread them out
+    let port_row_length = |ty: &str| -> u32 {
+        net.cells
+            .iter()
+            .zip(net.metadata.iter())
+            .filter(|(_, metadata)| metadata.ty == ty)
+            .map(|(cell, _)| cell.sx as u32 + 2)
+            .sum()
+    };
+    let size_x = ((area * config.hierarchy.macro_aspect_ratio).sqrt().ceil() as u32)
+        .max(port_row_length("MCPNR_SWITCHES"))
+        .max(port_row_length("MCPNR_LIGHTS"))
+        .max(2)
+        .next_multiple_of(2);
+    let size_z =
+        ((area / size_x as f32).ceil() as u32 + PORT_ROWS_DEPTH).next_multiple_of(BLOCKS_PER_Z_ROW);
+
+    GeometryConfig {
+        size_x,
+        size_y: tiers,
+        size_z,
+        target_fill,
+        // The density map is in the coordinates of the top level region
+        density_map: Vec::new(),
+    }
+}
+
+/// Positions of the leaf cells of a placed module, by flattened name, and the position of the
+/// first pin of each port in `port_cells`, both relative to the origin of the module. Soft macros
+/// in the module are expanded in to their cells.
+fn record_positions(
+    net: &NetlistHypergraph,
+    legalized: &[LegalizedCell],
+    macros: &HashMap<String, MacroCells>,
+    port_cells: &HashMap<String, String>,
+) -> (MacroCells, HashMap<String, [i32; 3]>) {
+    let mut cells = HashMap::new();
+    let mut pins = HashMap::new();
+    for (cell, metadata) in legalized.iter().zip(net.metadata.iter()) {
+        if let Some(port) = port_cells.get(&metadata.name) {
+            let [x, y, z] = io_macros::pin_offset(0);
+            pins.insert(
+                port.clone(),
+                [
+                    (cell.x + x) as i32,
+                    (cell.tier_y * BLOCKS_PER_TIER + y) as i32,
+                    (cell.z + z) as i32,
+                ],
+            );
+        } else if let Some(macro_cells) = macros.get(&metadata.ty) {
+            for (name, (x, tier_y, z)) in macro_cells.iter() {
+                cells.insert(
+                    format!("{}{}{}", metadata.name, HIERARCHY_SEPARATOR, name),
+                    (cell.x + x, cell.tier_y + tier_y, cell.z + z),
+                );
+            }
+        } else {
+            cells.insert(metadata.name.clone(), (cell.x, cell.tier_y, cell.z));
+        }
+    }
+
+    (cells, pins)
+}
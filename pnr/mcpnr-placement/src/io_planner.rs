@@ -0,0 +1,184 @@
+//! I/O planning. Places the switch and light macros before global placement, either distributed
+//! along an edge of the placement region or at positions from a constraints file. The macros are
+//! fixed for the rest of the flow.
+
+use anyhow::{anyhow, ensure, Context, Result};
+use serde::Deserialize;
+use std::{collections::HashMap, path::Path, str::FromStr};
+
+use crate::{
+    config::{GeometryConfig, IOPlanConfig},
+    core::NetlistHypergraph,
+    placement_cell::PlacementCell,
+};
+
+/// An edge of the placement region
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum IoEdge {
+    /// The Z- edge
+    North,
+    /// The Z+ edge
+    South,
+    /// The X+ edge
+    East,
+    /// The X- edge
+    West,
+}
+
+impl FromStr for IoEdge {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "north" => Ok(IoEdge::North),
+            "south" => Ok(IoEdge::South),
+            "east" => Ok(IoEdge::East),
+            "west" => Ok(IoEdge::West),
+            _ => Err(anyhow!(
+                "Unknown edge {:?}, expected one of north, south, east or west",
+                s
+            )),
+        }
+    }
+}
+
+/// A fixed position for an I/O macro from the constraints file. Coordinates are in blocks, except
+/// for `tier`.
+#[derive(Debug, Deserialize)]
+pub struct IoConstraint {
+    x: u32,
+    z: u32,
+    #[serde(default)]
+    tier: u32,
+}
+
+/// Load a constraints file, which is a JSON object mapping cell names to positions:
+///
+/// ```json
+/// { "inputs": { "x": 0, "z": 0 }, "outputs": { "x": 32, "z": 60, "tier": 0 } }
+/// ```
+pub fn load_constraints(path: &Path) -> Result<HashMap<String, IoConstraint>> {
+    let reader = std::fs::File::open(path)
+        .with_context(|| anyhow!("Open I/O constraints file {:?}", path))?;
+    serde_json::from_reader(std::io::BufReader::new(reader))
+        .with_context(|| anyhow!("Parse I/O constraints file {:?}", path))
+}
+
+/// Place the I/O macros in `net` according to `config`. Macros that are neither constrained nor
+/// assigned to an edge keep the position from their POS_X/POS_Y/POS_Z parameters.
+pub fn plan_io(
+    config: &IOPlanConfig,
+    geometry: &GeometryConfig,
+    net: &mut NetlistHypergraph,
+) -> Result<()> {
+    let _span = tracing::info_span!("plan_io").entered();
+
+    let mut constraints = match config.constraints_file {
+        Some(ref path) => load_constraints(path)?,
+        None => HashMap::new(),
+    };
+
+    let mut edges: HashMap<IoEdge, Vec<usize>> = HashMap::new();
+    for (idx, metadata) in net.metadata.iter().enumerate() {
+        let edge = match metadata.ty.as_str() {
+            "MCPNR_SWITCHES" => config.switch_edge,
+            "MCPNR_LIGHTS" => config.light_edge,
+            _ => continue,
+        };
+
+        if let Some(constraint) = constraints.remove(&metadata.name) {
+            let cell = &mut net.cells[idx];
+            cell.x = constraint.x as f32;
+            cell.tier_y = constraint.tier as f32;
+            cell.z = constraint.z as f32;
+        } else if let Some(edge) = edge {
+            edges.entry(edge).or_default().push(idx);
+        }
+    }
+
+    if let Some(name) = constraints.keys().next() {
+        return Err(anyhow!(
+            "I/O constraint for {:?}, which is not a switch or light macro in the design",
+            name
+        ));
+    }
+
+    for (edge, mut cells) in edges.into_iter() {
+        cells.sort_by(|a, b| net.metadata[*a].name.cmp(&net.metadata[*b].name));
+        distribute_along_edge(edge, &cells, geometry, net)
+            .with_context(|| anyhow!("Distribute I/O along the {:?} edge", edge))?;
+    }
+
+    for (cell, metadata) in net.cells.iter().zip(net.metadata.iter()) {
+        if !(metadata.ty == "MCPNR_SWITCHES" || metadata.ty == "MCPNR_LIGHTS") {
+            continue;
+        }
+        ensure!(
+            cell.x + cell.sx <= geometry.size_x as f32
+                && cell.z + cell.sz <= geometry.size_z as f32
+                && cell.tier_y + cell.s_tier_y <= geometry.size_y as f32,
+            "I/O macro {:?} at ({}, {}, {}) does not fit in the placement region",
+            metadata.name,
+            cell.x,
+            cell.tier_y,
+            cell.z
+        );
+    }
+
+    Ok(())
+}
+
+/// Spread the given cells evenly along an edge, in order, on the bottom tier.
+fn distribute_along_edge(
+    edge: IoEdge,
+    cells: &[usize],
+    geometry: &GeometryConfig,
+    net: &mut NetlistHypergraph,
+) -> Result<()> {
+    // Macros can't be rotated, so along the north/south edges they are laid out along X and along
+    // the east/west edges they are stacked along Z.
+    let along_x = matches!(edge, IoEdge::North | IoEdge::South);
+    let edge_length = if along_x {
+        geometry.size_x
+    } else {
+        geometry.size_z
+    };
+    let extent = |cell: &PlacementCell| if along_x { cell.sx } else { cell.sz };
+
+    let total: u32 = cells.iter().map(|&i| extent(&net.cells[i]) as u32).sum();
+    ensure!(
+        total <= edge_length,
+        "{} blocks of I/O do not fit along an edge of {} blocks",
+        total,
+        edge_length
+    );
+    // Keep everything on even coordinates to line up with the routing grid
+    let gap = ((edge_length - total) / (cells.len() as u32 + 1)) & !1;
+
+    let mut position = gap;
+    for &i in cells {
+        let cell = &mut net.cells[i];
+        cell.tier_y = 0.0;
+        match edge {
+            IoEdge::North => {
+                cell.x = position as f32;
+                cell.z = 0.0;
+            }
+            IoEdge::South => {
+                cell.x = position as f32;
+                cell.z = geometry.size_z as f32 - cell.sz;
+            }
+            IoEdge::West => {
+                cell.x = 0.0;
+                cell.z = position as f32;
+            }
+            IoEdge::East => {
+                cell.x = geometry.size_x as f32 - cell.sx;
+                cell.z = position as f32;
+            }
+        }
+        position += extent(cell) as u32 + gap;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,397 @@
+//! Implementation of the Abacus legalizer, described in Spindler et al., "Abacus: Fast
+//! Legalization of Standard Cell Circuits with Minimal Movement" (ISPD 2008).
+//!
+//! Cells are visited in order of their x coordinate. Each one is tentatively appended to every row
+//! that has room for it, where it joins a cluster of abutting cells if it would overlap one, and
+//! each cluster sits at the position minimizing the squared displacement of its cells. The row
+//! giving the smallest displacement for the new cell wins. Compared to TETRIS this spreads dense
+//! regions out around where global placement wanted them, instead of pushing everything right.
+
+use anyhow::Result;
+use itertools::Itertools;
+use mcpnr_common::{BLOCKS_PER_TIER, BLOCKS_PER_Z_ROW};
+
+use crate::{
+    config::GeometryConfig,
+    placement_cell::{LegalizedCell, PlacementCell},
+};
+
+use super::{displacement, row_span, DisplacementStats, Legalizer, RowGrid};
+
+#[derive(Default)]
+pub struct AbacusLegalizer {}
+
+impl AbacusLegalizer {
+    pub fn new() -> Self {
+        AbacusLegalizer {}
+    }
+}
+
+impl Legalizer for AbacusLegalizer {
+    fn legalize(
+        &self,
+        config: &GeometryConfig,
+        cells: &Vec<PlacementCell>,
+    ) -> Result<Vec<LegalizedCell>> {
+        let _span = tracing::info_span!("abacus_legalize").entered();
+
+        // Locked cells keep the position they start with, everything else gets overwritten below
+        let mut output = cells
+            .iter()
+            .map(LegalizedCell::from_placement)
+            .collect_vec();
+        let mut rows = RowGrid::new(config, cells);
+        let mut stats = DisplacementStats::default();
+
+        let (multi_row, single_row): (Vec<usize>, Vec<usize>) = (0..cells.len())
+            .filter(|&i| !cells[i].pos_locked)
+            .sorted_by(|&a, &b| cells[a].x.total_cmp(&cells[b].x))
+            .partition(|&i| row_span(&output[i]) != (1, 1));
+
+        // Abacus only deals with cells that fit in a single row. The few cells that don't are
+        // placed greedily first, and then block the rows they cover like fixed cells.
+        for i in multi_row {
+            match place_multi_row(config, &rows, &cells[i], &output[i]) {
+                Some((displacement, x, tier, z)) => {
+                    output[i].x = x;
+                    output[i].tier_y = tier;
+                    output[i].z = z;
+                    rows.add_blockage(&output[i]);
+                    stats.record(displacement);
+                }
+                None => {
+                    warn_no_room(i, &output[i]);
+                    stats.failed += 1;
+                }
+            }
+        }
+
+        let mut segments = (0..rows.row_count())
+            .map(|row| Segment::for_row(config.size_x, rows.blockages(row)))
+            .collect_vec();
+        for i in single_row {
+            let cell = &cells[i];
+            let width = output[i].sx;
+
+            // (displacement, row, segment)
+            let mut best: Option<(f32, usize, usize)> = None;
+            for z_row in 0..rows.z_rows {
+                for tier in 0..rows.tiers {
+                    let z = z_row * BLOCKS_PER_Z_ROW;
+                    // Moving between rows costs at least this much, so skip rows that can't win
+                    let row_displacement = (tier as f32 - cell.tier_y).abs()
+                        * BLOCKS_PER_TIER as f32
+                        + (z as f32 - cell.z).abs();
+                    if matches!(best, Some((d, ..)) if d <= row_displacement) {
+                        continue;
+                    }
+
+                    let row = rows.row_idx(tier, z_row);
+                    for (s, segment) in segments[row].iter().enumerate() {
+                        if !segment.fits(width) {
+                            continue;
+                        }
+                        let x = segment.trial(cell.x, width);
+                        let displacement = displacement(cell, x, tier, z);
+                        let better = match best {
+                            Some((d, ..)) => displacement < d,
+                            None => true,
+                        };
+                        if better {
+                            best = Some((displacement, row, s));
+                        }
+                    }
+                }
+            }
+
+            match best {
+                Some((_, row, s)) => segments[row][s].insert(i, cell.x, width),
+                None => {
+                    warn_no_room(i, &output[i]);
+                    stats.failed += 1;
+                }
+            }
+        }
+
+        for (row, row_segments) in segments.iter().enumerate() {
+            let tier = row as u32 % rows.tiers;
+            let z = (row as u32 / rows.tiers) * BLOCKS_PER_Z_ROW;
+            for segment in row_segments.iter() {
+                for cluster in segment.clusters.iter() {
+                    let mut x = segment.position(&cluster.shape);
+                    for &(i, width) in cluster.cells.iter() {
+                        output[i].x = x;
+                        output[i].tier_y = tier;
+                        output[i].z = z;
+                        stats.record(displacement(&cells[i], x, tier, z));
+                        x += width;
+                    }
+                }
+            }
+        }
+
+        stats.report();
+        stats.ensure_all_placed()?;
+
+        Ok(output)
+    }
+}
+
+fn warn_no_room(cell: usize, legalized: &LegalizedCell) {
+    tracing::warn!(
+        cell = cell,
+        "No room left for a {}x{}x{} cell",
+        legalized.sx,
+        legalized.s_tier_y,
+        legalized.sz
+    );
+}
+
+/// Find the position with the least displacement for a cell spanning several rows, returning
+/// `(displacement, x, tier, z)`.
+fn place_multi_row(
+    config: &GeometryConfig,
+    rows: &RowGrid,
+    cell: &PlacementCell,
+    legalized: &LegalizedCell,
+) -> Option<(f32, u32, u32, u32)> {
+    let (tiers, z_rows) = row_span(legalized);
+
+    let mut best: Option<(f32, u32, u32, u32)> = None;
+    for z_row in 0..(rows.z_rows + 1).saturating_sub(z_rows) {
+        for tier in 0..(rows.tiers + 1).saturating_sub(tiers) {
+            let covered = rows.covered(tier, tiers, z_row, z_rows);
+            let z = z_row * BLOCKS_PER_Z_ROW;
+            // Blockages can only push the cell right, so also try from the left edge in case the
+            // space to the right of the target is full
+            for start in [legalized.x & !1, 0] {
+                let x = rows.skip_blockages(covered.clone(), start, legalized.sx);
+                if x + legalized.sx > config.size_x {
+                    continue;
+                }
+                let displacement = displacement(cell, x, tier, z);
+                let better = match best {
+                    Some((d, ..)) => displacement < d,
+                    None => true,
+                };
+                if better {
+                    best = Some((displacement, x, tier, z));
+                }
+            }
+        }
+    }
+
+    best
+}
+
+/// The parts of a cluster needed to find its position. With `weight` the total weight of the
+/// cells and `q` the weighted sum of (target x - offset in cluster), the cluster position with the
+/// least squared displacement is `q / weight`.
+#[derive(Clone, Copy, Debug)]
+struct ClusterShape {
+    weight: f32,
+    q: f32,
+    width: u32,
+}
+
+impl ClusterShape {
+    /// A cluster holding only a cell of `width` that wants to be at `x`
+    fn cell(x: f32, width: u32) -> Self {
+        Self {
+            weight: 1.0,
+            q: x,
+            width,
+        }
+    }
+
+    /// The cluster formed by placing `next` directly after `self`
+    fn merge(self, next: ClusterShape) -> Self {
+        Self {
+            weight: self.weight + next.weight,
+            q: self.q + next.q - next.weight * self.width as f32,
+            width: self.width + next.width,
+        }
+    }
+}
+
+/// A run of abutting cells
+struct Cluster {
+    shape: ClusterShape,
+    /// (cell index, width) for each cell in the cluster, from left to right
+    cells: Vec<(usize, u32)>,
+}
+
+/// A stretch of a row between blockages. Its clusters are kept sorted and non-overlapping.
+struct Segment {
+    start: u32,
+    end: u32,
+    used: u32,
+    clusters: Vec<Cluster>,
+}
+
+impl Segment {
+    /// Split a row into the segments between its blockages. Segments are trimmed to even
+    /// coordinates to keep cells aligned to the routing grid.
+    fn for_row(size_x: u32, blockages: &[(u32, u32)]) -> Vec<Segment> {
+        let mut segments = Vec::new();
+        let mut start = 0;
+        for &(blockage_start, blockage_end) in blockages.iter().chain([(size_x, size_x)].iter()) {
+            let segment_start = (start + 1) & !1;
+            let segment_end = blockage_start.min(size_x) & !1;
+            if segment_end > segment_start {
+                segments.push(Segment {
+                    start: segment_start,
+                    end: segment_end,
+                    used: 0,
+                    clusters: Vec::new(),
+                });
+            }
+            start = start.max(blockage_end);
+        }
+
+        segments
+    }
+
+    fn fits(&self, width: u32) -> bool {
+        self.used + width <= self.end - self.start
+    }
+
+    /// Legal position of a cluster in this segment, as close to its optimum as possible
+    fn position(&self, shape: &ClusterShape) -> u32 {
+        let optimal = ((shape.q / shape.weight) / 2.0).round() * 2.0;
+        (optimal.max(self.start as f32) as u32).min(self.end - shape.width)
+    }
+
+    /// Where a cell of `width` that wants to be at `x` would end up if it were inserted, without
+    /// changing anything.
+    fn trial(&self, x: f32, width: u32) -> u32 {
+        let mut shape = ClusterShape::cell(x, width);
+        for previous in self.clusters.iter().rev() {
+            if self.position(&previous.shape) + previous.shape.width <= self.position(&shape) {
+                break;
+            }
+            shape = previous.shape.merge(shape);
+        }
+
+        self.position(&shape) + shape.width - width
+    }
+
+    /// Append a cell to the segment, merging clusters as needed
+    fn insert(&mut self, cell: usize, x: f32, width: u32) {
+        let mut cluster = Cluster {
+            shape: ClusterShape::cell(x, width),
+            cells: vec![(cell, width)],
+        };
+        while let Some(previous) = self.clusters.last() {
+            if self.position(&previous.shape) + previous.shape.width
+                <= self.position(&cluster.shape)
+            {
+                break;
+            }
+            let mut previous = self.clusters.pop().unwrap();
+            previous.shape = previous.shape.merge(cluster.shape);
+            previous.cells.extend(cluster.cells);
+            cluster = previous;
+        }
+
+        self.used += width;
+        self.clusters.push(cluster);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::AbacusLegalizer;
+
+    use crate::{
+        config::GeometryConfig,
+        legalizer::{tetris::TetrisLegalizer, Legalizer},
+        placement_cell::{LegalizedCell, PlacementCell},
+    };
+
+    fn cell(x: f32, sx: f32) -> PlacementCell {
+        PlacementCell {
+            x,
+            tier_y: 0.0,
+            z: 0.0,
+            sx,
+            s_tier_y: 1.0,
+            sz: 6.0,
+            pos_locked: false,
+        }
+    }
+
+    fn single_row() -> GeometryConfig {
+        GeometryConfig {
+            size_x: 32,
+            size_y: 1,
+            size_z: 8,
+            target_fill: 0.8,
+            density_map: Vec::new(),
+        }
+    }
+
+    fn assert_legal(config: &GeometryConfig, legalized: &[LegalizedCell]) {
+        for (i, a) in legalized.iter().enumerate() {
+            assert!(a.x + a.sx <= config.size_x, "{:?} out of bounds", a);
+            for b in legalized[i + 1..].iter() {
+                let overlaps = a.x < b.x + b.sx
+                    && b.x < a.x + a.sx
+                    && a.tier_y == b.tier_y
+                    && a.z / 8 == b.z / 8;
+                assert!(!overlaps, "{:?} overlaps {:?}", a, b);
+            }
+        }
+    }
+
+    fn total_displacement(cells: &[PlacementCell], legalized: &[LegalizedCell]) -> f32 {
+        cells
+            .iter()
+            .zip(legalized.iter())
+            .map(|(c, l)| (c.x - l.x as f32).abs())
+            .sum()
+    }
+
+    #[test]
+    fn dense_row_spreads_around_target() {
+        let config = single_row();
+        let cells = vec![
+            cell(10.0, 4.0),
+            cell(10.0, 4.0),
+            cell(10.0, 4.0),
+            cell(10.0, 4.0),
+        ];
+
+        let legalized = AbacusLegalizer::new().legalize(&config, &cells).unwrap();
+        assert_legal(&config, &legalized);
+
+        let xs: Vec<u32> = legalized.iter().map(|l| l.x).collect();
+        assert_eq!(xs, vec![4, 8, 12, 16]);
+
+        let tetris = TetrisLegalizer::new(8).legalize(&config, &cells).unwrap();
+        assert!(total_displacement(&cells, &legalized) < total_displacement(&cells, &tetris));
+    }
+
+    #[test]
+    fn respects_fixed_cells() {
+        let config = single_row();
+        let mut fixed = cell(12.0, 4.0);
+        fixed.pos_locked = true;
+        let cells = vec![fixed, cell(12.0, 4.0), cell(12.0, 4.0), cell(13.0, 4.0)];
+
+        let legalized = AbacusLegalizer::new().legalize(&config, &cells).unwrap();
+        assert_legal(&config, &legalized);
+        assert_eq!(legalized[0].x, 12);
+    }
+
+    #[test]
+    fn cells_that_dont_fit_are_an_error() {
+        let config = single_row();
+        let mut cells = vec![cell(0.0, 16.0), cell(8.0, 16.0), cell(16.0, 4.0)];
+
+        assert!(AbacusLegalizer::new().legalize(&config, &cells).is_err());
+        cells.pop();
+        let legalized = AbacusLegalizer::new().legalize(&config, &cells).unwrap();
+        assert_legal(&config, &legalized);
+    }
+}
@@ -0,0 +1,287 @@
+//! Incremental legalization, for repairing an already legal placement after a single cell has been
+//! moved by hand. The moved cell stays where it was put, and the cells it now overlaps are moved
+//! to the closest free position, with everything else treated as fixed. Cells placed again inside
+//! a region are legalized the same way, staying inside the region.
+
+use anyhow::{anyhow, bail, Result};
+use itertools::Itertools;
+use mcpnr_common::{BLOCKS_PER_TIER, BLOCKS_PER_Z_ROW};
+
+use crate::{
+    config::GeometryConfig,
+    placement_cell::{LegalizedCell, PlacementCell},
+    placer::region::Region,
+};
+
+use super::{row_span, RowGrid};
+
+/// Resolve the overlaps introduced by moving `legalized[moved]`, returning the indicies of the
+/// cells that had to make room for it. Fails without changing anything if the moved cell is out
+/// of bounds or overlaps a position-locked cell.
+pub fn legalize_incremental(
+    config: &GeometryConfig,
+    cells: &[PlacementCell],
+    legalized: &mut [LegalizedCell],
+    moved: usize,
+) -> Result<Vec<usize>> {
+    let _span = tracing::info_span!("incremental_legalize", moved = moved).entered();
+
+    let moved_cell = &legalized[moved];
+    if moved_cell.x + moved_cell.sx > config.size_x
+        || moved_cell.tier_y + moved_cell.s_tier_y.max(1) > config.size_y
+        || moved_cell.z + moved_cell.sz > config.size_z
+    {
+        bail!("Cell {} is outside of the placement region", moved);
+    }
+
+    let displaced = (0..legalized.len())
+        .filter(|&i| i != moved && overlaps(&legalized[i], moved_cell))
+        .collect_vec();
+    if let Some(&locked) = displaced.iter().find(|&&i| cells[i].pos_locked) {
+        bail!("Cell {} overlaps position-locked cell {}", moved, locked);
+    }
+
+    let mut rows = RowGrid::new(config, &[]);
+    for (i, cell) in legalized.iter().enumerate() {
+        if !displaced.contains(&i) {
+            rows.add_blockage(cell);
+        }
+    }
+
+    let bounds = Region {
+        min_x: 0,
+        min_z: 0,
+        max_x: config.size_x,
+        max_z: config.size_z,
+    };
+    let mut positions = Vec::with_capacity(displaced.len());
+    for &i in displaced.iter() {
+        let cell = &legalized[i];
+        let (x, tier, z) = nearest_free_position(&rows, cell, &bounds)
+            .ok_or_else(|| anyhow!("No room left to move cell {} out of the way", i))?;
+        rows.add_blockage(&LegalizedCell {
+            x,
+            tier_y: tier,
+            z,
+            ..*cell
+        });
+        positions.push((i, x, tier, z));
+    }
+
+    for (i, x, tier, z) in positions {
+        legalized[i].x = x;
+        legalized[i].tier_y = tier;
+        legalized[i].z = z;
+    }
+
+    tracing::info!(displaced = displaced.len(), "Incremental legalization done");
+
+    Ok(displaced)
+}
+
+/// Legalize the cells free to move, which all have to end up inside `region`, around the
+/// position-locked ones, which are already legal and stay where they are. In order along X, each
+/// cell goes to the free position inside the region closest to where placement put it. Fails if a
+/// cell doesn't fit in what's left of the region.
+pub fn legalize_in_region(
+    config: &GeometryConfig,
+    cells: &[PlacementCell],
+    region: &Region,
+) -> Result<Vec<LegalizedCell>> {
+    let _span = tracing::info_span!("region_legalize").entered();
+
+    let mut rows = RowGrid::new(config, cells);
+    let mut legalized = cells
+        .iter()
+        .map(LegalizedCell::from_placement)
+        .collect_vec();
+
+    let mobile = (0..cells.len())
+        .filter(|&i| !cells[i].pos_locked)
+        .sorted_by(|&a, &b| cells[a].x.total_cmp(&cells[b].x))
+        .collect_vec();
+    for &i in mobile.iter() {
+        let cell = &legalized[i];
+        let (x, tier, z) = nearest_free_position(&rows, cell, region).ok_or_else(|| {
+            anyhow!(
+                "No room left in {:?} for cell {}, make the region bigger",
+                region,
+                i
+            )
+        })?;
+        legalized[i].x = x;
+        legalized[i].tier_y = tier;
+        legalized[i].z = z;
+        rows.add_blockage(&legalized[i]);
+    }
+
+    tracing::info!(cells = mobile.len(), "Region legalization done");
+
+    Ok(legalized)
+}
+
+/// Whether two cells overlap, with cells occupying every Z row they touch.
+fn overlaps(a: &LegalizedCell, b: &LegalizedCell) -> bool {
+    let z_rows = |c: &LegalizedCell| {
+        let (_, z_rows) = row_span(c);
+        let first = c.z / BLOCKS_PER_Z_ROW;
+        (first, first + z_rows)
+    };
+    let (a_z, a_z_end) = z_rows(a);
+    let (b_z, b_z_end) = z_rows(b);
+
+    a.x < b.x + b.sx
+        && b.x < a.x + a.sx
+        && a.tier_y < b.tier_y + b.s_tier_y.max(1)
+        && b.tier_y < a.tier_y + a.s_tier_y.max(1)
+        && a_z < b_z_end
+        && b_z < a_z_end
+}
+
+/// Find the free position inside `bounds` closest to where `cell` currently is, returning
+/// `(x, tier, z)`.
+fn nearest_free_position(
+    rows: &RowGrid,
+    cell: &LegalizedCell,
+    bounds: &Region,
+) -> Option<(u32, u32, u32)> {
+    let (tiers, z_rows) = row_span(cell);
+    let distance = |x: u32, tier: u32, z: u32| {
+        x.abs_diff(cell.x) + tier.abs_diff(cell.tier_y) * BLOCKS_PER_TIER + z.abs_diff(cell.z)
+    };
+
+    let mut best: Option<(u32, u32, u32, u32)> = None;
+    for z_row in 0..(rows.z_rows + 1).saturating_sub(z_rows) {
+        for tier in 0..(rows.tiers + 1).saturating_sub(tiers) {
+            let covered = rows.covered(tier, tiers, z_row, z_rows);
+            let z = z_row * BLOCKS_PER_Z_ROW;
+            if z < bounds.min_z || z + cell.sz > bounds.max_z {
+                continue;
+            }
+
+            // Free spots start either where the cell is now, or directly after a blockage. The
+            // latter covers the gaps to the left of the cell.
+            let starts = covered
+                .clone()
+                .flat_map(|row| rows.blockages(row).iter().map(|&(_, end)| (end + 1) & !1))
+                .chain([cell.x & !1, (bounds.min_x + 1) & !1]);
+            for start in starts {
+                let x = rows.skip_blockages(covered.clone(), start, cell.sx);
+                if x < bounds.min_x || x + cell.sx > bounds.max_x {
+                    continue;
+                }
+                let d = distance(x, tier, z);
+                let better = match best {
+                    Some((best_d, ..)) => d < best_d,
+                    None => true,
+                };
+                if better {
+                    best = Some((d, x, tier, z));
+                }
+            }
+        }
+    }
+
+    best.map(|(_, x, tier, z)| (x, tier, z))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{legalize_in_region, legalize_incremental};
+
+    use crate::{
+        config::GeometryConfig,
+        placement_cell::{LegalizedCell, PlacementCell},
+        placer::region::Region,
+    };
+
+    fn cell(x: u32, z: u32, pos_locked: bool) -> (PlacementCell, LegalizedCell) {
+        (
+            PlacementCell {
+                x: x as f32,
+                tier_y: 0.0,
+                z: z as f32,
+                sx: 4.0,
+                s_tier_y: 1.0,
+                sz: 6.0,
+                pos_locked,
+            },
+            LegalizedCell {
+                x,
+                tier_y: 0,
+                z,
+                sx: 4,
+                s_tier_y: 1,
+                sz: 6,
+            },
+        )
+    }
+
+    fn config() -> GeometryConfig {
+        GeometryConfig {
+            size_x: 16,
+            size_y: 1,
+            size_z: 16,
+            target_fill: 0.8,
+            density_map: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn moved_cell_pushes_overlapping_cell_aside() {
+        let (cells, mut legalized): (Vec<_>, Vec<_>) =
+            vec![cell(0, 0, false), cell(4, 0, false), cell(8, 0, false)]
+                .into_iter()
+                .unzip();
+
+        // Drop cell 0 on top of cell 1
+        legalized[0].x = 4;
+        let displaced = legalize_incremental(&config(), &cells, &mut legalized, 0).unwrap();
+
+        assert_eq!(displaced, vec![1]);
+        assert_eq!((legalized[0].x, legalized[0].z), (4, 0));
+        // The gap the moved cell left behind is the closest free spot
+        assert_eq!((legalized[1].x, legalized[1].z), (0, 0));
+        assert_eq!((legalized[2].x, legalized[2].z), (8, 0));
+    }
+
+    #[test]
+    fn locked_cells_are_not_moved() {
+        let (cells, mut legalized): (Vec<_>, Vec<_>) = vec![cell(0, 0, false), cell(8, 8, true)]
+            .into_iter()
+            .unzip();
+
+        legalized[0].x = 8;
+        legalized[0].z = 8;
+        assert!(legalize_incremental(&config(), &cells, &mut legalized, 0).is_err());
+        assert_eq!((legalized[1].x, legalized[1].z), (8, 8));
+    }
+
+    #[test]
+    fn region_cells_stay_inside_the_region() {
+        let region: Region = "4,0,12,16".parse().unwrap();
+        // Both free cells were placed on top of each other, next to a fixed cell in the region
+        let cells = vec![
+            cell(4, 0, false).0,
+            cell(4, 0, false).0,
+            cell(8, 8, true).0,
+            cell(12, 0, true).0,
+        ];
+
+        let legalized = legalize_in_region(&config(), &cells, &region).unwrap();
+
+        assert_eq!((legalized[0].x, legalized[0].z), (4, 0));
+        // The spot to the right in the same row is free, but past the edge of the region
+        assert_eq!((legalized[1].x, legalized[1].z), (8, 0));
+        assert_eq!((legalized[2].x, legalized[2].z), (8, 8));
+        assert_eq!((legalized[3].x, legalized[3].z), (12, 0));
+    }
+
+    #[test]
+    fn full_regions_are_an_error() {
+        let region: Region = "4,0,8,8".parse().unwrap();
+        let cells = vec![cell(4, 0, false).0, cell(4, 0, false).0];
+
+        assert!(legalize_in_region(&config(), &cells, &region).is_err());
+    }
+}
@@ -1,13 +1,186 @@
+use anyhow::{ensure, Result};
+use mcpnr_common::{BLOCKS_PER_TIER, BLOCKS_PER_Z_ROW};
+
 use crate::{
-    config::GeometryConfig,
+    config::{GeometryConfig, LegalizerConfig},
     placement_cell::{LegalizedCell, PlacementCell},
 };
 
+pub mod abacus;
+pub mod incremental;
 pub mod tetris;
 
 /// Abstract interface over legalizers. Takes in a collection of [PlacementCell]s and converts them
 /// to [LegalizedCell]s.
-pub(crate) trait Legalizer {
-    /// Legalize the provided cells.
-    fn legalize(&self, config: &GeometryConfig, cells: &Vec<PlacementCell>) -> Vec<LegalizedCell>;
+pub trait Legalizer {
+    /// Legalize the provided cells. Fails if there's no room left for some of them.
+    fn legalize(
+        &self,
+        config: &GeometryConfig,
+        cells: &Vec<PlacementCell>,
+    ) -> Result<Vec<LegalizedCell>>;
+}
+
+/// Construct the legalizer selected by the configuration
+pub fn from_config(config: &LegalizerConfig) -> Box<dyn Legalizer> {
+    match *config {
+        LegalizerConfig::Tetris { left_limit } => {
+            Box::new(tetris::TetrisLegalizer::new(left_limit))
+        }
+        LegalizerConfig::Abacus => Box::new(abacus::AbacusLegalizer::new()),
+    }
+}
+
+/// The rows cells are legalized in to. A row is 1 tier high and [`BLOCKS_PER_Z_ROW`] blocks deep,
+/// and spans the placement region along X.
+pub(super) struct RowGrid {
+    pub tiers: u32,
+    pub z_rows: u32,
+    /// (start, end) x ranges covered by fixed cells, for each row, sorted by start
+    blockages: Vec<Vec<(u32, u32)>>,
+}
+
+impl RowGrid {
+    /// Build the rows for the region, with the position-locked `cells` blocking the rows they sit
+    /// in.
+    pub fn new(config: &GeometryConfig, cells: &[PlacementCell]) -> Self {
+        let tiers = config.size_y;
+        let z_rows = config.size_z / BLOCKS_PER_Z_ROW;
+
+        let mut rows = Self {
+            tiers,
+            z_rows,
+            blockages: vec![Vec::new(); (tiers * z_rows) as usize],
+        };
+        for cell in cells.iter().filter(|cell| cell.pos_locked) {
+            rows.add_blockage(&LegalizedCell::from_placement(cell));
+        }
+
+        rows
+    }
+
+    pub fn row_count(&self) -> usize {
+        self.blockages.len()
+    }
+
+    /// Takes a (tier, row coordinate) pair for (y,z) and converts it to the row index
+    pub fn row_idx(&self, tier: u32, z_row: u32) -> usize {
+        (tier + z_row * self.tiers) as usize
+    }
+
+    /// Indicies of the rows covered by a rectangle of `tiers` x `z_rows` rows
+    pub fn covered(
+        &self,
+        tier: u32,
+        tiers: u32,
+        z_row: u32,
+        z_rows: u32,
+    ) -> impl Iterator<Item = usize> + Clone + '_ {
+        (z_row..z_row + z_rows)
+            .flat_map(move |z| (tier..tier + tiers).map(move |t| self.row_idx(t, z)))
+    }
+
+    /// Blocked (start, end) x ranges in a row, sorted by start
+    pub fn blockages(&self, row: usize) -> &[(u32, u32)] {
+        &self.blockages[row]
+    }
+
+    /// Mark the rows a cell overlaps as blocked where it sits
+    pub fn add_blockage(&mut self, cell: &LegalizedCell) {
+        let first_z_row = cell.z / BLOCKS_PER_Z_ROW;
+        let last_z_row = (cell.z + cell.sz.max(1) - 1) / BLOCKS_PER_Z_ROW;
+        for z_row in first_z_row..=last_z_row.min(self.z_rows.saturating_sub(1)) {
+            for tier in cell.tier_y..(cell.tier_y + cell.s_tier_y).min(self.tiers) {
+                let row = self.row_idx(tier, z_row);
+                let blockages = &mut self.blockages[row];
+                let pos = blockages.partition_point(|&(start, _)| start <= cell.x);
+                blockages.insert(pos, (cell.x, cell.x + cell.sx));
+            }
+        }
+    }
+
+    /// Move `x` right until a cell of width `sx` starting there overlaps none of the blockages in
+    /// the given rows
+    pub fn skip_blockages(
+        &self,
+        rows: impl Iterator<Item = usize> + Clone,
+        mut x: u32,
+        sx: u32,
+    ) -> u32 {
+        loop {
+            let start_x = x;
+            for row in rows.clone() {
+                for &(start, end) in self.blockages[row].iter() {
+                    if x < end && start < x + sx {
+                        x = end;
+                    }
+                }
+            }
+            if x == start_x {
+                return x;
+            }
+        }
+    }
+}
+
+/// Number of (tiers, Z rows) a cell spans
+pub(super) fn row_span(cell: &LegalizedCell) -> (u32, u32) {
+    (
+        cell.s_tier_y.max(1),
+        cell.sz.max(1).div_ceil(BLOCKS_PER_Z_ROW),
+    )
+}
+
+/// Manhattan distance in blocks between a cell's global placement and a legal position
+pub(super) fn displacement(cell: &PlacementCell, x: u32, tier: u32, z: u32) -> f32 {
+    (x as f32 - cell.x).abs()
+        + (tier as f32 - cell.tier_y).abs() * BLOCKS_PER_TIER as f32
+        + (z as f32 - cell.z).abs()
+}
+
+/// Running statistics on how far a legalizer moved cells from their global placement, in blocks
+#[derive(Default)]
+pub(super) struct DisplacementStats {
+    pub count: usize,
+    pub failed: usize,
+    total: f32,
+    max: f32,
+}
+
+impl DisplacementStats {
+    pub fn record(&mut self, displacement: f32) {
+        self.count += 1;
+        self.total += displacement;
+        self.max = self.max.max(displacement);
+    }
+
+    pub fn mean(&self) -> f32 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.total / self.count as f32
+        }
+    }
+
+    /// Fail if any cell couldn't be legalized
+    pub fn ensure_all_placed(&self) -> Result<()> {
+        ensure!(
+            self.failed == 0,
+            "No room left for {} of the {} cells to legalize, the placement region is too full",
+            self.failed,
+            self.count + self.failed
+        );
+        Ok(())
+    }
+
+    /// Emit the statistics as a tracing event
+    pub fn report(&self) {
+        tracing::info!(
+            cells = self.count,
+            failed = self.failed,
+            max_displacement = self.max,
+            avg_displacement = self.mean(),
+            "Legalization done"
+        );
+    }
 }
@@ -6,16 +6,16 @@ use std::{
     mem::{ManuallyDrop, MaybeUninit},
 };
 
+use anyhow::Result;
 use itertools::Itertools;
-use mcpnr_common::{BLOCKS_PER_Z_ROW, BLOCKS_PER_TIER};
-use nalgebra::Vector3;
+use mcpnr_common::{BLOCKS_PER_TIER, BLOCKS_PER_Z_ROW};
 
 use crate::{
     config::GeometryConfig,
     placement_cell::{LegalizedCell, PlacementCell},
 };
 
-use super::Legalizer;
+use super::{displacement, row_span, DisplacementStats, Legalizer, RowGrid};
 
 pub struct TetrisLegalizer {
     /// The "left limit" from the TETRIS paper. Represents how far left of the original X location
@@ -29,8 +29,20 @@ impl TetrisLegalizer {
     }
 }
 
+/// Cost of moving a cell from its global placement to a legal position. Moving to another Z row
+/// costs as much per block as moving to another tier does, so cells slide along their row first.
+fn cost(cell: &PlacementCell, x: u32, tier: u32, z: u32) -> f32 {
+    (x as f32 - cell.x).abs()
+        + (tier as f32 - cell.tier_y).abs() * BLOCKS_PER_TIER as f32
+        + (z as f32 - cell.z).abs() * BLOCKS_PER_Z_ROW as f32
+}
+
 impl Legalizer for TetrisLegalizer {
-    fn legalize(&self, config: &GeometryConfig, cells: &Vec<PlacementCell>) -> Vec<LegalizedCell> {
+    fn legalize(
+        &self,
+        config: &GeometryConfig,
+        cells: &Vec<PlacementCell>,
+    ) -> Result<Vec<LegalizedCell>> {
         let _span = tracing::info_span!("tetris_legalize").entered();
         // !!!! INTERNAL SAFETY REQUIREMENTS !!!!
         // We build the output vector out of order, which means we allocate the whole thing as
@@ -69,31 +81,27 @@ impl Legalizer for TetrisLegalizer {
         // The heart of the TETRIS algorithm is maintaining a sense of the "left most" (minimum X
         // coordinate) for every possible (y,z) tuple that won't collide with something already
         // legalized. The brute-force way to do this is to have 1 bit per location in the block
-        // grid, but we make a few optimizations.
-        //   1) we reduce the (y,z) space to be represented in terms of rows of 6 blocks and 1-high
-        //      layers (as that's the size of most of the cells)
-        //   2) we store only the leftmost free x coordinate for each of these tuples. This can
-        //      break horribly if there are fixed position cells that are located on the +x edge
-        //      but we ignore that possibility for now (and warn about it in CellFactory::build_*).
+        // grid, but we instead reduce the (y,z) space to rows that are 1 tier high and
+        // BLOCKS_PER_Z_ROW blocks deep (as that's the size of most of the cells). Each row keeps
+        // the leftmost free x coordinate, plus the x ranges blocked by fixed position cells (the
+        // I/O macros), which can be anywhere in a row.
+        //
+        // Cells that are more than 1 tier high or BLOCKS_PER_Z_ROW deep occupy a rectangle of
+        // rows, and can only go where every one of those rows is free.
         //
         // For each cell then, we:
-        //  - immediately lock it and update the min_x[(y,z)] if it's pos-locked
-        //  - for each row(y,z)
-        //      - compute the "cost" if we were to put the cell there, taking left_limit in to
-        //        account
-        //      - if this cost is better than any we've seen before, keep it in mind
-        //  - select the best found row and update the min_x for that row
+        //  - leave it where it is if it's pos-locked
+        //  - for each rectangle of rows the cell could occupy
+        //      - find the leftmost x where it fits in all of them, taking left_limit and the
+        //        blockages in to account
+        //      - compute the cost of moving the cell there from its global placement, and keep the
+        //        cheapest one in mind
+        //  - move the cell to the best found rectangle and update the rows it covers
         //
-        let max_y = config.size_y;
-        // Takes a (layer, row coordinate) pair for (y,z) and converts it to the row index
-        let row_idx = |y: u32, z: u32| {
-            (y + z * max_y) as usize
-        };
-        let mut min_x = Vec::with_capacity((max_y * config.size_z / BLOCKS_PER_Z_ROW) as usize);
-        for _ in 0..min_x.capacity() {
-            min_x.push(0u32);
-        }
+        let rows = RowGrid::new(config, cells);
+        let mut min_x = vec![0u32; rows.row_count()];
 
+        let mut stats = DisplacementStats::default();
         for cell_i in cell_order {
             let cell = &cells[cell_i];
             let mut legalized = LegalizedCell::from_placement(cell);
@@ -101,44 +109,67 @@ impl Legalizer for TetrisLegalizer {
             // locked cells need to be where  they say they are, regardless of what else we're
             // doing to them. Other cells get properly legalized.
             if !cell.pos_locked {
-                let mut min_cost = f32::INFINITY;
-                let mut min_cost_pos = Vector3::new(0u32, 0, 0);
-                for (i, &x) in min_x.iter().enumerate() {
-                    let x = if legalized.x > self.left_limit && x < legalized.x - self.left_limit {
-                        legalized.x
-                    } else {
-                        x
-                    };
-                    let y = (i as u32) % max_y;
-                    let z_row = (i as u32) / max_y;
+                let (tiers, z_rows) = row_span(&legalized);
 
-                    let min_pos = Vector3::new(x as f32, y as f32, (z_row * BLOCKS_PER_Z_ROW) as f32);
-                    let cell_pos = Vector3::new(cell.x, cell.tier_y, cell.z);
-                    let delta = (min_pos - cell_pos).abs();
+                // (cost, x, tier, z)
+                let mut best: Option<(f32, u32, u32, u32)> = None;
+                for z_row in 0..(rows.z_rows + 1).saturating_sub(z_rows) {
+                    for tier in 0..(rows.tiers + 1).saturating_sub(tiers) {
+                        let covered = rows.covered(tier, tiers, z_row, z_rows);
 
-                    let cost = delta.x + delta.y * BLOCKS_PER_TIER as f32 + delta.z * BLOCKS_PER_Z_ROW as f32;
+                        let frontier = covered.clone().map(|row| min_x[row]).max().unwrap_or(0);
+                        let x = if frontier + self.left_limit < legalized.x {
+                            legalized.x
+                        } else {
+                            frontier
+                        };
+                        let x = rows.skip_blockages(covered, x, legalized.sx);
+                        if x + legalized.sx > config.size_x {
+                            continue;
+                        }
 
-                    if cost < min_cost && x + legalized.sx <= config.size_x {
-                        min_cost = cost;
-                        min_cost_pos = Vector3::new(x, y, z_row * BLOCKS_PER_Z_ROW);
+                        let z = z_row * BLOCKS_PER_Z_ROW;
+                        let cost = cost(cell, x, tier, z);
+                        let better = match best {
+                            Some((c, ..)) => cost < c,
+                            None => true,
+                        };
+                        if better {
+                            best = Some((cost, x, tier, z));
+                        }
                     }
                 }
 
-                legalized.x = min_cost_pos.x;
-                legalized.tier_y = min_cost_pos.y;
-                legalized.z = min_cost_pos.z;
+                match best {
+                    Some((_, x, tier, z)) => {
+                        legalized.x = x;
+                        legalized.tier_y = tier;
+                        legalized.z = z;
+                        for row in rows.covered(tier, tiers, z / BLOCKS_PER_Z_ROW, z_rows) {
+                            min_x[row] = x + legalized.sx;
+                        }
+                        stats.record(displacement(cell, x, tier, z));
+                    }
+                    None => {
+                        tracing::warn!(
+                            cell = cell_i,
+                            "No room left for a {}x{}x{} cell",
+                            legalized.sx,
+                            legalized.s_tier_y,
+                            legalized.sz
+                        );
+                        stats.failed += 1;
+                    }
+                }
             }
 
-            let row_x = legalized.x;
-            let row_y = legalized.tier_y;
-            let row_z = legalized.z / BLOCKS_PER_Z_ROW;
-            min_x[row_idx(row_y, row_z)] = row_x + legalized.sx;
-
             // See INTERNAL SAFETY REQUIREMENTS comment above
             output[cell_i].write(legalized);
         }
 
-        {
+        stats.report();
+
+        let output = {
             let mut output = ManuallyDrop::new(output);
             let length = output.len();
             let capacity = output.capacity();
@@ -148,6 +179,106 @@ impl Legalizer for TetrisLegalizer {
             //
             // Do not drop the original "output" because we've rebuilt it here
             unsafe { Vec::from_raw_parts(std::mem::transmute(data), length, capacity) }
+        };
+        stats.ensure_all_placed()?;
+
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TetrisLegalizer;
+
+    use crate::{
+        config::GeometryConfig,
+        legalizer::Legalizer,
+        placement_cell::{LegalizedCell, PlacementCell},
+    };
+
+    fn cell(x: f32, tier_y: f32, z: f32, sx: f32, s_tier_y: f32, sz: f32) -> PlacementCell {
+        PlacementCell {
+            x,
+            tier_y,
+            z,
+            sx,
+            s_tier_y,
+            sz,
+            pos_locked: false,
+        }
+    }
+
+    fn overlaps(a: &LegalizedCell, b: &LegalizedCell) -> bool {
+        a.x < b.x + b.sx
+            && b.x < a.x + a.sx
+            && a.tier_y < b.tier_y + b.s_tier_y
+            && b.tier_y < a.tier_y + a.s_tier_y
+            && a.z < b.z + b.sz
+            && b.z < a.z + a.sz
+    }
+
+    #[test]
+    fn no_overlaps_with_tall_and_deep_cells() {
+        let config = GeometryConfig {
+            size_x: 32,
+            size_y: 2,
+            size_z: 32,
+            target_fill: 0.8,
+            density_map: Vec::new(),
+        };
+        let mut cells = vec![
+            // Spans both tiers
+            cell(4.0, 0.0, 0.0, 4.0, 2.0, 6.0),
+            // Spans two Z rows
+            cell(4.0, 0.0, 4.0, 4.0, 1.0, 12.0),
+            cell(5.0, 1.0, 1.0, 4.0, 1.0, 6.0),
+            cell(6.0, 0.0, 0.0, 4.0, 1.0, 6.0),
+            cell(0.0, 0.0, 0.0, 2.0, 1.0, 4.0),
+        ];
+        let mut fixed = cell(8.0, 0.0, 0.0, 4.0, 1.0, 4.0);
+        fixed.pos_locked = true;
+        cells.push(fixed);
+
+        let legalized = TetrisLegalizer::new(8).legalize(&config, &cells).unwrap();
+
+        for (i, a) in legalized.iter().enumerate() {
+            assert!(a.x + a.sx <= config.size_x, "{:?} out of bounds", a);
+            assert!(
+                a.tier_y + a.s_tier_y <= config.size_y,
+                "{:?} out of bounds",
+                a
+            );
+            assert!(a.z + a.sz <= config.size_z, "{:?} out of bounds", a);
+            for b in legalized[i + 1..].iter() {
+                assert!(!overlaps(a, b), "{:?} overlaps {:?}", a, b);
+            }
         }
+
+        // The fixed cell stays put
+        assert_eq!(
+            (legalized[5].x, legalized[5].tier_y, legalized[5].z),
+            (8, 0, 0)
+        );
+    }
+
+    #[test]
+    fn cells_without_room_are_an_error() {
+        // One row, with only room for one of the cells
+        let config = GeometryConfig {
+            size_x: 8,
+            size_y: 1,
+            size_z: 8,
+            target_fill: 0.8,
+            density_map: Vec::new(),
+        };
+        let mut cells = vec![
+            cell(0.0, 0.0, 0.0, 6.0, 1.0, 6.0),
+            cell(2.0, 0.0, 0.0, 6.0, 1.0, 6.0),
+        ];
+
+        let legalizer = TetrisLegalizer::new(8);
+        assert!(legalizer.legalize(&config, &cells).is_err());
+        cells.pop();
+        assert!(legalizer.legalize(&config, &cells).is_ok());
     }
 }
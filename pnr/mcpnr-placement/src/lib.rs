@@ -0,0 +1,15 @@
+//! Placement algorithms for the MCPNR flow. The `mcpnr-placement` binary drives them from the
+//! command line, they're a library as well so the benchmarks and the Python bindings can get at
+//! them.
+
+mod blockage_map;
+pub mod cli;
+pub mod config;
+pub mod core;
+pub mod doctor;
+pub mod flow;
+mod hierarchy;
+pub mod io_planner;
+pub mod legalizer;
+pub mod placement_cell;
+pub mod placer;
@@ -1,291 +1,281 @@
-use anyhow::{anyhow, Context, Result};
+// The web build only has the GUI, so most of the command line flow goes unused there
+#![cfg_attr(target_arch = "wasm32", allow(dead_code, unused_imports))]
+
+use anyhow::{Context, Result};
 use clap::{Arg, Command};
-use config::PlacementStep;
-use legalizer::{tetris::TetrisLegalizer, Legalizer};
-use mcpnr_common::prost::Message;
-use mcpnr_common::protos::mcpnr::PlacedDesign;
-use mcpnr_common::yosys::Design;
-use nalgebra::Vector3;
-use placement_cell::{CellFactory, LegalizedCell};
-use placer::analytical::{
-    AnchoredByNet, Clique, DecompositionStrategy, MoveableStar, ThresholdCrossover,
-};
-use placer::diffusion::DiffusionPlacer;
-use tracing::{debug_span, info_span};
+use mcpnr_common::results;
+use mcpnr_placement::cli::{add_common_args, add_io_args, place_command};
+use mcpnr_placement::{config, core, doctor, flow, legalizer, placement_cell, placer};
+use std::path::Path;
+#[cfg(not(target_arch = "wasm32"))]
+use tracing_chrome::{ChromeLayerBuilder, FlushGuard};
+#[cfg(not(target_arch = "wasm32"))]
 use tracing_subscriber::fmt::format::FmtSpan;
 
 use crate::config::Config;
-use crate::core::NetlistHypergraph;
 
-mod config;
-mod core;
+mod diff;
 mod gui;
-pub mod legalizer;
-mod placement_cell;
-pub mod placer;
+mod sweep;
+
+fn run_report(matches: &clap::ArgMatches) -> Result<()> {
+    let mut runs = results::read_runs(Path::new(matches.value_of_os("RESULTS").unwrap()))?;
+    if let Some(design) = matches.value_of("DESIGN") {
+        runs.retain(|run| run.design == design);
+    }
+    let last = matches
+        .value_of("LAST")
+        .unwrap()
+        .parse()
+        .context("Parse LAST")?;
+    results::write_report(&mut std::io::stdout().lock(), &runs, last)
+        .context("Write results report")
+}
+
+/// Log spans and events filtered by RUST_LOG, and record every span to `profile` if there is one.
+/// The trace is only complete once the returned guard is dropped.
+#[cfg(not(target_arch = "wasm32"))]
+fn init_tracing(profile: Option<&Path>) -> Option<FlushGuard> {
+    use tracing_subscriber::{filter::filter_fn, prelude::*, EnvFilter};
+
+    let filter_layer = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new("info"))
+        .expect("Failed to initialize tracing env filter");
+    let fmt_layer = tracing_subscriber::fmt::layer()
+        .with_span_events(FmtSpan::NEW | FmtSpan::CLOSE)
+        .compact()
+        .with_filter(filter_layer);
+
+    // Only spans go in the trace, the time is spent inside them and the events are just noise
+    let (chrome_layer, guard) = match profile {
+        Some(path) => {
+            let (layer, guard) = ChromeLayerBuilder::new()
+                .file(path)
+                .include_args(true)
+                .build();
+            let layer = layer.with_filter(filter_fn(|meta| meta.is_span()));
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+
+    tracing_subscriber::registry()
+        .with(fmt_layer)
+        .with(chrome_layer)
+        .init();
 
-fn add_common_args<'help>(command: Command<'help>) -> Command<'help> {
-    command
+    guard
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn main() -> Result<()> {
+    let gui_command = add_io_args(
+        add_common_args(
+            Command::new("gui").before_help("Run a GUI for interactive debugging of the placer"),
+        ),
+        true,
+    )
+    .arg(
+        Arg::new("FROM_PLACED")
+            .long("from-placed")
+            .value_name("FILE")
+            .allow_invalid_utf8(true)
+            .help("Load a design the placer already placed instead of the input design")
+            .long_help("
+Load a placed design, as written by the place subcommand, instead of the input design. Files ending in .json are read as the JSON written with --output-json. The cells start out where that placement put them and legalized, so the placement can be inspected and refined without running the schedule again.
+"),
+    )
+    .mut_arg("INPUT", |arg| arg.required_unless_present("FROM_PLACED"))
+    .mut_arg("OUTPUT", |arg| arg.required_unless_present("FROM_PLACED"));
+    let place_command = place_command();
+    let replace_command = add_io_args(
+        add_common_args(Command::new("replace").before_help(
+            "Place the cells in one region of a placed design again, keeping the rest where they are",
+        )),
+        true,
+    )
+    .arg(
+        Arg::new("REGION")
+            .long("region")
+            .value_name("X0,Z0,X1,Z1")
+            .required(true)
+            .help("Box of blocks whose cells are placed again, on every tier")
+            .long_help("
+Box of blocks whose cells are placed again, on every tier, from the first corner up to but not including the second. Cells not wholly inside it are fixed where they are and the placement schedule runs on the ones inside, so they settle around their fixed neighbours, and are legalized inside the region, which has to have room for them. INPUT is a placed design here, as written by the place subcommand.
+"),
+    );
+    let doctor_command = add_io_args(
+        add_common_args(Command::new("doctor").before_help(
+            "Check the technology library, configuration and (optionally) input design for problems",
+        )),
+        false,
+    );
+    let diff_command = Command::new("diff")
+        .before_help("Compare two placed designs of the same netlist")
+        .arg(
+            Arg::new("BEFORE")
+                .help("Placed design to compare against")
+                .index(1)
+                .allow_invalid_utf8(true)
+                .required(true),
+        )
+        .arg(
+            Arg::new("AFTER")
+                .help("Placed design to compare")
+                .index(2)
+                .allow_invalid_utf8(true)
+                .required(true),
+        )
         .arg(
             Arg::new("TECHLIB")
                 .long("techlib")
                 .value_name("TECHLIB")
                 .allow_invalid_utf8(true)
                 .required(true)
-                .help("Specify the path to the technology library")
-                .long_help("
-The technology library is expected to be a folder, containing a folder named \"structures\" with a minecraft NBT structure format for each standard cell.
-"),
+                .help("Technology library both designs were placed with"),
         )
         .arg(
-            Arg::new("SIZE_X")
-                .long("size-x")
-                .value_name("SIZE_X")
-                .default_value("192"),
+            Arg::new("TOP")
+                .long("top")
+                .value_name("CELLS")
+                .default_value("20")
+                .help("Number of the most moved cells to list"),
+        );
+    let report_command = Command::new("report")
+        .before_help(
+            "Print the runs logged to a results file with --results, and how their results changed",
         )
         .arg(
-            Arg::new("SIZE_Y")
-                .long("size-y")
-                .value_name("SIZE_Y")
-                .default_value("4"),
+            Arg::new("RESULTS")
+                .help("CSV file the runs were logged to")
+                .index(1)
+                .allow_invalid_utf8(true)
+                .required(true),
         )
         .arg(
-            Arg::new("SIZE_Z")
-                .long("size-z")
-                .value_name("SIZE_Z")
-                .default_value("192"),
+            Arg::new("DESIGN").long("design").value_name("NAME").help(
+                "Only report the runs of this design, named after the stem of its input file",
+            ),
         )
         .arg(
-            Arg::new("INPUT")
-                .help("Input design, as the output of a Yosys write_protobuf command")
+            Arg::new("LAST")
+                .long("last")
+                .value_name("RUNS")
+                .default_value("10")
+                .help("Number of the latest runs of each design to list"),
+        );
+    let sweep_command = Command::new("sweep")
+        .before_help("Place a design with every combination of a set of option values")
+        .arg(
+            Arg::new("SWEEP")
+                .help("JSON file giving the arguments shared by every run and the values to try")
+                .long_help("
+JSON file giving the arguments of the place subcommand shared by every run, including the input design but not the output file, and the values to try for each option by its long name, like `{\"args\": [\"--techlib\", \"techlib\", \"counter.json\"], \"parameters\": {\"delta-t\": [0.05, 0.1, 0.2], \"clique-threshold\": \"3..6\", \"auto-size\": [true, false]}}`. Ranges like \"3..6\" include both ends.
+")
                 .index(1)
                 .allow_invalid_utf8(true)
                 .required(true),
         )
         .arg(
-            Arg::new("OUTPUT")
-                .help("Output file location")
-                .index(2)
+            Arg::new("DIR")
+                .long("dir")
+                .value_name("DIR")
                 .allow_invalid_utf8(true)
-                .required(true),
+                .required(true)
+                .help("Directory to write the placed design, log and results of each run to"),
         )
-}
-
-fn load_design(config: &Config) -> Result<Design> {
-    let reader = std::fs::File::open(&config.io.input_file)
-        .with_context(|| anyhow!("Open input file {:?}", config.io.input_file))?;
-    let reader = std::io::BufReader::new(reader);
-    serde_json::from_reader(reader).with_context(|| anyhow!("Failed to parse reader"))
-}
-
-fn load_cells(config: &Config, design: Design) -> Result<(NetlistHypergraph, String)> {
-    let top_module = design
-        .modules
-        .get("top")
-        .ok_or_else(|| anyhow!("Failed to locate top module"))?;
-
-    let mut cell_factory = CellFactory::new(config.io.structure_directory.clone());
-
-    let cells = NetlistHypergraph::from_module(top_module.clone(), &mut cell_factory)
-        .with_context(|| "Extract cells")?;
-
-    Ok((cells, design.creator))
-}
-
-fn min_f32(a: f32, b: f32) -> f32 {
-    if a < b {
-        a
-    } else {
-        b
-    }
-}
-
-fn max_f32(a: f32, b: f32) -> f32 {
-    if a > b {
-        a
-    } else {
-        b
-    }
-}
-
-fn center_all_moveable_cells(config: &Config, cells: &mut NetlistHypergraph) {
-    // Set our initial guess for the minimum position to the maximum
-    let mut current_min = Vector3::new(
-        config.geometry.size_x as f32,
-        config.geometry.size_y as f32,
-        config.geometry.size_z as f32,
-    );
-    // and use that handy value to compute the desired center
-    let desired_center = current_min / 2.0;
-    let mut current_max = Vector3::zeros();
-
-    for cell in cells.cells.iter_mut() {
-        if cell.pos_locked {
-            continue;
-        }
-        current_max.x = max_f32(current_max.x, cell.x + cell.sx);
-        current_max.y = max_f32(current_max.y, cell.tier_y + cell.s_tier_y);
-        current_max.z = max_f32(current_max.z, cell.z + cell.sz);
-
-        current_min.x = min_f32(current_min.x, cell.x);
-        current_min.y = min_f32(current_min.y, cell.tier_y);
-        current_min.z = min_f32(current_min.z, cell.z);
-    }
-
-    let delta = ((current_max - current_min) / 2.0) + current_min - desired_center;
-
-    for cell in cells.cells.iter_mut() {
-        if cell.pos_locked {
-            continue;
-        }
-        cell.x -= delta.x;
-        cell.tier_y -= delta.y;
-        cell.z -= delta.z;
-    }
-}
-
-fn place_algorithm(config: &Config, cells: &mut NetlistHypergraph) -> Result<()> {
-    let _span = info_span!("overall_place").entered();
-    for step in &config.schedule.schedule {
-        match step {
-            PlacementStep::CenterCells => {
-                let _span = info_span!("center_cells").entered();
-                center_all_moveable_cells(config, cells);
-            }
-            PlacementStep::UnconstrainedAnalytical { clique_threshold } => {
-                let _span = info_span!("unconstrained").entered();
-                let mut strategy =
-                    ThresholdCrossover::new(*clique_threshold, Clique::new(), MoveableStar::new());
-                strategy.execute(cells)?;
-            }
-            PlacementStep::Diffusion(diffusion_config) => {
-                let _span = info_span!(
-                    "diffusion",
-                    iterations = diffusion_config.iterations,
-                    region_size = diffusion_config.region_size,
-                    delta_t = diffusion_config.delta_t
-                )
-                .entered();
-                // Iterate between diffusion and some light analytic recover
-                let mut density = DiffusionPlacer::new(&config, &diffusion_config);
-
-                density.splat(cells);
-
-                // Diffusion simulation
-                for narrow_iteration in 0..diffusion_config.iterations {
-                    let _span =
-                        debug_span!("narrow_iteration", narrow_iteration = narrow_iteration)
-                            .entered();
-                    density.compute_velocities();
-                    density.move_cells(cells, diffusion_config.delta_t);
-                    density.step_time(diffusion_config.delta_t);
-                }
-            }
-            PlacementStep::ConstrainedAnalytical {
-                clique_threshold,
-                iterations,
-            } => {
-                let _span = info_span!(
-                    "analytical",
-                    iterations = iterations,
-                    clique_threshold = clique_threshold
-                )
-                .entered();
-                for _ in 0..*iterations {
-                    // Analytic wirelength recovery phase
-                    let mut strategy = ThresholdCrossover::new(
-                        *clique_threshold,
-                        Clique::new(),
-                        AnchoredByNet::new(),
-                    );
-
-                    strategy.execute(cells)?;
-                }
-            }
-        }
-    }
-
-    Ok(())
-}
-
-fn legalize_algorithm(config: &Config, netlist: &NetlistHypergraph) -> Vec<LegalizedCell> {
-    TetrisLegalizer::new(config.legalizer.left_limit).legalize(&config.geometry, &netlist.cells)
-}
-
-fn place(config: &Config, design: Design) -> Result<PlacedDesign> {
-    let (mut cells, creator) = load_cells(config, design).with_context(|| anyhow!("Load cells"))?;
-
-    place_algorithm(&config, &mut cells)
-        .with_context(|| anyhow!("Initial analytical placement"))?;
-
-    let legalized_cells = legalize_algorithm(&config, &cells);
-
-    Ok(cells.build_output(legalized_cells, creator))
-}
-
-fn run_placement(config: &Config) -> Result<()> {
-    let design = load_design(config).with_context(|| anyhow!("Load design"))?;
-
-    let placed_design = place(&config, design)
-        .with_context(|| anyhow!("Place design from {:?}", config.io.input_file))?;
-
-    {
-        use std::io::Write;
-        let mut outf = std::fs::File::create(&config.io.output_file).with_context(|| {
-            anyhow!(
-                "Failed to open/create output file {:?}",
-                config.io.output_file
-            )
-        })?;
-        let encoded = placed_design.encode_to_vec();
-
-        outf.write_all(&encoded[..]).with_context(|| {
-            anyhow!("Failed to write to output file {:?}", config.io.output_file)
-        })?;
-    }
-
-    Ok(())
-}
-
-fn main() -> Result<()> {
-    {
-        use tracing_subscriber::{prelude::*, EnvFilter};
-
-        let fmt_layer = tracing_subscriber::fmt::layer()
-            .with_span_events(FmtSpan::NEW | FmtSpan::CLOSE)
-            .compact();
-        let filter_layer = EnvFilter::try_from_default_env()
-            .or_else(|_| EnvFilter::try_new("info"))
-            .expect("Failed to initialize tracing env filter");
-
-        tracing_subscriber::registry()
-            .with(filter_layer)
-            .with(fmt_layer)
-            .init();
-    }
-
-    let gui_command = add_common_args(
-        Command::new("gui").before_help("Run a GUI for interactive debugging of the placer"),
-    );
-    let place_command =
-        add_common_args(Command::new("place").before_help("Run the placer in headless mode"));
+        .arg(
+            Arg::new("JOBS")
+                .long("jobs")
+                .short('j')
+                .value_name("JOBS")
+                .default_value("1")
+                .help("Number of placer processes to run at once"),
+        )
+        .arg(
+            Arg::new("RESULTS")
+                .long("results")
+                .value_name("FILE")
+                .allow_invalid_utf8(true)
+                .help("Also append the results of every run to this CSV file, see the report subcommand"),
+        );
     let mut command = Command::new("mcpnr-placement")
         .version(env!("CARGO_PKG_VERSION"))
         .author(env!("CARGO_PKG_AUTHORS"))
         .about("Placement phase for the MCPNR flow")
-        .subcommands(vec![gui_command, place_command]);
+        .subcommands(vec![
+            gui_command,
+            place_command,
+            replace_command,
+            doctor_command,
+            diff_command,
+            report_command,
+            sweep_command,
+        ]);
     let matches = command.get_matches_mut();
 
+    let profile = matches
+        .subcommand()
+        .and_then(|(_, matches)| matches.value_of_os("PROFILE"));
+    let _profile_guard = init_tracing(profile.map(Path::new));
+
     match matches.subcommand() {
         Some(("gui", matches)) => {
-            gui::run_gui(&Config::from_args(matches).context("Building config from args")?)
+            let config = Config::from_args(matches).context("Building config from args")?;
+            doctor::ensure_config(&config)?;
+            gui::run_gui(&config, matches.value_of_os("FROM_PLACED").map(Path::new))
         }
         Some(("place", matches)) => {
-            run_placement(&Config::from_args(matches).context("Building config from args")?)
+            let config = Config::from_args(matches).context("Building config from args")?;
+            doctor::ensure_config(&config)?;
+            flow::run_placement(&config)
+        }
+        Some(("replace", matches)) => {
+            let config = Config::from_args(matches).context("Building config from args")?;
+            doctor::ensure_config(&config)?;
+            let region = matches
+                .value_of("REGION")
+                .unwrap()
+                .parse()
+                .context("Parse REGION")?;
+            flow::run_replace(&config, &region)
+        }
+        Some(("doctor", matches)) => {
+            doctor::run_doctor(&Config::from_args(matches).context("Building config from args")?)
         }
+        Some(("diff", matches)) => diff::run_diff(&diff::DiffArgs {
+            before: matches.value_of_os("BEFORE").unwrap().into(),
+            after: matches.value_of_os("AFTER").unwrap().into(),
+            techlib: matches.value_of_os("TECHLIB").unwrap().into(),
+            top: matches
+                .value_of("TOP")
+                .unwrap()
+                .parse()
+                .context("Parse TOP")?,
+        }),
+        Some(("report", matches)) => run_report(matches),
+        Some(("sweep", matches)) => sweep::run_sweep(&sweep::SweepArgs {
+            sweep_file: matches.value_of_os("SWEEP").unwrap().into(),
+            dir: matches.value_of_os("DIR").unwrap().into(),
+            jobs: matches
+                .value_of("JOBS")
+                .unwrap()
+                .parse()
+                .context("Parse JOBS")?,
+            results_file: matches.value_of_os("RESULTS").map(Into::into),
+        }),
         None => command
             .print_long_help()
             .context("Failed to write long help"),
         e => panic!("Unhandled subcommand {:?}", e),
     }
 }
+
+/// The web build only runs the GUI, on a design picked in the browser
+#[cfg(target_arch = "wasm32")]
+fn main() {
+    console_error_panic_hook::set_once();
+    tracing_wasm::set_as_global_default();
+
+    gui::web::start("mcpnr_canvas");
+}
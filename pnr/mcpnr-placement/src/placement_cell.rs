@@ -1,7 +1,16 @@
 use anyhow::{anyhow, Context, Result};
-use mcpnr_common::{minecraft_types::Structure, yosys::Cell, CellExt, BLOCKS_PER_TIER};
+use mcpnr_common::{
+    blockers::BlockerRules,
+    io_macros,
+    minecraft_types::{load_structure, read_structure, Structure},
+    yosys::Cell,
+    CellExt, BLOCKS_PER_TIER,
+};
 use nalgebra::Vector3;
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
 /// Internal type containing the metadata we care about from a given cell's NBT data.
 pub(crate) struct PlacementStructureData {
@@ -11,13 +20,25 @@ pub(crate) struct PlacementStructureData {
     sy: u32,
     /// Z size, in blocks.
     sz: u32,
+    /// Position of the first pin of each port, in blocks from the structure origin.
+    pins: HashMap<String, [i32; 3]>,
+}
+
+/// Where a [CellFactory] reads the techlib's structures from
+enum StructureSource {
+    /// The structure directory of a techlib
+    Directory(PathBuf),
+    /// Contents of the structure files by file name, for the web build which has no file system
+    Memory(HashMap<String, Vec<u8>>),
 }
 
 /// yeah it's a java thing get over it.
 ///
 /// caches structure reads so we can avoid re-parsing on every cell
 pub struct CellFactory {
-    structure_directory: PathBuf,
+    structures: StructureSource,
+    /// Rules telling which blocks and entities in the structures mark pins
+    rules: BlockerRules,
     structure_cache: HashMap<String, PlacementStructureData>,
 }
 
@@ -79,13 +100,38 @@ impl LegalizedCell {
 }
 
 impl CellFactory {
-    pub fn new(structure_directory: PathBuf) -> Self {
+    pub fn new(structure_directory: PathBuf, rules: BlockerRules) -> Self {
+        Self {
+            structures: StructureSource::Directory(structure_directory),
+            rules,
+            structure_cache: Default::default(),
+        }
+    }
+
+    /// Create a factory reading the structures from memory, given the contents of each structure
+    /// file by its name
+    pub fn from_memory(structures: HashMap<String, Vec<u8>>, rules: BlockerRules) -> Self {
         Self {
-            structure_directory,
+            structures: StructureSource::Memory(structures),
+            rules,
             structure_cache: Default::default(),
         }
     }
 
+    /// Read a structure from the techlib, bypassing the cache.
+    pub fn read_structure(&self, structure_name: &str) -> Result<Structure> {
+        match self.structures {
+            StructureSource::Directory(ref directory) => {
+                load_structure(&directory.join(structure_name))
+            }
+            StructureSource::Memory(ref structures) => match structures.get(structure_name) {
+                Some(data) => read_structure(&mut &data[..], Path::new(structure_name)),
+                None => Err(anyhow!("No structure named {:?}", structure_name)),
+            },
+        }
+        .with_context(|| format!("Failed to load structure for {:?}", structure_name))
+    }
+
     pub(crate) fn load_structure(
         &mut self,
         structure_name: &str,
@@ -96,18 +142,7 @@ impl CellFactory {
                 .ok_or_else(|| -> ! { unreachable!() })
                 .map_err(Into::into)
         } else {
-            let nbt_cell_file = self.structure_directory.join(structure_name);
-            let mut nbt_cell_file = std::fs::File::open(&nbt_cell_file).with_context(|| {
-                format!(
-                    "Failed to open structure file {:?} for reading",
-                    nbt_cell_file
-                )
-            })?;
-            let (cell, _): (Structure, _) = quartz_nbt::serde::deserialize_from(
-                &mut nbt_cell_file,
-                quartz_nbt::io::Flavor::GzCompressed,
-            )
-            .with_context(|| format!("Failed to parse structure for {:?}", structure_name))?;
+            let cell = self.read_structure(structure_name)?;
 
             let cell_extents = cell.blocks.iter().fold(
                 ((0, 0, 0), (0, 0, 0)),
@@ -127,10 +162,19 @@ impl CellFactory {
                 },
             );
 
+            let mut pins = HashMap::new();
+            for pin in cell
+                .pins(&self.rules)
+                .with_context(|| anyhow!("Reading pins of {}", structure_name))?
+            {
+                pins.entry(pin.port).or_insert(pin.pos);
+            }
+
             let cell_data = PlacementStructureData {
                 sx: (((cell_extents.1).0) - ((cell_extents.0).0)) as u32,
                 sy: (((cell_extents.1).1) - ((cell_extents.0).1)) as u32,
                 sz: (((cell_extents.1).2) - ((cell_extents.0).2)) as u32,
+                pins,
             };
 
             log::info!(
@@ -147,6 +191,14 @@ impl CellFactory {
         }
     }
 
+    /// Make cells of type `name` stand for an already placed block of `size` blocks, with the
+    /// given pin positions, instead of a structure from the techlib.
+    pub fn add_soft_macro(&mut self, name: &str, size: [u32; 3], pins: HashMap<String, [i32; 3]>) {
+        let [sx, sy, sz] = size;
+        self.structure_cache
+            .insert(name.to_owned(), PlacementStructureData { sx, sy, sz, pins });
+    }
+
     pub fn build_cell(&mut self, cell: &Cell) -> Result<PlacementCell> {
         // TODO: maybe all these should output a sy of 1.0 since most of the rest of the code
         // effectively already assumes that the y coordinate is in layers
@@ -163,14 +215,41 @@ impl CellFactory {
         }
     }
 
+    /// Offset from the center of `placement` to the pin for bit `bit` of `port` on `cell`.
+    pub fn pin_offset(
+        &mut self,
+        cell: &Cell,
+        placement: &PlacementCell,
+        port: &str,
+        bit: usize,
+    ) -> Result<Vector3<f32>> {
+        let [x, y, z] = match cell.ty.as_ref() {
+            "MCPNR_SWITCHES" | "MCPNR_LIGHTS" => {
+                io_macros::pin_offset(bit as u32).map(|c| c as i32)
+            }
+            _ => {
+                // Each bit of a multi-bit port has its own pin, like `A[3]`
+                let pins = &self.load_structure(&cell.ty)?.pins;
+                *pins
+                    .get(&format!("{}[{}]", port, bit))
+                    .or_else(|| pins.get(port))
+                    .ok_or_else(|| {
+                        anyhow!("{} has no pin for bit {} of port {:?}", cell.ty, bit, port)
+                    })?
+            }
+        };
+
+        // Pins are in blocks, aim for the middle of the block
+        Ok(Vector3::new(
+            x as f32 + 0.5 - placement.sx / 2.0,
+            (y as f32 + 0.5) / (BLOCKS_PER_TIER as f32) - placement.s_tier_y / 2.0,
+            z as f32 + 0.5 - placement.sz / 2.0,
+        ))
+    }
+
     pub fn build_switches<'design>(&mut self, cell: &Cell) -> Result<PlacementCell> {
         let (x, y, z) = get_cell_pos(cell)?;
         let nswitches = cell.get_param_i64_with_default("NSWITCH", 1)?;
-        if x > 0 && z > 0 {
-            log::warn!(
-                "Switches located at (x,z) ({x}, {z}) will cause the legalizer to misbehave!"
-            );
-        }
         Ok(PlacementCell {
             x: x as f32,
             tier_y: (y / BLOCKS_PER_TIER) as f32,
@@ -185,9 +264,6 @@ impl CellFactory {
     pub fn build_lights<'design>(&mut self, cell: &Cell) -> Result<PlacementCell> {
         let (x, y, z) = get_cell_pos(cell)?;
         let nlight = cell.get_param_i64_with_default("NLIGHT", 1)?;
-        if x > 0 && z > 0 {
-            log::warn!("Lights located at (x,z) ({x}, {z}) will cause the legalizer to misbehave!");
-        }
         Ok(PlacementCell {
             x: x as f32,
             tier_y: (y / BLOCKS_PER_TIER) as f32,
@@ -72,15 +72,15 @@ mod test {
 
         // move the moveable cells to a position that will cause locking to have a significant effect
         net.cells[0].x = 9.0;
-        net.cells[0].y = 9.0;
+        net.cells[0].tier_y = 9.0;
         net.cells[0].z = 9.0;
 
         net.cells[1].x = 8.9;
-        net.cells[1].y = 8.9;
+        net.cells[1].tier_y = 8.9;
         net.cells[1].z = 8.9;
 
         net.cells[2].x = 9.1;
-        net.cells[2].y = 9.1;
+        net.cells[2].tier_y = 9.1;
         net.cells[2].z = 9.1;
 
         let mut strategy = AnchoredByNet::new();
@@ -89,7 +89,7 @@ mod test {
         for i in 0..3 {
             eprintln!("Check index {i}");
             assert_relative_eq!(net.cells[i].x, 2.1428574, epsilon = 1e-9);
-            assert_relative_eq!(net.cells[i].y, 2.1428574, epsilon = 1e-9);
+            assert_relative_eq!(net.cells[i].tier_y, 2.1428574, epsilon = 1e-9);
             assert_relative_eq!(net.cells[i].z, 2.1428574, epsilon = 1e-9);
         }
     }
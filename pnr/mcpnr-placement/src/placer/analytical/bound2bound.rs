@@ -0,0 +1,154 @@
+use crate::core::{NetlistHypergraph, Signal};
+
+use super::{DecompositionStrategy, NetStrategy};
+
+/// Connections closer than this (in blocks) are treated as if they were this long when computing
+/// weights. Without a floor the weights of coincident cells blow up, which is common right after
+/// the cells have been stacked on top of each other.
+const MIN_DISTANCE: f32 = 1.0;
+
+/// A strategy that uses the bound-to-bound net model from Spindler et al., "Kraftwerk2 - A Fast
+/// Force-Directed Quadratic Placement Approach Using an Accurate Net Model".
+///
+/// Along each axis, the two cells at the bounds of a net are connected to each other, and every
+/// inner cell is connected to both bounds. Weighting each connection by
+/// `2 / ((p - 1) * distance)` makes the quadratic cost of the net exactly its half-perimeter
+/// wirelength at the current positions. Since the weights depend on the positions, this strategy
+/// should be executed repeatedly, recomputing the model from the previous solution each time.
+#[derive(Default)]
+pub struct Bound2Bound {}
+
+impl Bound2Bound {
+    /// Allocate a new bound-to-bound strategy
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl DecompositionStrategy for Bound2Bound {
+    fn reset(&mut self) {
+        // Nothing to do
+    }
+
+    fn analyze(&mut self, _net: &NetlistHypergraph, signal: &Signal) -> NetStrategy {
+        match signal.moveable_cells {
+            0 => NetStrategy::AllFixed,
+            _ => NetStrategy::Bound2Bound,
+        }
+    }
+
+    fn extra_entries(&self) -> usize {
+        0
+    }
+}
+
+/// Compute the bound-to-bound connections for a signal along a single axis, as `(i, j, weight)`
+/// triples of pin indicies (into [`Signal::connected_cells`]) and the weight of the connection
+/// between them.
+pub(super) fn connections(
+    net: &NetlistHypergraph,
+    signal: &Signal,
+    axis: usize,
+) -> Vec<(usize, usize, f32)> {
+    let pins = signal.connected_cells.len();
+    if pins < 2 {
+        return Vec::new();
+    }
+
+    let coord = |pin: usize| signal.pin_pos(net, pin)[axis];
+    // Take the first minimum and the last maximum, so the bounds are distinct pins even if every
+    // pin is at the same position.
+    let mut lower = 0;
+    let mut upper = 0;
+    for pin in 1..pins {
+        if coord(pin) < coord(lower) {
+            lower = pin;
+        }
+        if coord(pin) >= coord(upper) {
+            upper = pin;
+        }
+    }
+
+    let scale = 2.0 / ((pins - 1) as f32);
+    let weight = |a: usize, b: usize| scale / (coord(a) - coord(b)).abs().max(MIN_DISTANCE);
+
+    let mut result = Vec::with_capacity(2 * pins - 3);
+    result.push((lower, upper, weight(lower, upper)));
+    for pin in 0..pins {
+        if pin == lower || pin == upper {
+            continue;
+        }
+        result.push((pin, lower, weight(pin, lower)));
+        result.push((pin, upper, weight(pin, upper)));
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use approx::assert_relative_eq;
+
+    use super::{connections, Bound2Bound};
+
+    use crate::{netlist, placer::analytical::DecompositionStrategy};
+
+    #[test]
+    fn quadratic_cost_matches_hpwl() {
+        let net = netlist![
+            cells: [],
+            fixed_cells: [
+                fixed_0 => (0, 0, 0), (1, 1, 1);
+                fixed_1 => (3, 1, 7), (1, 1, 1);
+                fixed_2 => (5, 2, 2), (1, 1, 1);
+                fixed_3 => (9, 4, 4), (1, 1, 1);
+            ],
+            signals: [
+                [fixed_0, fixed_1, fixed_2, fixed_3]
+            ]
+        ];
+
+        let signal = &net.signals[0];
+        for (axis, hpwl) in [(0, 9.0), (1, 4.0), (2, 7.0)] {
+            let cost: f32 = connections(&net, signal, axis)
+                .into_iter()
+                .map(|(i, j, weight)| {
+                    let d = signal.pin_pos(&net, i)[axis] - signal.pin_pos(&net, j)[axis];
+                    weight * d * d
+                })
+                .sum();
+
+            assert_relative_eq!(cost, 2.0 * hpwl, epsilon = 1e-4);
+        }
+    }
+
+    /// Any position between the bounds has the same HPWL, so the bound-to-bound model keeps an
+    /// inner cell where it is instead of pulling it to the center like a clique would.
+    #[test]
+    fn inner_cell_keeps_position() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let mut net = netlist![
+            cells: [
+                mobile_0 => (1, 1, 1);
+            ],
+            fixed_cells: [
+                fixed_0 => (0, 0, 0), (1, 1, 1);
+                fixed_1 => (4, 4, 4), (1, 1, 1);
+            ],
+            signals: [
+                [fixed_0, mobile_0, fixed_1]
+            ]
+        ];
+        net.cells[0].x = 1.0;
+        net.cells[0].tier_y = 1.0;
+        net.cells[0].z = 1.0;
+
+        let mut strategy = Bound2Bound::new();
+        strategy.execute(&mut net).expect("Strategy success");
+
+        assert_relative_eq!(net.cells[0].x, 1.0, epsilon = 1e-5);
+        assert_relative_eq!(net.cells[0].tier_y, 1.0, epsilon = 1e-5);
+        assert_relative_eq!(net.cells[0].z, 1.0, epsilon = 1e-5);
+    }
+}
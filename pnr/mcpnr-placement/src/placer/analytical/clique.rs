@@ -32,6 +32,7 @@ impl DecompositionStrategy for Clique {
 #[cfg(test)]
 mod test {
     use approx::assert_relative_eq;
+    use nalgebra::Vector3;
 
     use super::Clique;
 
@@ -59,7 +60,7 @@ mod test {
         strategy.execute(&mut net).expect("Strategy success");
 
         assert_relative_eq!(net.cells[0].x, 1.0, epsilon = 1e-6);
-        assert_relative_eq!(net.cells[0].y, 1.0, epsilon = 1e-6);
+        assert_relative_eq!(net.cells[0].tier_y, 1.0, epsilon = 1e-6);
         assert_relative_eq!(net.cells[0].z, 1.0, epsilon = 1e-6);
     }
 
@@ -87,11 +88,11 @@ mod test {
         strategy.execute(&mut net).expect("Strategy success");
 
         assert_relative_eq!(net.cells[0].x, 1.0, epsilon = 1e-6);
-        assert_relative_eq!(net.cells[0].y, 1.0, epsilon = 1e-6);
+        assert_relative_eq!(net.cells[0].tier_y, 1.0, epsilon = 1e-6);
         assert_relative_eq!(net.cells[0].z, 1.0, epsilon = 1e-6);
 
         assert_relative_eq!(net.cells[1].x, 2.0, epsilon = 1e-6);
-        assert_relative_eq!(net.cells[1].y, 2.0, epsilon = 1e-6);
+        assert_relative_eq!(net.cells[1].tier_y, 2.0, epsilon = 1e-6);
         assert_relative_eq!(net.cells[1].z, 2.0, epsilon = 1e-6);
     }
 
@@ -122,8 +123,65 @@ mod test {
         for i in 0..3 {
             eprintln!("Check index {i}");
             assert_relative_eq!(net.cells[i].x, 0.5, epsilon = 1e-6);
-            assert_relative_eq!(net.cells[i].y, 0.5, epsilon = 1e-6);
+            assert_relative_eq!(net.cells[i].tier_y, 0.5, epsilon = 1e-6);
             assert_relative_eq!(net.cells[i].z, 0.5, epsilon = 1e-6);
         }
     }
+
+    #[test]
+    fn weighted_nets() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let mut net = netlist![
+            cells: [
+                mobile_0 => (1, 1, 1);
+            ],
+            fixed_cells: [
+                fixed_0 => (0, 0, 0), (1, 1, 1);
+                fixed_1 => (3, 3, 3), (1, 1, 1);
+            ],
+            signals: [
+                [mobile_0, fixed_0],
+                [mobile_0, fixed_1]
+            ]
+        ];
+        // Pull twice as hard towards fixed_0
+        net.signals[0].weight = 2.0;
+
+        let mut strategy = Clique::new();
+        strategy.execute(&mut net).expect("Strategy success");
+
+        assert_relative_eq!(net.cells[0].x, 1.0, epsilon = 1e-6);
+        assert_relative_eq!(net.cells[0].tier_y, 1.0, epsilon = 1e-6);
+        assert_relative_eq!(net.cells[0].z, 1.0, epsilon = 1e-6);
+    }
+
+    #[test]
+    fn pin_offsets() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let mut net = netlist![
+            cells: [
+                mobile_0 => (1, 1, 1);
+            ],
+            fixed_cells: [
+                fixed_0 => (0, 0, 0), (1, 1, 1);
+                fixed_1 => (3, 3, 3), (1, 1, 1);
+            ],
+            signals: [
+                [mobile_0, fixed_0],
+                [mobile_0, fixed_1]
+            ]
+        ];
+        // The pin towards fixed_0 sits a block east of the center, so the cell ends up a block
+        // closer to fixed_0 along X only
+        net.signals[0].pin_offsets[0] = Vector3::new(1.0, 0.0, 0.0);
+
+        let mut strategy = Clique::new();
+        strategy.execute(&mut net).expect("Strategy success");
+
+        assert_relative_eq!(net.cells[0].x, 1.0, epsilon = 1e-6);
+        assert_relative_eq!(net.cells[0].tier_y, 1.5, epsilon = 1e-6);
+        assert_relative_eq!(net.cells[0].z, 1.5, epsilon = 1e-6);
+    }
 }
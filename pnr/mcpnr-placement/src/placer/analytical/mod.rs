@@ -2,21 +2,143 @@
 use anyhow::{anyhow, Context, Result};
 use nalgebra::Vector3;
 use ndarray::{Array1, Array2};
+#[cfg(not(target_arch = "wasm32"))]
 use ndarray_linalg::{CholeskyFactorized, CholeskyInplace, SolveC, UPLO};
 
-use crate::core::{NetlistHypergraph, Signal};
+use crate::{
+    config::ObjectiveConfig,
+    core::{NetlistHypergraph, Signal},
+};
 
 // TODO: mod anchor_cell, see comments in anchor_net
 mod anchor_net;
+mod bound2bound;
 mod clique;
 mod moveable_star;
+pub(crate) mod sparse;
 mod threshold_crossover;
 
 pub use anchor_net::AnchoredByNet;
+pub use bound2bound::Bound2Bound;
 pub use clique::Clique;
 pub use moveable_star::MoveableStar;
 pub use threshold_crossover::ThresholdCrossover;
 
+use sparse::{conjugate_gradient, ConjugateGradientSettings, SparseMatrixBuilder};
+
+/// Problems with at least this many entries are solved with the sparse iterative solver instead of
+/// a dense Cholesky factorization. The dense solve is exact and quick for small problems, but is
+/// O(n^3) in time and O(n^2) in memory.
+pub const SPARSE_SOLVER_THRESHOLD: usize = 2048;
+
+/// Storage for the hessian, which is dense for small problems and sparse for large ones
+#[derive(Clone)]
+enum Hessian {
+    Dense(Array2<f32>),
+    Sparse(SparseMatrixBuilder),
+}
+
+impl Hessian {
+    fn size(&self) -> usize {
+        match self {
+            Hessian::Dense(hessian) => hessian.shape()[0],
+            Hessian::Sparse(hessian) => hessian.size(),
+        }
+    }
+
+    fn add(&mut self, i: usize, j: usize, value: f32) {
+        match self {
+            Hessian::Dense(hessian) => hessian[(i, j)] += value,
+            Hessian::Sparse(hessian) => hessian.add(i, j, value as f64),
+        }
+    }
+
+    /// Solve the system for each of the provided right hand sides, which are labeled with the
+    /// axis they belong to for diagnostics.
+    fn solve(self, rhs: Vec<(&'static str, Array1<f32>)>) -> Result<Vec<Array1<f32>>> {
+        match self {
+            Hessian::Dense(hessian) => Self::solve_dense(hessian, rhs),
+            Hessian::Sparse(hessian) => Self::solve_sparse(hessian, rhs),
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn solve_dense(
+        mut hessian: Array2<f32>,
+        rhs: Vec<(&'static str, Array1<f32>)>,
+    ) -> Result<Vec<Array1<f32>>> {
+        for i in 0..hessian.shape()[0] {
+            for j in i..hessian.shape()[0] {
+                assert!(hessian[(i, j)] == hessian[(j, i)]);
+            }
+        }
+
+        let decomp = tracing::debug_span!("invert_hessian").in_scope(|| -> Result<_> {
+            hessian
+                .cholesky_inplace(UPLO::Lower)
+                .with_context(|| anyhow!("The hessian has become non-hermitian"))?;
+
+            Ok(CholeskyFactorized {
+                factor: hessian,
+                uplo: UPLO::Lower,
+            })
+        })?;
+
+        rhs.into_iter()
+            .map(|(axis, mut b)| {
+                tracing::debug_span!("solve", axis).in_scope(|| -> Result<_> {
+                    decomp
+                        .solvec_inplace(&mut b)
+                        .with_context(|| anyhow!("Solve failed for {}", axis))?;
+                    Ok(b)
+                })
+            })
+            .collect()
+    }
+
+    /// There's no LAPACK in the web build, so solve small problems with conjugate gradient too
+    #[cfg(target_arch = "wasm32")]
+    fn solve_dense(
+        hessian: Array2<f32>,
+        rhs: Vec<(&'static str, Array1<f32>)>,
+    ) -> Result<Vec<Array1<f32>>> {
+        let mut sparse = SparseMatrixBuilder::new(hessian.shape()[0]);
+        for ((i, j), &value) in hessian.indexed_iter() {
+            if value != 0.0 {
+                sparse.add(i, j, value as f64);
+            }
+        }
+        Self::solve_sparse(sparse, rhs)
+    }
+
+    fn solve_sparse(
+        hessian: SparseMatrixBuilder,
+        rhs: Vec<(&'static str, Array1<f32>)>,
+    ) -> Result<Vec<Array1<f32>>> {
+        let hessian = tracing::debug_span!("compress_hessian").in_scope(|| hessian.build());
+        tracing::debug!(nnz = hessian.nnz(), "Sparse hessian");
+        debug_assert!(hessian.is_symmetric());
+
+        let settings = ConjugateGradientSettings::for_size(hessian.size());
+        rhs.into_iter()
+            .map(|(axis, b)| {
+                tracing::debug_span!("solve", axis).in_scope(|| -> Result<_> {
+                    let x = conjugate_gradient(&hessian, &b.mapv(|v| v as f64), settings)
+                        .with_context(|| anyhow!("Solve failed for {}", axis))?;
+                    Ok(x.mapv(|v| v as f32))
+                })
+            })
+            .collect()
+    }
+}
+
+/// The hessians of a problem. Most net models contribute the same terms along every axis, so a
+/// single hessian (and a single factorization) is shared until a model needs per-axis weights.
+enum Hessians {
+    Shared(Hessian),
+    PerAxis([Hessian; 3]),
+}
+
 /// Problem statement for quadratic analytical placement, separated by axis. This is assuming the
 /// weighted quadratic error,
 ///
@@ -32,23 +154,88 @@ pub use threshold_crossover::ThresholdCrossover;
 ///    A x = -b
 /// $$
 pub struct AnalyticWirelengthProblem {
-    hessian: Array2<f32>,
+    hessians: Hessians,
     x_vector: Array1<f32>,
     y_vector: Array1<f32>,
     z_vector: Array1<f32>,
 }
 
+/// Bytes of the dense hessians of a problem with `size` entries. Counts one hessian per axis, since
+/// a net model can need per-axis weights.
+fn dense_hessian_bytes(size: usize) -> u64 {
+    3 * size as u64 * size as u64 * std::mem::size_of::<f32>() as u64
+}
+
 impl AnalyticWirelengthProblem {
-    /// Create a new problem instance of the given size
+    /// Create a new problem instance of the given size, picking the solver based on
+    /// [`SPARSE_SOLVER_THRESHOLD`].
     pub fn new(size: usize) -> Self {
+        if size >= SPARSE_SOLVER_THRESHOLD {
+            Self::new_sparse(size)
+        } else {
+            Self::new_dense(size)
+        }
+    }
+
+    /// Create a new problem instance that will be solved with a dense Cholesky factorization
+    pub fn new_dense(size: usize) -> Self {
+        tracing::debug!(size, bytes = dense_hessian_bytes(size), "Dense hessian");
+        Self::with_hessian(Hessian::Dense(Array2::zeros((size, size))))
+    }
+
+    /// Bytes of hessian a problem with `size` entries needs, if [`Self::new`] stores it densely
+    pub fn dense_allocation_size(size: usize) -> Option<u64> {
+        (size < SPARSE_SOLVER_THRESHOLD).then(|| dense_hessian_bytes(size))
+    }
+
+    /// Create a new problem instance that will be solved with sparse conjugate gradient
+    pub fn new_sparse(size: usize) -> Self {
+        Self::with_hessian(Hessian::Sparse(SparseMatrixBuilder::new(size)))
+    }
+
+    fn with_hessian(hessian: Hessian) -> Self {
+        let size = hessian.size();
         Self {
-            hessian: Array2::zeros((size, size)),
+            hessians: Hessians::Shared(hessian),
             x_vector: Array1::zeros(size),
             y_vector: Array1::zeros(size),
             z_vector: Array1::zeros(size),
         }
     }
 
+    /// Get the hessian for a single axis, splitting the shared hessian if required.
+    fn axis_hessian_mut(&mut self, axis: usize) -> &mut Hessian {
+        if let Hessians::Shared(hessian) = &self.hessians {
+            let split = [hessian.clone(), hessian.clone(), hessian.clone()];
+            self.hessians = Hessians::PerAxis(split);
+        }
+
+        match &mut self.hessians {
+            Hessians::Shared(_) => unreachable!(),
+            Hessians::PerAxis(hessians) => &mut hessians[axis],
+        }
+    }
+
+    fn add_hessian_entry(&mut self, i: usize, j: usize, value: f32) {
+        match &mut self.hessians {
+            Hessians::Shared(hessian) => hessian.add(i, j, value),
+            Hessians::PerAxis(hessians) => {
+                for hessian in hessians.iter_mut() {
+                    hessian.add(i, j, value);
+                }
+            }
+        }
+    }
+
+    fn axis_vector_mut(&mut self, axis: usize) -> &mut Array1<f32> {
+        match axis {
+            0 => &mut self.x_vector,
+            1 => &mut self.y_vector,
+            2 => &mut self.z_vector,
+            _ => panic!("Invalid axis {}", axis),
+        }
+    }
+
     /// Adds a cost term for 2 mobile entities in the quadratic formulation, using the weighting
     /// equation
     ///
@@ -62,10 +249,40 @@ impl AnalyticWirelengthProblem {
     ///  -w_{ij} to A_{i,j} and A_{j,i}
     /// $$
     pub fn cell_mobile_mobile(&mut self, i: usize, j: usize, weight: f32) {
-        self.hessian[(i, i)] += weight;
-        self.hessian[(j, j)] += weight;
-        self.hessian[(i, j)] -= weight;
-        self.hessian[(j, i)] -= weight;
+        self.add_hessian_entry(i, i, weight);
+        self.add_hessian_entry(j, j, weight);
+        self.add_hessian_entry(i, j, -weight);
+        self.add_hessian_entry(j, i, -weight);
+    }
+
+    /// Like [`Self::cell_mobile_mobile`], but connecting pins offset from the cell centers by
+    /// $o_i$ and $o_j$:
+    ///
+    /// $$
+    ///    w_{ij} (x_i + o_i - x_j - o_j)^2
+    /// $$
+    ///
+    /// The offsets are constant, so on top of the hessian entries this contributes:
+    /// $$
+    ///  w_{ij} (o_j - o_i) to b_i
+    ///  w_{ij} (o_i - o_j) to b_j
+    /// $$
+    pub fn pin_mobile_mobile(
+        &mut self,
+        i: usize,
+        offset_i: Vector3<f32>,
+        j: usize,
+        offset_j: Vector3<f32>,
+        weight: f32,
+    ) {
+        self.cell_mobile_mobile(i, j, weight);
+
+        for axis in 0..3 {
+            let delta = weight * (offset_j[axis] - offset_i[axis]);
+            let vector = self.axis_vector_mut(axis);
+            vector[i] += delta;
+            vector[j] -= delta;
+        }
     }
 
     /// A connection from a fixed position (e.g. a pinned cell or an anchor) to a mobile cell, in
@@ -82,51 +299,111 @@ impl AnalyticWirelengthProblem {
     ///  w_{ij} x_j to b_i
     /// $$
     pub fn cell_fixed_mobile(&mut self, mobile_index: usize, weight: f32, fixed_pos: Vector3<f32>) {
-        self.hessian[(mobile_index, mobile_index)] += weight;
+        self.add_hessian_entry(mobile_index, mobile_index, weight);
 
         self.x_vector[mobile_index] += weight * fixed_pos.x;
         self.y_vector[mobile_index] += weight * fixed_pos.y;
         self.z_vector[mobile_index] += weight * fixed_pos.z;
     }
 
-    /// Solve the problem
-    pub fn solve(mut self) -> Result<(Array1<f32>, Array1<f32>, Array1<f32>)> {
-        let _span = tracing::debug_span!("problem_solve", size = self.hessian.shape()[0]).entered();
+    /// Like [`Self::cell_mobile_mobile`], but only along a single axis (0 = x, 1 = y, 2 = z).
+    pub fn cell_mobile_mobile_axis(&mut self, axis: usize, i: usize, j: usize, weight: f32) {
+        let hessian = self.axis_hessian_mut(axis);
+        hessian.add(i, i, weight);
+        hessian.add(j, j, weight);
+        hessian.add(i, j, -weight);
+        hessian.add(j, i, -weight);
+    }
+
+    /// Like [`Self::pin_mobile_mobile`], but only along a single axis (0 = x, 1 = y, 2 = z).
+    pub fn pin_mobile_mobile_axis(
+        &mut self,
+        axis: usize,
+        i: usize,
+        offset_i: f32,
+        j: usize,
+        offset_j: f32,
+        weight: f32,
+    ) {
+        self.cell_mobile_mobile_axis(axis, i, j, weight);
+
+        let delta = weight * (offset_j - offset_i);
+        let vector = self.axis_vector_mut(axis);
+        vector[i] += delta;
+        vector[j] -= delta;
+    }
 
-        for i in 0 .. self.hessian.shape()[0] {
-            for j in i .. self.hessian.shape()[0] {
-                assert!(self.hessian[(i, j)] == self.hessian[(j, i)]);
+    /// Like [`Self::cell_fixed_mobile`], but only along a single axis (0 = x, 1 = y, 2 = z).
+    pub fn cell_fixed_mobile_axis(
+        &mut self,
+        axis: usize,
+        mobile_index: usize,
+        weight: f32,
+        fixed_pos: f32,
+    ) {
+        self.axis_hessian_mut(axis)
+            .add(mobile_index, mobile_index, weight);
+        self.axis_vector_mut(axis)[mobile_index] += weight * fixed_pos;
+    }
+
+    /// Solve the problem
+    pub fn solve(self) -> Result<(Array1<f32>, Array1<f32>, Array1<f32>)> {
+        let _span = tracing::debug_span!("problem_solve", size = self.x_vector.len()).entered();
+
+        match self.hessians {
+            Hessians::Shared(hessian) => {
+                let mut solutions = hessian
+                    .solve(vec![
+                        ("X", self.x_vector),
+                        ("Y", self.y_vector),
+                        ("Z", self.z_vector),
+                    ])?
+                    .into_iter();
+                let x = solutions.next().unwrap();
+                let y = solutions.next().unwrap();
+                let z = solutions.next().unwrap();
+                Ok((x, y, z))
+            }
+            Hessians::PerAxis([x_hessian, y_hessian, z_hessian]) => {
+                let x = x_hessian.solve(vec![("X", self.x_vector)])?.remove(0);
+                let y = y_hessian.solve(vec![("Y", self.y_vector)])?.remove(0);
+                let z = z_hessian.solve(vec![("Z", self.z_vector)])?.remove(0);
+                Ok((x, y, z))
             }
         }
+    }
+}
 
-        let decomp = tracing::debug_span!("invert_hessian").in_scope(|| -> Result<_> {
-            self.hessian
-                .cholesky_inplace(UPLO::Lower)
-                .with_context(|| anyhow!("The hessian has become non-hermitian"))?;
-
-            Ok(CholeskyFactorized {
-                factor: self.hessian,
-                uplo: UPLO::Lower,
-            })
-        })?;
+/// Pull the pins of a net spanning several tiers towards their mean along Y, with a weight
+/// proportional to the number of tier crossings the net currently needs. Nets on a single tier are
+/// left alone, so this only adds to the wirelength along Y where it will cost an inter-tier via.
+fn add_tier_crossing_penalty(
+    problem: &mut AnalyticWirelengthProblem,
+    net: &NetlistHypergraph,
+    signal: &Signal,
+    objective: &ObjectiveConfig,
+) {
+    if signal.moveable_cells == 0 {
+        return;
+    }
 
-        tracing::debug_span!("solve_x").in_scope(|| {
-            decomp
-                .solvec_inplace(&mut self.x_vector)
-                .with_context(|| anyhow!("Solve failed for X"))
-        })?;
-        tracing::debug_span!("solve_y").in_scope(|| {
-            decomp
-                .solvec_inplace(&mut self.y_vector)
-                .with_context(|| anyhow!("Solve failed for Y"))
-        })?;
-        tracing::debug_span!("solve_z").in_scope(|| {
-            decomp
-                .solvec_inplace(&mut self.z_vector)
-                .with_context(|| anyhow!("Solve failed for Z"))
-        })?;
+    let pins = signal.connected_cells.len();
+    let (min_y, max_y, sum_y) = (0..pins).map(|pin| signal.pin_pos(net, pin).y).fold(
+        (f32::INFINITY, f32::NEG_INFINITY, 0.0),
+        |(min, max, sum), y| (min.min(y), max.max(y), sum + y),
+    );
+    let crossings = max_y.floor() - min_y.floor();
+    if crossings < 1.0 {
+        return;
+    }
 
-        return Ok((self.x_vector, self.y_vector, self.z_vector));
+    let mean_y = sum_y / (pins as f32);
+    let weight =
+        objective.tier_crossing_weight * signal.weight * crossings / (signal.moveable_cells as f32);
+    for (pin, &i) in signal.connected_cells.iter().enumerate() {
+        if !net.cells[i].pos_locked {
+            problem.cell_fixed_mobile_axis(1, i, weight, mean_y - signal.pin_offsets[pin].y);
+        }
     }
 }
 
@@ -170,6 +447,9 @@ pub enum NetStrategy {
     StarModel { star_idx: StarIndex },
     /// Anchor model, which anchors all the pins in the net to their current center of gravity
     Anchor,
+    /// Bound-to-bound model, which connects every pin to the pins at the bounds of the net with
+    /// weights derived from the current positions, separately for each axis.
+    Bound2Bound,
 }
 
 /// Used by placers to determine how to decompose multi-pin nets.
@@ -182,8 +462,18 @@ pub trait DecompositionStrategy {
     /// analysis results.
     fn extra_entries(&self) -> usize;
 
-    /// Default execution implementation
+    /// Optimize plain wirelength, see [`Self::execute_with`]
     fn execute(&mut self, net: &mut NetlistHypergraph) -> Result<()> {
+        self.execute_with(net, &ObjectiveConfig::WIRELENGTH_ONLY)
+    }
+
+    /// Default execution implementation, minimizing the wirelength plus the extra terms of
+    /// `objective`
+    fn execute_with(
+        &mut self,
+        net: &mut NetlistHypergraph,
+        objective: &ObjectiveConfig,
+    ) -> Result<()> {
         let _span = tracing::debug_span!("analytical_strategy").entered();
 
         // 2 passes are required because we need to know the problem size up front, and that's only
@@ -199,9 +489,6 @@ pub trait DecompositionStrategy {
         let mut problem =
             AnalyticWirelengthProblem::new(net.mobile_cell_count + self.extra_entries());
 
-        // placeholder weight
-        let weight: f32 = 1.0;
-
         // Second pass, actually does most of the work
         tracing::debug_span!("full_pass").in_scope(|| {
             self.reset();
@@ -211,29 +498,35 @@ pub trait DecompositionStrategy {
                 .map(|signal| (signal, self.analyze(net, signal)));
 
             for (signal, strategy) in strategies {
+                let weight = signal.weight;
                 match strategy {
                     NetStrategy::AllFixed => {
                         // Do nothing, the analysis claims all nets are fixed
                     }
                     NetStrategy::CliqueModel => {
-                        let weight = weight / ((signal.connected_cells.len() - 1) as f32);
-                        for (idx, &i) in signal.connected_cells.iter().enumerate() {
-                            let cell_i = &net.cells[i];
-                            for &j in signal.connected_cells.iter().skip(idx + 1) {
-                                let cell_j = &net.cells[j];
-
-                                match (cell_i.pos_locked, cell_j.pos_locked) {
+                        let pins = signal.connected_cells.len();
+                        let weight = weight / ((pins - 1) as f32);
+                        for pin_i in 0..pins {
+                            let i = signal.connected_cells[pin_i];
+                            let offset_i = signal.pin_offsets[pin_i];
+                            for pin_j in (pin_i + 1)..pins {
+                                let j = signal.connected_cells[pin_j];
+                                let offset_j = signal.pin_offsets[pin_j];
+
+                                match (net.cells[i].pos_locked, net.cells[j].pos_locked) {
                                     (true, true) => {
                                         // Both cells fixed, nothing to do
                                     }
                                     (true, false) => {
-                                        problem.cell_fixed_mobile(j, weight, cell_i.center_pos());
+                                        let pos_i = signal.pin_pos(net, pin_i);
+                                        problem.cell_fixed_mobile(j, weight, pos_i - offset_j);
                                     }
                                     (false, true) => {
-                                        problem.cell_fixed_mobile(i, weight, cell_j.center_pos());
+                                        let pos_j = signal.pin_pos(net, pin_j);
+                                        problem.cell_fixed_mobile(i, weight, pos_j - offset_i);
                                     }
                                     (false, false) => {
-                                        problem.cell_mobile_mobile(i, j, weight);
+                                        problem.pin_mobile_mobile(i, offset_i, j, offset_j, weight);
                                     }
                                 }
                             }
@@ -241,39 +534,77 @@ pub trait DecompositionStrategy {
                     }
                     NetStrategy::StarModel { star_idx } => {
                         let weight = weight / (signal.moveable_cells as f32);
-                        for &i in signal.connected_cells.iter() {
-                            let cell_i = &net.cells[i];
-
-                            if cell_i.pos_locked {
+                        for (pin, &i) in signal.connected_cells.iter().enumerate() {
+                            if net.cells[i].pos_locked {
                                 problem.cell_fixed_mobile(
                                     star_idx.0 as usize,
                                     weight,
-                                    cell_i.center_pos(),
+                                    signal.pin_pos(net, pin),
                                 )
                             } else {
-                                problem.cell_mobile_mobile(
+                                problem.pin_mobile_mobile(
                                     net.mobile_cell_count + star_idx.0 as usize,
+                                    Vector3::zeros(),
                                     i,
+                                    signal.pin_offsets[pin],
                                     weight,
                                 );
                             }
                         }
                     }
                     NetStrategy::Anchor => {
-                        let cog: Vector3<f32> = signal
-                            .connected_cells
-                            .iter()
-                            .map(|i| net.cells[*i].center_pos())
+                        let pins = signal.connected_cells.len();
+                        let cog: Vector3<f32> = (0..pins)
+                            .map(|pin| signal.pin_pos(net, pin))
                             .fold(Vector3::zeros(), |a, b| a + b)
-                            / (signal.connected_cells.len() as f32);
+                            / (pins as f32);
 
                         let weight = weight / (signal.moveable_cells as f32);
 
-                        for i in signal.iter_mobile(net) {
-                            problem.cell_fixed_mobile(i, weight, cog);
+                        for (pin, &i) in signal.connected_cells.iter().enumerate() {
+                            if !net.cells[i].pos_locked {
+                                problem.cell_fixed_mobile(i, weight, cog - signal.pin_offsets[pin]);
+                            }
+                        }
+                    }
+                    NetStrategy::Bound2Bound => {
+                        for axis in 0..3 {
+                            let connections = bound2bound::connections(net, signal, axis);
+                            for (pin_i, pin_j, b2b_weight) in connections {
+                                let weight = weight * b2b_weight;
+                                let i = signal.connected_cells[pin_i];
+                                let j = signal.connected_cells[pin_j];
+                                let offset_i = signal.pin_offsets[pin_i][axis];
+                                let offset_j = signal.pin_offsets[pin_j][axis];
+
+                                match (net.cells[i].pos_locked, net.cells[j].pos_locked) {
+                                    (true, true) => {
+                                        // Both cells fixed, nothing to do
+                                    }
+                                    (true, false) => problem.cell_fixed_mobile_axis(
+                                        axis,
+                                        j,
+                                        weight,
+                                        signal.pin_pos(net, pin_i)[axis] - offset_j,
+                                    ),
+                                    (false, true) => problem.cell_fixed_mobile_axis(
+                                        axis,
+                                        i,
+                                        weight,
+                                        signal.pin_pos(net, pin_j)[axis] - offset_i,
+                                    ),
+                                    (false, false) => problem.pin_mobile_mobile_axis(
+                                        axis, i, offset_i, j, offset_j, weight,
+                                    ),
+                                }
+                            }
                         }
                     }
                 }
+
+                if objective.tier_crossing_weight > 0.0 {
+                    add_tier_crossing_penalty(&mut problem, net, signal, objective);
+                }
             }
         });
 
@@ -293,3 +624,55 @@ pub trait DecompositionStrategy {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use approx::assert_relative_eq;
+
+    use super::{clique::Clique, DecompositionStrategy};
+
+    use crate::{config::ObjectiveConfig, netlist};
+
+    /// The cell is pulled four times harder towards `fixed_0`, on its own tier, than towards
+    /// `fixed_1`, four tiers up. Counting the tier crossings makes the net to `fixed_1` pull harder
+    /// along Y only.
+    #[test]
+    fn tier_crossings_pull_harder_along_y() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let place = |tier_crossing_weight| {
+            let mut net = netlist![
+                cells: [
+                    mobile_0 => (1, 1, 1);
+                ],
+                fixed_cells: [
+                    fixed_0 => (0, 0, 0), (1, 1, 1);
+                    fixed_1 => (4, 4, 4), (1, 1, 1);
+                ],
+                signals: [
+                    [mobile_0, fixed_0],
+                    [mobile_0, fixed_1]
+                ]
+            ];
+            net.signals[0].weight = 4.0;
+
+            let objective = ObjectiveConfig {
+                tier_crossing_weight,
+            };
+            Clique::new()
+                .execute_with(&mut net, &objective)
+                .expect("Strategy success");
+            (net.cells[0].x, net.cells[0].tier_y)
+        };
+
+        let (x, y) = place(0.0);
+        assert_relative_eq!(x, 0.8, epsilon = 1e-5);
+        assert_relative_eq!(y, 0.8, epsilon = 1e-5);
+
+        // The net to fixed_1 spans 4 tiers, so it adds a pull towards the middle of its pins with
+        // 4 times its weight
+        let (x, y) = place(1.0);
+        assert_relative_eq!(x, 0.8, epsilon = 1e-5);
+        assert_relative_eq!(y, 4.0 / 3.0, epsilon = 1e-5);
+    }
+}
@@ -71,7 +71,7 @@ mod test {
         for i in 0..3 {
             eprintln!("Check index {i}");
             assert_relative_eq!(net.cells[i].x, 0.5, epsilon = 1e-6);
-            assert_relative_eq!(net.cells[i].y, 0.5, epsilon = 1e-6);
+            assert_relative_eq!(net.cells[i].tier_y, 0.5, epsilon = 1e-6);
             assert_relative_eq!(net.cells[i].z, 0.5, epsilon = 1e-6);
         }
     }
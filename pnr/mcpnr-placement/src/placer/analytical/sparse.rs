@@ -0,0 +1,265 @@
+//! Sparse matrix storage and an iterative solver for large analytical placement problems.
+//!
+//! The hessians built by the decomposition strategies have a handful of entries per row (one per
+//! connection), so storing them densely wastes almost all of the memory and a direct
+//! factorization is O(n^3). Instead, we accumulate the entries row by row, compress them to CSR
+//! and solve with a Jacobi-preconditioned conjugate gradient.
+use anyhow::{anyhow, ensure, Result};
+use ndarray::Array1;
+use std::collections::BTreeMap;
+
+/// Accumulates entries of a square sparse matrix before it is compressed into a [`CsrMatrix`].
+/// Repeated additions to the same entry are summed.
+#[derive(Clone)]
+pub struct SparseMatrixBuilder {
+    rows: Vec<BTreeMap<usize, f64>>,
+}
+
+impl SparseMatrixBuilder {
+    /// Create a new builder for a `size` x `size` matrix
+    pub fn new(size: usize) -> Self {
+        Self {
+            rows: vec![BTreeMap::new(); size],
+        }
+    }
+
+    /// Number of rows (and columns) in the matrix
+    pub fn size(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Add `value` to the entry at `(row, col)`
+    pub fn add(&mut self, row: usize, col: usize, value: f64) {
+        debug_assert!(col < self.rows.len());
+        *self.rows[row].entry(col).or_insert(0.0) += value;
+    }
+
+    /// Compress the accumulated entries into CSR form
+    pub fn build(self) -> CsrMatrix {
+        let nnz = self.rows.iter().map(|row| row.len()).sum();
+        let mut row_ptr = Vec::with_capacity(self.rows.len() + 1);
+        let mut col_idx = Vec::with_capacity(nnz);
+        let mut values = Vec::with_capacity(nnz);
+
+        row_ptr.push(0);
+        for row in self.rows.into_iter() {
+            for (col, value) in row.into_iter() {
+                col_idx.push(col);
+                values.push(value);
+            }
+            row_ptr.push(col_idx.len());
+        }
+
+        CsrMatrix {
+            row_ptr,
+            col_idx,
+            values,
+        }
+    }
+}
+
+/// Square matrix in compressed sparse row format
+pub struct CsrMatrix {
+    /// Offsets into `col_idx` and `values` for the start of each row, with one extra entry at the
+    /// end marking the end of the last row.
+    row_ptr: Vec<usize>,
+    col_idx: Vec<usize>,
+    values: Vec<f64>,
+}
+
+impl CsrMatrix {
+    /// Number of rows (and columns) in the matrix
+    pub fn size(&self) -> usize {
+        self.row_ptr.len() - 1
+    }
+
+    /// Number of stored entries
+    pub fn nnz(&self) -> usize {
+        self.values.len()
+    }
+
+    fn row(&self, row: usize) -> impl Iterator<Item = (usize, f64)> + '_ {
+        let range = self.row_ptr[row]..self.row_ptr[row + 1];
+        self.col_idx[range.clone()]
+            .iter()
+            .copied()
+            .zip(self.values[range].iter().copied())
+    }
+
+    /// Check that the matrix is symmetric, which conjugate gradient relies on.
+    pub fn is_symmetric(&self) -> bool {
+        (0..self.size()).all(|i| {
+            self.row(i).all(|(j, value)| {
+                self.row(j)
+                    .find(|&(col, _)| col == i)
+                    .map(|(_, transposed)| transposed == value)
+                    .unwrap_or(false)
+            })
+        })
+    }
+
+    /// Extract the diagonal of the matrix
+    pub fn diagonal(&self) -> Array1<f64> {
+        Array1::from_shape_fn(self.size(), |i| {
+            self.row(i)
+                .find(|&(col, _)| col == i)
+                .map(|(_, value)| value)
+                .unwrap_or(0.0)
+        })
+    }
+
+    /// Compute `out = self * v`
+    pub fn mul_vec_into(&self, v: &Array1<f64>, out: &mut Array1<f64>) {
+        debug_assert_eq!(v.len(), self.size());
+        debug_assert_eq!(out.len(), self.size());
+        for (i, o) in out.iter_mut().enumerate() {
+            *o = self.row(i).map(|(j, value)| value * v[j]).sum();
+        }
+    }
+}
+
+/// Settings for [`conjugate_gradient`]
+#[derive(Clone, Copy, Debug)]
+pub struct ConjugateGradientSettings {
+    /// Stop once the residual norm has been reduced by this factor relative to the norm of the
+    /// right hand side.
+    pub tolerance: f64,
+    /// Give up after this many iterations
+    pub max_iterations: usize,
+}
+
+impl ConjugateGradientSettings {
+    /// Reasonable settings for a system of the given size. In exact arithmetic CG converges in at
+    /// most `size` iterations, we allow some slack for rounding.
+    pub fn for_size(size: usize) -> Self {
+        Self {
+            tolerance: 1e-9,
+            max_iterations: 2 * size + 100,
+        }
+    }
+}
+
+/// Solve `a x = b` for a symmetric positive definite `a` using conjugate gradient, with a Jacobi
+/// (diagonal) preconditioner.
+pub fn conjugate_gradient(
+    a: &CsrMatrix,
+    b: &Array1<f64>,
+    settings: ConjugateGradientSettings,
+) -> Result<Array1<f64>> {
+    let size = a.size();
+    ensure!(
+        b.len() == size,
+        "Right hand side has {} entries but the matrix has {} rows",
+        b.len(),
+        size
+    );
+
+    let mut x = Array1::zeros(size);
+    let b_norm = b.dot(b).sqrt();
+    if b_norm == 0.0 {
+        return Ok(x);
+    }
+
+    // Rows with a zero diagonal would make the preconditioner blow up. Those can only come from
+    // entries that are not connected to anything, which makes the system singular anyway, so
+    // leave them unscaled and let the iteration report the breakdown.
+    let inverse_diagonal = a
+        .diagonal()
+        .mapv(|d| if d.abs() > f64::EPSILON { 1.0 / d } else { 1.0 });
+
+    let mut r = b.clone();
+    let mut z = &inverse_diagonal * &r;
+    let mut p = z.clone();
+    let mut ap = Array1::zeros(size);
+    let mut rz = r.dot(&z);
+
+    for iteration in 0..settings.max_iterations {
+        a.mul_vec_into(&p, &mut ap);
+        let pap = p.dot(&ap);
+        if pap <= 0.0 {
+            return Err(anyhow!(
+                "The hessian is not positive definite (breakdown at iteration {})",
+                iteration
+            ));
+        }
+
+        let alpha = rz / pap;
+        x.scaled_add(alpha, &p);
+        r.scaled_add(-alpha, &ap);
+
+        let r_norm = r.dot(&r).sqrt();
+        if r_norm <= settings.tolerance * b_norm {
+            tracing::debug!(
+                iterations = iteration + 1,
+                residual = r_norm / b_norm,
+                "CG converged"
+            );
+            return Ok(x);
+        }
+
+        z = &inverse_diagonal * &r;
+        let rz_next = r.dot(&z);
+        let beta = rz_next / rz;
+        rz = rz_next;
+        p = &z + &(beta * &p);
+    }
+
+    let residual = r.dot(&r).sqrt() / b_norm;
+    Err(anyhow!(
+        "Conjugate gradient failed to converge after {} iterations (relative residual {})",
+        settings.max_iterations,
+        residual
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use approx::assert_relative_eq;
+    use ndarray::Array1;
+
+    use super::{conjugate_gradient, ConjugateGradientSettings, SparseMatrixBuilder};
+
+    /// Path graph between two anchors, the same shape as a chain of 2-pin nets between 2 fixed
+    /// cells. The solution is evenly spaced along the chain.
+    #[test]
+    fn chain_between_anchors() {
+        let n = 50;
+        let mut builder = SparseMatrixBuilder::new(n);
+        for i in 0..n - 1 {
+            builder.add(i, i, 1.0);
+            builder.add(i + 1, i + 1, 1.0);
+            builder.add(i, i + 1, -1.0);
+            builder.add(i + 1, i, -1.0);
+        }
+        // Anchors at 0 and n + 1
+        builder.add(0, 0, 1.0);
+        builder.add(n - 1, n - 1, 1.0);
+        let mut b = Array1::zeros(n);
+        b[n - 1] = (n + 1) as f64;
+
+        let a = builder.build();
+        assert!(a.is_symmetric());
+        assert_eq!(a.nnz(), 3 * n - 2);
+
+        let x = conjugate_gradient(&a, &b, ConjugateGradientSettings::for_size(n))
+            .expect("CG converges");
+        for i in 0..n {
+            assert_relative_eq!(x[i], (i + 1) as f64, epsilon = 1e-6);
+        }
+    }
+
+    #[test]
+    fn singular_system_is_an_error() {
+        let mut builder = SparseMatrixBuilder::new(2);
+        builder.add(0, 0, 1.0);
+        builder.add(1, 1, 1.0);
+        builder.add(0, 1, -1.0);
+        builder.add(1, 0, -1.0);
+        let b = Array1::from(vec![1.0, 0.0]);
+
+        assert!(
+            conjugate_gradient(&builder.build(), &b, ConjugateGradientSettings::for_size(2))
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,219 @@
+//! Detection of mobile cells that analytical placement collapsed on to (nearly) the same position.
+//! Diffusion moves cells along the density gradient at their position, so a stack of cells all
+//! moves together and the density under it never drops. Stacks like these are the usual cause of
+//! diffusion blowing up, and spotting them early points at the nets that pulled them together.
+
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use nalgebra::Vector3;
+
+use crate::core::NetlistHypergraph;
+
+/// Cells whose centers fall in the same cube of this many blocks (tiers along Y) count as stacked.
+pub const CLUSTER_TOLERANCE: f32 = 0.25;
+
+/// Smallest number of stacked cells that is reported as a cluster
+pub const MIN_CLUSTER_SIZE: usize = 4;
+
+/// Number of clusters described in detail by [`report_clusters`], largest first
+const REPORTED_CLUSTERS: usize = 5;
+
+/// Number of cells and nets named for each cluster by [`report_clusters`]
+const REPORTED_NAMES: usize = 8;
+
+/// A group of mobile cells collapsed on to (nearly) the same position
+#[derive(Clone, Debug)]
+pub struct CellCluster {
+    /// Indices in to [`NetlistHypergraph::cells`] of the cells in the cluster
+    pub cells: Vec<usize>,
+    /// Mean center of the cells, in blocks along X and Z and tiers along Y
+    pub center: Vector3<f32>,
+    /// Indices in to [`NetlistHypergraph::signals`] of the signals connecting two or more cells of
+    /// the cluster, which are what pulled them together
+    pub signals: Vec<usize>,
+}
+
+/// Find the clusters of at least `min_size` mobile cells whose centers fall in the same cube of
+/// `tolerance` blocks, largest first. Cells either side of a cube boundary are not grouped, which
+/// is fine for spotting stacks of cells at identical coordinates.
+pub fn find_clusters(net: &NetlistHypergraph, tolerance: f32, min_size: usize) -> Vec<CellCluster> {
+    let mut buckets: HashMap<(i64, i64, i64), Vec<usize>> = HashMap::new();
+    for (i, cell) in net.cells.iter().enumerate().take(net.mobile_cell_count) {
+        let center = cell.center_pos() / tolerance;
+        let key = (
+            center.x.floor() as i64,
+            center.y.floor() as i64,
+            center.z.floor() as i64,
+        );
+        buckets.entry(key).or_default().push(i);
+    }
+
+    let mut cluster_of = vec![None; net.cells.len()];
+    let mut clusters: Vec<CellCluster> = buckets
+        .into_values()
+        .filter(|cells| cells.len() >= min_size)
+        .map(|cells| {
+            let center = cells
+                .iter()
+                .map(|&i| net.cells[i].center_pos())
+                .fold(Vector3::zeros(), |a, b| a + b)
+                / cells.len() as f32;
+            CellCluster {
+                cells,
+                center,
+                signals: vec![],
+            }
+        })
+        .sorted_by(|a, b| {
+            b.cells
+                .len()
+                .cmp(&a.cells.len())
+                .then_with(|| a.cells.cmp(&b.cells))
+        })
+        .collect();
+
+    for (cluster_idx, cluster) in clusters.iter().enumerate() {
+        for &i in cluster.cells.iter() {
+            cluster_of[i] = Some(cluster_idx);
+        }
+    }
+    for (signal_idx, signal) in net.signals.iter().enumerate() {
+        let counts = signal
+            .connected_cells
+            .iter()
+            .filter_map(|&i| cluster_of[i])
+            .counts();
+        for (cluster_idx, count) in counts {
+            if count >= 2 {
+                clusters[cluster_idx].signals.push(signal_idx);
+            }
+        }
+    }
+    for cluster in clusters.iter_mut() {
+        cluster.signals.sort_unstable();
+    }
+
+    clusters
+}
+
+/// Names of the first few cells and nets of `cluster`
+fn describe(net: &NetlistHypergraph, cluster: &CellCluster) -> (String, String) {
+    let more = |count: usize| if count > REPORTED_NAMES { ", ..." } else { "" };
+    let cells = cluster
+        .cells
+        .iter()
+        .take(REPORTED_NAMES)
+        .map(|&i| match net.metadata.get(i) {
+            Some(metadata) => metadata.name.clone(),
+            None => format!("#{}", i),
+        })
+        .join(", ")
+        + more(cluster.cells.len());
+    let signals = cluster
+        .signals
+        .iter()
+        .take(REPORTED_NAMES)
+        .map(|&i| {
+            let signal = &net.signals[i];
+            match net.signal_name(signal) {
+                Some(name) => name.to_owned(),
+                None => format!("${}", signal.id),
+            }
+        })
+        .join(", ")
+        + more(cluster.signals.len());
+    (cells, signals)
+}
+
+/// Warn about the clusters found after the step named `after`, naming the cells and nets of the
+/// largest few.
+pub fn report_clusters(net: &NetlistHypergraph, clusters: &[CellCluster], after: &str) {
+    if clusters.is_empty() {
+        tracing::debug!("No stacked cells after {}", after);
+        return;
+    }
+
+    let stacked: usize = clusters.iter().map(|cluster| cluster.cells.len()).sum();
+    tracing::warn!(
+        "{} cells are stacked in {} clusters after {}, diffusion may struggle to spread them",
+        stacked,
+        clusters.len(),
+        after
+    );
+    for cluster in clusters.iter().take(REPORTED_CLUSTERS) {
+        let (cells, signals) = describe(net, cluster);
+        tracing::warn!(
+            "{} cells at ({:.1}, {:.1}, {:.1}): {}, pulled together by {} nets: {}",
+            cluster.cells.len(),
+            cluster.center.x,
+            cluster.center.y,
+            cluster.center.z,
+            cells,
+            cluster.signals.len(),
+            signals,
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nalgebra::Vector3;
+
+    use super::find_clusters;
+    use crate::{
+        core::{NetlistHypergraph, Signal},
+        placement_cell::PlacementCell,
+    };
+
+    fn cell(x: f32, z: f32) -> PlacementCell {
+        PlacementCell {
+            x,
+            tier_y: 0.0,
+            z,
+            sx: 1.0,
+            s_tier_y: 1.0,
+            sz: 2.0,
+            pos_locked: false,
+        }
+    }
+
+    fn signal(id: u64, connected_cells: Vec<usize>) -> Signal {
+        Signal {
+            pin_offsets: vec![Vector3::zeros(); connected_cells.len()],
+            moveable_cells: connected_cells.len(),
+            connected_cells,
+            id,
+            weight: 1.0,
+        }
+    }
+
+    #[test]
+    fn finds_stacked_cells_and_their_nets() {
+        let cells = vec![
+            cell(4.0, 4.0),
+            cell(4.01, 4.0),
+            cell(4.0, 4.02),
+            cell(10.0, 4.0),
+            cell(4.0, 4.0),
+            cell(20.0, 20.0),
+            cell(20.0, 20.0),
+        ];
+        let signals = vec![
+            signal(1, vec![0, 1]),
+            signal(2, vec![1, 3]),
+            signal(3, vec![2, 4, 5]),
+            signal(4, vec![5, 6]),
+        ];
+        let net = NetlistHypergraph::test_new(cells, 7, signals);
+
+        let clusters = find_clusters(&net, 0.25, 2);
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters[0].cells, vec![0, 1, 2, 4]);
+        assert_eq!(clusters[0].signals, vec![0, 2]);
+        assert_eq!(clusters[1].cells, vec![5, 6]);
+        assert_eq!(clusters[1].signals, vec![3]);
+
+        assert!(find_clusters(&net, 0.25, 5).is_empty());
+    }
+}
@@ -1,11 +1,14 @@
 use approx::abs_diff_eq;
 use log::debug;
-use ndarray::{s, Array3, Axis, Slice, Zip};
+use nalgebra::Vector3;
+use ndarray::{Array3, Axis, Slice, Zip};
+use rayon::prelude::*;
 use tracing::debug_span;
 
 use crate::{
-    config::{Config, DiffusionConfig},
+    config::{Config, DiffusionConfig, DiffusionMode},
     core::NetlistHypergraph,
+    placement_cell::PlacementCell,
 };
 
 #[cfg(test)]
@@ -22,63 +25,130 @@ mod test;
 ///   - DPlace2.0 - A stable and efficient analytical placement based on diffusion, Asian
 ///     South-Pacific Design Automation Conference 2008 Page 346 - 351
 pub struct DiffusionPlacer {
-    /// Size of each diffusion region.
+    /// Size of each diffusion region along X and Z, in blocks.
     pub region_size: usize,
+    /// Size of each diffusion region along Y, in tiers.
+    pub region_size_y: usize,
+    /// Whether cells diffuse between the tiers, or only within them
+    pub mode: DiffusionMode,
+    /// Size of the placement region, in blocks along X and Z and in tiers along Y
+    pub size: [f32; 3],
     /// Target cell fill ratio
     pub target_ratio: f32,
+    /// Whether to compute velocities and move cells on the rayon thread pool. On by default, the
+    /// serial path is kept for benchmarking and debugging.
+    pub parallel: bool,
 
     /// The amount of cell volume contained in each placer region
     pub density: Array3<f32>,
+    /// The next density, written by [`DiffusionPlacer::step_time`] and then swapped with
+    /// `density`. Kept around between steps so stepping doesn't allocate.
+    density_next: Array3<f32>,
     /// X velocity field
     pub vel_x: Array3<f32>,
     /// Y velocity field
     pub vel_y: Array3<f32>,
     /// Z velocity field
     pub vel_z: Array3<f32>,
+    /// Scale from the cell volume in each region to its density. This is 1 for most regions, but
+    /// regions cut short by the far edges of the placement region are scaled up to the density a
+    /// full region with the same fill would have.
+    region_scale: Array3<f32>,
+    /// Target fill of each region, from the density map of the geometry where it covers the
+    /// region and `target_ratio` everywhere else
+    region_target: Array3<f32>,
 }
 
 impl DiffusionPlacer {
-    /// Construct a new diffusion placer, with size `(size_x, size_y, size_z)` (specified in cell
-    /// grid units) and the given `region_size` (also in cell grid units). The diffusion will then
-    /// take place on a grid of size `(2 + size_x / region_size, 2 + size_y / region_size, 2 + size_z /
-    /// region_size)`.
+    /// Construct a new diffusion placer, for the placement region in `config` and with the given
+    /// `region_size` (in blocks along X and Z). Along Y, regions are `region_size` tiers tall in
+    /// [`DiffusionMode::Volume`] and 1 tier tall in [`DiffusionMode::PerTier`]. The diffusion will
+    /// then take place on a grid of size `(2 + ceil(size_x / region_size), 2 + ceil(size_y /
+    /// region_size_y), 2 + ceil(size_z / region_size))`, where the last region along each axis may
+    /// be cut short by the edge of the placement region.
     ///
     /// We add 2 cells to act as a border across which cells cannot traverse, without having to
     /// deal with the complexity of ensuring nonzero velocity to push cells off the borders of the
     /// placement region.
     pub fn new(config: &Config, diffusion_config: &DiffusionConfig) -> Self {
-        // TODO: handle this more gracefully
-        assert!(config.geometry.size_x % diffusion_config.region_size == 0);
-        assert!(config.geometry.size_z % diffusion_config.region_size == 0);
-
-        let shape = [
-            2 + (config.geometry.size_x / diffusion_config.region_size) as usize,
-            2 + (config.geometry.size_y / diffusion_config.region_size) as usize,
-            2 + (config.geometry.size_z / diffusion_config.region_size) as usize,
+        assert!(diffusion_config.region_size > 0);
+
+        let geometry = &config.geometry;
+        let size = [geometry.size_x, geometry.size_y, geometry.size_z];
+        let target_ratio = geometry.target_fill;
+        let region_size_y = match diffusion_config.mode {
+            DiffusionMode::Volume => diffusion_config.region_size,
+            DiffusionMode::PerTier => 1,
+        };
+        let region_sizes = [
+            diffusion_config.region_size,
+            region_size_y,
+            diffusion_config.region_size,
         ];
+        let regions: [u32; 3] = std::array::from_fn(|axis| size[axis].div_ceil(region_sizes[axis]));
+        let shape = regions.map(|regions| 2 + regions as usize);
+
+        // Fraction of a full region the region at index `region` along `axis` covers, inverted
+        let axis_scale = |axis: usize, region: usize| -> f32 {
+            let region = region as u32;
+            if region == 0 || region > regions[axis] {
+                // Border regions never hold any cells
+                return 1.0;
+            }
+            let start = (region - 1) * region_sizes[axis];
+            let end = (region * region_sizes[axis]).min(size[axis]);
+            region_sizes[axis] as f32 / (end - start) as f32
+        };
+
+        // Regions take the target of the last box of the density map their center is in
+        let region_target = |region: [usize; 3]| -> f32 {
+            if (0..3).any(|axis| region[axis] == 0 || region[axis] as u32 > regions[axis]) {
+                return target_ratio;
+            }
+            let center: [f32; 3] = std::array::from_fn(|axis| {
+                let start = (region[axis] as u32 - 1) * region_sizes[axis];
+                let end = (region[axis] as u32 * region_sizes[axis]).min(size[axis]);
+                (start + end) as f32 / 2.0
+            });
+            geometry
+                .density_map
+                .iter()
+                .rev()
+                .find(|density| density.contains(center[0], center[1], center[2]))
+                .map_or(target_ratio, |density| density.target_fill)
+        };
 
         Self {
             region_size: diffusion_config.region_size as usize,
+            region_size_y: region_size_y as usize,
+            mode: diffusion_config.mode,
+            size: size.map(|s| s as f32),
             density: Array3::zeros(shape),
-            target_ratio: config.geometry.target_fill,
+            density_next: Array3::zeros(shape),
+            target_ratio,
+            parallel: true,
             vel_x: Array3::zeros(shape),
             vel_y: Array3::zeros(shape),
             vel_z: Array3::zeros(shape),
+            region_scale: Array3::from_shape_fn(shape, |(x, y, z)| {
+                axis_scale(0, x) * axis_scale(1, y) * axis_scale(2, z)
+            }),
+            region_target: Array3::from_shape_fn(shape, |(x, y, z)| region_target([x, y, z])),
         }
     }
 
+    /// Size of the regions along each axis, in blocks along X and Z and in tiers along Y
+    fn region_sizes(&self) -> [f32; 3] {
+        [
+            self.region_size as f32,
+            self.region_size_y as f32,
+            self.region_size as f32,
+        ]
+    }
+
     /// Fill in the density field using the given netlist
     pub fn splat(&mut self, net: &NetlistHypergraph) {
-        let region_size_f = self.region_size as f32;
-        let (size_x, size_y, size_z) = {
-            let shape = self.density.shape();
-
-            (
-                (shape[0] - 2) as f32,
-                (shape[1] - 2) as f32,
-                (shape[2] - 2) as f32,
-            )
-        };
+        let region_sizes = self.region_sizes();
 
         // Start with a clean slate
         self.density.fill(0.0);
@@ -87,52 +157,34 @@ impl DiffusionPlacer {
         // strategies are more efficient, e.g. iterating over the region grid instead and then
         // finding the cells in an acceleration structure.
         for cell in net.cells.iter() {
-            // We add region_size_f after clamping to account for the marigin
-            let cell_x_start = region_size_f + cell.x.clamp(0.0, size_x * region_size_f);
-            let cell_y_start = region_size_f + cell.tier_y.clamp(0.0, size_y * region_size_f);
-            let cell_z_start = region_size_f + cell.z.clamp(0.0, size_z * region_size_f);
-
-            let cell_x_end = region_size_f + (cell.x + cell.sx).clamp(0.0, size_x * region_size_f);
-            let cell_y_end =
-                region_size_f + (cell.tier_y + cell.s_tier_y).clamp(0.0, size_y * region_size_f);
-            let cell_z_end = region_size_f + (cell.z + cell.sz).clamp(0.0, size_z * region_size_f);
-
-            let mut cell_x = cell_x_start;
-            let mut cell_y = cell_y_start;
-            let mut cell_z = cell_z_start;
-
-            let region_x_start = cell_x as usize / self.region_size;
-            let region_y_start = cell_y as usize / self.region_size;
-            let region_z_start = cell_z as usize / self.region_size;
-
-            let region_x_end = cell_x_end as usize / self.region_size;
-            let region_y_end = cell_y_end as usize / self.region_size;
-            let region_z_end = cell_z_end as usize / self.region_size;
-
-            cell_z = cell_z_start;
-            for region_z in region_z_start..=region_z_end {
-                let span_z = advance_coord(&mut cell_z, cell_z_end, region_z, self.region_size);
-                cell_y = cell_y_start;
-                for region_y in region_y_start..=region_y_end {
-                    let span_y = advance_coord(&mut cell_y, cell_y_end, region_y, self.region_size);
-                    cell_x = cell_x_start;
-                    for region_x in region_x_start..=region_x_end {
-                        let span_x =
-                            advance_coord(&mut cell_x, cell_x_end, region_x, self.region_size);
+            let start = [cell.x, cell.tier_y, cell.z];
+            let end = [
+                cell.x + cell.sx,
+                cell.tier_y + cell.s_tier_y,
+                cell.z + cell.sz,
+            ];
+            let [spans_x, spans_y, spans_z] = std::array::from_fn(|axis| {
+                region_spans(start[axis], end[axis], self.size[axis], region_sizes[axis])
+            });
 
+            for &(region_z, span_z) in spans_z.iter() {
+                for &(region_y, span_y) in spans_y.iter() {
+                    for &(region_x, span_x) in spans_x.iter() {
                         let coord = (region_x, region_y, region_z);
                         self.density[coord] += span_x * span_y * span_z;
                     }
                 }
             }
         }
+        let total_real_mass = self.density.sum();
+        self.density *= &self.region_scale;
 
         // Push the density up globaly to avoid zeros, and better represent the actual desired end
         // state where all cells are target_ratio full
 
-        let volume = size_x * size_y * size_z;
-        let target_mass = self.target_ratio * volume * (region_size_f.powi(3));
-        let total_real_mass = self.density.sum();
+        let shape = self.density.shape();
+        let volume = ((shape[0] - 2) * (shape[1] - 2) * (shape[2] - 2)) as f32;
+        let target_mass = self.target_ratio * self.size.iter().product::<f32>();
         let extra_density = (target_mass - total_real_mass) / volume;
         if extra_density < 0.0 {
             log::warn!("Overall grid is overfilled, can not add baseline density (real mass: {total_real_mass} > target_mass: {target_mass})");
@@ -144,142 +196,191 @@ impl DiffusionPlacer {
             )
             .for_each(|d| *d += extra_density_per_cell);
         }
+
+        // Regions with a lower target than the rest start out with the difference already
+        // filled, so the flow pushes cells out of them until the real fill is about at their
+        // target. Regions with a higher target draw cells in.
+        let region_volume = region_sizes.iter().product::<f32>();
+        let target_ratio = self.target_ratio;
+        Zip::from(&mut self.density)
+            .and(&self.region_target)
+            .for_each(|d, target| {
+                if *target != target_ratio {
+                    *d = (*d + (target_ratio - target) * region_volume).max(0.0);
+                }
+            });
     }
 
     /// Compute the flow velocities, based on the current density in each region.
     pub fn compute_velocities(&mut self) {
-        let _span = debug_span!("velocity").entered();
+        let _span = debug_span!("velocity", parallel = self.parallel).entered();
         // Implements:
         //   v_0(x, y, z) = - (d(x+1) - d(x - 1)) / (2 * d(x))
+        let velocity = |v: &mut f32, z: &f32, p: &f32, n: &f32| {
+            if abs_diff_eq!(*z, 0.0) {
+                *v = 0.0;
+            } else {
+                *v = (n - p) / (2.0 * z);
+            }
+        };
         let mut velocities = [&mut self.vel_x, &mut self.vel_y, &mut self.vel_z];
 
         for axis in 0..3 {
+            if axis == 1 && self.mode == DiffusionMode::PerTier {
+                // Cells stay on their tier, so there's no flow between them
+                continue;
+            }
             let vel_grid = &mut velocities[axis];
             let axis = Axis(axis);
-            Zip::from(vel_grid.slice_axis_mut(axis, Slice::from(1isize..-1)))
+            let zip = Zip::from(vel_grid.slice_axis_mut(axis, Slice::from(1isize..-1)))
                 .and(self.density.slice_axis(axis, Slice::from(1isize..-1)))
                 .and(self.density.slice_axis(axis, Slice::from(2isize..)))
-                .and(self.density.slice_axis(axis, Slice::from(..-2isize)))
-                .for_each(|v, z, p, n| {
-                    if abs_diff_eq!(*z, 0.0) {
-                        *v = 0.0;
-                    } else {
-                        *v = (n - p) / (2.0 * z);
-                    }
-                });
+                .and(self.density.slice_axis(axis, Slice::from(..-2isize)));
+            if self.parallel {
+                zip.par_for_each(velocity);
+            } else {
+                zip.for_each(velocity);
+            }
         }
     }
 
     /// Move cells according to the computed velocity fields.
     pub fn move_cells(&self, net: &mut NetlistHypergraph, dt: f32) {
-        let axies = [&self.vel_x, &self.vel_y, &self.vel_z];
+        let _span = debug_span!("move_cells", parallel = self.parallel).entered();
+        let region_sizes = Vector3::from(self.region_sizes());
+
+        let stats = if self.parallel {
+            net.cells
+                .par_iter_mut()
+                .fold(SkipStats::default, |mut stats, cell| {
+                    self.move_cell(cell, &region_sizes, dt, &mut stats);
+                    stats
+                })
+                .reduce(SkipStats::default, SkipStats::merge)
+        } else {
+            let mut stats = SkipStats::default();
+            for cell in net.cells.iter_mut() {
+                self.move_cell(cell, &region_sizes, dt, &mut stats);
+            }
+            stats
+        };
 
-        let shape = self.density.shape();
+        debug!(
+            "Skipped {}/{} for fix/lo/hi {}/{:?}/{:?}",
+            stats.skipped,
+            net.cells.len(),
+            stats.fixed,
+            stats.low,
+            stats.high
+        );
+    }
 
-        let mut skip_cell_count = 0;
-        let mut skip_cell_fixed_counter = 0;
-        let mut skip_cell_low_count = [0; 3];
-        let mut skip_cell_high_count = [0; 3];
+    /// Move a single cell according to the velocity fields, or push it back inside the placement
+    /// region if it's strayed outside.
+    fn move_cell(
+        &self,
+        cell: &mut PlacementCell,
+        region_sizes: &Vector3<f32>,
+        dt: f32,
+        stats: &mut SkipStats,
+    ) {
+        let axies = [&self.vel_x, &self.vel_y, &self.vel_z];
 
-        for cell in net.cells.iter_mut() {
-            if cell.pos_locked {
-                skip_cell_count += 1;
-                skip_cell_fixed_counter += 1;
-                continue;
-            }
+        if cell.pos_locked {
+            stats.skipped += 1;
+            stats.fixed += 1;
+            return;
+        }
 
-            let p = cell.center_pos() / (self.region_size as f32);
+        let p = cell.center_pos().component_div(region_sizes);
 
-            let mut skip_cell = false;
-            if cell.x < 0.0 {
-                // Skip the cell
-                cell.x = 0.0;
-                skip_cell_low_count[0] += 1;
-                skip_cell = true;
-            }
+        let mut skip_cell = false;
+        if cell.x < 0.0 {
+            // Skip the cell
+            cell.x = 0.0;
+            stats.low[0] += 1;
+            skip_cell = true;
+        }
 
-            if cell.tier_y < 0.0 {
-                cell.tier_y = 0.0;
-                skip_cell_low_count[1] += 1;
-                skip_cell = true;
-            }
+        if cell.tier_y < 0.0 {
+            cell.tier_y = 0.0;
+            stats.low[1] += 1;
+            skip_cell = true;
+        }
 
-            if cell.z < 0.0 {
-                cell.z = 0.0;
-                skip_cell_low_count[2] += 1;
-                skip_cell = true;
-            }
+        if cell.z < 0.0 {
+            cell.z = 0.0;
+            stats.low[2] += 1;
+            skip_cell = true;
+        }
 
-            let x_limit = ((shape[0] - 2) * self.region_size) as f32;
-            if cell.x + cell.sx > x_limit {
-                cell.x = x_limit - cell.sx;
-                skip_cell_high_count[0] += 1;
-                skip_cell = true;
-            }
+        let x_limit = self.size[0];
+        if cell.x + cell.sx > x_limit {
+            cell.x = x_limit - cell.sx;
+            stats.high[0] += 1;
+            skip_cell = true;
+        }
 
-            let y_limit = ((shape[1] - 2) * self.region_size) as f32;
-            if cell.tier_y + cell.s_tier_y > y_limit {
-                cell.tier_y = y_limit - cell.s_tier_y;
-                skip_cell_high_count[1] += 1;
-                skip_cell = true;
-            }
+        let y_limit = self.size[1];
+        if cell.tier_y + cell.s_tier_y > y_limit {
+            cell.tier_y = y_limit - cell.s_tier_y;
+            stats.high[1] += 1;
+            skip_cell = true;
+        }
 
-            let z_limit = ((shape[2] - 2) * self.region_size) as f32;
-            if cell.z + cell.sz > z_limit {
-                cell.z = z_limit - cell.sz;
-                skip_cell_high_count[2] += 1;
-                skip_cell = true;
-            }
+        let z_limit = self.size[2];
+        if cell.z + cell.sz > z_limit {
+            cell.z = z_limit - cell.sz;
+            stats.high[2] += 1;
+            skip_cell = true;
+        }
 
-            if skip_cell {
-                skip_cell_count += 1;
-                continue;
-            }
+        if skip_cell {
+            stats.skipped += 1;
+            return;
+        }
 
-            // Inset from the margin
-            let i = (p.x as usize + 1, p.y as usize + 1, p.z as usize + 1);
-
-            let f0 = (p.x.fract(), p.y.fract(), p.z.fract());
-            let f1 = (1.0 - f0.0, 1.0 - f0.1, 1.0 - f0.2);
-            let c000 = (i.0 + 0, i.1 + 0, i.2 + 0);
-            let c001 = (i.0 + 0, i.1 + 0, i.2 + 1);
-            let c010 = (i.0 + 0, i.1 + 1, i.2 + 0);
-            let c011 = (i.0 + 0, i.1 + 1, i.2 + 1);
-            let c100 = (i.0 + 1, i.1 + 0, i.2 + 0);
-            let c101 = (i.0 + 1, i.1 + 0, i.2 + 1);
-            let c110 = (i.0 + 1, i.1 + 1, i.2 + 0);
-            let c111 = (i.0 + 1, i.1 + 1, i.2 + 1);
-
-            for (axis, vel) in axies.iter().enumerate() {
-                let v000 = vel[c000];
-                let v001 = vel[c001];
-                let v010 = vel[c010];
-                let v011 = vel[c011];
-                let v100 = vel[c100];
-                let v101 = vel[c101];
-                let v110 = vel[c110];
-                let v111 = vel[c111];
-
-                let x00 = (v000 * f1.0) + (v001 * f0.0);
-                let x01 = (v010 * f1.0) + (v011 * f0.0);
-                let x10 = (v100 * f1.0) + (v101 * f0.0);
-                let x11 = (v110 * f1.0) + (v111 * f0.0);
-
-                let y0 = (x00 * f1.1) + (x01 * f0.1);
-                let y1 = (x10 * f1.1) + (x11 * f0.1);
-
-                let v = (y0 * f1.2) + (y1 * f0.2);
-
-                match axis {
-                    0 => cell.x += v * dt,
-                    1 => cell.tier_y += v * dt,
-                    2 => cell.z += v * dt,
-                    _ => unreachable!("Only 3 axies"),
-                }
+        // Inset from the margin
+        let i = (p.x as usize + 1, p.y as usize + 1, p.z as usize + 1);
+
+        let f0 = (p.x.fract(), p.y.fract(), p.z.fract());
+        let f1 = (1.0 - f0.0, 1.0 - f0.1, 1.0 - f0.2);
+        let c000 = (i.0 + 0, i.1 + 0, i.2 + 0);
+        let c001 = (i.0 + 0, i.1 + 0, i.2 + 1);
+        let c010 = (i.0 + 0, i.1 + 1, i.2 + 0);
+        let c011 = (i.0 + 0, i.1 + 1, i.2 + 1);
+        let c100 = (i.0 + 1, i.1 + 0, i.2 + 0);
+        let c101 = (i.0 + 1, i.1 + 0, i.2 + 1);
+        let c110 = (i.0 + 1, i.1 + 1, i.2 + 0);
+        let c111 = (i.0 + 1, i.1 + 1, i.2 + 1);
+
+        for (axis, vel) in axies.iter().enumerate() {
+            let v000 = vel[c000];
+            let v001 = vel[c001];
+            let v010 = vel[c010];
+            let v011 = vel[c011];
+            let v100 = vel[c100];
+            let v101 = vel[c101];
+            let v110 = vel[c110];
+            let v111 = vel[c111];
+
+            let x00 = (v000 * f1.0) + (v001 * f0.0);
+            let x01 = (v010 * f1.0) + (v011 * f0.0);
+            let x10 = (v100 * f1.0) + (v101 * f0.0);
+            let x11 = (v110 * f1.0) + (v111 * f0.0);
+
+            let y0 = (x00 * f1.1) + (x01 * f0.1);
+            let y1 = (x10 * f1.1) + (x11 * f0.1);
+
+            let v = (y0 * f1.2) + (y1 * f0.2);
+
+            match axis {
+                0 => cell.x += v * dt,
+                1 => cell.tier_y += v * dt,
+                2 => cell.z += v * dt,
+                _ => unreachable!("Only 3 axies"),
             }
         }
-
-        debug!("Skipped {skip_cell_count}/{} for fix/lo/hi {skip_cell_fixed_counter}/{skip_cell_low_count:?}/{skip_cell_high_count:?}", net.cells.len());
     }
 
     /// Step the density forward in time.
@@ -287,77 +388,110 @@ impl DiffusionPlacer {
     /// Uses the "forward-time centered space" scheme, as recommended by the "Diffusion-Based Placement
     /// Migration" paper.
     pub fn step_time(&mut self, dt: f32) {
-        let mut density_prime = self.density.clone();
-
         // The FTCS scheme is formulated like:
         //  d(x) = d(x) + (dt / 2) * (d(x+1) + d(x-1) - 2d(x))
         // where the (dt/2) term is repeated for each individual axis.
         //
         // Since we have 3 axis to step, we want to subtract 6 * (dt/2) and then add the
         //   (dt/2) * (x+1, x-1, y+1, y-1, z+1, z-1)
-        // offset values.
-        let self_scale = 1.0 - (3.0 * dt);
-        density_prime.iter_mut().for_each(|x| {
-            *x = *x * self_scale;
-        });
-
+        // offset values. Diffusing each tier on its own skips the Y axis, leaving 2 axes to step.
+        let diffuse_y = self.mode == DiffusionMode::Volume;
+        let self_scale = 1.0 - (if diffuse_y { 3.0 } else { 2.0 } * dt);
         let offset_scale = dt / 2.0;
 
-        // x+1 slice
-        Zip::from(density_prime.slice_mut(s![.., .., ..-1]))
-            .and(self.density.slice(s![.., .., 1..]))
-            .for_each(|prime, orig| {
-                *prime += orig * offset_scale;
-            });
-
-        // x-1 slice
-        Zip::from(density_prime.slice_mut(s![.., .., 1..]))
-            .and(self.density.slice(s![.., .., ..-1]))
-            .for_each(|prime, orig| {
-                *prime += orig * offset_scale;
-            });
-
-        // y+1 slice
-        Zip::from(density_prime.slice_mut(s![.., ..-1, ..]))
-            .and(self.density.slice(s![.., 1.., ..]))
-            .for_each(|prime, orig| {
-                *prime += orig * offset_scale;
-            });
-
-        // y-1 slice
-        Zip::from(density_prime.slice_mut(s![.., 1.., ..]))
-            .and(self.density.slice(s![.., ..-1, ..]))
-            .for_each(|prime, orig| {
-                *prime += orig * offset_scale;
-            });
-
-        // z+1 slice
-        Zip::from(density_prime.slice_mut(s![..-1, .., ..]))
-            .and(self.density.slice(s![1.., .., ..]))
-            .for_each(|prime, orig| {
-                *prime += orig * offset_scale;
-            });
-
-        // z-1 slice
-        Zip::from(density_prime.slice_mut(s![1.., .., ..]))
-            .and(self.density.slice(s![..-1, .., ..]))
-            .for_each(|prime, orig| {
-                *prime += orig * offset_scale;
-            });
+        // Both grids are in standard layout, so walk them as flat slices. Neighbours along Z are
+        // adjacent, and neighbours along Y and X are a row and a plane away. Neighbours off the
+        // edge of the grid contribute nothing.
+        let (size_x, size_y, size_z) = self.density.dim();
+        let stride_y = size_z;
+        let stride_x = size_y * size_z;
+        let density = self
+            .density
+            .as_slice()
+            .expect("Density grid is in standard layout");
+        let next = self
+            .density_next
+            .as_slice_mut()
+            .expect("Density grid is in standard layout");
+        for x in 0..size_x {
+            for y in 0..size_y {
+                let row = x * stride_x + y * stride_y;
+                for z in 0..size_z {
+                    let i = row + z;
+                    let mut d = density[i] * self_scale;
+                    if z + 1 < size_z {
+                        d += density[i + 1] * offset_scale;
+                    }
+                    if z > 0 {
+                        d += density[i - 1] * offset_scale;
+                    }
+                    if diffuse_y {
+                        if y + 1 < size_y {
+                            d += density[i + stride_y] * offset_scale;
+                        }
+                        if y > 0 {
+                            d += density[i - stride_y] * offset_scale;
+                        }
+                    }
+                    if x + 1 < size_x {
+                        d += density[i + stride_x] * offset_scale;
+                    }
+                    if x > 0 {
+                        d += density[i - stride_x] * offset_scale;
+                    }
+                    next[i] = d;
+                }
+            }
+        }
 
-        // TODO: probably want to keep the density_prime array around to reduce allocation
-        // throughput.
-        std::mem::swap(&mut self.density, &mut density_prime);
+        std::mem::swap(&mut self.density, &mut self.density_next);
     }
 }
 
-fn advance_coord(cell: &mut f32, end: f32, region: usize, region_size: usize) -> f32 {
-    let next_cell = ((region + 1) * region_size) as f32;
-    let span = if end < next_cell { end } else { next_cell } - *cell;
+/// Counts of the cells [`DiffusionPlacer::move_cells`] didn't move, for debugging
+#[derive(Clone, Copy, Debug, Default)]
+struct SkipStats {
+    /// Every cell that didn't move
+    skipped: usize,
+    /// Cells that are locked in place
+    fixed: usize,
+    /// Cells pushed back inside the low edge of the placement region, along each axis
+    low: [usize; 3],
+    /// Cells pushed back inside the high edge of the placement region, along each axis
+    high: [usize; 3],
+}
 
-    *cell = next_cell;
+impl SkipStats {
+    fn merge(mut self, other: Self) -> Self {
+        self.skipped += other.skipped;
+        self.fixed += other.fixed;
+        for axis in 0..3 {
+            self.low[axis] += other.low[axis];
+            self.high[axis] += other.high[axis];
+        }
+        self
+    }
+}
 
-    assert!(span >= 0.0);
+/// Split the span `start..end` of a cell along one axis in to the regions it covers, as pairs of
+/// the region index (counting the border region) and the length of the span in that region. The
+/// span is clamped to the placement region, `0..limit`.
+fn region_spans(start: f32, end: f32, limit: f32, region_size: f32) -> Vec<(usize, f32)> {
+    let start = start.clamp(0.0, limit);
+    let end = end.clamp(0.0, limit);
+
+    let mut spans = Vec::new();
+    let mut region = (start / region_size) as usize;
+    let mut position = start;
+    loop {
+        let region_end = ((region + 1) as f32 * region_size).min(end);
+        spans.push((region + 1, region_end - position));
+        if region_end >= end {
+            break;
+        }
+        position = region_end;
+        region += 1;
+    }
 
-    span
+    spans
 }
@@ -1,28 +1,66 @@
 use std::path::PathBuf;
 
 use super::DiffusionPlacer;
-use crate::{config::Config, netlist};
+use crate::{
+    config::{Config, DensityRegion, DiffusionMode},
+    netlist,
+};
 use approx::assert_relative_eq;
 use ndarray::s;
 
 fn test_diffuser() -> DiffusionPlacer {
+    diffuser_with((16, 16, 16), DiffusionMode::Volume)
+}
+
+fn diffuser_with(size: (u32, u32, u32), mode: DiffusionMode) -> DiffusionPlacer {
+    diffuser_with_fill(size, mode, 0.0, Vec::new())
+}
+
+fn diffuser_with_fill(
+    (size_x, size_y, size_z): (u32, u32, u32),
+    mode: DiffusionMode,
+    target_fill: f32,
+    density_map: Vec<DensityRegion>,
+) -> DiffusionPlacer {
     let config = Config {
         io: crate::config::IOConfig {
             input_file: PathBuf::new(),
             output_file: PathBuf::new(),
             structure_directory: PathBuf::new(),
+            blocker_rules_file: PathBuf::new(),
+            blockage_map_file: None,
+            placed_json_file: None,
+            timing_constraints_file: None,
+            net_weights_file: None,
+            results_file: None,
+        },
+        io_plan: crate::config::IOPlanConfig {
+            switch_edge: None,
+            light_edge: None,
+            constraints_file: None,
         },
         geometry: crate::config::GeometryConfig {
-            size_x: 16,
-            size_y: 16,
-            size_z: 16,
-            target_fill: 0.0,
+            size_x,
+            size_y,
+            size_z,
+            target_fill,
+            density_map,
         },
         schedule: crate::config::PlacementSchedule { schedule: vec![] },
+        objective: crate::config::ObjectiveConfig::WIRELENGTH_ONLY,
+        legalizer: crate::config::LegalizerConfig::Tetris { left_limit: 8 },
+        hierarchy: crate::config::HierarchyConfig {
+            soft_macros: false,
+            macro_aspect_ratio: 1.0,
+            refine_schedule: None,
+        },
+        auto_size: None,
+        max_memory: None,
     };
 
     let diffusion_config = crate::config::DiffusionConfig {
         region_size: 2,
+        mode,
         iterations: 1,
         delta_t: 0.1,
     };
@@ -161,6 +199,66 @@ fn splat_solid() {
     assert_relative_eq!(diffuser.density[(3, 3, 3)], 1.0);
 }
 
+/// Regions cut short by the edge of the placement region are as dense as a full region with the
+/// same fill
+#[test]
+fn splat_partial_region() {
+    let netlist = netlist!(
+        cells: [
+        ],
+        fixed_cells: [
+            fixed_0 => (4, 0, 4), (1, 1, 1);
+        ],
+        signals: [
+        ]
+    );
+
+    let mut diffuser = diffuser_with((5, 1, 5), DiffusionMode::PerTier);
+    assert_eq!(diffuser.density.shape(), &[5, 3, 5]);
+
+    diffuser.splat(&netlist);
+
+    // The last region along X and Z is 1 block wide instead of 2, so holds a quarter as much
+    assert_relative_eq!(diffuser.density[(3, 1, 3)], 4.0);
+    assert_relative_eq!(diffuser.density[(2, 1, 2)], 0.0);
+}
+
+/// Regions under a box of the density map with a lower target than the rest start out fuller by
+/// the difference, so the flow pushes cells out of them
+#[test]
+fn splat_density_map() {
+    let netlist = netlist!(
+        cells: [
+        ],
+        fixed_cells: [
+        ],
+        signals: [
+        ]
+    );
+
+    let mut diffuser = diffuser_with_fill(
+        (16, 1, 16),
+        DiffusionMode::PerTier,
+        0.75,
+        vec![DensityRegion {
+            x: 0,
+            z: 0,
+            size_x: 4,
+            size_z: 16,
+            tier: None,
+            target_fill: 0.5,
+        }],
+    );
+
+    diffuser.splat(&netlist);
+
+    // An empty design is filled up evenly to the overall target, 0.75 of 4 blocks spread over the
+    // 64 regions of the tier is 0.046875 per region
+    assert_relative_eq!(diffuser.density[(1, 1, 1)], 0.046875 + 1.0);
+    assert_relative_eq!(diffuser.density[(2, 1, 8)], 0.046875 + 1.0);
+    assert_relative_eq!(diffuser.density[(3, 1, 1)], 0.046875);
+}
+
 #[test]
 fn diffuse_simple() {
     let mut diffuser = test_diffuser();
@@ -211,6 +309,35 @@ fn diffuse_simple() {
     assert_relative_eq!(diffuser.density[(3, 3, 3)], 0.0);
 }
 
+/// Stepping twice reuses the density buffers, which mustn't leak anything from the first step in
+/// to the second
+#[test]
+fn diffuse_twice() {
+    let mut diffuser = test_diffuser();
+
+    diffuser.density[(2, 2, 2)] = 1.0;
+    diffuser.step_time(0.01);
+    diffuser.step_time(0.01);
+
+    // Each neighbour spreads 0.005 of its first step density back to the middle
+    assert_relative_eq!(
+        diffuser.density[(2, 2, 2)],
+        0.97 * 0.97 + 6.0 * 0.005 * 0.005,
+        epsilon = 1e-6
+    );
+    assert_relative_eq!(
+        diffuser.density[(1, 2, 2)],
+        2.0 * 0.97 * 0.005,
+        epsilon = 1e-6
+    );
+    assert_relative_eq!(diffuser.density[(0, 2, 2)], 0.005 * 0.005, epsilon = 1e-6);
+    assert_relative_eq!(
+        diffuser.density[(1, 1, 2)],
+        2.0 * 0.005 * 0.005,
+        epsilon = 1e-6
+    );
+}
+
 #[test]
 fn vel_simple() {
     let mut diffuser = test_diffuser();
@@ -247,12 +374,68 @@ fn movement_sanity() {
     );
 
     net.cells[0].x = 0.5;
-    net.cells[0].y = 0.5;
+    net.cells[0].tier_y = 0.5;
     net.cells[0].z = 0.5;
 
     diffuser.move_cells(&mut net, 0.25);
 
     assert_relative_eq!(net.cells[0].x, 0.53125);
-    assert_relative_eq!(net.cells[0].y, 0.53125);
+    assert_relative_eq!(net.cells[0].tier_y, 0.53125);
     assert_relative_eq!(net.cells[0].z, 0.53125);
 }
+
+#[test]
+fn diffuse_per_tier() {
+    let mut diffuser = diffuser_with((16, 16, 16), DiffusionMode::PerTier);
+
+    diffuser.density[(2, 2, 2)] = 1.0;
+    diffuser.step_time(0.01);
+
+    // Spreads along X and Z as in 3D, but nothing crosses in to the tiers above and below
+    assert_relative_eq!(diffuser.density[(2, 2, 2)], 0.98);
+    assert_relative_eq!(diffuser.density[(1, 2, 2)], 0.005);
+    assert_relative_eq!(diffuser.density[(3, 2, 2)], 0.005);
+    assert_relative_eq!(diffuser.density[(2, 2, 1)], 0.005);
+    assert_relative_eq!(diffuser.density[(2, 2, 3)], 0.005);
+    assert_relative_eq!(diffuser.density[(2, 1, 2)], 0.0);
+    assert_relative_eq!(diffuser.density[(2, 3, 2)], 0.0);
+}
+
+/// The parallel and serial paths compute the same flow and move cells the same way
+#[test]
+fn parallel_matches_serial() {
+    let make_net = || {
+        netlist!(
+            cells: [
+                mobile_0 => (1, 1, 2);
+                mobile_1 => (1, 1, 2);
+                mobile_2 => (2, 1, 2);
+            ],
+            fixed_cells: [
+                fixed_0 => (4, 2, 4), (2, 1, 2);
+            ],
+            signals: []
+        )
+    };
+    let mut nets = [make_net(), make_net()];
+    let mut diffusers = [test_diffuser(), test_diffuser()];
+    diffusers[1].parallel = false;
+
+    for (net, diffuser) in nets.iter_mut().zip(diffusers.iter_mut()) {
+        for (i, cell) in net.cells[..3].iter_mut().enumerate() {
+            cell.x = 3.0 + i as f32;
+            cell.tier_y = 2.0;
+            cell.z = 5.0 - i as f32;
+        }
+        diffuser.splat(net);
+        diffuser.compute_velocities();
+        diffuser.move_cells(net, 0.1);
+    }
+
+    assert_eq!(diffusers[0].vel_x, diffusers[1].vel_x);
+    assert_eq!(diffusers[0].vel_y, diffusers[1].vel_y);
+    assert_eq!(diffusers[0].vel_z, diffusers[1].vel_z);
+    for (parallel, serial) in nets[0].cells.iter().zip(nets[1].cells.iter()) {
+        assert_eq!(parallel.center_pos(), serial.center_pos());
+    }
+}
@@ -0,0 +1,393 @@
+//! Placement of hard macros: techlib cells like RAM banks and displays, which are huge next to the
+//! gates around them. The usual flow would spread them out like any other cell and leave the
+//! legalizer to find a hole big enough for each, so instead they are placed first by simulated
+//! annealing over the macro positions alone. The macros are then locked in place and the rest of
+//! the schedule places the standard cells around them.
+
+use anyhow::{ensure, Result};
+use mcpnr_common::xorshift::XorShift;
+use mcpnr_common::BLOCKS_PER_Z_ROW;
+use nalgebra::Vector3;
+
+use crate::{
+    config::{GeometryConfig, HardMacroConfig},
+    core::NetlistHypergraph,
+};
+
+/// Cost of a macro overlapping another macro or a fixed cell, per block of overlap (in tiers
+/// along Y). High enough that overlap is never worth the wirelength once the annealer has cooled.
+const OVERLAP_PENALTY: f32 = 64.0;
+
+/// Temperature the annealer cools to by the last move, relative to the starting temperature
+const FINAL_TEMPERATURE: f32 = 1e-3;
+
+/// Chance of a move swapping two macros instead of displacing one
+const SWAP_PROBABILITY: f32 = 0.2;
+
+/// Number of random moves used to estimate the starting temperature
+const TEMPERATURE_SAMPLES: usize = 64;
+
+/// Seed for the random moves, fixed so the placement is the same from run to run
+const SEED: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// Position of a macro, as (x, tier_y, z). X is always even and Z is always at the start of a
+/// row, so the position is legal as is.
+type MacroPosition = (u32, u32, u32);
+
+struct Annealer<'a> {
+    net: &'a NetlistHypergraph,
+    geometry: &'a GeometryConfig,
+    /// Index in to [`NetlistHypergraph::cells`] of each macro
+    macros: Vec<usize>,
+    /// Current position of each macro
+    positions: Vec<MacroPosition>,
+    /// For each cell, the index in to `macros` if the cell is a macro
+    macro_index: Vec<Option<usize>>,
+    /// Signals connected to each macro
+    macro_signals: Vec<Vec<usize>>,
+}
+
+impl<'a> Annealer<'a> {
+    fn new(
+        net: &'a NetlistHypergraph,
+        geometry: &'a GeometryConfig,
+        macros: Vec<usize>,
+    ) -> Result<Self> {
+        let mut macro_index = vec![None; net.cells.len()];
+        for (m, &cell_idx) in macros.iter().enumerate() {
+            let cell = &net.cells[cell_idx];
+            ensure!(
+                cell.sx as u32 <= geometry.size_x
+                    && cell.s_tier_y as u32 <= geometry.size_y
+                    && cell.sz as u32 <= geometry.size_z,
+                "Hard macro of size {}x{}x{} doesn't fit in the {}x{}x{} placement region",
+                cell.sx,
+                cell.s_tier_y,
+                cell.sz,
+                geometry.size_x,
+                geometry.size_y,
+                geometry.size_z
+            );
+            macro_index[cell_idx] = Some(m);
+        }
+
+        let mut macro_signals = vec![Vec::new(); macros.len()];
+        for (signal_idx, signal) in net.signals.iter().enumerate() {
+            for &cell_idx in signal.connected_cells.iter() {
+                if let Some(m) = macro_index[cell_idx] {
+                    if macro_signals[m].last() != Some(&signal_idx) {
+                        macro_signals[m].push(signal_idx);
+                    }
+                }
+            }
+        }
+
+        let mut annealer = Self {
+            net,
+            geometry,
+            positions: vec![(0, 0, 0); macros.len()],
+            macros,
+            macro_index,
+            macro_signals,
+        };
+        // Start from wherever the previous steps put the macros
+        for m in 0..annealer.macros.len() {
+            let cell = &net.cells[annealer.macros[m]];
+            annealer.positions[m] = annealer.legal_position(m, cell.x, cell.tier_y, cell.z);
+        }
+
+        Ok(annealer)
+    }
+
+    /// Closest legal position for macro `m` to the given position
+    fn legal_position(&self, m: usize, x: f32, tier_y: f32, z: f32) -> MacroPosition {
+        let cell = &self.net.cells[self.macros[m]];
+        let snap = |v: f32, step: u32, max: u32| -> u32 {
+            let max = max / step * step;
+            ((v.max(0.0) / step as f32).round() as u32 * step).min(max)
+        };
+
+        (
+            snap(x, 2, self.geometry.size_x - cell.sx as u32),
+            snap(tier_y, 1, self.geometry.size_y - cell.s_tier_y as u32),
+            snap(z, BLOCKS_PER_Z_ROW, self.geometry.size_z - cell.sz as u32),
+        )
+    }
+
+    /// Minimum and maximum corner of macro `m` at its current position
+    fn bounds(&self, m: usize) -> (Vector3<f32>, Vector3<f32>) {
+        let cell = &self.net.cells[self.macros[m]];
+        let (x, tier_y, z) = self.positions[m];
+        let min = Vector3::new(x as f32, tier_y as f32, z as f32);
+        (min, min + Vector3::new(cell.sx, cell.s_tier_y, cell.sz))
+    }
+
+    /// Weighted half-perimeter wirelength of a signal, with the macros at their current positions
+    fn hpwl(&self, signal_idx: usize) -> f32 {
+        let signal = &self.net.signals[signal_idx];
+        let mut pins = signal
+            .connected_cells
+            .iter()
+            .zip(signal.pin_offsets.iter())
+            .map(|(&cell_idx, offset)| {
+                let center = match self.macro_index[cell_idx] {
+                    Some(m) => {
+                        let (min, max) = self.bounds(m);
+                        (min + max) / 2.0
+                    }
+                    None => self.net.cells[cell_idx].center_pos(),
+                };
+                center + offset
+            });
+        let first = match pins.next() {
+            Some(pin) => pin,
+            None => return 0.0,
+        };
+        let (min, max) = pins.fold((first, first), |(min, max), pin| {
+            (min.inf(&pin), max.sup(&pin))
+        });
+
+        signal.weight * (max - min).sum()
+    }
+
+    /// Overlap of macro `m` with the other macros and the fixed cells
+    fn overlap(&self, m: usize) -> f32 {
+        let (min, max) = self.bounds(m);
+        let overlap = |other_min: Vector3<f32>, other_max: Vector3<f32>| -> f32 {
+            (max.inf(&other_max) - min.sup(&other_min))
+                .map(|d| d.max(0.0))
+                .product()
+        };
+
+        let macros = (0..self.macros.len())
+            .filter(|&n| n != m)
+            .map(|n| {
+                let (other_min, other_max) = self.bounds(n);
+                overlap(other_min, other_max)
+            })
+            .sum::<f32>();
+        let fixed = self.net.cells[self.net.mobile_cell_count..]
+            .iter()
+            .map(|cell| {
+                let other_min = Vector3::new(cell.x, cell.tier_y, cell.z);
+                overlap(
+                    other_min,
+                    other_min + Vector3::new(cell.sx, cell.s_tier_y, cell.sz),
+                )
+            })
+            .sum::<f32>();
+
+        macros + fixed
+    }
+
+    /// Part of the cost that depends on the positions of the macros in `moved`
+    fn cost(&self, moved: &[usize]) -> f32 {
+        let mut signals: Vec<usize> = moved
+            .iter()
+            .flat_map(|&m| self.macro_signals[m].iter().copied())
+            .collect();
+        signals.sort_unstable();
+        signals.dedup();
+
+        signals.into_iter().map(|s| self.hpwl(s)).sum::<f32>()
+            + OVERLAP_PENALTY * moved.iter().map(|&m| self.overlap(m)).sum::<f32>()
+    }
+
+    /// Total wirelength of the signals connected to macros
+    fn wirelength(&self) -> f32 {
+        let mut signals: Vec<usize> = self.macro_signals.iter().flatten().copied().collect();
+        signals.sort_unstable();
+        signals.dedup();
+
+        signals.into_iter().map(|s| self.hpwl(s)).sum()
+    }
+
+    /// Pick a random move, as the macros it moves and where to. `window` from 0 to 1 scales how
+    /// far a single macro can be displaced.
+    fn propose(&self, rng: &mut XorShift, window: f32) -> Vec<(usize, MacroPosition)> {
+        let m = rng.below(self.macros.len());
+        if self.macros.len() > 1 && rng.unit() < SWAP_PROBABILITY {
+            let n = (m + 1 + rng.below(self.macros.len() - 1)) % self.macros.len();
+            let (mx, my, mz) = self.positions[m];
+            let (nx, ny, nz) = self.positions[n];
+            return vec![
+                (m, self.legal_position(m, nx as f32, ny as f32, nz as f32)),
+                (n, self.legal_position(n, mx as f32, my as f32, mz as f32)),
+            ];
+        }
+
+        let span_xz = (self.geometry.size_x.max(self.geometry.size_z) as f32 * window).max(2.0);
+        let span_y = (self.geometry.size_y as f32 * window).max(1.0);
+        let (x, tier_y, z) = self.positions[m];
+        vec![(
+            m,
+            self.legal_position(
+                m,
+                x as f32 + rng.signed_unit() * span_xz,
+                tier_y as f32 + rng.signed_unit() * span_y,
+                z as f32 + rng.signed_unit() * span_xz,
+            ),
+        )]
+    }
+
+    /// Change in cost from making `moves`. The moves are made, so they have to be undone if they
+    /// aren't accepted.
+    fn apply(&mut self, moves: &[(usize, MacroPosition)]) -> (f32, Vec<(usize, MacroPosition)>) {
+        let moved: Vec<usize> = moves.iter().map(|(m, _)| *m).collect();
+        let before = self.cost(&moved);
+        let undo = moves
+            .iter()
+            .map(|&(m, position)| (m, std::mem::replace(&mut self.positions[m], position)))
+            .collect();
+
+        (self.cost(&moved) - before, undo)
+    }
+
+    fn undo(&mut self, undo: Vec<(usize, MacroPosition)>) {
+        for (m, position) in undo {
+            self.positions[m] = position;
+        }
+    }
+
+    fn anneal(&mut self, iterations: u32) {
+        let mut rng = XorShift::new(SEED);
+
+        // Start hot enough that a typical uphill move is accepted about a third of the time
+        let mut uphill = Vec::new();
+        for _ in 0..TEMPERATURE_SAMPLES {
+            let moves = self.propose(&mut rng, 1.0);
+            let (delta, undo) = self.apply(&moves);
+            self.undo(undo);
+            if delta > 0.0 {
+                uphill.push(delta);
+            }
+        }
+        let start_temperature = if uphill.is_empty() {
+            1.0
+        } else {
+            uphill.iter().sum::<f32>() / uphill.len() as f32
+        };
+
+        for iteration in 0..iterations {
+            let progress = iteration as f32 / iterations as f32;
+            let temperature = start_temperature * FINAL_TEMPERATURE.powf(progress);
+            let moves = self.propose(&mut rng, temperature / start_temperature);
+            let (delta, undo) = self.apply(&moves);
+            if delta > 0.0 && rng.unit() >= (-delta / temperature).exp() {
+                self.undo(undo);
+            }
+        }
+    }
+}
+
+/// Place every mobile cell with a footprint of at least [`HardMacroConfig::footprint_threshold`]
+/// blocks by simulated annealing, and lock them there. The mobile cells are reordered by
+/// [`NetlistHypergraph::lock_cells`].
+pub fn place_hard_macros(
+    config: &HardMacroConfig,
+    geometry: &GeometryConfig,
+    net: &mut NetlistHypergraph,
+) -> Result<()> {
+    let macros: Vec<usize> = (0..net.mobile_cell_count)
+        .filter(|&idx| {
+            let cell = &net.cells[idx];
+            (cell.sx * cell.sz) as u32 >= config.footprint_threshold
+        })
+        .collect();
+    if macros.is_empty() {
+        tracing::info!(
+            "No cells with a footprint of {} blocks or more",
+            config.footprint_threshold
+        );
+        return Ok(());
+    }
+
+    let mut annealer = Annealer::new(net, geometry, macros)?;
+    let initial_wirelength = annealer.wirelength();
+    annealer.anneal(config.iterations);
+    tracing::info!(
+        "Placed {} hard macros, wirelength {} -> {}",
+        annealer.macros.len(),
+        initial_wirelength,
+        annealer.wirelength()
+    );
+
+    let overlapping = (0..annealer.macros.len())
+        .filter(|&m| annealer.overlap(m) > 0.0)
+        .count();
+    ensure!(
+        overlapping == 0,
+        "{} hard macros still overlap after annealing, the placement region is too small for them",
+        overlapping
+    );
+
+    let Annealer {
+        macros, positions, ..
+    } = annealer;
+    for (&cell_idx, &(x, tier_y, z)) in macros.iter().zip(positions.iter()) {
+        let cell = &mut net.cells[cell_idx];
+        cell.x = x as f32;
+        cell.tier_y = tier_y as f32;
+        cell.z = z as f32;
+    }
+    net.lock_cells(&macros);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::place_hard_macros;
+    use crate::{
+        config::{GeometryConfig, HardMacroConfig},
+        netlist,
+    };
+
+    /// Two macros stacked on top of each other are pulled apart, each towards the fixed cell it's
+    /// connected to, and locked there without overlapping.
+    #[test]
+    fn macros_spread_towards_their_nets() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let mut net = netlist![
+            cells: [
+                macro_a => (8, 1, 8);
+                macro_b => (8, 1, 8);
+                gate => (1, 1, 2);
+            ],
+            fixed_cells: [
+                fixed_a => (0, 0, 0), (2, 1, 2);
+                fixed_b => (62, 0, 62), (2, 1, 2);
+            ],
+            signals: [
+                [fixed_a, macro_a],
+                [fixed_b, macro_b]
+            ]
+        ];
+        for cell in net.cells[..net.mobile_cell_count].iter_mut() {
+            cell.x = 28.0;
+            cell.z = 28.0;
+        }
+
+        let config = HardMacroConfig {
+            footprint_threshold: 64,
+            iterations: 4000,
+        };
+        let geometry = GeometryConfig {
+            size_x: 64,
+            size_y: 1,
+            size_z: 64,
+            target_fill: 0.8,
+            density_map: Vec::new(),
+        };
+        place_hard_macros(&config, &geometry, &mut net).expect("Placement success");
+
+        // The unconnected gate stays mobile and moves to the front, the macros are locked after it
+        assert_eq!(net.mobile_cell_count, 1);
+        let (a, b) = (&net.cells[1], &net.cells[2]);
+        assert!(a.pos_locked && b.pos_locked);
+        assert!(a.x + a.sx <= b.x || a.z + a.sz <= b.z);
+        assert!(a.x + a.z < 32.0, "macro_a at {}, {}", a.x, a.z);
+        assert!(b.x + b.z > 64.0, "macro_b at {}, {}", b.x, b.z);
+    }
+}
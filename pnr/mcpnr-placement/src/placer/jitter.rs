@@ -0,0 +1,26 @@
+//! Deterministic jitter of the mobile cells. Analytical placement of symmetric circuits often puts
+//! many cells at exactly the same coordinates, and diffusion can't pull apart cells that sit on
+//! top of each other since they all see the same density gradient. A small random offset breaks
+//! the tie.
+
+use mcpnr_common::xorshift::XorShift;
+
+use crate::{config::GeometryConfig, core::NetlistHypergraph};
+
+/// Move every mobile cell by a pseudo-random offset of up to `magnitude` blocks along X and Z,
+/// drawn from a generator seeded with `seed`. Y is left alone, a tier is far more than a jitter.
+/// Cells are kept inside the placement region.
+pub fn jitter_cells(
+    geometry: &GeometryConfig,
+    net: &mut NetlistHypergraph,
+    magnitude: f32,
+    seed: u64,
+) {
+    let mut rng = XorShift::new(seed);
+    for cell in net.cells.iter_mut().take(net.mobile_cell_count) {
+        let dx = rng.signed_unit() * magnitude;
+        let dz = rng.signed_unit() * magnitude;
+        cell.x = (cell.x + dx).clamp(0.0, (geometry.size_x as f32 - cell.sx).max(0.0));
+        cell.z = (cell.z + dz).clamp(0.0, (geometry.size_z as f32 - cell.sz).max(0.0));
+    }
+}
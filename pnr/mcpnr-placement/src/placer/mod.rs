@@ -4,5 +4,10 @@
 mod test;
 
 pub mod analytical;
+pub mod clusters;
 pub mod diffusion;
+pub mod hard_macros;
+pub mod jitter;
+pub mod region;
+pub mod spectral;
 
@@ -0,0 +1,141 @@
+//! Incremental placement, for placing the cells of one part of an already placed design again
+//! after it's been edited by hand or by an ECO. Every cell outside the region is locked where it
+//! is, so the usual placement steps treat them as fixed anchors and only move the cells inside.
+
+use std::str::FromStr;
+
+use anyhow::{anyhow, ensure, Context, Result};
+
+use crate::{core::NetlistHypergraph, placement_cell::LegalizedCell};
+
+/// Box of the placement region, on every tier, from the minimum corner up to but not including
+/// the maximum one. Written `X0,Z0,X1,Z1` in blocks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub min_x: u32,
+    pub min_z: u32,
+    pub max_x: u32,
+    pub max_z: u32,
+}
+
+impl FromStr for Region {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let coords = s
+            .split(',')
+            .map(|coord| {
+                coord
+                    .trim()
+                    .parse::<u32>()
+                    .with_context(|| anyhow!("Invalid coordinate {:?}", coord))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let (min_x, min_z, max_x, max_z) = match coords.as_slice() {
+            [min_x, min_z, max_x, max_z] => (*min_x, *min_z, *max_x, *max_z),
+            _ => return Err(anyhow!("Expected X0,Z0,X1,Z1, got {:?}", s)),
+        };
+        ensure!(
+            min_x < max_x && min_z < max_z,
+            "Region {:?} is empty, the second corner has to be past the first",
+            s
+        );
+
+        Ok(Self {
+            min_x,
+            min_z,
+            max_x,
+            max_z,
+        })
+    }
+}
+
+impl Region {
+    /// Whether all of `cell` is inside the region
+    pub fn contains(&self, cell: &LegalizedCell) -> bool {
+        cell.x >= self.min_x
+            && cell.z >= self.min_z
+            && cell.x + cell.sx <= self.max_x
+            && cell.z + cell.sz <= self.max_z
+    }
+}
+
+/// Lock every cell of `net` that isn't wholly inside `region` where it is, leaving the cells
+/// inside free to move. Like [`NetlistHypergraph::lock_cells`], this reorders the cells. Returns
+/// the number of cells left to place.
+pub fn lock_outside(net: &mut NetlistHypergraph, region: &Region) -> usize {
+    let outside: Vec<usize> = (0..net.mobile_cell_count)
+        .filter(|&idx| !region.contains(&LegalizedCell::from_placement(&net.cells[idx])))
+        .collect();
+    net.lock_cells(&outside);
+    net.mobile_cell_count
+}
+
+/// Pull the cells still free to move back inside `region`, for any placement step that pushed
+/// them out. Cells too big for the region are left at its minimum corner.
+pub fn clamp_inside(net: &mut NetlistHypergraph, region: &Region) {
+    for cell in net.cells[..net.mobile_cell_count].iter_mut() {
+        let max_x = (region.max_x as f32 - cell.sx).max(region.min_x as f32);
+        let max_z = (region.max_z as f32 - cell.sz).max(region.min_z as f32);
+        cell.x = cell.x.clamp(region.min_x as f32, max_x);
+        cell.z = cell.z.clamp(region.min_z as f32, max_z);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{clamp_inside, lock_outside, Region};
+    use crate::{core::NetlistHypergraph, placement_cell::PlacementCell};
+
+    fn cell(x: u32, z: u32, pos_locked: bool) -> PlacementCell {
+        PlacementCell {
+            x: x as f32,
+            tier_y: 0.0,
+            z: z as f32,
+            sx: 4.0,
+            s_tier_y: 1.0,
+            sz: 6.0,
+            pos_locked,
+        }
+    }
+
+    #[test]
+    fn regions_parse_from_corners() {
+        assert_eq!(
+            "8,0,24,16".parse::<Region>().unwrap(),
+            Region {
+                min_x: 8,
+                min_z: 0,
+                max_x: 24,
+                max_z: 16
+            }
+        );
+        assert!("8,0,24".parse::<Region>().is_err());
+        assert!("8,0,8,16".parse::<Region>().is_err());
+        assert!("8,0,x,16".parse::<Region>().is_err());
+    }
+
+    #[test]
+    fn only_cells_inside_stay_mobile() {
+        // Inside, straddling the edge, outside, and an I/O macro that was never mobile
+        let cells = vec![
+            cell(8, 0, false),
+            cell(22, 0, false),
+            cell(40, 8, false),
+            cell(10, 8, true),
+        ];
+        let mut net = NetlistHypergraph::test_new(cells, 3, Vec::new());
+        let region: Region = "8,0,24,16".parse().unwrap();
+
+        assert_eq!(lock_outside(&mut net, &region), 1);
+        assert_eq!((net.cells[0].x, net.cells[0].z), (8.0, 0.0));
+        assert!(net.cells[1..].iter().all(|cell| cell.pos_locked));
+
+        net.cells[0].x = 30.0;
+        net.cells[0].z = -2.0;
+        clamp_inside(&mut net, &region);
+        assert_eq!((net.cells[0].x, net.cells[0].z), (20.0, 0.0));
+        // Locked cells stay put
+        assert_eq!(net.cells[1].x, 22.0);
+    }
+}
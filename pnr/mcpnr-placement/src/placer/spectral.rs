@@ -0,0 +1,381 @@
+//! Spectral placement, which spreads the cells out along the eigenvectors of the netlist's graph
+//! Laplacian with the smallest nontrivial eigenvalues.
+//!
+//! The unconstrained quadratic solve pulls every mobile cell towards the fixed cells, and in
+//! designs with few I/O connections that collapses everything in to a small blob that diffusion
+//! then has to tear apart. The low eigenvectors of the Laplacian minimize the same quadratic
+//! wirelength, but under the constraint that the cells are spread out (unit norm, and orthogonal
+//! to each other and to the trivial constant eigenvector). That gives a starting point where
+//! connected cells are still close together, but the design as a whole is already spread out.
+use anyhow::Result;
+use mcpnr_common::xorshift::XorShift;
+use ndarray::{Array1, Array2};
+
+use crate::{
+    config::GeometryConfig,
+    core::NetlistHypergraph,
+    placer::analytical::sparse::{CsrMatrix, SparseMatrixBuilder},
+};
+
+/// Number of Lanczos iterations. The whole basis is kept for reorthogonalization, so this also
+/// sets the memory used, see [`basis_size`].
+pub const LANCZOS_STEPS: usize = 64;
+
+/// Nets with more pins than this are left out of the Laplacian. They're usually clocks and resets,
+/// which say little about where cells should go and would add a dense block to the matrix.
+pub const MAX_NET_PINS: usize = 64;
+
+/// Ritz values below this fraction of the largest one are taken to be zero. Those come from parts
+/// of the netlist that are not connected to the rest, and their eigenvectors place every cell of a
+/// part at the same coordinate.
+const ZERO_EIGENVALUE: f64 = 1e-9;
+
+/// Seed of the pseudo-random vector the Lanczos iteration starts from
+const START_SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+/// Most sweeps of Jacobi rotations to diagonalize the Lanczos tridiagonal with in the web build.
+/// It converges quadratically, so this is never reached in practice.
+#[cfg(any(test, target_arch = "wasm32"))]
+const JACOBI_SWEEPS: usize = 50;
+
+/// Bytes of Lanczos basis needed to place `mobile_cells` cells
+pub fn basis_size(mobile_cells: usize) -> u64 {
+    (LANCZOS_STEPS as u64 + 1) * mobile_cells as u64 * std::mem::size_of::<f64>() as u64
+}
+
+/// Place the mobile cells along the low eigenvectors of the netlist Laplacian: the first along X,
+/// the second along Z and, in designs with more than one tier, the third along Y. Each axis is
+/// scaled to span the placement region, and flipped so cells connected to the fixed cells end up
+/// on their side of the region.
+pub fn place_spectral(geometry: &GeometryConfig, net: &mut NetlistHypergraph) -> Result<()> {
+    if net.mobile_cell_count < 2 {
+        return Ok(());
+    }
+
+    let axes: &[usize] = if geometry.size_y > 1 {
+        &[0, 2, 1]
+    } else {
+        &[0, 2]
+    };
+
+    let laplacian = tracing::debug_span!("laplacian").in_scope(|| laplacian(net));
+    tracing::debug!(nnz = laplacian.nnz(), "Sparse laplacian");
+
+    let eigenvectors = tracing::debug_span!("lanczos")
+        .in_scope(|| smallest_eigenvectors(&laplacian, axes.len()))?;
+    if eigenvectors.len() < axes.len() {
+        tracing::warn!(
+            found = eigenvectors.len(),
+            wanted = axes.len(),
+            "Too few nontrivial eigenvectors, leaving the remaining axes alone"
+        );
+    }
+
+    for (&axis, mut vector) in axes.iter().zip(eigenvectors) {
+        if fixed_pull(geometry, net, axis, &vector) < 0.0 {
+            vector.mapv_inplace(|v| -v);
+        }
+        spread_along(geometry, net, axis, &vector);
+    }
+
+    Ok(())
+}
+
+/// Laplacian of the graph formed by the mobile cells, connecting every pair of mobile cells on a
+/// net with the weight a clique model would give them. Connections to fixed cells are left out,
+/// otherwise the constant vector would no longer be an eigenvector.
+fn laplacian(net: &NetlistHypergraph) -> CsrMatrix {
+    let mut builder = SparseMatrixBuilder::new(net.mobile_cell_count);
+    for signal in net.signals.iter() {
+        let pins = signal.connected_cells.len();
+        if signal.moveable_cells < 2 || pins > MAX_NET_PINS {
+            continue;
+        }
+
+        let weight = (signal.weight / ((pins - 1) as f32)) as f64;
+        let mobile: Vec<usize> = signal.iter_mobile(net).collect();
+        for (idx, &i) in mobile.iter().enumerate() {
+            for &j in mobile[idx + 1..].iter() {
+                builder.add(i, i, weight);
+                builder.add(j, j, weight);
+                builder.add(i, j, -weight);
+                builder.add(j, i, -weight);
+            }
+        }
+    }
+
+    builder.build()
+}
+
+/// Approximate the eigenvectors for the `count` smallest nonzero eigenvalues of the Laplacian
+/// `laplacian`, smallest first, using Lanczos iteration with full reorthogonalization. The constant
+/// vector is projected out of the Krylov space, as it's always an eigenvector with eigenvalue zero.
+/// Returns fewer than `count` vectors if the Krylov space runs out before then.
+fn smallest_eigenvectors(laplacian: &CsrMatrix, count: usize) -> Result<Vec<Array1<f64>>> {
+    let size = laplacian.size();
+    if size < 2 {
+        return Ok(vec![]);
+    }
+    let steps = LANCZOS_STEPS.min(size - 1);
+    let constant = Array1::from_elem(size, 1.0 / (size as f64).sqrt());
+
+    // Fixed pseudo-random start, so the placement is the same from run to run
+    let mut rng = XorShift::new(START_SEED);
+    let mut start = Array1::from_shape_fn(size, |_| rng.signed_unit() as f64);
+    let projection = constant.dot(&start);
+    start.scaled_add(-projection, &constant);
+    let norm = start.dot(&start).sqrt();
+    start /= norm;
+
+    let mut basis = vec![start];
+    let mut alpha = Vec::with_capacity(steps);
+    let mut beta = Vec::with_capacity(steps);
+    let mut w = Array1::zeros(size);
+    for step in 0..steps {
+        laplacian.mul_vec_into(&basis[step], &mut w);
+        alpha.push(basis[step].dot(&w));
+
+        // Orthogonalizing twice keeps the basis orthogonal to working precision
+        for _ in 0..2 {
+            for q in std::iter::once(&constant).chain(basis.iter()) {
+                let projection = q.dot(&w);
+                w.scaled_add(-projection, q);
+            }
+        }
+
+        let norm = w.dot(&w).sqrt();
+        // A tiny remainder means the Krylov space is invariant, there's nothing more to find
+        if step + 1 == steps || norm < 1e-12 {
+            break;
+        }
+        beta.push(norm);
+        basis.push(&w / norm);
+    }
+
+    // Eigen decomposition of the tridiagonal projection of the Laplacian on to the basis
+    let m = alpha.len();
+    let mut tridiagonal = Array2::zeros((m, m));
+    for (i, &a) in alpha.iter().enumerate() {
+        tridiagonal[(i, i)] = a;
+    }
+    for (i, &b) in beta.iter().enumerate() {
+        tridiagonal[(i, i + 1)] = b;
+        tridiagonal[(i + 1, i)] = b;
+    }
+    let (values, vectors) = symmetric_eigen(tridiagonal)?;
+
+    let largest = values.iter().copied().fold(0.0, f64::max);
+    Ok((0..m)
+        .filter(|&k| values[k] > ZERO_EIGENVALUE * largest)
+        .take(count)
+        .map(|k| {
+            tracing::debug!(eigenvalue = values[k], "Ritz pair");
+            let mut ritz = Array1::zeros(size);
+            for (j, q) in basis.iter().enumerate() {
+                ritz.scaled_add(vectors[(j, k)], q);
+            }
+            ritz
+        })
+        .collect())
+}
+
+/// Eigenvalues of a symmetric matrix in ascending order, with the matching eigenvectors as columns
+#[cfg(not(target_arch = "wasm32"))]
+fn symmetric_eigen(matrix: Array2<f64>) -> Result<(Array1<f64>, Array2<f64>)> {
+    use anyhow::Context;
+    use ndarray_linalg::{Eigh, UPLO};
+
+    matrix
+        .eigh(UPLO::Lower)
+        .context("Eigen decomposition of the Lanczos tridiagonal")
+}
+
+/// There's no LAPACK in the web build, so the tridiagonal is diagonalized with Jacobi rotations
+#[cfg(target_arch = "wasm32")]
+fn symmetric_eigen(matrix: Array2<f64>) -> Result<(Array1<f64>, Array2<f64>)> {
+    Ok(jacobi_eigen(matrix))
+}
+
+/// Cyclic Jacobi eigen decomposition of the symmetric matrix `a`, returning the same as
+/// [`symmetric_eigen`]. Every sweep is O(n^3), which is fine for the [`LANCZOS_STEPS`] sized
+/// tridiagonal but not for anything much bigger.
+#[cfg(any(test, target_arch = "wasm32"))]
+fn jacobi_eigen(mut a: Array2<f64>) -> (Array1<f64>, Array2<f64>) {
+    let n = a.nrows();
+    let mut v = Array2::eye(n);
+
+    for _ in 0..JACOBI_SWEEPS {
+        let off_diagonal: f64 = a
+            .indexed_iter()
+            .filter(|((i, j), _)| i != j)
+            .map(|(_, x)| x * x)
+            .sum();
+        let diagonal: f64 = a.diag().iter().map(|x| x * x).sum();
+        if off_diagonal <= f64::EPSILON * f64::EPSILON * diagonal {
+            break;
+        }
+
+        for p in 0..n {
+            for q in p + 1..n {
+                let apq = a[(p, q)];
+                if apq == 0.0 {
+                    continue;
+                }
+                // Rotate in the (p, q) plane by the angle that zeroes a[(p, q)]
+                let theta = (a[(q, q)] - a[(p, p)]) / (2.0 * apq);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+
+                for k in 0..n {
+                    let (akp, akq) = (a[(k, p)], a[(k, q)]);
+                    a[(k, p)] = c * akp - s * akq;
+                    a[(k, q)] = s * akp + c * akq;
+                }
+                for k in 0..n {
+                    let (apk, aqk) = (a[(p, k)], a[(q, k)]);
+                    a[(p, k)] = c * apk - s * aqk;
+                    a[(q, k)] = s * apk + c * aqk;
+                }
+                for k in 0..n {
+                    let (vkp, vkq) = (v[(k, p)], v[(k, q)]);
+                    v[(k, p)] = c * vkp - s * vkq;
+                    v[(k, q)] = s * vkp + c * vkq;
+                }
+            }
+        }
+    }
+
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|&i, &j| a[(i, i)].total_cmp(&a[(j, j)]));
+    let values = order.iter().map(|&i| a[(i, i)]).collect();
+    let vectors = Array2::from_shape_fn((n, n), |(row, col)| v[(row, order[col])]);
+    (values, vectors)
+}
+
+fn axis_size(geometry: &GeometryConfig, axis: usize) -> f32 {
+    match axis {
+        0 => geometry.size_x as f32,
+        1 => geometry.size_y as f32,
+        2 => geometry.size_z as f32,
+        _ => panic!("Invalid axis {}", axis),
+    }
+}
+
+/// How much the cells connected to fixed cells lean towards them along `axis` when placed by
+/// `vector`. Negative if the vector would place them on the far side of the region.
+fn fixed_pull(
+    geometry: &GeometryConfig,
+    net: &NetlistHypergraph,
+    axis: usize,
+    vector: &Array1<f64>,
+) -> f64 {
+    let center = axis_size(geometry, axis) / 2.0;
+    net.signals
+        .iter()
+        .filter(|signal| signal.moveable_cells < signal.connected_cells.len())
+        .map(|signal| {
+            let fixed: f32 = (0..signal.connected_cells.len())
+                .filter(|&pin| net.cells[signal.connected_cells[pin]].pos_locked)
+                .map(|pin| signal.pin_pos(net, pin)[axis] - center)
+                .sum();
+            let mobile: f64 = signal.iter_mobile(net).map(|i| vector[i]).sum();
+            fixed as f64 * mobile
+        })
+        .sum()
+}
+
+/// Move the mobile cells along `axis` so the smallest entry of `vector` is at the start of the
+/// region and the largest at the end.
+fn spread_along(
+    geometry: &GeometryConfig,
+    net: &mut NetlistHypergraph,
+    axis: usize,
+    vector: &Array1<f64>,
+) {
+    let min = vector.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = vector.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let range = max - min;
+    let size = axis_size(geometry, axis);
+
+    for (i, cell) in net.cells.iter_mut().take(net.mobile_cell_count).enumerate() {
+        let t = if range > 0.0 {
+            ((vector[i] - min) / range) as f32
+        } else {
+            0.5
+        };
+        match axis {
+            0 => cell.x = t * (size - cell.sx).max(0.0),
+            1 => cell.tier_y = t * (size - cell.s_tier_y).max(0.0),
+            2 => cell.z = t * (size - cell.sz).max(0.0),
+            _ => panic!("Invalid axis {}", axis),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use approx::assert_relative_eq;
+
+    use ndarray::Array2;
+
+    use super::{jacobi_eigen, smallest_eigenvectors, symmetric_eigen};
+    use crate::placer::analytical::sparse::SparseMatrixBuilder;
+
+    /// The eigenvectors of a path graph's Laplacian are cosines, with the Fiedler vector going
+    /// monotonically from one end of the path to the other.
+    #[test]
+    fn path_graph_eigenvectors() {
+        let n = 20;
+        let mut builder = SparseMatrixBuilder::new(n);
+        for i in 0..n - 1 {
+            builder.add(i, i, 1.0);
+            builder.add(i + 1, i + 1, 1.0);
+            builder.add(i, i + 1, -1.0);
+            builder.add(i + 1, i, -1.0);
+        }
+
+        let vectors = smallest_eigenvectors(&builder.build(), 2).expect("Lanczos success");
+        assert_eq!(vectors.len(), 2);
+
+        for (k, vector) in vectors.iter().enumerate() {
+            let expected: Vec<f64> = (0..n)
+                .map(|i| {
+                    (std::f64::consts::PI * (k + 1) as f64 * (i as f64 + 0.5) / n as f64).cos()
+                })
+                .collect();
+            let norm = expected.iter().map(|e| e * e).sum::<f64>().sqrt();
+            let sign = vector[0].signum() / norm;
+            for i in 0..n {
+                assert_relative_eq!(vector[i], sign * expected[i], epsilon = 1e-6);
+            }
+        }
+    }
+
+    /// The web build's Jacobi decomposition agrees with LAPACK on a Lanczos-like tridiagonal
+    #[test]
+    fn jacobi_matches_lapack() {
+        let n = 12;
+        let tridiagonal = Array2::from_shape_fn((n, n), |(i, j)| match i.abs_diff(j) {
+            0 => 2.0 + (i as f64 * 0.7).sin(),
+            1 => 0.5 + 0.1 * i.min(j) as f64,
+            _ => 0.0,
+        });
+
+        let (values, vectors) = jacobi_eigen(tridiagonal.clone());
+        let (expected_values, expected_vectors) =
+            symmetric_eigen(tridiagonal).expect("LAPACK success");
+        for k in 0..n {
+            assert_relative_eq!(values[k], expected_values[k], epsilon = 1e-9);
+            // Eigenvectors are only defined up to their sign
+            let sign = vectors.column(k).dot(&expected_vectors.column(k)).signum();
+            for i in 0..n {
+                assert_relative_eq!(
+                    vectors[(i, k)],
+                    sign * expected_vectors[(i, k)],
+                    epsilon = 1e-9
+                );
+            }
+        }
+    }
+}
@@ -2,6 +2,7 @@ use crate::{
     core::{NetlistHypergraph, Signal},
     placement_cell::PlacementCell,
 };
+use nalgebra::Vector3;
 use std::collections::{hash_map::Entry, HashMap};
 
 /// Test utility function to build a netlist from a list of named cells and their sizes (or sizes +
@@ -20,10 +21,10 @@ pub fn make_netlist<'a>(
         let cell_idx = cells.len();
         cells.push(PlacementCell {
             x: 0.0,
-            y: 0.0,
+            tier_y: 0.0,
             z: 0.0,
             sx: *sx as f32,
-            sy: *sy as f32,
+            s_tier_y: *sy as f32,
             sz: *sz as f32,
             pos_locked: false,
         });
@@ -40,10 +41,10 @@ pub fn make_netlist<'a>(
         let cell_idx = cells.len();
         cells.push(PlacementCell {
             x: *x as f32,
-            y: *y as f32,
+            tier_y: *y as f32,
             z: *z as f32,
             sx: *sx as f32,
-            sy: *sy as f32,
+            s_tier_y: *sy as f32,
             sz: *sz as f32,
             pos_locked: true,
         });
@@ -64,7 +65,10 @@ pub fn make_netlist<'a>(
                     .iter()
                     .filter(|idx| !cells[**idx].pos_locked)
                     .count(),
+                pin_offsets: vec![Vector3::zeros(); connected_cells.len()],
                 connected_cells,
+                id: 0,
+                weight: 1.0,
             }
         })
         .collect();
@@ -0,0 +1,365 @@
+//! Implementation of the `sweep` subcommand, which places a design once for every combination of
+//! a set of option values and summarizes the results, instead of editing the options and rerunning
+//! the placer by hand. Each run is a separate `place` process, so runs can go in parallel and one
+//! that crashes doesn't take the others with it.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use itertools::Itertools;
+use mcpnr_common::results::{self, RunRecord};
+use mcpnr_placement::cli::place_command;
+use serde::Deserialize;
+use tracing::{info, warn};
+
+/// Arguments of the `sweep` subcommand
+pub struct SweepArgs {
+    /// JSON file describing the sweep, see [`SweepFile`]
+    pub sweep_file: PathBuf,
+    /// Directory to write the placed design, log and results of each run to
+    pub dir: PathBuf,
+    /// Number of runs to have going at once
+    pub jobs: usize,
+    /// Results file to log every run to as well, if any
+    pub results_file: Option<PathBuf>,
+}
+
+/// Sweep description, like
+///
+/// ```json
+/// {
+///     "args": ["--techlib", "techlib", "--size-x", "64", "counter.json"],
+///     "parameters": {
+///         "delta-t": [0.05, 0.1, 0.2],
+///         "clique-threshold": "3..6",
+///         "legalizer": ["tetris", "abacus"]
+///     }
+/// }
+/// ```
+#[derive(Debug, Deserialize)]
+struct SweepFile {
+    /// Arguments of the place subcommand shared by every run, including the input design but not
+    /// the output file
+    args: Vec<String>,
+    /// Values to try for each option, by the long name of the option
+    parameters: BTreeMap<String, Values>,
+}
+
+/// Values to try for one option
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Values {
+    /// Values of an option that takes one, or `true` and `false` for a flag
+    List(Vec<serde_json::Value>),
+    /// Inclusive range of integers, like `"3..6"`
+    Range(String),
+}
+
+/// One value of one option, as it's passed to the placer
+#[derive(Clone, Debug)]
+struct Setting {
+    option: String,
+    /// `None` for a flag that's left out, `Some("")` for a flag that's passed
+    value: Option<String>,
+}
+
+impl Setting {
+    fn args(&self) -> Vec<String> {
+        match self.value.as_deref() {
+            None => Vec::new(),
+            Some("") => vec![format!("--{}", self.option)],
+            Some(value) => vec![format!("--{}", self.option), value.to_owned()],
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self.value.as_deref() {
+            None => "off".to_owned(),
+            Some("") => "on".to_owned(),
+            Some(value) => value.to_owned(),
+        }
+    }
+}
+
+/// How one run of the sweep went
+enum Outcome {
+    Placed(RunRecord),
+    Failed(String),
+}
+
+fn load_sweep(path: &Path) -> Result<SweepFile> {
+    let file = std::fs::File::open(path).with_context(|| anyhow!("Open sweep {:?}", path))?;
+    serde_json::from_reader(std::io::BufReader::new(file))
+        .with_context(|| anyhow!("Parse sweep {:?}", path))
+}
+
+/// The settings to try for `option`
+fn expand(option: &str, values: &Values) -> Result<Vec<Setting>> {
+    let setting = |value| Setting {
+        option: option.to_owned(),
+        value,
+    };
+
+    let settings = match values {
+        Values::List(values) => values
+            .iter()
+            .map(|value| match value {
+                serde_json::Value::Bool(true) => Ok(setting(Some(String::new()))),
+                serde_json::Value::Bool(false) => Ok(setting(None)),
+                serde_json::Value::Number(n) => Ok(setting(Some(n.to_string()))),
+                serde_json::Value::String(s) if !s.is_empty() => Ok(setting(Some(s.clone()))),
+                _ => Err(anyhow!(
+                    "Values of {} must be numbers, strings or booleans, not {}",
+                    option,
+                    value
+                )),
+            })
+            .collect::<Result<Vec<_>>>()?,
+        Values::Range(range) => {
+            let (start, end) = range
+                .split_once("..")
+                .ok_or_else(|| anyhow!("{:?} for {} isn't a range like 3..6", range, option))?;
+            let start: i64 = start.trim().parse().context("Parse start of range")?;
+            let end: i64 = end.trim().parse().context("Parse end of range")?;
+            (start..=end)
+                .map(|i| setting(Some(i.to_string())))
+                .collect()
+        }
+    };
+
+    ensure!(!settings.is_empty(), "No values to try for {}", option);
+    Ok(settings)
+}
+
+/// Every combination of the settings of the parameters of `sweep`
+fn combinations(sweep: &SweepFile) -> Result<Vec<Vec<Setting>>> {
+    ensure!(
+        !sweep.parameters.is_empty(),
+        "The sweep has no parameters to vary"
+    );
+    let settings = sweep
+        .parameters
+        .iter()
+        .map(|(option, values)| expand(option, values))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(settings.into_iter().multi_cartesian_product().collect())
+}
+
+/// Arguments of the place subcommand for run `idx`, writing its files to `dir`
+fn run_args(sweep: &SweepFile, dir: &Path, idx: usize, settings: &[Setting]) -> Vec<String> {
+    let mut args = vec!["place".to_owned()];
+    args.extend(sweep.args.iter().cloned());
+    args.extend(settings.iter().flat_map(Setting::args));
+    args.push("--results".to_owned());
+    args.push(run_file(dir, idx, "csv").to_string_lossy().into_owned());
+    args.push(run_file(dir, idx, "pb").to_string_lossy().into_owned());
+    args
+}
+
+fn run_file(dir: &Path, idx: usize, extension: &str) -> PathBuf {
+    dir.join(format!("run-{}.{}", idx, extension))
+}
+
+/// Run the placer with `args` as run `idx`, and read back the results it logged
+fn place(args: &[String], dir: &Path, idx: usize) -> Result<Outcome> {
+    let results_path = run_file(dir, idx, "csv");
+    let log_path = run_file(dir, idx, "log");
+    // Results from an earlier sweep in to the same directory would be appended to
+    if results_path.exists() {
+        std::fs::remove_file(&results_path)
+            .with_context(|| anyhow!("Remove old results {:?}", results_path))?;
+    }
+
+    let log = std::fs::File::create(&log_path)
+        .with_context(|| anyhow!("Create log file {:?}", log_path))?;
+    let status =
+        std::process::Command::new(std::env::current_exe().context("Find the placer executable")?)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(log.try_clone()?)
+            .stderr(log)
+            .status()
+            .with_context(|| anyhow!("Start run {}", idx))?;
+
+    if !status.success() {
+        return Ok(Outcome::Failed(format!(
+            "{}, see {:?}",
+            status,
+            log_path.file_name().unwrap_or_default()
+        )));
+    }
+    match results::read_runs(&results_path)?.pop() {
+        Some(record) => Ok(Outcome::Placed(record)),
+        None => bail!("Run {} didn't log any results to {:?}", idx, results_path),
+    }
+}
+
+/// Print a table of the runs, best HPWL first, and the mean HPWL of each value of each parameter
+fn print_summary<W: Write>(
+    mut w: W,
+    sweep: &SweepFile,
+    runs: &[Vec<Setting>],
+    outcomes: &[Outcome],
+) -> std::io::Result<()> {
+    let order = (0..runs.len())
+        .sorted_by(|&a, &b| match (&outcomes[a], &outcomes[b]) {
+            (Outcome::Placed(a), Outcome::Placed(b)) => a
+                .hpwl
+                .partial_cmp(&b.hpwl)
+                .unwrap_or(std::cmp::Ordering::Equal),
+            (Outcome::Placed(_), Outcome::Failed(_)) => std::cmp::Ordering::Less,
+            (Outcome::Failed(_), Outcome::Placed(_)) => std::cmp::Ordering::Greater,
+            _ => a.cmp(&b),
+        })
+        .collect_vec();
+
+    let header = std::iter::once("run".to_owned())
+        .chain(sweep.parameters.keys().cloned())
+        .chain(["hpwl".to_owned(), "runtime".to_owned()])
+        .collect_vec();
+    let rows = order
+        .iter()
+        .map(|&idx| {
+            let mut row = vec![idx.to_string()];
+            row.extend(runs[idx].iter().map(Setting::describe));
+            match &outcomes[idx] {
+                Outcome::Placed(record) => {
+                    row.push(record.hpwl.map_or("-".to_owned(), |h| format!("{:.0}", h)));
+                    row.push(format!("{:.1}s", record.runtime));
+                }
+                Outcome::Failed(reason) => {
+                    row.push(format!("failed ({})", reason));
+                    row.push(String::new());
+                }
+            }
+            row
+        })
+        .collect_vec();
+    let widths = (0..header.len())
+        .map(|col| {
+            rows.iter()
+                .map(|row| row[col].len())
+                .chain(std::iter::once(header[col].len()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect_vec();
+
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        let line = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, &width)| format!("{:<width$}", cell, width = width))
+            .join("  ");
+        writeln!(w, "{}", line.trim_end())?;
+    }
+
+    // The mean over every run with each value shows which way each parameter pulls
+    writeln!(w)?;
+    for (param_idx, option) in sweep.parameters.keys().enumerate() {
+        let mut means: Vec<(String, f64, usize)> = Vec::new();
+        for (settings, outcome) in runs.iter().zip(outcomes.iter()) {
+            let hpwl = match outcome {
+                Outcome::Placed(RunRecord {
+                    hpwl: Some(hpwl), ..
+                }) => *hpwl,
+                _ => continue,
+            };
+            let value = settings[param_idx].describe();
+            match means.iter_mut().find(|(v, _, _)| *v == value) {
+                Some((_, total, count)) => {
+                    *total += hpwl;
+                    *count += 1;
+                }
+                None => means.push((value, hpwl, 1)),
+            }
+        }
+        let means = means
+            .iter()
+            .map(|(value, total, count)| format!("{} {:.0}", value, total / *count as f64))
+            .join(", ");
+        writeln!(w, "mean hpwl by {}: {}", option, means)?;
+    }
+
+    Ok(())
+}
+
+/// Run the sweep in `args` and print a summary of the results to stdout
+pub fn run_sweep(args: &SweepArgs) -> Result<()> {
+    let sweep = load_sweep(&args.sweep_file)?;
+    let runs = combinations(&sweep)?;
+    std::fs::create_dir_all(&args.dir)
+        .with_context(|| anyhow!("Create sweep directory {:?}", args.dir))?;
+
+    // A typo in an option would otherwise only show up in the logs of every single run
+    let arg_lists = runs
+        .iter()
+        .enumerate()
+        .map(|(idx, settings)| run_args(&sweep, &args.dir, idx, settings))
+        .collect_vec();
+    for (idx, arg_list) in arg_lists.iter().enumerate() {
+        place_command()
+            .try_get_matches_from(arg_list)
+            .map_err(|e| anyhow!("{}", e))
+            .with_context(|| anyhow!("Check the arguments of run {}", idx))?;
+    }
+
+    info!(
+        "Sweeping {} combinations, {} at a time, in to {:?}",
+        runs.len(),
+        args.jobs,
+        args.dir
+    );
+    let next = AtomicUsize::new(0);
+    let outcomes: Mutex<Vec<Option<Outcome>>> =
+        Mutex::new(std::iter::repeat_with(|| None).take(runs.len()).collect());
+    std::thread::scope(|scope| {
+        for _ in 0..args.jobs.max(1) {
+            scope.spawn(|| loop {
+                let idx = next.fetch_add(1, Ordering::Relaxed);
+                if idx >= runs.len() {
+                    break;
+                }
+                let outcome = match place(&arg_lists[idx], &args.dir, idx) {
+                    Ok(outcome) => outcome,
+                    Err(e) => Outcome::Failed(format!("{:#}", e)),
+                };
+                match outcome {
+                    Outcome::Placed(ref record) => info!(
+                        "Run {} ({}) placed with HPWL {:.0}",
+                        idx,
+                        runs[idx]
+                            .iter()
+                            .map(|s| format!("{}={}", s.option, s.describe()))
+                            .join(" "),
+                        record.hpwl.unwrap_or(f64::NAN)
+                    ),
+                    Outcome::Failed(ref reason) => warn!("Run {} failed: {}", idx, reason),
+                }
+                outcomes.lock().unwrap()[idx] = Some(outcome);
+            });
+        }
+    });
+    let outcomes = outcomes
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|outcome| outcome.expect("Every run has an outcome"))
+        .collect_vec();
+
+    if let Some(ref path) = args.results_file {
+        for outcome in outcomes.iter() {
+            if let Outcome::Placed(record) = outcome {
+                results::append_run(path, record)
+                    .with_context(|| anyhow!("Log results to {:?}", path))?;
+            }
+        }
+    }
+
+    print_summary(std::io::stdout().lock(), &sweep, &runs, &outcomes).context("Write sweep summary")
+}
@@ -0,0 +1,559 @@
+//! Python bindings for the MCPNR flow, built as the `mcpnr` module with maturin:
+//!
+//! ```python
+//! import mcpnr
+//!
+//! placed = mcpnr.place("counter.json", "counter.pb", "techlib", size_x=64, delta_t=0.05)
+//! print(sum(1 for cell in placed.cells() if cell.type == "MCPNR_LIGHTS"))
+//! routed = mcpnr.route("counter.pb", "counter-routed.json", "techlib", tiers=2)
+//! routed.write_schematic("counter.schem")
+//!
+//! # Or place and route again, up to three more times, with the nets that didn't route weighted up
+//! placed, routed = mcpnr.place_and_route(
+//!     "counter.json", "counter.pb", "counter-routed.json", "techlib", iterations=3,
+//!     place_options={"size_x": 64}, route_options={"tiers": 2},
+//! )
+//! ```
+//!
+//! Designs, placements and routed outputs are loaded and written with the same code as the tools,
+//! and [`place`] and [`route`] run the flows of the `mcpnr-placement` and `mcpnr-routing` crates
+//! in-process, with the same options as the binaries, then load what they wrote.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+use mcpnr_common::anvil::DEFAULT_DATA_VERSION;
+use mcpnr_common::block_storage::stream::{read_rle_json_file, write_rle_json_file};
+use mcpnr_common::net_weights::NetWeights;
+use mcpnr_common::protos::mcpnr::placed_design;
+use mcpnr_common::protos::{read_placed_design, write_placed_design_json};
+use mcpnr_common::{schematic, yosys};
+use mcpnr_placement::cli::place_command;
+use mcpnr_placement::config::Config as PlacementConfig;
+use mcpnr_placement::{doctor, flow};
+use mcpnr_routing::config::{Cli, Config as RoutingConfig, RouterCommand};
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyDict, PyList};
+
+/// Synthesized design, as written by Yosys `write_json`
+#[pyclass]
+struct Design {
+    inner: yosys::Design,
+}
+
+#[pymethods]
+impl Design {
+    #[staticmethod]
+    fn load(path: PathBuf) -> Result<Self> {
+        let file =
+            std::fs::File::open(&path).with_context(|| anyhow!("Open input file {:?}", path))?;
+        let inner = serde_json::from_reader(std::io::BufReader::new(file))
+            .with_context(|| anyhow!("Parse design {:?}", path))?;
+        Ok(Self { inner })
+    }
+
+    #[getter]
+    fn creator(&self) -> &str {
+        &self.inner.creator
+    }
+
+    /// Names of the modules of the design, sorted
+    fn modules(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.inner.modules.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Instance name and type of every cell of `module`, sorted by name
+    fn cells(&self, module: &str) -> Result<Vec<(String, String)>> {
+        let module = self
+            .inner
+            .modules
+            .get(module)
+            .ok_or_else(|| anyhow!("No module named {:?}", module))?;
+        let mut cells: Vec<(String, String)> = module
+            .cells
+            .iter()
+            .map(|(name, cell)| (name.clone(), cell.ty.clone()))
+            .collect();
+        cells.sort();
+        Ok(cells)
+    }
+}
+
+/// Cell of a placed design
+#[pyclass]
+#[derive(Clone)]
+struct PlacedCell {
+    /// Instance name, empty for designs placed before names were kept
+    #[pyo3(get)]
+    name: String,
+    #[pyo3(get)]
+    r#type: String,
+    /// Minimum (x, y, z) corner, in blocks
+    #[pyo3(get)]
+    pos: (u32, u32, u32),
+}
+
+#[pymethods]
+impl PlacedCell {
+    fn __repr__(&self) -> String {
+        format!(
+            "PlacedCell(name={:?}, type={:?}, pos={:?})",
+            self.name, self.r#type, self.pos
+        )
+    }
+}
+
+impl From<&placed_design::Cell> for PlacedCell {
+    fn from(cell: &placed_design::Cell) -> Self {
+        let pos = cell.pos.as_ref().map_or((0, 0, 0), |p| (p.x, p.y, p.z));
+        Self {
+            name: cell.name.clone(),
+            r#type: cell.r#type.clone(),
+            pos,
+        }
+    }
+}
+
+/// Design placed by `mcpnr-placement`
+#[pyclass]
+struct PlacedDesign {
+    inner: mcpnr_common::protos::mcpnr::PlacedDesign,
+}
+
+#[pymethods]
+impl PlacedDesign {
+    /// Load a placed design, as JSON if the file ends in .json, in the streaming format if it ends
+    /// in .pbs or .jsonl and as protobuf otherwise
+    #[staticmethod]
+    fn load(path: PathBuf) -> Result<Self> {
+        Ok(Self {
+            inner: read_placed_design(&path)?,
+        })
+    }
+
+    #[getter]
+    fn creator(&self) -> &str {
+        &self.inner.creator
+    }
+
+    fn cells(&self) -> Vec<PlacedCell> {
+        self.inner.cells.iter().map(PlacedCell::from).collect()
+    }
+
+    /// Write the design as the pretty-printed JSON of `mcpnr-placement --output-json`
+    fn write_json(&self, path: PathBuf) -> Result<()> {
+        write_placed_design_json(&self.inner, &path)
+    }
+
+    fn __len__(&self) -> usize {
+        self.inner.cells.len()
+    }
+}
+
+/// Blocks of a routed (or only splatted) design, as written by `mcpnr-routing`
+#[pyclass]
+struct BlockStorage {
+    inner: mcpnr_common::block_storage::BlockStorage,
+}
+
+#[pymethods]
+impl BlockStorage {
+    /// Load a routed output written as structure JSON, gzipped or not
+    #[staticmethod]
+    fn load(path: PathBuf) -> Result<Self> {
+        Ok(Self {
+            inner: read_rle_json_file(&path)?,
+        })
+    }
+
+    /// Size of the output along (x, y, z), in blocks
+    #[getter]
+    fn extents(&self) -> (u32, u32, u32) {
+        let [x, y, z] = *self.inner.extents();
+        (x, y, z)
+    }
+
+    /// Name of the block at (x, y, z), like `minecraft:redstone_wire`
+    fn block(&self, x: u32, y: u32, z: u32) -> Result<Option<String>> {
+        let index = *self.inner.get_block(x, y, z)?;
+        Ok(self.inner.info_for_index(index).map(|b| b.name.clone()))
+    }
+
+    /// Number of blocks of each kind, air included
+    fn block_counts(&self) -> HashMap<String, usize> {
+        let mut counts = HashMap::new();
+        for index in self.inner.iter_block_indicies() {
+            if let Some(block) = self.inner.info_for_index(index) {
+                *counts.entry(block.name.clone()).or_insert(0) += 1;
+            }
+        }
+        counts
+    }
+
+    /// Write the blocks as structure JSON, gzipped if `path` ends in .gz, which `BlockStorage.load`
+    /// and the router's ECO mode read back
+    fn write_json(&self, path: PathBuf) -> Result<()> {
+        write_rle_json_file(&self.inner, &path)
+    }
+
+    /// Write the blocks as a WorldEdit schematic for the Minecraft version with `data_version`
+    #[pyo3(signature = (path, data_version = DEFAULT_DATA_VERSION))]
+    fn write_schematic(&self, path: PathBuf, data_version: i32) -> Result<()> {
+        let file =
+            std::fs::File::create(&path).with_context(|| anyhow!("Create output {:?}", path))?;
+        schematic::write_schematic(&self.inner, std::io::BufWriter::new(file), data_version)
+            .with_context(|| anyhow!("Write schematic {:?}", path))
+    }
+}
+
+/// Turn keyword arguments in to command line options: `size_x=64` is `--size-x 64`, `True` passes
+/// a flag, `False` and `None` leave the option out and a list passes the option once per item.
+fn options_to_args(options: Option<&PyDict>) -> PyResult<Vec<String>> {
+    let mut args = Vec::new();
+    for (key, value) in options.into_iter().flatten() {
+        let option = format!("--{}", key.extract::<String>()?.replace('_', "-"));
+        let values = match value.downcast::<PyList>() {
+            Ok(list) => list.iter().collect(),
+            Err(_) => vec![value],
+        };
+        for value in values {
+            if value.is_none() {
+                continue;
+            }
+            if let Ok(flag) = value.downcast::<PyBool>() {
+                if flag.is_true() {
+                    args.push(option.clone());
+                }
+                continue;
+            }
+            args.push(option.clone());
+            args.push(value.str()?.to_str()?.to_owned());
+        }
+    }
+    Ok(args)
+}
+
+/// Place with the command line `args` of `mcpnr-placement place`, checking the options first as
+/// the binary does
+fn run_placement(args: &[String]) -> Result<()> {
+    let matches = place_command()
+        .try_get_matches_from(args)
+        .context("Parse placement options")?;
+    let config = PlacementConfig::from_args(&matches).context("Building config from args")?;
+    doctor::ensure_config(&config)?;
+    flow::run_placement(&config)
+}
+
+/// Route with the command line `args` of `mcpnr-routing route`
+fn run_routing(args: &[String]) -> Result<()> {
+    let cli = Cli::try_parse_from(
+        std::iter::once("mcpnr-routing").chain(args.iter().map(String::as_str)),
+    )
+    .context("Parse routing options")?;
+    match cli.command {
+        Some(RouterCommand::Route(args)) => {
+            mcpnr_routing::route(&RoutingConfig::from_route_args(&args))
+        }
+        command => Err(anyhow!("Expected routing options, got {:?}", command)),
+    }
+}
+
+/// Command line of `subcommand` of a tool on `input` with the technology library `techlib`,
+/// writing `output`, with `options` and `extra` options after them
+fn tool_args(
+    subcommand: &str,
+    [input, output, techlib]: [&Path; 3],
+    options: &[String],
+    extra: &[(&str, &Path)],
+) -> Vec<String> {
+    let mut args = vec![subcommand.to_owned(), "--techlib".to_owned()];
+    args.push(techlib.to_string_lossy().into_owned());
+    args.extend(options.iter().cloned());
+    for (option, path) in extra {
+        args.push(option.to_string());
+        args.push(path.to_string_lossy().into_owned());
+    }
+    args.extend([input, output].map(|path| path.to_string_lossy().into_owned()));
+    args
+}
+
+/// Load a routed output, unless it's a schematic, which can't be read back
+fn load_routed(output: PathBuf) -> Result<Option<BlockStorage>> {
+    if output.extension() == Some(schematic::SCHEMATIC_EXTENSION.as_ref()) {
+        return Ok(None);
+    }
+    Ok(Some(BlockStorage::load(output)?))
+}
+
+/// Where [`place_and_route`] writes each attempt at `path` before deciding whether to keep it.
+/// It's `path` with `.attempt` before the extension, so the tools still see the same format.
+fn attempt_path(path: &Path) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_owned();
+    name.push(".attempt");
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
+
+/// Move an attempt written to `attempt` over `path`, replacing the one kept before
+fn keep_attempt(attempt: &Path, path: &Path) -> Result<()> {
+    std::fs::rename(attempt, path).with_context(|| anyhow!("Move {:?} to {:?}", attempt, path))
+}
+
+/// Place the Yosys JSON design `input` with the technology library `techlib`, write it to `output`
+/// and return it. Other options of `mcpnr-placement place` are given as keyword arguments.
+#[pyfunction]
+#[pyo3(signature = (input, output, techlib, **options))]
+fn place(
+    input: PathBuf,
+    output: PathBuf,
+    techlib: PathBuf,
+    options: Option<&PyDict>,
+) -> PyResult<PlacedDesign> {
+    let options = options_to_args(options)?;
+    run_placement(&tool_args(
+        "place",
+        [&input, &output, &techlib],
+        &options,
+        &[],
+    ))?;
+    Ok(PlacedDesign::load(output)?)
+}
+
+/// Route the placed design `input` with the technology library `techlib` and write it to
+/// `output`. Returns the routed blocks, unless `output` is a schematic, which can't be read back.
+/// Other options of `mcpnr-routing route` are given as keyword arguments.
+#[pyfunction]
+#[pyo3(signature = (input, output, techlib, **options))]
+fn route(
+    input: PathBuf,
+    output: PathBuf,
+    techlib: PathBuf,
+    options: Option<&PyDict>,
+) -> PyResult<Option<BlockStorage>> {
+    let options = options_to_args(options)?;
+    run_routing(&tool_args(
+        "route",
+        [&input, &output, &techlib],
+        &options,
+        &[],
+    ))?;
+    Ok(load_routed(output)?)
+}
+
+/// Place `input` to `placed` and route it to `output`, then, up to `iterations` times, place it
+/// again with the nets routing had trouble with weighted up and route it again. Stops early once
+/// routing has no trouble left. The weights build up over the attempts, in a `.weights.json` file
+/// next to `placed`, and each routing attempt's feedback is in a `.feedback.json` file next to
+/// `output`. Only the attempt that left the fewest nets unrouted, the first of them if there's a
+/// tie, is kept in `placed` and `output`. Returns its placed design and routed blocks, as
+/// [`place`] and [`route`] do, with options for each tool given as dictionaries.
+#[pyfunction]
+#[pyo3(signature = (input, placed, output, techlib, iterations = 0, place_options = None, route_options = None))]
+fn place_and_route(
+    input: PathBuf,
+    placed: PathBuf,
+    output: PathBuf,
+    techlib: PathBuf,
+    iterations: u32,
+    place_options: Option<&PyDict>,
+    route_options: Option<&PyDict>,
+) -> PyResult<(PlacedDesign, Option<BlockStorage>)> {
+    let weights_file = placed.with_extension("weights.json");
+    let feedback_file = output.with_extension("feedback.json");
+    let placed_attempt = attempt_path(&placed);
+    let output_attempt = attempt_path(&output);
+    let place_options = options_to_args(place_options)?;
+    let route_options = options_to_args(route_options)?;
+    let mut weights = NetWeights::default();
+    let mut fewest_unrouted = None;
+
+    for attempt in 0..=iterations {
+        let weighted = [("--net-weights", weights_file.as_path())];
+        let extra: &[(&str, &Path)] = if attempt > 0 { &weighted } else { &[] };
+        run_placement(&tool_args(
+            "place",
+            [&input, &placed_attempt, &techlib],
+            &place_options,
+            extra,
+        ))?;
+
+        let extra = [("--net-feedback", feedback_file.as_path())];
+        run_routing(&tool_args(
+            "route",
+            [&placed_attempt, &output_attempt, &techlib],
+            &route_options,
+            &extra,
+        ))?;
+
+        let feedback = NetWeights::read(&feedback_file)?;
+        if !matches!(fewest_unrouted, Some(fewest) if feedback.unrouted >= fewest) {
+            keep_attempt(&placed_attempt, &placed)?;
+            keep_attempt(&output_attempt, &output)?;
+            fewest_unrouted = Some(feedback.unrouted);
+        }
+        if feedback.nets.is_empty() {
+            break;
+        }
+        weights.accumulate(&feedback);
+        weights.write(&weights_file)?;
+    }
+    for path in [&placed_attempt, &output_attempt] {
+        if path.exists() {
+            std::fs::remove_file(path).with_context(|| anyhow!("Remove {:?}", path))?;
+        }
+    }
+
+    Ok((PlacedDesign::load(placed)?, load_routed(output)?))
+}
+
+#[pymodule]
+fn mcpnr(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<Design>()?;
+    m.add_class::<PlacedCell>()?;
+    m.add_class::<PlacedDesign>()?;
+    m.add_class::<BlockStorage>()?;
+    m.add_function(wrap_pyfunction!(place, m)?)?;
+    m.add_function(wrap_pyfunction!(route, m)?)?;
+    m.add_function(wrap_pyfunction!(place_and_route, m)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use serde_json::json;
+
+    use super::{run_placement, run_routing, tool_args, BlockStorage, PlacedDesign};
+
+    fn techlib() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("../../yosys-synth_mc/techlib")
+    }
+
+    /// A switch driving a light through an inverter, with the light 24 blocks along Z from the
+    /// switch
+    fn write_inverter(path: &Path) -> anyhow::Result<()> {
+        let cell = |ty: &str, parameters, port: &str, direction: &str, bit: u32| {
+            json!({
+                "hide_name": 0,
+                "type": ty,
+                "parameters": parameters,
+                "attributes": {},
+                "port_directions": { port: direction },
+                "connections": { port: [bit] },
+            })
+        };
+        let design = json!({
+            "creator": "test",
+            "modules": {
+                "top": {
+                    "attributes": {},
+                    "ports": {},
+                    "cells": {
+                        "switch": cell("MCPNR_SWITCHES", json!({}), "O", "output", 2),
+                        "light": cell("MCPNR_LIGHTS", json!({ "POS_Z": "11000" }), "I", "input", 3),
+                        "not": {
+                            "hide_name": 0,
+                            "type": "gate_not.nbt",
+                            "parameters": {},
+                            "attributes": {},
+                            "port_directions": { "A": "input", "Y": "output" },
+                            "connections": { "A": [2], "Y": [3] },
+                        },
+                    },
+                    "netnames": {},
+                },
+            },
+        });
+        std::fs::write(path, serde_json::to_vec(&design)?)?;
+        Ok(())
+    }
+
+    #[test]
+    fn tool_args_put_the_files_last() {
+        let args = tool_args(
+            "place",
+            [
+                Path::new("in.json"),
+                Path::new("out.pb"),
+                Path::new("techlib"),
+            ],
+            &["--size-x".to_owned(), "64".to_owned()],
+            &[("--net-weights", Path::new("out.weights.json"))],
+        );
+
+        assert_eq!(
+            args,
+            [
+                "place",
+                "--techlib",
+                "techlib",
+                "--size-x",
+                "64",
+                "--net-weights",
+                "out.weights.json",
+                "in.json",
+                "out.pb"
+            ]
+        );
+    }
+
+    #[test]
+    fn places_and_routes_in_process() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("mcpnr-py-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let input = dir.join("inverter.json");
+        let placed = dir.join("inverter.pb");
+        let output = dir.join("inverter-routed.json");
+        write_inverter(&input)?;
+
+        let size = ["--size-x", "32", "--size-y", "1", "--size-z", "32"].map(str::to_owned);
+        run_placement(&tool_args(
+            "place",
+            [&input, &placed, &techlib()],
+            &size,
+            &[],
+        ))?;
+        let placed_design = PlacedDesign::load(placed.clone())?;
+        run_routing(&tool_args(
+            "route",
+            [&placed, &output, &techlib()],
+            &[],
+            &[],
+        ))?;
+        let routed = BlockStorage::load(output)?;
+        std::fs::remove_dir_all(&dir)?;
+
+        let mut names: Vec<String> = placed_design.cells().into_iter().map(|c| c.name).collect();
+        names.sort();
+        assert_eq!(names, ["light", "not", "switch"]);
+        assert!(routed
+            .block_counts()
+            .contains_key("minecraft:redstone_wire"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn bad_options_are_an_error() {
+        let args = tool_args(
+            "route",
+            [
+                Path::new("in.pb"),
+                Path::new("out.json"),
+                Path::new("techlib"),
+            ],
+            &["--no-such-option".to_owned()],
+            &[],
+        );
+
+        assert!(run_routing(&args).is_err());
+    }
+}
@@ -0,0 +1,57 @@
+//! Benchmarks of the detail router on synthetic serpentine mazes, where every route has to wind
+//! back and forth across the whole grid.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use mcpnr_common::block_storage::Direction;
+use mcpnr_routing::{
+    detail_routing::{DetailRouter, GridCell, GridCellPosition, LAYERS_PER_TIER},
+    RouteId,
+};
+
+const ROUTE: RouteId = RouteId(0);
+
+/// A `size` x `size` maze one tier tall. Walls run along X every 4 rows of Z through every layer,
+/// with a single gap at alternating ends, so the only path from the driver in one corner to the
+/// sink in the other snakes through every corridor.
+fn maze(size: u32) -> (DetailRouter, GridCellPosition, GridCellPosition) {
+    let mut router = DetailRouter::new(size, LAYERS_PER_TIER, size);
+    let last = size as i32 - 1;
+
+    for (wall, z) in (2..last - 1).step_by(4).enumerate() {
+        let gap = if wall % 2 == 0 { last } else { 0 };
+        for y in 0..LAYERS_PER_TIER as i32 {
+            for x in (0..=last).filter(|x| *x != gap) {
+                *router
+                    .get_cell_mut(GridCellPosition::new(x.into(), y, z.into()))
+                    .unwrap() = GridCell::Blocked;
+            }
+        }
+    }
+
+    let driver = GridCellPosition::new(0.into(), 0, 0.into());
+    let sink = GridCellPosition::new(last.into(), 0, last.into());
+    *router.get_cell_mut(driver).unwrap() = GridCell::Blocked;
+    *router.get_cell_mut(sink).unwrap() = GridCell::Blocked;
+
+    (router, driver, sink)
+}
+
+fn route_maze(c: &mut Criterion) {
+    let mut group = c.benchmark_group("route_maze");
+    group.sample_size(20);
+    for size in [32, 64, 128] {
+        let (mut router, driver, sink) = maze(size);
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| {
+            b.iter(|| {
+                router
+                    .route(driver, Direction::North, sink, Direction::North, ROUTE)
+                    .unwrap();
+                router.rip_up(ROUTE).unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, route_maze);
+criterion_main!(benches);
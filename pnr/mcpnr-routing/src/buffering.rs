@@ -0,0 +1,706 @@
+//! Buffer insertion, a pass over the placed design between placement and routing. Copies of a
+//! buffer cell from the techlib are added in two ways:
+//!
+//! - As repeaters in connections longer than a limit, so the signal is refreshed along the way
+//!   and the router isn't left with wires that need repeaters of their own.
+//! - On nets with more sinks than their driver can drive, see [`FanoutLimits`]. A group of
+//!   neighbouring sinks at a time is moved over to a new net driven by a buffer, whose input
+//!   takes their place on the old net, until the driver is within its limit.
+//!
+//! Buffers can end up driving other buffers, so very long or large nets become a tree. Each one
+//! goes in the free space closest to where it's wanted, on the routing grid and on the tier of the
+//! sinks it drives, so none of the cells that were already placed have to move and the output
+//! doesn't grow.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use itertools::Itertools;
+use mcpnr_common::protos::mcpnr::{
+    placed_design::Cell, signal::Type, BitVector, PlacedDesign, Position, Signal,
+};
+use mcpnr_common::BLOCKS_PER_TIER;
+use tracing::warn;
+
+use crate::detail_routing::wire_segment::WIRE_GRID_SCALE;
+use crate::netlist::{pin_metadata, PinDirection};
+use crate::netlist_check::FanoutLimits;
+use crate::placement_check::footprint;
+use crate::structure_cache::StructureCache;
+
+/// Blocks kept clear around each buffer, so there's room to wire up its pins and the pins of the
+/// cells next to it
+const BUFFER_CLEARANCE: u32 = WIRE_GRID_SCALE as u32;
+
+/// One bit of a port of a cell in the placed design
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct PortBit {
+    pub cell: usize,
+    pub port: String,
+    pub bit: usize,
+}
+
+/// The cell type copied to buffer nets
+struct Buffer<'a> {
+    ty: &'a str,
+    input: String,
+    output: String,
+    size: [u32; 3],
+    /// Position of the input pin in the cell
+    input_offset: [u32; 3],
+    /// Position of the output pin in the cell
+    output_offset: [u32; 3],
+    /// Most sinks each buffer can drive, if that's limited
+    limit: Option<usize>,
+}
+
+impl<'a> Buffer<'a> {
+    fn new(ty: &'a str, structure_cache: &StructureCache, limits: &FanoutLimits) -> Result<Self> {
+        let structure = structure_cache
+            .get(ty)
+            .ok_or_else(|| anyhow!("Unknown buffer cell type {:?}", ty))?;
+        let (mut inputs, mut outputs) = (Vec::new(), Vec::new());
+        for port in structure.ports().sorted() {
+            match structure.pin(port, 0)?.direction {
+                PinDirection::Input => inputs.push(port),
+                PinDirection::Output => outputs.push(port),
+            }
+        }
+        let (input, output) = match (inputs.as_slice(), outputs.as_slice()) {
+            ([input], [output])
+                if structure.port_width(input) == Some(1)
+                    && structure.port_width(output) == Some(1) =>
+            {
+                (input.to_string(), output.to_string())
+            }
+            _ => bail!(
+                "Buffer cell {:?} needs one single-bit input and one single-bit output, but has \
+                 inputs {:?} and outputs {:?}",
+                ty,
+                inputs,
+                outputs
+            ),
+        };
+
+        let input_pin = structure.pin(&input, 0)?;
+        let input_offset = [input_pin.offset_x, input_pin.offset_y, input_pin.offset_z];
+        let output_pin = structure.pin(&output, 0)?;
+        let output_offset = [
+            output_pin.offset_x,
+            output_pin.offset_y,
+            output_pin.offset_z,
+        ];
+
+        let cell = Cell {
+            r#type: ty.to_owned(),
+            ..Default::default()
+        };
+        let size = footprint(&cell, structure_cache)?
+            .ok_or_else(|| anyhow!("Buffer cell {:?} has no structure", ty))?;
+
+        let limit = limits.limit(ty);
+        ensure!(
+            limit != Some(1),
+            "Buffer cell {:?} can only drive one sink, so buffering can't take any sinks off a net",
+            ty
+        );
+
+        Ok(Self {
+            ty,
+            input,
+            output,
+            size,
+            input_offset,
+            output_offset,
+            limit,
+        })
+    }
+}
+
+/// Minimum and maximum (exclusive) corners of a cell
+pub(crate) type Footprint = ([u32; 3], [u32; 3]);
+
+fn overlaps(a: &Footprint, b: &Footprint) -> bool {
+    (0..3).all(|axis| a.0[axis] < b.1[axis] && b.0[axis] < a.1[axis])
+}
+
+pub(crate) fn cell_position(cell: &Cell) -> [u32; 3] {
+    cell.pos
+        .as_ref()
+        .map_or([0, 0, 0], |pos| [pos.x, pos.y, pos.z])
+}
+
+/// The position on the routing grid closest to `target` in X and Z where a cell of `size` fits
+/// at height `y` without coming within [`BUFFER_CLEARANCE`] of any of `occupied`, or inside
+/// `extents`.
+pub(crate) fn free_spot(
+    target: [u32; 2],
+    y: u32,
+    size: [u32; 3],
+    occupied: &[Footprint],
+    extents: [u32; 3],
+) -> Option<[u32; 3]> {
+    let grid = WIRE_GRID_SCALE as i64;
+    let (tx, tz) = (target[0] as i64 / grid, target[1] as i64 / grid);
+    let fits = |x: i64, z: i64| -> Option<[u32; 3]> {
+        let (x, z) = (u32::try_from(x * grid).ok()?, u32::try_from(z * grid).ok()?);
+        let max = [x + size[0], y + size[1], z + size[2]];
+        if (0..3).any(|axis| max[axis] > extents[axis]) {
+            return None;
+        }
+        let clear = (
+            [
+                x.saturating_sub(BUFFER_CLEARANCE),
+                y,
+                z.saturating_sub(BUFFER_CLEARANCE),
+            ],
+            [max[0] + BUFFER_CLEARANCE, max[1], max[2] + BUFFER_CLEARANCE],
+        );
+        if occupied.iter().any(|other| overlaps(&clear, other)) {
+            return None;
+        }
+        Some([x, y, z])
+    };
+
+    // Rings of grid positions further and further out from the target
+    let radius = (extents[0].max(extents[2]) as i64) / grid + 1;
+    (0..=radius).find_map(|r| {
+        (-r..=r)
+            .cartesian_product(-r..=r)
+            .filter(|(dx, dz)| dx.abs() == r || dz.abs() == r)
+            .find_map(|(dx, dz)| fits(tx + dx, tz + dz))
+    })
+}
+
+/// What to buffer, and with which cell
+#[derive(Clone, Copy, Debug)]
+pub struct BufferOptions<'a> {
+    /// Techlib cell to copy for each buffer or repeater
+    pub buffer_type: &'a str,
+    /// Most sinks each cell type can drive
+    pub fanout_limits: &'a FanoutLimits,
+    /// Longest distance from a driver pin to a sink pin, in blocks, before a repeater goes in
+    /// between them
+    pub max_wire_length: Option<u32>,
+}
+
+/// Cells added by [`insert_buffers`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BufferReport {
+    /// Repeaters breaking up long connections
+    pub repeaters: usize,
+    /// Buffers taking sinks off drivers with too many of them
+    pub fanout_buffers: usize,
+}
+
+/// Distance between two pins along the routing grid's axes, in blocks
+fn distance(a: [u32; 3], b: [u32; 3]) -> u32 {
+    (0..3).map(|axis| a[axis].abs_diff(b[axis])).sum()
+}
+
+/// Pins on each net, in net order
+pub(crate) type Pins = BTreeMap<i64, Vec<PortBit>>;
+
+/// Drivers and sinks of every net of the design, in net order
+pub(crate) fn connectivity(
+    design: &PlacedDesign,
+    structure_cache: &StructureCache,
+) -> Result<(Pins, Pins)> {
+    let mut drivers: Pins = BTreeMap::new();
+    let mut sinks: Pins = BTreeMap::new();
+    for (cell_idx, cell) in design.cells.iter().enumerate() {
+        for (port, bits) in cell.connection.iter().sorted_by_key(|(port, _)| *port) {
+            for (bit, signal) in bits.signal.iter().enumerate() {
+                let net = match signal.r#type {
+                    Some(Type::Id(net)) => net,
+                    _ => continue,
+                };
+                let metadata = pin_metadata(structure_cache, &cell.r#type, port, bit)
+                    .with_context(|| {
+                        anyhow!("Pin {}[{}] of cell {}", port, bit, cell.describe(cell_idx))
+                    })?;
+                let pins = match metadata.direction {
+                    PinDirection::Output => &mut drivers,
+                    PinDirection::Input => &mut sinks,
+                };
+                pins.entry(net).or_default().push(PortBit {
+                    cell: cell_idx,
+                    port: port.clone(),
+                    bit,
+                });
+            }
+        }
+    }
+    Ok((drivers, sinks))
+}
+
+/// Footprints of the cells already in the design, and the lowest net number not in it
+pub(crate) fn occupancy(
+    design: &PlacedDesign,
+    structure_cache: &StructureCache,
+) -> Result<(Vec<Footprint>, i64)> {
+    let mut occupied = Vec::with_capacity(design.cells.len());
+    let mut next_net = 0;
+    for cell in design.cells.iter() {
+        if let Some(size) = footprint(cell, structure_cache)? {
+            let min = cell_position(cell);
+            occupied.push((min, [0, 1, 2].map(|axis| min[axis] + size[axis])));
+        }
+        for signal in cell.connection.values().flat_map(|bits| bits.signal.iter()) {
+            if let Some(Type::Id(net)) = signal.r#type {
+                next_net = next_net.max(net + 1);
+            }
+        }
+    }
+    Ok((occupied, next_net))
+}
+
+/// Net driven by a repeater, its output pin position, and the sinks on it with their positions
+type Repeater = (i64, [u32; 3], Vec<(PortBit, [u32; 3])>);
+
+/// Adds buffer cells to a design, keeping track of where there's room for them
+struct Inserter<'a> {
+    design: &'a mut PlacedDesign,
+    structure_cache: &'a StructureCache,
+    buffer: Buffer<'a>,
+    /// Footprints of every cell, buffers included
+    occupied: Vec<Footprint>,
+    /// Lowest net number not in the design
+    next_net: i64,
+    inserted: usize,
+    extents: [u32; 3],
+    tiers: u32,
+}
+
+impl<'a> Inserter<'a> {
+    fn connectivity(&self) -> Result<(Pins, Pins)> {
+        connectivity(self.design, self.structure_cache)
+    }
+
+    /// Block position of a pin
+    fn pin_position(&self, pin: &PortBit) -> Result<[u32; 3]> {
+        let cell = &self.design.cells[pin.cell];
+        let metadata = pin_metadata(self.structure_cache, &cell.r#type, &pin.port, pin.bit)?;
+        let [x, y, z] = cell_position(cell);
+        Ok([
+            x + metadata.offset_x,
+            y + metadata.offset_y,
+            z + metadata.offset_z,
+        ])
+    }
+
+    /// Connect `sinks` to `net` instead of whatever they were on
+    fn move_sinks(&mut self, sinks: &[PortBit], net: i64) {
+        for sink in sinks {
+            let bits = self.design.cells[sink.cell]
+                .connection
+                .get_mut(&sink.port)
+                .expect("Sinks are found from the connections");
+            bits.signal[sink.bit] = Signal {
+                r#type: Some(Type::Id(net)),
+            };
+        }
+    }
+
+    /// Add a buffer on `net` as close to `target` on the tier of `near` as there's room for, and
+    /// move `sinks` over to its output. Returns its input and output pins and the net its output
+    /// drives.
+    fn add_buffer(
+        &mut self,
+        net: i64,
+        target: [u32; 2],
+        near: [u32; 3],
+        sinks: &[PortBit],
+    ) -> Result<(PortBit, PortBit, i64)> {
+        let min = self.buffer_spot(net, target, near)?;
+        Ok(self.place_buffer(net, min, sinks))
+    }
+
+    /// Minimum corner of the free space closest to `target` on the tier of `near` a buffer on
+    /// `net` fits in
+    fn buffer_spot(&self, net: i64, target: [u32; 2], near: [u32; 3]) -> Result<[u32; 3]> {
+        let tier = (near[1] / BLOCKS_PER_TIER).min(self.tiers.saturating_sub(1));
+        free_spot(
+            target,
+            tier * BLOCKS_PER_TIER,
+            self.buffer.size,
+            &self.occupied,
+            self.extents,
+        )
+        .ok_or_else(|| {
+            anyhow!(
+                "No room for a {:?} buffer on tier {} for net {}",
+                self.buffer.ty,
+                tier,
+                net
+            )
+        })
+    }
+
+    /// Add a buffer on `net` with its minimum corner at `min`, and move `sinks` over to its
+    /// output. Returns its input and output pins and the net its output drives.
+    fn place_buffer(
+        &mut self,
+        net: i64,
+        min: [u32; 3],
+        sinks: &[PortBit],
+    ) -> (PortBit, PortBit, i64) {
+        let buffered = self.next_net;
+        self.next_net += 1;
+        self.move_sinks(sinks, buffered);
+
+        let bit = |net: i64| BitVector {
+            signal: vec![Signal {
+                r#type: Some(Type::Id(net)),
+            }],
+        };
+        let [x, y, z] = min;
+        self.design.cells.push(Cell {
+            r#type: self.buffer.ty.to_owned(),
+            name: format!("$buffer${}", self.inserted),
+            pos: Some(Position { x, y, z }),
+            connection: [
+                (self.buffer.input.clone(), bit(net)),
+                (self.buffer.output.clone(), bit(buffered)),
+            ]
+            .into(),
+            ..Default::default()
+        });
+        self.occupied.push((
+            min,
+            [0, 1, 2].map(|axis| min[axis] + self.buffer.size[axis]),
+        ));
+        self.inserted += 1;
+
+        let cell = self.design.cells.len() - 1;
+        let pin = |port: &String| PortBit {
+            cell,
+            port: port.clone(),
+            bit: 0,
+        };
+        (pin(&self.buffer.input), pin(&self.buffer.output), buffered)
+    }
+
+    /// Put repeaters in every connection longer than `max_length` blocks, each `max_length` blocks
+    /// from the driver towards the sink. Sinks further on the same way share repeaters, as long as
+    /// the repeater can drive them. Returns the number of repeaters added.
+    fn split_long_nets(&mut self, max_length: u32) -> Result<usize> {
+        let inserted = self.inserted;
+        let (drivers, mut sinks) = self.connectivity()?;
+
+        // Nets with more than one driver could be driven from any of them, so they're left alone
+        let mut queue = VecDeque::new();
+        for (net, net_drivers) in drivers.iter() {
+            if let [driver] = net_drivers.as_slice() {
+                queue.push_back((*net, self.pin_position(driver)?));
+            }
+        }
+
+        let buffer_limit = self.buffer.limit.unwrap_or(usize::MAX);
+        while let Some((net, source)) = queue.pop_front() {
+            let mut far = Vec::new();
+            for sink in sinks.remove(&net).unwrap_or_default() {
+                let position = self.pin_position(&sink)?;
+                let length = distance(source, position);
+                if length > max_length {
+                    far.push((length, sink, position));
+                }
+            }
+            far.sort_by_key(|(length, sink, _)| (*length, sink.cell));
+
+            let mut repeaters: Vec<Repeater> = Vec::new();
+            for (length, sink, position) in far {
+                let shared = repeaters
+                    .iter_mut()
+                    .filter(|(_, output, attached)| {
+                        attached.len() < buffer_limit && distance(*output, position) < length
+                    })
+                    .min_by_key(|(_, output, _)| distance(*output, position));
+                if let Some((repeated, _, attached)) = shared {
+                    let repeated = *repeated;
+                    attached.push((sink.clone(), position));
+                    self.move_sinks(&[sink], repeated);
+                    continue;
+                }
+
+                // Manhattan distance is the same along any path, so head straight for the sink,
+                // short enough that the repeater's input is still in reach once it's on the grid
+                let flat = length - source[1].abs_diff(position[1]);
+                let reach = max_length
+                    .saturating_sub(source[1].abs_diff(position[1]) + 2 * WIRE_GRID_SCALE as u32);
+                if reach == 0 {
+                    warn!(
+                        "Net {} climbs too far between its pins for a repeater within {} blocks \
+                         to get any closer to sink {}",
+                        net,
+                        max_length,
+                        self.design.cells[sink.cell].describe(sink.cell)
+                    );
+                    continue;
+                }
+                let along = |axis: usize| {
+                    let (from, to) = (source[axis] as i64, position[axis] as i64);
+                    let pin = from + (to - from) * reach as i64 / flat.max(1) as i64;
+                    (pin - self.buffer.input_offset[axis] as i64).max(0) as u32
+                };
+                let min = self.buffer_spot(net, [along(0), along(2)], position)?;
+                let output = [0, 1, 2].map(|axis| min[axis] + self.buffer.output_offset[axis]);
+                // A repeater that had to go further away than the sink doesn't help, and going
+                // round again would only add more of them
+                if distance(output, position) >= length {
+                    warn!(
+                        "No room for a repeater close enough to sink {} of net {}",
+                        self.design.cells[sink.cell].describe(sink.cell),
+                        net
+                    );
+                    continue;
+                }
+                let (_, _, repeated) = self.place_buffer(net, min, std::slice::from_ref(&sink));
+                repeaters.push((repeated, output, vec![(sink, position)]));
+            }
+
+            for (repeated, output, attached) in repeaters {
+                sinks.insert(
+                    repeated,
+                    attached.into_iter().map(|(sink, _)| sink).collect(),
+                );
+                queue.push_back((repeated, output));
+            }
+        }
+
+        Ok(self.inserted - inserted)
+    }
+
+    /// Move sinks of nets whose drivers have more than their fan-out limit over to buffers near
+    /// groups of neighbouring sinks. Returns the number of buffers added.
+    fn split_high_fanout_nets(&mut self, limits: &FanoutLimits) -> Result<usize> {
+        let inserted = self.inserted;
+        let (drivers, mut sinks) = self.connectivity()?;
+
+        for (net, net_drivers) in drivers.iter() {
+            let limit = match net_drivers
+                .iter()
+                .filter_map(|driver| limits.limit(&self.design.cells[driver.cell].r#type))
+                .min()
+            {
+                Some(limit) => limit,
+                None => continue,
+            };
+            let mut net_sinks = sinks.remove(net).unwrap_or_default();
+
+            while net_sinks.len() > limit {
+                // Each buffer takes its group of sinks off the net, but adds its own input
+                let take = self
+                    .buffer
+                    .limit
+                    .unwrap_or(usize::MAX)
+                    .min(net_sinks.len() - limit + 1);
+                net_sinks.sort_by_key(|sink| {
+                    let [x, _, z] = cell_position(&self.design.cells[sink.cell]);
+                    (x, z, sink.cell)
+                });
+                let group: Vec<PortBit> = net_sinks.drain(..take).collect();
+
+                let positions: Vec<[u32; 3]> = group
+                    .iter()
+                    .map(|sink| cell_position(&self.design.cells[sink.cell]))
+                    .collect();
+                let centroid = |axis: usize| {
+                    (positions.iter().map(|p| p[axis] as u64).sum::<u64>() / positions.len() as u64)
+                        as u32
+                };
+                let (input, _, _) =
+                    self.add_buffer(*net, [centroid(0), centroid(2)], positions[0], &group)?;
+                net_sinks.push(input);
+            }
+        }
+
+        Ok(self.inserted - inserted)
+    }
+}
+
+/// Add copies of the buffer cell in `options` to the end of the design's cells: repeaters in
+/// connections longer than the longest wire allowed, then buffers on nets whose drivers have more
+/// sinks than their [`FanoutLimits`]. The cells are placed in the free space of an output of
+/// `extents` with `tiers` tiers.
+pub fn insert_buffers(
+    design: &mut PlacedDesign,
+    structure_cache: &StructureCache,
+    options: &BufferOptions,
+    extents: [u32; 3],
+    tiers: u32,
+) -> Result<BufferReport> {
+    let buffer = Buffer::new(options.buffer_type, structure_cache, options.fanout_limits)?;
+
+    let (occupied, next_net) = occupancy(design, structure_cache)?;
+
+    let mut inserter = Inserter {
+        design,
+        structure_cache,
+        buffer,
+        occupied,
+        next_net,
+        inserted: 0,
+        extents,
+        tiers,
+    };
+    let repeaters = match options.max_wire_length {
+        Some(max_length) => inserter.split_long_nets(max_length)?,
+        None => 0,
+    };
+    let fanout_buffers = inserter.split_high_fanout_nets(options.fanout_limits)?;
+
+    Ok(BufferReport {
+        repeaters,
+        fanout_buffers,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use mcpnr_common::blockers::BlockerRules;
+    use mcpnr_common::protos::mcpnr::PlacedDesign;
+
+    use super::{insert_buffers, BufferOptions, BufferReport};
+    use crate::netlist::Netlist;
+    use crate::netlist_check::{FanoutLimits, NetlistCheck};
+    use crate::placement_check::PlacementCheck;
+    use crate::structure_cache::StructureCache;
+    use crate::test_util::{cell, techlib};
+
+    // An inverter stands in for a buffer in these tests, the connections work out the same
+
+    #[test]
+    fn buffers_bring_drivers_within_their_limits() -> anyhow::Result<()> {
+        let mut cells = vec![cell("", "MCPNR_SWITCHES", (0, 0, 0), &[("O", &[1])])];
+        cells.extend(
+            (0..7).map(|idx| cell("", "gate_not.nbt", (8 + idx * 8, 0, 0), &[("A", &[1])])),
+        );
+        let mut design = PlacedDesign {
+            cells,
+            ..Default::default()
+        };
+        let structure_cache = StructureCache::new(&techlib(), &design, &BlockerRules::builtin())?;
+        let limits = FanoutLimits {
+            default: Some(3),
+            ..Default::default()
+        };
+        let options = BufferOptions {
+            buffer_type: "gate_not.nbt",
+            fanout_limits: &limits,
+            max_wire_length: None,
+        };
+        let extents = [72, 16, 40];
+
+        let report = insert_buffers(&mut design, &structure_cache, &options, extents, 1)?;
+        // Each buffer takes three sinks off the net and puts itself on it instead
+        assert_eq!(
+            report,
+            BufferReport {
+                repeaters: 0,
+                fanout_buffers: 2
+            }
+        );
+        assert_eq!(design.cells.len(), 10);
+
+        let netlist = Netlist::new(&design, &structure_cache)?;
+        let check = NetlistCheck::new(&design, &netlist, &structure_cache, &limits)?;
+        assert!(check.overloaded_drivers.is_empty());
+        assert!(check.undriven_nets.is_empty());
+        assert_eq!(
+            netlist
+                .iter_nets()
+                .map(|(_, net)| net.fanout())
+                .sum::<usize>(),
+            9
+        );
+        PlacementCheck::new(&design, &structure_cache, extents, 1)?.ensure_clean(&design)?;
+
+        // Nothing to do within the limits
+        let unlimited = FanoutLimits::default();
+        let options = BufferOptions {
+            fanout_limits: &unlimited,
+            ..options
+        };
+        let report = insert_buffers(&mut design, &structure_cache, &options, extents, 1)?;
+        assert_eq!(report, BufferReport::default());
+
+        Ok(())
+    }
+
+    #[test]
+    fn repeaters_break_up_long_connections() -> anyhow::Result<()> {
+        // Two sinks far off the same way, and one close by
+        let mut design = PlacedDesign {
+            cells: vec![
+                cell("", "MCPNR_SWITCHES", (0, 0, 0), &[("O", &[1])]),
+                cell("", "gate_not.nbt", (8, 0, 0), &[("A", &[1])]),
+                cell("", "gate_not.nbt", (70, 0, 0), &[("A", &[1])]),
+                cell("", "gate_not.nbt", (70, 0, 8), &[("A", &[1])]),
+            ],
+            ..Default::default()
+        };
+        let structure_cache = StructureCache::new(&techlib(), &design, &BlockerRules::builtin())?;
+        let limits = FanoutLimits::default();
+        let options = BufferOptions {
+            buffer_type: "gate_not.nbt",
+            fanout_limits: &limits,
+            max_wire_length: Some(30),
+        };
+        let extents = [80, 16, 24];
+
+        let report = insert_buffers(&mut design, &structure_cache, &options, extents, 1)?;
+        // The far sinks share a repeater 30 blocks out, and another 30 blocks further on
+        assert_eq!(report.repeaters, 2);
+        assert_eq!(report.fanout_buffers, 0);
+        PlacementCheck::new(&design, &structure_cache, extents, 1)?.ensure_clean(&design)?;
+
+        let netlist = Netlist::new(&design, &structure_cache)?;
+        let mut connections = 0;
+        for (_, net) in netlist.iter_nets() {
+            let driver = net.iter_drivers(&netlist).next().unwrap();
+            for sink in net.iter_sinks(&netlist) {
+                let length = driver.x.abs_diff(sink.x)
+                    + driver.y.abs_diff(sink.y)
+                    + driver.z.abs_diff(sink.z);
+                assert!(
+                    length <= 30,
+                    "{:?} to {:?} is {} blocks",
+                    driver,
+                    sink,
+                    length
+                );
+                connections += 1;
+            }
+        }
+        assert_eq!(connections, 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn repeaters_only_go_where_they_help() -> anyhow::Result<()> {
+        // The sink is a whole tier up, more than the longest wire allowed, so no repeater can
+        // start out any closer to it than the driver
+        let mut design = PlacedDesign {
+            cells: vec![
+                cell("", "MCPNR_SWITCHES", (0, 0, 0), &[("O", &[1])]),
+                cell("", "gate_not.nbt", (40, 16, 0), &[("A", &[1])]),
+            ],
+            ..Default::default()
+        };
+        let structure_cache = StructureCache::new(&techlib(), &design, &BlockerRules::builtin())?;
+        let limits = FanoutLimits::default();
+        let options = BufferOptions {
+            buffer_type: "gate_not.nbt",
+            fanout_limits: &limits,
+            max_wire_length: Some(16),
+        };
+
+        let report = insert_buffers(&mut design, &structure_cache, &options, [48, 32, 16], 2)?;
+        assert_eq!(report, BufferReport::default());
+        assert_eq!(design.cells.len(), 2);
+
+        Ok(())
+    }
+}
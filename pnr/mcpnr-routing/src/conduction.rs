@@ -0,0 +1,346 @@
+//! Tests that splatted wire segments and vias conduct. The golden tests only notice that the
+//! blocks of a segment changed, not whether they still carry a signal, so here every combination
+//! of layers and directions [`splat_wire_segment`] and [`splat_via`] support is splatted between a
+//! straight wire coming in and one going out, and a simple model of redstone dust checks the two
+//! ends are connected.
+//!
+//! The model only knows dust, air and solid blocks. Dust has to stand on a solid block, connects
+//! to dust next to it on the same level, steps up on to dust one block higher unless there's a
+//! solid block over it, and steps down to dust one block lower unless there's a solid block over
+//! that dust.
+
+use std::collections::{HashSet, VecDeque};
+
+use anyhow::{anyhow, ensure, Result};
+use mcpnr_common::block_storage::{Block, BlockStorage, Direction, Position};
+
+use crate::detail_routing::wire_segment::{
+    pin_via_direction, splat_pin_via, splat_via, splat_wire_segment, LayerPosition, WireTierLayer,
+    WIRE_BASE_BLOCK, WIRE_GRID_SCALE,
+};
+use crate::detail_routing::{Layer, ALL_LAYERS};
+
+/// Room for two grid cells of wire on either side of [`WIRE_START`] and a via up to M3
+const EXTENTS: [u32; 3] = [24, 24, 24];
+const WIRE_START: (i32, i32) = (5, 5);
+
+const DIRECTIONS: [Direction; 4] = [
+    Direction::North,
+    Direction::South,
+    Direction::East,
+    Direction::West,
+];
+
+const HORIZONTAL: [(i32, i32); 4] = [(0, -1), (0, 1), (1, 0), (-1, 0)];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Air,
+    Dust,
+    Solid,
+}
+
+fn kind(o: &BlockStorage, pos: Position) -> Result<Kind> {
+    let [sx, sy, sz] = *o.extents();
+    if pos.x < 0
+        || pos.y < 0
+        || pos.z < 0
+        || pos.x >= sx as i32
+        || pos.y >= sy as i32
+        || pos.z >= sz as i32
+    {
+        return Ok(Kind::Air);
+    }
+    let block = o.get_block(pos.x as u32, pos.y as u32, pos.z as u32)?;
+    let name = &o
+        .info_for_index(*block)
+        .ok_or_else(|| anyhow!("Block at {} is not in the palette", pos))?
+        .name;
+    Ok(match name.as_str() {
+        "minecraft:air" => Kind::Air,
+        "minecraft:redstone_wire" => Kind::Dust,
+        _ => Kind::Solid,
+    })
+}
+
+/// Dust that dust at `pos` connects to
+fn connections(o: &BlockStorage, pos: Position) -> Result<Vec<Position>> {
+    let mut connected = Vec::new();
+    let open_above = kind(o, pos.offset(Direction::Up))? != Kind::Solid;
+    for (dx, dz) in HORIZONTAL {
+        let side = Position::new(pos.x + dx, pos.y, pos.z + dz);
+        match kind(o, side)? {
+            Kind::Dust => connected.push(side),
+            Kind::Solid => {
+                let up = side.offset(Direction::Up);
+                if open_above && kind(o, up)? == Kind::Dust {
+                    connected.push(up);
+                }
+            }
+            Kind::Air => {
+                let down = side.offset(Direction::Down);
+                if kind(o, down)? == Kind::Dust {
+                    connected.push(down);
+                }
+            }
+        }
+    }
+    Ok(connected)
+}
+
+/// Whether a signal at dust `from` reaches dust `to`
+fn conducts(o: &BlockStorage, from: Position, to: Position) -> Result<bool> {
+    ensure!(kind(o, from)? == Kind::Dust, "No dust at {}", from);
+    let mut seen = HashSet::from([from]);
+    let mut queue = VecDeque::from([from]);
+    while let Some(pos) = queue.pop_front() {
+        if pos == to {
+            return Ok(true);
+        }
+        for next in connections(o, pos)? {
+            if seen.insert(next) {
+                queue.push_back(next);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Dust that would pop off because there's nothing under it
+fn floating_dust(o: &BlockStorage) -> Result<Vec<Position>> {
+    let [sx, sy, sz] = *o.extents();
+    let mut floating = Vec::new();
+    for y in 0..sy as i32 {
+        for z in 0..sz as i32 {
+            for x in 0..sx as i32 {
+                let pos = Position::new(x, y, z);
+                if kind(o, pos)? == Kind::Dust
+                    && kind(o, pos.offset(Direction::Down))? != Kind::Solid
+                {
+                    floating.push(pos);
+                }
+            }
+        }
+    }
+    Ok(floating)
+}
+
+/// Dust in the corner of grid cell `pos` on `layer` of the first tier
+fn corner_dust(pos: LayerPosition, layer: Layer) -> Position {
+    Position::new(
+        pos.x.to_block_coord(),
+        layer.to_y_idx() as i32 + 1,
+        pos.y.to_block_coord(),
+    )
+}
+
+/// Splat a straight wire through `pos` in direction `direction`
+fn straight(
+    o: &mut BlockStorage,
+    pos: LayerPosition,
+    layer: Layer,
+    direction: Direction,
+    base: &Block,
+) -> Result<()> {
+    let wire = (WireTierLayer::new(0, layer), direction);
+    splat_wire_segment(o, pos, wire, wire, base)?;
+    Ok(())
+}
+
+#[test]
+fn wire_segments_conduct() -> Result<()> {
+    assert_eq!(WIRE_GRID_SCALE, 2, "The model assumes 2 block grid cells");
+    let base = Block::new(WIRE_BASE_BLOCK.to_owned());
+    let start = LayerPosition::new(WIRE_START.0.into(), WIRE_START.1.into());
+
+    let mut layer_pairs = Vec::new();
+    for (idx, layer) in ALL_LAYERS.into_iter().enumerate() {
+        layer_pairs.push((layer, layer));
+        if let Some(next) = ALL_LAYERS.get(idx + 1) {
+            layer_pairs.push((layer, *next));
+            layer_pairs.push((*next, layer));
+        }
+    }
+
+    let mut supported = 0;
+    let mut broken = Vec::new();
+    for (input_layer, output_layer) in layer_pairs {
+        for input_direction in DIRECTIONS {
+            for output_direction in DIRECTIONS {
+                let [sx, sy, sz] = EXTENTS;
+                let mut o = BlockStorage::new(sx, sy, sz);
+                let name = format!(
+                    "{:?} {:?} -> {:?} {:?}",
+                    input_layer, input_direction, output_layer, output_direction
+                );
+
+                let previous = start.offset(input_direction.mirror())?;
+                straight(&mut o, previous, input_layer, input_direction, &base)?;
+                let next = match splat_wire_segment(
+                    &mut o,
+                    start,
+                    (WireTierLayer::new(0, input_layer), input_direction),
+                    (WireTierLayer::new(0, output_layer), output_direction),
+                    &base,
+                ) {
+                    Ok((next, _)) => next,
+                    // Unsupported combinations are covered by the golden tests
+                    Err(_) => continue,
+                };
+                supported += 1;
+                straight(&mut o, next, output_layer, output_direction, &base)?;
+
+                let from = corner_dust(previous, input_layer);
+                let to = corner_dust(next, output_layer);
+                if !conducts(&o, from, to)? {
+                    broken.push(format!("{}: {} doesn't reach {}", name, from, to));
+                }
+                for pos in floating_dust(&o)? {
+                    broken.push(format!("{}: dust at {} has nothing under it", name, pos));
+                }
+            }
+        }
+    }
+
+    assert!(supported > 0);
+    assert!(
+        broken.is_empty(),
+        "{} of {} wire segments are broken:\n{}",
+        broken.len(),
+        supported,
+        broken.join("\n")
+    );
+
+    Ok(())
+}
+
+#[test]
+fn vias_conduct() -> Result<()> {
+    let base = Block::new(WIRE_BASE_BLOCK.to_owned());
+    let start = LayerPosition::new(WIRE_START.0.into(), WIRE_START.1.into());
+
+    let mut vias = 0;
+    let mut broken = Vec::new();
+    for (lower_idx, lower_layer) in ALL_LAYERS.into_iter().enumerate() {
+        for upper_layer in &ALL_LAYERS[lower_idx + 1..] {
+            for input_direction in DIRECTIONS {
+                for output_direction in DIRECTIONS {
+                    let [sx, sy, sz] = EXTENTS;
+                    let mut o = BlockStorage::new(sx, sy, sz);
+                    let name = format!(
+                        "{:?} {:?} -> {:?} {:?}",
+                        lower_layer, input_direction, upper_layer, output_direction
+                    );
+
+                    let previous = start.offset(input_direction.mirror())?;
+                    let next = start.offset(output_direction)?;
+                    straight(&mut o, previous, lower_layer, input_direction, &base)?;
+                    splat_via(
+                        &mut o,
+                        start,
+                        (WireTierLayer::new(0, lower_layer), input_direction),
+                        (WireTierLayer::new(0, *upper_layer), output_direction),
+                        &base,
+                    )?;
+                    straight(&mut o, next, *upper_layer, output_direction, &base)?;
+                    vias += 1;
+
+                    let from = corner_dust(previous, lower_layer);
+                    let to = corner_dust(next, *upper_layer);
+                    if !conducts(&o, from, to)? {
+                        broken.push(format!("{}: {} doesn't reach {}", name, from, to));
+                    }
+                    for pos in floating_dust(&o)? {
+                        broken.push(format!("{}: dust at {} has nothing under it", name, pos));
+                    }
+                }
+            }
+        }
+    }
+
+    assert!(
+        broken.is_empty(),
+        "{} of {} vias are broken:\n{}",
+        broken.len(),
+        vias,
+        broken.join("\n")
+    );
+
+    Ok(())
+}
+
+#[test]
+fn pin_vias_conduct() -> Result<()> {
+    let base = Block::new(WIRE_BASE_BLOCK.to_owned());
+    let start = LayerPosition::new(WIRE_START.0.into(), WIRE_START.1.into());
+
+    let mut vias = 0;
+    let mut broken = Vec::new();
+    for (lower_idx, lower_layer) in ALL_LAYERS.into_iter().enumerate() {
+        for upper_layer in &ALL_LAYERS[lower_idx + 1..] {
+            for (dx, dz) in [(0, 0), (1, 0), (0, 1)] {
+                for direction in DIRECTIONS {
+                    // A pin facing up on the lower layer, and one facing down on the upper layer
+                    for pin_layer in [lower_layer, *upper_layer] {
+                        let [sx, sy, sz] = EXTENTS;
+                        let mut o = BlockStorage::new(sx, sy, sz);
+                        let corner = corner_dust(start, pin_layer);
+                        let pin = Position::new(corner.x + dx, corner.y, corner.z + dz);
+                        let name = format!(
+                            "{:?} -> {:?} {:?} with the pin at {} on {:?}",
+                            lower_layer, upper_layer, direction, pin, pin_layer
+                        );
+
+                        // The cell the pin is on, which the via has to leave alone
+                        let sign = o.add_new_block_type(Block::new("minecraft:oak_sign".into()));
+                        let target = o.add_new_block_type(Block::new("minecraft:target".into()));
+                        *o.get_block_mut(pin.x as u32, pin.y as u32, pin.z as u32)? = sign;
+                        *o.get_block_mut(pin.x as u32, pin.y as u32 - 1, pin.z as u32)? = target;
+
+                        let pin_direction = pin_via_direction(pin)?;
+                        let (lower, upper, wire, wire_layer) = if pin_layer == lower_layer {
+                            let next = start.offset(direction)?;
+                            (pin_direction, direction, next, *upper_layer)
+                        } else {
+                            let previous = start.offset(direction.mirror())?;
+                            (direction, pin_direction.mirror(), previous, lower_layer)
+                        };
+                        straight(&mut o, wire, wire_layer, direction, &base)?;
+                        splat_pin_via(
+                            &mut o,
+                            start,
+                            (WireTierLayer::new(0, lower_layer), lower),
+                            (WireTierLayer::new(0, *upper_layer), upper),
+                            pin,
+                            &base,
+                        )?;
+                        vias += 1;
+
+                        let to = corner_dust(wire, wire_layer);
+                        if !conducts(&o, pin, to)? {
+                            broken.push(format!("{}: {} doesn't reach {}", name, pin, to));
+                        }
+                        if kind(&o, pin.offset(Direction::Down))? != Kind::Solid
+                            || *o.get_block(pin.x as u32, pin.y as u32 - 1, pin.z as u32)? != target
+                        {
+                            broken.push(format!("{}: the block under the pin changed", name));
+                        }
+                        for pos in floating_dust(&o)? {
+                            broken.push(format!("{}: dust at {} has nothing under it", name, pos));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    assert!(
+        broken.is_empty(),
+        "{} of {} pin vias are broken:\n{}",
+        broken.len(),
+        vias,
+        broken.join("\n")
+    );
+
+    Ok(())
+}
@@ -0,0 +1,713 @@
+//! Command line interface and configuration for the router.
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use mcpnr_common::{block_storage::Position, blockers::BLOCKER_RULES_FILE, memory::parse_size};
+use std::{path::PathBuf, time::Duration};
+
+use crate::detail_routing::LAYERS_PER_TIER;
+use crate::netlist_check::FANOUT_LIMITS_FILE;
+use crate::splat::DYE_COLORS;
+
+/// Top level command line interface
+#[derive(Debug, Parser)]
+#[clap(
+    name = "mcpnr-routing",
+    version,
+    author,
+    about = "Routing phase for the MCPNR flow"
+)]
+pub struct Cli {
+    #[clap(subcommand)]
+    pub command: Option<RouterCommand>,
+
+    /// Write the router's spans to this file in the Chrome trace format, to be profiled in
+    /// Perfetto or chrome://tracing. Spans down to single nets are recorded whatever RUST_LOG is
+    /// set to.
+    #[clap(long, global = true, value_name = "FILE")]
+    pub trace_json: Option<PathBuf>,
+}
+
+/// Subcommands supported by the router
+#[derive(Debug, Subcommand)]
+pub enum RouterCommand {
+    /// Route a placed design and write the resulting world
+    Route(RouteArgs),
+    /// Load a placed design against the technology library and check that it is routable,
+    /// without routing it
+    Verify(CommonArgs),
+    /// Splat the placed cells without routing them and write the resulting world
+    Export(OutputArgs),
+    /// Run a GUI for interactive debugging of the router
+    Gui(CommonArgs),
+    /// Run the full routing flow without writing any output
+    DryRun(CommonArgs),
+    /// Check the technology library, options and placed design for problems, and suggest how to
+    /// fix them
+    Doctor(CommonArgs),
+    /// Insert buffers on the long and high fan-out nets of a placed design, or copy their
+    /// drivers, and write the result as a placed design to route
+    Buffer(BufferArgs),
+    /// Compare two routed outputs of the same design, block by block and, given the placed
+    /// design, net by net
+    Diff(DiffArgs),
+    /// Print the runs logged to a results file with --results, and how their results changed
+    Report(ReportArgs),
+}
+
+/// Arguments shared by every subcommand
+#[derive(Clone, Debug, Args)]
+pub struct CommonArgs {
+    /// Path to the technology library.
+    ///
+    /// The technology library is expected to be a folder, containing a folder named "structures"
+    /// with a minecraft NBT structure format for each standard cell.
+    #[clap(long, value_name = "TECHLIB")]
+    pub techlib: PathBuf,
+
+    /// Number of tiers in the output. I/O macros placed on top of the top tier add a roof tier
+    /// above it, which holds nothing else
+    #[clap(long, value_name = "TIERS", default_value = "1")]
+    pub tiers: u32,
+
+    /// Input design, as the output of the MCPNR placer. Files ending in .json are read as the
+    /// placer's JSON output, files ending in .pbs or .jsonl in the streaming format and anything
+    /// else as protobuf.
+    #[clap(value_name = "INPUT")]
+    pub input: PathBuf,
+
+    /// Blockage map written by the placer with --blockage-map. When given, the blocked cells and
+    /// pins are taken from it instead of being worked out from the splatted blocks.
+    #[clap(long, value_name = "FILE")]
+    pub blockage_map: Option<PathBuf>,
+
+    #[clap(flatten)]
+    pub budget: BudgetArgs,
+
+    #[clap(flatten)]
+    pub infrastructure: InfrastructureArgs,
+
+    #[clap(flatten)]
+    pub markers: MarkerArgs,
+
+    /// Route the bits of each multi-bit signal one after another, laying each bit out alongside
+    /// the previous one so buses come out as parallel wires on the same layers
+    #[clap(long)]
+    pub bus_routing: bool,
+
+    /// Keep wires to tracks: X on M0 and M2, Z on M1 and M3. Parallel wires come out evenly
+    /// spaced, and routes change layer to turn instead of jogging across other nets' tracks.
+    #[clap(long)]
+    pub track_routing: bool,
+
+    /// Clock period the design is meant to run at, in redstone ticks. The timing report says
+    /// whether the slowest path through the design fits in it.
+    #[clap(long, value_name = "TICKS")]
+    pub clock_period: Option<u32>,
+
+    /// Constraints file giving the timing criticality of nets, with lines like
+    /// `set_criticality 0.9 carry*`. More critical nets are routed first.
+    #[clap(long, value_name = "FILE")]
+    pub timing_constraints: Option<PathBuf>,
+
+    /// Order to route nets of the same criticality in, in every routing pass. With --bus-routing
+    /// each bus goes where its first net in this order would.
+    #[clap(long, value_enum, value_name = "ORDER", default_value = "index")]
+    pub net_order: NetOrder,
+
+    /// What to do with nets that have more than one driver
+    #[clap(long, value_enum, value_name = "MODE", default_value = "report")]
+    pub multi_driver: MultiDriverMode,
+
+    /// Stop before routing if the netlist has undriven nets, floating inputs or drivers with more
+    /// sinks than their fan-out limit, instead of just warning about them
+    #[clap(long)]
+    pub strict: bool,
+
+    /// Most sinks any cell's output may drive, for cell types without a limit of their own in the
+    /// techlib's fanout_limits.json
+    #[clap(long, value_name = "SINKS", value_parser = parse_fanout_limit)]
+    pub max_fanout: Option<usize>,
+
+    /// Split nets with more sinks than their driver's fan-out limit by inserting copies of this
+    /// techlib cell, which must have one input and one output carrying the same signal, like
+    /// `buffer.nbt`. They're placed in free space next to the sinks they drive.
+    #[clap(long, value_name = "CELL")]
+    pub buffer_cell: Option<String>,
+
+    /// Put copies of --buffer-cell in as repeaters on connections whose pins are more than this
+    /// many blocks apart, so the signal is refreshed along the way. Every wire then has to reach
+    /// its sink on the signal strength its driver pin puts out, after both pins' derating.
+    #[clap(long, value_name = "BLOCKS", requires = "buffer-cell")]
+    pub max_wire_length: Option<u32>,
+
+    /// Copy small drivers, like inverters and buffers, of nets with more than this many sinks, so
+    /// each copy drives a group of nearby sinks. Done before any buffering.
+    #[clap(long, value_name = "SINKS", value_parser = parse_fanout_limit)]
+    pub replicate_above: Option<usize>,
+
+    /// Write a JSON map of routing congestion to this file: for every cell of every layer, how
+    /// many nets' searches wanted it and whether a wire ended up there. Cells many nets wanted
+    /// show where growing the die or adding a tier would help.
+    #[clap(long, value_name = "FILE")]
+    pub congestion_map: Option<PathBuf>,
+
+    /// Write weights for the nets routing had trouble with to this file, for the placer's
+    /// --net-weights. Placing again with them keeps those nets shorter.
+    #[clap(long, value_name = "FILE")]
+    pub net_feedback: Option<PathBuf>,
+
+    /// Record the order the searches for this net, by name or number, expanded cells in, and
+    /// write it to --wavefront-dir as a series of PPM frames. Shows why a route wandered where it
+    /// did.
+    #[clap(long, value_name = "NET", requires = "wavefront-dir")]
+    pub wavefront: Option<String>,
+
+    /// Directory to write the --wavefront frames to
+    #[clap(long, value_name = "DIR", requires = "wavefront")]
+    pub wavefront_dir: Option<PathBuf>,
+
+    /// Cells expanded between --wavefront frames
+    #[clap(long, value_name = "CELLS", default_value = "100")]
+    pub wavefront_interval: usize,
+
+    /// Fail up front if the output and routing grid would need more than this much memory, like
+    /// `8G` or `512MiB`
+    #[clap(long, value_name = "SIZE", value_parser = parse_memory_size)]
+    pub max_memory: Option<u64>,
+
+    /// Append a row with the results of routing (unrouted nets, blocks of wire, runtime) to this
+    /// CSV file. The placer's --results can log to the same file, see the report subcommand.
+    #[clap(long, value_name = "FILE")]
+    pub results: Option<PathBuf>,
+}
+
+/// How the router handles nets driven by more than one cell
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum MultiDriverMode {
+    /// Leave the net unrouted, and report its drivers with the Yosys passes that usually fix it
+    Report,
+    /// Wire every driver in to the same net, so it carries the OR of all of them
+    Merge,
+}
+
+/// Order the router takes nets of the same criticality in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum NetOrder {
+    /// Ascending net number
+    Index,
+    /// Smallest bounding box around the pins first, so short nets take direct routes before long
+    /// ones wander over them
+    BoundingBox,
+    /// Most sinks first, while there's still room for all their branches
+    Fanout,
+    /// Highest `mcpnr_route_priority` attribute first, nets without one last
+    Priority,
+}
+
+/// How wires on the metal layers are held up
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SupportMode {
+    /// A pillar down to the next block under every routing grid cell of wire
+    Pillars,
+    /// A floor under every metal layer that has wires in it
+    Floor,
+}
+
+/// Limits on how much work the router spends on nets in a single pass. Nets that exceed a budget
+/// are deferred to the next pass.
+#[derive(Clone, Debug, Args)]
+pub struct BudgetArgs {
+    /// Wall-clock time allowed for each routing pass. Once it runs out, the remaining nets in the
+    /// pass are deferred.
+    #[clap(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    pub pass_time_budget: Option<Duration>,
+
+    /// Number of search nodes the router may expand for a single net in a pass before the net is
+    /// deferred.
+    #[clap(long, value_name = "NODES")]
+    pub net_expansion_budget: Option<u64>,
+}
+
+/// Blocks added around the cells so the design can be looked after in-game. Wires are kept out of
+/// them.
+#[derive(Clone, Debug, Args)]
+pub struct InfrastructureArgs {
+    /// Put a light every BLOCKS blocks along X and Z, at the top of the cell layer of every tier,
+    /// so mobs don't spawn between the cells. Lights only go where the cell layer is empty.
+    #[clap(long, value_name = "BLOCKS")]
+    pub light_spacing: Option<u32>,
+
+    /// Block to light the design with. It has to stand on its own, so torches don't work.
+    #[clap(long, value_name = "BLOCK", default_value = "minecraft:glowstone")]
+    pub light_block: String,
+
+    /// Leave a corridor to walk along Z every BLOCKS blocks along X, on the floor of every tier
+    #[clap(long, value_name = "BLOCKS")]
+    pub corridor_spacing: Option<u32>,
+
+    /// Width of the corridors, in blocks
+    #[clap(long, value_name = "BLOCKS", default_value = "2")]
+    pub corridor_width: u32,
+}
+
+/// How the edges of the die are marked, along the top of the output
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum BorderStyle {
+    /// No border
+    Off,
+    /// Every other pair of blocks
+    Checkered,
+    /// Every block
+    Solid,
+}
+
+/// How much of each layer of each tier is marked with stained glass
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TierMarkers {
+    /// No markers
+    Off,
+    /// Around the edges of the die only
+    Frame,
+    /// The whole plane of the layer
+    Full,
+}
+
+/// Stained glass colors of the tier markers: two for each layer from LI up to M3, alternating in
+/// a checkerboard
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TierMarkerColors(pub [[String; 2]; LAYERS_PER_TIER as usize]);
+
+/// Blocks marking the edges of the die and the layers of each tier, so the output can be found
+/// around in-game. Wires can't go through the border, but can through the tier markers.
+#[derive(Clone, Debug, Args)]
+pub struct MarkerArgs {
+    /// How to mark the edges of the die, along the top of the output
+    #[clap(long, value_enum, value_name = "STYLE", default_value = "checkered")]
+    pub border: BorderStyle,
+
+    /// Color of the wool of the border
+    #[clap(long, value_name = "COLOR", value_parser = parse_dye_color, default_value = "black")]
+    pub border_color: String,
+
+    /// Mark the bottom of each layer of each tier with stained glass
+    #[clap(long, value_enum, value_name = "DENSITY", default_value = "off")]
+    pub tier_markers: TierMarkers,
+
+    /// Colors of the tier markers, as a pair of colors for each layer from LI up to M3, like
+    /// `white/black,light_gray/gray,...`. The two colors of each layer alternate in a
+    /// checkerboard.
+    #[clap(
+        long,
+        value_name = "COLORS",
+        value_parser = parse_tier_marker_colors,
+        default_value = "white/black,light_gray/gray,blue/light_blue,magenta/pink,red/orange"
+    )]
+    pub tier_marker_colors: TierMarkerColors,
+}
+
+fn parse_dye_color(s: &str) -> Result<String, String> {
+    if DYE_COLORS.contains(&s) {
+        Ok(s.to_owned())
+    } else {
+        Err(format!("expected one of {}", DYE_COLORS.join(", ")))
+    }
+}
+
+fn parse_tier_marker_colors(s: &str) -> Result<TierMarkerColors, String> {
+    let pairs = s
+        .split(',')
+        .map(|pair| match pair.trim().split_once('/') {
+            Some((a, b)) => Ok([parse_dye_color(a.trim())?, parse_dye_color(b.trim())?]),
+            None => Err(format!("expected COLOR/COLOR but got {:?}", pair)),
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let count = pairs.len();
+    pairs.try_into().map(TierMarkerColors).map_err(|_| {
+        format!(
+            "expected a pair of colors for each of the {} layers but got {}",
+            LAYERS_PER_TIER, count
+        )
+    })
+}
+
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    let seconds: f64 = s.parse().map_err(|e| format!("{}", e))?;
+    Duration::try_from_secs_f64(seconds).map_err(|e| format!("{}", e))
+}
+
+fn parse_fanout_limit(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>().map_err(|e| format!("{}", e))? {
+        0 => Err("a cell has to be able to drive at least one sink".to_owned()),
+        limit => Ok(limit),
+    }
+}
+
+fn parse_memory_size(s: &str) -> Result<u64, String> {
+    parse_size(s).map_err(|e| format!("{}", e))
+}
+
+fn parse_position(s: &str) -> Result<Position, String> {
+    let coords = s
+        .split(',')
+        .map(|c| c.trim().parse().map_err(|e| format!("{}", e)))
+        .collect::<Result<Vec<i32>, String>>()?;
+    match coords[..] {
+        [x, y, z] => Ok(Position::new(x, y, z)),
+        _ => Err(format!(
+            "expected X,Y,Z but got {} coordinates",
+            coords.len()
+        )),
+    }
+}
+
+/// Arguments for subcommands that write an output world
+#[derive(Clone, Debug, Args)]
+pub struct OutputArgs {
+    #[clap(flatten)]
+    pub common: CommonArgs,
+
+    /// Output file location. It's written as a WorldEdit schematic if it ends in .schem, as gzipped
+    /// structure JSON if it ends in .gz, and as structure JSON otherwise.
+    #[clap(value_name = "OUTPUT")]
+    pub output: PathBuf,
+
+    /// Don't write a markdown summary of the design next to the output file
+    #[clap(long)]
+    pub no_summary: bool,
+
+    /// Put a sign with the net name next to the routed wires every BLOCKS blocks, so wires can be
+    /// identified in-game
+    #[clap(long, value_name = "BLOCKS")]
+    pub net_labels: Option<u32>,
+
+    /// Put the wires of each net on a different color of concrete instead of calcite, so nets can
+    /// be traced in-game
+    #[clap(long)]
+    pub colorize_nets: bool,
+
+    /// Put glass under the routed wires of the metal layers, so they don't float in midair
+    #[clap(long, value_enum, value_name = "MODE")]
+    pub supports: Option<SupportMode>,
+
+    /// Also write the output straight in to the region files of this Minecraft world save.
+    /// Chunks the design touches are replaced.
+    #[clap(long, value_name = "WORLD")]
+    pub world: Option<PathBuf>,
+
+    /// Where the origin of the design goes in the world save
+    #[clap(
+        long,
+        value_name = "X,Y,Z",
+        value_parser = parse_position,
+        allow_hyphen_values = true,
+        default_value = "0,0,0"
+    )]
+    pub world_offset: Position,
+}
+
+/// Arguments for the route subcommand
+#[derive(Clone, Debug, Args)]
+pub struct RouteArgs {
+    #[clap(flatten)]
+    pub output: OutputArgs,
+
+    #[clap(flatten)]
+    pub eco: EcoArgs,
+
+    /// Instead of routing everything, read commands from stdin to list, route and rip up nets one
+    /// at a time, look at the routing grid and save the output along the way. Type `help` at the
+    /// prompt for the commands.
+    #[clap(long)]
+    pub interactive: bool,
+}
+
+/// Arguments for the buffer subcommand
+#[derive(Clone, Debug, Args)]
+pub struct BufferArgs {
+    #[clap(flatten)]
+    pub common: CommonArgs,
+
+    /// Where to write the buffered design, in any format the router reads
+    #[clap(value_name = "OUTPUT")]
+    pub output: PathBuf,
+}
+
+/// Arguments for the diff subcommand
+#[derive(Clone, Debug, Args)]
+pub struct DiffArgs {
+    /// Structure JSON written by the earlier route
+    #[clap(value_name = "BEFORE")]
+    pub before: PathBuf,
+
+    /// Structure JSON written by the later route
+    #[clap(value_name = "AFTER")]
+    pub after: PathBuf,
+
+    /// Placed design both outputs were routed from. With it, the wires of each net are picked out
+    /// of both outputs and the nets whose wires changed are listed.
+    #[clap(long, value_name = "FILE", requires = "techlib")]
+    pub design: Option<PathBuf>,
+
+    /// Path to the technology library the outputs were routed with, needed for --design
+    #[clap(long, value_name = "TECHLIB")]
+    pub techlib: Option<PathBuf>,
+
+    /// Number of changed nets to list
+    #[clap(long, value_name = "NETS", default_value = "20")]
+    pub top: usize,
+
+    /// The border and tier markers the outputs were routed with, needed for --design to tell them
+    /// apart from wires
+    #[clap(flatten)]
+    pub markers: MarkerArgs,
+}
+
+/// Arguments for the report subcommand
+#[derive(Clone, Debug, Args)]
+pub struct ReportArgs {
+    /// CSV file the runs were logged to with --results
+    #[clap(value_name = "RESULTS")]
+    pub results: PathBuf,
+
+    /// Only report the runs of this design, named after the stem of its input file
+    #[clap(long, value_name = "NAME")]
+    pub design: Option<String>,
+
+    /// Number of the latest runs of each design to list. The trends cover every run.
+    #[clap(long, value_name = "RUNS", default_value = "10")]
+    pub last: usize,
+}
+
+/// Arguments for rerouting a few nets of an earlier route instead of the whole design
+#[derive(Clone, Debug, Args)]
+pub struct EcoArgs {
+    /// Structure JSON written by an earlier route of the same placement. Only the nets given with
+    /// --eco-net are ripped up and routed again, every other wire is kept where it is.
+    #[clap(
+        long,
+        value_name = "FILE",
+        requires = "eco-net",
+        conflicts_with = "blockage-map"
+    )]
+    pub eco: Option<PathBuf>,
+
+    /// Net to reroute with --eco, by name or number. Can be given more than once.
+    #[clap(long, value_name = "NET", requires = "eco")]
+    pub eco_net: Vec<String>,
+}
+
+/// Nets to reroute on top of an earlier route
+#[derive(Clone, Debug)]
+pub struct EcoConfig {
+    /// Structure JSON of the earlier route
+    pub previous_output: PathBuf,
+    /// Names or numbers of the nets to reroute
+    pub nets: Vec<String>,
+}
+
+/// Net to record the searches of, see [`crate::detail_routing::Wavefront`]
+#[derive(Clone, Debug)]
+pub struct WavefrontConfig {
+    /// Name or number of the net
+    pub net: String,
+    /// Directory to write the frames to
+    pub dir: PathBuf,
+    /// Cells expanded between frames
+    pub interval: usize,
+}
+
+/// Lights and corridors to add to the design
+#[derive(Clone, Debug)]
+pub struct InfrastructureConfig {
+    /// Blocks between lights along X and Z, if the design is lit
+    pub light_spacing: Option<u32>,
+    /// Name of the block to light the design with
+    pub light_block: String,
+    /// Blocks between corridors along X, if there are any
+    pub corridor_spacing: Option<u32>,
+    /// Width of each corridor in blocks
+    pub corridor_width: u32,
+}
+
+/// Border and tier markers to draw around the design
+#[derive(Clone, Debug)]
+pub struct MarkerConfig {
+    /// How to mark the edges of the die
+    pub border: BorderStyle,
+    /// Dye color of the border's wool
+    pub border_color: String,
+    /// How much of each layer to mark
+    pub tier_markers: TierMarkers,
+    /// Dye colors of the tier markers of each layer
+    pub tier_marker_colors: TierMarkerColors,
+}
+
+impl From<&MarkerArgs> for MarkerConfig {
+    fn from(args: &MarkerArgs) -> Self {
+        Self {
+            border: args.border,
+            border_color: args.border_color.clone(),
+            tier_markers: args.tier_markers,
+            tier_marker_colors: args.tier_marker_colors.clone(),
+        }
+    }
+}
+
+/// Overall routing configuration
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Input file name (a mcpnr placement file)
+    pub input_file: PathBuf,
+    /// Directory of the structure database, derived from the path to the technology library.
+    pub structure_directory: PathBuf,
+    /// Extra rules for which blocks wires must avoid, derived from the path to the technology
+    /// library. The file doesn't have to exist.
+    pub blocker_rules_file: PathBuf,
+    /// Output file name, if this command writes one
+    pub output_file: Option<PathBuf>,
+    /// Number of tiers in the output
+    pub tiers: u32,
+    /// Blockage map from the placer, if one was given
+    pub blockage_map_file: Option<PathBuf>,
+    /// Whether to write a markdown summary of the design next to the output file
+    pub write_summary: bool,
+    /// Wall-clock time allowed for each routing pass
+    pub pass_time_budget: Option<Duration>,
+    /// Node expansions allowed for each net in each routing pass
+    pub net_expansion_budget: Option<u64>,
+    /// Whether to route the bits of buses together
+    pub bus_routing: bool,
+    /// Whether to keep wires to the tracks of each layer
+    pub track_routing: bool,
+    /// Clock period to check the slowest path against, in redstone ticks
+    pub clock_period: Option<u32>,
+    /// Timing constraints to apply on top of the criticality attributes in the design, if any
+    pub timing_constraints_file: Option<PathBuf>,
+    /// Order to route nets of the same criticality in
+    pub net_order: NetOrder,
+    /// How to handle nets with more than one driver
+    pub multi_driver: MultiDriverMode,
+    /// Whether problems found by the netlist check are fatal
+    pub strict: bool,
+    /// Fan-out limits of the techlib's cells, derived from the path to the technology library.
+    /// The file doesn't have to exist.
+    pub fanout_limits_file: PathBuf,
+    /// Fan-out limit for cell types without their own, overriding the techlib's default
+    pub max_fanout: Option<usize>,
+    /// Cell type to buffer nets over their driver's fan-out limit with, if they should be
+    pub buffer_cell: Option<String>,
+    /// Longest connection to leave without a repeater, in blocks, if long nets are buffered
+    pub max_wire_length: Option<u32>,
+    /// Fan-out above which small drivers are copied, if they should be
+    pub replicate_above: Option<usize>,
+    /// Where to write the congestion map, if one is wanted
+    pub congestion_map_file: Option<PathBuf>,
+    /// Where to write net weights for the next placement, if anywhere
+    pub net_feedback_file: Option<PathBuf>,
+    /// Net to dump the search wavefront of, if any
+    pub wavefront: Option<WavefrontConfig>,
+    /// Memory the output and routing grid may use, in bytes, if limited
+    pub max_memory: Option<u64>,
+    /// CSV file to log the results of routing to, if any
+    pub results_file: Option<PathBuf>,
+    /// Blocks between the signs labelling routed wires with their net, if they're wanted
+    pub net_label_interval: Option<u32>,
+    /// Whether wires sit on a color per net instead of calcite
+    pub colorize_nets: bool,
+    /// How to hold up the wires of the metal layers, if at all
+    pub supports: Option<SupportMode>,
+    /// World save to write the output in to, if any
+    pub world: Option<PathBuf>,
+    /// Where the origin of the design goes in `world`
+    pub world_offset: Position,
+    /// Earlier route to reroute some nets of, instead of routing everything
+    pub eco: Option<EcoConfig>,
+    /// Whether to route from commands typed in instead of routing everything
+    pub interactive: bool,
+    /// Lights and corridors to add around the cells
+    pub infrastructure: InfrastructureConfig,
+    /// Border and tier markers to draw
+    pub markers: MarkerConfig,
+}
+
+impl Config {
+    /// Construct a configuration for a subcommand that does not write any output
+    pub fn from_common_args(args: &CommonArgs) -> Self {
+        Self {
+            input_file: args.input.clone(),
+            structure_directory: args.techlib.join("structures"),
+            blocker_rules_file: args.techlib.join(BLOCKER_RULES_FILE),
+            output_file: None,
+            tiers: args.tiers,
+            blockage_map_file: args.blockage_map.clone(),
+            write_summary: false,
+            pass_time_budget: args.budget.pass_time_budget,
+            net_expansion_budget: args.budget.net_expansion_budget,
+            bus_routing: args.bus_routing,
+            track_routing: args.track_routing,
+            clock_period: args.clock_period,
+            timing_constraints_file: args.timing_constraints.clone(),
+            net_order: args.net_order,
+            multi_driver: args.multi_driver,
+            strict: args.strict,
+            fanout_limits_file: args.techlib.join(FANOUT_LIMITS_FILE),
+            max_fanout: args.max_fanout,
+            buffer_cell: args.buffer_cell.clone(),
+            max_wire_length: args.max_wire_length,
+            replicate_above: args.replicate_above,
+            congestion_map_file: args.congestion_map.clone(),
+            net_feedback_file: args.net_feedback.clone(),
+            wavefront: args
+                .wavefront
+                .clone()
+                .zip(args.wavefront_dir.clone())
+                .map(|(net, dir)| WavefrontConfig {
+                    net,
+                    dir,
+                    interval: args.wavefront_interval,
+                }),
+            max_memory: args.max_memory,
+            results_file: args.results.clone(),
+            net_label_interval: None,
+            colorize_nets: false,
+            supports: None,
+            world: None,
+            world_offset: Position::new(0, 0, 0),
+            eco: None,
+            interactive: false,
+            infrastructure: InfrastructureConfig {
+                light_spacing: args.infrastructure.light_spacing,
+                light_block: args.infrastructure.light_block.clone(),
+                corridor_spacing: args.infrastructure.corridor_spacing,
+                corridor_width: args.infrastructure.corridor_width,
+            },
+            markers: MarkerConfig::from(&args.markers),
+        }
+    }
+
+    /// Construct a configuration for a subcommand that writes an output world
+    pub fn from_output_args(args: &OutputArgs) -> Self {
+        Self {
+            output_file: Some(args.output.clone()),
+            write_summary: !args.no_summary,
+            net_label_interval: args.net_labels,
+            colorize_nets: args.colorize_nets,
+            supports: args.supports,
+            world: args.world.clone(),
+            world_offset: args.world_offset,
+            ..Self::from_common_args(&args.common)
+        }
+    }
+
+    /// Construct a configuration for the route subcommand
+    pub fn from_route_args(args: &RouteArgs) -> Self {
+        Self {
+            eco: args.eco.eco.as_ref().map(|previous_output| EcoConfig {
+                previous_output: previous_output.clone(),
+                nets: args.eco.eco_net.clone(),
+            }),
+            interactive: args.interactive,
+            ..Self::from_output_args(&args.output)
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Tidying up finished routes. Searches leave behind cells no sink's wire goes through, like taps
+//! nothing branched from and escapes of equivalent drivers that weren't needed, and backtracking
+//! happily produces staircases where an L would do. Neither changes what's connected, but stubs
+//! keep other nets out of the cells they hold and staircases are harder to follow in-game.
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{anyhow, Context, Result};
+use mcpnr_common::block_storage::{Direction, ALL_DIRECTIONS};
+
+use super::{DetailRouter, GridCell, GridCellPosition};
+use crate::RouteId;
+
+/// What [`DetailRouter::clean_up`] changed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CleanupStats {
+    /// Cells freed because no sink's wire went through them
+    pub pruned: u64,
+    /// Staircase steps squared off, each taking two corners out of a wire
+    pub straightened: u64,
+}
+
+impl DetailRouter {
+    /// Prune and straighten finished routes. `routes` gives the sink pins of each route and the
+    /// direction each faces, as passed to [`Self::route`]. Every sink has to be connected.
+    pub fn clean_up(
+        &mut self,
+        routes: &HashMap<RouteId, Vec<(GridCellPosition, Direction)>>,
+    ) -> Result<CleanupStats> {
+        let mut stats = CleanupStats::default();
+
+        let mut used: HashMap<RouteId, HashSet<GridCellPosition>> = HashMap::new();
+        for (id, sinks) in routes.iter() {
+            let cells = used.entry(*id).or_default();
+            for (sink, direction) in sinks.iter() {
+                let path = self
+                    .trace_route(*sink, *direction, *id)
+                    .with_context(|| anyhow!("Trace route {:?} from sink {}", id, sink))?;
+                cells.extend(path.into_iter().map(|(pos, _)| pos));
+            }
+        }
+
+        let stubs: Vec<GridCellPosition> = self
+            .grid
+            .iter()
+            .filter(|(pos, cell)| match cell {
+                GridCell::Occupied(_, id) => used.get(id).is_some_and(|used| !used.contains(pos)),
+                _ => false,
+            })
+            .map(|(pos, _)| pos)
+            .collect();
+        for pos in stubs {
+            *self.grid.get_mut(pos) = GridCell::Free;
+            stats.pruned += 1;
+        }
+
+        for (id, sinks) in routes.iter() {
+            let pins: HashSet<GridCellPosition> = sinks
+                .iter()
+                .map(|(sink, direction)| sink.offset(*direction))
+                .collect();
+            for (sink, direction) in sinks.iter() {
+                while self.straighten_one(*sink, *direction, *id, &pins)? {
+                    stats.straightened += 1;
+                }
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// Find the first staircase step on the wire from `sink` and square it off, turning the cells
+    /// leaving in `[a, b, a, b]` in to `[a, a, b, b]` by moving the cell between the two middle
+    /// corners. That takes away two corners without changing the length, so skew and timing are
+    /// unaffected. Returns whether anything changed.
+    ///
+    /// Only free cells in the same layer are moved in to, and cells other branches of the route
+    /// lead in to, or that a sink pin escapes to, are left alone.
+    fn straighten_one(
+        &mut self,
+        sink: GridCellPosition,
+        direction: Direction,
+        id: RouteId,
+        pins: &HashSet<GridCellPosition>,
+    ) -> Result<bool> {
+        let path = self.trace_route(sink, direction, id)?;
+        for step in path.windows(4) {
+            let (a, b) = (step[0].1, step[1].1);
+            let is_staircase = a != b
+                && a != b.mirror()
+                && !matches!(a, Direction::Up | Direction::Down)
+                && !matches!(b, Direction::Up | Direction::Down)
+                && step[2].1 == a
+                && step[3].1 == b;
+            if !is_staircase {
+                continue;
+            }
+
+            // step[1] leads to step[2] one way, moving the cell after it lets it go the other
+            let (corner, old) = (step[1].0, step[2].0);
+            let new = corner.offset(step[2].1);
+            if pins.contains(&old) || self.has_other_branches(old, corner, id) {
+                continue;
+            }
+            if !matches!(self.get_cell(new), Ok(GridCell::Free)) || self.is_blocked(new, id) {
+                continue;
+            }
+
+            *self.get_cell_mut(corner)? = GridCell::Occupied(step[2].1, id);
+            *self.get_cell_mut(new)? = GridCell::Occupied(b, id);
+            *self.get_cell_mut(old)? = GridCell::Free;
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    /// Whether any cell of the route `id` other than `from` leads in to `pos`
+    fn has_other_branches(
+        &self,
+        pos: GridCellPosition,
+        from: GridCellPosition,
+        id: RouteId,
+    ) -> bool {
+        ALL_DIRECTIONS.iter().any(|d| {
+            let neighbor = pos.offset(*d);
+            neighbor != from
+                && matches!(
+                    self.get_cell(neighbor),
+                    Ok(GridCell::Occupied(nd, nid)) if *nid == id && neighbor.offset(*nd) == pos
+                )
+        })
+    }
+}
@@ -0,0 +1,155 @@
+//! Where routing got crowded. While tracking is on, every cell a search expands counts the net
+//! that wanted it, so comparing that demand against which cells ended up as wire shows the parts
+//! of the die nets fought over, and where more area or another tier would help most.
+
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+
+use super::grid::CellGrid;
+use super::wire_segment::WireCoord;
+use super::{DetailRouter, GridCell, GridCellPosition, Layer, LAYERS_PER_TIER};
+use crate::RouteId;
+
+/// A cell of the demand grid
+#[derive(Clone, Copy, Debug, Default)]
+pub(super) struct Demand {
+    /// One more than the last route counted, so back to back searches for the same net, like
+    /// the ones for each of its sinks, only count it once. 0 before any route has been.
+    last: u32,
+    /// Number of nets whose searches expanded the cell. A net is counted again if another net's
+    /// search expanded the cell since it was last counted, so nets rerouted after others can be
+    /// counted more than once.
+    nets: u32,
+}
+
+/// Demand and final occupancy of every cell of the routing grid, a layer at a time
+#[derive(Clone, Debug, Serialize)]
+pub struct CongestionMap {
+    pub size_x: u32,
+    pub size_z: u32,
+    pub layers: Vec<CongestionLayer>,
+}
+
+/// One layer of a [`CongestionMap`]. Rows are Z and columns X.
+#[derive(Clone, Debug, Serialize)]
+pub struct CongestionLayer {
+    pub tier: u32,
+    pub layer: String,
+    /// Number of nets whose searches wanted each cell
+    pub demand: Vec<Vec<u32>>,
+    /// 1 where a wire ended up, 0 elsewhere
+    pub occupied: Vec<Vec<u32>>,
+}
+
+/// The cell the most nets wanted
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hotspot {
+    pub tier: u32,
+    pub layer: String,
+    pub x: u32,
+    pub z: u32,
+    pub nets: u32,
+}
+
+impl DetailRouter {
+    /// Count the nets each search wants from here on, for [`Self::congestion_map`]. Turning it off
+    /// forgets the counts so far.
+    pub fn set_congestion_tracking(&mut self, tracking: bool) {
+        self.demand = tracking.then(|| {
+            CellGrid::new(
+                self.grid.storage(),
+                [self.size_x, self.size_y, self.size_z],
+                Demand::default(),
+            )
+        });
+    }
+
+    /// Bytes [`Self::set_congestion_tracking`] allocates up front for a router this size, if its
+    /// grids are dense
+    pub fn congestion_allocation_size(size_x: u32, size_y: u32, size_z: u32) -> Option<u64> {
+        Self::allocation_size(size_x, size_y, size_z)?;
+        let cell = std::mem::size_of::<Demand>() as u64;
+        Some(size_x as u64 * size_y as u64 * size_z as u64 * cell)
+    }
+
+    /// Count `id` as wanting `pos`, if congestion is being tracked
+    #[inline]
+    pub(super) fn record_demand(&mut self, pos: GridCellPosition, id: RouteId) {
+        if let Some(ref mut demand) = self.demand {
+            let cell = demand.get_mut(pos);
+            if cell.last != id.0 + 1 {
+                cell.last = id.0 + 1;
+                cell.nets += 1;
+            }
+        }
+    }
+
+    /// Demand counted since tracking was turned on, against the cells occupied now
+    pub fn congestion_map(&self) -> Result<CongestionMap> {
+        let demand = self
+            .demand
+            .as_ref()
+            .ok_or_else(|| anyhow!("Congestion tracking is off"))?;
+        let layers = (0..self.size_y)
+            .map(|y| {
+                let layer = Layer::from_compact_idx(y % LAYERS_PER_TIER as i32)?;
+                let cells = |f: &dyn Fn(GridCellPosition) -> u32| -> Vec<Vec<u32>> {
+                    (0..self.size_z)
+                        .map(|z| {
+                            (0..self.size_x)
+                                .map(|x| f(GridCellPosition::new(WireCoord(x), y, WireCoord(z))))
+                                .collect()
+                        })
+                        .collect()
+                };
+                Ok(CongestionLayer {
+                    tier: y as u32 / LAYERS_PER_TIER,
+                    layer: format!("{:?}", layer),
+                    demand: cells(&|pos| demand.get(pos).nets),
+                    occupied: cells(&|pos| {
+                        matches!(self.grid.get(pos), GridCell::Occupied(_, _)) as u32
+                    }),
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(CongestionMap {
+            size_x: self.size_x as u32,
+            size_z: self.size_z as u32,
+            layers,
+        })
+    }
+}
+
+impl CongestionMap {
+    /// The cell the most nets wanted, or `None` if no search ran
+    pub fn hotspot(&self) -> Option<Hotspot> {
+        self.layers
+            .iter()
+            .flat_map(|layer| {
+                layer.demand.iter().enumerate().flat_map(move |(z, row)| {
+                    row.iter()
+                        .enumerate()
+                        .map(move |(x, nets)| (*nets, layer, x as u32, z as u32))
+                })
+            })
+            .filter(|(nets, _, _, _)| *nets > 0)
+            .max_by_key(|(nets, _, _, _)| *nets)
+            .map(|(nets, layer, x, z)| Hotspot {
+                tier: layer.tier,
+                layer: layer.layer.clone(),
+                x,
+                z,
+                nets,
+            })
+    }
+
+    pub fn write_json(&self, path: &Path) -> Result<()> {
+        let file = std::fs::File::create(path)
+            .with_context(|| anyhow!("Failed to create congestion map {:?}", path))?;
+        serde_json::to_writer(std::io::BufWriter::new(file), self)
+            .with_context(|| anyhow!("Failed to write congestion map {:?}", path))
+    }
+}
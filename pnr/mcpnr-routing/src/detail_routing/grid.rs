@@ -0,0 +1,159 @@
+//! Storage for a value per cell of the routing grid. Small grids are a single dense array. Big ones
+//! are split in to cubic pages that are only allocated once something is written to them, since
+//! most of the volume of a tall, sparse design is never touched by a route.
+
+use std::collections::HashMap;
+
+use itertools::Either;
+
+use super::wire_segment::WireCoord;
+use super::GridCellPosition;
+
+/// Side of the pages of a paged grid, in grid cells
+pub const PAGE_SIZE: i32 = 16;
+const PAGE_CELLS: usize = (PAGE_SIZE * PAGE_SIZE * PAGE_SIZE) as usize;
+
+/// How the cells of the routing grid are stored
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GridStorage {
+    /// One array covering the whole grid, allocated up front
+    Dense,
+    /// Pages of [`PAGE_SIZE`] cells a side, allocated when something is first written to them
+    Paged,
+}
+
+/// A value for every cell of a grid. Positions have to be inside the grid, the router checks them
+/// before they get here.
+pub(super) struct CellGrid<T> {
+    size: [i32; 3],
+    /// Value of every cell that hasn't been written since the grid was last filled. Only pages
+    /// that haven't been allocated still rely on it.
+    fill: T,
+    cells: Cells<T>,
+}
+
+enum Cells<T> {
+    /// Stored in x - z - y order
+    Dense(Vec<T>),
+    /// By page coordinate, each page stored in x - z - y order
+    Paged(HashMap<[i32; 3], Box<[T]>>),
+}
+
+impl<T: Copy> CellGrid<T> {
+    pub fn new(storage: GridStorage, size: [i32; 3], fill: T) -> Self {
+        let cells = match storage {
+            GridStorage::Dense => {
+                Cells::Dense(vec![fill; size.iter().map(|s| *s as usize).product()])
+            }
+            GridStorage::Paged => Cells::Paged(HashMap::new()),
+        };
+        Self { size, fill, cells }
+    }
+
+    #[inline]
+    pub fn get(&self, pos: GridCellPosition) -> &T {
+        match self.cells {
+            Cells::Dense(ref cells) => &cells[dense_idx(self.size, pos)],
+            Cells::Paged(ref pages) => match pages.get(&page_key(pos)) {
+                Some(page) => &page[page_idx(pos)],
+                None => &self.fill,
+            },
+        }
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self, pos: GridCellPosition) -> &mut T {
+        let fill = self.fill;
+        match self.cells {
+            Cells::Dense(ref mut cells) => &mut cells[dense_idx(self.size, pos)],
+            Cells::Paged(ref mut pages) => {
+                let page = pages
+                    .entry(page_key(pos))
+                    .or_insert_with(|| vec![fill; PAGE_CELLS].into_boxed_slice());
+                &mut page[page_idx(pos)]
+            }
+        }
+    }
+
+    pub fn storage(&self) -> GridStorage {
+        match self.cells {
+            Cells::Dense(_) => GridStorage::Dense,
+            Cells::Paged(_) => GridStorage::Paged,
+        }
+    }
+
+    /// Set every cell to `value`. Paged grids let go of all their pages.
+    pub fn fill(&mut self, value: T) {
+        self.fill = value;
+        match self.cells {
+            Cells::Dense(ref mut cells) => cells.fill(value),
+            Cells::Paged(ref mut pages) => pages.clear(),
+        }
+    }
+
+    /// Every stored cell and its position. Cells in pages that were never allocated are left out,
+    /// they all hold the fill value.
+    pub fn iter(&self) -> impl Iterator<Item = (GridCellPosition, &T)> {
+        let size = self.size;
+        match self.cells {
+            Cells::Dense(ref cells) => {
+                Either::Left(cells.iter().enumerate().map(move |(idx, v)| {
+                    let x = idx % size[0] as usize;
+                    let z = (idx / size[0] as usize) % size[2] as usize;
+                    let y = idx / (size[0] * size[2]) as usize;
+                    (
+                        GridCellPosition::new(WireCoord(x as i32), y as i32, WireCoord(z as i32)),
+                        v,
+                    )
+                }))
+            }
+            Cells::Paged(ref pages) => Either::Right(pages.iter().flat_map(move |(key, page)| {
+                page.iter()
+                    .enumerate()
+                    .map(move |(idx, v)| {
+                        let offset = |i: usize| (idx / i) as i32 % PAGE_SIZE;
+                        let pos = GridCellPosition::new(
+                            WireCoord(key[0] * PAGE_SIZE + offset(1)),
+                            key[1] * PAGE_SIZE + offset((PAGE_SIZE * PAGE_SIZE) as usize),
+                            WireCoord(key[2] * PAGE_SIZE + offset(PAGE_SIZE as usize)),
+                        );
+                        (pos, v)
+                    })
+                    // Pages on the far edges stick out past the grid
+                    .filter(move |(pos, _)| {
+                        pos.x.0 < size[0] && pos.y < size[1] && pos.z.0 < size[2]
+                    })
+            })),
+        }
+    }
+
+    /// Every stored cell, see [`Self::iter`]
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        match self.cells {
+            Cells::Dense(ref mut cells) => Either::Left(cells.iter_mut()),
+            Cells::Paged(ref mut pages) => {
+                Either::Right(pages.values_mut().flat_map(|page| page.iter_mut()))
+            }
+        }
+    }
+}
+
+#[inline(always)]
+fn dense_idx(size: [i32; 3], pos: GridCellPosition) -> usize {
+    pos.x.0 as usize
+        + pos.z.0 as usize * size[0] as usize
+        + pos.y as usize * (size[0] * size[2]) as usize
+}
+
+#[inline(always)]
+fn page_key(pos: GridCellPosition) -> [i32; 3] {
+    [pos.x.0 / PAGE_SIZE, pos.y / PAGE_SIZE, pos.z.0 / PAGE_SIZE]
+}
+
+#[inline(always)]
+fn page_idx(pos: GridCellPosition) -> usize {
+    let x = (pos.x.0 % PAGE_SIZE) as usize;
+    let y = (pos.y % PAGE_SIZE) as usize;
+    let z = (pos.z.0 % PAGE_SIZE) as usize;
+    x + z * PAGE_SIZE as usize + y * (PAGE_SIZE * PAGE_SIZE) as usize
+}
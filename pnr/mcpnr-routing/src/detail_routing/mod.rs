@@ -1,14 +1,29 @@
 use crate::RouteId;
 use anyhow::{anyhow, bail, ensure, Context, Result};
-use log::{debug, info};
 use mcpnr_common::block_storage::{Direction, Position, ALL_DIRECTIONS};
-use std::{collections::BinaryHeap, fmt::Display};
-
+use std::{
+    collections::{BinaryHeap, HashSet},
+    fmt::Display,
+};
+use tracing::{debug, info};
+
+use self::congestion::Demand;
+use self::grid::CellGrid;
 use self::wire_segment::WireCoord;
 
+pub use self::cleanup::CleanupStats;
+pub use self::congestion::{CongestionLayer, CongestionMap, Hotspot};
+pub use self::grid::GridStorage;
+pub use self::wavefront::{Frame, Wavefront};
+
 #[cfg(test)]
 mod tests;
 
+mod cleanup;
+mod congestion;
+mod grid;
+pub mod splat_log;
+mod wavefront;
 pub mod wire_segment;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -52,6 +67,10 @@ impl GridCellPosition {
             Direction::Down => GridCellPosition::new(self.x, self.y - 1, self.z),
         }
     }
+
+    pub fn layer(&self) -> Result<Layer> {
+        Layer::from_compact_idx(self.y % LAYERS_PER_TIER as i32)
+    }
 }
 
 impl TryFrom<Position> for GridCellPosition {
@@ -161,53 +180,234 @@ impl Layer {
             _ => Err(anyhow!("Unsupported compact idx in conversion {}", compact)),
         }
     }
+
+    /// Whether wires on this layer run along `d` when routing on tracks. The metal layers
+    /// alternate between X and Z so each one crosses the layers next to it, while local
+    /// interconnect runs both ways since that's where wires jog to reach their pins.
+    pub fn is_track_direction(self, d: Direction) -> bool {
+        let along_x = match d {
+            Direction::East | Direction::West => true,
+            Direction::North | Direction::South => false,
+            Direction::Up | Direction::Down => return true,
+        };
+        match self {
+            Layer::LI => true,
+            Layer::M0 | Layer::M2 => along_x,
+            Layer::M1 | Layer::M3 => !along_x,
+        }
+    }
 }
 
 pub const ALL_LAYERS: [Layer; 5] = [Layer::LI, Layer::M0, Layer::M1, Layer::M2, Layer::M3];
 
 pub const LAYERS_PER_TIER: u32 = ALL_LAYERS.len() as u32;
 
+/// Extra cost of a step across the tracks of a metal layer when routing on tracks. It's the same as
+/// a via, so going up or down a layer to run the other way is always at least as cheap for more
+/// than a couple of cells.
+const OFF_TRACK_COST: u32 = 1000;
+
+/// How far past the box around its ends a search may look for a route
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SearchWindow {
+    /// This many cells past the box on every side
+    Margin(i32),
+    /// Anywhere in the grid
+    Unbounded,
+}
+
+impl Display for SearchWindow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Margin(margin) => write!(f, "{} cells past the pins", margin),
+            Self::Unbounded => write!(f, "the whole grid"),
+        }
+    }
+}
+
+/// Windows a search tries in turn until one of them has a route. Most routes are close to the box
+/// around their ends and the first window keeps their searches small, the wider ones are for
+/// routes that have to detour around something.
+pub const SEARCH_WINDOWS: [SearchWindow; 3] = [
+    SearchWindow::Margin(2),
+    SearchWindow::Margin(4),
+    SearchWindow::Unbounded,
+];
+
+/// Largest routing and score grids [`DetailRouter::new`] allocates densely, in bytes. Anything
+/// bigger is paged.
+pub const DENSE_GRID_LIMIT: u64 = 1 << 30;
+
 pub struct DetailRouter {
     size_x: i32,
     size_y: i32,
     size_z: i32,
 
-    zsi: usize,
-    ysi: usize,
-
-    grid: Vec<GridCell>,
-    score_grid: Vec<u32>,
+    grid: CellGrid<GridCell>,
+    score_grid: CellGrid<Score>,
+    /// Scores from searches before this one are stale, see [`Score`]
+    search_epoch: u32,
 
     current_bounds_min: GridCellPosition,
     current_bounds_max: GridCellPosition,
+
+    /// Number of search nodes that may still be expanded before routing fails with
+    /// [`RoutingError::BudgetExhausted`], or `None` for no limit.
+    expansion_budget: Option<u64>,
+
+    /// Most cells the wire from a sink back to a driver may take, or `None` for no limit.
+    length_limit: Option<u32>,
+
+    /// Free cells that are cheaper to route through, so a route can be laid out alongside an
+    /// existing one.
+    guide: HashSet<GridCellPosition>,
+
+    /// Work done by searches since the last call to [`Self::take_search_stats`]
+    search_stats: SearchStats,
+
+    /// Whether steps across the tracks of a layer cost extra, see [`Layer::is_track_direction`]
+    track_routing: bool,
+
+    /// Nets that wanted each cell, if congestion is being tracked
+    demand: Option<CellGrid<Demand>>,
+
+    /// Cells expanded for the watched net, if one is being watched
+    wavefront: Option<Wavefront>,
 }
 
-impl DetailRouter {
-    pub fn new(size_x: u32, size_y: u32, size_z: u32) -> Self {
-        let capacity = (size_x * size_y * size_z) as usize;
+/// A cell of the score grid. The cost only counts if the epoch is the router's current one, so
+/// starting a search doesn't have to clear the whole grid.
+#[derive(Clone, Copy, Debug, Default)]
+struct Score {
+    epoch: u32,
+    cost: u32,
+    /// Direction of the cell the cheapest path here came from, which is one step closer to the
+    /// sink. Backtracking follows these rather than looking for the cheapest neighbor, since a
+    /// neighbor on another layer can be cheaper without being on the path once the via is paid.
+    towards_sink: Option<Direction>,
+}
 
-        let mut grid = Vec::with_capacity(capacity);
-        let mut score_grid = Vec::with_capacity(capacity);
+/// How much work the router's searches did, for profiling
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SearchStats {
+    /// Number of search nodes expanded
+    pub expanded: u64,
+    /// Largest number of items in the search queue at once
+    pub peak_queue: usize,
+    /// Widest window a search had to fall back to, if any needed more than the first of
+    /// [`SEARCH_WINDOWS`]
+    pub widest_window: Option<SearchWindow>,
+}
 
-        grid.resize(capacity, GridCell::Free);
-        score_grid.resize(capacity, 0);
+impl DetailRouter {
+    /// Create a router for a grid of the given size. The grid is dense unless that would take more
+    /// than [`DENSE_GRID_LIMIT`], in which case it's paged.
+    pub fn new(size_x: u32, size_y: u32, size_z: u32) -> Self {
+        let storage = match Self::allocation_size(size_x, size_y, size_z) {
+            Some(_) => GridStorage::Dense,
+            None => GridStorage::Paged,
+        };
+        Self::with_storage(size_x, size_y, size_z, storage)
+    }
 
+    pub fn with_storage(size_x: u32, size_y: u32, size_z: u32, storage: GridStorage) -> Self {
         let size_x = size_x as i32;
         let size_y = size_y as i32;
         let size_z = size_z as i32;
+        let size = [size_x, size_y, size_z];
 
         Self {
             size_x,
             size_y,
             size_z,
-            zsi: size_x as usize,
-            ysi: (size_x * size_z) as usize,
-            grid,
-            score_grid,
+            grid: CellGrid::new(storage, size, GridCell::Free),
+            score_grid: CellGrid::new(storage, size, Score::default()),
+            search_epoch: 0,
 
             current_bounds_min: GridCellPosition::new(WireCoord(0), 0, WireCoord(0)),
             current_bounds_max: GridCellPosition::new(WireCoord(0), 0, WireCoord(0)),
+
+            expansion_budget: None,
+
+            length_limit: None,
+
+            guide: HashSet::new(),
+
+            search_stats: SearchStats::default(),
+
+            track_routing: false,
+
+            demand: None,
+
+            wavefront: None,
+        }
+    }
+
+    /// Bytes [`Self::new`] allocates up front for the routing and score grids of a router this
+    /// size, or `None` if the grids would be too big and are paged instead. Paged grids only
+    /// allocate the parts routes reach, so how much they need depends on the design.
+    pub fn allocation_size(size_x: u32, size_y: u32, size_z: u32) -> Option<u64> {
+        let cell = std::mem::size_of::<GridCell>() + std::mem::size_of::<Score>();
+        let bytes = size_x as u64 * size_y as u64 * size_z as u64 * cell as u64;
+        (bytes <= DENSE_GRID_LIMIT).then(|| bytes)
+    }
+
+    /// Limit the number of search nodes expanded by subsequent calls to [`Self::route`]. The
+    /// budget is shared between calls until it is reset, so it can cover every sink of a net.
+    pub fn set_expansion_budget(&mut self, budget: Option<u64>) {
+        self.expansion_budget = budget;
+    }
+
+    /// Limit the wires subsequent calls to [`Self::route`] lay to `limit` cells from the sink back
+    /// to a driver, counting the part of the route the new wire joins. The search keeps only the
+    /// cheapest path to each cell, so it can miss a route that's short enough only when taken the
+    /// expensive way.
+    pub fn set_length_limit(&mut self, limit: Option<u32>) {
+        self.length_limit = limit;
+    }
+
+    /// Work done by searches since the last call, which resets the counts
+    pub fn take_search_stats(&mut self) -> SearchStats {
+        std::mem::take(&mut self.search_stats)
+    }
+
+    /// Make subsequent routes keep to the tracks of each layer, so parallel wires come out evenly
+    /// spaced and the search mostly stays on one axis per layer.
+    pub fn set_track_routing(&mut self, track_routing: bool) {
+        self.track_routing = track_routing;
+    }
+
+    /// Make subsequent calls to [`Self::route`] prefer going through the given cells. Pass an empty
+    /// set to route without a guide.
+    pub fn set_guide(&mut self, guide: HashSet<GridCellPosition>) {
+        self.guide = guide;
+    }
+
+    /// Every cell occupied by the route `id`.
+    pub fn route_cells(&self, id: RouteId) -> Vec<GridCellPosition> {
+        self.grid
+            .iter()
+            .filter(|(_, cell)| matches!(cell, GridCell::Occupied(_, i) if *i == id))
+            .map(|(pos, _)| pos)
+            .collect()
+    }
+
+    /// Cells the route `id` goes through from each of `sinks`, as pin positions and directions,
+    /// back to a driver. Only follows the route instead of looking through the whole grid like
+    /// [`Self::route_cells`], so stubs no sink goes through are left out. Sinks the route doesn't
+    /// reach add nothing.
+    pub fn route_tree(
+        &self,
+        id: RouteId,
+        sinks: &[(GridCellPosition, Direction)],
+    ) -> Result<HashSet<GridCellPosition>> {
+        let mut cells = HashSet::new();
+        for (sink, sink_direction) in sinks {
+            let path = self.trace_from(sink.offset(*sink_direction), id)?;
+            cells.extend(path.into_iter().map(|(pos, _)| pos));
         }
+
+        Ok(cells)
     }
 
     pub fn route(
@@ -217,6 +417,174 @@ impl DetailRouter {
         sink: GridCellPosition,
         sink_direction: Direction,
         id: RouteId,
+    ) -> Result<()> {
+        info!("Begin routing net {:?} from {} to {}", id, driver, sink);
+
+        let driver = self.mark_driver(driver, driver_direction, id)?;
+        // Start the sink one cell away in the direction the pin requests.
+        let sink = sink.offset(sink_direction);
+
+        match self.get_cell(sink)? {
+            GridCell::Free if self.in_via_keepout(sink, id) => {
+                return Err(RoutingError::Unroutable)
+                    .context("Sink pin points directly at a cell next to another net's via");
+            }
+            GridCell::Free => {}
+            GridCell::Blocked => {
+                self.debug_dump();
+                return Err(RoutingError::Unroutable)
+                    .context("Sink pin points directly at an unroutable cell");
+            }
+            GridCell::Occupied(_, i) => {
+                if *i != id {
+                    return Err(RoutingError::Unroutable).context(anyhow!(
+                        "Sink pin points directly at a cell already occupied by route {:?}",
+                        id
+                    ));
+                }
+            }
+        };
+
+        // We block movement back to the original sink because that's already marked and would
+        // cause an erronious early-out
+        self.search_widening(driver, sink, sink_direction.mirror(), id, self.length_limit)
+    }
+
+    /// Route from `driver` to a free cell `tap` that isn't a pin, so the route `id` passes through
+    /// it. Later sinks of the net can then branch off from there.
+    pub fn route_to_tap(
+        &mut self,
+        driver: GridCellPosition,
+        driver_direction: Direction,
+        tap: GridCellPosition,
+        id: RouteId,
+    ) -> Result<()> {
+        info!("Begin routing net {:?} from {} to tap {}", id, driver, tap);
+
+        let driver = self.mark_driver(driver, driver_direction, id)?;
+        match self.get_cell(tap)? {
+            GridCell::Free => {}
+            GridCell::Occupied(_, i) if *i == id => return Ok(()),
+            _ => {
+                return Err(RoutingError::Unroutable)
+                    .context(anyhow!("Tap {} is not a free cell", tap));
+            }
+        }
+
+        // Nothing is waiting above a tap, so that's the direction to give up. Taps aren't the end
+        // of a wire, so the length limit waits for the sinks branching from them.
+        self.search_widening(driver, tap, Direction::Up, id, None)
+    }
+
+    /// Number of cells the route `id` takes to get from the sink pin at `sink` back to a driver.
+    pub fn path_length(
+        &self,
+        sink: GridCellPosition,
+        sink_direction: Direction,
+        id: RouteId,
+    ) -> Result<u32> {
+        Ok(self.trace_route(sink, sink_direction, id)?.len() as u32)
+    }
+
+    /// Cells the route `id` goes through from the sink pin at `sink` back to a driver, with the
+    /// direction the route leaves each cell in.
+    pub fn trace_route(
+        &self,
+        sink: GridCellPosition,
+        sink_direction: Direction,
+        id: RouteId,
+    ) -> Result<Vec<(GridCellPosition, Direction)>> {
+        let path = self.trace_from(sink.offset(sink_direction), id)?;
+        if path.is_empty() {
+            return Err(anyhow!("Sink {} is not connected to route {:?}", sink, id));
+        }
+        Ok(path)
+    }
+
+    /// Cells the route `id` goes through from `pos` back to a driver, with the direction the route
+    /// leaves each cell in. Empty if `pos` isn't part of the route.
+    fn trace_from(
+        &self,
+        mut pos: GridCellPosition,
+        id: RouteId,
+    ) -> Result<Vec<(GridCellPosition, Direction)>> {
+        let mut visited = HashSet::new();
+        let mut path = Vec::new();
+        while let GridCell::Occupied(d, grid_route) = self.get_cell(pos)? {
+            if *grid_route != id {
+                break;
+            }
+            if !visited.insert(pos) {
+                return Err(anyhow!("Route {:?} loops at {}", id, pos));
+            }
+            path.push((pos, *d));
+            pos = pos.offset(*d);
+        }
+
+        Ok(path)
+    }
+
+    /// Limit the search to `window` around the box around `a` and `b`, so there's slack to get
+    /// around obstacles.
+    fn set_bounds(&mut self, a: GridCellPosition, b: GridCellPosition, window: SearchWindow) {
+        let margin = match window {
+            SearchWindow::Margin(margin) => margin,
+            SearchWindow::Unbounded => {
+                self.current_bounds_min = GridCellPosition::new(WireCoord(0), 0, WireCoord(0));
+                self.current_bounds_max =
+                    GridCellPosition::new(self.size_x.into(), self.size_y, self.size_z.into());
+                return;
+            }
+        };
+        self.current_bounds_min = GridCellPosition::new(
+            std::cmp::max(std::cmp::min(a.x, b.x) - margin, WireCoord(0)),
+            std::cmp::max(std::cmp::min(a.y, b.y) - margin, 0),
+            std::cmp::max(std::cmp::min(a.z, b.z) - margin, WireCoord(0)),
+        );
+        self.current_bounds_max = GridCellPosition::new(
+            std::cmp::min(std::cmp::max(a.x, b.x) + margin, self.size_x.into()),
+            std::cmp::min(std::cmp::max(a.y, b.y) + margin, self.size_y),
+            std::cmp::min(std::cmp::max(a.z, b.z) + margin, self.size_z.into()),
+        );
+    }
+
+    /// [`Self::search`] in each of [`SEARCH_WINDOWS`] in turn, until one has a route or the search
+    /// fails for some other reason than there not being one.
+    fn search_widening(
+        &mut self,
+        driver: GridCellPosition,
+        sink: GridCellPosition,
+        illegal_direction: Direction,
+        id: RouteId,
+        length_limit: Option<u32>,
+    ) -> Result<()> {
+        let mut result = Ok(());
+        for (idx, window) in SEARCH_WINDOWS.into_iter().enumerate() {
+            if idx > 0 {
+                debug!("Widening the search for net {:?} to {}", id, window);
+                let widest = &mut self.search_stats.widest_window;
+                *widest = (*widest).max(Some(window));
+            }
+            self.set_bounds(driver, sink, window);
+            result = self.search(driver, sink, illegal_direction, id, length_limit);
+            match result {
+                Err(ref e) if e.downcast_ref() == Some(&RoutingError::Unroutable) => {}
+                _ => break,
+            }
+        }
+        result
+    }
+
+    /// Search from `sink` until reaching any cell of the route `id`, then claim the cheapest path
+    /// back to `sink`. The search may not leave `sink` in `illegal_direction`, and the wire from
+    /// `sink` back to a driver may not be more than `length_limit` cells.
+    fn search(
+        &mut self,
+        driver: GridCellPosition,
+        sink: GridCellPosition,
+        illegal_direction: Direction,
+        id: RouteId,
+        length_limit: Option<u32>,
     ) -> Result<()> {
         // TODO: implement A* by adding an estimate to this
         #[derive(PartialEq, Eq)]
@@ -226,6 +594,8 @@ impl DetailRouter {
             pos: GridCellPosition,
 
             illegal_direction: Direction,
+            /// Cells from the sink to here, both included
+            length: u32,
         }
 
         impl PartialOrd for RouteQueueItem {
@@ -246,101 +616,66 @@ impl DetailRouter {
             }
         }
 
-        log::info!("Begin routing net {:?} from {} to {}", id, driver, sink);
-
-        // TODO: Use temporary just-right-sized routing grid instead of the full one
-        for score in self.score_grid.iter_mut() {
-            *score = std::u32::MAX;
-        }
-
-        self.current_bounds_min = GridCellPosition::new(
-            std::cmp::max(std::cmp::min(driver.x, sink.x) - 2, WireCoord(0)),
-            std::cmp::max(std::cmp::min(driver.y, sink.y) - 2, 0),
-            std::cmp::max(std::cmp::min(driver.z, sink.z) - 2, WireCoord(0)),
-        );
-        self.current_bounds_max = GridCellPosition::new(
-            std::cmp::min(std::cmp::max(driver.x, sink.x) + 2, self.size_x.into()),
-            std::cmp::min(std::cmp::max(driver.y, sink.y) + 2, self.size_y),
-            std::cmp::min(std::cmp::max(driver.z, sink.z) + 2, self.size_z.into()),
-        );
-
-        // Start the driver one cell away in the direction that will cause entry into the driver
-        let driver = driver.offset(driver_direction.mirror());
-        // Start the sink one cell away in the direction the pin requests.
-        let sink = sink.offset(sink_direction);
-
-        // Immediately mark the driver position as occupied and facing in the appropriate
-        // direction. This helps terminate the search early, and someone needs to do it so it may
-        // as well be us.
-        *self
-            .get_cell_mut(driver)
-            .context("Driver pin offset mark")? = GridCell::Occupied(driver_direction, id);
-
-        match self.get_cell(driver)? {
-            GridCell::Free => {}
-            GridCell::Blocked => {
-                self.debug_dump();
-                return Err(RoutingError::Unroutable)
-                    .context("Driver pin points directly at an unroutable cell");
-            }
-            GridCell::Occupied(_, i) => {
-                if *i != id {
-                    return Err(RoutingError::Unroutable).context(anyhow!(
-                        "Driver pin points directly at a cell already occupied by route {:?}",
-                        id
-                    ));
-                }
-            }
-        };
-
-        match self.get_cell(sink)? {
-            GridCell::Free => {}
-            GridCell::Blocked => {
-                self.debug_dump();
-                return Err(RoutingError::Unroutable)
-                    .context("Sink pin points directly at an unroutable cell");
-            }
-            GridCell::Occupied(_, i) => {
-                if *i != id {
-                    return Err(RoutingError::Unroutable).context(anyhow!(
-                        "Sink pin points directly at a cell already occupied by route {:?}",
-                        id
-                    ));
-                }
-            }
-        };
+        self.start_search();
 
         let mut routing_queue = BinaryHeap::new();
 
         // Start at the sink and iterate until we either bottom out (explored everything and found
         // no route) or we find our way to something already owned by our net.
-        //
-        // We block movement back to the original sink because that's already marked and would
-        // cause an erronious early-out
         routing_queue.push(RouteQueueItem {
             cost: 0,
             pos: sink,
-            illegal_direction: sink_direction.mirror(),
+            illegal_direction,
+            length: 1,
         });
 
         while let Some(item) = routing_queue.pop() {
             debug!("Process queue item {} (cost: {})", item.pos, item.cost);
-            let idx = self
-                .pos_to_idx(item.pos)
-                .context("Failed to get index for popped item")?;
-            // assert!(item.cost < self.score_grid[idx]);
-            if item.cost >= self.score_grid[idx] {
+            let queue_len = routing_queue.len() + 1;
+            self.search_stats.peak_queue = self.search_stats.peak_queue.max(queue_len);
+            self.check_bounds(item.pos)
+                .context("Popped item is outside the grid")?;
+            // assert!(item.cost < self.score(item.pos));
+            if item.cost >= self.score(item.pos) {
                 continue;
             }
 
-            self.score_grid[idx] = item.cost;
-            let item_grid = self.grid[idx];
+            if let Some(ref mut remaining) = self.expansion_budget {
+                if *remaining == 0 {
+                    return Err(RoutingError::BudgetExhausted)
+                        .context(anyhow!("Expansion budget exhausted routing net {:?}", id));
+                }
+                *remaining -= 1;
+            }
+            self.search_stats.expanded += 1;
+
+            self.set_score(item.pos, item.cost, item.illegal_direction);
+            self.record_demand(item.pos, id);
+            self.record_visit(item.pos, id);
+            let item_grid = *self.grid.get(item.pos);
 
             if let GridCell::Occupied(_, occupied_id) = item_grid {
                 if occupied_id == id {
-                    return self.do_backtrack(sink, item.pos, item.illegal_direction, id);
+                    let fits = match length_limit {
+                        Some(limit) => {
+                            // The cell joined is counted in both parts
+                            let to_driver = self.trace_from(item.pos, id)?.len() as u32;
+                            item.length + to_driver - 1 <= limit
+                        }
+                        None => true,
+                    };
+                    if fits {
+                        return self.do_backtrack(sink, item.pos, item.illegal_direction, id);
+                    }
+                    debug!(
+                        "Joining route {:?} at {} makes the wire too long",
+                        id, item.pos
+                    );
                 }
             }
+            if length_limit.is_some_and(|limit| item.length >= limit) {
+                continue;
+            }
             self.for_each_neighbor(
                 item.pos,
                 item.illegal_direction,
@@ -351,12 +686,21 @@ impl DetailRouter {
                         debug!("Skipping {} because it leaves bounding box", neighbor);
                         return Ok(());
                     }
-                    let idx = self
-                        .pos_to_idx(neighbor)
-                        .context("Failed to get index of new neighbor")?;
-                    let grid = self.grid[idx];
+                    self.check_bounds(neighbor)
+                        .context("New neighbor is outside the grid")?;
+                    if matches!(move_direction, Direction::Up | Direction::Down)
+                        && !(self.via_clear(item.pos, id) && self.via_clear(neighbor, id))
+                    {
+                        debug!(
+                            "Skipping {} because a via there would touch another net",
+                            neighbor
+                        );
+                        return Ok(());
+                    }
+                    let grid = *self.grid.get(neighbor);
                     let cost = item.cost
                         + match grid {
+                            GridCell::Free if self.guide.contains(&neighbor) => 60,
                             GridCell::Free => 100,
                             GridCell::Blocked => 10_000_000,
                             GridCell::Occupied(_, nid) => {
@@ -374,14 +718,18 @@ impl DetailRouter {
                         }
                         + match move_direction {
                             Direction::Up | Direction::Down => 1000,
+                            d if self.track_routing && !item.pos.layer()?.is_track_direction(d) => {
+                                OFF_TRACK_COST
+                            }
                             _ => 0,
                         };
-                    if cost < self.score_grid[idx] {
+                    if cost < self.score(neighbor) {
                         debug!("Pushing item for {} (cost: {})", neighbor, cost);
                         routing_queue.push(RouteQueueItem {
                             cost,
                             pos: neighbor,
                             illegal_direction: move_direction.mirror(),
+                            length: item.length + 1,
                         })
                     }
 
@@ -399,6 +747,46 @@ impl DetailRouter {
         Err(RoutingError::Unroutable)?
     }
 
+    /// Mark the cell in front of a driver pin as belonging to the route `id`, returning its
+    /// position. Searches for the route terminate when they reach a cell owned by the route, so
+    /// marking every pin that drives a net lets each sink connect to whichever is closest.
+    pub fn mark_driver(
+        &mut self,
+        driver: GridCellPosition,
+        driver_direction: Direction,
+        id: RouteId,
+    ) -> Result<GridCellPosition> {
+        // Start the driver one cell away in the direction that will cause entry into the driver
+        let driver = driver.offset(driver_direction.mirror());
+        // Immediately mark the driver position as occupied and facing in the appropriate
+        // direction. This helps terminate the search early, and someone needs to do it so it may
+        // as well be us.
+        *self
+            .get_cell_mut(driver)
+            .context("Driver pin offset mark")? = GridCell::Occupied(driver_direction, id);
+
+        match self.get_cell(driver)? {
+            GridCell::Free => {}
+            GridCell::Blocked => {
+                self.debug_dump();
+                return Err(RoutingError::Unroutable)
+                    .context("Driver pin points directly at an unroutable cell");
+            }
+            GridCell::Occupied(_, i) => {
+                if *i != id {
+                    return Err(RoutingError::Unroutable).context(anyhow!(
+                        "Driver pin points directly at a cell already occupied by route {:?}",
+                        id
+                    ));
+                }
+            }
+        };
+
+        Ok(driver)
+    }
+
+    /// Claim the path the search found from `first_net_touch` back to `sink`, which arrived at
+    /// `first_net_touch` from direction `start_direction`.
     fn do_backtrack(
         &mut self,
         sink: GridCellPosition,
@@ -408,47 +796,33 @@ impl DetailRouter {
     ) -> Result<()> {
         debug!("Begin backtrack");
 
-        let mut min_direction = start_direction.mirror();
-        let mut min_position = first_net_touch;
-        let min_pos_idx = self.pos_to_idx(min_position)?;
-        let mut min_cost = self.score_grid[min_pos_idx];
-        let mut last_min_pos = min_position;
+        let mut position = first_net_touch;
+        let mut direction = start_direction;
+        self.check_bounds(position)?;
+        let mut cost = self.score(position);
 
-        while min_position != sink {
-            self.for_each_neighbor(
-                min_position,
-                min_direction,
-                id,
-                |neighbor, move_direction| -> Result<()> {
-                    let neighbor_idx = self.pos_to_idx(neighbor)?;
-                    if self.score_grid[neighbor_idx] < min_cost {
-                        min_cost = self.score_grid[neighbor_idx];
-
-                        min_position = neighbor;
-                        // Mirror the direction because the step taken here moves us *towards* the
-                        // sink, while we want to record the path *away* from the sink.
-                        min_direction = move_direction.mirror();
-                    }
-                    Ok(())
-                },
-            )?;
-
-            let min_pos_idx = self.pos_to_idx(min_position)?;
-            self.grid[min_pos_idx] = GridCell::Occupied(min_direction, id);
-
-            if last_min_pos == min_position {
+        while position != sink {
+            let next = position.offset(direction);
+            self.check_bounds(next)?;
+            let next_cost = self.score(next);
+            if next_cost >= cost {
                 self.debug_dump();
                 return Err(RoutingError::Unroutable).context(anyhow!(
                     "Backtrack for net {:?} did not make progress at {}",
                     id,
-                    min_position
+                    next
                 ));
-                // panic!(
-                //     "Backtrack for net {:?} did not make progress at {}",
-                //     id, min_position
-                // );
             }
-            last_min_pos = min_position;
+
+            // Mirror the direction because the step taken here moves us *towards* the sink, while
+            // we want to record the path *away* from the sink.
+            *self.get_cell_mut(next)? = GridCell::Occupied(direction.mirror(), id);
+
+            position = next;
+            cost = next_cost;
+            direction = self.towards_sink(position).ok_or_else(|| {
+                anyhow!("Backtrack for net {:?} reached unsearched {}", id, position)
+            })?;
         }
 
         Ok(())
@@ -456,14 +830,60 @@ impl DetailRouter {
 
     #[inline]
     pub fn get_cell(&self, pos: GridCellPosition) -> Result<&GridCell> {
-        // Unwrap is ok because pos_to_idx does bounds checking
-        Ok(self.grid.get(self.pos_to_idx(pos)?).unwrap())
+        self.check_bounds(pos)?;
+        Ok(self.grid.get(pos))
     }
 
     #[inline]
     pub fn get_cell_mut(&mut self, pos: GridCellPosition) -> Result<&mut GridCell> {
-        let idx = self.pos_to_idx(pos)?;
-        Ok(self.grid.get_mut(idx).unwrap())
+        self.check_bounds(pos)?;
+        Ok(self.grid.get_mut(pos))
+    }
+
+    /// Make every score stale for a new search. Dense grids just move on to the next epoch, so this
+    /// doesn't cost anything however big they are. Paged grids drop the pages of the last search
+    /// instead, which costs about as much as the search did and keeps them from filling up with
+    /// pages every net has touched at some point.
+    fn start_search(&mut self) {
+        self.search_epoch = self.search_epoch.wrapping_add(1);
+        // Epoch 0 is the fill value, so on wrapping around every score really has to be cleared
+        if self.search_epoch == 0 || self.score_grid.storage() == GridStorage::Paged {
+            self.score_grid.fill(Score::default());
+            self.search_epoch = self.search_epoch.max(1);
+        }
+    }
+
+    /// Cost of the cheapest path found to `pos` by the current search, or `u32::MAX` if it
+    /// hasn't been reached. `pos` must be in bounds.
+    #[inline]
+    fn score(&self, pos: GridCellPosition) -> u32 {
+        let score = self.score_grid.get(pos);
+        if score.epoch == self.search_epoch {
+            score.cost
+        } else {
+            std::u32::MAX
+        }
+    }
+
+    #[inline]
+    fn set_score(&mut self, pos: GridCellPosition, cost: u32, towards_sink: Direction) {
+        *self.score_grid.get_mut(pos) = Score {
+            epoch: self.search_epoch,
+            cost,
+            towards_sink: Some(towards_sink),
+        };
+    }
+
+    /// Direction the cheapest path to `pos` found by the current search came from, if it has been
+    /// reached. `pos` must be in bounds.
+    #[inline]
+    fn towards_sink(&self, pos: GridCellPosition) -> Option<Direction> {
+        let score = self.score_grid.get(pos);
+        if score.epoch == self.search_epoch {
+            score.towards_sink
+        } else {
+            None
+        }
     }
 
     #[inline]
@@ -472,7 +892,7 @@ impl DetailRouter {
     }
 
     #[inline(always)]
-    fn pos_to_idx(&self, pos: GridCellPosition) -> Result<usize> {
+    fn check_bounds(&self, pos: GridCellPosition) -> Result<()> {
         if pos.x.0 < 0
             || pos.y < 0
             || pos.z.0 < 0
@@ -485,17 +905,59 @@ impl DetailRouter {
                 bounds: (self.size_x, self.size_y, self.size_z),
             })?
         } else {
-            let x = pos.x.0 as usize;
-            let y = pos.y as usize;
-            let z = pos.z.0 as usize;
-            Ok(x + z * self.zsi + y * self.ysi)
+            Ok(())
         }
     }
 
+    /// Net whose via goes through `pos`, if any. That's a cell of a route that climbs or drops
+    /// out of it, or that the route climbs or drops in to.
+    fn via_at(&self, pos: GridCellPosition) -> Option<RouteId> {
+        let (d, id) = match self.get_cell(pos) {
+            Ok(GridCell::Occupied(d, id)) => (*d, *id),
+            _ => return None,
+        };
+        let enters_going = |d: Direction| {
+            matches!(
+                self.get_cell(pos.offset(d.mirror())),
+                Ok(GridCell::Occupied(nd, nid)) if *nid == id && *nd == d
+            )
+        };
+        let is_via = matches!(d, Direction::Up | Direction::Down)
+            || enters_going(Direction::Up)
+            || enters_going(Direction::Down);
+        is_via.then_some(id)
+    }
+
+    /// Whether `pos` is east or south of a via of another net than `id`. Vias climb around the
+    /// whole column of their cell, see [`wire_segment::splat_via`], so anything there would
+    /// touch them.
+    fn in_via_keepout(&self, pos: GridCellPosition, id: RouteId) -> bool {
+        [Direction::West, Direction::North]
+            .into_iter()
+            .any(|d| matches!(self.via_at(pos.offset(d)), Some(via) if via != id))
+    }
+
+    /// Whether the route `id` can put a via through `pos` without touching another net in the
+    /// cells east and south of it
+    fn via_clear(&self, pos: GridCellPosition, id: RouteId) -> bool {
+        [Direction::East, Direction::South].into_iter().all(|d| {
+            match self.get_cell(pos.offset(d)) {
+                Ok(GridCell::Occupied(_, nid)) => *nid == id,
+                _ => true,
+            }
+        })
+    }
+
     fn is_blocked(&self, pos: GridCellPosition, id: RouteId) -> bool {
         match self.get_cell(pos) {
             Ok(cell) => match cell {
-                GridCell::Free => false,
+                GridCell::Free => {
+                    let keepout = self.in_via_keepout(pos, id);
+                    if keepout {
+                        debug!("Cell {} is next to a via of another net", pos);
+                    }
+                    keepout
+                }
                 GridCell::Blocked => {
                     debug!("Cell {} is directly blocked", pos);
                     true
@@ -546,8 +1008,9 @@ impl DetailRouter {
     }
 
     pub fn rip_up(&mut self, id: RouteId) -> Result<()> {
+        self.forget_visits(id);
         // TODO: make this API more efficient?
-        for (_, cell) in self.grid.iter_mut().enumerate() {
+        for cell in self.grid.values_mut() {
             match cell {
                 GridCell::Occupied(_, i) if *i == id => *cell = GridCell::Free,
                 _ => {}
@@ -558,6 +1021,10 @@ impl DetailRouter {
     }
 
     fn debug_dump(&self) {
+        // A search over the whole grid makes for a huge dump, so don't build it for nothing
+        if !tracing::enabled!(tracing::Level::DEBUG) {
+            return;
+        }
         for y in 0..self.current_bounds_max.y {
             let min_x = std::cmp::max(self.current_bounds_min.x - 2, 0.into());
             let min_z = std::cmp::max(self.current_bounds_min.z - 2, 0.into());
@@ -578,14 +1045,14 @@ impl DetailRouter {
                 let mut buf_c = String::new();
                 for z in min_z.0..self.current_bounds_max.z.0 {
                     let pos = GridCellPosition::new(WireCoord(x), y, WireCoord(z));
-                    let idx = self.pos_to_idx(pos).unwrap();
-                    let score = self.score_grid[idx];
+                    self.check_bounds(pos).unwrap();
+                    let score = self.score(pos);
                     if score == std::u32::MAX {
                         buf_s.push_str("x__x ");
                     } else {
                         buf_s.push_str(&format!("{:4} ", score));
                     }
-                    match self.grid[idx] {
+                    match self.grid.get(pos) {
                         GridCell::Free => buf_c.push_str("FFF "),
                         GridCell::Blocked => buf_c.push_str("BBB "),
                         GridCell::Occupied(d, RouteId(i)) => {
@@ -610,6 +1077,8 @@ impl DetailRouter {
 #[derive(Debug, PartialEq)]
 pub enum RoutingError {
     Unroutable,
+    /// The search gave up after expanding as many nodes as the budget allowed
+    BudgetExhausted,
     OutOfBounds {
         pos: GridCellPosition,
         bounds: (i32, i32, i32),
@@ -622,6 +1091,7 @@ impl Display for RoutingError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Unroutable => write!(f, "path was unroutable"),
+            Self::BudgetExhausted => write!(f, "node expansion budget exhausted"),
             Self::OutOfBounds {
                 pos:
                     GridCellPosition {
@@ -0,0 +1,47 @@
+//! Record of the blocks written for each route, so a route can be taken back out of the output
+//! after it has been splatted, not just out of the routing grid.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context, Result};
+use mcpnr_common::block_storage::{BlockChange, BlockStorage};
+
+use crate::RouteId;
+
+#[derive(Debug, Default)]
+pub struct SplatLog {
+    routes: HashMap<RouteId, Vec<BlockChange>>,
+}
+
+impl SplatLog {
+    /// Add blocks changed for the route `id`, from [`BlockStorage::finish_journal`]
+    pub fn insert(&mut self, id: RouteId, changes: Vec<BlockChange>) {
+        self.routes.entry(id).or_default().extend(changes);
+    }
+
+    /// Number of block changes recorded for the route `id`
+    pub fn changes(&self, id: RouteId) -> usize {
+        self.routes.get(&id).map_or(0, Vec::len)
+    }
+
+    /// Every block any route changed, some of them more than once
+    pub fn positions(&self) -> impl Iterator<Item = [u32; 3]> + '_ {
+        self.routes
+            .values()
+            .flat_map(|changes| changes.iter().map(|change| change.pos))
+    }
+
+    /// Put back every block the route `id` changed, returning whether it had changed any. Routes
+    /// splatted after it should be removed first, in case they touched the same blocks.
+    pub fn remove(&mut self, id: RouteId, output: &mut BlockStorage) -> Result<bool> {
+        match self.routes.remove(&id) {
+            Some(changes) => {
+                output
+                    .undo(&changes)
+                    .with_context(|| anyhow!("Remove the blocks of route {:?}", id))?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
@@ -1,9 +1,11 @@
-use log::info;
+use std::collections::HashMap;
+
+use tracing::info;
 
 use super::*;
 
 fn init(size_x: u32, size_y: u32, size_z: u32) -> DetailRouter {
-    let _ = env_logger::builder().is_test(true).try_init();
+    let _ = tracing_subscriber::fmt().with_test_writer().try_init();
     DetailRouter::new(size_x, size_y, size_z)
 }
 
@@ -184,20 +186,16 @@ pub fn it_can_route_across_layers() -> Result<()> {
         ),
     ];
 
-    // Add some hills.
-    *router.get_cell_mut(GridCellPosition::new(2.into(), 0, 0.into()))? =
-        GridCell::Occupied(Direction::Up, RouteId(2));
-    *router.get_cell_mut(GridCellPosition::new(2.into(), 0, 4.into()))? =
-        GridCell::Occupied(Direction::Up, RouteId(2));
-    *router.get_cell_mut(GridCellPosition::new(0.into(), 0, 2.into()))? =
-        GridCell::Occupied(Direction::Up, RouteId(2));
-    *router.get_cell_mut(GridCellPosition::new(4.into(), 0, 2.into()))? =
-        GridCell::Occupied(Direction::Up, RouteId(2));
+    // Add some hills. They're blocked rather than another net's wires, since the routes have to
+    // climb right next to them and a via can't go beside another net.
+    *router.get_cell_mut(GridCellPosition::new(2.into(), 0, 0.into()))? = GridCell::Blocked;
+    *router.get_cell_mut(GridCellPosition::new(2.into(), 0, 4.into()))? = GridCell::Blocked;
+    *router.get_cell_mut(GridCellPosition::new(0.into(), 0, 2.into()))? = GridCell::Blocked;
+    *router.get_cell_mut(GridCellPosition::new(4.into(), 0, 2.into()))? = GridCell::Blocked;
 
     for x in 1..=3 {
         for z in 1..=3 {
-            *router.get_cell_mut(GridCellPosition::new(x.into(), 0, z.into()))? =
-                GridCell::Occupied(Direction::Up, RouteId(2));
+            *router.get_cell_mut(GridCellPosition::new(x.into(), 0, z.into()))? = GridCell::Blocked;
         }
     }
 
@@ -215,3 +213,508 @@ pub fn it_can_route_across_layers() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+pub fn it_gives_up_when_the_expansion_budget_runs_out() -> Result<()> {
+    let mut router = init(5, 5, 5);
+
+    let driver = GridCellPosition::new(0.into(), 0, 0.into());
+    let sink = GridCellPosition::new(0.into(), 0, 4.into());
+    *router.get_cell_mut(driver)? = GridCell::Blocked;
+    *router.get_cell_mut(sink)? = GridCell::Blocked;
+
+    router.set_expansion_budget(Some(1));
+    let e = router
+        .route(driver, Direction::North, sink, Direction::North, RouteId(0))
+        .expect_err("Route should exhaust the budget");
+    assert_eq!(e.downcast_ref(), Some(&RoutingError::BudgetExhausted));
+
+    // With the budget lifted the same net routes fine
+    router.rip_up(RouteId(0))?;
+    router.set_expansion_budget(None);
+    test_routing_and_suffixes(
+        &mut router,
+        driver,
+        Direction::North,
+        sink,
+        Direction::North,
+        RouteId(0),
+    )?;
+
+    Ok(())
+}
+
+#[test]
+pub fn it_routes_between_pins_facing_up() -> Result<()> {
+    let mut router = init(5, 5, 5);
+
+    // Pins on top of two cells, with the cells themselves in the way on the layer they're on
+    let driver = GridCellPosition::new(0.into(), 0, 0.into());
+    let sink = GridCellPosition::new(4.into(), 0, 0.into());
+    for x in 0..5 {
+        *router.get_cell_mut(GridCellPosition::new(x.into(), 0, 0.into()))? = GridCell::Blocked;
+    }
+
+    // Wires leave a driver the opposite way to the direction it faces, so this one escapes up
+    test_routing_and_suffixes(
+        &mut router,
+        driver,
+        Direction::Down,
+        sink,
+        Direction::Up,
+        RouteId(0),
+    )?;
+    let pathway = assert_connected(&router, driver, sink, Direction::Up, RouteId(0))?;
+    ensure!(
+        pathway.first() == Some(&GridCellPosition::new(4.into(), 1, 0.into()))
+            && pathway.last() == Some(&GridCellPosition::new(0.into(), 1, 0.into())),
+        "Route should start and end right above the pins, got {:?}",
+        pathway
+    );
+
+    Ok(())
+}
+
+#[test]
+pub fn it_connects_to_the_closest_equivalent_driver() -> Result<()> {
+    let mut router = init(5, 5, 5);
+
+    let driver = GridCellPosition::new(0.into(), 0, 0.into());
+    let alternate = GridCellPosition::new(4.into(), 0, 0.into());
+    let sink = GridCellPosition::new(4.into(), 0, 4.into());
+    *router.get_cell_mut(driver)? = GridCell::Blocked;
+    *router.get_cell_mut(alternate)? = GridCell::Blocked;
+    *router.get_cell_mut(sink)? = GridCell::Blocked;
+
+    router.mark_driver(alternate, Direction::North, RouteId(0))?;
+    router.route(driver, Direction::North, sink, Direction::North, RouteId(0))?;
+
+    let pathway = assert_connected(&router, alternate, sink, Direction::North, RouteId(0))?;
+    ensure!(
+        pathway.iter().all(|pos| pos.x == WireCoord(4)),
+        "Route should go straight to the alternate driver, got {:?}",
+        pathway
+    );
+
+    Ok(())
+}
+
+#[test]
+pub fn it_follows_the_guide() -> Result<()> {
+    let mut router = init(5, 1, 5);
+
+    let driver = GridCellPosition::new(0.into(), 0, 0.into());
+    let sink = GridCellPosition::new(4.into(), 0, 4.into());
+    *router.get_cell_mut(driver)? = GridCell::Blocked;
+    *router.get_cell_mut(sink)? = GridCell::Blocked;
+
+    // Any monotone path is equally short, so only the guide decides which one gets taken
+    let guide: Vec<GridCellPosition> = [(4, 2), (4, 1), (3, 1), (2, 1), (1, 1)]
+        .into_iter()
+        .map(|(x, z)| GridCellPosition::new(x.into(), 0, z.into()))
+        .collect();
+    router.set_guide(guide.iter().copied().collect());
+    router.route(driver, Direction::North, sink, Direction::North, RouteId(0))?;
+
+    let pathway = assert_connected(&router, driver, sink, Direction::North, RouteId(0))?;
+    ensure!(
+        guide.iter().all(|pos| pathway.contains(pos)),
+        "Route should follow the guide {:?}, got {:?}",
+        guide,
+        pathway
+    );
+
+    let mut cells = router.route_cells(RouteId(0));
+    cells.sort_by_key(|pos| (pos.x, pos.z));
+    let mut expected = pathway.clone();
+    expected.sort_by_key(|pos| (pos.x, pos.z));
+    assert_eq!(cells, expected);
+
+    Ok(())
+}
+
+#[test]
+pub fn it_branches_from_a_tap() -> Result<()> {
+    let mut router = init(9, 1, 5);
+
+    let driver = GridCellPosition::new(4.into(), 0, 0.into());
+    let sinks = [
+        GridCellPosition::new(0.into(), 0, 4.into()),
+        GridCellPosition::new(8.into(), 0, 4.into()),
+    ];
+    *router.get_cell_mut(driver)? = GridCell::Blocked;
+    for sink in sinks {
+        *router.get_cell_mut(sink)? = GridCell::Blocked;
+    }
+
+    let tap = GridCellPosition::new(4.into(), 0, 3.into());
+    router.route_to_tap(driver, Direction::North, tap, RouteId(0))?;
+    for sink in sinks {
+        router.route(driver, Direction::North, sink, Direction::North, RouteId(0))?;
+        let pathway = assert_connected(&router, driver, sink, Direction::North, RouteId(0))?;
+        ensure!(
+            pathway.contains(&tap),
+            "Route should branch at the tap {}, got {:?}",
+            tap,
+            pathway
+        );
+        assert_eq!(
+            router.path_length(sink, Direction::North, RouteId(0))?,
+            pathway.len() as u32
+        );
+    }
+    assert_eq!(
+        router.path_length(sinks[0], Direction::North, RouteId(0))?,
+        7
+    );
+    assert_eq!(
+        router.path_length(sinks[1], Direction::North, RouteId(0))?,
+        7
+    );
+
+    Ok(())
+}
+
+#[test]
+pub fn route_tree_follows_the_route_back_from_each_sink() -> Result<()> {
+    let mut router = init(9, 1, 5);
+
+    let driver = GridCellPosition::new(4.into(), 0, 0.into());
+    let sinks = [(0, 4), (8, 4)].map(|(x, z)| {
+        (
+            GridCellPosition::new(x.into(), 0, z.into()),
+            Direction::North,
+        )
+    });
+    *router.get_cell_mut(driver)? = GridCell::Blocked;
+    for (sink, _) in sinks {
+        *router.get_cell_mut(sink)? = GridCell::Blocked;
+    }
+    for (sink, sink_direction) in sinks {
+        router.route(driver, Direction::North, sink, sink_direction, RouteId(0))?;
+    }
+
+    let all: HashSet<_> = router.route_cells(RouteId(0)).into_iter().collect();
+    assert_eq!(router.route_tree(RouteId(0), &sinks)?, all);
+
+    let first = router.route_tree(RouteId(0), &sinks[..1])?;
+    let path = assert_connected(&router, driver, sinks[0].0, Direction::North, RouteId(0))?;
+    assert_eq!(first, path.into_iter().collect());
+    assert!(!first.contains(&sinks[1].0.offset(Direction::North)));
+
+    // Nothing of another route
+    assert!(router.route_tree(RouteId(1), &sinks)?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+pub fn paged_grids_route_like_dense_ones() -> Result<()> {
+    let _ = tracing_subscriber::fmt().with_test_writer().try_init();
+
+    // Big enough to span several pages, with the far ones sticking out past the edge
+    let driver = GridCellPosition::new(2.into(), 0, 3.into());
+    let sink = GridCellPosition::new(37.into(), 0, 18.into());
+    let mut routes = Vec::new();
+    for storage in [GridStorage::Dense, GridStorage::Paged] {
+        let mut router = DetailRouter::with_storage(40, 1, 20, storage);
+        *router.get_cell_mut(driver)? = GridCell::Blocked;
+        *router.get_cell_mut(sink)? = GridCell::Blocked;
+
+        router.route(driver, Direction::North, sink, Direction::North, RouteId(0))?;
+        assert_connected(&router, driver, sink, Direction::North, RouteId(0))?;
+
+        let mut cells = router.route_cells(RouteId(0));
+        cells.sort_by_key(|pos| (pos.x, pos.z));
+        routes.push(cells);
+
+        router.rip_up(RouteId(0))?;
+        assert!(router.route_cells(RouteId(0)).is_empty());
+    }
+    assert_eq!(routes[0], routes[1]);
+
+    Ok(())
+}
+
+#[test]
+pub fn scores_from_earlier_searches_are_ignored() -> Result<()> {
+    let mut router = init(9, 1, 5);
+    let driver = GridCellPosition::new(0.into(), 0, 2.into());
+    let sink = GridCellPosition::new(8.into(), 0, 2.into());
+    *router.get_cell_mut(driver)? = GridCell::Blocked;
+    *router.get_cell_mut(sink)? = GridCell::Blocked;
+
+    // Start one search short of the epoch wrapping around, so the second search has to clear the
+    // scores the first left behind
+    router.search_epoch = u32::MAX - 1;
+    for route in [RouteId(0), RouteId(1)] {
+        router.route(driver, Direction::North, sink, Direction::North, route)?;
+        assert_connected(&router, driver, sink, Direction::North, route)?;
+        router.rip_up(route)?;
+    }
+    assert_eq!(router.search_epoch, 1);
+
+    Ok(())
+}
+
+#[test]
+pub fn backtrack_follows_the_path_the_search_found() -> Result<()> {
+    // The guide makes M0 cheap enough that far from the sink it has a lower score than the LI
+    // cells the route runs through, but not so cheap that going up and back down is worth it
+    let mut router = init(49, 2, 1);
+    let driver = GridCellPosition::new(0.into(), 0, 0.into());
+    let sink = GridCellPosition::new(48.into(), 0, 0.into());
+    *router.get_cell_mut(driver)? = GridCell::Blocked;
+    *router.get_cell_mut(sink)? = GridCell::Blocked;
+    router.set_guide(
+        (0..49)
+            .map(|x| GridCellPosition::new(x.into(), 1, 0.into()))
+            .collect(),
+    );
+
+    router.route(driver, Direction::West, sink, Direction::West, RouteId(0))?;
+    let path = router.trace_route(sink, Direction::West, RouteId(0))?;
+    assert!(
+        path.iter().all(|(pos, _)| pos.y == 0),
+        "Route changed layer: {:?}",
+        path
+    );
+
+    Ok(())
+}
+
+#[test]
+pub fn searches_widen_to_detour_around_walls() -> Result<()> {
+    let mut router = init(11, 1, 12);
+    let driver = GridCellPosition::new(0.into(), 0, 2.into());
+    let sink = GridCellPosition::new(10.into(), 0, 2.into());
+    *router.get_cell_mut(driver)? = GridCell::Blocked;
+    *router.get_cell_mut(sink)? = GridCell::Blocked;
+    // Only the end of the wall furthest from the pins is open
+    for z in 0..10 {
+        *router.get_cell_mut(GridCellPosition::new(5.into(), 0, z.into()))? = GridCell::Blocked;
+    }
+
+    router.route(driver, Direction::West, sink, Direction::West, RouteId(0))?;
+    assert_connected(&router, driver, sink, Direction::West, RouteId(0))?;
+    assert_eq!(
+        router.take_search_stats().widest_window,
+        Some(SearchWindow::Unbounded)
+    );
+
+    // With a gap right between the pins the first window is enough
+    router.rip_up(RouteId(0))?;
+    *router.get_cell_mut(GridCellPosition::new(5.into(), 0, 2.into()))? = GridCell::Free;
+    router.route(driver, Direction::West, sink, Direction::West, RouteId(0))?;
+    assert_eq!(router.take_search_stats().widest_window, None);
+
+    Ok(())
+}
+
+#[test]
+pub fn length_limits_rule_out_long_wires() -> Result<()> {
+    let mut router = init(11, 1, 5);
+    let driver = GridCellPosition::new(0.into(), 0, 2.into());
+    let sink = GridCellPosition::new(10.into(), 0, 2.into());
+    *router.get_cell_mut(driver)? = GridCell::Blocked;
+    *router.get_cell_mut(sink)? = GridCell::Blocked;
+
+    // The straight wire between the pins is 9 cells
+    router.set_length_limit(Some(8));
+    let e = router
+        .route(driver, Direction::West, sink, Direction::West, RouteId(0))
+        .unwrap_err();
+    assert_eq!(e.downcast_ref(), Some(&RoutingError::Unroutable));
+
+    router.rip_up(RouteId(0))?;
+    router.set_length_limit(Some(9));
+    router.route(driver, Direction::West, sink, Direction::West, RouteId(0))?;
+    assert_eq!(router.path_length(sink, Direction::West, RouteId(0))?, 9);
+
+    Ok(())
+}
+
+#[test]
+pub fn track_routing_runs_each_layer_one_way() -> Result<()> {
+    // M0 runs along X, so a route in Z between two pins on M0 should hop to another layer
+    let driver = GridCellPosition::new(1.into(), 1, 0.into());
+    let sink = GridCellPosition::new(1.into(), 1, 8.into());
+    for track_routing in [false, true] {
+        let mut router = init(3, 3, 9);
+        router.set_track_routing(track_routing);
+        *router.get_cell_mut(driver)? = GridCell::Blocked;
+        *router.get_cell_mut(sink)? = GridCell::Blocked;
+
+        router.route(driver, Direction::North, sink, Direction::North, RouteId(0))?;
+        let path = router.trace_route(sink, Direction::North, RouteId(0))?;
+        info!("Route with track routing {}: {:?}", track_routing, path);
+
+        // The last cell only points back at the driver pin
+        let off_track = path[..path.len() - 1]
+            .iter()
+            .filter(|(pos, d)| !pos.layer().unwrap().is_track_direction(*d))
+            .count();
+        if track_routing {
+            assert_eq!(off_track, 0, "Route left the tracks: {:?}", path);
+        } else {
+            assert!(
+                off_track > 0,
+                "Route should run straight along M0: {:?}",
+                path
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+pub fn clean_up_prunes_stubs_and_straightens_staircases() -> Result<()> {
+    let mut router = init(6, 1, 6);
+    let id = RouteId(0);
+    let driver = GridCellPosition::new(0.into(), 0, 0.into());
+    let sink = GridCellPosition::new(4.into(), 0, 4.into());
+    *router.get_cell_mut(driver)? = GridCell::Blocked;
+    *router.get_cell_mut(sink)? = GridCell::Blocked;
+
+    // A staircase from the sink back to the driver, and a stub nothing uses
+    let mut pos = sink.offset(Direction::North);
+    for d in [
+        Direction::West,
+        Direction::North,
+        Direction::West,
+        Direction::North,
+        Direction::West,
+        Direction::North,
+        Direction::West,
+    ] {
+        *router.get_cell_mut(pos)? = GridCell::Occupied(d, id);
+        pos = pos.offset(d);
+    }
+    assert_eq!(pos, driver);
+    let stub = GridCellPosition::new(0.into(), 0, 4.into());
+    *router.get_cell_mut(stub)? = GridCell::Occupied(Direction::East, id);
+
+    let routes = HashMap::from([(id, vec![(sink, Direction::North)])]);
+    let stats = router.clean_up(&routes)?;
+
+    assert_eq!(stats.pruned, 1);
+    assert_eq!(stats.straightened, 2);
+    assert_eq!(*router.get_cell(stub)?, GridCell::Free);
+    let directions: Vec<_> = router
+        .trace_route(sink, Direction::North, id)?
+        .into_iter()
+        .map(|(_, d)| d)
+        .collect();
+    assert_eq!(
+        directions,
+        vec![
+            Direction::West,
+            Direction::West,
+            Direction::North,
+            Direction::North,
+            Direction::North,
+            Direction::West,
+            Direction::West,
+        ]
+    );
+    assert_eq!(router.route_cells(id).len(), 7);
+
+    Ok(())
+}
+
+#[test]
+pub fn congestion_map_counts_each_net_that_wanted_a_cell() -> Result<()> {
+    let mut router = init(9, 1, 5);
+    router.set_congestion_tracking(true);
+    let driver = GridCellPosition::new(0.into(), 0, 2.into());
+    let sink = GridCellPosition::new(8.into(), 0, 2.into());
+    *router.get_cell_mut(driver)? = GridCell::Blocked;
+    *router.get_cell_mut(sink)? = GridCell::Blocked;
+
+    // Both nets want the straight line between the pins, but only the second keeps it
+    router.route(driver, Direction::North, sink, Direction::North, RouteId(0))?;
+    router.rip_up(RouteId(0))?;
+    router.route(driver, Direction::North, sink, Direction::North, RouteId(1))?;
+
+    let map = router.congestion_map()?;
+    assert_eq!(map.layers.len(), 1);
+    let route = router.route_cells(RouteId(1));
+    for pos in route.iter() {
+        let (x, z) = (pos.x.0 as usize, pos.z.0 as usize);
+        assert_eq!(map.layers[0].demand[z][x], 2, "Demand at {}", pos);
+        assert_eq!(map.layers[0].occupied[z][x], 1, "Occupancy at {}", pos);
+    }
+    let occupied: u32 = map.layers[0].occupied.iter().flatten().sum();
+    assert_eq!(occupied as usize, route.len());
+    assert_eq!(map.hotspot().map(|hotspot| hotspot.nets), Some(2));
+
+    Ok(())
+}
+
+#[test]
+pub fn wavefront_records_the_watched_net_only() -> Result<()> {
+    let mut router = init(9, 1, 5);
+    router.set_wavefront_tracking(Some(RouteId(1)));
+    let driver = GridCellPosition::new(0.into(), 0, 2.into());
+    let sink = GridCellPosition::new(8.into(), 0, 2.into());
+    *router.get_cell_mut(driver)? = GridCell::Blocked;
+    *router.get_cell_mut(sink)? = GridCell::Blocked;
+
+    router.route(driver, Direction::North, sink, Direction::North, RouteId(0))?;
+    router.rip_up(RouteId(0))?;
+    router.route(driver, Direction::North, sink, Direction::North, RouteId(1))?;
+
+    let wavefront = router.wavefront.clone().expect("Tracking is on");
+    // The search starts from the cell in front of the sink pin
+    assert_eq!(
+        wavefront.visits.first(),
+        Some(&GridCellPosition::new(8.into(), 0, 1.into()))
+    );
+    assert!(wavefront.visits.len() as u64 <= router.take_search_stats().expanded);
+
+    // The whole grid, since the search reached both ends of it
+    let frame = wavefront.frame(&router, 0, wavefront.visits.len());
+    assert_eq!((frame.width, frame.height), (36, 20));
+    let mut ppm = Vec::new();
+    frame.write_ppm(&mut ppm)?;
+    assert!(ppm.starts_with(b"P6\n36 20\n255\n"));
+    assert_eq!(ppm.len(), 13 + 36 * 20 * 3);
+
+    router.rip_up(RouteId(1))?;
+    let wavefront = router.take_wavefront().expect("Tracking is on");
+    assert_eq!(wavefront.visits.len(), 0);
+    let frame = wavefront.frame(&router, 0, 0);
+    assert_eq!((frame.width, frame.height, frame.pixels.len()), (0, 0, 0));
+
+    Ok(())
+}
+
+#[test]
+pub fn vias_keep_clear_of_other_nets() -> Result<()> {
+    let mut router = init(3, 2, 2);
+    let driver = GridCellPosition::new(0.into(), 1, 0.into());
+    let sink = GridCellPosition::new(0.into(), 0, 0.into());
+    *router.get_cell_mut(driver)? = GridCell::Blocked;
+    *router.get_cell_mut(sink)? = GridCell::Blocked;
+    // Another net's wires run right next to where the shortest via would go
+    for y in 0..2 {
+        *router.get_cell_mut(GridCellPosition::new(2.into(), y, 0.into()))? =
+            GridCell::Occupied(Direction::North, RouteId(1));
+    }
+
+    router.route(driver, Direction::West, sink, Direction::East, RouteId(0))?;
+    let path = router.trace_route(sink, Direction::East, RouteId(0))?;
+    let via = path
+        .iter()
+        .find(|(_, d)| *d == Direction::Up)
+        .expect("The route changes layer");
+    assert_eq!(via.0.z, 1.into(), "Via touches the other net: {:?}", path);
+
+    // Nothing else can go next to the via now, but the net itself can
+    let beside = GridCellPosition::new(2.into(), 0, 1.into());
+    assert!(router.is_blocked(beside, RouteId(2)));
+    assert!(!router.is_blocked(beside, RouteId(0)));
+
+    Ok(())
+}
@@ -0,0 +1,197 @@
+//! The order the searches for one net expanded cells in, to see why its route wandered where it
+//! did. The recording is written as a series of PPM frames, each showing the cells expanded so far
+//! over the routing grid as it ended up, which ImageMagick or ffmpeg can turn in to an animation.
+
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+
+use super::wire_segment::WireCoord;
+use super::{DetailRouter, GridCell, GridCellPosition};
+use crate::RouteId;
+
+/// Pixels along each side of a grid cell in the frames
+const CELL_PIXELS: usize = 4;
+
+/// Grid cells shown around the cells the searches expanded
+const MARGIN: i32 = 2;
+
+const FREE_COLOR: [u8; 3] = [16, 16, 16];
+const BLOCKED_COLOR: [u8; 3] = [96, 96, 96];
+const OTHER_NET_COLOR: [u8; 3] = [112, 80, 32];
+const ROUTE_COLOR: [u8; 3] = [255, 255, 255];
+/// Cells expanded since the previous frame
+const FRONT_COLOR: [u8; 3] = [255, 255, 0];
+/// Rows between the layers of a frame
+const LAYER_GAP_COLOR: [u8; 3] = [0, 0, 0];
+
+/// Cells expanded by the searches for one net, in order
+#[derive(Clone, Debug)]
+pub struct Wavefront {
+    pub id: RouteId,
+    pub visits: Vec<GridCellPosition>,
+}
+
+impl DetailRouter {
+    /// Record the cells the searches for `id` expand from here on, for [`Self::take_wavefront`].
+    /// Ripping the net up forgets what was recorded, so only the searches for its latest route are
+    /// kept.
+    pub fn set_wavefront_tracking(&mut self, id: Option<RouteId>) {
+        self.wavefront = id.map(|id| Wavefront {
+            id,
+            visits: Vec::new(),
+        });
+    }
+
+    /// Note that a search for `id` expanded `pos`, if that net is being watched
+    #[inline]
+    pub(super) fn record_visit(&mut self, pos: GridCellPosition, id: RouteId) {
+        if let Some(ref mut wavefront) = self.wavefront {
+            if wavefront.id == id {
+                wavefront.visits.push(pos);
+            }
+        }
+    }
+
+    /// Forget what was recorded for `id`, if that net is being watched
+    pub(super) fn forget_visits(&mut self, id: RouteId) {
+        if let Some(ref mut wavefront) = self.wavefront {
+            if wavefront.id == id {
+                wavefront.visits.clear();
+            }
+        }
+    }
+
+    /// The cells expanded for the watched net, which stops watching it
+    pub fn take_wavefront(&mut self) -> Option<Wavefront> {
+        self.wavefront.take()
+    }
+}
+
+/// One image of the wavefront, as rows of RGB pixels
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[u8; 3]>,
+}
+
+impl Frame {
+    pub fn write_ppm(&self, mut w: impl Write) -> Result<()> {
+        write!(w, "P6\n{} {}\n255\n", self.width, self.height)?;
+        let bytes: Vec<u8> = self.pixels.iter().flatten().copied().collect();
+        w.write_all(&bytes)?;
+        w.flush()?;
+        Ok(())
+    }
+}
+
+impl Wavefront {
+    /// Draw the grid around the expanded cells, with the first `shown` visits colored from blue
+    /// to red in the order they were expanded and the ones after `front` highlighted. Each layer
+    /// the searches reached is a band of the image, from the bottom layer at the top down. The
+    /// frame is empty if nothing was expanded.
+    pub fn frame(&self, router: &DetailRouter, front: usize, shown: usize) -> Frame {
+        if self.visits.is_empty() {
+            return Frame {
+                width: 0,
+                height: 0,
+                pixels: Vec::new(),
+            };
+        }
+
+        let mut min = [i32::MAX; 2];
+        let mut max = [i32::MIN; 2];
+        let mut layers = Vec::new();
+        for pos in &self.visits {
+            min = [min[0].min(pos.x.0), min[1].min(pos.z.0)];
+            max = [max[0].max(pos.x.0), max[1].max(pos.z.0)];
+            layers.push(pos.y);
+        }
+        layers.sort_unstable();
+        layers.dedup();
+        let (min_x, min_z) = ((min[0] - MARGIN).max(0), (min[1] - MARGIN).max(0));
+        let max_x = (max[0] + MARGIN).min(router.size_x - 1);
+        let max_z = (max[1] + MARGIN).min(router.size_z - 1);
+        let (cells_x, cells_z) = (
+            (max_x - min_x + 1).max(0) as usize,
+            (max_z - min_z + 1).max(0) as usize,
+        );
+
+        // When each cell was last expanded, of the visits shown
+        let mut order = vec![None; cells_x * cells_z * layers.len()];
+        let cell_idx = |pos: &GridCellPosition| -> usize {
+            let layer = layers.binary_search(&pos.y).unwrap_or_default();
+            (layer * cells_z + (pos.z.0 - min_z) as usize) * cells_x + (pos.x.0 - min_x) as usize
+        };
+        for (visit, pos) in self.visits.iter().take(shown).enumerate() {
+            order[cell_idx(pos)] = Some(visit);
+        }
+
+        let width = cells_x * CELL_PIXELS;
+        let band = cells_z * CELL_PIXELS + 1;
+        let height = (band * layers.len()).saturating_sub(1);
+        let mut pixels = vec![LAYER_GAP_COLOR; width * height];
+        for (layer, y) in layers.iter().enumerate() {
+            for dz in 0..cells_z {
+                for dx in 0..cells_x {
+                    let pos = GridCellPosition::new(
+                        WireCoord(min_x + dx as i32),
+                        *y,
+                        WireCoord(min_z + dz as i32),
+                    );
+                    let visited = order[cell_idx(&pos)];
+                    let color = match (router.get_cell(pos).ok(), visited) {
+                        (Some(GridCell::Occupied(_, id)), _) if *id == self.id => ROUTE_COLOR,
+                        (_, Some(visit)) if visit >= front => FRONT_COLOR,
+                        (_, Some(visit)) => {
+                            let t = visit as f32 / shown.max(1) as f32;
+                            [(255.0 * t) as u8, 64, (255.0 * (1.0 - t)) as u8]
+                        }
+                        (Some(GridCell::Blocked), None) => BLOCKED_COLOR,
+                        (Some(GridCell::Occupied(_, _)), None) => OTHER_NET_COLOR,
+                        _ => FREE_COLOR,
+                    };
+                    for py in 0..CELL_PIXELS {
+                        let row = layer * band + dz * CELL_PIXELS + py;
+                        let start = row * width + dx * CELL_PIXELS;
+                        pixels[start..start + CELL_PIXELS].fill(color);
+                    }
+                }
+            }
+        }
+
+        Frame {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Write a frame for every `interval` cells expanded in to `dir`, as `frame_00000.ppm` and
+    /// on, and return the number of frames
+    pub fn write_frames(
+        &self,
+        router: &DetailRouter,
+        dir: &Path,
+        interval: usize,
+    ) -> Result<usize> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| anyhow!("Failed to create wavefront directory {:?}", dir))?;
+        let interval = interval.max(1);
+        let mut frames = 0;
+        for front in (0..self.visits.len()).step_by(interval) {
+            let shown = std::cmp::min(front + interval, self.visits.len());
+            let path = dir.join(format!("frame_{:05}.ppm", frames));
+            let file = std::fs::File::create(&path)
+                .with_context(|| anyhow!("Failed to create wavefront frame {:?}", path))?;
+            self.frame(router, front, shown)
+                .write_ppm(std::io::BufWriter::new(file))
+                .with_context(|| anyhow!("Failed to write wavefront frame {:?}", path))?;
+            frames += 1;
+        }
+
+        Ok(frames)
+    }
+}
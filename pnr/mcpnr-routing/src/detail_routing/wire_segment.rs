@@ -1,8 +1,8 @@
 use std::ops::{Add, Sub};
 
 use anyhow::{anyhow, bail, ensure, Context, Result};
-use log::debug;
 use mcpnr_common::block_storage::{Block, BlockStorage};
+use tracing::debug;
 
 use crate::detail_routing::Position;
 
@@ -10,6 +10,35 @@ use super::{Direction, Layer};
 
 pub const WIRE_GRID_SCALE: i32 = 2;
 
+/// Block the redstone of a wire sits on, unless nets are colored
+pub const WIRE_BASE_BLOCK: &str = "minecraft:calcite";
+
+/// Blocks that wires of different nets sit on when nets are colored, so they can be told apart
+/// in-game. Nets cycle through these in order.
+const NET_BASE_BLOCKS: [&str; 16] = [
+    "minecraft:white_concrete",
+    "minecraft:orange_concrete",
+    "minecraft:magenta_concrete",
+    "minecraft:light_blue_concrete",
+    "minecraft:yellow_concrete",
+    "minecraft:lime_concrete",
+    "minecraft:pink_concrete",
+    "minecraft:gray_concrete",
+    "minecraft:light_gray_concrete",
+    "minecraft:cyan_concrete",
+    "minecraft:purple_concrete",
+    "minecraft:blue_concrete",
+    "minecraft:brown_concrete",
+    "minecraft:green_concrete",
+    "minecraft:red_concrete",
+    "minecraft:black_concrete",
+];
+
+/// The entry of [`NET_BASE_BLOCKS`] for net index `net`, wrapping back to white after black
+pub fn net_base_block(net: u32) -> Block {
+    Block::new(NET_BASE_BLOCKS[net as usize % NET_BASE_BLOCKS.len()].into())
+}
+
 #[repr(transparent)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct WireCoord(pub i32);
@@ -100,12 +129,15 @@ impl WireTierLayer {
 /// the direction `input.1` on the layer `input.0` and flowing out of the cell on layer `output.0`
 /// in direction `output.1`.
 ///
+/// The redstone sits on `base`, normally [`WIRE_BASE_BLOCK`].
+///
 /// Returns the position to which signal was routed.
 pub fn splat_wire_segment(
     o: &mut BlockStorage,
     start_position: LayerPosition,
     input: (WireTierLayer, Direction),
     output: (WireTierLayer, Direction),
+    base: &Block,
 ) -> Result<(LayerPosition, WireTierLayer)> {
     ensure!(
         input.0.adjacent(output.0),
@@ -125,7 +157,7 @@ pub fn splat_wire_segment(
 
     // TODO: cache these
     let b_air = o.add_new_block_type(Block::new("minecraft:air".into()));
-    let b_calcite = o.add_new_block_type(Block::new("minecraft:calcite".into()));
+    let b_base = o.add_new_block_type(base.clone());
     let b_redstone = o.add_new_block_type(Block::new("minecraft:redstone_wire".into()));
 
     if input.0 == output.0 {
@@ -137,7 +169,7 @@ pub fn splat_wire_segment(
             .context("Start Z")?;
         let iy = input.0.tier * 16 + input.0.layer.to_y_idx();
         // Same layer routing, very easy.
-        (*o.get_block_mut(ix0 + 0, iy + 0, iz0 + 0)?) = b_calcite;
+        (*o.get_block_mut(ix0 + 0, iy + 0, iz0 + 0)?) = b_base;
         (*o.get_block_mut(ix0 + 0, iy + 1, iz0 + 0)?) = b_redstone;
 
         match (input.1, output.1) {
@@ -149,7 +181,7 @@ pub fn splat_wire_segment(
                 // North-East wire
                 // _ x
                 // _ x
-                (*o.get_block_mut(ix0 + 0, iy + 0, iz0 + 1)?) = b_calcite;
+                (*o.get_block_mut(ix0 + 0, iy + 0, iz0 + 1)?) = b_base;
                 (*o.get_block_mut(ix0 + 0, iy + 1, iz0 + 1)?) = b_redstone;
             }
             (Direction::East, Direction::East)
@@ -160,7 +192,7 @@ pub fn splat_wire_segment(
                 // South-East wire
                 // _ _
                 // x x
-                (*o.get_block_mut(ix0 + 1, iy + 0, iz0 + 0)?) = b_calcite;
+                (*o.get_block_mut(ix0 + 1, iy + 0, iz0 + 0)?) = b_base;
                 (*o.get_block_mut(ix0 + 1, iy + 1, iz0 + 0)?) = b_redstone;
             }
             (Direction::South, Direction::West) | (Direction::East, Direction::North) => {
@@ -174,8 +206,8 @@ pub fn splat_wire_segment(
                 // South-West wire
                 // _ x
                 // x x
-                (*o.get_block_mut(ix0 + 0, iy + 0, iz0 + 1)?) = b_calcite;
-                (*o.get_block_mut(ix0 + 1, iy + 0, iz0 + 0)?) = b_calcite;
+                (*o.get_block_mut(ix0 + 0, iy + 0, iz0 + 1)?) = b_base;
+                (*o.get_block_mut(ix0 + 1, iy + 0, iz0 + 0)?) = b_base;
 
                 (*o.get_block_mut(ix0 + 0, iy + 1, iz0 + 1)?) = b_redstone;
                 (*o.get_block_mut(ix0 + 1, iy + 1, iz0 + 0)?) = b_redstone;
@@ -187,16 +219,36 @@ pub fn splat_wire_segment(
         }
         Ok((start_position.offset(output.1)?, input.0))
     } else {
+        ensure!(
+            output.1 != input.1.mirror(),
+            "Vias can't turn back on themselves, {:?} -> {:?}",
+            input.1,
+            output.1
+        );
+
         // We don't care about directionality, the wire legalizer should fix that for us.
         // Therefore we ensure the input is always lower in the stackup than the output
         let end_position = start_position.offset(input.1)?.offset(output.1)?;
         let (start_position, input, output) = if input.0 < output.0 {
             (start_position, input, output)
         } else {
+            // Coming down, the ramp runs back along the input direction from the far cell, and
+            // any turn happens at its foot on the lower layer
+            let foot = start_position.offset(input.1)?;
+            if input.1 != output.1 {
+                splat_wire_segment(
+                    o,
+                    foot,
+                    (output.0, output.1.mirror()),
+                    (output.0, input.1.mirror()),
+                    base,
+                )
+                .context("Turn at the foot of the via")?;
+            }
             (
-                start_position.offset(input.1)?,
+                foot,
                 (output.0, input.1.mirror()),
-                (input.0, output.1.mirror()),
+                (input.0, input.1.mirror()),
             )
         };
 
@@ -205,7 +257,7 @@ pub fn splat_wire_segment(
             let y: u32 = (p.y + y).try_into().context("NE fill Y")?;
             let z: u32 = (p.z + z).try_into().context("NE fill Z")?;
 
-            (*o.get_block_mut(x, y + 0, z)?) = b_calcite;
+            (*o.get_block_mut(x, y + 0, z)?) = b_base;
             (*o.get_block_mut(x, y + 1, z)?) = b_redstone;
             (*o.get_block_mut(x, y + 2, z)?) = b_air;
 
@@ -264,7 +316,7 @@ pub fn splat_wire_segment(
                 let y: u32 = next_position.y.try_into().context("LI->M0 ramp start Y")?;
                 let z: u32 = next_position.z.try_into().context("LI->M0 ramp start Z")?;
 
-                (*o.get_block_mut(x, y + 0, z)?) = b_calcite;
+                (*o.get_block_mut(x, y + 0, z)?) = b_base;
                 (*o.get_block_mut(x, y + 1, z)?) = b_redstone;
                 (*o.get_block_mut(x, y + 2, z)?) = b_air;
 
@@ -326,7 +378,7 @@ pub fn splat_wire_segment(
                     .try_into()
                     .context("inter-metal ramp start Z")?;
 
-                (*o.get_block_mut(x, y + 0, z)?) = b_calcite;
+                (*o.get_block_mut(x, y + 0, z)?) = b_base;
                 (*o.get_block_mut(x, y + 1, z)?) = b_redstone;
                 (*o.get_block_mut(x, y + 2, z)?) = b_air;
 
@@ -337,3 +389,261 @@ pub fn splat_wire_segment(
         Ok((end_position, output.0))
     }
 }
+
+/// Blocks of a grid cell, as `(x, z)`, in the order a via climbs around them. The via enters and
+/// leaves through the first, second or last of them, which are the ones the other wires use.
+const VIA_SPIRAL: [(u32, u32); 4] = [(0, 0), (1, 0), (1, 1), (0, 1)];
+
+/// Splat a via from `lower` straight up to `upper` within the grid cell at `position`, for routes
+/// that change layer without moving. The wire comes in on the lower layer going in direction
+/// `lower.1` and leaves on the upper layer going in direction `upper.1`. Which way the signal
+/// flows doesn't matter, a via going down is the same as one going up the other way.
+///
+/// The via spirals up around the column of the grid cell a block at a time, with a few flat steps
+/// at the bottom or top to line up with the wires it joins. Each level is a quarter turn from the
+/// last, so nothing ever sits right on top of dust the via still has to climb from. Since it uses
+/// the whole column, unlike the other wires it would touch wires in the cells east and south of it,
+/// so the router keeps other nets out of them.
+pub fn splat_via(
+    o: &mut BlockStorage,
+    position: LayerPosition,
+    lower: (WireTierLayer, Direction),
+    upper: (WireTierLayer, Direction),
+    base: &Block,
+) -> Result<()> {
+    let dust = via_dust(position, lower, upper)?;
+
+    let b_base = o.add_new_block_type(base.clone());
+    let b_redstone = o.add_new_block_type(Block::new("minecraft:redstone_wire".into()));
+    for (x, y, z) in dust {
+        (*o.get_block_mut(x, y - 1, z)?) = b_base;
+        (*o.get_block_mut(x, y, z)?) = b_redstone;
+    }
+
+    Ok(())
+}
+
+/// Positions of the redstone of the via [`splat_via`] splats, in the order it climbs them. Each
+/// one sits on a base block right under it.
+fn via_dust(
+    position: LayerPosition,
+    lower: (WireTierLayer, Direction),
+    upper: (WireTierLayer, Direction),
+) -> Result<Vec<(u32, u32, u32)>> {
+    ensure!(
+        lower.0.tier == upper.0.tier,
+        "ITVs are not yet supported, {:?} -> {:?}",
+        lower,
+        upper
+    );
+    ensure!(
+        lower.0.layer < upper.0.layer,
+        "Vias climb from a lower layer to a higher one, not {:?} -> {:?}",
+        lower.0.layer,
+        upper.0.layer
+    );
+
+    // Where in the spiral the wires next to this cell meet it
+    let entry = match lower.1 {
+        Direction::South | Direction::East => 0,
+        Direction::West => 1,
+        Direction::North => 3,
+        d => bail!("Vias can't be entered going {:?}", d),
+    };
+    let exit = match upper.1 {
+        Direction::North | Direction::West => 0,
+        Direction::East => 1,
+        Direction::South => 3,
+        d => bail!("Vias can't be left going {:?}", d),
+    };
+    let height = upper.0.layer.to_y_idx() - lower.0.layer.to_y_idx();
+
+    // Turn whichever way needs the fewest flat steps. Up to two at the bottom and one at the top
+    // always stay clear of the climb.
+    let (turn, flat_bottom, flat_top) = [1, 3]
+        .into_iter()
+        .flat_map(|turn| {
+            (0..=2).flat_map(move |bottom| (0..=1).map(move |top| (turn, bottom, top)))
+        })
+        .filter(|(turn, bottom, top)| (entry + turn * (bottom + height + top)) % 4 == exit)
+        .min_by_key(|(_, bottom, top)| bottom + top)
+        .ok_or_else(|| anyhow!("No via from {:?} to {:?}", lower, upper))?;
+
+    let x0: u32 = position.x.to_block_coord().try_into().context("Via X")?;
+    let z0: u32 = position.y.to_block_coord().try_into().context("Via Z")?;
+    let y0 = lower.0.tier * 16 + lower.0.layer.to_y_idx();
+    let levels = (0..flat_bottom)
+        .map(|_| 0)
+        .chain(0..=height)
+        .chain((0..flat_top).map(|_| height));
+    Ok(levels
+        .enumerate()
+        .map(|(step, level)| {
+            let (dx, dz) = VIA_SPIRAL[(entry + turn * step as u32) as usize % 4];
+            (x0 + dx, y0 + level + 1, z0 + dz)
+        })
+        .collect())
+}
+
+/// Direction a via has to be entered going on its lower layer, or left going the other way on
+/// its upper layer, to start or end at the block `pin` is marked at. Pins that face up or down
+/// escape through a via in their own grid cell, and only three of its four blocks are where a
+/// via can start or end.
+pub fn pin_via_direction(pin: Position) -> Result<Direction> {
+    let corner = (
+        pin.x.rem_euclid(WIRE_GRID_SCALE),
+        pin.z.rem_euclid(WIRE_GRID_SCALE),
+    );
+    match corner {
+        (0, 0) => Ok(Direction::South),
+        (1, 0) => Ok(Direction::West),
+        (0, 1) => Ok(Direction::North),
+        _ => bail!(
+            "Pin at {} is in the south east corner of its grid cell, vias can't start there",
+            pin
+        ),
+    }
+}
+
+/// Splat a via like [`splat_via`] that starts or ends at a pin facing up or down, at block
+/// position `pin`. The sign marking the pin is replaced with redstone, which has to be where the
+/// via meets the pin's layer: at the height of its wires, in the block [`pin_via_direction`]
+/// picks the direction for.
+///
+/// The pin shares its grid cell with the cell it belongs to, so unlike other vias this one only
+/// puts redstone where there's air, and only puts `base` under it where there's nothing already.
+pub fn splat_pin_via(
+    o: &mut BlockStorage,
+    position: LayerPosition,
+    lower: (WireTierLayer, Direction),
+    upper: (WireTierLayer, Direction),
+    pin: Position,
+    base: &Block,
+) -> Result<()> {
+    let dust = via_dust(position, lower, upper)?;
+    let pin_block: (u32, u32, u32) = (
+        pin.x.try_into().context("Pin X")?,
+        pin.y.try_into().context("Pin Y")?,
+        pin.z.try_into().context("Pin Z")?,
+    );
+    ensure!(
+        dust.first() == Some(&pin_block) || dust.last() == Some(&pin_block),
+        "Pin at {} is not where the via from {:?} to {:?} meets its layer",
+        pin,
+        lower,
+        upper
+    );
+
+    let b_air = o.add_new_block_type(Block::new("minecraft:air".into()));
+    let b_base = o.add_new_block_type(base.clone());
+    let b_redstone = o.add_new_block_type(Block::new("minecraft:redstone_wire".into()));
+
+    // Check the whole via is clear before touching anything, so a failed escape leaves the cell
+    // as it was
+    for &(x, y, z) in dust.iter() {
+        let existing = *o.get_block(x, y, z)?;
+        ensure!(
+            (x, y, z) == pin_block || existing == b_air || existing == b_redstone,
+            "Escape from pin at {} would overwrite {:?} at {}",
+            pin,
+            o.info_for_index(existing),
+            Position::new(x as i32, y as i32, z as i32)
+        );
+    }
+
+    for (x, y, z) in dust {
+        o.remove_block_entity(x, y, z);
+        *o.get_block_mut(x, y, z)? = b_redstone;
+        let under = o.get_block_mut(x, y - 1, z)?;
+        if *under == b_air {
+            *under = b_base;
+        }
+    }
+
+    Ok(())
+}
+
+/// Splat the wire that escapes a cell from its pin to the routing grid.
+///
+/// The sign marking the pin at block position `pin` is replaced with redstone, and more redstone
+/// runs from there in direction `toward` up to the edge of the neighbouring wire grid cell, where
+/// the routed wire picks up. The pin has to sit at the height of the local interconnect wires, in
+/// line with the wires of the grid cells in direction `toward`.
+///
+/// The redstone sits on `base` wherever the cell doesn't already have a block under it.
+///
+/// Returns the grid cell the escape leads in to.
+pub fn splat_pin_escape(
+    o: &mut BlockStorage,
+    pin: Position,
+    toward: Direction,
+    base: &Block,
+) -> Result<LayerPosition> {
+    let (along, across) = match toward {
+        Direction::North | Direction::South => (pin.z, pin.x),
+        Direction::East | Direction::West => (pin.x, pin.z),
+        _ => bail!("Pins can only escape horizontally, not {:?}", toward),
+    };
+    ensure!(
+        pin.y.rem_euclid(16) == Layer::LI.to_y_idx() as i32 + 1,
+        "Pin at {} is not at the height of the local interconnect wires",
+        pin
+    );
+    ensure!(
+        across.rem_euclid(WIRE_GRID_SCALE) == 0,
+        "Pin at {} is off the wire grid, so it can't escape {:?}",
+        pin,
+        toward
+    );
+
+    let access = LayerPosition::new(
+        WireCoord::from_block_coord(pin.x),
+        WireCoord::from_block_coord(pin.z),
+    )
+    .offset(toward)?;
+    // First block of the grid cell the escape runs in to
+    let edge = match toward {
+        Direction::South => access.y.to_block_coord(),
+        Direction::East => access.x.to_block_coord(),
+        Direction::North => access.y.to_block_coord() + WIRE_GRID_SCALE - 1,
+        _ => access.x.to_block_coord() + WIRE_GRID_SCALE - 1,
+    };
+
+    let b_air = o.add_new_block_type(Block::new("minecraft:air".into()));
+    let b_base = o.add_new_block_type(base.clone());
+    let b_redstone = o.add_new_block_type(Block::new("minecraft:redstone_wire".into()));
+
+    // Check the whole escape is clear before touching anything, so a failed escape leaves the
+    // cell as it was
+    let mut escape = Vec::new();
+    let mut pos = pin;
+    for step in 0..(edge - along).abs() {
+        let x: u32 = pos.x.try_into().context("Pin escape X")?;
+        let y: u32 = pos.y.try_into().context("Pin escape Y")?;
+        let z: u32 = pos.z.try_into().context("Pin escape Z")?;
+        // The pin itself is the sign, everything after it has to be free
+        if step > 0 {
+            let existing = *o.get_block(x, y, z)?;
+            ensure!(
+                existing == b_air || existing == b_redstone,
+                "Escape from pin at {} would overwrite {:?} at {}",
+                pin,
+                o.info_for_index(existing),
+                pos
+            );
+        }
+        escape.push((x, y, z));
+        pos = pos.offset(toward);
+    }
+
+    for (x, y, z) in escape {
+        o.remove_block_entity(x, y, z);
+        *o.get_block_mut(x, y, z)? = b_redstone;
+        let under = o.get_block_mut(x, y - 1, z)?;
+        if *under == b_air {
+            *under = b_base;
+        }
+    }
+
+    Ok(access)
+}
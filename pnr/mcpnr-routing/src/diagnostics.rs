@@ -0,0 +1,138 @@
+//! Reports for routes that failed, naming the net and cells involved. The detail router only
+//! knows grid positions and route IDs, so this is where they get turned back in to something a
+//! designer can find in the netlist or the world.
+
+use std::fmt::Display;
+
+use anyhow::Result;
+use mcpnr_common::block_storage::Position;
+
+use crate::detail_routing::{GridCellPosition, RoutingError};
+use crate::netlist::{Netlist, Pin};
+
+/// One end of a failed route
+#[derive(Clone, Debug)]
+pub struct PinSite {
+    /// Description of the cell the pin belongs to, see [`Netlist::describe_cell`]
+    pub cell: String,
+    /// Position of the pin in blocks, relative to the design
+    pub block: Position,
+    pub grid: GridCellPosition,
+}
+
+impl PinSite {
+    pub fn new(netlist: &Netlist, pin: &Pin) -> Result<Self> {
+        let block = Position::new(pin.x as i32, pin.y as i32, pin.z as i32);
+        Ok(Self {
+            cell: netlist.describe_cell(pin.cell).to_owned(),
+            block,
+            grid: block.try_into()?,
+        })
+    }
+}
+
+impl Display for PinSite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} at block ({}, {}, {}), grid {}",
+            self.cell, self.block.x, self.block.y, self.block.z, self.grid
+        )
+    }
+}
+
+/// Everything known about a route the router couldn't make. Attach it to the error from the
+/// detail router with [`anyhow::Context`], the error itself is still there to downcast.
+#[derive(Clone, Debug)]
+pub struct RouteFailure {
+    pub net: u32,
+    pub net_name: Option<String>,
+    pub driver: PinSite,
+    /// The pin the route was headed for, or another driver being merged in to the net
+    pub sink: PinSite,
+    /// What the designer could try, if the cause is one we know about
+    pub suggestion: Option<&'static str>,
+}
+
+impl RouteFailure {
+    pub fn new(
+        netlist: &Netlist,
+        net: u32,
+        driver: &Pin,
+        sink: &Pin,
+        error: &anyhow::Error,
+    ) -> Result<Self> {
+        Ok(Self {
+            net,
+            net_name: netlist.net_name(net as i64).map(str::to_owned),
+            driver: PinSite::new(netlist, driver)?,
+            sink: PinSite::new(netlist, sink)?,
+            suggestion: error.downcast_ref().and_then(suggestion),
+        })
+    }
+}
+
+fn suggestion(error: &RoutingError) -> Option<&'static str> {
+    match error {
+        RoutingError::Unroutable => Some(
+            "every path between the pins is blocked. Leave more room between cells when \
+             placing, route on more --tiers, or give the net a higher criticality with \
+             --timing-constraints so it's routed before the nets in its way",
+        ),
+        RoutingError::OutOfBounds { .. } => Some(
+            "the search ran off the edge of the routing grid. Check the pins are inside the \
+             placement area",
+        ),
+        RoutingError::BudgetExhausted => None,
+    }
+}
+
+impl Display for RouteFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.net_name {
+            Some(ref name) => writeln!(f, "Failed to route net {} (net {})", name, self.net)?,
+            None => writeln!(f, "Failed to route net {}", self.net)?,
+        }
+        write!(f, "  driver: {}\n  sink:   {}", self.driver, self.sink)?;
+        if let Some(suggestion) = self.suggestion {
+            write!(f, "\n  hint:   {}", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mcpnr_common::block_storage::Position;
+
+    use super::{suggestion, PinSite, RouteFailure};
+    use crate::detail_routing::RoutingError;
+
+    #[test]
+    fn report_names_net_cells_and_blocks() -> anyhow::Result<()> {
+        let site = |cell: &str, x: i32| -> anyhow::Result<PinSite> {
+            let block = Position::new(x, 4, 6);
+            Ok(PinSite {
+                cell: cell.to_owned(),
+                block,
+                grid: block.try_into()?,
+            })
+        };
+        let failure = RouteFailure {
+            net: 3,
+            net_name: Some("carry".to_owned()),
+            driver: site("u0 (gate_not.nbt)", 2)?,
+            sink: site("u1 (gate_not.nbt)", 10)?,
+            suggestion: suggestion(&RoutingError::Unroutable),
+        };
+
+        let report = failure.to_string();
+        let lines: Vec<_> = report.lines().collect();
+        assert_eq!(lines[0], "Failed to route net carry (net 3)");
+        assert!(lines[1].starts_with("  driver: u0 (gate_not.nbt) at block (2, 4, 6), grid "));
+        assert!(lines[2].starts_with("  sink:   u1 (gate_not.nbt) at block (10, 4, 6), grid "));
+        assert!(lines[3].starts_with("  hint:   every path between the pins is blocked"));
+
+        Ok(())
+    }
+}
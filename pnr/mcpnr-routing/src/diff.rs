@@ -0,0 +1,331 @@
+//! Implementation of the `diff` subcommand, which compares two routed outputs of the same design
+//! block by block, so a change to the techlib or the router's cost model can be reviewed before
+//! the output is pasted in to a world. Given the placed design as well, the wires of each net are
+//! told apart the same way ECO rerouting does, and the nets whose routes changed are listed.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use mcpnr_common::block_storage::{stream::read_rle_json_file, BlockStorage, Position};
+use mcpnr_common::blockers::{BlockerRules, BLOCKER_RULES_FILE};
+use mcpnr_common::protos::read_placed_design;
+use mcpnr_common::{schematic, BLOCKS_PER_TIER};
+
+use crate::config::{DiffArgs, MarkerConfig};
+use crate::detail_routing::Layer;
+use crate::eco::{net_pins, net_wires};
+use crate::netlist::Netlist;
+use crate::splat::Splatter;
+use crate::structure_cache::StructureCache;
+
+/// Blocks that differ between the two outputs in one layer of one tier
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct LayerDiff {
+    /// Air before, something else after
+    added: usize,
+    /// Something before, air after
+    removed: usize,
+    /// A different block, or the same block with different block entity data (like sign text)
+    changed: usize,
+}
+
+impl LayerDiff {
+    fn total(&self) -> usize {
+        self.added + self.removed + self.changed
+    }
+}
+
+/// Blocks of one net's wires that differ between the two outputs
+struct NetDiff {
+    name: String,
+    /// Blocks of wire in each output
+    before: usize,
+    after: usize,
+    /// Blocks only in the later output, only in the earlier one, and in both but different
+    added: usize,
+    removed: usize,
+    changed: usize,
+}
+
+/// Read a routed output written as structure JSON
+fn read_output(path: &Path) -> Result<BlockStorage> {
+    if path.extension() == Some(schematic::SCHEMATIC_EXTENSION.as_ref()) {
+        bail!(
+            "{:?} is a WorldEdit schematic, only outputs written as structure JSON can be compared",
+            path
+        );
+    }
+    read_rle_json_file(path).with_context(|| anyhow!("Failed to read output {:?}", path))
+}
+
+/// Whether the blocks at `(x, y, z)` of the two outputs are the same, block entity included
+fn same_block(
+    before: &BlockStorage,
+    after: &BlockStorage,
+    (x, y, z): (u32, u32, u32),
+) -> Result<bool> {
+    let a = before.info_for_index(*before.get_block(x, y, z)?);
+    let b = after.info_for_index(*after.get_block(x, y, z)?);
+    Ok(a == b && before.block_entity(x, y, z) == after.block_entity(x, y, z))
+}
+
+fn is_air(storage: &BlockStorage, (x, y, z): (u32, u32, u32)) -> Result<bool> {
+    Ok(match storage.info_for_index(*storage.get_block(x, y, z)?) {
+        Some(block) => block.name == "minecraft:air",
+        None => true,
+    })
+}
+
+/// Count the blocks that differ between two outputs of the same size, by tier and layer
+fn diff_blocks(
+    before: &BlockStorage,
+    after: &BlockStorage,
+) -> Result<BTreeMap<(u32, Layer), LayerDiff>> {
+    ensure!(
+        before.extents() == after.extents(),
+        "The outputs are {:?} and {:?} blocks, were they routed from different placements or with \
+         different --tiers?",
+        before.extents(),
+        after.extents()
+    );
+
+    let mut layers: BTreeMap<(u32, Layer), LayerDiff> = BTreeMap::new();
+    for (pos, _) in before.iter_block_coords() {
+        if same_block(before, after, pos)? {
+            continue;
+        }
+
+        let (_, y, _) = pos;
+        let layer = Layer::from_y_idx((y % BLOCKS_PER_TIER) as i32)?;
+        let diff = layers.entry((y / BLOCKS_PER_TIER, layer)).or_default();
+        match (is_air(before, pos)?, is_air(after, pos)?) {
+            (true, false) => diff.added += 1,
+            (false, true) => diff.removed += 1,
+            _ => diff.changed += 1,
+        }
+    }
+
+    Ok(layers)
+}
+
+/// Find the wires of every net in both outputs, using a fresh splat of the cells of `design_path`
+/// to tell what's wire, and return the nets whose wires differ, most changed first.
+fn diff_nets(
+    args: &DiffArgs,
+    design_path: &Path,
+    before: &BlockStorage,
+    after: &BlockStorage,
+) -> Result<Vec<NetDiff>> {
+    let techlib = args
+        .techlib
+        .as_ref()
+        .ok_or_else(|| anyhow!("--design needs --techlib to splat the cells"))?;
+    let design = read_placed_design(design_path)?;
+    let rules = BlockerRules::load(&techlib.join(BLOCKER_RULES_FILE))?;
+    let mut structure_cache = StructureCache::new(&techlib.join("structures"), &design, &rules)?;
+    let netlist = Netlist::new(&design, &structure_cache)?;
+
+    let [sx, sy, sz] = *before.extents();
+    let mut fresh = BlockStorage::new(sx, sy, sz);
+    structure_cache.build_palette_maps(&mut fresh)?;
+    let markers = MarkerConfig::from(&args.markers);
+    crate::do_splat(&design, &structure_cache, &markers, &mut fresh)?;
+    // Where the outputs have no wires they have tier markers, which the splat needs too
+    Splatter::new(&mut fresh, &structure_cache).draw_tier_markers(&mut fresh, &markers)?;
+
+    let mut nets = Vec::new();
+    for (net_idx, net) in netlist.iter_nets() {
+        let pins = net_pins(&netlist, net);
+        let wires_before = net_wires(before, &fresh, &pins)
+            .with_context(|| anyhow!("Find the earlier wires of net {}", net_idx))?;
+        let wires_after = net_wires(after, &fresh, &pins)
+            .with_context(|| anyhow!("Find the later wires of net {}", net_idx))?;
+
+        let added = wires_after.difference(&wires_before).count();
+        let removed = wires_before.difference(&wires_after).count();
+        let mut changed = 0;
+        for pos in wires_before.intersection(&wires_after) {
+            let Position { x, y, z } = *pos;
+            if !same_block(before, after, (x as u32, y as u32, z as u32))? {
+                changed += 1;
+            }
+        }
+
+        if added + removed + changed > 0 {
+            nets.push(NetDiff {
+                name: match netlist.net_name(*net_idx) {
+                    Some(name) => name.to_owned(),
+                    None => format!("${}", net_idx),
+                },
+                before: wires_before.len(),
+                after: wires_after.len(),
+                added,
+                removed,
+                changed,
+            });
+        }
+    }
+
+    nets.sort_by(|a, b| {
+        (b.added + b.removed + b.changed)
+            .cmp(&(a.added + a.removed + a.changed))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    Ok(nets)
+}
+
+fn print_layers<W: Write>(
+    w: &mut W,
+    layers: &BTreeMap<(u32, Layer), LayerDiff>,
+) -> std::io::Result<()> {
+    let total: usize = layers.values().map(LayerDiff::total).sum();
+    if total == 0 {
+        return writeln!(w, "The outputs are identical");
+    }
+
+    writeln!(w, "{} blocks differ", total)?;
+    writeln!(
+        w,
+        "{:<6} {:<6} {:>8} {:>8} {:>8}",
+        "tier", "layer", "added", "removed", "changed"
+    )?;
+    for ((tier, layer), diff) in layers.iter() {
+        writeln!(
+            w,
+            "{:<6} {:<6} {:>8} {:>8} {:>8}",
+            tier,
+            format!("{:?}", layer),
+            diff.added,
+            diff.removed,
+            diff.changed
+        )?;
+    }
+    Ok(())
+}
+
+fn print_nets<W: Write>(w: &mut W, nets: &[NetDiff], top: usize) -> std::io::Result<()> {
+    writeln!(w)?;
+    if nets.is_empty() {
+        return writeln!(w, "No net's wires changed");
+    }
+
+    writeln!(w, "{} nets' wires changed", nets.len())?;
+    let width = nets
+        .iter()
+        .take(top)
+        .map(|net| net.name.len())
+        .chain(std::iter::once(3))
+        .max()
+        .unwrap_or(0);
+    writeln!(
+        w,
+        "{:<width$} {:>8} {:>8} {:>8} {:>8} {:>8}",
+        "net",
+        "before",
+        "after",
+        "added",
+        "removed",
+        "changed",
+        width = width
+    )?;
+    for net in nets.iter().take(top) {
+        writeln!(
+            w,
+            "{:<width$} {:>8} {:>8} {:>8} {:>8} {:>8}",
+            net.name,
+            net.before,
+            net.after,
+            net.added,
+            net.removed,
+            net.changed,
+            width = width
+        )?;
+    }
+    if nets.len() > top {
+        writeln!(w, "... and {} more nets", nets.len() - top)?;
+    }
+    Ok(())
+}
+
+/// Compare the outputs in `args` and print the differences to stdout
+pub fn run_diff(args: &DiffArgs) -> Result<()> {
+    let before = read_output(&args.before)?;
+    let after = read_output(&args.after)?;
+
+    let layers = diff_blocks(&before, &after)?;
+    let mut stdout = std::io::stdout().lock();
+    print_layers(&mut stdout, &layers)?;
+
+    if let Some(ref design) = args.design {
+        let nets = diff_nets(args, design, &before, &after)?;
+        print_nets(&mut stdout, &nets, args.top)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use mcpnr_common::block_storage::{Block, BlockStorage};
+
+    use super::{diff_blocks, LayerDiff};
+    use crate::detail_routing::Layer;
+
+    #[test]
+    fn counts_differences_by_layer() -> anyhow::Result<()> {
+        let mut before = BlockStorage::new(4, 32, 4);
+        let calcite = before.add_new_block_type(Block::new("minecraft:calcite".to_owned()));
+        let wire = before.add_new_block_type(Block::new("minecraft:redstone_wire".to_owned()));
+        *before.get_block_mut(0, 4, 0)? = calcite;
+        *before.get_block_mut(1, 4, 0)? = calcite;
+        *before.get_block_mut(0, 5, 0)? = wire;
+        *before.get_block_mut(0, 21, 0)? = wire;
+
+        // The palette is in a different order, which doesn't count as a difference
+        let mut after = BlockStorage::new(4, 32, 4);
+        let wire = after.add_new_block_type(Block::new("minecraft:redstone_wire".to_owned()));
+        let calcite = after.add_new_block_type(Block::new("minecraft:calcite".to_owned()));
+        let glass = after.add_new_block_type(Block::new("minecraft:glass".to_owned()));
+        *after.get_block_mut(0, 4, 0)? = calcite;
+        *after.get_block_mut(1, 4, 0)? = glass;
+        *after.get_block_mut(0, 5, 0)? = wire;
+        *after.get_block_mut(2, 1, 2)? = calcite;
+
+        let layers = diff_blocks(&before, &after)?;
+        assert_eq!(
+            layers.into_iter().collect::<Vec<_>>(),
+            vec![
+                (
+                    (0, Layer::LI),
+                    LayerDiff {
+                        added: 1,
+                        removed: 0,
+                        changed: 0
+                    }
+                ),
+                (
+                    (0, Layer::M0),
+                    LayerDiff {
+                        added: 0,
+                        removed: 0,
+                        changed: 1
+                    }
+                ),
+                (
+                    (1, Layer::M0),
+                    LayerDiff {
+                        added: 0,
+                        removed: 1,
+                        changed: 0
+                    }
+                ),
+            ]
+        );
+
+        assert!(diff_blocks(&before, &BlockStorage::new(4, 16, 4)).is_err());
+
+        Ok(())
+    }
+}
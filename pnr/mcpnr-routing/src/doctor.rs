@@ -0,0 +1,168 @@
+//! Implementation of the `doctor` subcommand, which checks the techlib, the command line options
+//! and the placed design for the problems that would otherwise stop the router one at a time.
+
+use anyhow::{bail, Result};
+use mcpnr_common::doctor::{check_techlib, Report, Severity, TechlibInfo};
+use mcpnr_common::minecraft_types::is_structure_file;
+use mcpnr_common::protos::read_placed_design;
+use mcpnr_common::timing_constraints::TimingConstraints;
+use mcpnr_common::{BLOCKS_PER_TIER, MAX_TIERS};
+
+use crate::config::CommonArgs;
+
+/// Run every check, print the findings and fail if any of them were errors.
+pub fn run_doctor(args: &CommonArgs) -> Result<()> {
+    let mut report = Report::new();
+
+    let techlib = check_techlib(&mut report, &args.techlib);
+    check_args(&mut report, args);
+    check_input(&mut report, args, &techlib);
+
+    report.print(std::io::stdout().lock())?;
+    if report.has_errors() {
+        bail!("Doctor found {} errors", report.count(Severity::Error));
+    }
+
+    Ok(())
+}
+
+fn check_args(report: &mut Report, args: &CommonArgs) {
+    report.require(
+        (1..=MAX_TIERS).contains(&args.tiers),
+        "tiers",
+        format!("{} tiers", args.tiers),
+        format!("{} tiers is outside of 1..={}", args.tiers, MAX_TIERS),
+        format!(
+            "Pass --tiers between 1 and {}, more tiers don't fit in the world height",
+            MAX_TIERS
+        ),
+    );
+
+    if args.budget.pass_time_budget.is_some_and(|b| b.is_zero()) {
+        report.warning(
+            "budgets",
+            "the pass time budget is zero, every net will be deferred",
+            "Raise --pass-time-budget or leave it off",
+        );
+    }
+    if args.budget.net_expansion_budget == Some(0) {
+        report.warning(
+            "budgets",
+            "the net expansion budget is zero, every net will be deferred",
+            "Raise --net-expansion-budget or leave it off",
+        );
+    }
+
+    let infrastructure = &args.infrastructure;
+    if infrastructure.light_spacing == Some(0) {
+        report.error(
+            "infrastructure",
+            "the light spacing is zero",
+            "Pass a positive --light-spacing",
+        );
+    }
+    if let Some(spacing) = infrastructure.corridor_spacing {
+        report.require(
+            infrastructure.corridor_width > 0 && spacing > infrastructure.corridor_width,
+            "infrastructure",
+            format!(
+                "{} block corridors every {} blocks",
+                infrastructure.corridor_width, spacing
+            ),
+            format!(
+                "{} block corridors can't be {} blocks apart",
+                infrastructure.corridor_width, spacing
+            ),
+            "Pass a positive --corridor-width below --corridor-spacing",
+        );
+    }
+
+    if let Some(ref path) = args.timing_constraints {
+        match TimingConstraints::load(path) {
+            Ok(constraints) => report.ok(
+                "timing constraints",
+                format!("{} constrained net patterns", constraints.len()),
+            ),
+            Err(e) => report.error(
+                "timing constraints",
+                format!("{:#}", e),
+                "Fix --timing-constraints, each line must be `set_criticality VALUE NET...`",
+            ),
+        }
+    }
+}
+
+fn check_input(report: &mut Report, args: &CommonArgs, techlib: &TechlibInfo) {
+    let design = match read_placed_design(&args.input) {
+        Ok(design) => design,
+        Err(e) => {
+            report.error(
+                "input",
+                format!("{} is not a placed design: {:#}", args.input.display(), e),
+                "Pass the output of mcpnr-placement as INPUT, the input must come from the same \
+                 MCPNR version as the router",
+            );
+            return;
+        }
+    };
+    report.ok(
+        "input",
+        format!("{} cells, {} nets", design.cells.len(), design.nets.len()),
+    );
+
+    let unplaced = design
+        .cells
+        .iter()
+        .filter(|cell| cell.pos.is_none())
+        .count();
+    report.require(
+        unplaced == 0,
+        "input",
+        "every cell has a position",
+        format!("{} cells have no position", unplaced),
+        "The placer and router protobuf definitions disagree, rebuild both from the same checkout",
+    );
+
+    let mut missing: Vec<&str> = design
+        .cells
+        .iter()
+        .map(|cell| cell.r#type.as_str())
+        .filter(|ty| is_structure_file(ty) && !techlib.structures.contains_key(*ty))
+        .collect();
+    missing.sort_unstable();
+    missing.dedup();
+    if !techlib.structures.is_empty() {
+        report.require(
+            missing.is_empty(),
+            "cell types",
+            "every cell type has a structure",
+            format!("no structure for {}", missing.join(", ")),
+            "Synthesize the design against this techlib, or add the missing structures to it",
+        );
+    }
+
+    let height = design
+        .cells
+        .iter()
+        .filter_map(|cell| {
+            let pos = cell.pos.as_ref()?;
+            let size_y = techlib
+                .structures
+                .get(&cell.r#type)
+                .map_or(0, |s| s.size[1].max(0) as u32);
+            Some(pos.y + size_y)
+        })
+        .max()
+        .unwrap_or(0);
+    let tiers_needed = height.div_ceil(BLOCKS_PER_TIER);
+    report.require(
+        tiers_needed <= args.tiers,
+        "tiers",
+        format!("the design fits in {} tiers", args.tiers),
+        format!(
+            "cells are placed up to y={}, which needs {} tiers but only {} were requested",
+            height, tiers_needed, args.tiers
+        ),
+        format!("Pass --tiers {} or more", tiers_needed),
+    );
+}
@@ -0,0 +1,256 @@
+//! Rerouting a few nets of an earlier route (an engineering change order) without routing the
+//! whole design again. The earlier output is the only record of its routes, so the wires of the
+//! nets being rerouted are found by comparing it with a fresh splat of the cells: anything that
+//! differs is wire, and the wires of a net are the differences connected to its pins. Every other
+//! net's wires stay where they are and block the new routes like any other block would.
+
+use std::collections::{BTreeSet, HashSet, VecDeque};
+use std::path::Path;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use mcpnr_common::block_storage::{
+    stream::read_rle_json_file, Block, BlockStorage, Position, ALL_DIRECTIONS,
+};
+use tracing::info;
+
+use crate::netlist::{Net, Netlist};
+use crate::supports::SUPPORT_BLOCK;
+
+/// Look up the nets to reroute, given by name or by number
+pub fn resolve_nets(netlist: &Netlist, nets: &[String]) -> Result<BTreeSet<u32>> {
+    nets.iter()
+        .map(|net| {
+            let by_name = netlist
+                .iter_nets()
+                .find(|(net_idx, _)| netlist.net_name(**net_idx) == Some(net.as_str()))
+                .map(|(net_idx, _)| *net_idx);
+            let net_idx = match (by_name, net.parse::<i64>()) {
+                (Some(net_idx), _) => net_idx,
+                (None, Ok(net_idx)) if netlist.iter_nets().any(|(n, _)| *n == net_idx) => net_idx,
+                _ => bail!("The design has no net {:?}", net),
+            };
+            Ok(net_idx as u32)
+        })
+        .collect()
+}
+
+/// Read the earlier output at `path` and take the wires of `nets` out of it, using `fresh`, the
+/// design with only its cells splatted, to tell what's wire.
+pub fn load_previous_output(
+    path: &Path,
+    fresh: &BlockStorage,
+    netlist: &Netlist,
+    nets: &BTreeSet<u32>,
+) -> Result<BlockStorage> {
+    let mut previous = read_rle_json_file(path)
+        .with_context(|| anyhow!("Failed to read earlier output {:?}", path))?;
+    ensure!(
+        previous.extents() == fresh.extents(),
+        "Earlier output {:?} is {:?} blocks but the design needs {:?}, was it routed with \
+         different --tiers or from a different placement?",
+        path,
+        previous.extents(),
+        fresh.extents()
+    );
+
+    let markers = clear_tier_markers(&mut previous, fresh)?;
+    if markers > 0 {
+        info!("Cleared {} blocks of tier markers", markers);
+    }
+
+    for net_idx in nets {
+        let net = netlist
+            .iter_nets()
+            .find(|(n, _)| **n == *net_idx as i64)
+            .map(|(_, net)| net)
+            .ok_or_else(|| anyhow!("Unknown net {}", net_idx))?;
+        let removed = remove_wires(&mut previous, fresh, &net_pins(netlist, net))
+            .with_context(|| anyhow!("Remove the wires of net {}", net_idx))?;
+        info!("Removed {} blocks of net {}", removed, net_idx);
+    }
+
+    Ok(previous)
+}
+
+/// Take the tier markers out of `previous`, putting air back in every position where it has
+/// stained glass and `fresh` has air, and return how many blocks changed. Markers are drawn after
+/// routing in the space the wires left, so the new routes are free to go through them, and they're
+/// drawn again afterwards.
+fn clear_tier_markers(previous: &mut BlockStorage, fresh: &BlockStorage) -> Result<usize> {
+    let air = Block::new("minecraft:air".to_owned());
+    let air_idx = previous.add_new_block_type(air.clone());
+    let glass: HashSet<_> = previous
+        .palette()
+        .iter()
+        .enumerate()
+        .filter(|(_, block)| block.name.ends_with("_stained_glass"))
+        .map(|(idx, _)| idx)
+        .collect();
+    let mut cleared = 0;
+    for ((x, y, z), block) in previous.iter_block_coords_mut() {
+        if glass.contains(&block.index())
+            && fresh.info_for_index(*fresh.get_block(x, y, z)?) == Some(&air)
+        {
+            *block = air_idx;
+            cleared += 1;
+        }
+    }
+
+    Ok(cleared)
+}
+
+/// Positions of every pin of `net`, where its wires start
+pub fn net_pins(netlist: &Netlist, net: &Net) -> Vec<Position> {
+    net.iter_sinks(netlist)
+        .chain(net.iter_drivers(netlist))
+        .chain(net.iter_alternate_drivers(netlist))
+        .map(|pin| Position::new(pin.x as i32, pin.y as i32, pin.z as i32))
+        .collect()
+}
+
+/// Find the blocks where `previous` differs from `fresh` and that are connected to one of the
+/// `pins`. Wires of different nets never touch, so these are exactly the wires of the net the pins
+/// belong to, along with its labels. Supports under the wires are left out, as they touch the wires
+/// of every net they're near.
+pub fn net_wires(
+    previous: &BlockStorage,
+    fresh: &BlockStorage,
+    pins: &[Position],
+) -> Result<HashSet<Position>> {
+    let differs = |pos: Position| -> Result<bool> {
+        let (x, y, z) = match (pos.x.try_into(), pos.y.try_into(), pos.z.try_into()) {
+            (Ok(x), Ok(y), Ok(z)) => (x, y, z),
+            _ => return Ok(false),
+        };
+        let (a, b) = match (previous.get_block(x, y, z), fresh.get_block(x, y, z)) {
+            (Ok(a), Ok(b)) => (*a, *b),
+            _ => return Ok(false),
+        };
+        let a = previous.info_for_index(a);
+        if a.is_some_and(|block| block.name == SUPPORT_BLOCK) {
+            return Ok(false);
+        }
+        Ok(a != fresh.info_for_index(b))
+    };
+
+    let mut seen: HashSet<Position> = HashSet::new();
+    let mut queue: VecDeque<Position> = VecDeque::new();
+    for pin in pins {
+        if differs(*pin)? && seen.insert(*pin) {
+            queue.push_back(*pin);
+        }
+    }
+    while let Some(pos) = queue.pop_front() {
+        for d in ALL_DIRECTIONS {
+            let neighbor = pos.offset(d);
+            if !seen.contains(&neighbor) && differs(neighbor)? {
+                seen.insert(neighbor);
+                queue.push_back(neighbor);
+            }
+        }
+    }
+
+    Ok(seen)
+}
+
+/// Put back the blocks of `fresh` everywhere `previous` differs from it and is connected to one
+/// of the `pins`, returning how many blocks changed. This takes out the wires of the net the pins
+/// belong to, see [`net_wires`].
+fn remove_wires(
+    previous: &mut BlockStorage,
+    fresh: &BlockStorage,
+    pins: &[Position],
+) -> Result<usize> {
+    let wires = net_wires(previous, fresh, pins)?;
+    for pos in wires.iter() {
+        let (x, y, z) = (pos.x as u32, pos.y as u32, pos.z as u32);
+        let block = fresh
+            .info_for_index(*fresh.get_block(x, y, z)?)
+            .ok_or_else(|| anyhow!("Block at {} isn't in the palette", pos))?
+            .clone();
+        let block = previous.add_new_block_type(block);
+        *previous.get_block_mut(x, y, z)? = block;
+        match fresh.block_entity(x, y, z) {
+            Some(nbt) => previous.set_block_entity(x, y, z, nbt.clone())?,
+            None => {
+                previous.remove_block_entity(x, y, z);
+            }
+        }
+    }
+
+    Ok(wires.len())
+}
+
+#[cfg(test)]
+mod test {
+    use mcpnr_common::block_storage::{Block, BlockStorage, Position};
+
+    use super::{clear_tier_markers, remove_wires};
+
+    #[test]
+    fn removes_only_the_wires_connected_to_the_pins() -> anyhow::Result<()> {
+        let mut fresh = BlockStorage::new(8, 3, 4);
+        let sign = fresh.add_new_block_type(Block::new("minecraft:oak_sign".to_owned()));
+        *fresh.get_block_mut(0, 1, 0)? = sign;
+        *fresh.get_block_mut(0, 1, 3)? = sign;
+
+        // Two nets' wires running east from their pins, which the escapes replaced
+        let mut previous = BlockStorage::new(8, 3, 4);
+        let calcite = previous.add_new_block_type(Block::new("minecraft:calcite".to_owned()));
+        let wire = previous.add_new_block_type(Block::new("minecraft:redstone_wire".to_owned()));
+        for z in [0, 3] {
+            for x in 0..6 {
+                *previous.get_block_mut(x, 0, z)? = calcite;
+                *previous.get_block_mut(x, 1, z)? = wire;
+            }
+        }
+
+        let removed = remove_wires(&mut previous, &fresh, &[Position::new(0, 1, 0)])?;
+        assert_eq!(removed, 12);
+        let name = |storage: &BlockStorage, x, y, z| -> anyhow::Result<String> {
+            let block = storage.get_block(x, y, z)?;
+            Ok(storage.info_for_index(*block).unwrap().name.clone())
+        };
+        assert_eq!(name(&previous, 0, 1, 0)?, "minecraft:oak_sign");
+        assert_eq!(name(&previous, 5, 1, 0)?, "minecraft:air");
+        assert_eq!(name(&previous, 5, 1, 3)?, "minecraft:redstone_wire");
+
+        Ok(())
+    }
+
+    #[test]
+    fn clears_only_markers_in_empty_space() -> anyhow::Result<()> {
+        // A cell with stained glass in it, which has to stay
+        let mut fresh = BlockStorage::new(4, 1, 1);
+        let red = Block::new("minecraft:red_stained_glass".to_owned());
+        let red_idx = fresh.add_new_block_type(red.clone());
+        *fresh.get_block_mut(0, 0, 0)? = red_idx;
+
+        let mut previous = BlockStorage::new(4, 1, 1);
+        let red_idx = previous.add_new_block_type(red);
+        let white =
+            previous.add_new_block_type(Block::new("minecraft:white_stained_glass".to_owned()));
+        *previous.get_block_mut(0, 0, 0)? = red_idx;
+        *previous.get_block_mut(1, 0, 0)? = red_idx;
+        *previous.get_block_mut(2, 0, 0)? = white;
+
+        assert_eq!(clear_tier_markers(&mut previous, &fresh)?, 2);
+        let names: Vec<_> = (0..4)
+            .map(|x| {
+                let block = previous.get_block(x, 0, 0)?;
+                Ok(previous.info_for_index(*block).unwrap().name.clone())
+            })
+            .collect::<anyhow::Result<_>>()?;
+        assert_eq!(
+            names,
+            [
+                "minecraft:red_stained_glass",
+                "minecraft:air",
+                "minecraft:air",
+                "minecraft:air"
+            ]
+        );
+
+        Ok(())
+    }
+}
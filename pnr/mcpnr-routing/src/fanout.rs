@@ -0,0 +1,242 @@
+//! Balanced distribution of high fan-out nets like clocks and resets.
+//!
+//! Nets with a skew bound are routed as an H-tree: the sinks are split in half along the longer
+//! side of their bounding box over and over, and the route visits a tap in the middle of each
+//! group before branching out to the halves. That keeps the wire from the driver to every sink
+//! roughly the same length. Whatever skew is left over is soaked up by turning up the delay of
+//! repeaters in front of the sinks that would otherwise see the signal early.
+
+use crate::detail_routing::{wire_segment::WIRE_GRID_SCALE, GridCellPosition};
+
+/// Number of blocks a redstone signal travels before it has to be refreshed by a repeater.
+pub const REDSTONE_SIGNAL_RANGE: u32 = 15;
+
+/// Longest delay a single repeater can be set to, in redstone ticks.
+pub const MAX_REPEATER_DELAY: u32 = 4;
+
+/// One step of routing a balanced net, in the order they should be routed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FanoutStep {
+    /// Route to a branching point in the middle of a group of sinks
+    Tap(GridCellPosition),
+    /// Route to the sink with this index
+    Sink(usize),
+}
+
+/// Order the sinks of a net as an H-tree, with a tap at the centre of each group of more than two
+/// sinks. Each tap comes before anything in its group, so the branches of the group can connect to
+/// it.
+pub fn h_tree(sinks: &[GridCellPosition]) -> Vec<FanoutStep> {
+    let mut steps = Vec::with_capacity(sinks.len() * 2);
+    let mut group: Vec<usize> = (0..sinks.len()).collect();
+    split_group(sinks, &mut group, &mut steps);
+    steps
+}
+
+fn split_group(sinks: &[GridCellPosition], group: &mut [usize], steps: &mut Vec<FanoutStep>) {
+    if group.len() <= 2 {
+        steps.extend(group.iter().map(|idx| FanoutStep::Sink(*idx)));
+        return;
+    }
+
+    let count = group.len() as i32;
+    let (sum_x, sum_y, sum_z) = group.iter().fold((0, 0, 0), |(x, y, z), idx| {
+        let sink = sinks[*idx];
+        (x + sink.x.0, y + sink.y, z + sink.z.0)
+    });
+    steps.push(FanoutStep::Tap(GridCellPosition::new(
+        (sum_x / count).into(),
+        sum_y / count,
+        (sum_z / count).into(),
+    )));
+
+    let span = |f: fn(&GridCellPosition) -> i32| {
+        let (min, max) = group.iter().fold((i32::MAX, i32::MIN), |(min, max), idx| {
+            let v = f(&sinks[*idx]);
+            (min.min(v), max.max(v))
+        });
+        max - min
+    };
+    if span(|p| p.x.0) >= span(|p| p.z.0) {
+        group.sort_by_key(|idx| (sinks[*idx].x, sinks[*idx].z, *idx));
+    } else {
+        group.sort_by_key(|idx| (sinks[*idx].z, sinks[*idx].x, *idx));
+    }
+
+    let (low, high) = group.split_at_mut(group.len() / 2);
+    split_group(sinks, low, steps);
+    split_group(sinks, high, steps);
+}
+
+/// Signal strength a driver pin puts on the first block of its wire, given its `sig_derating`.
+pub fn drive_strength(derating: u32) -> u32 {
+    REDSTONE_SIGNAL_RANGE.saturating_sub(derating)
+}
+
+/// Signal strength the last block of wire in front of a sink pin needs, given its `sig_derating`.
+/// A sink that isn't derated still needs some signal to see anything.
+pub fn required_strength(derating: u32) -> u32 {
+    derating.max(1)
+}
+
+/// Redstone ticks a signal spends in the repeaters along `cells` routing grid cells of wire, from
+/// a driver pin derated by `driver_derating` to a sink derated by `sink_derating`. The driver's
+/// signal fades by one every block, and each repeater brings it back to full strength, so a
+/// weak driver or a demanding sink needs its first repeater sooner.
+pub fn wire_delay(cells: u32, driver_derating: u32, sink_derating: u32) -> u32 {
+    let blocks = cells * WIRE_GRID_SCALE as u32;
+    // Blocks of wire beyond what the driver's signal covers while still strong enough for the sink
+    let shortfall = (blocks + required_strength(sink_derating))
+        .saturating_sub(drive_strength(driver_derating) + 1);
+    shortfall.div_ceil(REDSTONE_SIGNAL_RANGE)
+}
+
+/// Most routing grid cells of wire a driver pin derated by `driver_derating` can reach a sink
+/// derated by `sink_derating` over without a repeater.
+pub fn unrepeated_cells(driver_derating: u32, sink_derating: u32) -> u32 {
+    let blocks =
+        (drive_strength(driver_derating) + 1).saturating_sub(required_strength(sink_derating));
+    blocks / WIRE_GRID_SCALE as u32
+}
+
+/// How well the sinks of a balanced net line up, and the extra delay needed to bring them in to
+/// line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SkewReport {
+    pub net: u32,
+    /// Skew the net was asked to stay within, in redstone ticks
+    pub max_skew: u32,
+    /// Ticks from the driver to each sink, through the repeaters on the routed wire
+    pub arrival: Vec<u32>,
+    /// Extra ticks to delay each sink by
+    pub padding: Vec<u32>,
+}
+
+impl SkewReport {
+    /// Work out the padding that brings every sink within `max_skew` ticks of the slowest one.
+    pub fn new(net: u32, max_skew: u32, arrival: Vec<u32>) -> Self {
+        let target = arrival
+            .iter()
+            .max()
+            .map_or(0, |latest| latest.saturating_sub(max_skew));
+        let padding = arrival.iter().map(|a| target.saturating_sub(*a)).collect();
+        Self {
+            net,
+            max_skew,
+            arrival,
+            padding,
+        }
+    }
+
+    /// Skew of the routed wire, before any padding.
+    pub fn routed_skew(&self) -> u32 {
+        match (self.arrival.iter().min(), self.arrival.iter().max()) {
+            (Some(min), Some(max)) => max - min,
+            _ => 0,
+        }
+    }
+
+    /// Skew once the padding is in place.
+    pub fn padded_skew(&self) -> u32 {
+        let padded = || {
+            self.arrival
+                .iter()
+                .zip(self.padding.iter())
+                .map(|(a, p)| a + p)
+        };
+        match (padded().min(), padded().max()) {
+            (Some(min), Some(max)) => max - min,
+            _ => 0,
+        }
+    }
+
+    /// Number of repeaters to add in front of the sinks to provide the padding. Repeaters already
+    /// on the wire run at their shortest delay, so each added one can make up a full
+    /// [`MAX_REPEATER_DELAY`] ticks.
+    pub fn padding_repeaters(&self) -> u32 {
+        self.padding
+            .iter()
+            .map(|p| p.div_ceil(MAX_REPEATER_DELAY))
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{h_tree, unrepeated_cells, wire_delay, FanoutStep, SkewReport};
+    use crate::detail_routing::GridCellPosition;
+
+    fn pos(x: i32, z: i32) -> GridCellPosition {
+        GridCellPosition::new(x.into(), 3, z.into())
+    }
+
+    #[test]
+    fn h_tree_taps_the_middle_of_each_group() {
+        let sinks = [pos(0, 0), pos(20, 0), pos(0, 8), pos(20, 8)];
+        assert_eq!(
+            h_tree(&sinks),
+            vec![
+                FanoutStep::Tap(pos(10, 4)),
+                FanoutStep::Sink(0),
+                FanoutStep::Sink(2),
+                FanoutStep::Sink(1),
+                FanoutStep::Sink(3),
+            ]
+        );
+
+        let sinks: Vec<_> = (0..8).map(|x| pos(x * 4, 0)).collect();
+        let steps = h_tree(&sinks);
+        assert_eq!(steps[0], FanoutStep::Tap(pos(14, 0)));
+        assert_eq!(steps[1], FanoutStep::Tap(pos(6, 0)));
+        assert_eq!(
+            steps
+                .iter()
+                .filter(|s| matches!(s, FanoutStep::Sink(_)))
+                .count(),
+            8
+        );
+    }
+
+    #[test]
+    fn derating_brings_repeaters_closer() {
+        // A full strength driver reaches 15 blocks, then each repeater another 15
+        assert_eq!(wire_delay(15, 0, 0), 1);
+        assert_eq!(wire_delay(16, 0, 0), 2);
+
+        // Derating either end shortens the first stretch of wire
+        assert_eq!(wire_delay(6, 3, 0), 0);
+        assert_eq!(wire_delay(7, 3, 0), 1);
+        assert_eq!(wire_delay(6, 0, 4), 0);
+        assert_eq!(wire_delay(7, 0, 4), 1);
+        assert_eq!(wire_delay(22, 0, 4), 3);
+
+        // A driver too weak for the sink needs a repeater however short the wire
+        assert_eq!(wire_delay(1, 10, 8), 1);
+        assert_eq!(wire_delay(6, 10, 8), 1);
+        assert_eq!(wire_delay(7, 10, 8), 2);
+    }
+
+    #[test]
+    fn unrepeated_wires_stop_before_the_first_repeater() {
+        for (driver, sink) in [(0, 0), (3, 0), (0, 4), (6, 6)] {
+            let cells = unrepeated_cells(driver, sink);
+            assert_eq!(wire_delay(cells, driver, sink), 0);
+            assert_eq!(wire_delay(cells + 1, driver, sink), 1);
+        }
+        assert_eq!(unrepeated_cells(0, 0), 7);
+        // Nothing is short enough for a driver too weak for the sink
+        assert_eq!(unrepeated_cells(10, 8), 0);
+    }
+
+    #[test]
+    fn padding_bounds_the_skew() {
+        assert_eq!(wire_delay(7, 0, 0), 0);
+        assert_eq!(wire_delay(8, 0, 0), 1);
+
+        let report = SkewReport::new(3, 1, vec![5, 0, 4]);
+        assert_eq!(report.routed_skew(), 5);
+        assert_eq!(report.padding, vec![0, 4, 0]);
+        assert_eq!(report.padded_skew(), 1);
+        assert_eq!(report.padding_repeaters(), 1);
+    }
+}
@@ -0,0 +1,105 @@
+//! Net weights for the next placement, from how routing went. See
+//! [`mcpnr_common::net_weights`].
+
+use std::collections::HashSet;
+
+use mcpnr_common::net_weights::NetWeights;
+
+use crate::{DeferralReason, RoutingReport};
+
+/// Weight of a net the router couldn't route
+const UNROUTED_WEIGHT: f32 = 4.0;
+
+/// Weight of a net whose searches fought other nets for space: they expanded so many cells the
+/// net was put off to a later pass, or they had to widen to find a route at all
+const CONGESTED_WEIGHT: f32 = 1.5;
+
+/// Weights for the nets `report` shows trouble with. A net both unrouted and congested gets both
+/// weights multiplied together. Nets left unrouted because they have several drivers or none
+/// aren't counted, no placement is going to route those.
+pub fn net_feedback(report: &RoutingReport) -> NetWeights {
+    let unroutable: HashSet<u32> = report
+        .multi_driver_nets
+        .iter()
+        .map(|multi_driver| multi_driver.net)
+        .chain(report.undriven_nets.iter().copied())
+        .collect();
+    let unrouted: Vec<u32> = report
+        .unrouted_nets
+        .iter()
+        .copied()
+        .filter(|net| !unroutable.contains(net))
+        .collect();
+
+    let mut weights = NetWeights {
+        unrouted: unrouted.len(),
+        ..Default::default()
+    };
+    let mut scale = |net: u32, weight: f32| {
+        *weights.nets.entry(net as i64).or_insert(1.0) *= weight;
+    };
+
+    for net in unrouted {
+        scale(net, UNROUTED_WEIGHT);
+    }
+    let mut congested: Vec<u32> = report
+        .deferrals
+        .iter()
+        .filter(|deferral| deferral.reason == DeferralReason::NetExpansions)
+        .map(|deferral| deferral.net)
+        .chain(report.widened_searches.iter().map(|widened| widened.net))
+        .collect();
+    congested.sort_unstable();
+    congested.dedup();
+    for net in congested {
+        scale(net, CONGESTED_WEIGHT);
+    }
+
+    weights
+}
+
+#[cfg(test)]
+mod test {
+    use super::net_feedback;
+    use crate::timing::TimingReport;
+    use crate::{Deferral, DeferralReason, MultiDriverNet, RoutingReport};
+
+    #[test]
+    fn troubled_nets_weigh_more() {
+        let deferral = |net, reason| Deferral {
+            net,
+            pass: 0,
+            reason,
+        };
+        let report = RoutingReport {
+            // 13 has two drivers and 17 none, moving cells around won't help either
+            unrouted_nets: vec![3, 7, 13, 17],
+            deferrals: vec![
+                deferral(7, DeferralReason::NetExpansions),
+                deferral(7, DeferralReason::NetExpansions),
+                deferral(9, DeferralReason::NetExpansions),
+                // Running out of time says nothing about the net
+                deferral(11, DeferralReason::PassTime),
+            ],
+            multi_driver_nets: vec![MultiDriverNet {
+                net: 13,
+                driver_cells: vec![0, 1],
+            }],
+            undriven_nets: vec![17],
+            skew: Vec::new(),
+            widened_searches: Vec::new(),
+            timing: TimingReport::default(),
+            wire_blocks: 0,
+        };
+
+        let weights = net_feedback(&report);
+        assert_eq!(weights.unrouted, 2);
+        assert_eq!(weights.weight(3), 4.0);
+        assert_eq!(weights.weight(7), 6.0);
+        assert_eq!(weights.weight(9), 1.5);
+        assert_eq!(weights.weight(11), 1.0);
+        assert_eq!(weights.weight(13), 1.0);
+        assert_eq!(weights.weight(17), 1.0);
+        assert_eq!(weights.nets.len(), 3);
+    }
+}
@@ -0,0 +1,256 @@
+//! Golden image regression tests for splatting. Each standard cell of the techlib, each
+//! combination of layers and directions a wire segment can take, and the escapes from pins in each
+//! direction are splatted in to a small [`BlockStorage`], and the blocks that come out are compared
+//! against the snapshots checked in under `testdata/golden`.
+//!
+//! After an intended change to the output, update the snapshots with
+//!
+//! ```sh
+//! MCPNR_BLESS=1 cargo test -p mcpnr-routing golden
+//! ```
+//!
+//! and review the diff before committing it.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use mcpnr_common::block_storage::{Block, BlockStorage, Direction, Position};
+use mcpnr_common::blockers::BlockerRules;
+use mcpnr_common::minecraft_types::is_structure_file;
+use mcpnr_common::protos::mcpnr::{self, Parameter, PlacedDesign};
+use mcpnr_common::protos::mcpnr::{parameter::Value as ParameterValue, placed_design::Cell};
+use mcpnr_common::schematic::block_state;
+use serde_json::{json, Map, Value};
+
+use crate::detail_routing::wire_segment::{
+    splat_pin_escape, splat_wire_segment, LayerPosition, WireTierLayer, WIRE_BASE_BLOCK,
+};
+use crate::detail_routing::Layer;
+use crate::splat::Splatter;
+use crate::structure_cache::StructureCache;
+
+/// Environment variable that makes the tests write the snapshots instead of checking them
+const BLESS_VAR: &str = "MCPNR_BLESS";
+
+/// Big enough for any standard cell, and the I/O macros with [`IO_MACRO_BITS`] bits
+const CELL_EXTENTS: [u32; 3] = [8, 16, 8];
+const IO_MACRO_BITS: i64 = 3;
+
+/// Room for a wire segment to go a few blocks in every direction from [`WIRE_START`] and climb
+/// through every layer of the first tier
+const WIRE_EXTENTS: [u32; 3] = [16, 24, 16];
+const WIRE_START: (i32, i32) = (4, 4);
+
+/// Pin signs to escape from, at both ends of the wire grid cell (2, 2) along z, and at the far end
+/// along x, which is off the grid for wires running north-south
+const PIN_EXTENTS: [u32; 3] = [10, 2, 10];
+const PIN_POSITIONS: [(i32, i32); 3] = [(4, 4), (4, 5), (5, 4)];
+
+const DIRECTIONS: [Direction; 4] = [
+    Direction::North,
+    Direction::South,
+    Direction::East,
+    Direction::West,
+];
+
+/// Layer pairs to splat wire segments between. Same layer wires only differ in height, and vias
+/// between metal layers all take the same path, so one of each is enough.
+const LAYER_PAIRS: [(Layer, Layer); 6] = [
+    (Layer::LI, Layer::LI),
+    (Layer::M1, Layer::M1),
+    (Layer::LI, Layer::M0),
+    (Layer::M0, Layer::LI),
+    (Layer::M0, Layer::M1),
+    (Layer::M1, Layer::M0),
+];
+
+fn techlib_structures() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../../yosys-synth_mc/techlib/structures")
+}
+
+/// Every block other than air in `o` as `"x y z state"`, followed by the block entities as
+/// `"x y z nbt"`, both in storage order
+fn snapshot(o: &BlockStorage) -> Result<Value> {
+    let [sx, sy, sz] = *o.extents();
+    let air = Block::new("minecraft:air".to_owned());
+    let mut blocks = Vec::new();
+    for y in 0..sy {
+        for z in 0..sz {
+            for x in 0..sx {
+                let block = o
+                    .info_for_index(*o.get_block(x, y, z)?)
+                    .ok_or_else(|| anyhow!("Block at {} {} {} is not in the palette", x, y, z))?;
+                if *block != air {
+                    blocks.push(format!("{} {} {} {}", x, y, z, block_state(block)));
+                }
+            }
+        }
+    }
+    blocks.extend(
+        o.iter_block_entities()
+            .map(|([x, y, z], nbt)| format!("{} {} {} {}", x, y, z, nbt)),
+    );
+
+    Ok(json!(blocks))
+}
+
+/// Compare `actual` against the snapshot `name`, or overwrite the snapshot in bless mode
+fn check_golden(name: &str, actual: Map<String, Value>) -> Result<()> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("testdata/golden")
+        .join(format!("{}.json", name));
+
+    if std::env::var_os(BLESS_VAR).is_some() {
+        let text = serde_json::to_string_pretty(&actual)? + "\n";
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        return std::fs::write(&path, text).with_context(|| anyhow!("Write {:?}", path));
+    }
+
+    let expected = std::fs::read_to_string(&path)
+        .with_context(|| anyhow!("Read {:?}, rerun with {}=1 to create it", path, BLESS_VAR))?;
+    let expected: Map<String, Value> =
+        serde_json::from_str(&expected).with_context(|| anyhow!("Parse {:?}", path))?;
+
+    let mut keys: Vec<_> = expected.keys().chain(actual.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    let mismatched: Vec<_> = keys
+        .into_iter()
+        .filter(|key| expected.get(*key) != actual.get(*key))
+        .collect();
+    if !mismatched.is_empty() {
+        bail!(
+            "Splat output no longer matches {:?} for {:?}. If that's intended, rerun with {}=1 to \
+             update the snapshot",
+            path,
+            mismatched,
+            BLESS_VAR
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn standard_cells_match_golden() -> Result<()> {
+    let mut types = Vec::new();
+    for entry in std::fs::read_dir(techlib_structures())? {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        if is_structure_file(&name) {
+            types.push(name);
+        }
+    }
+    types.sort();
+
+    let cell = |ty: &str, parameter: Option<&str>| Cell {
+        r#type: ty.to_owned(),
+        pos: Some(mcpnr::Position { x: 0, y: 0, z: 0 }),
+        parameter: parameter
+            .map(|name| {
+                (
+                    name.to_owned(),
+                    Parameter {
+                        value: Some(ParameterValue::Int(IO_MACRO_BITS)),
+                    },
+                )
+            })
+            .into_iter()
+            .collect(),
+        ..Default::default()
+    };
+    let design = PlacedDesign {
+        cells: types
+            .iter()
+            .map(|ty| cell(ty, None))
+            .chain([
+                cell("MCPNR_LIGHTS", Some("NLIGHT")),
+                cell("MCPNR_SWITCHES", Some("NSWITCH")),
+            ])
+            .collect(),
+        ..Default::default()
+    };
+    let mut structure_cache =
+        StructureCache::new(&techlib_structures(), &design, &BlockerRules::builtin())?;
+
+    let mut snapshots = Map::new();
+    for cell in design.cells.iter() {
+        let [sx, sy, sz] = CELL_EXTENTS;
+        let mut o = BlockStorage::new(sx, sy, sz);
+        structure_cache.build_palette_maps(&mut o)?;
+        let splatter = Splatter::new(&mut o, &structure_cache);
+        splatter.splat_cell(cell, &mut o)?;
+        snapshots.insert(cell.r#type.clone(), snapshot(&o)?);
+    }
+
+    check_golden("cells", snapshots)
+}
+
+#[test]
+fn wire_segments_match_golden() -> Result<()> {
+    let base = Block::new(WIRE_BASE_BLOCK.to_owned());
+    let start = LayerPosition::new(WIRE_START.0.into(), WIRE_START.1.into());
+
+    let mut snapshots = Map::new();
+    for (input_layer, output_layer) in LAYER_PAIRS {
+        for input_direction in DIRECTIONS {
+            for output_direction in DIRECTIONS {
+                let [sx, sy, sz] = WIRE_EXTENTS;
+                let mut o = BlockStorage::new(sx, sy, sz);
+                let result = splat_wire_segment(
+                    &mut o,
+                    start,
+                    (WireTierLayer::new(0, input_layer), input_direction),
+                    (WireTierLayer::new(0, output_layer), output_direction),
+                    &base,
+                );
+
+                // Unsupported combinations are part of the snapshot too, so supporting a new one
+                // or dropping an old one shows up
+                let value = match result {
+                    Ok((end, layer)) => json!({
+                        "end": format!("{} {} {:?}", end.x.0, end.y.0, layer),
+                        "blocks": snapshot(&o)?,
+                    }),
+                    Err(e) => json!({ "error": e.to_string() }),
+                };
+                snapshots.insert(
+                    format!(
+                        "{:?} {:?} -> {:?} {:?}",
+                        input_layer, input_direction, output_layer, output_direction
+                    ),
+                    value,
+                );
+            }
+        }
+    }
+
+    check_golden("wire_segments", snapshots)
+}
+
+#[test]
+fn pin_escapes_match_golden() -> Result<()> {
+    let base = Block::new(WIRE_BASE_BLOCK.to_owned());
+
+    let mut snapshots = Map::new();
+    for (x, z) in PIN_POSITIONS {
+        for direction in DIRECTIONS {
+            let [sx, sy, sz] = PIN_EXTENTS;
+            let mut o = BlockStorage::new(sx, sy, sz);
+            let sign = o.add_new_block_type(Block::new("minecraft:oak_sign".to_owned()));
+            let (ux, uz) = (x as u32, z as u32);
+            *o.get_block_mut(ux, 1, uz)? = sign;
+            o.set_block_entity(ux, 1, uz, json!({ "id": "minecraft:sign" }))?;
+
+            let value = match splat_pin_escape(&mut o, Position::new(x, 1, z), direction, &base) {
+                Ok(access) => json!({
+                    "access": format!("{} {}", access.x.0, access.y.0),
+                    "blocks": snapshot(&o)?,
+                }),
+                Err(e) => json!({ "error": e.to_string() }),
+            };
+            snapshots.insert(format!("{} {} {:?}", x, z, direction), value);
+        }
+    }
+
+    check_golden("pin_escapes", snapshots)
+}
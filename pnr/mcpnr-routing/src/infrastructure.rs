@@ -0,0 +1,172 @@
+//! Blocks a design needs to be looked after in-game that aren't part of its logic: a grid of
+//! lights, so mobs don't spawn between the cells, and corridors to walk along Z through every
+//! tier. They're splatted after the cells and before routing, and the router keeps wires out of
+//! them whether it takes its blockages from the splatted blocks or from a blockage map.
+//!
+//! Lights sit at the top of the LI layer, the ceiling over the cells, and only go in routing grid
+//! columns where that layer is empty so they never land inside a cell. Corridors are two blocks
+//! of headroom on the floor of each tier, with a floor put under them on every tier but the
+//! first. Cells are never moved out of the way, the placer doesn't know about corridors, so any
+//! blocks of cells left in a corridor are only counted.
+
+use anyhow::{ensure, Result};
+use mcpnr_common::block_storage::{Block, BlockStorage, Position};
+use mcpnr_common::BLOCKS_PER_TIER;
+
+use crate::config::InfrastructureConfig;
+use crate::detail_routing::wire_segment::WIRE_GRID_SCALE;
+
+/// Height of the LI layer, which the cells sit in
+const CELL_LAYER_HEIGHT: u32 = 4;
+
+/// Blocks of headroom in a corridor
+const CORRIDOR_HEIGHT: u32 = 2;
+
+/// Block corridors on upper tiers are floored with
+const CORRIDOR_FLOOR_BLOCK: &str = "minecraft:smooth_stone";
+
+/// What [`splat_infrastructure`] added
+#[derive(Clone, Debug, Default)]
+pub struct Infrastructure {
+    /// Number of lights placed
+    pub lights: usize,
+    /// Number of corridors, counting each tier separately
+    pub corridors: usize,
+    /// Blocks of cells the corridors run through
+    pub obstructed: usize,
+    /// Positions wires must not be routed through
+    pub blocked: Vec<Position>,
+}
+
+/// Add the lights and corridors `config` asks for to `o`, which holds the splatted cells
+pub fn splat_infrastructure(
+    config: &InfrastructureConfig,
+    o: &mut BlockStorage,
+) -> Result<Infrastructure> {
+    let mut infrastructure = Infrastructure::default();
+    let [size_x, size_y, size_z] = *o.extents();
+    let tiers = size_y / BLOCKS_PER_TIER;
+    let b_air = o.add_new_block_type(Block::new("minecraft:air".into()));
+    let scale = WIRE_GRID_SCALE as u32;
+
+    if let Some(spacing) = config.corridor_spacing {
+        ensure!(
+            config.corridor_width > 0 && spacing > config.corridor_width,
+            "Corridors {} blocks wide can't be {} blocks apart",
+            config.corridor_width,
+            spacing
+        );
+        let b_floor = o.add_new_block_type(Block::new(CORRIDOR_FLOOR_BLOCK.into()));
+        // Whole routing grid columns, so wires can't squeeze along the edge of a corridor
+        let width = config.corridor_width.div_ceil(scale) * scale;
+        for start_x in (spacing..size_x).step_by(spacing as usize) {
+            let start_x = start_x - start_x % scale;
+            let end_x = std::cmp::min(start_x + width, size_x);
+            for tier in 0..tiers {
+                let floor_y = tier * BLOCKS_PER_TIER;
+                for x in start_x..end_x {
+                    for z in 0..size_z {
+                        for y in floor_y..floor_y + CORRIDOR_HEIGHT {
+                            if *o.get_block(x, y, z)? != b_air {
+                                infrastructure.obstructed += 1;
+                            }
+                            infrastructure.blocked.push(position(x, y, z));
+                        }
+                        if tier > 0 {
+                            let block = o.get_block_mut(x, floor_y - 1, z)?;
+                            if *block == b_air {
+                                *block = b_floor;
+                            }
+                            infrastructure.blocked.push(position(x, floor_y - 1, z));
+                        }
+                    }
+                }
+                infrastructure.corridors += 1;
+            }
+        }
+    }
+
+    if let Some(spacing) = config.light_spacing {
+        ensure!(spacing > 0, "Lights can't be 0 blocks apart");
+        let b_light = o.add_new_block_type(Block::new(config.light_block.clone()));
+        let columns = |size: u32| {
+            (spacing / 2..size)
+                .step_by(spacing as usize)
+                .map(move |v| v - v % scale)
+                .filter(move |v| v + scale <= size)
+        };
+        for tier in 0..tiers {
+            let floor_y = tier * BLOCKS_PER_TIER;
+            for x in columns(size_x) {
+                for z in columns(size_z) {
+                    let mut empty = true;
+                    for dx in 0..scale {
+                        for y in floor_y..floor_y + CELL_LAYER_HEIGHT {
+                            for dz in 0..scale {
+                                empty &= *o.get_block(x + dx, y, z + dz)? == b_air;
+                            }
+                        }
+                    }
+                    let y = floor_y + CELL_LAYER_HEIGHT - 1;
+                    if empty {
+                        *o.get_block_mut(x, y, z)? = b_light;
+                        infrastructure.blocked.push(position(x, y, z));
+                        infrastructure.lights += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(infrastructure)
+}
+
+fn position(x: u32, y: u32, z: u32) -> Position {
+    Position::new(x as i32, y as i32, z as i32)
+}
+
+#[cfg(test)]
+mod test {
+    use mcpnr_common::block_storage::{Block, BlockStorage, Position};
+
+    use super::splat_infrastructure;
+    use crate::config::InfrastructureConfig;
+
+    #[test]
+    fn lights_skip_cells_and_corridors_block_wires() -> anyhow::Result<()> {
+        let mut o = BlockStorage::new(16, 32, 16);
+        let cell = o.add_new_block_type(Block::new("minecraft:calcite".to_owned()));
+        // A cell under the light that would go at (4, 3, 4) on the first tier, and one in the
+        // corridor on the second
+        *o.get_block_mut(5, 1, 5)? = cell;
+        *o.get_block_mut(8, 17, 3)? = cell;
+
+        let infrastructure = splat_infrastructure(
+            &InfrastructureConfig {
+                light_spacing: Some(8),
+                light_block: "minecraft:glowstone".to_owned(),
+                corridor_spacing: Some(8),
+                corridor_width: 1,
+            },
+            &mut o,
+        )?;
+
+        let name = |o: &BlockStorage, x, y, z| -> anyhow::Result<String> {
+            let block = o.get_block(x, y, z)?;
+            Ok(o.info_for_index(*block).unwrap().name.clone())
+        };
+        assert_eq!(infrastructure.lights, 7);
+        assert_eq!(name(&o, 4, 3, 4)?, "minecraft:air");
+        assert_eq!(name(&o, 12, 3, 4)?, "minecraft:glowstone");
+        assert_eq!(name(&o, 4, 19, 4)?, "minecraft:glowstone");
+
+        // Rounded up to a whole grid column, with a floor on the second tier
+        assert_eq!(infrastructure.corridors, 2);
+        assert_eq!(infrastructure.obstructed, 1);
+        assert_eq!(name(&o, 9, 15, 0)?, "minecraft:smooth_stone");
+        assert!(infrastructure.blocked.contains(&Position::new(9, 1, 15)));
+        assert!(!infrastructure.blocked.contains(&Position::new(10, 1, 15)));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,380 @@
+//! `route --interactive`, a small REPL over the router for working out what's wrong with one net
+//! without waiting for every pass over the whole design. Nets can be routed and ripped up one at a
+//! time, the routing grid looked at as text, and the output saved at any point. Leaving the REPL
+//! carries on with the rest of the flow, so the output file is still written at the end.
+
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use mcpnr_common::block_storage::{BlockStorage, Direction};
+
+use crate::config::Config;
+use crate::detail_routing::wire_segment::WireCoord;
+use crate::detail_routing::{
+    DetailRouter, GridCell, GridCellPosition, Layer, ALL_LAYERS, LAYERS_PER_TIER,
+};
+use crate::structure_cache::StructureCache;
+use crate::{eco, write_structure, NetState, RouteId, Router};
+
+const HELP: &str = "\
+nets                        list every net and whether it's routed
+route NET                   route one net, by name or number
+rip NET                     rip up one net
+dump X Z WIDTH DEPTH [LAYER] [TIER]
+                            show part of the routing grid, in grid cells
+save FILE                   write the output as it is now
+run                         route everything left, then carry on with the flow
+quit                        carry on with the flow without routing anything else
+help                        show this";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Nets,
+    Route(String),
+    RipUp(String),
+    Dump {
+        x: i32,
+        z: i32,
+        size_x: u32,
+        size_z: u32,
+        layer: Layer,
+        tier: u32,
+    },
+    Save(PathBuf),
+    Run,
+    Quit,
+    Help,
+}
+
+impl Command {
+    /// Parse a line typed at the prompt, or `None` if it's blank
+    pub fn parse(line: &str) -> Result<Option<Self>> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return Ok(None),
+        };
+        let arg = |idx: usize, name: &str| -> Result<&str> {
+            args.get(idx)
+                .copied()
+                .ok_or_else(|| anyhow!("`{}` needs {}", command, name))
+        };
+        let number = |idx: usize, name: &str| -> Result<i64> {
+            arg(idx, name)?
+                .parse()
+                .with_context(|| anyhow!("Parse {}", name))
+        };
+
+        let command = match command {
+            "nets" => Self::Nets,
+            "route" => Self::Route(arg(0, "a net")?.to_owned()),
+            "rip" => Self::RipUp(arg(0, "a net")?.to_owned()),
+            "dump" => Self::Dump {
+                x: number(0, "X")?.try_into()?,
+                z: number(1, "Z")?.try_into()?,
+                size_x: number(2, "WIDTH")?.try_into()?,
+                size_z: number(3, "DEPTH")?.try_into()?,
+                layer: match args.get(4) {
+                    Some(name) => ALL_LAYERS
+                        .into_iter()
+                        .find(|layer| format!("{:?}", layer).eq_ignore_ascii_case(name))
+                        .ok_or_else(|| anyhow!("No layer {:?}, try LI or M0 to M3", name))?,
+                    None => Layer::LI,
+                },
+                tier: match args.get(5) {
+                    Some(_) => number(5, "TIER")?.try_into()?,
+                    None => 0,
+                },
+            },
+            "save" => Self::Save(arg(0, "a file")?.into()),
+            "run" => Self::Run,
+            "quit" | "exit" => Self::Quit,
+            "help" | "?" => Self::Help,
+            _ => bail!(
+                "Unknown command {:?}, type `help` for the commands",
+                command
+            ),
+        };
+        Ok(Some(command))
+    }
+}
+
+/// Part of the routing grid as text, a row per Z. Free cells are `.`, blocked ones `#`, and cells
+/// of a route point towards the driver, with `u` and `d` for up and down. Cells outside the grid
+/// are left blank. The nets in the window are listed underneath.
+pub fn dump_grid(
+    router: &DetailRouter,
+    x: i32,
+    z: i32,
+    size_x: u32,
+    size_z: u32,
+    y: i32,
+) -> String {
+    let mut text = String::new();
+    let mut nets = std::collections::BTreeSet::new();
+    for dz in 0..size_z as i32 {
+        text.push_str(&format!("{:>5} ", z + dz));
+        for dx in 0..size_x as i32 {
+            let pos = GridCellPosition::new(WireCoord(x + dx), y, WireCoord(z + dz));
+            text.push(match router.get_cell(pos) {
+                Ok(GridCell::Free) => '.',
+                Ok(GridCell::Blocked) => '#',
+                Ok(GridCell::Occupied(direction, RouteId(net))) => {
+                    nets.insert(*net);
+                    match direction {
+                        Direction::North => '^',
+                        Direction::South => 'v',
+                        Direction::East => '>',
+                        Direction::West => '<',
+                        Direction::Up => 'u',
+                        Direction::Down => 'd',
+                    }
+                }
+                Err(_) => ' ',
+            });
+        }
+        text.push('\n');
+    }
+    let nets: Vec<String> = nets.iter().map(|net| net.to_string()).collect();
+    text.push_str(&format!("Nets: {}", nets.join(", ")));
+    text
+}
+
+/// Read commands from `input` until the user quits or it runs out, writing the results to
+/// `out`. Commands that fail only print why.
+pub(crate) fn run_repl(
+    router: &mut Router,
+    config: &Config,
+    structure_cache: &StructureCache,
+    output: &mut BlockStorage,
+    mut input: impl BufRead,
+    mut out: impl Write,
+) -> Result<()> {
+    writeln!(out, "Interactive routing, type `help` for the commands")?;
+    loop {
+        write!(out, "> ")?;
+        out.flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let command = match Command::parse(&line) {
+            Ok(Some(command)) => command,
+            Ok(None) => continue,
+            Err(e) => {
+                writeln!(out, "{:#}", e)?;
+                continue;
+            }
+        };
+        match command {
+            Command::Quit => return Ok(()),
+            Command::Run => return router.rnr_loop(output),
+            command => {
+                if let Err(e) = execute(router, config, structure_cache, output, command, &mut out)
+                {
+                    writeln!(out, "{:#}", e)?;
+                }
+            }
+        }
+    }
+}
+
+fn execute(
+    router: &mut Router,
+    config: &Config,
+    structure_cache: &StructureCache,
+    output: &mut BlockStorage,
+    command: Command,
+    out: &mut impl Write,
+) -> Result<()> {
+    match command {
+        Command::Nets => {
+            let mut nets: Vec<(&u32, &NetState)> = router
+                .net_states
+                .iter()
+                .map(|(net_idx, (state, _))| (net_idx, state))
+                .collect();
+            nets.sort_by_key(|(net_idx, _)| **net_idx);
+            for (net_idx, state) in nets {
+                let state = match state {
+                    NetState::Unrouted => "unrouted",
+                    NetState::RippedUpInPass(_) => "ripped up",
+                    NetState::Routed => "routed",
+                    NetState::Kept => "kept",
+                };
+                let name = router.netlist.net_name(*net_idx as i64).unwrap_or_default();
+                writeln!(out, "{:>6} {:<10} {}", net_idx, state, name)?;
+            }
+        }
+        Command::Route(net) => {
+            let net_idx = resolve_net(router, &net)?;
+            match router.net_states[&net_idx].0 {
+                NetState::Routed | NetState::Kept => {
+                    bail!("Net {} is already routed, rip it up first", net_idx)
+                }
+                _ => {}
+            }
+            if let Some(state) = router.net_states.get_mut(&net_idx) {
+                state.0 = NetState::Unrouted;
+            }
+            let guide = router.bus_guide(net_idx)?;
+            router.detail_router.set_guide(guide);
+            let result = router.route_net(net_idx);
+            router.detail_router.set_guide(Default::default());
+            let stats = router.detail_router.take_search_stats();
+            router.record_widening(net_idx, &stats);
+            result.with_context(|| anyhow!("Route net {}", net_idx))?;
+            let routed = router.net_states[&net_idx].0 == NetState::Routed;
+            writeln!(
+                out,
+                "Net {} {} after expanding {} nodes",
+                net_idx,
+                if routed { "routed" } else { "not fully routed" },
+                stats.expanded
+            )?;
+        }
+        Command::RipUp(net) => {
+            let net_idx = resolve_net(router, &net)?;
+            let (state, net) = router.net_states[&net_idx];
+            ensure!(
+                state != NetState::Kept,
+                "Net {} is kept from the earlier route",
+                net_idx
+            );
+            router.rip_up_net(net_idx, net, output)?;
+            if let Some(state) = router.net_states.get_mut(&net_idx) {
+                state.0 = NetState::Unrouted;
+            }
+            writeln!(out, "Ripped up net {}", net_idx)?;
+        }
+        Command::Dump {
+            x,
+            z,
+            size_x,
+            size_z,
+            layer,
+            tier,
+        } => {
+            let y = (tier * LAYERS_PER_TIER) as i32 + layer.to_compact_idx();
+            writeln!(
+                out,
+                "{}",
+                dump_grid(&router.detail_router, x, z, size_x, size_z, y)
+            )?;
+        }
+        Command::Save(path) => {
+            // Splat the routes so far, and take them back out again once they're written
+            let mut nets: Vec<_> = router
+                .net_states
+                .iter()
+                .filter(|(_, (state, _))| *state != NetState::Kept)
+                .map(|(net_idx, (_, net))| (*net_idx, *net))
+                .collect();
+            nets.sort_by_key(|(net_idx, _)| *net_idx);
+            let mut splatted = Vec::new();
+            for (net_idx, net) in nets {
+                router.splat_net(config, net_idx, net, output)?;
+                splatted.push(net_idx);
+            }
+            // Written beside the file first, so a save that fails part way through doesn't leave
+            // an earlier save cut short
+            let partial = partial_path(&path);
+            let result = write_structure(&partial, structure_cache, output);
+            for net_idx in splatted.into_iter().rev() {
+                router.splats.remove(RouteId(net_idx), output)?;
+            }
+            if let Err(e) = result {
+                let _ = std::fs::remove_file(&partial);
+                return Err(e);
+            }
+            std::fs::rename(&partial, &path)
+                .with_context(|| anyhow!("Move {:?} to {:?}", partial, path))?;
+            writeln!(out, "Saved to {:?}", path)?;
+        }
+        Command::Run | Command::Quit => {}
+        Command::Help => writeln!(out, "{}", HELP)?,
+    }
+
+    Ok(())
+}
+
+/// Where a save to `path` is written before it's moved in to place. It's `path` with `.partial`
+/// before the extension, so it's written in the same format.
+fn partial_path(path: &Path) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_owned();
+    name.push(".partial");
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
+
+fn resolve_net(router: &Router, net: &str) -> Result<u32> {
+    let nets = eco::resolve_nets(router.netlist, &[net.to_owned()])?;
+    nets.into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("The design has no net {:?}", net))
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use mcpnr_common::block_storage::Direction;
+
+    use super::{dump_grid, partial_path, Command};
+    use crate::detail_routing::wire_segment::WireCoord;
+    use crate::detail_routing::{DetailRouter, GridCell, GridCellPosition, Layer};
+    use crate::RouteId;
+
+    #[test]
+    fn parses_commands() -> anyhow::Result<()> {
+        assert_eq!(Command::parse("  \n")?, None);
+        assert_eq!(
+            Command::parse("route carry[3]\n")?,
+            Some(Command::Route("carry[3]".to_owned()))
+        );
+        assert_eq!(
+            Command::parse("dump 4 0 8 2 m1 1")?,
+            Some(Command::Dump {
+                x: 4,
+                z: 0,
+                size_x: 8,
+                size_z: 2,
+                layer: Layer::M1,
+                tier: 1,
+            })
+        );
+        assert!(Command::parse("rip").is_err());
+        assert!(Command::parse("dump 0 0 1 1 M9").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn dumps_cells_as_text() -> anyhow::Result<()> {
+        let mut router = DetailRouter::new(3, 5, 2);
+        *router.get_cell_mut(GridCellPosition::new(WireCoord(0), 0, WireCoord(0)))? =
+            GridCell::Blocked;
+        *router.get_cell_mut(GridCellPosition::new(WireCoord(1), 0, WireCoord(1)))? =
+            GridCell::Occupied(Direction::East, RouteId(7));
+
+        assert_eq!(
+            dump_grid(&router, 0, 0, 4, 2, 0),
+            "    0 #.. \n    1 .>. \nNets: 7"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn partial_saves_sit_next_to_the_output() {
+        assert_eq!(
+            partial_path(Path::new("out/world.nbt")),
+            Path::new("out/world.partial.nbt")
+        );
+        assert_eq!(partial_path(Path::new("world")), Path::new("world.partial"));
+    }
+}
@@ -0,0 +1,97 @@
+//! Signs with the net name next to routed wires, so the wires can be told apart in-game without a
+//! separate report.
+//!
+//! Same-layer wires never use the south-east corner of their routing grid cell, so that's where the
+//! signs go. They're only put on straight stretches of wire, where nothing else in the cell needs
+//! the space.
+
+use anyhow::{Context, Result};
+use mcpnr_common::block_storage::{Block, BlockStorage, Direction, PropertyValue};
+use serde_json::json;
+
+use crate::detail_routing::{GridCellPosition, Layer, LAYERS_PER_TIER};
+
+/// Characters that fit on a line of a sign
+const SIGN_LINE_LENGTH: usize = 15;
+
+/// Block entity for a sign showing `name`, wrapped over as many lines as it needs.
+pub fn sign_text(name: &str) -> serde_json::Value {
+    let chars: Vec<char> = name.chars().collect();
+    let mut lines = chars
+        .chunks(SIGN_LINE_LENGTH)
+        .map(|line| line.iter().collect::<String>());
+    let mut nbt = json!({ "id": "minecraft:sign" });
+    for tag in ["Text1", "Text2", "Text3", "Text4"] {
+        let line = lines.next().unwrap_or_default();
+        nbt[tag] = json!(json!({ "text": line }).to_string());
+    }
+    nbt
+}
+
+/// Cells along a route to put a label next to, one every `interval` cells. `path` is the route
+/// from a sink back towards its driver, as given by
+/// [`crate::detail_routing::DetailRouter::trace_route`]. `since_label` carries the number of
+/// cells since the last label over from earlier calls, so labels stay evenly spaced where routes
+/// join up.
+pub fn label_cells(
+    path: &[(GridCellPosition, Direction)],
+    interval: u32,
+    since_label: &mut u32,
+) -> Vec<(GridCellPosition, Direction)> {
+    let mut cells = Vec::new();
+    for window in path.windows(2) {
+        let (previous, (pos, direction)) = (window[0], window[1]);
+        *since_label += 1;
+        let straight =
+            previous.1 == direction && !matches!(direction, Direction::Up | Direction::Down);
+        if straight && *since_label >= interval {
+            cells.push((pos, direction));
+            *since_label = 0;
+        }
+    }
+    cells
+}
+
+/// Put a sign with block entity `nbt` in the spare corner of a routing grid cell. Returns `false`
+/// without changing anything if something is already there.
+pub fn splat_label(
+    o: &mut BlockStorage,
+    pos: GridCellPosition,
+    direction: Direction,
+    nbt: serde_json::Value,
+) -> Result<bool> {
+    let tier = pos.y as u32 / LAYERS_PER_TIER;
+    let layer = Layer::from_compact_idx(pos.y % LAYERS_PER_TIER as i32)?;
+    let x: u32 = (pos.x.to_block_coord() + 1).try_into().context("Label X")?;
+    let y = tier * 16 + layer.to_y_idx();
+    let z: u32 = (pos.z.to_block_coord() + 1).try_into().context("Label Z")?;
+
+    let b_air = o.add_new_block_type(Block::new("minecraft:air".into()));
+    if *o.get_block(x, y, z)? != b_air || *o.get_block(x, y + 1, z)? != b_air {
+        return Ok(false);
+    }
+
+    // The sign sits to the east of north-south wires and the south of east-west ones, so face it
+    // away from the wire
+    let rotation = match direction {
+        Direction::North | Direction::South => "12",
+        _ => "0",
+    };
+    let b_calcite = o.add_new_block_type(Block::new("minecraft:calcite".into()));
+    let b_sign = o.add_new_block_type(Block {
+        name: "minecraft:oak_sign".into(),
+        properties: Some(
+            [(
+                "rotation".to_owned(),
+                PropertyValue::String(rotation.to_owned()),
+            )]
+            .into_iter()
+            .collect(),
+        ),
+    });
+    *o.get_block_mut(x, y, z)? = b_calcite;
+    *o.get_block_mut(x, y + 1, z)? = b_sign;
+    o.set_block_entity(x, y + 1, z, nbt)?;
+
+    Ok(true)
+}
@@ -0,0 +1,2013 @@
+//! Routing for the MCPNR flow. The `mcpnr-routing` binary drives it from the command line, it's a
+//! library as well so the benchmarks and the Python bindings can get at it.
+
+mod buffering;
+#[cfg(test)]
+mod conduction;
+pub mod config;
+mod diagnostics;
+mod diff;
+mod doctor;
+mod eco;
+mod fanout;
+mod feedback;
+#[cfg(test)]
+mod golden;
+mod infrastructure;
+mod interactive;
+mod labels;
+mod netlist;
+mod netlist_check;
+mod placement_check;
+mod replication;
+mod routing_2d;
+mod splat;
+mod structure_cache;
+mod summary;
+mod supports;
+#[cfg(test)]
+mod test_util;
+mod timing;
+
+pub mod detail_routing;
+
+use anyhow::{anyhow, ensure, Context, Result};
+use buffering::{BufferOptions, BufferReport};
+use config::{
+    Config, MarkerConfig, MultiDriverMode, NetOrder, ReportArgs, RouterCommand, WavefrontConfig,
+};
+use detail_routing::splat_log::SplatLog;
+use detail_routing::wire_segment::{
+    net_base_block, pin_via_direction, splat_pin_escape, splat_pin_via, splat_via,
+    splat_wire_segment, LayerPosition, WireTierLayer, WIRE_BASE_BLOCK,
+};
+use detail_routing::{
+    DetailRouter, GridCell, GridCellPosition, Layer, RoutingError, SearchStats, SearchWindow,
+};
+use diagnostics::RouteFailure;
+use fanout::{FanoutStep, SkewReport};
+use infrastructure::Infrastructure;
+use itertools::Itertools;
+use mcpnr_common::block_storage::{Block, BlockStorage, Direction, Position};
+use mcpnr_common::blockage_map::BlockageMap;
+use mcpnr_common::blockers::{find_blockers, BlockerRules};
+use mcpnr_common::memory::MemoryBudget;
+use mcpnr_common::protos::mcpnr::{parameter::Value, Parameter, PlacedDesign};
+use mcpnr_common::protos::{read_placed_design, write_placed_design};
+use mcpnr_common::provenance::Provenance;
+use mcpnr_common::results::{self, RunRecord};
+use mcpnr_common::timing_constraints::{TimingConstraints, CRITICALITY_ATTRIBUTE};
+use mcpnr_common::{anvil, schematic, BLOCKS_PER_TIER};
+use netlist::{Net, Netlist, Pin};
+use netlist_check::{FanoutLimits, NetlistCheck};
+use placement_check::PlacementCheck;
+use splat::Splatter;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use structure_cache::StructureCache;
+use summary::DesignSummary;
+use timing::TimingReport;
+use tracing::{debug, debug_span, field, info, info_span, warn};
+
+use crate::detail_routing::wire_segment::WIRE_GRID_SCALE;
+use crate::detail_routing::LAYERS_PER_TIER;
+
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RouteId(pub u32);
+
+const GEN_TEST_SQUARES: bool = false;
+
+fn do_splat(
+    design: &PlacedDesign,
+    structure_cache: &StructureCache,
+    markers: &MarkerConfig,
+    output_structure: &mut BlockStorage,
+) -> Result<()> {
+    let splatter = Splatter::new(output_structure, structure_cache);
+
+    // Corners of the I/O macros, which the border leaves out
+    let mut io = Vec::new();
+    for (cell_idx, cell) in design.cells.iter().enumerate() {
+        let pos = match cell.pos.as_ref() {
+            Some(pos) if placement_check::is_io_macro(cell) => pos,
+            _ => continue,
+        };
+        let size = placement_check::footprint(cell, structure_cache)
+            .and_then(|size| size.context("I/O macro has no footprint"))
+            .with_context(|| anyhow!("Footprint of cell {}", cell.describe(cell_idx)))?;
+        let min = [pos.x, pos.y, pos.z];
+        io.push((min, [0, 1, 2].map(|axis| min[axis] + size[axis])));
+    }
+    splatter
+        .draw_border(output_structure, &io, markers)
+        .context("Error during border draw")?;
+
+    for (cell_idx, cell) in design.cells.iter().enumerate() {
+        splatter
+            .splat_cell(cell, output_structure)
+            .with_context(|| anyhow!("Error during splat of cell {}", cell.describe(cell_idx)))?;
+    }
+
+    if GEN_TEST_SQUARES {
+        // Square of wires
+        // Each side has 5 steps LI -> M0, M0 -> M1, M1 -> M1, M1 -> M0, M0 -> LI and corners (so 7
+        // total wire cells)
+        let wires = [
+            (WireTierLayer::new(0, Layer::LI), Direction::South),
+            (WireTierLayer::new(0, Layer::M0), Direction::South),
+            (WireTierLayer::new(0, Layer::M1), Direction::South),
+            (WireTierLayer::new(0, Layer::M1), Direction::South),
+            (WireTierLayer::new(0, Layer::M0), Direction::South),
+            (WireTierLayer::new(0, Layer::LI), Direction::South),
+            (WireTierLayer::new(0, Layer::LI), Direction::East),
+            (WireTierLayer::new(0, Layer::M0), Direction::East),
+            (WireTierLayer::new(0, Layer::M1), Direction::East),
+            (WireTierLayer::new(0, Layer::M1), Direction::East),
+            (WireTierLayer::new(0, Layer::M0), Direction::East),
+            (WireTierLayer::new(0, Layer::LI), Direction::East),
+            (WireTierLayer::new(0, Layer::LI), Direction::North),
+            (WireTierLayer::new(0, Layer::M0), Direction::North),
+            (WireTierLayer::new(0, Layer::M1), Direction::North),
+            (WireTierLayer::new(0, Layer::M1), Direction::North),
+            (WireTierLayer::new(0, Layer::M0), Direction::North),
+            (WireTierLayer::new(0, Layer::LI), Direction::North),
+            (WireTierLayer::new(0, Layer::LI), Direction::West),
+            (WireTierLayer::new(0, Layer::M0), Direction::West),
+            (WireTierLayer::new(0, Layer::M1), Direction::West),
+            (WireTierLayer::new(0, Layer::M1), Direction::West),
+            (WireTierLayer::new(0, Layer::M0), Direction::West),
+            (WireTierLayer::new(0, Layer::LI), Direction::West),
+        ];
+        let base = Block::new(WIRE_BASE_BLOCK.into());
+        let mut p = LayerPosition::new(11.into(), 0.into());
+        for i in 0..wires.len() {
+            let s = wires[(i + wires.len() - 1) % wires.len()];
+            let e = wires[i];
+            info!("{:?} -> {:?} at {:?}", s, e, p);
+            let (pn, _) = splat_wire_segment(output_structure, p, s, e, &base)?;
+            p = pn;
+        }
+        let mut p = LayerPosition::new(9.into(), 10.into());
+        for i in (0..wires.len()).rev() {
+            let e = wires[(i + wires.len() - 1) % wires.len()];
+            let s = wires[i];
+            info!("{:?} -> {:?} at {:?}", s, e, p);
+            let (pn, _) = splat_wire_segment(output_structure, p, s, e, &base)?;
+            p = pn;
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum NetState {
+    Unrouted,
+    RippedUpInPass(u32),
+    Routed,
+    /// Routed by an earlier run, and left as it is in the output
+    Kept,
+}
+
+const MAX_ROUTING_PASSES: u32 = 3;
+
+/// Why a net was deferred to the next routing pass
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeferralReason {
+    /// The pass ran out of time before getting to the net
+    PassTime,
+    /// The search for the net's routes expanded too many nodes
+    NetExpansions,
+}
+
+/// A net that was put off until the next pass instead of being searched exhaustively
+#[derive(Clone, Debug)]
+pub struct Deferral {
+    pub net: u32,
+    pub pass: u32,
+    pub reason: DeferralReason,
+}
+
+/// A net with more than one driver, left unrouted
+#[derive(Clone, Debug)]
+pub struct MultiDriverNet {
+    pub net: u32,
+    /// Index in the placed design of the cell of each driver, in net order
+    pub driver_cells: Vec<usize>,
+}
+
+/// A net whose searches had to look further from its pins than usual
+#[derive(Clone, Debug)]
+pub struct WidenedSearch {
+    pub net: u32,
+    /// Widest window any search for the net fell back to
+    pub window: SearchWindow,
+}
+
+/// Outcome of the routing phase
+pub struct RoutingReport {
+    /// Indicies of all nets that are not fully routed, in ascending order
+    pub unrouted_nets: Vec<u32>,
+    /// Every deferral made during routing, in the order they happened
+    pub deferrals: Vec<Deferral>,
+    /// Nets that weren't routed because they have several drivers, in ascending net order
+    pub multi_driver_nets: Vec<MultiDriverNet>,
+    /// Nets that weren't routed because nothing drives them, in ascending order
+    pub undriven_nets: Vec<u32>,
+    /// Skew of each routed net with a skew bound, in ascending net order
+    pub skew: Vec<SkewReport>,
+    /// Nets whose searches had to widen to find a route, or to give up, in ascending net order
+    pub widened_searches: Vec<WidenedSearch>,
+    /// Delays through the routed design
+    pub timing: TimingReport,
+    /// Blocks written by the splatted routes
+    pub wire_blocks: usize,
+}
+
+/// Key to sort nets of the same criticality by for `order`, lowest first. The sorts are stable,
+/// so nets with the same key keep net order.
+fn net_order_key(order: NetOrder, netlist: &Netlist, net_idx: i64, net: &Net) -> i64 {
+    match order {
+        NetOrder::Index => 0,
+        NetOrder::BoundingBox => net.bounding_box_size(netlist).into(),
+        NetOrder::Fanout => -(net.fanout() as i64),
+        NetOrder::Priority => -i64::from(netlist.priority(net_idx)),
+    }
+}
+
+/// A pin wires connect to
+#[derive(Clone, Copy, Debug)]
+struct KnownPin {
+    /// Block the pin is marked at
+    block: Position,
+    /// Direction wires connect to the pin from
+    direction: Direction,
+}
+
+struct Router<'nets> {
+    netlist: &'nets Netlist,
+    net_states: HashMap<u32, (NetState, &'nets Net)>,
+    known_pins: HashMap<GridCellPosition, KnownPin>,
+    /// Order to route nets in. With bus routing the bits of each bus come first, one after another
+    net_order: Vec<u32>,
+    /// Previous bit of the bus each net belongs to, if bus routing is on
+    bus_previous: HashMap<u32, u32>,
+    detail_router: DetailRouter,
+    routing_pass: u32,
+    pass_time_budget: Option<Duration>,
+    net_expansion_budget: Option<u64>,
+    /// Whether each wire has to reach its sink on the driver's signal alone. Long connections
+    /// got repeater cells before routing, so nothing else refreshes the signal.
+    limit_wire_length: bool,
+    deferrals: Vec<Deferral>,
+    multi_driver: MultiDriverMode,
+    /// Nets left unrouted because they have several drivers, found so far
+    multi_driver_nets: BTreeMap<u32, MultiDriverNet>,
+    /// Nets left unrouted because nothing drives them, found so far
+    undriven_nets: BTreeSet<u32>,
+    /// Skew of the current route of each balanced net
+    skew: BTreeMap<u32, SkewReport>,
+    /// Widest search window each net has needed beyond the first
+    widened_searches: BTreeMap<u32, SearchWindow>,
+    /// Blocks written to the output for each splatted net
+    splats: SplatLog,
+}
+
+impl<'nets> Router<'nets> {
+    fn new(
+        config: &Config,
+        netlist: &'nets Netlist,
+        output: &mut BlockStorage,
+        infrastructure: &Infrastructure,
+    ) -> Result<Self> {
+        let (size_x, size_y, size_z) = routing_grid_size(output);
+        let mut detail_router = DetailRouter::new(size_x, size_y, size_z);
+        detail_router.set_track_routing(config.track_routing);
+        detail_router.set_congestion_tracking(config.congestion_map_file.is_some());
+        if let Some(ref wavefront) = config.wavefront {
+            let nets = eco::resolve_nets(netlist, std::slice::from_ref(&wavefront.net))
+                .context("Find the --wavefront net")?;
+            detail_router.set_wavefront_tracking(nets.into_iter().next().map(RouteId));
+        }
+
+        let mut known_pins: HashMap<GridCellPosition, KnownPin> = HashMap::new();
+
+        {
+            let mut mark_in_extents = |pos: Position, v| match pos
+                .try_into()
+                .and_then(|pos| detail_router.get_cell_mut(pos))
+            {
+                Ok(vm) => *vm = v,
+                Err(_) => {}
+            };
+
+            let blockers = match config.blockage_map_file {
+                Some(ref path) => {
+                    info!("Taking blockages from {:?}", path);
+                    BlockageMap::read(path)?.to_blockers()
+                }
+                None => {
+                    let rules = BlockerRules::load(&config.blocker_rules_file)?;
+                    find_blockers(output, &rules)?
+                }
+            };
+
+            for (_, name) in blockers.unrecognized.iter() {
+                warn!("Unrecognized block type {}", name);
+            }
+            for pos in blockers.blocked {
+                mark_in_extents(pos, GridCell::Blocked);
+            }
+            // A blockage map doesn't know about the lights and corridors, so always mark them
+            for pos in infrastructure.blocked.iter() {
+                mark_in_extents(*pos, GridCell::Blocked);
+            }
+            for (pos, d) in blockers.pins {
+                let grid_cell: GridCellPosition = pos.try_into()?;
+                info!("Mark known pin at {:?}", grid_cell);
+                known_pins.insert(
+                    grid_cell,
+                    KnownPin {
+                        block: pos,
+                        direction: d,
+                    },
+                );
+            }
+        }
+
+        // Pins marked by entities, like item frames, aren't blocks in the output, so take their
+        // direction from the cell's structure instead
+        for pin in netlist.iter_pins() {
+            if let Some(facing) = pin.facing {
+                let pos = Position::new(pin.x as i32, pin.y as i32, pin.z as i32);
+                let grid_cell: GridCellPosition = pos.try_into()?;
+                known_pins.entry(grid_cell).or_insert(KnownPin {
+                    block: pos,
+                    direction: facing,
+                });
+            }
+        }
+
+        info!("Initial blocker mark done");
+
+        // TODO: use unrandomized hashermap
+        let net_states: HashMap<u32, (NetState, &netlist::Net)> = netlist
+            .iter_nets()
+            .map(|(net_idx, net)| (*net_idx as u32, (NetState::Unrouted, net)))
+            .collect();
+
+        // Route the most critical nets first, while there's the most room for them to take direct
+        // routes. Ties go by --net-order, and after that keep net order.
+        let by_criticality = |a: f32, b: f32| b.partial_cmp(&a).unwrap_or(Ordering::Equal);
+        let order_keys: HashMap<i64, i64> = netlist
+            .iter_nets()
+            .map(|(idx, net)| (*idx, net_order_key(config.net_order, netlist, *idx, net)))
+            .collect();
+        let order_key = |net_idx: &i64| order_keys.get(net_idx).copied().unwrap_or(0);
+
+        let mut net_order: Vec<u32> = Vec::new();
+        let mut bus_previous = HashMap::new();
+        if config.bus_routing {
+            let mut buses: Vec<&[i64]> = netlist.iter_buses().collect();
+            let bus_criticality = |bus: &[i64]| {
+                bus.iter()
+                    .map(|net_idx| netlist.criticality(*net_idx))
+                    .fold(0.0, f32::max)
+            };
+            let bus_order_key = |bus: &[i64]| bus.iter().map(order_key).min().unwrap_or(0);
+            buses.sort_by(|a, b| {
+                by_criticality(bus_criticality(a), bus_criticality(b))
+                    .then_with(|| bus_order_key(a).cmp(&bus_order_key(b)))
+            });
+            for bus in buses {
+                for (previous, net_idx) in bus.iter().tuple_windows() {
+                    bus_previous.insert(*net_idx as u32, *previous as u32);
+                }
+                net_order.extend(bus.iter().map(|net_idx| *net_idx as u32));
+            }
+            info!("Routing {} buses as groups", netlist.iter_buses().count());
+        }
+        let in_bus: HashSet<u32> = net_order.iter().copied().collect();
+        let mut other_nets: Vec<i64> = netlist
+            .iter_nets()
+            .map(|(net_idx, _)| *net_idx)
+            .filter(|net_idx| !in_bus.contains(&(*net_idx as u32)))
+            .collect();
+        other_nets.sort_by(|a, b| {
+            by_criticality(netlist.criticality(*a), netlist.criticality(*b))
+                .then_with(|| order_key(a).cmp(&order_key(b)))
+        });
+        net_order.extend(other_nets.into_iter().map(|net_idx| net_idx as u32));
+
+        Ok(Self {
+            detail_router,
+            netlist,
+            net_states,
+            known_pins,
+            net_order,
+            bus_previous,
+            routing_pass: 0,
+            pass_time_budget: config.pass_time_budget,
+            net_expansion_budget: config.net_expansion_budget,
+            limit_wire_length: config.max_wire_length.is_some(),
+            deferrals: Vec::new(),
+            multi_driver: config.multi_driver,
+            multi_driver_nets: BTreeMap::new(),
+            undriven_nets: BTreeSet::new(),
+            skew: BTreeMap::new(),
+            widened_searches: BTreeMap::new(),
+            splats: SplatLog::default(),
+        })
+    }
+
+    fn rnr_loop(&mut self, output: &mut BlockStorage) -> Result<()> {
+        self.routing_pass = 0;
+        while self.routing_pass < MAX_ROUTING_PASSES
+            && self
+                .net_states
+                .values()
+                .any(|(s, _)| !matches!(s, NetState::Routed | NetState::Kept))
+        {
+            let pass_span = info_span!(
+                "routing_pass",
+                pass = self.routing_pass,
+                expanded = field::Empty
+            )
+            .entered();
+            info!("Begin routing pass {}", self.routing_pass);
+            for (net_idx, net) in self.netlist.iter_nets() {
+                let net_idx: u32 = (*net_idx)
+                    .try_into()
+                    .with_context(|| anyhow!("Convert net_idx {}", net_idx))?;
+                if (self.routing_pass + net_idx) % 30 == 0
+                    && self.routing_pass != MAX_ROUTING_PASSES - 1
+                    && self.net_states[&net_idx].0 != NetState::Kept
+                {
+                    self.rip_up_net(net_idx, net, output)?;
+                }
+            }
+
+            let pass_start = Instant::now();
+            let mut pass_expanded = 0;
+            for net_idx in self.net_order.clone() {
+                if !self.needs_routing(net_idx) {
+                    continue;
+                }
+                if let Some(budget) = self.pass_time_budget {
+                    if pass_start.elapsed() > budget {
+                        self.defer(net_idx, DeferralReason::PassTime);
+                        continue;
+                    }
+                }
+                let net_span = debug_span!(
+                    "route_net",
+                    net = net_idx,
+                    expanded = field::Empty,
+                    peak_queue = field::Empty
+                )
+                .entered();
+                let guide = self.bus_guide(net_idx)?;
+                self.detail_router.set_guide(guide);
+                let result = self.route_net(net_idx);
+                self.detail_router.set_guide(HashSet::new());
+                let stats = self.detail_router.take_search_stats();
+                net_span.record("expanded", stats.expanded);
+                net_span.record("peak_queue", stats.peak_queue);
+                pass_expanded += stats.expanded;
+                self.record_widening(net_idx, &stats);
+                if let Err(e) = result {
+                    tracing::error!("Failed to route net {:?}: {:?}", net_idx, e)
+                }
+            }
+
+            self.clean_up_routes()?;
+
+            pass_span.record("expanded", pass_expanded);
+            self.routing_pass += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Take the route of `net_idx` out of the routing grid, and its blocks out of `output` if it
+    /// has been splatted, so it's routed again in a later pass
+    fn rip_up_net(&mut self, net_idx: u32, net: &Net, output: &mut BlockStorage) -> Result<()> {
+        info!("Rip up net {}", net_idx);
+        self.net_states
+            .get_mut(&net_idx)
+            .map(|v| v.0 = NetState::RippedUpInPass(self.routing_pass));
+
+        self.clear_route(net_idx, net)?;
+        if self.splats.remove(RouteId(net_idx), output)? {
+            debug!("Removed the blocks of net {}", net_idx);
+        }
+
+        Ok(())
+    }
+
+    /// Take whatever route `net_idx` has out of the routing grid, freeing its cells for other
+    /// nets, and mark its pins as its own again ready for the next try
+    fn clear_route(&mut self, net_idx: u32, net: &Net) -> Result<()> {
+        self.detail_router
+            .rip_up(RouteId(net_idx))
+            .with_context(|| anyhow!("Rip up net {:?}", net_idx))?;
+        self.skew.remove(&net_idx);
+
+        for pin in net
+            .iter_sinks(self.netlist)
+            .chain(net.iter_drivers(self.netlist))
+            .chain(net.iter_alternate_drivers(self.netlist))
+        {
+            let pos = Position::new(pin.x as i32, pin.y as i32, pin.z as i32);
+            let pos: GridCellPosition = pos.try_into()?;
+            let pin_direction = self
+                .known_pins
+                .get(&pos)
+                .map(|pin| &pin.direction)
+                .ok_or_else(|| anyhow!("Failed to find pin {}", pos))?;
+            *(self
+                .detail_router
+                .get_cell_mut(pos)
+                .context("Get start cell")?) = GridCell::Occupied(*pin_direction, RouteId(net_idx));
+        }
+
+        Ok(())
+    }
+
+    /// Splat the wires of `net_idx` in to `output`, recording the blocks they change so the net
+    /// can be ripped up again afterwards
+    fn splat_net(
+        &mut self,
+        config: &Config,
+        net_idx: u32,
+        net: &Net,
+        output: &mut BlockStorage,
+    ) -> Result<()> {
+        output.start_journal();
+        let result = self.splat_net_wires(config, net_idx, net, output);
+        let changes = output.finish_journal();
+        self.splats.insert(RouteId(net_idx), changes);
+        debug!(
+            "Net {} changed {} blocks",
+            net_idx,
+            self.splats.changes(RouteId(net_idx))
+        );
+        result
+    }
+
+    fn splat_net_wires(
+        &self,
+        config: &Config,
+        net_idx: u32,
+        net: &Net,
+        output: &mut BlockStorage,
+    ) -> Result<()> {
+        let netlist = self.netlist;
+        let base = if config.colorize_nets {
+            net_base_block(net_idx)
+        } else {
+            Block::new(WIRE_BASE_BLOCK.into())
+        };
+        // Drivers only need their escape, the wire from each sink leads back to one of them
+        for pin in net
+            .iter_drivers(netlist)
+            .chain(net.iter_alternate_drivers(netlist))
+        {
+            let pin_pos = Position::new(pin.x as i32, pin.y as i32, pin.z as i32);
+            let direction = self.pin_direction(pin_pos)?;
+            // Escapes up or down are vias, splatted along with the route climbing in to them
+            if let Direction::Up | Direction::Down = direction {
+                continue;
+            }
+            if self.escape_is_routed(pin_pos, direction.mirror(), net_idx)? {
+                if let Err(e) = splat_pin_escape(output, pin_pos, direction.mirror(), &base) {
+                    warn!(
+                        "Failed to splat escape from driver pin at {}: {}",
+                        pin_pos, e
+                    );
+                }
+            }
+        }
+        // Merged drivers lead in to the wire from the first one, the same way sinks do
+        for pin in net.iter_drivers(netlist).skip(1) {
+            let pin_pos = Position::new(pin.x as i32, pin.y as i32, pin.z as i32);
+            let direction = self.pin_direction(pin_pos)?.mirror();
+            if self.escape_is_routed(pin_pos, direction, net_idx)? {
+                splat_route(self, output, net_idx, pin_pos, direction, &base)?;
+            }
+        }
+        for pin in net.iter_sinks(netlist) {
+            let pin_pos = Position::new(pin.x as i32, pin.y as i32, pin.z as i32);
+            let direction = self.pin_direction(pin_pos)?;
+            if !self.escape_is_routed(pin_pos, direction, net_idx)? {
+                continue;
+            }
+            // Escapes up or down are vias, splatted along with the route
+            if !matches!(direction, Direction::Up | Direction::Down) {
+                if let Err(e) = splat_pin_escape(output, pin_pos, direction, &base) {
+                    warn!("Failed to splat escape to sink pin at {}: {}", pin_pos, e);
+                    continue;
+                }
+            }
+            splat_route(self, output, net_idx, pin_pos, direction, &base)?;
+        }
+
+        Ok(())
+    }
+
+    /// Prune stubs from the routes of every routed net and straighten them, freeing cells for the
+    /// nets still to be routed
+    fn clean_up_routes(&mut self) -> Result<()> {
+        let mut routes = HashMap::new();
+        for (net_idx, (state, net)) in self.net_states.iter() {
+            if *state != NetState::Routed {
+                continue;
+            }
+            let mut sinks = Vec::new();
+            for pin in net.iter_sinks(self.netlist) {
+                let pos = Position::new(pin.x as i32, pin.y as i32, pin.z as i32);
+                sinks.push((pos.try_into()?, self.pin_direction(pos)?));
+            }
+            // Merged drivers are routed to like sinks, from the other side of their pin
+            for pin in net.iter_drivers(self.netlist).skip(1) {
+                let pos = Position::new(pin.x as i32, pin.y as i32, pin.z as i32);
+                sinks.push((pos.try_into()?, self.pin_direction(pos)?.mirror()));
+            }
+            routes.insert(RouteId(*net_idx), sinks);
+        }
+
+        let stats = self
+            .detail_router
+            .clean_up(&routes)
+            .context("Clean up routes")?;
+        info!(
+            "Pruned {} stub cells and straightened {} staircase steps",
+            stats.pruned, stats.straightened
+        );
+
+        Ok(())
+    }
+
+    /// Cells the route for `net_idx` should follow to run alongside the previous bit of its bus:
+    /// the route of the previous bit, moved by the distance between the two drivers. Empty if the
+    /// net isn't part of a bus, or the previous bit isn't routed.
+    fn bus_guide(&self, net_idx: u32) -> Result<HashSet<GridCellPosition>> {
+        let previous = match self.bus_previous.get(&net_idx) {
+            Some(previous) if self.net_states[previous].0 == NetState::Routed => *previous,
+            _ => return Ok(HashSet::new()),
+        };
+        let driver_position = |net_idx: u32| -> Result<Option<GridCellPosition>> {
+            let net = self.net_states[&net_idx].1;
+            net.iter_drivers(self.netlist)
+                .next()
+                .map(|pin| Position::new(pin.x as i32, pin.y as i32, pin.z as i32).try_into())
+                .transpose()
+        };
+        let (driver, previous_driver) =
+            match (driver_position(net_idx)?, driver_position(previous)?) {
+                (Some(driver), Some(previous_driver)) => (driver, previous_driver),
+                _ => return Ok(HashSet::new()),
+            };
+
+        // Follow the previous bit's route back from its sinks, a scan of the whole grid for
+        // every bit of every bus adds up quickly
+        let mut previous_sinks = Vec::new();
+        for pin in self.net_states[&previous].1.iter_sinks(self.netlist) {
+            let pos: GridCellPosition =
+                Position::new(pin.x as i32, pin.y as i32, pin.z as i32).try_into()?;
+            if let Some(known_pin) = self.known_pins.get(&pos) {
+                previous_sinks.push((pos, known_pin.direction));
+            }
+        }
+        let route = self
+            .detail_router
+            .route_tree(RouteId(previous), &previous_sinks)
+            .with_context(|| anyhow!("Trace route of net {:?}", previous))?;
+
+        let dx = driver.x.0 - previous_driver.x.0;
+        let dy = driver.y - previous_driver.y;
+        let dz = driver.z.0 - previous_driver.z.0;
+        Ok(route
+            .into_iter()
+            .map(|pos| GridCellPosition::new(pos.x + dx, pos.y + dy, pos.z + dz))
+            .collect())
+    }
+
+    /// Redstone ticks from the driver of a net to one of its sinks along the routed wire. Padding
+    /// to balance the net isn't counted, since it isn't in the wire. `None` if the sink isn't
+    /// connected.
+    fn routed_ticks(&self, net_idx: u32, sink_idx: usize) -> Result<Option<u32>> {
+        let net = self.net_states[&net_idx].1;
+        let (driver, sink) = match (
+            net.iter_drivers(self.netlist).next(),
+            net.iter_sinks(self.netlist).nth(sink_idx),
+        ) {
+            (Some(driver), Some(sink)) => (driver, sink),
+            _ => return Ok(None),
+        };
+        let end: GridCellPosition =
+            Position::new(sink.x as i32, sink.y as i32, sink.z as i32).try_into()?;
+        let direction = self
+            .known_pins
+            .get(&end)
+            .map(|pin| &pin.direction)
+            .ok_or_else(|| anyhow!("Failed to find sink pin {}", end))?;
+        let length = match self
+            .detail_router
+            .path_length(end, *direction, RouteId(net_idx))
+        {
+            Ok(length) => length,
+            Err(_) => return Ok(None),
+        };
+        Ok(Some(fanout::wire_delay(
+            length,
+            driver.sig_derating,
+            sink.sig_derating,
+        )))
+    }
+
+    /// The free cell closest to `pos` in the same layer, looking at most two cells away.
+    fn free_tap(&self, pos: GridCellPosition) -> Option<GridCellPosition> {
+        (0..=2)
+            .flat_map(|r| (-r..=r).cartesian_product(-r..=r))
+            .map(|(dx, dz)| GridCellPosition::new(pos.x + dx, pos.y, pos.z + dz))
+            .find(|tap| matches!(self.detail_router.get_cell(*tap), Ok(GridCell::Free)))
+    }
+
+    /// Direction of the pin whose sign is at block position `pin`
+    fn pin_direction(&self, pin: Position) -> Result<Direction> {
+        let cell: GridCellPosition = pin.try_into()?;
+        self.known_pins
+            .get(&cell)
+            .map(|pin| pin.direction)
+            .ok_or_else(|| anyhow!("Failed to find pin {}", pin))
+    }
+
+    /// Whether the route `net_idx` reaches the grid cell the pin at `pin` escapes to in direction
+    /// `toward`
+    fn escape_is_routed(&self, pin: Position, toward: Direction, net_idx: u32) -> Result<bool> {
+        let cell: GridCellPosition = pin.try_into()?;
+        Ok(matches!(
+            self.detail_router.get_cell(cell.offset(toward))?,
+            GridCell::Occupied(_, RouteId(id)) if *id == net_idx
+        ))
+    }
+
+    /// Leave every net but the ones in `reroute` as the earlier run routed them
+    fn keep_routed(&mut self, reroute: &BTreeSet<u32>) {
+        for (net_idx, (state, _)) in self.net_states.iter_mut() {
+            if !reroute.contains(net_idx) {
+                *state = NetState::Kept;
+            }
+        }
+    }
+
+    /// Indicies of all nets that are not currently fully routed, in ascending order.
+    fn unrouted_nets(&self) -> Vec<u32> {
+        let mut nets: Vec<u32> = self
+            .net_states
+            .iter()
+            .filter(|(_, (state, _))| !matches!(state, NetState::Routed | NetState::Kept))
+            .map(|(net_idx, _)| *net_idx)
+            .collect();
+        nets.sort();
+        nets
+    }
+
+    /// Whether the net should be routed in the current pass
+    fn needs_routing(&self, net_idx: u32) -> bool {
+        match self.net_states[&net_idx].0 {
+            NetState::RippedUpInPass(p) => p != self.routing_pass,
+            NetState::Routed | NetState::Kept => false,
+            NetState::Unrouted => true,
+        }
+    }
+
+    /// Remember how far the searches in `stats`, which were all for `net_idx`, had to widen
+    fn record_widening(&mut self, net_idx: u32, stats: &SearchStats) {
+        if let Some(window) = stats.widest_window {
+            info!("Net {} needed a search window of {}", net_idx, window);
+            let widest = self.widened_searches.entry(net_idx).or_insert(window);
+            *widest = (*widest).max(window);
+        }
+    }
+
+    fn defer(&mut self, net_idx: u32, reason: DeferralReason) {
+        warn!(
+            "Deferring net {} in pass {} ({:?})",
+            net_idx, self.routing_pass, reason
+        );
+        self.deferrals.push(Deferral {
+            net: net_idx,
+            pass: self.routing_pass,
+            reason,
+        });
+    }
+
+    /// Record that `net_idx` is left unrouted because it has all of `drivers`, warning about it the
+    /// first time
+    fn report_multi_driver(&mut self, net_idx: u32, drivers: &[&Pin]) {
+        if self.multi_driver_nets.contains_key(&net_idx) {
+            return;
+        }
+        warn!(
+            "Net {} has {} drivers, leaving it unrouted. Run `tribuf -logic` in Yosys if they are \
+             tri-state buffers, or `check -assert` to find the conflicting assignments. Pass \
+             --multi-driver merge to wire them together as an OR instead.",
+            net_idx,
+            drivers.len()
+        );
+        for driver in drivers {
+            warn!(
+                "  driven by cell {} at ({}, {}, {})",
+                self.netlist.describe_cell(driver.cell),
+                driver.x,
+                driver.y,
+                driver.z
+            );
+        }
+        self.multi_driver_nets.insert(
+            net_idx,
+            MultiDriverNet {
+                net: net_idx,
+                driver_cells: drivers.iter().map(|driver| driver.cell).collect(),
+            },
+        );
+    }
+
+    fn route_net(&mut self, net_idx: u32) -> Result<()> {
+        if !self.needs_routing(net_idx) {
+            return Ok(());
+        }
+        let net = self.net_states[&net_idx].1;
+
+        let drivers: Vec<_> = net.iter_drivers(self.netlist).collect();
+        let (driver, extra_drivers) = match drivers.split_first() {
+            Some((driver, extra_drivers)) => (*driver, extra_drivers),
+            None => {
+                // Already reported by the netlist check
+                debug!("Undriven net {:?}", net);
+                self.undriven_nets.insert(net_idx);
+                return Ok(());
+            }
+        };
+        if !extra_drivers.is_empty() && self.multi_driver == MultiDriverMode::Report {
+            self.report_multi_driver(net_idx, &drivers);
+            return Ok(());
+        }
+
+        let start = Position::new(driver.x as i32, driver.y as i32, driver.z as i32);
+        let start: GridCellPosition = start.try_into()?;
+        if let GridCell::Occupied(_, RouteId(id)) = self.detail_router.get_cell(start)? {
+            if id != &net_idx {
+                warn!(
+                    "Starting position of net {} at {} is occupied by another net {}",
+                    net_idx, start, id
+                )
+            }
+        }
+        let start_direction = self
+            .known_pins
+            .get(&start)
+            .map(|pin| &pin.direction)
+            .ok_or_else(|| anyhow!("Failed to find driver pin {}", start))?;
+        *(self
+            .detail_router
+            .get_cell_mut(start)
+            .context("Get start cell")?) = GridCell::Blocked;
+
+        // Claim the equivalent driver pins up front, so each sink's search can end at whichever
+        // driver pin (or existing part of the route) is closest.
+        for alternate in net.iter_alternate_drivers(self.netlist) {
+            let pos = Position::new(alternate.x as i32, alternate.y as i32, alternate.z as i32);
+            let pos: GridCellPosition = pos.try_into()?;
+            let direction = *self
+                .known_pins
+                .get(&pos)
+                .map(|pin| &pin.direction)
+                .ok_or_else(|| anyhow!("Failed to find alternate driver pin {}", pos))?;
+            *(self
+                .detail_router
+                .get_cell_mut(pos)
+                .context("Get alternate driver cell")?) = GridCell::Blocked;
+            self.detail_router
+                .mark_driver(pos, direction, RouteId(net_idx))
+                .with_context(|| anyhow!("Mark alternate driver pin {} of net {}", pos, net_idx))?;
+        }
+
+        let mut this_net_all_routed = true;
+        self.detail_router
+            .set_expansion_budget(self.net_expansion_budget);
+        // Merging other drivers in isn't a connection to a sink
+        self.detail_router.set_length_limit(None);
+
+        // Only happens when merging. The other drivers are wired in to the route as though they
+        // were sinks, so the redstone from all of them meets and the net carries their OR.
+        for extra in extra_drivers {
+            let pos = Position::new(extra.x as i32, extra.y as i32, extra.z as i32);
+            let pos: GridCellPosition = pos.try_into()?;
+            let direction = *self
+                .known_pins
+                .get(&pos)
+                .map(|pin| &pin.direction)
+                .ok_or_else(|| anyhow!("Failed to find driver pin {}", pos))?;
+            *(self
+                .detail_router
+                .get_cell_mut(pos)
+                .context("Get extra driver cell")?) = GridCell::Blocked;
+
+            match self.detail_router.route(
+                start,
+                *start_direction,
+                pos,
+                direction.mirror(),
+                RouteId(net_idx),
+            ) {
+                Ok(_) => {}
+                Err(e) => match e.downcast_ref() {
+                    Some(RoutingError::Unroutable) => {
+                        warn!("Failed to merge another driver in to net {}", net_idx);
+                        let failure = RouteFailure::new(self.netlist, net_idx, driver, extra, &e)?;
+                        log_route_failure(&failure, &e);
+                        this_net_all_routed = false;
+                    }
+                    Some(RoutingError::BudgetExhausted) => {
+                        self.clear_route(net_idx, net)?;
+                        self.defer(net_idx, DeferralReason::NetExpansions);
+                        return Ok(());
+                    }
+                    _ => {
+                        let failure = RouteFailure::new(self.netlist, net_idx, driver, extra, &e)?;
+                        return Err(e.context(failure));
+                    }
+                },
+            }
+        }
+
+        let sinks: Vec<_> = net.iter_sinks(self.netlist).collect();
+        let sink_positions = sinks
+            .iter()
+            .map(|sink| Position::new(sink.x as i32, sink.y as i32, sink.z as i32).try_into())
+            .collect::<Result<Vec<GridCellPosition>>>()?;
+        let max_skew = self.netlist.max_skew(net_idx as i64);
+        let steps = match max_skew {
+            Some(_) => fanout::h_tree(&sink_positions),
+            None => {
+                // The sinks that need the strongest signal go first, so they get the most direct
+                // wires and the fewest repeaters
+                let mut order: Vec<_> = (0..sinks.len()).collect();
+                order.sort_by_key(|idx| std::cmp::Reverse(sinks[*idx].sig_derating));
+                order.into_iter().map(FanoutStep::Sink).collect()
+            }
+        };
+
+        for step in steps {
+            let sink_idx = match step {
+                FanoutStep::Sink(sink_idx) => sink_idx,
+                FanoutStep::Tap(tap) => {
+                    // Taps only even out the branches, so the sinks can still be routed without
+                    // one
+                    let tap = match self.free_tap(tap) {
+                        Some(tap) => tap,
+                        None => {
+                            debug!("No free cell for tap {} of net {}", tap, net_idx);
+                            continue;
+                        }
+                    };
+                    match self.detail_router.route_to_tap(
+                        start,
+                        *start_direction,
+                        tap,
+                        RouteId(net_idx),
+                    ) {
+                        Ok(_) => {}
+                        Err(e) => match e.downcast_ref() {
+                            Some(RoutingError::Unroutable) => {
+                                debug!("Failed to route net {} to tap {}", net_idx, tap);
+                            }
+                            Some(RoutingError::BudgetExhausted) => {
+                                this_net_all_routed = false;
+                                self.clear_route(net_idx, net)?;
+                                self.defer(net_idx, DeferralReason::NetExpansions);
+                                break;
+                            }
+                            _ => return Err(e),
+                        },
+                    }
+                    continue;
+                }
+            };
+            let sink = sinks[sink_idx];
+            let end = sink_positions[sink_idx];
+            if let GridCell::Occupied(_, RouteId(id)) =
+                self.detail_router.get_cell(end).context("Get end cell")?
+            {
+                if id != &net_idx {
+                    warn!(
+                        "Ending position of net {} at {} is occupied by another net {}",
+                        net_idx, end, id
+                    );
+                }
+            }
+            let end_direction = self
+                .known_pins
+                .get(&end)
+                .map(|pin| &pin.direction)
+                .ok_or_else(|| anyhow!("Failed to find sink pin {}", end))?;
+            *(self
+                .detail_router
+                .get_cell_mut(end)
+                .context("Get end cell")?) = GridCell::Blocked;
+
+            let length_limit = self
+                .limit_wire_length
+                .then(|| fanout::unrepeated_cells(driver.sig_derating, sink.sig_derating));
+            self.detail_router.set_length_limit(length_limit);
+            match self.detail_router.route(
+                start,
+                *start_direction,
+                end,
+                *end_direction,
+                RouteId(net_idx),
+            ) {
+                Ok(_) => {}
+                Err(e) => match e.downcast_ref() {
+                    Some(RoutingError::Unroutable) => {
+                        let failure = RouteFailure::new(self.netlist, net_idx, driver, sink, &e)?;
+                        log_route_failure(&failure, &e);
+                        this_net_all_routed = false;
+                        continue;
+                    }
+                    Some(RoutingError::BudgetExhausted) => {
+                        // The budget covers the whole net, so there's no point trying the other
+                        // sinks. What's been routed so far is taken out again, so it doesn't keep
+                        // other nets out of its cells until the next pass.
+                        this_net_all_routed = false;
+                        self.clear_route(net_idx, net)?;
+                        self.defer(net_idx, DeferralReason::NetExpansions);
+                        break;
+                    }
+                    _ => {
+                        let failure = RouteFailure::new(self.netlist, net_idx, driver, sink, &e)?;
+                        return Err(e.context(failure));
+                    }
+                },
+            }
+        }
+
+        if let (true, Some(max_skew)) = (this_net_all_routed, max_skew) {
+            let arrival = sinks
+                .iter()
+                .zip(sink_positions.iter())
+                .map(|(sink, end)| -> Result<u32> {
+                    let direction = self
+                        .known_pins
+                        .get(end)
+                        .map(|pin| &pin.direction)
+                        .ok_or_else(|| anyhow!("Failed to find sink pin {}", end))?;
+                    let length =
+                        self.detail_router
+                            .path_length(*end, *direction, RouteId(net_idx))?;
+                    Ok(fanout::wire_delay(
+                        length,
+                        driver.sig_derating,
+                        sink.sig_derating,
+                    ))
+                })
+                .collect::<Result<Vec<_>>>()
+                .with_context(|| anyhow!("Measure skew of net {}", net_idx))?;
+            let report = SkewReport::new(net_idx, max_skew, arrival);
+            if report.routed_skew() > max_skew {
+                // The splatted wires don't have the padding in them, so the bound only holds once
+                // someone adds it
+                warn!(
+                    "Net {} has {} ticks of skew, over its bound of {}. It needs {} padding \
+                     repeaters in front of its early sinks, which aren't placed.",
+                    net_idx,
+                    report.routed_skew(),
+                    max_skew,
+                    report.padding_repeaters()
+                );
+            } else {
+                info!(
+                    "Net {} has {} ticks of skew (bound {})",
+                    net_idx,
+                    report.routed_skew(),
+                    max_skew
+                );
+            }
+            self.skew.insert(net_idx, report);
+        }
+
+        if this_net_all_routed {
+            info!("Mark net {:?} routed", net_idx);
+            self.net_states
+                .get_mut(&net_idx)
+                .map(|v| v.0 = NetState::Routed);
+        }
+
+        Ok(())
+    }
+}
+
+/// Warn about a route that couldn't be made, with the errors from the detail router under it
+fn log_route_failure(failure: &RouteFailure, error: &anyhow::Error) {
+    for line in failure.to_string().lines() {
+        warn!("{}", line);
+    }
+    for e in error.chain() {
+        warn!("  because ... {}", e);
+    }
+}
+
+/// Route all nets, or only those in `reroute` when the rest were routed by an earlier run,
+/// reporting any nets that could not be routed and any deferrals along the way
+fn do_route(
+    config: &Config,
+    design: &PlacedDesign,
+    netlist: &Netlist,
+    structure_cache: &StructureCache,
+    output: &mut BlockStorage,
+    infrastructure: &Infrastructure,
+    reroute: Option<&BTreeSet<u32>>,
+) -> Result<RoutingReport> {
+    if GEN_TEST_SQUARES {
+        return Ok(RoutingReport {
+            unrouted_nets: Vec::new(),
+            deferrals: Vec::new(),
+            multi_driver_nets: Vec::new(),
+            undriven_nets: Vec::new(),
+            skew: Vec::new(),
+            widened_searches: Vec::new(),
+            timing: TimingReport::default(),
+            wire_blocks: 0,
+        });
+    }
+
+    let mut router = Router::new(config, netlist, output, infrastructure)?;
+    if let Some(reroute) = reroute {
+        router.keep_routed(reroute);
+    }
+    if config.interactive {
+        interactive::run_repl(
+            &mut router,
+            config,
+            structure_cache,
+            output,
+            std::io::stdin().lock(),
+            std::io::stdout(),
+        )?;
+    } else {
+        router.rnr_loop(output)?;
+    }
+    if let Some(ref path) = config.congestion_map_file {
+        write_congestion_map(&router, path).context("Write congestion map")?;
+    }
+    if let Some(ref wavefront) = config.wavefront {
+        write_wavefront(&mut router, wavefront).context("Write wavefront")?;
+    }
+    let timing = timing::analyze(design, netlist, structure_cache, |net_idx, sink_idx| {
+        router.routed_ticks(net_idx, sink_idx)
+    })
+    .context("Timing analysis")?;
+    log_timing(config, netlist, &timing);
+    let report = RoutingReport {
+        unrouted_nets: router.unrouted_nets(),
+        deferrals: std::mem::take(&mut router.deferrals),
+        multi_driver_nets: std::mem::take(&mut router.multi_driver_nets)
+            .into_values()
+            .collect(),
+        undriven_nets: std::mem::take(&mut router.undriven_nets)
+            .into_iter()
+            .collect(),
+        skew: std::mem::take(&mut router.skew).into_values().collect(),
+        widened_searches: std::mem::take(&mut router.widened_searches)
+            .into_iter()
+            .map(|(net, window)| WidenedSearch { net, window })
+            .collect(),
+        timing,
+        wire_blocks: router.splats.positions().collect::<HashSet<_>>().len(),
+    };
+    if let Some(ref path) = config.net_feedback_file {
+        let weights = feedback::net_feedback(&report);
+        weights.write(path).context("Write net feedback")?;
+        info!(
+            "Wrote weights for {} nets routing had trouble with to {:?}",
+            weights.nets.len(),
+            path
+        );
+    }
+
+    if let Some(interval) = config.net_label_interval {
+        label_nets(&router, interval, output).context("Label nets")?;
+    }
+
+    info!("Begin wire splats");
+    for (net_idx, net) in netlist.iter_nets() {
+        if router.net_states[&(*net_idx as u32)].0 == NetState::Kept {
+            continue;
+        }
+        router.splat_net(config, *net_idx as u32, net, output)?;
+    }
+
+    if let Some(mode) = config.supports {
+        let placed = supports::splat_supports(
+            mode,
+            output,
+            router.splats.positions(),
+            &infrastructure.blocked,
+        )
+        .context("Support wires")?;
+        info!("Placed {} blocks of wire supports", placed);
+    }
+
+    Ok(report)
+}
+
+/// Write the frames of the searches the router recorded for the net `config` names
+fn write_wavefront(router: &mut Router, config: &WavefrontConfig) -> Result<()> {
+    let wavefront = match router.detail_router.take_wavefront() {
+        Some(wavefront) if !wavefront.visits.is_empty() => wavefront,
+        _ => {
+            warn!(
+                "Net {:?} was never searched for, no wavefront to write",
+                config.net
+            );
+            return Ok(());
+        }
+    };
+    let frames = wavefront.write_frames(&router.detail_router, &config.dir, config.interval)?;
+    info!(
+        "Wrote {} frames of the {} cells expanded for net {} to {:?}",
+        frames,
+        wavefront.visits.len(),
+        wavefront.id.0,
+        config.dir
+    );
+
+    Ok(())
+}
+
+/// Write the congestion map the router tracked to `path`, and say where it was worst
+fn write_congestion_map(router: &Router, path: &Path) -> Result<()> {
+    let map = router.detail_router.congestion_map()?;
+    map.write_json(path)?;
+    match map.hotspot() {
+        Some(hotspot) => info!(
+            "Wrote congestion map to {:?}, {} nets wanted ({}, {}) in {} of tier {}",
+            path, hotspot.nets, hotspot.x, hotspot.z, hotspot.layer, hotspot.tier
+        ),
+        None => info!("Wrote congestion map to {:?}, nothing was searched", path),
+    }
+
+    Ok(())
+}
+
+/// Splat the wire of `net_idx` from the pin whose sign is at `pin`, which it leaves in `direction`,
+/// back to where it joins the rest of the net
+fn splat_route(
+    router: &Router,
+    output: &mut BlockStorage,
+    net_idx: u32,
+    pin: Position,
+    direction: Direction,
+    base: &Block,
+) -> Result<()> {
+    let pin_cell: GridCellPosition = pin.try_into()?;
+    let mut prev_direction = direction;
+    let mut pos = pin_cell.offset(prev_direction);
+    if let Direction::Up | Direction::Down = direction {
+        // The wire climbs straight out of the pin's own grid cell
+        let entry = match pin_via_direction(pin) {
+            Ok(entry) => entry,
+            Err(e) => {
+                warn!("Failed to splat escape from pin at {}: {}", pin, e);
+                return Ok(());
+            }
+        };
+        match splat_route_via(
+            router,
+            output,
+            net_idx,
+            (pin_cell, Some(pin)),
+            entry,
+            direction,
+            base,
+        )? {
+            Some((next, exit)) => (pos, prev_direction) = (next, exit),
+            None => return Ok(()),
+        }
+    }
+    debug!(
+        "Splat wire at {:?} {:?} for net {}",
+        pos,
+        router.detail_router.get_cell(pos),
+        net_idx,
+    );
+
+    while let GridCell::Occupied(d, id) = router
+        .detail_router
+        .get_cell(pos)
+        .context("Wire splat backtrack")?
+    {
+        if id.0 != net_idx {
+            break;
+        }
+        let d = *d;
+
+        if let Direction::Up | Direction::Down = d {
+            // Splat the whole via at once, it has to know which way the route leaves it
+            match splat_route_via(
+                router,
+                output,
+                net_idx,
+                (pos, None),
+                prev_direction,
+                d,
+                base,
+            )? {
+                Some((next, exit)) => (pos, prev_direction) = (next, exit),
+                None => break,
+            }
+            continue;
+        }
+
+        let tier = pos.y as u32 / LAYERS_PER_TIER;
+        let layer = Layer::from_compact_idx(pos.y % LAYERS_PER_TIER as i32)?;
+        let wire_pos = (WireTierLayer::new(tier, layer), prev_direction);
+        if let Err(e) = splat_wire_segment(
+            output,
+            LayerPosition::new(pos.x, pos.z),
+            wire_pos,
+            (wire_pos.0, d),
+            base,
+        ) {
+            warn!("Failed to splat wire at {:?}: {}", wire_pos, e);
+        }
+
+        prev_direction = d;
+        pos = pos.offset(d);
+    }
+
+    Ok(())
+}
+
+/// Splat the via of `net_idx` that the route enters at `start` going `entry` and climbs from going
+/// `d`, up to where the route turns, and return the grid cell after it along with the direction
+/// the route leaves in. `start.1` is the pin the via starts at, if the route climbs straight out
+/// of one. A via that ends at a pin facing up or down, like a driver on top of a cell, is the end
+/// of the route, so there's no cell after it.
+fn splat_route_via(
+    router: &Router,
+    output: &mut BlockStorage,
+    net_idx: u32,
+    start: (GridCellPosition, Option<Position>),
+    entry: Direction,
+    d: Direction,
+    base: &Block,
+) -> Result<Option<(GridCellPosition, Direction)>> {
+    let (start, start_pin) = start;
+    let mut end = start;
+    let mut end_pin = None;
+    let exit = loop {
+        let next = end.offset(d);
+        match router.detail_router.get_cell(next) {
+            Ok(GridCell::Occupied(next_d, id)) if id.0 == net_idx => {
+                end = next;
+                if *next_d != d {
+                    break *next_d;
+                }
+            }
+            _ => match router.known_pins.get(&next) {
+                Some(pin) if pin.direction == d => match pin_via_direction(pin.block) {
+                    Ok(direction) => {
+                        end = next;
+                        end_pin = Some(pin.block);
+                        break direction.mirror();
+                    }
+                    Err(e) => {
+                        warn!("Failed to splat escape to pin at {}: {}", pin.block, e);
+                        break d;
+                    }
+                },
+                _ => break d,
+            },
+        }
+    };
+
+    let tier_layer = |pos: GridCellPosition| -> Result<WireTierLayer> {
+        Ok(WireTierLayer::new(
+            pos.y as u32 / LAYERS_PER_TIER,
+            Layer::from_compact_idx(pos.y % LAYERS_PER_TIER as i32)?,
+        ))
+    };
+    let start_pos = (tier_layer(start)?, entry);
+    let end_pos = (tier_layer(end)?, exit);
+    let (lower, upper) = if d == Direction::Up {
+        (start_pos, end_pos)
+    } else {
+        ((end_pos.0, exit.mirror()), (start_pos.0, entry.mirror()))
+    };
+    let position = LayerPosition::new(start.x, start.z);
+    let splatted = match start_pin.or(end_pin) {
+        Some(pin) => splat_pin_via(output, position, lower, upper, pin, base),
+        None => splat_via(output, position, lower, upper, base),
+    };
+    if let Err(e) = splatted {
+        warn!("Failed to splat via at {:?}: {}", start, e);
+    }
+
+    Ok(match end_pin {
+        Some(_) => None,
+        None => Some((end.offset(exit), exit)),
+    })
+}
+
+/// Put a sign with the net name next to the routed wires every `interval` blocks
+fn label_nets(router: &Router, interval: u32, output: &mut BlockStorage) -> Result<()> {
+    let interval = std::cmp::max(interval / WIRE_GRID_SCALE as u32, 1);
+    let mut labels = 0;
+    for (net_idx, net) in router.netlist.iter_nets() {
+        let name = match router.netlist.net_name(*net_idx) {
+            Some(name) => name.to_owned(),
+            None => format!("net {}", net_idx),
+        };
+        let id = RouteId(*net_idx as u32);
+
+        // Sinks share the wire closer to the driver, only label that once
+        let mut labelled = HashSet::new();
+        let mut since_label = 0;
+        for sink in net.iter_sinks(router.netlist) {
+            let end: GridCellPosition =
+                Position::new(sink.x as i32, sink.y as i32, sink.z as i32).try_into()?;
+            let direction = match router.known_pins.get(&end) {
+                Some(pin) => pin.direction,
+                None => continue,
+            };
+            let path = match router.detail_router.trace_route(end, direction, id) {
+                Ok(path) => path,
+                Err(_) => continue,
+            };
+            let unlabelled: Vec<_> = path
+                .into_iter()
+                .take_while(|(pos, _)| labelled.insert(*pos))
+                .collect();
+            for (pos, direction) in labels::label_cells(&unlabelled, interval, &mut since_label) {
+                if labels::splat_label(output, pos, direction, labels::sign_text(&name))? {
+                    labels += 1;
+                }
+            }
+        }
+    }
+    info!("Placed {} net labels", labels);
+
+    Ok(())
+}
+
+/// Log the slowest path through the design, and whether it fits in the requested clock period
+fn log_timing(config: &Config, netlist: &Netlist, timing: &TimingReport) {
+    match timing.max_clock() {
+        Some(hz) => info!(
+            "Slowest path takes {} ticks, the design can be clocked at up to {:.2} Hz",
+            timing.worst_ticks, hz
+        ),
+        None => info!("No path through the design takes any time"),
+    }
+    for connection in timing.worst_path.iter() {
+        info!(
+            "  cell {} -> net {} ({} ticks of wire{}) -> cell {} ({} ticks)",
+            netlist.describe_cell(connection.driver_cell),
+            connection.net,
+            connection.wire_ticks,
+            if connection.routed { "" } else { ", estimated" },
+            netlist.describe_cell(connection.sink_cell),
+            timing.cell_ticks[connection.sink_cell]
+        );
+    }
+    if !timing.loops.is_empty() {
+        warn!(
+            "Cut combinational loops at cells {:?} for timing analysis",
+            timing
+                .loops
+                .iter()
+                .map(|cell_idx| netlist.describe_cell(*cell_idx))
+                .collect::<Vec<_>>()
+        );
+    }
+    if let Some(period) = config.clock_period {
+        if timing.worst_ticks > period {
+            warn!(
+                "Slowest path takes {} ticks, more than the {} tick clock period",
+                timing.worst_ticks, period
+            );
+        }
+    }
+}
+
+/// Hint for running out of memory, since the output and routing grid both scale with the volume
+/// of the design
+const MEMORY_HINT: &str = "reduce --tiers, or place the design in a smaller area";
+
+/// Size of the output for `design`, see [`placement_check::output_extents`]
+fn output_extents(
+    config: &Config,
+    design: &PlacedDesign,
+    structure_cache: &StructureCache,
+) -> Result<[u32; 3]> {
+    if GEN_TEST_SQUARES {
+        let size = 2 * 7 * 4;
+        return Ok([size, 16, size]);
+    }
+    placement_check::output_extents(design, structure_cache, config.tiers)
+}
+
+fn build_output(extents: [u32; 3], memory: &mut MemoryBudget) -> Result<BlockStorage> {
+    let [sx, sy, sz] = extents;
+    memory.reserve(
+        "Output",
+        BlockStorage::allocation_size(sx, sy, sz),
+        MEMORY_HINT,
+    )?;
+    Ok(BlockStorage::new(sx, sy, sz))
+}
+
+/// Size of the detail router's grid for routing `output`, in grid cells. It has as many tiers as
+/// the output, including the roof if there's I/O on it.
+fn routing_grid_size(output: &BlockStorage) -> (u32, u32, u32) {
+    let extents = output.extents();
+    (
+        extents[0] + (WIRE_GRID_SCALE as u32 - 1) / WIRE_GRID_SCALE as u32,
+        extents[1] / BLOCKS_PER_TIER * LAYERS_PER_TIER,
+        extents[2] + (WIRE_GRID_SCALE as u32 - 1) / WIRE_GRID_SCALE as u32,
+    )
+}
+
+fn load_placed_design(config: &Config) -> Result<PlacedDesign> {
+    let mut design = read_placed_design(&config.input_file)?;
+
+    if let Some(ref path) = config.timing_constraints_file {
+        let constraints = TimingConstraints::load(path)?;
+        for (name, metadata) in design.nets.iter_mut() {
+            if let Some(criticality) = constraints.criticality(name) {
+                metadata.attributes.insert(
+                    CRITICALITY_ATTRIBUTE.to_owned(),
+                    Parameter {
+                        value: Some(Value::Str(criticality.to_string())),
+                    },
+                );
+            }
+        }
+    }
+
+    Ok(design)
+}
+
+/// Minecraft data version to tag the output with, going by the cells it was built from
+fn output_data_version(structure_cache: &StructureCache) -> i32 {
+    structure_cache
+        .data_version()
+        .unwrap_or(anvil::DEFAULT_DATA_VERSION)
+}
+
+/// Write the output file, if the config asks for one
+fn write_output(
+    config: &Config,
+    structure_cache: &StructureCache,
+    output_structure: &BlockStorage,
+) -> Result<()> {
+    match config.output_file {
+        Some(ref output_file) => write_structure(output_file, structure_cache, output_structure),
+        None => Ok(()),
+    }
+}
+
+/// Write `output_structure` to `path`, as a WorldEdit schematic if it has a `.schem` extension, as
+/// gzipped structure JSON if it has a `.gz` extension and as structure JSON otherwise.
+fn write_structure(
+    path: &Path,
+    structure_cache: &StructureCache,
+    output_structure: &BlockStorage,
+) -> Result<()> {
+    if path.extension() != Some(schematic::SCHEMATIC_EXTENSION.as_ref()) {
+        return mcpnr_common::block_storage::stream::write_rle_json_file(output_structure, path)
+            .with_context(|| anyhow!("Failed to write to output file {:?}", path));
+    }
+
+    let outf = std::fs::File::create(path)
+        .with_context(|| anyhow!("Failed to open/create output file {:?}", path))?;
+    let outf = std::io::BufWriter::new(outf);
+    schematic::write_schematic(output_structure, outf, output_data_version(structure_cache))
+        .with_context(|| anyhow!("Failed to write to output file {:?}", path))
+}
+
+fn write_world(
+    config: &Config,
+    structure_cache: &StructureCache,
+    output_structure: &BlockStorage,
+) -> Result<()> {
+    let world = match config.world {
+        Some(ref world) => world,
+        None => return Ok(()),
+    };
+
+    let data_version = output_data_version(structure_cache);
+    anvil::export_world(output_structure, world, config.world_offset, data_version)
+        .with_context(|| anyhow!("Failed to write to world save {:?}", world))?;
+    Ok(())
+}
+
+/// Fan-out limits of the techlib's cells, with the default from the config if it has one
+fn load_fanout_limits(config: &Config) -> Result<FanoutLimits> {
+    let mut limits = FanoutLimits::load(&config.fanout_limits_file)?;
+    if config.max_fanout.is_some() {
+        limits.default = config.max_fanout;
+    }
+    Ok(limits)
+}
+
+/// Copy the small drivers of nets with more sinks than the config's threshold, if there is one
+fn replicate_drivers(
+    config: &Config,
+    design: &mut PlacedDesign,
+    structure_cache: &StructureCache,
+) -> Result<()> {
+    let threshold = match config.replicate_above {
+        Some(threshold) => threshold,
+        None => return Ok(()),
+    };
+    let extents = output_extents(config, design, structure_cache)?;
+    let copies =
+        replication::replicate_drivers(design, structure_cache, threshold, extents, config.tiers)
+            .context("Replicate drivers")?;
+    if copies > 0 {
+        info!("Added {} copies of high fan-out drivers", copies);
+    }
+    Ok(())
+}
+
+/// Buffer the long nets and the nets with more sinks than their drivers can drive, if the config
+/// gives a cell to do it with. The buffer cell's structure is added to `structure_cache`.
+fn buffer_nets(
+    config: &Config,
+    design: &mut PlacedDesign,
+    structure_cache: &mut StructureCache,
+    rules: &BlockerRules,
+    fanout_limits: &FanoutLimits,
+) -> Result<()> {
+    let buffer_cell = match config.buffer_cell {
+        Some(ref buffer_cell) => buffer_cell,
+        None => return Ok(()),
+    };
+    structure_cache
+        .load_type(&config.structure_directory, buffer_cell, rules)
+        .with_context(|| anyhow!("Load buffer cell {:?}", buffer_cell))?;
+    // Buffers go in the space the output has anyway
+    let extents = output_extents(config, design, structure_cache)?;
+    let options = BufferOptions {
+        buffer_type: buffer_cell,
+        fanout_limits,
+        max_wire_length: config.max_wire_length,
+    };
+    let report =
+        buffering::insert_buffers(design, structure_cache, &options, extents, config.tiers)
+            .context("Insert buffers")?;
+    if report != BufferReport::default() {
+        info!(
+            "Inserted {} repeaters on long nets and {} buffers on high fan-out nets",
+            report.repeaters, report.fanout_buffers
+        );
+    }
+    Ok(())
+}
+
+/// Insert buffers in the input design and write it out again, for routing later
+fn run_buffer(config: &Config, output: &Path) -> Result<()> {
+    ensure!(
+        config.buffer_cell.is_some() || config.replicate_above.is_some(),
+        "Buffering needs a cell to buffer with or drivers to copy, pass --buffer-cell or \
+         --replicate-above"
+    );
+    let mut placed_design = load_placed_design(config)?;
+
+    let rules = BlockerRules::load(&config.blocker_rules_file)?;
+    let mut structure_cache =
+        StructureCache::load_available(&config.structure_directory, &placed_design, &rules)?;
+    let fanout_limits = load_fanout_limits(config)?;
+    replicate_drivers(config, &mut placed_design, &structure_cache)?;
+    buffer_nets(
+        config,
+        &mut placed_design,
+        &mut structure_cache,
+        &rules,
+        &fanout_limits,
+    )?;
+    check_placement(config, &placed_design, &structure_cache)?;
+
+    placed_design
+        .provenance
+        .push(routing_provenance(config).context("Record provenance")?);
+    write_placed_design(&placed_design, output)
+}
+
+/// Check the connectivity of the netlist and warn about any problems, failing on them if the
+/// config is strict
+fn check_netlist(
+    config: &Config,
+    design: &PlacedDesign,
+    netlist: &Netlist,
+    structure_cache: &StructureCache,
+    fanout_limits: &FanoutLimits,
+) -> Result<NetlistCheck> {
+    let check = NetlistCheck::new(design, netlist, structure_cache, fanout_limits)
+        .context("Check netlist")?;
+    check.log(netlist);
+    info!(
+        "Netlist check found {} undriven nets, {} nets without sinks, {} floating inputs and {} \
+         overloaded drivers",
+        check.undriven_nets.len(),
+        check.sinkless_nets.len(),
+        check.floating_inputs.len(),
+        check.overloaded_drivers.len()
+    );
+    if config.strict {
+        check.ensure_clean()?;
+    }
+    Ok(check)
+}
+
+/// Check every cell can be splatted where it's placed, failing with a list of every problem if
+/// not. Returns the size of the output the cells were checked against.
+fn check_placement(
+    config: &Config,
+    design: &PlacedDesign,
+    structure_cache: &StructureCache,
+) -> Result<[u32; 3]> {
+    let extents = output_extents(config, design, structure_cache)?;
+    let check = PlacementCheck::new(design, structure_cache, extents, config.tiers)
+        .context("Check placement")?;
+    info!(
+        "Placement check found {} problems in {} cells",
+        check.problem_count(),
+        design.cells.len()
+    );
+    check.ensure_clean(design)?;
+    Ok(extents)
+}
+
+fn run_verify(config: &Config) -> Result<()> {
+    let mut placed_design = load_placed_design(config)?;
+
+    let rules = BlockerRules::load(&config.blocker_rules_file)?;
+    let mut structure_cache =
+        StructureCache::load_available(&config.structure_directory, &placed_design, &rules)?;
+    let fanout_limits = load_fanout_limits(config)?;
+    replicate_drivers(config, &mut placed_design, &structure_cache)?;
+    buffer_nets(
+        config,
+        &mut placed_design,
+        &mut structure_cache,
+        &rules,
+        &fanout_limits,
+    )?;
+    check_placement(config, &placed_design, &structure_cache)?;
+    let netlist = netlist::Netlist::new(&placed_design, &structure_cache)?;
+    check_netlist(
+        config,
+        &placed_design,
+        &netlist,
+        &structure_cache,
+        &fanout_limits,
+    )?;
+
+    info!(
+        "Design verified: {} cells, {} nets, {} pins",
+        placed_design.cells.len(),
+        netlist.iter_nets().count(),
+        netlist.iter_pins().count(),
+    );
+
+    Ok(())
+}
+
+/// Run the splat and (optionally) routing phases, writing the result if the config requests it.
+fn run_flow(config: &Config, route: bool) -> Result<()> {
+    let start = Instant::now();
+    let mut placed_design = load_placed_design(config)?;
+
+    let rules = BlockerRules::load(&config.blocker_rules_file)?;
+    let mut structure_cache =
+        StructureCache::load_available(&config.structure_directory, &placed_design, &rules)?;
+    let fanout_limits = load_fanout_limits(config)?;
+    replicate_drivers(config, &mut placed_design, &structure_cache)?;
+    buffer_nets(
+        config,
+        &mut placed_design,
+        &mut structure_cache,
+        &rules,
+        &fanout_limits,
+    )?;
+    // Checked before anything else, so the netlist and splat only see cells that fit
+    let extents = check_placement(config, &placed_design, &structure_cache)?;
+    let netlist = netlist::Netlist::new(&placed_design, &structure_cache)?;
+    let netlist_check = check_netlist(
+        config,
+        &placed_design,
+        &netlist,
+        &structure_cache,
+        &fanout_limits,
+    )?;
+    let mut memory = MemoryBudget::new(config.max_memory);
+    let mut output_structure = build_output(extents, &mut memory)?;
+    if route {
+        // Checked before the splat, so a design that's too big to route fails straight away
+        let (size_x, size_y, size_z) = routing_grid_size(&output_structure);
+        match DetailRouter::allocation_size(size_x, size_y, size_z) {
+            Some(bytes) => memory.reserve("Routing grid", bytes, MEMORY_HINT)?,
+            None => info!(
+                "Routing grid is {}x{}x{}, paging it instead of allocating it up front",
+                size_x, size_y, size_z
+            ),
+        }
+        if config.congestion_map_file.is_some() {
+            if let Some(bytes) = DetailRouter::congestion_allocation_size(size_x, size_y, size_z) {
+                memory.reserve("Congestion map", bytes, MEMORY_HINT)?;
+            }
+        }
+    }
+
+    structure_cache.build_palette_maps(&mut output_structure)?;
+
+    do_splat(
+        &placed_design,
+        &structure_cache,
+        &config.markers,
+        &mut output_structure,
+    )?;
+    let infrastructure =
+        infrastructure::splat_infrastructure(&config.infrastructure, &mut output_structure)
+            .context("Add lights and corridors")?;
+    if config.infrastructure.light_spacing.is_some() {
+        info!("Placed {} lights", infrastructure.lights);
+    }
+    if config.infrastructure.corridor_spacing.is_some() {
+        info!("Left {} corridors", infrastructure.corridors);
+    }
+    if infrastructure.obstructed > 0 {
+        warn!(
+            "{} blocks of cells are in the corridors, which can't be walked through there",
+            infrastructure.obstructed
+        );
+    }
+
+    // Rerouting starts from the earlier output, without the wires of the nets being rerouted
+    let reroute = match (route, config.eco.as_ref()) {
+        (true, Some(eco_config)) => {
+            let nets = eco::resolve_nets(&netlist, &eco_config.nets)?;
+            let [sx, sy, sz] = *output_structure.extents();
+            memory.reserve(
+                "Earlier output",
+                BlockStorage::allocation_size(sx, sy, sz),
+                MEMORY_HINT,
+            )?;
+            output_structure = eco::load_previous_output(
+                &eco_config.previous_output,
+                &output_structure,
+                &netlist,
+                &nets,
+            )?;
+            info!(
+                "Rerouting {} nets on top of {:?}",
+                nets.len(),
+                eco_config.previous_output
+            );
+            Some(nets)
+        }
+        _ => None,
+    };
+
+    let routing_report = if route {
+        Some(do_route(
+            config,
+            &placed_design,
+            &netlist,
+            &structure_cache,
+            &mut output_structure,
+            &infrastructure,
+            reroute.as_ref(),
+        )?)
+    } else {
+        None
+    };
+
+    // Drawn after routing and only in the space left over, so the router never has to account
+    // for them
+    let tier_markers = Splatter::new(&mut output_structure, &structure_cache)
+        .draw_tier_markers(&mut output_structure, &config.markers)
+        .context("Error during tier marker draw")?;
+    if tier_markers > 0 {
+        info!("Placed {} blocks of tier markers", tier_markers);
+    }
+
+    let mut provenance = placed_design.provenance.clone();
+    provenance.push(routing_provenance(config).context("Record provenance")?);
+    output_structure.set_provenance(provenance);
+
+    write_output(config, &structure_cache, &output_structure)?;
+    write_world(config, &structure_cache, &output_structure)?;
+
+    if let (Some(output_file), true) = (config.output_file.as_ref(), config.write_summary) {
+        let summary = DesignSummary::new(
+            config,
+            &placed_design,
+            &netlist,
+            &structure_cache,
+            &output_structure,
+            &netlist_check,
+            routing_report.as_ref(),
+        )
+        .context("Build design summary")?;
+        summary::write_summary(output_file, &summary)?;
+    }
+
+    if let (Some(path), Some(report)) = (config.results_file.as_ref(), routing_report.as_ref()) {
+        log_results(config, path, report, start.elapsed())
+            .with_context(|| anyhow!("Log results to {:?}", path))?;
+    }
+
+    Ok(())
+}
+
+/// Hash of the options that affect the routing, see [`results::config_hash`]
+fn config_hash(config: &Config) -> String {
+    // Runs of the same options on different designs, or to different outputs, hash the same
+    let options = Config {
+        input_file: PathBuf::new(),
+        output_file: None,
+        world: None,
+        results_file: None,
+        ..config.clone()
+    };
+    results::config_hash(&options)
+}
+
+/// Provenance of the routed output, recording the options and inputs it was routed from
+fn routing_provenance(config: &Config) -> Result<Provenance> {
+    let mut inputs = vec![config.input_file.as_path()];
+    inputs.extend(config.timing_constraints_file.as_deref());
+    inputs.extend(config.eco.as_ref().map(|eco| eco.previous_output.as_path()));
+    Provenance::new(
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        config_hash(config),
+        &inputs,
+        Vec::new(),
+    )
+}
+
+/// Append the results of routing to the results file at `path`
+fn log_results(
+    config: &Config,
+    path: &Path,
+    report: &RoutingReport,
+    runtime: Duration,
+) -> Result<()> {
+    let mut record = RunRecord::new("routing", &config.input_file, config_hash(config), runtime);
+    record.routed_wirelength = Some(report.wire_blocks as u64);
+    record.failures = Some(report.unrouted_nets.len() as u64);
+    results::append_run(path, &record)
+}
+
+fn run_report(args: &ReportArgs) -> Result<()> {
+    let mut runs = results::read_runs(&args.results)?;
+    if let Some(ref design) = args.design {
+        runs.retain(|run| &run.design == design);
+    }
+    results::write_report(&mut std::io::stdout().lock(), &runs, args.last)
+        .context("Write results report")
+}
+
+/// Route the placed design `config.input_file` and write the outputs `config` asks for, as the
+/// `route` subcommand does
+pub fn route(config: &Config) -> Result<()> {
+    run_flow(config, true)
+}
+
+/// Run a subcommand of the `mcpnr-routing` binary
+pub fn run(command: RouterCommand) -> Result<()> {
+    match command {
+        RouterCommand::Route(args) => route(&Config::from_route_args(&args)),
+        RouterCommand::Verify(args) => run_verify(&Config::from_common_args(&args)),
+        RouterCommand::Export(args) => run_flow(&Config::from_output_args(&args), false),
+        RouterCommand::Gui(_) => Err(anyhow!("The router does not have a GUI yet")),
+        RouterCommand::DryRun(args) => run_flow(&Config::from_common_args(&args), true),
+        RouterCommand::Doctor(args) => doctor::run_doctor(&args),
+        RouterCommand::Buffer(args) => {
+            run_buffer(&Config::from_common_args(&args.common), &args.output)
+        }
+        RouterCommand::Diff(args) => diff::run_diff(&args),
+        RouterCommand::Report(args) => run_report(&args),
+    }
+}
@@ -1,43 +1,84 @@
-use std::collections::HashMap;
+use std::collections::{hash_map::Entry, HashMap, HashSet};
 
 use anyhow::{anyhow, ensure, Context, Result};
-use itertools::Itertools;
-use mcpnr_common::protos::mcpnr::{signal::{Type, ConstantDriver}, PlacedDesign};
+use itertools::{Itertools, MinMaxResult};
+use mcpnr_common::block_storage::Direction;
+use mcpnr_common::io_macros;
+use mcpnr_common::protos::mcpnr::{
+    parameter::Value,
+    signal::{ConstantDriver, Type},
+    Parameter, PlacedDesign,
+};
+use mcpnr_common::timing_constraints::{parse_criticality, CRITICALITY_ATTRIBUTE};
 
 use crate::structure_cache::StructureCache;
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
-pub enum PinDirection {
-    Input,
-    Output,
-}
+pub use mcpnr_common::minecraft_types::PinDirection;
+
+/// Net attribute asking for a net to be distributed as a balanced tree, with at most this many
+/// redstone ticks between the first and last sink to see a change, e.g.
+/// `(* mcpnr_max_skew = 1 *) wire clk;`
+pub const MAX_SKEW_ATTRIBUTE: &str = "mcpnr_max_skew";
+
+/// Net attribute putting a net ahead of ones with a lower priority when routing with
+/// `--net-order priority`, e.g. `(* mcpnr_route_priority = 10 *) wire bus_enable;`
+pub const PRIORITY_ATTRIBUTE: &str = "mcpnr_route_priority";
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct PinMetadata {
     pub offset_x: u32,
     pub offset_y: u32,
     pub offset_z: u32,
+    /// How much weaker than full strength an output drives its wire, or how much signal an input
+    /// needs to see, as given on the pin's sign.
     pub sig_derating: u32,
     pub direction: PinDirection,
+    /// Direction wires connect to the pin from, if the cell's structure says. Pins marked by
+    /// blocks are found in the splatted design anyway, this is for the ones that aren't.
+    pub facing: Option<Direction>,
+    /// Offsets of other pins on the cell that carry the same output signal, with their facings.
+    /// The router may connect each sink to whichever of these pins is most convenient.
+    pub equivalent_pins: Vec<([u32; 3], Option<Direction>)>,
 }
 
 #[derive(Debug)]
 pub struct Pin {
+    /// Index of the cell the pin belongs to in the placed design
+    pub cell: usize,
     pub x: u32,
     pub y: u32,
     pub z: u32,
     pub direction: PinDirection,
+    /// See [`PinMetadata::facing`]
+    pub facing: Option<Direction>,
+    /// See [`PinMetadata::sig_derating`]
+    pub sig_derating: u32,
 }
 
 #[derive(Default, Debug)]
 pub struct Net {
     drivers: Vec<u32>,
+    /// Pins equivalent to one of the drivers, which are not drivers in their own right
+    alternate_drivers: Vec<u32>,
     sinks: Vec<u32>,
 }
 
 pub struct Netlist {
     pins: Vec<Pin>,
     nets: HashMap<i64, Net>,
+    /// Human-readable name for each net, taken from the design's net metadata
+    net_names: HashMap<i64, String>,
+    /// Nets making up each multi-bit signal in the design, in bit order. A net is only ever part
+    /// of one bus.
+    buses: Vec<Vec<i64>>,
+    /// Skew bound in redstone ticks for nets with the [`MAX_SKEW_ATTRIBUTE`] attribute
+    max_skew: HashMap<i64, u32>,
+    /// Criticality of nets with the [`CRITICALITY_ATTRIBUTE`] attribute
+    criticality: HashMap<i64, f32>,
+    /// Routing priority of nets with the [`PRIORITY_ATTRIBUTE`] attribute
+    priority: HashMap<i64, u32>,
+    /// How to refer to each cell of the design in messages
+    cell_descriptions: Vec<String>,
 }
 
 impl Netlist {
@@ -45,21 +86,38 @@ impl Netlist {
         let mut pins = Vec::with_capacity(design.cells.len() * 2);
         let mut design_nets: HashMap<i64, Net> = HashMap::default();
 
-        for cell in design.cells.iter() {
+        for (cell_idx, cell) in design.cells.iter().enumerate() {
             let (base_x, base_y, base_z) = cell
                 .pos
                 .as_ref()
                 .map(|p| (p.x, p.y, p.z))
                 .unwrap_or((0, 0, 0));
             for (port, cell_nets) in cell.connection.iter() {
+                if let Some(structure) = structure_cache.get(&cell.r#type) {
+                    let width = structure.port_width(port).ok_or_else(|| {
+                        anyhow!(
+                            "Unknown port {:?} of cell {}",
+                            port,
+                            cell.describe(cell_idx)
+                        )
+                    })?;
+                    ensure!(
+                        width == cell_nets.signal.len(),
+                        "Port {:?} of cell {} has {} pins, but is connected to {} bits",
+                        port,
+                        cell.describe(cell_idx),
+                        width,
+                        cell_nets.signal.len()
+                    );
+                }
                 for (bit_idx, net) in cell_nets.signal.iter().enumerate() {
                     let pin_metadata = pin_metadata(structure_cache, &cell.r#type, &port, bit_idx)
                         .with_context(|| {
                             anyhow!(
-                                "Error while getting pin metadata for pin {}[{}] (cell {:?})",
+                                "Error while getting pin metadata for pin {}[{}] of cell {}",
                                 port,
                                 bit_idx,
-                                cell,
+                                cell.describe(cell_idx),
                             )
                         })?;
                     let net_idx = match net.r#type {
@@ -70,13 +128,12 @@ impl Netlist {
                             let _ = ConstantDriver::from_i32(c).ok_or_else(|| anyhow!("Unknown constant driver type {c}"))?;
                             continue;
                         }
-                        // TODO: plumb through cell names so we can report better errors here and elsewhere
                         _ => return Err(anyhow!(
-                            "Unsupported net index type {:?} processing pin {}[{}] (cell: {:?})",
+                            "Unsupported net index type {:?} processing pin {}[{}] of cell {}",
                             net.r#type,
                             port,
                             bit_idx,
-                            cell
+                            cell.describe(cell_idx)
                         )),
                     };
 
@@ -85,10 +142,13 @@ impl Netlist {
                         .try_into()
                         .context("Pin count exceeds u32::MAX")?;
                     pins.push(Pin {
+                        cell: cell_idx,
                         x: base_x + pin_metadata.offset_x,
                         y: base_y + pin_metadata.offset_y,
                         z: base_z + pin_metadata.offset_z,
                         direction: pin_metadata.direction,
+                        facing: pin_metadata.facing,
+                        sig_derating: pin_metadata.sig_derating,
                     });
                     let net = design_nets.entry(net_idx).or_default();
 
@@ -96,22 +156,186 @@ impl Netlist {
                         PinDirection::Input => net.sinks.push(pin_idx),
                         PinDirection::Output => net.drivers.push(pin_idx),
                     }
+
+                    for ([offset_x, offset_y, offset_z], facing) in pin_metadata.equivalent_pins {
+                        let pin_idx = pins
+                            .len()
+                            .try_into()
+                            .context("Pin count exceeds u32::MAX")?;
+                        pins.push(Pin {
+                            cell: cell_idx,
+                            x: base_x + offset_x,
+                            y: base_y + offset_y,
+                            z: base_z + offset_z,
+                            direction: pin_metadata.direction,
+                            facing,
+                            sig_derating: pin_metadata.sig_derating,
+                        });
+                        net.alternate_drivers.push(pin_idx);
+                    }
                 }
             }
         }
 
         for net in design_nets.values_mut() {
             net.drivers.sort();
+            net.alternate_drivers.sort();
             net.sinks.sort();
         }
 
+        // Prefer user-visible names over automatically generated ones, and break ties by picking
+        // the lexicographically smallest name so the result doesn't depend on map ordering.
+        let mut net_names: HashMap<i64, (bool, String)> = HashMap::new();
+        for (name, metadata) in design.nets.iter() {
+            let bits = match metadata.bits.as_ref() {
+                Some(bits) => bits,
+                None => continue,
+            };
+            for (bit_idx, bit) in bits.signal.iter().enumerate() {
+                if let Some(Type::Id(net_idx)) = bit.r#type {
+                    let name = if bits.signal.len() > 1 {
+                        format!("{}[{}]", name, bit_idx)
+                    } else {
+                        name.clone()
+                    };
+                    let candidate = (metadata.hide_name, name);
+                    match net_names.entry(net_idx) {
+                        Entry::Occupied(mut o) => {
+                            if &candidate < o.get() {
+                                o.insert(candidate);
+                            }
+                        }
+                        Entry::Vacant(v) => {
+                            v.insert(candidate);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut buses = Vec::new();
+        let mut in_bus = HashSet::new();
+        for (_, metadata) in design.nets.iter().sorted_by_key(|(name, _)| *name) {
+            let bits = match metadata.bits.as_ref() {
+                Some(bits) if !metadata.hide_name && bits.signal.len() > 1 => bits,
+                _ => continue,
+            };
+            let members: Vec<i64> = bits
+                .signal
+                .iter()
+                .filter_map(|bit| match bit.r#type {
+                    Some(Type::Id(net_idx)) => Some(net_idx),
+                    _ => None,
+                })
+                .filter(|net_idx| design_nets.contains_key(net_idx) && !in_bus.contains(net_idx))
+                .unique()
+                .collect();
+            if members.len() > 1 {
+                in_bus.extend(members.iter().copied());
+                buses.push(members);
+            }
+        }
+
+        // If a net has several names with a skew bound the tightest one wins
+        let mut max_skew: HashMap<i64, u32> = HashMap::new();
+        for (name, metadata) in design.nets.iter() {
+            let skew = match metadata.attributes.get(MAX_SKEW_ATTRIBUTE) {
+                Some(skew) => parse_u32(skew, "tick count")
+                    .with_context(|| anyhow!("Max skew for net {:?}", name))?,
+                None => continue,
+            };
+            for bit in metadata.bits.iter().flat_map(|bits| bits.signal.iter()) {
+                if let Some(Type::Id(net_idx)) = bit.r#type {
+                    let entry = max_skew.entry(net_idx).or_insert(skew);
+                    *entry = (*entry).min(skew);
+                }
+            }
+        }
+
+        // If a net has several names with a criticality the most critical one wins
+        let mut criticality: HashMap<i64, f32> = HashMap::new();
+        for (name, metadata) in design.nets.iter() {
+            let net_criticality = match metadata.attributes.get(CRITICALITY_ATTRIBUTE) {
+                Some(value) => parse_criticality_parameter(value)
+                    .with_context(|| anyhow!("Criticality for net {:?}", name))?,
+                None => continue,
+            };
+            for bit in metadata.bits.iter().flat_map(|bits| bits.signal.iter()) {
+                if let Some(Type::Id(net_idx)) = bit.r#type {
+                    let entry = criticality.entry(net_idx).or_insert(net_criticality);
+                    *entry = entry.max(net_criticality);
+                }
+            }
+        }
+
+        // If a net has several names with a priority the highest one wins
+        let mut priority: HashMap<i64, u32> = HashMap::new();
+        for (name, metadata) in design.nets.iter() {
+            let net_priority = match metadata.attributes.get(PRIORITY_ATTRIBUTE) {
+                Some(value) => parse_u32(value, "priority")
+                    .with_context(|| anyhow!("Routing priority for net {:?}", name))?,
+                None => continue,
+            };
+            for bit in metadata.bits.iter().flat_map(|bits| bits.signal.iter()) {
+                if let Some(Type::Id(net_idx)) = bit.r#type {
+                    let entry = priority.entry(net_idx).or_insert(net_priority);
+                    *entry = (*entry).max(net_priority);
+                }
+            }
+        }
+
         pins.shrink_to_fit();
         Ok(Netlist {
             pins,
             nets: design_nets,
+            buses,
+            max_skew,
+            criticality,
+            priority,
+            cell_descriptions: design
+                .cells
+                .iter()
+                .enumerate()
+                .map(|(idx, cell)| cell.describe(idx))
+                .collect(),
+            net_names: net_names
+                .into_iter()
+                .map(|(k, (_, name))| (k, name))
+                .collect(),
         })
     }
 
+    /// Look up the human-readable name of a net, if the design provided one
+    pub fn net_name(&self, net_idx: i64) -> Option<&str> {
+        self.net_names.get(&net_idx).map(String::as_str)
+    }
+
+    /// How to refer to the cell at `cell_idx` of the placed design in messages, by its instance
+    /// name if it has one
+    pub fn describe_cell(&self, cell_idx: usize) -> &str {
+        &self.cell_descriptions[cell_idx]
+    }
+
+    /// Nets of each multi-bit signal, in bit order
+    pub fn iter_buses(&self) -> impl Iterator<Item = &[i64]> {
+        self.buses.iter().map(Vec::as_slice)
+    }
+
+    /// Skew bound in redstone ticks if the net should be distributed as a balanced tree
+    pub fn max_skew(&self, net_idx: i64) -> Option<u32> {
+        self.max_skew.get(&net_idx).copied()
+    }
+
+    /// How timing critical a net is, from 0 to 1. Nets without a criticality are 0.
+    pub fn criticality(&self, net_idx: i64) -> f32 {
+        self.criticality.get(&net_idx).copied().unwrap_or(0.0)
+    }
+
+    /// Routing priority of a net, from the [`PRIORITY_ATTRIBUTE`] attribute. Nets without one are 0.
+    pub fn priority(&self, net_idx: i64) -> u32 {
+        self.priority.get(&net_idx).copied().unwrap_or(0)
+    }
+
     pub fn iter_pins(&self) -> impl Iterator<Item = &Pin> {
         self.pins.iter()
     }
@@ -129,15 +353,67 @@ impl Net {
         self.drivers.iter().map(|idx| &parent.pins[*idx as usize])
     }
 
+    /// Pins that carry the same signal as a driver, without being separate drivers themselves
+    pub fn iter_alternate_drivers<'netlist>(
+        &'netlist self,
+        parent: &'netlist Netlist,
+    ) -> impl Iterator<Item = &'netlist Pin> {
+        self.alternate_drivers
+            .iter()
+            .map(|idx| &parent.pins[*idx as usize])
+    }
+
     pub fn iter_sinks<'netlist>(
         &'netlist self,
         parent: &'netlist Netlist,
     ) -> impl Iterator<Item = &'netlist Pin> {
         self.sinks.iter().map(|idx| &parent.pins[*idx as usize])
     }
+
+    pub fn fanout(&self) -> usize {
+        self.sinks.len()
+    }
+
+    /// Half the perimeter of the box around the drivers and sinks of the net in X and Z, in
+    /// blocks. Zero for nets without pins.
+    pub fn bounding_box_size(&self, parent: &Netlist) -> u32 {
+        let pins: Vec<&Pin> = self
+            .iter_drivers(parent)
+            .chain(self.iter_sinks(parent))
+            .collect();
+        let span = |coords: MinMaxResult<u32>| match coords {
+            MinMaxResult::MinMax(min, max) => max - min,
+            _ => 0,
+        };
+        span(pins.iter().map(|pin| pin.x).minmax()) + span(pins.iter().map(|pin| pin.z).minmax())
+    }
 }
 
-fn pin_metadata(
+/// Parse an attribute holding a count, like redstone ticks. Yosys writes integer attributes as 32
+/// bit binary strings, but plain decimal is accepted too.
+fn parse_u32(value: &Parameter, what: &str) -> Result<u32> {
+    match value.value {
+        Some(Value::Int(i)) => u32::try_from(i).with_context(|| anyhow!("Invalid {} {}", what, i)),
+        Some(Value::Str(ref s)) if s.len() == 32 && s.chars().all(|c| c == '0' || c == '1') => {
+            Ok(u32::from_str_radix(s, 2)?)
+        }
+        Some(Value::Str(ref s)) => s
+            .trim()
+            .parse()
+            .with_context(|| anyhow!("Invalid {} {:?}", what, s)),
+        None => Err(anyhow!("Attribute has no value")),
+    }
+}
+
+fn parse_criticality_parameter(value: &Parameter) -> Result<f32> {
+    match value.value {
+        Some(Value::Int(i)) => parse_criticality(&i.to_string()),
+        Some(Value::Str(ref s)) => parse_criticality(s),
+        None => Err(anyhow!("Attribute has no value")),
+    }
+}
+
+pub fn pin_metadata(
     structure_cache: &StructureCache,
     cell_type: &str,
     port: &str,
@@ -150,12 +426,15 @@ fn pin_metadata(
                 "MCPNR_LIGHTS only supports an \"I\" port (got {:?})",
                 port
             );
+            let [offset_x, offset_y, offset_z] = io_macros::pin_offset(bit_idx as u32);
             Ok(PinMetadata {
-                offset_x: (bit_idx as u32) * 2,
-                offset_y: 1,
-                offset_z: 2,
+                offset_x,
+                offset_y,
+                offset_z,
                 sig_derating: 0,
                 direction: PinDirection::Input,
+                facing: None,
+                equivalent_pins: Vec::new(),
             })
         }
         "MCPNR_SWITCHES" => {
@@ -164,28 +443,113 @@ fn pin_metadata(
                 "MCPNR_SWITCHES only supports an \"O\" port (got {:?})",
                 port
             );
+            let [offset_x, offset_y, offset_z] = io_macros::pin_offset(bit_idx as u32);
             Ok(PinMetadata {
-                offset_x: (bit_idx as u32) * 2,
-                offset_y: 1,
-                offset_z: 2,
+                offset_x,
+                offset_y,
+                offset_z,
                 sig_derating: 0,
                 direction: PinDirection::Output,
+                facing: None,
+                equivalent_pins: Vec::new(),
             })
         }
-        _ => {
-            ensure!(
-                bit_idx == 0,
-                "NBT-style cells (like {:?}) only support single-bit input ports",
-                cell_type
-            );
-            structure_cache
-                .get(cell_type)
-                .ok_or_else(|| anyhow!("Unknown cell type {:?}", cell_type))
-                .and_then(|v| {
-                    v.pins.get(port).map(|v| v.clone()).ok_or_else(|| {
-                        anyhow!("Unknown port {:?} for cell type {:?}", port, cell_type)
-                    })
+        _ => structure_cache
+            .get(cell_type)
+            .ok_or_else(|| anyhow!("Unknown cell type {:?}", cell_type))?
+            .pin(port, bit_idx)
+            .cloned()
+            .with_context(|| anyhow!("Pin of cell type {:?}", cell_type)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mcpnr_common::blockers::BlockerRules;
+    use mcpnr_common::protos::mcpnr::{
+        parameter::Value, signal::Type, BitVector, NetMetadata, Parameter, PlacedDesign, Signal,
+    };
+
+    use super::{Netlist, PRIORITY_ATTRIBUTE};
+    use crate::structure_cache::StructureCache;
+    use crate::test_util::{cell, techlib};
+
+    fn bits(nets: &[i64]) -> BitVector {
+        BitVector {
+            signal: nets
+                .iter()
+                .map(|net| Signal {
+                    r#type: Some(Type::Id(*net)),
                 })
+                .collect(),
         }
     }
+
+    #[test]
+    fn measures_nets_for_ordering() -> anyhow::Result<()> {
+        let priority = |value: Value, nets: &[i64]| NetMetadata {
+            bits: Some(bits(nets)),
+            attributes: [(
+                PRIORITY_ATTRIBUTE.to_owned(),
+                Parameter { value: Some(value) },
+            )]
+            .into(),
+            ..Default::default()
+        };
+        let design = PlacedDesign {
+            cells: vec![
+                cell("", "MCPNR_SWITCHES", (0, 0, 0), &[("O", &[1])]),
+                cell("", "gate_not.nbt", (8, 0, 0), &[("A", &[1]), ("Y", &[2])]),
+                cell("", "gate_not.nbt", (40, 0, 0), &[("A", &[1]), ("Y", &[3])]),
+                cell("", "MCPNR_LIGHTS", (16, 0, 0), &[("I", &[2])]),
+            ],
+            nets: [
+                ("enable".to_owned(), priority(Value::Int(3), &[2])),
+                // The higher priority of the two names wins
+                ("a".to_owned(), priority(Value::Str("5".to_owned()), &[3])),
+                ("b".to_owned(), priority(Value::Int(7), &[3])),
+            ]
+            .into(),
+            ..Default::default()
+        };
+        let structure_cache = StructureCache::new(&techlib(), &design, &BlockerRules::builtin())?;
+        let netlist = Netlist::new(&design, &structure_cache)?;
+
+        let net = |net_idx: i64| &netlist.nets[&net_idx];
+        assert_eq!(net(1).fanout(), 2);
+        assert_eq!(net(2).fanout(), 1);
+        assert_eq!(net(3).fanout(), 0);
+        assert!(net(1).bounding_box_size(&netlist) > net(2).bounding_box_size(&netlist));
+        assert_eq!(net(3).bounding_box_size(&netlist), 0);
+
+        assert_eq!(netlist.priority(1), 0);
+        assert_eq!(netlist.priority(2), 3);
+        assert_eq!(netlist.priority(3), 7);
+
+        Ok(())
+    }
+    #[test]
+    fn port_widths_must_match_connections() -> anyhow::Result<()> {
+        let design = PlacedDesign {
+            cells: vec![
+                cell("", "MCPNR_SWITCHES", (0, 0, 0), &[("O", &[1])]),
+                cell(
+                    "",
+                    "gate_not.nbt",
+                    (8, 0, 0),
+                    &[("A", &[1, 3]), ("Y", &[2])],
+                ),
+            ],
+            ..Default::default()
+        };
+        let structure_cache = StructureCache::new(&techlib(), &design, &BlockerRules::builtin())?;
+        let error = Netlist::new(&design, &structure_cache).err().unwrap();
+        assert!(
+            format!("{}", error).contains("has 1 pins, but is connected to 2 bits"),
+            "{}",
+            error
+        );
+
+        Ok(())
+    }
 }
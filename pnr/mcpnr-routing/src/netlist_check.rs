@@ -0,0 +1,340 @@
+//! Checks on the connectivity of the netlist, run before routing so problems in the design turn up
+//! before the router has spent a long time on it.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use itertools::Itertools;
+use mcpnr_common::minecraft_types::PinDirection;
+use mcpnr_common::protos::mcpnr::{signal::ConstantDriver, signal::Type, PlacedDesign};
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::netlist::{pin_metadata, Netlist};
+use crate::structure_cache::{bus_bit, StructureCache};
+
+/// Why an input isn't driven by anything
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FloatingReason {
+    /// The cell's port isn't connected at all
+    Unconnected,
+    /// The port is tied to an undefined (`x`) or high impedance (`z`) constant
+    Undefined,
+}
+
+/// An input bit of a cell that nothing drives
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FloatingInput {
+    /// Index of the cell in the placed design
+    pub cell: usize,
+    pub port: String,
+    pub bit: usize,
+    pub reason: FloatingReason,
+}
+
+/// Name of the file in the techlib with the fan-out limits of its cells
+pub const FANOUT_LIMITS_FILE: &str = "fanout_limits.json";
+
+/// Most sinks an output of each cell type can drive before the cell stops behaving, like
+/// `{"default": 8, "cells": {"gate_not.nbt": 4}}`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub struct FanoutLimits {
+    /// Limit for cell types without one of their own, if there is one
+    #[serde(default)]
+    pub default: Option<usize>,
+    /// Limit for each cell type, by the name cells of that type have in the design. The I/O
+    /// macros can have one too.
+    #[serde(default)]
+    pub cells: BTreeMap<String, usize>,
+}
+
+impl FanoutLimits {
+    /// Load the limits in `path`. A missing file isn't an error, it just means there are no limits.
+    pub fn load(path: &Path) -> Result<Self> {
+        let limits: Self = match std::fs::File::open(path) {
+            Ok(file) => serde_json::from_reader(std::io::BufReader::new(file))
+                .with_context(|| anyhow!("Failed to parse fan-out limits {:?}", path))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => {
+                return Err(e).with_context(|| anyhow!("Failed to open fan-out limits {:?}", path))
+            }
+        };
+        ensure!(
+            limits.default != Some(0) && limits.cells.values().all(|limit| *limit > 0),
+            "Fan-out limits in {:?} must be at least 1",
+            path
+        );
+        Ok(limits)
+    }
+
+    /// Most sinks an output of a cell of type `cell_type` can drive, if it's limited
+    pub fn limit(&self, cell_type: &str) -> Option<usize> {
+        self.cells.get(cell_type).copied().or(self.default)
+    }
+}
+
+/// A driver with more sinks than its cell type's fan-out limit
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OverloadedDriver {
+    pub net: u32,
+    /// Index of the driving cell in the placed design
+    pub cell: usize,
+    pub fanout: usize,
+    pub limit: usize,
+}
+
+/// Connectivity problems found in a design
+#[derive(Clone, Debug, Default)]
+pub struct NetlistCheck {
+    /// Nets with sinks but no driver, in ascending order
+    pub undriven_nets: Vec<u32>,
+    /// Nets with a driver but no sinks, in ascending order. These are usually unused outputs, so
+    /// they're only worth a warning.
+    pub sinkless_nets: Vec<u32>,
+    /// Inputs that nothing drives, in cell order
+    pub floating_inputs: Vec<FloatingInput>,
+    /// Drivers of nets with more sinks than they can drive, in net order
+    pub overloaded_drivers: Vec<OverloadedDriver>,
+}
+
+impl NetlistCheck {
+    pub fn new(
+        design: &PlacedDesign,
+        netlist: &Netlist,
+        structure_cache: &StructureCache,
+        fanout_limits: &FanoutLimits,
+    ) -> Result<Self> {
+        let mut check = Self::default();
+
+        for (net_idx, net) in netlist.iter_nets() {
+            let net_idx = *net_idx as u32;
+            let driven = net.iter_drivers(netlist).next().is_some();
+            let sunk = net.iter_sinks(netlist).next().is_some();
+            match (driven, sunk) {
+                (false, true) => check.undriven_nets.push(net_idx),
+                (true, false) => check.sinkless_nets.push(net_idx),
+                _ => {}
+            }
+
+            for driver in net.iter_drivers(netlist) {
+                let limit = match fanout_limits.limit(&design.cells[driver.cell].r#type) {
+                    Some(limit) => limit,
+                    None => continue,
+                };
+                if net.fanout() > limit {
+                    check.overloaded_drivers.push(OverloadedDriver {
+                        net: net_idx,
+                        cell: driver.cell,
+                        fanout: net.fanout(),
+                        limit,
+                    });
+                }
+            }
+        }
+
+        for (cell_idx, cell) in design.cells.iter().enumerate() {
+            for (port, bits) in cell.connection.iter().sorted_by_key(|(port, _)| *port) {
+                for (bit, signal) in bits.signal.iter().enumerate() {
+                    let undefined = match signal.r#type {
+                        Some(Type::Constant(c)) => matches!(
+                            ConstantDriver::from_i32(c),
+                            Some(ConstantDriver::Z | ConstantDriver::X)
+                        ),
+                        _ => false,
+                    };
+                    if !undefined {
+                        continue;
+                    }
+                    let metadata = pin_metadata(structure_cache, &cell.r#type, port, bit)
+                        .with_context(|| {
+                            anyhow!("Pin {}[{}] of cell {}", port, bit, cell.describe(cell_idx))
+                        })?;
+                    if metadata.direction == PinDirection::Input {
+                        check.floating_inputs.push(FloatingInput {
+                            cell: cell_idx,
+                            port: port.clone(),
+                            bit,
+                            reason: FloatingReason::Undefined,
+                        });
+                    }
+                }
+            }
+
+            // The I/O macros have no structure, and a light that isn't connected is just off
+            let structure = match structure_cache.get(&cell.r#type) {
+                Some(structure) => structure,
+                None => continue,
+            };
+            for (port, bit) in structure
+                .pins
+                .iter()
+                .filter(|(_, pin)| pin.direction == PinDirection::Input)
+                .map(|(name, _)| bus_bit(name).unwrap_or((name, 0)))
+                .filter(|(port, _)| !cell.connection.contains_key(*port))
+                .sorted()
+            {
+                check.floating_inputs.push(FloatingInput {
+                    cell: cell_idx,
+                    port: port.to_owned(),
+                    bit,
+                    reason: FloatingReason::Unconnected,
+                });
+            }
+        }
+
+        Ok(check)
+    }
+
+    /// Whether there's anything that will keep the design from working once it's routed. Nets
+    /// without sinks don't count.
+    pub fn is_clean(&self) -> bool {
+        self.undriven_nets.is_empty()
+            && self.floating_inputs.is_empty()
+            && self.overloaded_drivers.is_empty()
+    }
+
+    /// Warn about every problem found
+    pub fn log(&self, netlist: &Netlist) {
+        let describe_net = |net_idx: u32| match netlist.net_name(net_idx as i64) {
+            Some(name) => format!("{} (net {})", name, net_idx),
+            None => format!("net {}", net_idx),
+        };
+        for net_idx in self.undriven_nets.iter() {
+            warn!("Undriven net {}", describe_net(*net_idx));
+        }
+        for net_idx in self.sinkless_nets.iter() {
+            warn!("Net {} has no sinks", describe_net(*net_idx));
+        }
+        for input in self.floating_inputs.iter() {
+            warn!(
+                "Floating input {}[{}] of cell {}: {}",
+                input.port,
+                input.bit,
+                netlist.describe_cell(input.cell),
+                match input.reason {
+                    FloatingReason::Unconnected => "not connected",
+                    FloatingReason::Undefined => "tied to an undefined constant",
+                }
+            );
+        }
+        for driver in self.overloaded_drivers.iter() {
+            warn!(
+                "Cell {} drives {} sinks on {}, but can only drive {}",
+                netlist.describe_cell(driver.cell),
+                driver.fanout,
+                describe_net(driver.net),
+                driver.limit
+            );
+        }
+    }
+
+    /// Fail if the check found anything other than nets without sinks
+    pub fn ensure_clean(&self) -> Result<()> {
+        if !self.is_clean() {
+            bail!(
+                "The netlist has {} undriven nets, {} floating inputs and {} overloaded drivers, \
+                 see the warnings above",
+                self.undriven_nets.len(),
+                self.floating_inputs.len(),
+                self.overloaded_drivers.len()
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mcpnr_common::blockers::BlockerRules;
+    use mcpnr_common::protos::mcpnr::{signal::ConstantDriver, signal::Type, PlacedDesign};
+
+    use super::{FanoutLimits, FloatingInput, FloatingReason, NetlistCheck, OverloadedDriver};
+    use crate::test_util::{cell, techlib};
+    use crate::{netlist::Netlist, structure_cache::StructureCache};
+
+    #[test]
+    fn finds_undriven_sinkless_and_floating() -> anyhow::Result<()> {
+        // The second bit of the lights is tied to an undefined constant
+        let mut lights = cell("", "MCPNR_LIGHTS", (32, 0, 0), &[("I", &[4, 0])]);
+        lights.connection.get_mut("I").unwrap().signal[1].r#type =
+            Some(Type::Constant(ConstantDriver::X as i32));
+        let design = PlacedDesign {
+            cells: vec![
+                cell("", "MCPNR_SWITCHES", (0, 0, 0), &[("O", &[1])]),
+                // Drives net 2, which nothing reads
+                cell("", "gate_not.nbt", (8, 0, 0), &[("A", &[1]), ("Y", &[2])]),
+                // Reads net 3, which nothing drives
+                cell("", "gate_not.nbt", (16, 0, 0), &[("A", &[3]), ("Y", &[4])]),
+                cell("", "gate_not.nbt", (24, 0, 0), &[("Y", &[5])]),
+                lights,
+                cell("", "MCPNR_LIGHTS", (40, 0, 0), &[("I", &[5])]),
+            ],
+            ..Default::default()
+        };
+        let structure_cache = StructureCache::new(&techlib(), &design, &BlockerRules::builtin())?;
+        let netlist = Netlist::new(&design, &structure_cache)?;
+
+        let check = NetlistCheck::new(&design, &netlist, &structure_cache, &Default::default())?;
+
+        assert_eq!(check.undriven_nets, vec![3]);
+        assert_eq!(check.sinkless_nets, vec![2]);
+        assert_eq!(
+            check.floating_inputs,
+            vec![
+                FloatingInput {
+                    cell: 3,
+                    port: "A".to_owned(),
+                    bit: 0,
+                    reason: FloatingReason::Unconnected,
+                },
+                FloatingInput {
+                    cell: 4,
+                    port: "I".to_owned(),
+                    bit: 1,
+                    reason: FloatingReason::Undefined,
+                },
+            ]
+        );
+        assert!(!check.is_clean());
+        assert!(check.ensure_clean().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn finds_overloaded_drivers() -> anyhow::Result<()> {
+        let design = PlacedDesign {
+            cells: vec![
+                cell("", "MCPNR_SWITCHES", (0, 0, 0), &[("O", &[1])]),
+                cell("", "gate_not.nbt", (8, 0, 0), &[("A", &[1]), ("Y", &[2])]),
+                cell("", "gate_not.nbt", (16, 0, 0), &[("A", &[2]), ("Y", &[3])]),
+                cell("", "gate_not.nbt", (24, 0, 0), &[("A", &[2]), ("Y", &[4])]),
+                cell("", "MCPNR_LIGHTS", (32, 0, 0), &[("I", &[3, 4])]),
+            ],
+            ..Default::default()
+        };
+        let structure_cache = StructureCache::new(&techlib(), &design, &BlockerRules::builtin())?;
+        let netlist = Netlist::new(&design, &structure_cache)?;
+
+        // The switches have a limit of their own, the cells fall back on the default
+        let limits = FanoutLimits {
+            default: Some(1),
+            cells: [("MCPNR_SWITCHES".to_owned(), 3)].into(),
+        };
+        let check = NetlistCheck::new(&design, &netlist, &structure_cache, &limits)?;
+        assert_eq!(check.undriven_nets, Vec::<u32>::new());
+        assert_eq!(
+            check.overloaded_drivers,
+            vec![OverloadedDriver {
+                net: 2,
+                cell: 1,
+                fanout: 2,
+                limit: 1,
+            }]
+        );
+        assert!(check.ensure_clean().is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,433 @@
+//! Checks on where the cells of a placed design are, run before the splat so a bad placement fails
+//! with every problem in it listed, instead of on the first block that lands outside the output
+//! part way through the splat.
+//!
+//! I/O macros can go anywhere cells can, and on the roof as well: the bottom of the tier above the
+//! top one, where lights can be seen from above. The output grows by a tier to fit them, and the
+//! router routes to them through it, but nothing else can be placed there.
+
+use std::fmt::Write;
+
+use anyhow::{anyhow, bail, Context, Result};
+use mcpnr_common::protos::mcpnr::{placed_design::Cell, signal::Type, PlacedDesign};
+use mcpnr_common::{CellExt, BLOCKS_PER_TIER};
+
+use crate::detail_routing::wire_segment::WIRE_GRID_SCALE;
+use crate::netlist::pin_metadata;
+use crate::structure_cache::StructureCache;
+
+/// Blocks of room the output leaves past the furthest pin in X and Z, for wires to turn around in
+const PIN_MARGIN: u32 = 4;
+
+/// Whether `cell` is one of the I/O macros, which are built in code rather than loaded from the
+/// techlib
+pub fn is_io_macro(cell: &Cell) -> bool {
+    matches!(cell.r#type.as_str(), "MCPNR_LIGHTS" | "MCPNR_SWITCHES")
+}
+
+/// Whether `cell` is on the roof of an output of `tiers` tiers
+fn on_roof(cell: &Cell, tiers: u32) -> bool {
+    cell.pos
+        .as_ref()
+        .is_some_and(|pos| pos.y == tiers * BLOCKS_PER_TIER)
+}
+
+/// Size of the blocks `cell` splats, or None if it's neither an I/O macro nor a structure in
+/// `structure_cache`
+pub fn footprint(cell: &Cell, structure_cache: &StructureCache) -> Result<Option<[u32; 3]>> {
+    // Each light or switch is 2x2x3 blocks, side by side along X
+    let io_macro = |param: &str| -> Result<Option<[u32; 3]>> {
+        let count = cell.get_param_i64_with_default(param, 1)?;
+        let count: u32 = count
+            .try_into()
+            .with_context(|| anyhow!("{} is {}", param, count))?;
+        Ok(Some([count * 2, 2, 3]))
+    };
+    match cell.r#type.as_str() {
+        "MCPNR_LIGHTS" => io_macro("NLIGHT"),
+        "MCPNR_SWITCHES" => io_macro("NSWITCH"),
+        ty => structure_cache
+            .get(ty)
+            .map(|structure| -> Result<_> {
+                let [x, y, z] = structure.structure.size;
+                Ok([x.try_into()?, y.try_into()?, z.try_into()?])
+            })
+            .transpose()
+            .context("Structure has a negative size"),
+    }
+}
+
+/// Size of the output for `design` on `tiers` tiers. It reaches [`PIN_MARGIN`] blocks past the
+/// furthest pin in X and Z, and at least as far as the footprint of every cell, so cells at the
+/// edge of the die aren't cut off. It's a tier taller if there are I/O macros on the roof. It's
+/// worked out from the design rather than the netlist, so the placement can be checked against it
+/// before the netlist is built. Cells of unknown types are left out, [`PlacementCheck`] reports
+/// them.
+pub fn output_extents(
+    design: &PlacedDesign,
+    structure_cache: &StructureCache,
+    tiers: u32,
+) -> Result<[u32; 3]> {
+    let (mut mx, mut mz) = (PIN_MARGIN, PIN_MARGIN);
+    for (cell_idx, cell) in design.cells.iter().enumerate() {
+        let size = match footprint(cell, structure_cache)
+            .with_context(|| anyhow!("Footprint of cell {}", cell.describe(cell_idx)))?
+        {
+            Some(size) => size,
+            None => continue,
+        };
+        let (base_x, base_z) = cell.pos.as_ref().map(|p| (p.x, p.z)).unwrap_or((0, 0));
+        mx = std::cmp::max(mx, base_x.saturating_add(size[0]));
+        mz = std::cmp::max(mz, base_z.saturating_add(size[2]));
+
+        for (port, bits) in cell.connection.iter() {
+            // Only pins on nets end up in the netlist, constants aren't routed
+            for (bit_idx, _) in bits
+                .signal
+                .iter()
+                .enumerate()
+                .filter(|(_, signal)| matches!(signal.r#type, Some(Type::Id(_))))
+            {
+                let metadata = pin_metadata(structure_cache, &cell.r#type, port, bit_idx)
+                    .with_context(|| {
+                        anyhow!(
+                            "Pin {}[{}] of cell {}",
+                            port,
+                            bit_idx,
+                            cell.describe(cell_idx)
+                        )
+                    })?;
+                let offsets =
+                    std::iter::once([metadata.offset_x, metadata.offset_y, metadata.offset_z])
+                        .chain(metadata.equivalent_pins.iter().map(|(offset, _)| *offset));
+                for [offset_x, _, offset_z] in offsets {
+                    let margin = |base: u32, offset: u32| {
+                        base.saturating_add(offset).saturating_add(PIN_MARGIN)
+                    };
+                    mx = std::cmp::max(mx, margin(base_x, offset_x));
+                    mz = std::cmp::max(mz, margin(base_z, offset_z));
+                }
+            }
+        }
+    }
+
+    let roof = design
+        .cells
+        .iter()
+        .any(|cell| is_io_macro(cell) && on_roof(cell, tiers));
+    let tiers = if roof { tiers + 1 } else { tiers };
+    Ok([mx, tiers * BLOCKS_PER_TIER, mz])
+}
+
+/// Placement problems found in a design. Cells are referred to by their index in the placed
+/// design.
+#[derive(Clone, Debug, Default)]
+pub struct PlacementCheck {
+    /// Cells without a position, in cell order
+    pub unplaced: Vec<usize>,
+    /// Cells that are neither an I/O macro nor a structure in the techlib, in cell order
+    pub unknown_types: Vec<usize>,
+    /// Cells off the routing grid, in cell order. Cells must be at an X and Z on the routing grid,
+    /// and at the bottom of a tier.
+    pub misaligned: Vec<usize>,
+    /// Cells on the roof that aren't I/O macros, in cell order
+    pub on_roof: Vec<usize>,
+    /// Cells whose footprint reaches past the output, in cell order. An output sized by
+    /// [`output_extents`] fits every cell in X and Z, so these are the cells above the top tier
+    /// and the roof.
+    pub out_of_bounds: Vec<usize>,
+    /// Pairs of cells whose footprints overlap, lower index first, in ascending order
+    pub overlaps: Vec<(usize, usize)>,
+    /// Size of the output the cells were checked against
+    pub extents: [u32; 3],
+}
+
+impl PlacementCheck {
+    /// Check the cells of `design` against an output of `extents` for `tiers` tiers
+    pub fn new(
+        design: &PlacedDesign,
+        structure_cache: &StructureCache,
+        extents: [u32; 3],
+        tiers: u32,
+    ) -> Result<Self> {
+        let mut check = Self {
+            extents,
+            ..Default::default()
+        };
+        let grid = WIRE_GRID_SCALE as u32;
+
+        // Minimum and maximum (exclusive) corners of each cell that has a footprint
+        let mut boxes = Vec::with_capacity(design.cells.len());
+        for (cell_idx, cell) in design.cells.iter().enumerate() {
+            let pos = match cell.pos.as_ref() {
+                Some(pos) => [pos.x, pos.y, pos.z],
+                None => {
+                    check.unplaced.push(cell_idx);
+                    continue;
+                }
+            };
+            if pos[0] % grid != 0 || pos[1] % BLOCKS_PER_TIER != 0 || pos[2] % grid != 0 {
+                check.misaligned.push(cell_idx);
+            }
+            if !is_io_macro(cell) && on_roof(cell, tiers) {
+                check.on_roof.push(cell_idx);
+            }
+            let size = match footprint(cell, structure_cache)
+                .with_context(|| anyhow!("Footprint of cell {}", cell.describe(cell_idx)))?
+            {
+                Some(size) => size,
+                None => {
+                    check.unknown_types.push(cell_idx);
+                    continue;
+                }
+            };
+            let max = [0, 1, 2].map(|axis| pos[axis].saturating_add(size[axis]));
+            if (0..3).any(|axis| max[axis] > extents[axis]) {
+                check.out_of_bounds.push(cell_idx);
+            }
+            if size.iter().all(|&s| s > 0) {
+                boxes.push((cell_idx, pos, max));
+            }
+        }
+
+        // Sweep along X, comparing each cell only with the cells it overlaps in X
+        boxes.sort_by_key(|(cell_idx, min, _)| (min[0], *cell_idx));
+        let mut active: Vec<(usize, [u32; 3], [u32; 3])> = Vec::new();
+        for (cell_idx, min, max) in boxes {
+            active.retain(|(_, _, other_max)| other_max[0] > min[0]);
+            for (other_idx, other_min, other_max) in active.iter() {
+                if (1..3).all(|axis| min[axis] < other_max[axis] && other_min[axis] < max[axis]) {
+                    check.overlaps.push((
+                        std::cmp::min(cell_idx, *other_idx),
+                        std::cmp::max(cell_idx, *other_idx),
+                    ));
+                }
+            }
+            active.push((cell_idx, min, max));
+        }
+        check.overlaps.sort();
+
+        Ok(check)
+    }
+
+    /// Number of problems found
+    pub fn problem_count(&self) -> usize {
+        self.unplaced.len()
+            + self.unknown_types.len()
+            + self.misaligned.len()
+            + self.on_roof.len()
+            + self.out_of_bounds.len()
+            + self.overlaps.len()
+    }
+
+    /// Whether every cell can be splatted where it's placed
+    pub fn is_clean(&self) -> bool {
+        self.problem_count() == 0
+    }
+
+    /// Fail if the check found anything, listing every problem in the error
+    pub fn ensure_clean(&self, design: &PlacedDesign) -> Result<()> {
+        if self.is_clean() {
+            return Ok(());
+        }
+
+        let describe = |cell_idx: usize| {
+            let cell = &design.cells[cell_idx];
+            match cell.pos.as_ref() {
+                Some(pos) => format!(
+                    "{} at ({}, {}, {})",
+                    cell.describe(cell_idx),
+                    pos.x,
+                    pos.y,
+                    pos.z
+                ),
+                None => cell.describe(cell_idx),
+            }
+        };
+        let mut listing = String::new();
+        for &cell_idx in self.unplaced.iter() {
+            writeln!(listing, "  {} isn't placed", describe(cell_idx))?;
+        }
+        for &cell_idx in self.unknown_types.iter() {
+            writeln!(
+                listing,
+                "  {} has no structure in the techlib",
+                describe(cell_idx)
+            )?;
+        }
+        for &cell_idx in self.misaligned.iter() {
+            writeln!(
+                listing,
+                "  {} isn't on the {} block routing grid at the bottom of a tier",
+                describe(cell_idx),
+                WIRE_GRID_SCALE
+            )?;
+        }
+        for &cell_idx in self.on_roof.iter() {
+            writeln!(
+                listing,
+                "  {} is on the roof, which only has room for I/O",
+                describe(cell_idx)
+            )?;
+        }
+        for &cell_idx in self.out_of_bounds.iter() {
+            writeln!(
+                listing,
+                "  {} reaches past the {}x{}x{} output",
+                describe(cell_idx),
+                self.extents[0],
+                self.extents[1],
+                self.extents[2]
+            )?;
+        }
+        for &(a, b) in self.overlaps.iter() {
+            writeln!(listing, "  {} overlaps {}", describe(a), describe(b))?;
+        }
+        bail!(
+            "The placement has {} problems:\n{}",
+            self.problem_count(),
+            listing.trim_end()
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mcpnr_common::blockers::BlockerRules;
+    use mcpnr_common::protos::mcpnr::{placed_design::Cell, PlacedDesign};
+
+    use super::{output_extents, PlacementCheck};
+    use crate::structure_cache::StructureCache;
+    use crate::test_util::{cell, techlib};
+
+    fn load_structures(design: &PlacedDesign) -> anyhow::Result<StructureCache> {
+        StructureCache::load_available(&techlib(), design, &BlockerRules::builtin())
+    }
+
+    #[test]
+    fn finds_every_problem() -> anyhow::Result<()> {
+        let design = PlacedDesign {
+            cells: vec![
+                cell("", "gate_not.nbt", (0, 0, 0), &[]),
+                cell("", "gate_not.nbt", (64, 16, 8), &[]),
+                // Overlaps the first cell
+                cell("", "gate_not.nbt", (0, 0, 2), &[]),
+                cell("", "gate_not.nbt", (3, 0, 16), &[]),
+                cell("", "gate_not.nbt", (16, 4, 16), &[]),
+                Cell {
+                    pos: None,
+                    ..cell("", "gate_not.nbt", (0, 0, 0), &[])
+                },
+                cell("", "gate_missing.nbt", (32, 0, 0), &[]),
+                // Past the end of X
+                cell("", "gate_not.nbt", (128, 0, 16), &[]),
+                // Above the top tier
+                cell("", "MCPNR_LIGHTS", (32, 32, 0), &[]),
+            ],
+            ..Default::default()
+        };
+        let structure_cache = load_structures(&design)?;
+
+        let check = PlacementCheck::new(&design, &structure_cache, [128, 32, 128], 2)?;
+
+        assert_eq!(check.unplaced, vec![5]);
+        assert_eq!(check.unknown_types, vec![6]);
+        assert_eq!(check.misaligned, vec![3, 4]);
+        assert_eq!(check.out_of_bounds, vec![7, 8]);
+        assert_eq!(check.overlaps, vec![(0, 2)]);
+        let error = format!("{:?}", check.ensure_clean(&design).unwrap_err());
+        assert!(error.contains("The placement has 7 problems"), "{}", error);
+        assert!(
+            error.contains("#6 (gate_missing.nbt) at (32, 0, 0)"),
+            "{}",
+            error
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn cells_touching_the_edges_fit() -> anyhow::Result<()> {
+        let design = PlacedDesign {
+            cells: vec![
+                cell("", "MCPNR_SWITCHES", (0, 0, 0), &[]),
+                // Right next to the switch, and right up against the far corner
+                cell("", "MCPNR_LIGHTS", (2, 0, 0), &[]),
+                cell("", "MCPNR_LIGHTS", (14, 16, 12), &[]),
+            ],
+            ..Default::default()
+        };
+        let structure_cache = load_structures(&design)?;
+
+        let check = PlacementCheck::new(&design, &structure_cache, [16, 32, 15], 2)?;
+
+        assert!(check.is_clean(), "{:?}", check);
+        check.ensure_clean(&design)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn extents_cover_pins_and_footprints() -> anyhow::Result<()> {
+        let mut design = PlacedDesign {
+            cells: vec![
+                // The switch's pin is at (10, 1, 22), and its footprint ends at (12, 2, 23)
+                cell("", "MCPNR_SWITCHES", (10, 0, 20), &[("O", &[1])]),
+                cell("", "MCPNR_LIGHTS", (0, 0, 0), &[("I", &[1])]),
+            ],
+            ..Default::default()
+        };
+        let structure_cache = load_structures(&design)?;
+        assert_eq!(output_extents(&design, &structure_cache, 1)?, [14, 16, 26]);
+
+        // Cells reaching past the last pin grow the output to fit them, but a cell above the top
+        // tier doesn't add a tier
+        design
+            .cells
+            .push(cell("", "gate_not.nbt", (30, 0, 40), &[]));
+        design.cells.push(cell("", "gate_not.nbt", (0, 16, 0), &[]));
+        let structure_cache = load_structures(&design)?;
+        let extents = output_extents(&design, &structure_cache, 1)?;
+        assert_eq!(extents, [32, 16, 46]);
+
+        let check = PlacementCheck::new(&design, &structure_cache, extents, 1)?;
+        assert_eq!(check.on_roof, vec![3]);
+        assert_eq!(check.out_of_bounds, vec![3]);
+        assert_eq!(check.problem_count(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn extents_of_an_empty_design() -> anyhow::Result<()> {
+        let design = PlacedDesign::default();
+        let structure_cache = load_structures(&design)?;
+        assert_eq!(output_extents(&design, &structure_cache, 2)?, [4, 32, 4]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn io_on_the_roof_adds_a_tier() -> anyhow::Result<()> {
+        let design = PlacedDesign {
+            cells: vec![
+                cell("", "MCPNR_SWITCHES", (0, 0, 0), &[("O", &[1])]),
+                cell("", "MCPNR_LIGHTS", (0, 16, 0), &[("I", &[1])]),
+                // Only I/O can go on the roof
+                cell("", "gate_not.nbt", (4, 16, 0), &[]),
+            ],
+            ..Default::default()
+        };
+        let structure_cache = load_structures(&design)?;
+        let extents = output_extents(&design, &structure_cache, 1)?;
+        assert_eq!(extents, [6, 32, 6]);
+
+        let check = PlacementCheck::new(&design, &structure_cache, extents, 1)?;
+        assert_eq!(check.on_roof, vec![2]);
+        assert_eq!(check.problem_count(), 1);
+        let error = format!("{:?}", check.ensure_clean(&design).unwrap_err());
+        assert!(error.contains("is on the roof"), "{}", error);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,366 @@
+//! Driver replication, an alternative to buffering high fan-out nets. Small driver cells, the
+//! inverters and buffers that typically fan a control signal out, are copied instead of having
+//! buffers put after them. The sinks of the net are split into groups of neighbours, each copy
+//! drives one group from the free space closest to it, and the original keeps the group nearest
+//! to itself. The copies share the original's inputs, so this adds a sink to each of its input
+//! nets for every copy, and those nets are looked at again in case that takes them over the
+//! threshold too.
+
+use std::collections::{HashSet, VecDeque};
+
+use anyhow::{anyhow, Result};
+use mcpnr_common::protos::mcpnr::{
+    placed_design::Cell, signal::Type, BitVector, PlacedDesign, Position, Signal,
+};
+use mcpnr_common::BLOCKS_PER_TIER;
+use tracing::warn;
+
+use crate::buffering::{cell_position, connectivity, free_spot, occupancy, PortBit};
+use crate::netlist::PinDirection;
+use crate::placement_check::footprint;
+use crate::structure_cache::StructureCache;
+
+/// Whether cells of this type are small enough to copy: one single-bit input and one single-bit
+/// output, like an inverter or a buffer
+fn replicable(ty: &str, structure_cache: &StructureCache) -> Result<bool> {
+    let structure = match structure_cache.get(ty) {
+        Some(structure) => structure,
+        None => return Ok(false),
+    };
+    let (mut inputs, mut outputs) = (0, 0);
+    for port in structure.ports() {
+        if structure.port_width(port) != Some(1) {
+            return Ok(false);
+        }
+        match structure.pin(port, 0)?.direction {
+            PinDirection::Input => inputs += 1,
+            PinDirection::Output => outputs += 1,
+        }
+    }
+    Ok(inputs == 1 && outputs == 1)
+}
+
+/// Split `sinks` into `parts` groups of neighbours of as close to the same size as they go, by
+/// cutting them in two across whichever of X and Z they're spread further along
+fn partition(mut sinks: Vec<(PortBit, [u32; 3])>, parts: usize) -> Vec<Vec<(PortBit, [u32; 3])>> {
+    if parts <= 1 || sinks.len() <= 1 {
+        return vec![sinks];
+    }
+    let spread = |axis: usize| {
+        let (min, max) = sinks
+            .iter()
+            .fold((u32::MAX, 0), |(min, max), (_, position)| {
+                (min.min(position[axis]), max.max(position[axis]))
+            });
+        max - min
+    };
+    let axis = if spread(0) >= spread(2) { 0 } else { 2 };
+    sinks.sort_by_key(|(sink, position)| (position[axis], position[2 - axis], sink.cell));
+
+    let low_parts = parts / 2;
+    let rest = sinks.split_off(sinks.len() * low_parts / parts);
+    let mut groups = partition(sinks, low_parts);
+    groups.extend(partition(rest, parts - low_parts));
+    groups
+}
+
+fn centroid(group: &[(PortBit, [u32; 3])]) -> [u32; 3] {
+    [0, 1, 2].map(|axis| {
+        (group.iter().map(|(_, p)| p[axis] as u64).sum::<u64>() / group.len() as u64) as u32
+    })
+}
+
+/// Copy the single driver of every net with more than `threshold` sinks, if it's small enough,
+/// until each copy drives at most `threshold` of them. The copies are added to the end of the
+/// design's cells, in the free space of an output of `extents` with `tiers` tiers, each named
+/// `<original>$replica$<n>` with the first `n` no other cell has. Returns the number of copies
+/// added.
+pub fn replicate_drivers(
+    design: &mut PlacedDesign,
+    structure_cache: &StructureCache,
+    threshold: usize,
+    extents: [u32; 3],
+    tiers: u32,
+) -> Result<usize> {
+    let (drivers, mut sinks) = connectivity(design, structure_cache)?;
+    let (mut occupied, mut next_net) = occupancy(design, structure_cache)?;
+    let mut names: HashSet<String> = design.cells.iter().map(|cell| cell.name.clone()).collect();
+    let mut added = 0;
+
+    let mut queue: VecDeque<i64> = drivers.keys().copied().collect();
+    while let Some(net) = queue.pop_front() {
+        // Nets with more than one driver could be driven from any of them, so they're left alone
+        let driver = match drivers[&net].as_slice() {
+            [driver] => driver,
+            _ => continue,
+        };
+        let net_sinks = sinks.remove(&net).unwrap_or_default();
+        if net_sinks.len() <= threshold
+            || !replicable(&design.cells[driver.cell].r#type, structure_cache)?
+        {
+            sinks.insert(net, net_sinks);
+            continue;
+        }
+
+        let original = design.cells[driver.cell].clone();
+        let size = footprint(&original, structure_cache)?.ok_or_else(|| {
+            anyhow!(
+                "Cell {} has a structure but no footprint",
+                original.describe(driver.cell)
+            )
+        })?;
+        let home = cell_position(&original);
+        let parts = net_sinks.len().div_ceil(threshold);
+        let positioned = net_sinks
+            .into_iter()
+            .map(|sink| {
+                let position = cell_position(&design.cells[sink.cell]);
+                (sink, position)
+            })
+            .collect();
+        let mut groups = partition(positioned, parts);
+
+        // The original stays where it is with the group closest to it
+        let nearest = (0..groups.len())
+            .min_by_key(|idx| {
+                let center = centroid(&groups[*idx]);
+                (0..3)
+                    .map(|axis| center[axis].abs_diff(home[axis]))
+                    .sum::<u32>()
+            })
+            .expect("There's at least one group");
+        let mut kept: Vec<PortBit> = groups
+            .remove(nearest)
+            .into_iter()
+            .map(|(sink, _)| sink)
+            .collect();
+
+        for group in groups {
+            let center = centroid(&group);
+            let tier = (center[1] / BLOCKS_PER_TIER).min(tiers.saturating_sub(1));
+            let min = match free_spot(
+                [center[0], center[2]],
+                tier * BLOCKS_PER_TIER,
+                size,
+                &occupied,
+                extents,
+            ) {
+                Some(min) => min,
+                None => {
+                    warn!(
+                        "No room for a copy of {} on tier {}, its sinks stay on net {}",
+                        original.describe(driver.cell),
+                        tier,
+                        net
+                    );
+                    kept.extend(group.into_iter().map(|(sink, _)| sink));
+                    continue;
+                }
+            };
+            occupied.push((min, [0, 1, 2].map(|axis| min[axis] + size[axis])));
+
+            let replicated = next_net;
+            next_net += 1;
+            for (sink, _) in group {
+                design.cells[sink.cell]
+                    .connection
+                    .get_mut(&sink.port)
+                    .expect("Sinks are found from the connections")
+                    .signal[sink.bit] = Signal {
+                    r#type: Some(Type::Id(replicated)),
+                };
+            }
+
+            let mut connection = original.connection.clone();
+            connection.insert(
+                driver.port.clone(),
+                BitVector {
+                    signal: vec![Signal {
+                        r#type: Some(Type::Id(replicated)),
+                    }],
+                },
+            );
+            // The copy's inputs count towards the fan-out of its input nets, which may have been
+            // under the threshold when they were looked at
+            for (port, bits) in connection.iter() {
+                if *port == driver.port {
+                    continue;
+                }
+                for (bit, signal) in bits.signal.iter().enumerate() {
+                    if let Some(Type::Id(input_net)) = signal.r#type {
+                        sinks.entry(input_net).or_default().push(PortBit {
+                            cell: design.cells.len(),
+                            port: port.clone(),
+                            bit,
+                        });
+                        if drivers.contains_key(&input_net) && !queue.contains(&input_net) {
+                            queue.push_back(input_net);
+                        }
+                    }
+                }
+            }
+
+            // Copies from an earlier run may already have taken the first few names
+            let name = (0..)
+                .map(|idx| format!("{}$replica${}", original.name, idx))
+                .find(|name| !names.contains(name))
+                .expect("Some replica name is free");
+            names.insert(name.clone());
+            let [x, y, z] = min;
+            design.cells.push(Cell {
+                name,
+                pos: Some(Position { x, y, z }),
+                connection,
+                ..original.clone()
+            });
+            added += 1;
+        }
+        sinks.insert(net, kept);
+    }
+
+    Ok(added)
+}
+
+#[cfg(test)]
+mod test {
+    use mcpnr_common::blockers::BlockerRules;
+    use mcpnr_common::protos::mcpnr::PlacedDesign;
+
+    use super::replicate_drivers;
+    use crate::netlist::Netlist;
+    use crate::placement_check::PlacementCheck;
+    use crate::structure_cache::StructureCache;
+    use crate::test_util::{cell, techlib};
+
+    #[test]
+    fn copies_drive_the_sinks_near_them() -> anyhow::Result<()> {
+        // An inverter driving a cluster of sinks next to it and another far off
+        let mut cells = vec![
+            cell("in", "MCPNR_SWITCHES", (0, 0, 0), &[("O", &[1])]),
+            cell(
+                "enable",
+                "gate_not.nbt",
+                (8, 0, 0),
+                &[("A", &[1]), ("Y", &[2])],
+            ),
+        ];
+        for idx in 0..3 {
+            let x = 16 + idx * 8;
+            cells.push(cell("near", "gate_not.nbt", (x, 0, 0), &[("A", &[2])]));
+            cells.push(cell("far", "gate_not.nbt", (x, 0, 40), &[("A", &[2])]));
+        }
+        let mut design = PlacedDesign {
+            cells,
+            ..Default::default()
+        };
+        let structure_cache = StructureCache::new(&techlib(), &design, &BlockerRules::builtin())?;
+        let extents = [48, 16, 56];
+
+        assert_eq!(
+            replicate_drivers(&mut design, &structure_cache, 3, extents, 1)?,
+            1
+        );
+        assert_eq!(design.cells.len(), 9);
+        let copy = &design.cells[8];
+        assert_eq!(copy.name, "enable$replica$0");
+        assert!(copy.pos.as_ref().unwrap().z >= 24, "{:?}", copy.pos);
+        PlacementCheck::new(&design, &structure_cache, extents, 1)?.ensure_clean(&design)?;
+
+        let netlist = Netlist::new(&design, &structure_cache)?;
+        let mut fanouts: Vec<usize> = netlist.iter_nets().map(|(_, net)| net.fanout()).collect();
+        fanouts.sort();
+        // Both copies hang off the input, and each drives one cluster
+        assert_eq!(fanouts, vec![2, 3, 3]);
+        for cell in design.cells.iter().filter(|cell| cell.name == "far") {
+            let net = cell.connection["A"].signal[0].r#type.clone();
+            assert_eq!(net, copy.connection["Y"].signal[0].r#type);
+        }
+
+        // Nothing is over the threshold any more
+        assert_eq!(
+            replicate_drivers(&mut design, &structure_cache, 3, extents, 1)?,
+            0
+        );
+
+        // Each inverter can be copied down to one sink apiece, but the switches driving all of
+        // them can't be copied at all
+        assert_eq!(
+            replicate_drivers(&mut design, &structure_cache, 1, extents, 1)?,
+            4
+        );
+        let netlist = Netlist::new(&design, &structure_cache)?;
+        let mut fanouts: Vec<usize> = netlist.iter_nets().map(|(_, net)| net.fanout()).collect();
+        fanouts.sort();
+        assert_eq!(fanouts, vec![1, 1, 1, 1, 1, 1, 6]);
+        // The new copies of the original carry on from the names the first run took
+        let replicas: Vec<&str> = design.cells[8..]
+            .iter()
+            .map(|cell| cell.name.as_str())
+            .collect();
+        assert_eq!(
+            replicas,
+            vec![
+                "enable$replica$0",
+                "enable$replica$1",
+                "enable$replica$2",
+                "enable$replica$0$replica$0",
+                "enable$replica$0$replica$1"
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn copies_take_their_input_nets_over_the_threshold() -> anyhow::Result<()> {
+        // "buffer" drives only "enable" at first, so it's under the threshold until the copies of
+        // "enable" hang off its output too
+        let mut cells = vec![
+            cell("in", "MCPNR_SWITCHES", (0, 0, 0), &[("O", &[1])]),
+            cell(
+                "buffer",
+                "gate_not.nbt",
+                (8, 0, 0),
+                &[("A", &[1]), ("Y", &[2])],
+            ),
+            cell(
+                "enable",
+                "gate_not.nbt",
+                (16, 0, 0),
+                &[("A", &[2]), ("Y", &[3])],
+            ),
+        ];
+        for z in [0, 40, 80] {
+            cells.push(cell("sink", "gate_not.nbt", (24, 0, z), &[("A", &[3])]));
+            cells.push(cell("sink", "gate_not.nbt", (32, 0, z), &[("A", &[3])]));
+        }
+        let mut design = PlacedDesign {
+            cells,
+            ..Default::default()
+        };
+        let structure_cache = StructureCache::new(&techlib(), &design, &BlockerRules::builtin())?;
+        let extents = [48, 16, 96];
+
+        assert_eq!(
+            replicate_drivers(&mut design, &structure_cache, 2, extents, 1)?,
+            3
+        );
+        assert!(design
+            .cells
+            .iter()
+            .any(|cell| cell.name == "buffer$replica$0"));
+        PlacementCheck::new(&design, &structure_cache, extents, 1)?.ensure_clean(&design)?;
+
+        let netlist = Netlist::new(&design, &structure_cache)?;
+        for (net_idx, net) in netlist.iter_nets() {
+            assert!(
+                net.fanout() <= 2,
+                "Net {} has {} sinks",
+                net_idx,
+                net.fanout()
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -1,7 +1,7 @@
 use crate::RouteId;
 use anyhow::Result;
-use log::debug;
 use std::{collections::BinaryHeap, fmt::Display};
+use tracing::debug;
 
 #[cfg(test)]
 mod tests;
@@ -98,7 +98,7 @@ impl Router2D {
             }
         }
 
-        log::info!("Begin routing from {} to {}", start, end);
+        tracing::info!("Begin routing from {} to {}", start, end);
 
         // TODO: use some sort of inline marker to avoid needing to clear the full grid on every
         // pass
@@ -3,7 +3,7 @@ use anyhow::Error;
 use super::*;
 
 fn init(size_x: u32, size_y: u32) -> Router2D {
-    let _ = env_logger::builder().is_test(true).try_init();
+    let _ = tracing_subscriber::fmt().with_test_writer().try_init();
     Router2D::new(size_x, size_y)
 }
 
@@ -27,7 +27,7 @@ fn print_router_grid(router: &Router2D) -> Result<()> {
                 GridCell::Blocked => buf.push_str("B "),
             }
         }
-        log::info!("{}", buf);
+        tracing::info!("{}", buf);
     }
     Ok(())
 }
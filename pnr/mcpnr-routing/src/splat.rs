@@ -2,14 +2,55 @@
 
 use anyhow::{anyhow, Context, Result};
 use mcpnr_common::{
-    block_storage::{Block, BlockStorage, BlockTypeIndex, PropertyValue},
+    block_storage::{Block, BlockStorage, BlockTypeIndex},
+    io_macros,
     protos::mcpnr::placed_design::Cell,
-    CellExt,
+    CellExt, BLOCKS_PER_TIER,
 };
 use std::collections::HashMap;
 
+use crate::config::{BorderStyle, MarkerConfig, TierMarkers};
+use crate::detail_routing::ALL_LAYERS;
 use crate::structure_cache::StructureCache;
 
+fn cell_base(cell: &Cell) -> (u32, u32, u32) {
+    cell.pos
+        .as_ref()
+        .map(|p| (p.x, p.y, p.z))
+        .unwrap_or((0, 0, 0))
+}
+
+/// Colors of Minecraft's dyes, which the wool and stained glass of the border and tier markers
+/// come in
+pub const DYE_COLORS: [&str; 16] = [
+    "white",
+    "orange",
+    "magenta",
+    "light_blue",
+    "yellow",
+    "lime",
+    "pink",
+    "gray",
+    "light_gray",
+    "cyan",
+    "purple",
+    "blue",
+    "brown",
+    "green",
+    "red",
+    "black",
+];
+
+fn on_edge(extents: [u32; 3], x: u32, z: u32) -> bool {
+    x == 0 || z == 0 || x == extents[0] - 1 || z == extents[2] - 1
+}
+
+/// Whether a position is on the first color of a checkerboard. Pairs of blocks alternate, so the
+/// pattern lines up with the routing grid.
+fn checker(a: u32, b: u32) -> bool {
+    ((a / 2) + (b / 2)) & 1 == 0
+}
+
 pub struct Splatter<'a> {
     structure_cache: &'a StructureCache,
     common_blocks: HashMap<String, BlockTypeIndex>,
@@ -17,90 +58,12 @@ pub struct Splatter<'a> {
 
 impl<'a> Splatter<'a> {
     pub fn new(o: &mut BlockStorage, structure_cache: &'a StructureCache) -> Self {
-        let mut common_blocks: HashMap<_, _> = [
-            ("air", Block::new("minecraft:air".to_owned())),
-            ("calcite", Block::new("minecraft:calcite".to_owned())),
-            (
-                "redstone_lamp",
-                Block::new("minecraft:redstone_lamp".to_owned()),
-            ),
-            (
-                "switch",
-                Block {
-                    name: "minecraft:lever".to_owned(),
-                    properties: Some(
-                        [
-                            ("face".to_owned(), PropertyValue::String("wall".to_owned())),
-                            (
-                                "facing".to_owned(),
-                                PropertyValue::String("north".to_owned()),
-                            ),
-                        ]
-                        .into_iter()
-                        .collect(),
-                    ),
-                },
-            ),
-            (
-                "repeater_z-",
-                Block {
-                    name: "minecraft:repeater".to_owned(),
-                    properties: Some(
-                        [(
-                            "facing".to_owned(),
-                            PropertyValue::String("south".to_owned()),
-                        )]
-                        .into_iter()
-                        .collect(),
-                    ),
-                },
-            ),
-            (
-                "sign_z-",
-                Block {
-                    name: "minecraft:oak_sign".to_owned(),
-                    properties: Some(
-                        [("rotation".to_owned(), PropertyValue::Byte(0))]
-                            .into_iter()
-                            .collect(),
-                    ),
-                },
-            ),
-            (
-                "sign_z+",
-                Block {
-                    name: "minecraft:oak_sign".to_owned(),
-                    properties: Some(
-                        [("rotation".to_owned(), PropertyValue::Byte(8))]
-                            .into_iter()
-                            .collect(),
-                    ),
-                },
-            ),
-        ]
-        .into_iter()
-        .map(|(k, v)| (k.to_owned(), o.add_new_block_type(v)))
-        .collect();
-
-        const COLORS: [&str; 16] = [
-            "white",
-            "orange",
-            "magenta",
-            "light_blue",
-            "yellow",
-            "lime",
-            "pink",
-            "gray",
-            "light_gray",
-            "cyan",
-            "purple",
-            "blue",
-            "brown",
-            "green",
-            "red",
-            "black",
-        ];
-        for color in COLORS {
+        let mut common_blocks: HashMap<_, _> = [("air", Block::new("minecraft:air".to_owned()))]
+            .into_iter()
+            .map(|(k, v)| (k.to_owned(), o.add_new_block_type(v)))
+            .collect();
+
+        for color in DYE_COLORS {
             // wool version
             let mut name = "wool_".to_owned();
             name.push_str(color);
@@ -127,91 +90,96 @@ impl<'a> Splatter<'a> {
         }
     }
 
-    pub fn draw_border(&self, o: &mut BlockStorage) -> Result<()> {
-        let extents = o.extents().clone();
-        let wool_black = self
-            .get_common_block("wool_black")
-            .context("Look up black wool")?;
-        {
-            let y = extents[1] - 1;
-            for x in 0..extents[0] {
-                if ((x / 2) + (y / 2)) % 2 == 1 {
-                    continue;
+    /// Clear the output to air and draw the border `markers` asks for, a ring of wool around the
+    /// top of the output marking the edges of the die. It's left out above the I/O macros, given
+    /// by the minimum and maximum (exclusive) corners in `io`, so it shows which parts of the edges
+    /// the I/O is on.
+    pub fn draw_border(
+        &self,
+        o: &mut BlockStorage,
+        io: &[([u32; 3], [u32; 3])],
+        markers: &MarkerConfig,
+    ) -> Result<()> {
+        let extents = *o.extents();
+        let air = self.get_common_block("air")?;
+        for x in 0..extents[0] {
+            for y in 0..extents[1] {
+                for z in 0..extents[2] {
+                    *(o.get_block_mut(x, y, z)?) = air;
                 }
-                *(o.get_block_mut(x, y, 0)?) = wool_black;
-                *(o.get_block_mut(x, y, extents[2] - 1)?) = wool_black;
             }
+        }
+
+        if markers.border == BorderStyle::Off {
+            return Ok(());
+        }
+        let wool = self
+            .get_common_block(&format!("wool_{}", markers.border_color))
+            .context("Look up border wool")?;
+        let above_io = |x: u32, z: u32| {
+            io.iter()
+                .any(|(min, max)| (min[0]..max[0]).contains(&x) && (min[2]..max[2]).contains(&z))
+        };
+        let y = extents[1] - 1;
+        for x in 0..extents[0] {
             for z in 0..extents[2] {
-                if ((z / 2) + (y / 2)) % 2 == 1 {
+                let along = if x == 0 || x == extents[0] - 1 { z } else { x };
+                if !on_edge(extents, x, z)
+                    || above_io(x, z)
+                    || (markers.border == BorderStyle::Checkered && !checker(along, y))
+                {
                     continue;
                 }
-                *(o.get_block_mut(0, y, z)?) = wool_black;
-                *(o.get_block_mut(extents[0] - 1, y, z)?) = wool_black;
+                *(o.get_block_mut(x, y, z)?) = wool;
             }
         }
 
-        /*
-        let tier_colors: [(BlockTypeIndex, BlockTypeIndex); 5] = [
-            // local interconnect / logic layer
-            (
-                self.get_common_block("glass_white")?,
-                self.get_common_block("glass_black")?,
-            ),
-            // Metal 1
-            (
-                self.get_common_block("glass_light_gray")?,
-                self.get_common_block("glass_gray")?,
-            ),
-            // Metal 2
-            (
-                self.get_common_block("glass_blue")?,
-                self.get_common_block("glass_light_blue")?,
-            ),
-            // Metal 3
-            (
-                self.get_common_block("glass_magenta")?,
-                self.get_common_block("glass_pink")?,
-            ),
-            // Metal 4
-            (
-                self.get_common_block("glass_red")?,
-                self.get_common_block("glass_orange")?,
-            ),
-        ];
-        for y in 0..extents[1] {
-            let (dark, light) = match y % 16 {
-                0 => tier_colors[0],
-                4 => tier_colors[1],
-                7 => tier_colors[2],
-                10 => tier_colors[3],
-                13 => tier_colors[4],
-                _ => continue,
-            };
-            for x in 0..extents[0] {
-                let xx = x / 2;
-                for z in 0..extents[2] {
-                    let zz = z / 2;
-                    let block = match (xx + zz) % 2 {
-                        0 => dark,
-                        1 => light,
-                        _ => unreachable!(),
-                    };
-
-                    *(o.get_block_mut(x, y, z)?) = block;
-                }
-            }
-        }
-        */
+        Ok(())
+    }
 
-        for x in 0..extents[0] {
-            for y in 0..extents[1] {
-                for z in 0..extents[2] {
-                    *(o.get_block_mut(x, y, z)?) = self.get_common_block("air")?;
+    /// Draw the tier markers `markers` asks for, stained glass at the bottom of each layer of each
+    /// tier, returning how many blocks were placed. Markers only go where the output is still air,
+    /// so this runs after routing: the router never sees them, and the wires and cells take the
+    /// space they need.
+    pub fn draw_tier_markers(&self, o: &mut BlockStorage, markers: &MarkerConfig) -> Result<usize> {
+        if markers.tier_markers == TierMarkers::Off {
+            return Ok(0);
+        }
+        let extents = *o.extents();
+        let air = self.get_common_block("air")?;
+        let colors = markers
+            .tier_marker_colors
+            .0
+            .iter()
+            .map(|pair| -> Result<_> {
+                Ok([
+                    self.get_common_block(&format!("glass_{}", pair[0]))?,
+                    self.get_common_block(&format!("glass_{}", pair[1]))?,
+                ])
+            })
+            .collect::<Result<Vec<_>>>()
+            .context("Look up tier marker glass")?;
+
+        let mut placed = 0;
+        for tier in 0..extents[1] / BLOCKS_PER_TIER {
+            for (layer, glass) in ALL_LAYERS.iter().zip(colors.iter()) {
+                let y = tier * BLOCKS_PER_TIER + layer.to_y_idx();
+                for x in 0..extents[0] {
+                    for z in 0..extents[2] {
+                        if markers.tier_markers == TierMarkers::Frame && !on_edge(extents, x, z) {
+                            continue;
+                        }
+                        let block = o.get_block_mut(x, y, z)?;
+                        if *block == air {
+                            *block = glass[if checker(x, z) { 0 } else { 1 }];
+                            placed += 1;
+                        }
+                    }
                 }
             }
         }
 
-        Ok(())
+        Ok(placed)
     }
 
     /// Splat a module with its minimum (x,y,z) coordinates at the provided
@@ -224,7 +192,7 @@ impl<'a> Splatter<'a> {
         } else {
             self.splat_structure_cell(cell, o)
         })
-        .with_context(|| anyhow!("While processing cell {:?}", cell))
+        .with_context(|| anyhow!("While processing {} at {:?}", cell.r#type, cell.pos))
     }
 
     fn get_common_block(&self, name: &str) -> Result<BlockTypeIndex> {
@@ -236,71 +204,12 @@ impl<'a> Splatter<'a> {
 
     fn splat_lights(&self, cell: &Cell, o: &mut BlockStorage) -> Result<()> {
         let nlights = cell.get_param_i64_with_default("NLIGHT", 1)?;
-
-        let (base_x, base_y, base_z) = cell
-            .pos
-            .as_ref()
-            .map(|p| (p.x, p.y, p.z))
-            .unwrap_or((0, 0, 0));
-
-        let b_air = self.get_common_block("air")?;
-        let b_calcite = self.get_common_block("calcite")?;
-        let b_light = self.get_common_block("redstone_lamp")?;
-        let z_repeater = self.get_common_block("repeater_z-")?;
-        let z_sign = self.get_common_block("sign_z-")?;
-
-        for light in 0..nlights {
-            let light_x = (light * 2) as u32 + base_x;
-
-            *(o.get_block_mut(light_x + 0, base_y + 0, base_z + 0)?) = b_calcite;
-            *(o.get_block_mut(light_x + 0, base_y + 1, base_z + 0)?) = b_light;
-            *(o.get_block_mut(light_x + 1, base_y + 0, base_z + 0)?) = b_calcite;
-            *(o.get_block_mut(light_x + 1, base_y + 1, base_z + 0)?) = b_calcite;
-
-            *(o.get_block_mut(light_x + 0, base_y + 0, base_z + 1)?) = b_calcite;
-            *(o.get_block_mut(light_x + 0, base_y + 1, base_z + 1)?) = z_repeater;
-            *(o.get_block_mut(light_x + 1, base_y + 0, base_z + 1)?) = b_air;
-            *(o.get_block_mut(light_x + 1, base_y + 1, base_z + 1)?) = b_air;
-
-            *(o.get_block_mut(light_x + 0, base_y + 1, base_z + 2)?) = z_sign;
-            *(o.get_block_mut(light_x + 0, base_y + 0, base_z + 2)?) = b_calcite;
-        }
-
-        Ok(())
+        io_macros::splat_lights(o, cell_base(cell), nlights)
     }
 
     fn splat_switches(&self, cell: &Cell, o: &mut BlockStorage) -> Result<()> {
         let nswitches = cell.get_param_i64_with_default("NSWITCH", 1)?;
-
-        let (base_x, base_y, base_z) = cell
-            .pos
-            .as_ref()
-            .map(|p| (p.x, p.y, p.z))
-            .unwrap_or((0, 0, 0));
-
-        let b_air = self.get_common_block("air")?;
-        let b_calcite = self.get_common_block("calcite")?;
-        let b_switch = self.get_common_block("switch")?;
-        let z_sign = self.get_common_block("sign_z+")?;
-
-        for switch in 0..nswitches {
-            let switch_x = (switch * 2) as u32 + base_x;
-
-            *(o.get_block_mut(switch_x + 0, base_y + 0, base_z + 0)?) = b_air;
-            *(o.get_block_mut(switch_x + 0, base_y + 1, base_z + 0)?) = b_switch;
-            *(o.get_block_mut(switch_x + 1, base_y + 0, base_z + 0)?) = b_air;
-            *(o.get_block_mut(switch_x + 1, base_y + 1, base_z + 0)?) = b_air;
-
-            *(o.get_block_mut(switch_x + 0, base_y + 0, base_z + 1)?) = b_calcite;
-            *(o.get_block_mut(switch_x + 0, base_y + 1, base_z + 1)?) = b_calcite;
-            *(o.get_block_mut(switch_x + 1, base_y + 0, base_z + 1)?) = b_calcite;
-            *(o.get_block_mut(switch_x + 1, base_y + 1, base_z + 1)?) = b_calcite;
-
-            *(o.get_block_mut(switch_x + 0, base_y + 1, base_z + 2)?) = z_sign;
-            *(o.get_block_mut(switch_x + 0, base_y + 0, base_z + 2)?) = b_calcite;
-        }
-
-        Ok(())
+        io_macros::splat_switches(o, cell_base(cell), nswitches)
     }
 
     fn splat_structure_cell(&self, cell: &Cell, o: &mut BlockStorage) -> Result<()> {
@@ -308,11 +217,7 @@ impl<'a> Splatter<'a> {
             .structure_cache
             .get(&cell.r#type)
             .ok_or_else(|| anyhow!("Unknown cell type {}", cell.r#type))?;
-        let (base_x, base_y, base_z) = cell
-            .pos
-            .as_ref()
-            .map(|p| (p.x, p.y, p.z))
-            .unwrap_or((0, 0, 0));
+        let (base_x, base_y, base_z) = cell_base(cell);
         for sblock in gate.structure.blocks.iter() {
             let [block_x, block_y, block_z] = sblock.pos;
             let x: u32 = (block_x + (base_x as i32)).try_into()?;
@@ -328,3 +233,142 @@ impl<'a> Splatter<'a> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use mcpnr_common::block_storage::{Block, BlockStorage};
+    use mcpnr_common::blockers::BlockerRules;
+    use mcpnr_common::protos::mcpnr::PlacedDesign;
+
+    use super::Splatter;
+    use crate::config::{BorderStyle, MarkerConfig, TierMarkerColors, TierMarkers};
+    use crate::structure_cache::StructureCache;
+
+    /// Draw the border and markers of a 16x16x8 output with I/O along its Z+ edge and a wire on
+    /// M1, and return the name of the block at each position asked for
+    fn draw(
+        border: BorderStyle,
+        tier_markers: TierMarkers,
+        positions: &[(u32, u32, u32)],
+    ) -> anyhow::Result<Vec<String>> {
+        let design = PlacedDesign::default();
+        let structure_cache = StructureCache::new("".as_ref(), &design, &BlockerRules::builtin())?;
+        let mut o = BlockStorage::new(16, 16, 8);
+        let splatter = Splatter::new(&mut o, &structure_cache);
+        let markers = MarkerConfig {
+            border,
+            border_color: "red".to_owned(),
+            tier_markers,
+            tier_marker_colors: TierMarkerColors(
+                [
+                    ["white", "black"],
+                    ["lime", "green"],
+                    ["red", "orange"],
+                    ["blue", "cyan"],
+                    ["gray", "pink"],
+                ]
+                .map(|pair| pair.map(str::to_owned)),
+            ),
+        };
+
+        splatter.draw_border(&mut o, &[([4, 0, 5], [8, 2, 8])], &markers)?;
+        // A wire already on M1, which the markers go around
+        let wire = o.add_new_block_type(Block::new("minecraft:redstone_wire".to_owned()));
+        *o.get_block_mut(8, 7, 5)? = wire;
+        splatter.draw_tier_markers(&mut o, &markers)?;
+
+        positions
+            .iter()
+            .map(|&(x, y, z)| {
+                let block = o.info_for_index(*o.get_block(x, y, z)?);
+                Ok(block.map_or_else(String::new, |block| block.name.clone()))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn checkered_border_leaves_out_io() -> anyhow::Result<()> {
+        let blocks = draw(
+            BorderStyle::Checkered,
+            TierMarkers::Off,
+            &[
+                (2, 15, 0),
+                (2, 15, 7),
+                (0, 15, 2),
+                (4, 15, 0),
+                (6, 15, 7),
+                (6, 0, 7),
+            ],
+        )?;
+        assert_eq!(
+            blocks,
+            [
+                "minecraft:red_wool",
+                "minecraft:red_wool",
+                "minecraft:red_wool",
+                "minecraft:air",
+                "minecraft:air",
+                "minecraft:air",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn border_styles() -> anyhow::Result<()> {
+        let positions = [(4, 15, 0), (0, 15, 4), (8, 15, 4)];
+        let solid = draw(BorderStyle::Solid, TierMarkers::Off, &positions)?;
+        assert_eq!(
+            solid,
+            ["minecraft:red_wool", "minecraft:red_wool", "minecraft:air"]
+        );
+        let off = draw(BorderStyle::Off, TierMarkers::Off, &positions)?;
+        assert!(
+            off.iter().all(|block| block == "minecraft:air"),
+            "{:?}",
+            off
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn tier_marker_densities() -> anyhow::Result<()> {
+        // Edge and middle of the bottom of LI and M1, a block between the layers and the wire
+        let positions = [
+            (0, 0, 0),
+            (8, 0, 4),
+            (2, 7, 0),
+            (8, 7, 4),
+            (0, 1, 0),
+            (8, 7, 5),
+        ];
+        let frame = draw(BorderStyle::Off, TierMarkers::Frame, &positions)?;
+        assert_eq!(
+            frame,
+            [
+                "minecraft:white_stained_glass",
+                "minecraft:air",
+                "minecraft:orange_stained_glass",
+                "minecraft:air",
+                "minecraft:air",
+                "minecraft:redstone_wire",
+            ]
+        );
+        let full = draw(BorderStyle::Off, TierMarkers::Full, &positions)?;
+        assert_eq!(
+            full,
+            [
+                "minecraft:white_stained_glass",
+                "minecraft:white_stained_glass",
+                "minecraft:orange_stained_glass",
+                "minecraft:red_stained_glass",
+                "minecraft:air",
+                "minecraft:redstone_wire",
+            ]
+        );
+
+        Ok(())
+    }
+}
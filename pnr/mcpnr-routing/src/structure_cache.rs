@@ -1,129 +1,173 @@
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, ensure, Context, Result};
 use itertools::Itertools;
 use mcpnr_common::{
-    block_storage::{Block, BlockStorage, BlockTypeIndex, PropertyValue},
-    minecraft_types::Structure,
+    block_storage::{BlockStorage, BlockTypeIndex},
+    blockers::BlockerRules,
+    minecraft_types::{is_structure_file, load_structure, Structure},
     protos::mcpnr::PlacedDesign,
 };
-use quartz_nbt::NbtCompound;
 use std::{collections::HashMap, path::Path};
 
+use crate::fanout::{drive_strength, REDSTONE_SIGNAL_RANGE};
 use crate::netlist::{PinDirection, PinMetadata};
 
 pub struct RoutableStructure {
     pub structure: Structure,
     pub palette_palette_map: HashMap<i32, BlockTypeIndex>,
+    /// Pins by the name on their marker, like `A` or `A[3]`
     pub pins: HashMap<String, PinMetadata>,
+    /// Names of the pins carrying each bit of each port, in bit order
+    ports: HashMap<String, Vec<String>>,
+}
+
+/// Split the name of a pin carrying one bit of a multi-bit port, like `A[3]`, in to the port name
+/// and the bit index. `None` for pins of single-bit ports.
+pub fn bus_bit(pin_name: &str) -> Option<(&str, usize)> {
+    let (port, index) = pin_name.strip_suffix(']')?.split_once('[')?;
+    Some((port, index.parse().ok()?))
 }
 
 impl RoutableStructure {
-    pub fn new(base: Structure) -> Result<Self> {
-        let pins = base
-            .blocks
-            .iter()
-            .filter_map(|block| -> Option<Result<_>> {
-                block.nbt.as_ref().map(|nbt| {
-                    fn get_text_element<'a>(
-                        nbt: &'a NbtCompound,
-                        element: &str,
-                    ) -> Result<String> {
-                        let content = nbt.get::<_, &str>(element).context("Get NBT tag")?;
-                        let content: serde_json::Value =
-                            serde_json::from_str(content).context("JSON parse")?;
-                        let content = content.as_object().ok_or_else(|| {
-                            anyhow!("JSON content root was not object, got {:?}", content)
-                        })?;
-                        let content = content.get("text").ok_or_else(|| {
-                            anyhow!("Text object was missing 'text' attribute: {:?}", content)
-                        })?;
-                        let content = content
-                            .as_str()
-                            .ok_or_else(|| anyhow!("Text object was not text, was {}", content))?;
-
-                        Ok(content.to_owned())
-                    }
-
-                    let text1 = get_text_element(&nbt, "Text1").context("Extract Text1")?;
-                    let text2 = get_text_element(&nbt, "Text2").context("Extract Text2")?;
-                    let text3 = get_text_element(&nbt, "Text3").context("Extract Text3")?;
-                    // let text4 = get_text_element(&nbt, "Text4").context("Extract Text4")?;
-
-                    let direction = match text2.as_ref() {
-                        "INPUT" => PinDirection::Input,
-                        "OUTPUT" => PinDirection::Output,
-                        _ => return Err(anyhow!("Unknown pin direction {}", text2)),
-                    };
-
-                    let sig_derating = text3
-                        .split_once("-")
-                        .map(|(_, derating)| {
-                            derating
-                                .parse::<u32>()
-                                .with_context(|| anyhow!("Convert integer {:?}", derating))
+    pub fn new(base: Structure, rules: &BlockerRules) -> Result<Self> {
+        let pin_list: Vec<(String, PinMetadata)> = base
+            .pins(rules)
+            .context("Error collecting pins")?
+            .into_iter()
+            .map(|pin| -> Result<_> {
+                Ok((
+                    pin.port,
+                    PinMetadata {
+                        offset_x: pin.pos[0]
+                            .try_into()
+                            .context(anyhow!("Converting X coordinate"))?,
+                        offset_y: pin.pos[1]
+                            .try_into()
+                            .context(anyhow!("Converting Y coordinate"))?,
+                        offset_z: pin.pos[2]
+                            .try_into()
+                            .context(anyhow!("Converting Z coordinate"))?,
+                        sig_derating: pin.sig_derating,
+                        direction: pin.direction,
+                        facing: pin.facing,
+                        equivalent_pins: Vec::new(),
+                    },
+                ))
+            })
+            .try_collect()?;
+
+        for (name, pin) in pin_list.iter() {
+            let usable = match pin.direction {
+                PinDirection::Output => drive_strength(pin.sig_derating) > 0,
+                PinDirection::Input => pin.sig_derating <= REDSTONE_SIGNAL_RANGE,
+            };
+            ensure!(
+                usable,
+                "Pin {:?} is derated by {}, which no redstone signal can satisfy",
+                name,
+                pin.sig_derating
+            );
+        }
+
+        // Several pins with the same name are equivalent outputs (the same signal brought out on
+        // more than one side of the cell). The first one found is the primary pin.
+        let mut pins: HashMap<String, PinMetadata> = HashMap::new();
+        for (name, pin) in pin_list {
+            match pins.get_mut(&name) {
+                None => {
+                    pins.insert(name, pin);
+                }
+                Some(primary) => {
+                    ensure!(
+                        primary.direction == PinDirection::Output
+                            && pin.direction == PinDirection::Output,
+                        "Port {:?} has more than one pin, which is only supported for outputs",
+                        name
+                    );
+                    ensure!(
+                        primary.sig_derating == pin.sig_derating,
+                        "Equivalent pins for port {:?} have different deratings ({} and {})",
+                        name,
+                        primary.sig_derating,
+                        pin.sig_derating
+                    );
+                    primary
+                        .equivalent_pins
+                        .push(([pin.offset_x, pin.offset_y, pin.offset_z], pin.facing));
+                }
+            }
+        }
+
+        let mut bits: HashMap<&str, Vec<Option<&String>>> = HashMap::new();
+        for name in pins.keys().sorted() {
+            let (port, bit_idx) = bus_bit(name).unwrap_or((name, 0));
+            let port_bits = bits.entry(port).or_default();
+            if port_bits.len() <= bit_idx {
+                port_bits.resize(bit_idx + 1, None);
+            }
+            ensure!(
+                port_bits[bit_idx].is_none(),
+                "Port {:?} has pins named both {:?} and {:?}",
+                port,
+                port_bits[bit_idx].unwrap(),
+                name
+            );
+            port_bits[bit_idx] = Some(name);
+        }
+        let ports = bits
+            .into_iter()
+            .map(|(port, port_bits)| -> Result<_> {
+                let names = port_bits
+                    .iter()
+                    .enumerate()
+                    .map(|(bit_idx, name)| {
+                        name.cloned().ok_or_else(|| {
+                            anyhow!("Port {:?} has no pin for bit {}", port, bit_idx)
                         })
-                        .unwrap_or(Ok(0))
-                        .with_context(|| anyhow!("Parse derating from {:?}", text3))?;
-
-                    Ok((
-                        text1.to_owned(),
-                        PinMetadata {
-                            offset_x: block.pos[0]
-                                .try_into()
-                                .context(anyhow!("Converting X coordinate"))?,
-                            offset_y: block.pos[1]
-                                .try_into()
-                                .context(anyhow!("Converting Y coordinate"))?,
-                            offset_z: block.pos[2]
-                                .try_into()
-                                .context(anyhow!("Converting Z coordinate"))?,
-                            sig_derating,
-                            direction,
-                        },
-                    ))
-                })
+                    })
+                    .try_collect()?;
+                Ok((port.to_owned(), names))
             })
-            .try_collect()
-            .context("Error collecting pins")?;
+            .try_collect()?;
 
         Ok(Self {
             structure: base,
             palette_palette_map: Default::default(),
             pins,
+            ports,
         })
     }
 
+    /// Names of the cell's ports, in no particular order
+    pub fn ports(&self) -> impl Iterator<Item = &str> {
+        self.ports.keys().map(String::as_str)
+    }
+
+    /// Number of bits of `port`, if the cell has it
+    pub fn port_width(&self, port: &str) -> Option<usize> {
+        self.ports.get(port).map(Vec::len)
+    }
+
+    /// The pin carrying bit `bit_idx` of `port`
+    pub fn pin(&self, port: &str, bit_idx: usize) -> Result<&PinMetadata> {
+        let names = self
+            .ports
+            .get(port)
+            .ok_or_else(|| anyhow!("Unknown port {:?}", port))?;
+        let name = names.get(bit_idx).ok_or_else(|| {
+            anyhow!(
+                "Port {:?} has {} bits, so there's no bit {}",
+                port,
+                names.len(),
+                bit_idx
+            )
+        })?;
+        Ok(&self.pins[name])
+    }
+
     fn build_palette_map(&mut self, output: &mut BlockStorage) -> Result<()> {
         for (idx, block) in self.structure.palette.iter().enumerate() {
-            self.palette_palette_map.insert(
-                idx as i32,
-                output.add_new_block_type(Block {
-                    name: block.name.clone(),
-                    properties: match block.properties.as_ref() {
-                        Some(c) => Some(
-                            c.inner()
-                                .iter()
-                                .map(|(k, v)| {
-                                    let v = match v {
-                                        quartz_nbt::NbtTag::Byte(ref v) => PropertyValue::Byte(*v),
-                                        quartz_nbt::NbtTag::String(ref s) => {
-                                            PropertyValue::String(s.to_owned())
-                                        }
-                                        _ => {
-                                            return Err(anyhow!(
-                                                "Unsupported property tag in mapping {:?}",
-                                                v
-                                            ))
-                                        }
-                                    };
-                                    Ok((k.to_owned(), v))
-                                })
-                                .try_collect()
-                                .with_context(|| format!("While mapping block {:?}", block))?,
-                        ),
-                        None => None,
-                    },
-                }),
-            );
+            self.palette_palette_map
+                .insert(idx as i32, output.add_new_block_type(block.to_block()?));
         }
 
         Ok(())
@@ -135,33 +179,39 @@ pub struct StructureCache {
 }
 
 impl StructureCache {
-    pub fn new(base_path: &Path, design: &PlacedDesign) -> Result<Self> {
+    /// Load the structure of every cell type in `design`, finding their pins with `rules`
+    pub fn new(base_path: &Path, design: &PlacedDesign, rules: &BlockerRules) -> Result<Self> {
+        Self::load(base_path, design, rules, false)
+    }
+
+    /// Like [`Self::new`], but leaving out the cell types with no structure in `base_path` instead
+    /// of failing on the first one, so they can be reported along with everything else
+    pub fn load_available(
+        base_path: &Path,
+        design: &PlacedDesign,
+        rules: &BlockerRules,
+    ) -> Result<Self> {
+        Self::load(base_path, design, rules, true)
+    }
+
+    fn load(
+        base_path: &Path,
+        design: &PlacedDesign,
+        rules: &BlockerRules,
+        skip_missing: bool,
+    ) -> Result<Self> {
         let structures = design
             .cells
             .iter()
-            .filter_map(|cell| {
-                if cell.r#type.ends_with(".nbt") {
-                    Some(&cell.r#type)
-                } else {
-                    None
-                }
-            })
+            .map(|cell| &cell.r#type)
+            .filter(|ty| is_structure_file(ty))
+            .filter(|ty| !skip_missing || base_path.join(ty).is_file())
             .unique()
             .map(|name| -> Result<_> {
-                let nbt_cell_file = (&base_path).join(name);
-                let mut nbt_cell_file = std::fs::File::open(&nbt_cell_file).with_context(|| {
-                    format!(
-                        "Failed to open structure file {:?} for reading",
-                        nbt_cell_file
-                    )
-                })?;
-                let (cell, _) = quartz_nbt::serde::deserialize_from(
-                    &mut nbt_cell_file,
-                    quartz_nbt::io::Flavor::GzCompressed,
-                )
-                .with_context(|| format!("Failed to parse structure for {:?}", name))?;
-
-                let cell = RoutableStructure::new(cell).with_context(|| anyhow!("Failed to process cell {}", name))?;
+                let cell = load_structure(&base_path.join(name))?;
+
+                let cell = RoutableStructure::new(cell, rules)
+                    .with_context(|| anyhow!("Failed to process cell {}", name))?;
 
                 Ok((name.into(), cell))
             })
@@ -170,6 +220,18 @@ impl StructureCache {
         Ok(Self { structures })
     }
 
+    /// Load the structure of cell type `name` from `base_path` too, if it isn't already loaded,
+    /// for cells about to be added to the design
+    pub fn load_type(&mut self, base_path: &Path, name: &str, rules: &BlockerRules) -> Result<()> {
+        if !self.structures.contains_key(name) {
+            let cell = load_structure(&base_path.join(name))?;
+            let cell = RoutableStructure::new(cell, rules)
+                .with_context(|| anyhow!("Failed to process cell {}", name))?;
+            self.structures.insert(name.to_owned(), cell);
+        }
+        Ok(())
+    }
+
     pub fn build_palette_maps(&mut self, output: &mut BlockStorage) -> Result<()> {
         for (name, structure) in self.structures.iter_mut() {
             structure
@@ -182,4 +244,74 @@ impl StructureCache {
     pub fn get(&self, name: &str) -> Option<&RoutableStructure> {
         self.structures.get(name)
     }
+
+    /// Newest Minecraft data version the structures were saved with, if there are any
+    pub fn data_version(&self) -> Option<i32> {
+        self.structures
+            .values()
+            .map(|s| s.structure.data_version)
+            .max()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mcpnr_common::blockers::BlockerRules;
+    use mcpnr_common::minecraft_types::{PaletteBlock, Structure, StructureBlock};
+    use quartz_nbt::compound;
+
+    use super::{bus_bit, RoutableStructure};
+
+    /// A cell with a sign marking a pin for each of `ports`
+    fn structure(ports: &[&str]) -> Structure {
+        let text = |text: &str| format!("{{\"text\":\"{}\"}}", text);
+        Structure {
+            data_version: 0,
+            size: [ports.len() as i32, 1, 1],
+            palette: vec![PaletteBlock {
+                name: "minecraft:birch_wall_sign".to_owned(),
+                properties: Some(compound! { "facing": "north" }),
+            }],
+            blocks: ports
+                .iter()
+                .enumerate()
+                .map(|(x, port)| StructureBlock {
+                    state: 0,
+                    pos: [x as i32, 0, 0],
+                    nbt: Some(compound! {
+                        "id": "minecraft:sign",
+                        "Text1": text(port),
+                        "Text2": text(if port.starts_with('Y') { "OUTPUT" } else { "INPUT" }),
+                        "Text3": text(""),
+                    }),
+                })
+                .collect(),
+            entities: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn indexed_pins_make_up_a_port() -> anyhow::Result<()> {
+        assert_eq!(bus_bit("A[12]"), Some(("A", 12)));
+        assert_eq!(bus_bit("A"), None);
+        assert_eq!(bus_bit("A[x]"), None);
+
+        let rules = BlockerRules::builtin();
+        let cell = RoutableStructure::new(structure(&["A[1]", "A[0]", "B", "Y[0]"]), &rules)?;
+        assert_eq!(cell.port_width("A"), Some(2));
+        assert_eq!(cell.port_width("B"), Some(1));
+        assert_eq!(cell.port_width("Y"), Some(1));
+        assert_eq!(cell.port_width("C"), None);
+        assert_eq!(cell.pin("A", 0)?.offset_x, 1);
+        assert_eq!(cell.pin("A", 1)?.offset_x, 0);
+        assert_eq!(cell.pin("Y", 0)?.offset_x, 3);
+        assert!(cell.pin("A", 2).is_err());
+        assert!(cell.pin("B", 1).is_err());
+
+        // Every bit needs a pin, and each bit only one name
+        assert!(RoutableStructure::new(structure(&["A[0]", "A[2]"]), &rules).is_err());
+        assert!(RoutableStructure::new(structure(&["A", "A[0]"]), &rules).is_err());
+
+        Ok(())
+    }
 }
@@ -0,0 +1,659 @@
+//! Human-readable summary of a routed design, written next to the router output so shared
+//! artifacts describe themselves.
+
+use anyhow::{anyhow, Context, Result};
+use mcpnr_common::{
+    block_storage::BlockStorage, protos::mcpnr::PlacedDesign, CellExt, BLOCKS_PER_TIER,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::Config,
+    netlist::Netlist,
+    netlist_check::{FloatingReason, NetlistCheck},
+    structure_cache::StructureCache,
+    DeferralReason, RoutingReport,
+};
+
+/// Number of connections listed in the timing section
+const SLOWEST_CONNECTIONS: usize = 10;
+
+/// Timing analysis results with the cells and nets described for people
+struct TimingSummary {
+    worst_ticks: u32,
+    max_clock: Option<f64>,
+    clock_period: Option<u32>,
+    /// Cell the slowest path starts at
+    path_start: String,
+    /// Net, wire ticks, sink cell and cell ticks of each step of the slowest path
+    path: Vec<(String, String, String, u32)>,
+    /// Net, driver cell, sink cell and wire ticks of the slowest connections
+    connections: Vec<(String, String, String, String)>,
+    connection_count: usize,
+    loops: Vec<String>,
+}
+
+/// Collected statistics about a design, ready to be rendered as markdown.
+pub struct DesignSummary {
+    name: String,
+    output_name: String,
+    creator: String,
+    /// What produced the output, described
+    provenance: Vec<String>,
+    input_file: PathBuf,
+    extents: [u32; 3],
+    tiers: u32,
+    cell_counts: BTreeMap<String, usize>,
+    cell_area: u64,
+    net_count: usize,
+    /// `None` if routing was not run
+    unrouted_nets: Option<Vec<String>>,
+    /// Nets deferred to a later pass because they ran over a routing budget
+    deferrals: Vec<String>,
+    /// Nets whose searches had to look further than usual from their pins, with how far
+    widened_searches: Vec<String>,
+    /// Problems found by the netlist check, already described
+    netlist_problems: Vec<String>,
+    /// Nets left unrouted because they have several drivers, with the cells driving them
+    multi_driver_nets: Vec<(String, Vec<String>)>,
+    /// Balanced nets, with their skew bound, routed skew, padded skew and padding repeaters
+    skew: Vec<(String, u32, u32, u32, u32)>,
+    /// `None` if routing was not run
+    timing: Option<TimingSummary>,
+    materials: Vec<(String, usize)>,
+}
+
+impl DesignSummary {
+    /// Gather statistics from the outputs of the flow. `routing` should be `None` if routing was
+    /// skipped.
+    pub fn new(
+        config: &Config,
+        design: &PlacedDesign,
+        netlist: &Netlist,
+        structure_cache: &StructureCache,
+        output: &BlockStorage,
+        netlist_check: &NetlistCheck,
+        routing: Option<&RoutingReport>,
+    ) -> Result<Self> {
+        let output_file = config.output_file.as_ref().unwrap_or(&config.input_file);
+        let name = output_file
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "design".to_owned());
+        let output_name = output_file
+            .file_name()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "design.json".to_owned());
+
+        let mut cell_counts = BTreeMap::new();
+        let mut cell_area = 0;
+        for cell in design.cells.iter() {
+            *cell_counts.entry(cell.r#type.clone()).or_insert(0) += 1;
+
+            let (sx, sz) = match cell.r#type.as_str() {
+                "MCPNR_SWITCHES" => (2 * cell.get_param_i64_with_default("NSWITCH", 1)?, 4),
+                "MCPNR_LIGHTS" => (2 * cell.get_param_i64_with_default("NLIGHT", 1)?, 2),
+                ty => {
+                    let structure = structure_cache
+                        .get(ty)
+                        .ok_or_else(|| anyhow!("Unknown cell type {:?}", ty))?;
+                    (
+                        structure.structure.size[0] as i64,
+                        structure.structure.size[2] as i64,
+                    )
+                }
+            };
+            cell_area += (sx * sz) as u64;
+        }
+
+        let mut material_counts: HashMap<_, usize> = HashMap::new();
+        for block in output.iter_block_indicies() {
+            *material_counts.entry(block).or_default() += 1;
+        }
+        let mut materials: Vec<(String, usize)> = material_counts
+            .into_iter()
+            .filter_map(|(block, count)| {
+                let info = output.info_for_index(block)?;
+                if info.name == "minecraft:air" {
+                    None
+                } else {
+                    Some((info.name.clone(), count))
+                }
+            })
+            .fold(BTreeMap::new(), |mut acc, (name, count)| {
+                // Merge block states that differ only by properties
+                *acc.entry(name).or_insert(0) += count;
+                acc
+            })
+            .into_iter()
+            .collect();
+        materials.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        let describe_net = |net_idx: u32| match netlist.net_name(net_idx as i64) {
+            Some(name) => format!("{} (net {})", name, net_idx),
+            None => format!("net {}", net_idx),
+        };
+        let unrouted_nets = routing.map(|report| {
+            report
+                .unrouted_nets
+                .iter()
+                .map(|net_idx| describe_net(*net_idx))
+                .collect()
+        });
+        let deferrals = routing
+            .map(|report| {
+                report
+                    .deferrals
+                    .iter()
+                    .map(|deferral| {
+                        let reason = match deferral.reason {
+                            DeferralReason::PassTime => "pass time budget",
+                            DeferralReason::NetExpansions => "node expansion budget",
+                        };
+                        format!(
+                            "{} in pass {} ({})",
+                            describe_net(deferral.net),
+                            deferral.pass,
+                            reason
+                        )
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let widened_searches = routing
+            .map(|report| {
+                report
+                    .widened_searches
+                    .iter()
+                    .map(|widened| format!("{} ({})", describe_net(widened.net), widened.window))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let describe_cell = |cell_idx: usize| {
+            let cell = &design.cells[cell_idx];
+            let name = if cell.name.is_empty() {
+                cell.r#type.clone()
+            } else {
+                format!("{} ({})", cell.name, cell.r#type)
+            };
+            match cell.pos {
+                Some(ref pos) => format!("{} at ({}, {}, {})", name, pos.x, pos.y, pos.z),
+                None => name,
+            }
+        };
+        let multi_driver_nets = routing
+            .map(|report| {
+                report
+                    .multi_driver_nets
+                    .iter()
+                    .map(|net| {
+                        (
+                            describe_net(net.net),
+                            net.driver_cells.iter().map(|c| describe_cell(*c)).collect(),
+                        )
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let netlist_problems = netlist_check
+            .undriven_nets
+            .iter()
+            .map(|net_idx| format!("{} has no driver", describe_net(*net_idx)))
+            .chain(netlist_check.floating_inputs.iter().map(|input| {
+                let reason = match input.reason {
+                    FloatingReason::Unconnected => "is not connected",
+                    FloatingReason::Undefined => "is tied to an undefined constant",
+                };
+                format!(
+                    "Input {}[{}] of {} {}",
+                    input.port,
+                    input.bit,
+                    describe_cell(input.cell),
+                    reason
+                )
+            }))
+            .chain(
+                netlist_check
+                    .sinkless_nets
+                    .iter()
+                    .map(|net_idx| format!("{} has no sinks", describe_net(*net_idx))),
+            )
+            .collect();
+        let describe_wire = |ticks: u32, routed: bool| {
+            if routed {
+                format!("{}", ticks)
+            } else {
+                format!("{} (estimated, unrouted)", ticks)
+            }
+        };
+        let timing = routing.map(|report| {
+            let timing = &report.timing;
+            TimingSummary {
+                worst_ticks: timing.worst_ticks,
+                max_clock: timing.max_clock(),
+                clock_period: config.clock_period,
+                path_start: timing
+                    .worst_path
+                    .first()
+                    .map(|c| describe_cell(c.driver_cell))
+                    .unwrap_or_default(),
+                path: timing
+                    .worst_path
+                    .iter()
+                    .map(|c| {
+                        (
+                            describe_net(c.net),
+                            describe_wire(c.wire_ticks, c.routed),
+                            describe_cell(c.sink_cell),
+                            timing.cell_ticks[c.sink_cell],
+                        )
+                    })
+                    .collect(),
+                connections: timing
+                    .connections
+                    .iter()
+                    .take(SLOWEST_CONNECTIONS)
+                    .map(|c| {
+                        (
+                            describe_net(c.net),
+                            describe_cell(c.driver_cell),
+                            describe_cell(c.sink_cell),
+                            describe_wire(c.wire_ticks, c.routed),
+                        )
+                    })
+                    .collect(),
+                connection_count: timing.connections.len(),
+                loops: timing.loops.iter().map(|c| describe_cell(*c)).collect(),
+            }
+        });
+        let skew = routing
+            .map(|report| {
+                report
+                    .skew
+                    .iter()
+                    .map(|skew| {
+                        (
+                            describe_net(skew.net),
+                            skew.max_skew,
+                            skew.routed_skew(),
+                            skew.padded_skew(),
+                            skew.padding_repeaters(),
+                        )
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(Self {
+            name,
+            output_name,
+            creator: design.creator.clone(),
+            provenance: output.provenance().iter().map(|p| p.describe()).collect(),
+            input_file: config.input_file.clone(),
+            extents: *output.extents(),
+            tiers: config.tiers,
+            cell_counts,
+            cell_area,
+            net_count: netlist.iter_nets().count(),
+            unrouted_nets,
+            deferrals,
+            widened_searches,
+            netlist_problems,
+            multi_driver_nets,
+            skew,
+            timing,
+            materials,
+        })
+    }
+
+    /// Fraction of the available cell area (across all tiers) covered by cells
+    pub fn utilization(&self) -> f64 {
+        let die_area = self.extents[0] as u64 * self.extents[2] as u64 * self.tiers as u64;
+        if die_area == 0 {
+            0.0
+        } else {
+            self.cell_area as f64 / die_area as f64
+        }
+    }
+
+    /// Render the summary as markdown
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        // Writing to a String can not fail, so the results are ignored throughout
+        let _ = self.render_into(&mut out);
+        out
+    }
+
+    fn render_into(&self, out: &mut String) -> std::fmt::Result {
+        writeln!(out, "# {}", self.name)?;
+        writeln!(out)?;
+        writeln!(
+            out,
+            "Generated by mcpnr-routing {} from `{}`.",
+            env!("CARGO_PKG_VERSION"),
+            self.input_file.display()
+        )?;
+        writeln!(out, "Design creator: {}", self.creator)?;
+        writeln!(out)?;
+        if !self.provenance.is_empty() {
+            writeln!(out, "Produced by:")?;
+            writeln!(out)?;
+            for provenance in self.provenance.iter() {
+                writeln!(out, "- {}", provenance)?;
+            }
+            writeln!(out)?;
+        }
+
+        writeln!(out, "## Overview")?;
+        writeln!(out)?;
+        writeln!(out, "| Property | Value |")?;
+        writeln!(out, "| --- | --- |")?;
+        writeln!(
+            out,
+            "| Size (x × y × z) | {} × {} × {} blocks |",
+            self.extents[0], self.extents[1], self.extents[2]
+        )?;
+        writeln!(
+            out,
+            "| Tiers | {} ({} blocks each) |",
+            self.tiers, BLOCKS_PER_TIER
+        )?;
+        writeln!(
+            out,
+            "| Cells | {} |",
+            self.cell_counts.values().sum::<usize>()
+        )?;
+        writeln!(out, "| Nets | {} |", self.net_count)?;
+        writeln!(out, "| Utilization | {:.1}% |", self.utilization() * 100.0)?;
+        match self.unrouted_nets {
+            Some(ref nets) => writeln!(out, "| Unrouted nets | {} |", nets.len())?,
+            None => writeln!(out, "| Unrouted nets | routing was not run |")?,
+        }
+        if !self.deferrals.is_empty() {
+            writeln!(out, "| Deferred nets | {} |", self.deferrals.len())?;
+        }
+        if !self.widened_searches.is_empty() {
+            writeln!(
+                out,
+                "| Widened searches | {} |",
+                self.widened_searches.len()
+            )?;
+        }
+        writeln!(out)?;
+
+        writeln!(out, "## Cells")?;
+        writeln!(out)?;
+        writeln!(out, "| Type | Count |")?;
+        writeln!(out, "| --- | --- |")?;
+        for (ty, count) in self.cell_counts.iter() {
+            writeln!(out, "| {} | {} |", ty, count)?;
+        }
+        writeln!(out)?;
+
+        if let Some(ref nets) = self.unrouted_nets {
+            writeln!(out, "## Unrouted nets")?;
+            writeln!(out)?;
+            if nets.is_empty() {
+                writeln!(out, "All nets were routed.")?;
+            }
+            for net in nets.iter() {
+                writeln!(out, "- {}", net)?;
+            }
+            writeln!(out)?;
+        }
+
+        if !self.deferrals.is_empty() {
+            writeln!(out, "## Deferred nets")?;
+            writeln!(out)?;
+            writeln!(
+                out,
+                "These nets ran over a routing budget and were put off until the next pass. \
+                 Deferrals in the last pass leave the net unrouted."
+            )?;
+            writeln!(out)?;
+            for deferral in self.deferrals.iter() {
+                writeln!(out, "- {}", deferral)?;
+            }
+            writeln!(out)?;
+        }
+
+        if !self.widened_searches.is_empty() {
+            writeln!(out, "## Widened searches")?;
+            writeln!(out)?;
+            writeln!(
+                out,
+                "No route for these nets was found close to their pins, so the router looked \
+                 further away. Each is listed with the widest window it needed. Nets that are \
+                 also unrouted had no route anywhere."
+            )?;
+            writeln!(out)?;
+            for widened in self.widened_searches.iter() {
+                writeln!(out, "- {}", widened)?;
+            }
+            writeln!(out)?;
+        }
+
+        if !self.netlist_problems.is_empty() {
+            writeln!(out, "## Netlist problems")?;
+            writeln!(out)?;
+            for problem in self.netlist_problems.iter() {
+                writeln!(out, "- {}", problem)?;
+            }
+            writeln!(out)?;
+        }
+
+        if !self.multi_driver_nets.is_empty() {
+            writeln!(out, "## Nets with several drivers")?;
+            writeln!(out)?;
+            writeln!(
+                out,
+                "These nets were left unrouted. Running `tribuf -logic` in Yosys turns tri-state \
+                 buffers in to logic, and `check -assert` points out conflicting assignments. \
+                 Routing with `--multi-driver merge` wires the drivers together as an OR instead."
+            )?;
+            writeln!(out)?;
+            for (net, drivers) in self.multi_driver_nets.iter() {
+                writeln!(out, "- {}, driven by {}", net, drivers.join(", "))?;
+            }
+            writeln!(out)?;
+        }
+
+        if !self.skew.is_empty() {
+            writeln!(out, "## Balanced nets")?;
+            writeln!(out)?;
+            writeln!(
+                out,
+                "Skew is in redstone ticks. The router doesn't place padding repeaters, they have \
+                 to be put in front of the sinks that see the signal early by hand."
+            )?;
+            writeln!(out)?;
+            writeln!(
+                out,
+                "| Net | Bound | Routed skew | Padded skew | Padding repeaters |"
+            )?;
+            writeln!(out, "| --- | --- | --- | --- | --- |")?;
+            for (net, bound, routed, padded, repeaters) in self.skew.iter() {
+                writeln!(
+                    out,
+                    "| {} | {} | {} | {} | {} |",
+                    net, bound, routed, padded, repeaters
+                )?;
+            }
+            writeln!(out)?;
+        }
+
+        if let Some(ref timing) = self.timing {
+            timing.render_into(out)?;
+        }
+
+        writeln!(out, "## Materials")?;
+        writeln!(out)?;
+        writeln!(out, "| Block | Count | Stacks of 64 |")?;
+        writeln!(out, "| --- | --- | --- |")?;
+        for (name, count) in self.materials.iter() {
+            writeln!(out, "| {} | {} | {} |", name, count, count.div_ceil(64))?;
+        }
+        writeln!(out)?;
+
+        writeln!(out, "## Pasting in-game")?;
+        writeln!(out)?;
+        writeln!(
+            out,
+            "The routed output is a JSON block dump. Close the world in Minecraft, then splat it \
+             in with the `routed-to-world` script shipped with MCPNR:"
+        )?;
+        writeln!(out)?;
+        writeln!(out, "```sh")?;
+        writeln!(
+            out,
+            "python3 pnr/routed-to-world/convert.py {} <path to world save> --base-x 0 --base-y 3 --base-z 0",
+            self.output_name
+        )?;
+        writeln!(out, "```")?;
+        writeln!(out)?;
+        writeln!(
+            out,
+            "The design occupies {} × {} × {} blocks starting at the base coordinates. Make sure \
+             that volume is clear before pasting.",
+            self.extents[0], self.extents[1], self.extents[2]
+        )?;
+
+        Ok(())
+    }
+}
+
+impl TimingSummary {
+    fn render_into(&self, out: &mut String) -> std::fmt::Result {
+        writeln!(out, "## Timing")?;
+        writeln!(out)?;
+        writeln!(
+            out,
+            "Delays are in redstone ticks, counting the repeaters on each wire and the delaying \
+             blocks in each cell."
+        )?;
+        writeln!(out)?;
+        writeln!(out, "| Property | Value |")?;
+        writeln!(out, "| --- | --- |")?;
+        writeln!(out, "| Slowest path | {} ticks |", self.worst_ticks)?;
+        match self.max_clock {
+            Some(hz) => writeln!(out, "| Fastest clock | {:.2} Hz |", hz)?,
+            None => writeln!(out, "| Fastest clock | unlimited |")?,
+        }
+        if let Some(period) = self.clock_period {
+            let verdict = if self.worst_ticks <= period {
+                format!("met with {} ticks to spare", period - self.worst_ticks)
+            } else {
+                format!("missed by {} ticks", self.worst_ticks - period)
+            };
+            writeln!(out, "| Clock period ({} ticks) | {} |", period, verdict)?;
+        }
+        writeln!(out)?;
+
+        if !self.path.is_empty() {
+            writeln!(out, "### Slowest path")?;
+            writeln!(out)?;
+            writeln!(out, "Starts at {}.", self.path_start)?;
+            writeln!(out)?;
+            writeln!(out, "| Net | Wire ticks | To | Cell ticks |")?;
+            writeln!(out, "| --- | --- | --- | --- |")?;
+            for (net, wire, cell, cell_ticks) in self.path.iter() {
+                writeln!(out, "| {} | {} | {} | {} |", net, wire, cell, cell_ticks)?;
+            }
+            writeln!(out)?;
+        }
+
+        if !self.connections.is_empty() {
+            writeln!(out, "### Slowest connections")?;
+            writeln!(out)?;
+            writeln!(out, "| Net | From | To | Wire ticks |")?;
+            writeln!(out, "| --- | --- | --- | --- |")?;
+            for (net, from, to, wire) in self.connections.iter() {
+                writeln!(out, "| {} | {} | {} | {} |", net, from, to, wire)?;
+            }
+            if self.connection_count > self.connections.len() {
+                writeln!(
+                    out,
+                    "\n{} more connections are faster.",
+                    self.connection_count - self.connections.len()
+                )?;
+            }
+            writeln!(out)?;
+        }
+
+        if !self.loops.is_empty() {
+            writeln!(
+                out,
+                "Combinational loops were cut at these cells to analyze them: {}.",
+                self.loops.join(", ")
+            )?;
+            writeln!(out)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Location of the summary file for a given output file
+pub fn summary_path(output_file: &Path) -> PathBuf {
+    output_file.with_extension("summary.md")
+}
+
+/// Write the summary next to `output_file`
+pub fn write_summary(output_file: &Path, summary: &DesignSummary) -> Result<()> {
+    let path = summary_path(output_file);
+    std::fs::write(&path, summary.render())
+        .with_context(|| anyhow!("Failed to write design summary {:?}", path))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    use super::DesignSummary;
+
+    fn summary() -> DesignSummary {
+        DesignSummary {
+            name: "adder".to_owned(),
+            output_name: "adder.json".to_owned(),
+            creator: "yosys".to_owned(),
+            provenance: vec![],
+            input_file: PathBuf::from("adder.pb"),
+            extents: [10, 16, 20],
+            tiers: 2,
+            cell_counts: BTreeMap::from([("MCPNR_AND".to_owned(), 3)]),
+            cell_area: 100,
+            net_count: 4,
+            unrouted_nets: Some(vec![]),
+            deferrals: vec![],
+            widened_searches: vec![],
+            netlist_problems: vec![],
+            multi_driver_nets: vec![],
+            skew: vec![],
+            timing: None,
+            materials: vec![
+                ("minecraft:redstone_wire".to_owned(), 65),
+                ("minecraft:stone".to_owned(), 64),
+                ("minecraft:repeater".to_owned(), 1),
+            ],
+        }
+    }
+
+    #[test]
+    fn utilization_covers_every_tier() {
+        assert_eq!(summary().utilization(), 0.25);
+    }
+
+    #[test]
+    fn materials_are_counted_in_whole_stacks() {
+        let rendered = summary().render();
+
+        assert!(rendered.contains("| minecraft:redstone_wire | 65 | 2 |"));
+        assert!(rendered.contains("| minecraft:stone | 64 | 1 |"));
+        assert!(rendered.contains("| minecraft:repeater | 1 | 1 |"));
+    }
+}
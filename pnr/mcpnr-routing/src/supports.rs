@@ -0,0 +1,178 @@
+//! Glass under the routed wires of the metal layers, which otherwise float in midair. Glass is
+//! transparent, so it neither cuts wires that step up or down next to it nor gets powered, and it
+//! only ever goes in to air, so nothing already in the output is touched.
+
+use std::collections::{BTreeSet, HashSet};
+
+use anyhow::Result;
+use mcpnr_common::block_storage::{Block, BlockStorage, Position};
+use mcpnr_common::BLOCKS_PER_TIER;
+
+use crate::config::SupportMode;
+use crate::detail_routing::wire_segment::WIRE_GRID_SCALE;
+use crate::detail_routing::Layer;
+
+/// Block the supports are made of. Rerouting leaves these behind instead of following them to the
+/// wires of other nets.
+pub const SUPPORT_BLOCK: &str = "minecraft:glass";
+
+/// Support the wires that were splatted at `wires`, without putting anything at `keep_clear`.
+/// Returns the number of blocks placed.
+///
+/// Pillars go under the wire in the corner of every routing grid cell and reach down to the first
+/// block that isn't air, stopping at the top of the cell layer of their tier. Floors fill the level
+/// under each metal layer that has wires, except for M0, which sits right on top of the cells: it
+/// only gets glass under its wires, and only above the cell layer.
+pub fn splat_supports(
+    mode: SupportMode,
+    o: &mut BlockStorage,
+    wires: impl IntoIterator<Item = [u32; 3]>,
+    keep_clear: &[Position],
+) -> Result<usize> {
+    let keep_clear: HashSet<Position> = keep_clear.iter().copied().collect();
+    let b_air = o.add_new_block_type(Block::new("minecraft:air".into()));
+    let b_redstone = o.add_new_block_type(Block::new("minecraft:redstone_wire".into()));
+    let b_support = o.add_new_block_type(Block::new(SUPPORT_BLOCK.into()));
+
+    let mut bases = BTreeSet::new();
+    for [x, y, z] in wires {
+        if y % BLOCKS_PER_TIER > Layer::M0.to_y_idx() && *o.get_block(x, y, z)? == b_redstone {
+            bases.insert([x, y - 1, z]);
+        }
+    }
+
+    // Nothing goes in the cell layer, which the cells of the tier have to themselves
+    let cell_ceiling = |y: u32| y - y % BLOCKS_PER_TIER + Layer::M0.to_y_idx();
+
+    let mut placed = 0;
+    let mut place = |o: &mut BlockStorage, [x, y, z]: [u32; 3]| -> Result<bool> {
+        let pos = Position::new(x as i32, y as i32, z as i32);
+        let block = o.get_block_mut(x, y, z)?;
+        if *block != b_air || keep_clear.contains(&pos) {
+            return Ok(false);
+        }
+        *block = b_support;
+        placed += 1;
+        Ok(true)
+    };
+
+    match mode {
+        SupportMode::Pillars => {
+            let scale = WIRE_GRID_SCALE as u32;
+            for [x, y, z] in bases {
+                if x % scale != 0 || z % scale != 0 {
+                    continue;
+                }
+                for y in (cell_ceiling(y)..y).rev() {
+                    if !place(o, [x, y, z])? {
+                        break;
+                    }
+                }
+            }
+        }
+        SupportMode::Floor => {
+            let mut floors = BTreeSet::new();
+            for [x, y, z] in bases {
+                let tier_y = y - y % BLOCKS_PER_TIER;
+                match Layer::from_y_idx((y % BLOCKS_PER_TIER) as i32)? {
+                    Layer::LI => {}
+                    Layer::M0 => {
+                        if y > cell_ceiling(y) {
+                            place(o, [x, y - 1, z])?;
+                        }
+                    }
+                    layer => {
+                        floors.insert(tier_y + layer.to_y_idx() - 1);
+                    }
+                }
+            }
+            let [size_x, _, size_z] = *o.extents();
+            for y in floors {
+                for x in 0..size_x {
+                    for z in 0..size_z {
+                        place(o, [x, y, z])?;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(placed)
+}
+
+#[cfg(test)]
+mod test {
+    use mcpnr_common::block_storage::{Block, BlockStorage, Position};
+
+    use super::splat_supports;
+    use crate::config::SupportMode;
+
+    /// A single block of wire on M1
+    fn wire_on_m1() -> anyhow::Result<BlockStorage> {
+        let mut o = BlockStorage::new(4, 16, 4);
+        let calcite = o.add_new_block_type(Block::new("minecraft:calcite".to_owned()));
+        let wire = o.add_new_block_type(Block::new("minecraft:redstone_wire".to_owned()));
+        *o.get_block_mut(2, 7, 2)? = calcite;
+        *o.get_block_mut(2, 8, 2)? = wire;
+        Ok(o)
+    }
+
+    #[test]
+    fn pillars_stop_at_blocks_kept_clear() -> anyhow::Result<()> {
+        let mut o = wire_on_m1()?;
+        let placed = splat_supports(
+            SupportMode::Pillars,
+            &mut o,
+            [[2, 7, 2], [2, 8, 2]],
+            &[Position::new(2, 5, 2)],
+        )?;
+        assert_eq!(placed, 1);
+        let glass = o.get_block(2, 6, 2)?;
+        assert_eq!(o.info_for_index(*glass).unwrap().name, "minecraft:glass");
+
+        Ok(())
+    }
+
+    #[test]
+    fn pillars_stop_at_the_cell_layer() -> anyhow::Result<()> {
+        let mut o = wire_on_m1()?;
+        let placed = splat_supports(SupportMode::Pillars, &mut o, [[2, 8, 2]], &[])?;
+        assert_eq!(placed, 3);
+        let name = |y| -> anyhow::Result<String> {
+            Ok(o.info_for_index(*o.get_block(2, y, 2)?)
+                .unwrap()
+                .name
+                .clone())
+        };
+        assert_eq!(name(4)?, "minecraft:glass");
+        assert_eq!(name(3)?, "minecraft:air");
+
+        Ok(())
+    }
+
+    #[test]
+    fn nothing_goes_under_m0() -> anyhow::Result<()> {
+        for mode in [SupportMode::Pillars, SupportMode::Floor] {
+            let mut o = BlockStorage::new(4, 16, 4);
+            let calcite = o.add_new_block_type(Block::new("minecraft:calcite".to_owned()));
+            let wire = o.add_new_block_type(Block::new("minecraft:redstone_wire".to_owned()));
+            *o.get_block_mut(2, 4, 2)? = calcite;
+            *o.get_block_mut(2, 5, 2)? = wire;
+
+            assert_eq!(splat_supports(mode, &mut o, [[2, 5, 2]], &[])?, 0);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn floors_fill_the_level_under_the_layer() -> anyhow::Result<()> {
+        let mut o = wire_on_m1()?;
+        let placed = splat_supports(SupportMode::Floor, &mut o, [[2, 8, 2]], &[])?;
+        assert_eq!(placed, 16);
+        let glass = o.get_block(0, 6, 3)?;
+        assert_eq!(o.info_for_index(*glass).unwrap().name, "minecraft:glass");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,34 @@
+//! Helpers shared by the unit tests
+
+use std::path::{Path, PathBuf};
+
+use mcpnr_common::protos::mcpnr::{placed_design::Cell, signal::Type, BitVector, Position, Signal};
+
+/// The standard cell structures of the techlib
+pub fn techlib() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../../yosys-synth_mc/techlib/structures")
+}
+
+/// A cell named `name` of type `ty` placed at `pos`, with each port connected to its nets, one bit
+/// per net
+pub fn cell(name: &str, ty: &str, pos: (u32, u32, u32), connections: &[(&str, &[i64])]) -> Cell {
+    let (x, y, z) = pos;
+    Cell {
+        name: name.to_owned(),
+        r#type: ty.to_owned(),
+        pos: Some(Position { x, y, z }),
+        connection: connections
+            .iter()
+            .map(|(port, nets)| {
+                let signal = nets
+                    .iter()
+                    .map(|net| Signal {
+                        r#type: Some(Type::Id(*net)),
+                    })
+                    .collect();
+                (port.to_string(), BitVector { signal })
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
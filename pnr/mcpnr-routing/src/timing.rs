@@ -0,0 +1,292 @@
+//! Estimate how long signals take to get through the routed design, in redstone ticks.
+//!
+//! Every connection from a driver pin to a sink pin is charged the repeaters its wire needs, and
+//! every cell the delaying blocks in its structure. Paths start at the switches and at register
+//! outputs, and end at the lights and register inputs. The slowest of them bounds how fast the
+//! design can be clocked.
+
+use anyhow::{anyhow, Result};
+use mcpnr_common::protos::mcpnr::PlacedDesign;
+use std::collections::VecDeque;
+
+use crate::{
+    detail_routing::wire_segment::WIRE_GRID_SCALE,
+    fanout,
+    netlist::{Netlist, Pin},
+    structure_cache::StructureCache,
+};
+
+/// Game speed, for turning tick counts into something users can compare against their clock.
+pub const REDSTONE_TICKS_PER_SECOND: f64 = 10.0;
+
+/// Input ports that mark a cell as a register. Paths end at a register's inputs and start again at
+/// its outputs.
+const CLOCK_PORTS: [&str; 2] = ["C", "CLK"];
+
+/// Delay of the wire from a driver pin to one of its sinks
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Connection {
+    pub net: u32,
+    pub driver_cell: usize,
+    pub sink_cell: usize,
+    /// Ticks spent in the repeaters along the wire
+    pub wire_ticks: u32,
+    /// Whether `wire_ticks` comes from the routed wire, rather than the distance between the pins
+    /// because the sink isn't routed.
+    pub routed: bool,
+}
+
+/// Results of timing analysis over a design
+#[derive(Clone, Debug, Default)]
+pub struct TimingReport {
+    /// Every driver to sink connection, slowest first
+    pub connections: Vec<Connection>,
+    /// The connections making up the slowest path, from its start to its end
+    pub worst_path: Vec<Connection>,
+    /// Ticks from the start of the slowest path to its end, cell delays included
+    pub worst_ticks: u32,
+    /// Delay of each cell in the design
+    pub cell_ticks: Vec<u32>,
+    /// Cells where a combinational loop was cut so the cells could be put in order, in ascending
+    /// order
+    pub loops: Vec<usize>,
+}
+
+impl TimingReport {
+    /// Fastest clock the slowest path can keep up with, in Hz. `None` if no path takes any time.
+    pub fn max_clock(&self) -> Option<f64> {
+        if self.worst_ticks == 0 {
+            None
+        } else {
+            Some(REDSTONE_TICKS_PER_SECOND / self.worst_ticks as f64)
+        }
+    }
+}
+
+/// Ticks a signal takes between two pins, going by the distance between them.
+fn estimate_wire_ticks(driver: &Pin, sink: &Pin) -> u32 {
+    let blocks = driver.x.abs_diff(sink.x) + driver.y.abs_diff(sink.y) + driver.z.abs_diff(sink.z);
+    fanout::wire_delay(
+        blocks / WIRE_GRID_SCALE as u32,
+        driver.sig_derating,
+        sink.sig_derating,
+    )
+}
+
+/// Work out the delay of every connection and the slowest path through the design.
+/// `routed_ticks` gives the delay of the routed wire from the driver of a net to one of its
+/// sinks (by index), or `None` if that sink isn't routed.
+pub fn analyze(
+    design: &PlacedDesign,
+    netlist: &Netlist,
+    structure_cache: &StructureCache,
+    mut routed_ticks: impl FnMut(u32, usize) -> Result<Option<u32>>,
+) -> Result<TimingReport> {
+    // The I/O macros count as registers, since paths start and end at them too
+    let mut cell_ticks = Vec::with_capacity(design.cells.len());
+    let mut registers = Vec::with_capacity(design.cells.len());
+    for cell in design.cells.iter() {
+        let (ticks, register) = match cell.r#type.as_str() {
+            "MCPNR_SWITCHES" | "MCPNR_LIGHTS" => (0, true),
+            ty => {
+                let structure = structure_cache
+                    .get(ty)
+                    .ok_or_else(|| anyhow!("Unknown cell type {:?}", ty))?;
+                (
+                    structure.structure.delay_ticks(),
+                    CLOCK_PORTS
+                        .iter()
+                        .any(|port| structure.pins.contains_key(*port)),
+                )
+            }
+        };
+        cell_ticks.push(ticks);
+        registers.push(register);
+    }
+
+    let mut connections = Vec::new();
+    for (net_idx, net) in netlist.iter_nets() {
+        let net_idx = *net_idx as u32;
+        let driver = match net.iter_drivers(netlist).next() {
+            Some(driver) => driver,
+            None => continue,
+        };
+        for (sink_idx, sink) in net.iter_sinks(netlist).enumerate() {
+            let (wire_ticks, routed) = match routed_ticks(net_idx, sink_idx)? {
+                Some(ticks) => (ticks, true),
+                None => (estimate_wire_ticks(driver, sink), false),
+            };
+            connections.push(Connection {
+                net: net_idx,
+                driver_cell: driver.cell,
+                sink_cell: sink.cell,
+                wire_ticks,
+                routed,
+            });
+        }
+    }
+
+    // Order the combinational cells so each comes after everything driving it, then work out when
+    // the output of each cell settles.
+    let mut inputs: Vec<Vec<usize>> = vec![Vec::new(); cell_ticks.len()];
+    let mut pending = vec![0usize; cell_ticks.len()];
+    for (idx, connection) in connections.iter().enumerate() {
+        if !registers[connection.sink_cell] {
+            inputs[connection.sink_cell].push(idx);
+            if !registers[connection.driver_cell] {
+                pending[connection.sink_cell] += 1;
+            }
+        }
+    }
+    let mut fanout: Vec<Vec<usize>> = vec![Vec::new(); cell_ticks.len()];
+    for (idx, connection) in connections.iter().enumerate() {
+        if !registers[connection.driver_cell] && !registers[connection.sink_cell] {
+            fanout[connection.driver_cell].push(idx);
+        }
+    }
+
+    // Registers settle as soon as they're clocked, so they're all ready before anything else
+    let mut settled: Vec<Option<u32>> = (0..cell_ticks.len())
+        .map(|cell| registers[cell].then(|| cell_ticks[cell]))
+        .collect();
+    // Connection that the latest input of each cell arrives on
+    let mut latest_input: Vec<Option<usize>> = vec![None; cell_ticks.len()];
+    let mut loops = Vec::new();
+    let mut ready: VecDeque<usize> = (0..cell_ticks.len())
+        .filter(|cell| !registers[*cell] && pending[*cell] == 0)
+        .collect();
+    let mut first_unsettled = 0;
+    loop {
+        let cell = match ready.pop_front() {
+            Some(cell) => cell,
+            None => {
+                while first_unsettled < cell_ticks.len() && settled[first_unsettled].is_some() {
+                    first_unsettled += 1;
+                }
+                if first_unsettled == cell_ticks.len() {
+                    break;
+                }
+                // Everything left waits on a loop, cut it at the first cell and carry on with the
+                // inputs that have settled
+                loops.push(first_unsettled);
+                first_unsettled
+            }
+        };
+        if settled[cell].is_some() {
+            continue;
+        }
+
+        let mut arrival = 0;
+        for idx in inputs[cell].iter() {
+            let connection = &connections[*idx];
+            if let Some(driver_settled) = settled[connection.driver_cell] {
+                let input = driver_settled + connection.wire_ticks;
+                if latest_input[cell].is_none() || input > arrival {
+                    arrival = input;
+                    latest_input[cell] = Some(*idx);
+                }
+            }
+        }
+        settled[cell] = Some(arrival + cell_ticks[cell]);
+
+        for idx in fanout[cell].iter() {
+            let sink = connections[*idx].sink_cell;
+            pending[sink] -= 1;
+            if pending[sink] == 0 {
+                ready.push_back(sink);
+            }
+        }
+    }
+
+    let worst = connections
+        .iter()
+        .enumerate()
+        .filter(|(_, connection)| registers[connection.sink_cell])
+        .map(|(idx, connection)| {
+            (
+                settled[connection.driver_cell].unwrap_or(0) + connection.wire_ticks,
+                idx,
+            )
+        })
+        .max_by_key(|(ticks, idx)| (*ticks, std::cmp::Reverse(*idx)));
+
+    let mut worst_path = Vec::new();
+    let mut worst_ticks = 0;
+    if let Some((ticks, idx)) = worst {
+        worst_ticks = ticks;
+        let mut next = Some(idx);
+        // Each cell's latest input comes from a cell that settled before it, so this can't go
+        // round a loop
+        while let Some(idx) = next {
+            let connection = &connections[idx];
+            worst_path.push(connection.clone());
+            next = latest_input[connection.driver_cell];
+        }
+        worst_path.reverse();
+    }
+
+    connections.sort_by(|a, b| {
+        b.wire_ticks
+            .cmp(&a.wire_ticks)
+            .then_with(|| a.net.cmp(&b.net))
+            .then_with(|| a.sink_cell.cmp(&b.sink_cell))
+    });
+
+    Ok(TimingReport {
+        connections,
+        worst_path,
+        worst_ticks,
+        cell_ticks,
+        loops,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use mcpnr_common::blockers::BlockerRules;
+    use mcpnr_common::protos::mcpnr::PlacedDesign;
+
+    use super::analyze;
+    use crate::test_util::{cell, techlib};
+    use crate::{netlist::Netlist, structure_cache::StructureCache};
+
+    #[test]
+    fn worst_path_adds_up_wires_and_cells() -> anyhow::Result<()> {
+        let design = PlacedDesign {
+            cells: vec![
+                cell("", "MCPNR_SWITCHES", (0, 0, 0), &[("O", &[1])]),
+                cell("", "gate_not.nbt", (8, 0, 0), &[("A", &[1]), ("Y", &[2])]),
+                cell("", "gate_not.nbt", (16, 0, 0), &[("A", &[2]), ("Y", &[3])]),
+                cell("", "MCPNR_LIGHTS", (24, 0, 0), &[("I", &[3])]),
+                // Inverter feeding itself, which has no start or end
+                cell("", "gate_not.nbt", (32, 0, 0), &[("A", &[4]), ("Y", &[4])]),
+            ],
+            ..Default::default()
+        };
+        let structure_cache = StructureCache::new(&techlib(), &design, &BlockerRules::builtin())?;
+        let netlist = Netlist::new(&design, &structure_cache)?;
+
+        let timing = analyze(&design, &netlist, &structure_cache, |net, _| {
+            Ok((net != 3).then_some(net))
+        })?;
+
+        let not_ticks = timing.cell_ticks[1];
+        assert!(not_ticks > 0);
+        assert_eq!(
+            timing
+                .worst_path
+                .iter()
+                .map(|c| (c.net, c.driver_cell, c.sink_cell, c.routed))
+                .collect::<Vec<_>>(),
+            vec![(1, 0, 1, true), (2, 1, 2, true), (3, 2, 3, false)]
+        );
+        assert_eq!(
+            timing.worst_ticks,
+            1 + 2 + 2 * not_ticks + timing.worst_path[2].wire_ticks
+        );
+        assert_eq!(timing.loops, vec![4]);
+        assert_eq!(timing.connections[0].net, 4);
+
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 import amulet
 import amulet_nbt
 import argparse
+import gzip
 import json
 
 from amulet.api.errors import ChunkDoesNotExist, ChunkLoadError
@@ -73,7 +74,9 @@ class WorldWrapper:
 def main():
     config = parse_args()
 
-    with open(config.INFILE) as inf:
+    with open(config.INFILE, 'rb') as inf:
+        gzipped = inf.read(2) == b'\x1f\x8b'
+    with (gzip.open if gzipped else open)(config.INFILE, 'rt') as inf:
         in_data = json.load(inf)
 
     world = amulet.level.load_level(config.OUTPUT_WORLD)
@@ -106,7 +109,13 @@ def main():
         return amulet.Block(namespace, base_name, properties)
 
     palette = list(map(json_to_block, in_data['palette']))
-    block_data = in_data['blocks']
+    if 'runs' in in_data:
+        # Run-length encoded output, expand it back out to one entry per block
+        block_data = []
+        for (pi, length) in in_data['runs']:
+            block_data.extend([{'pi': pi}] * length)
+    else:
+        block_data = in_data['blocks']
 
     ex = in_data['extents']['x']
     ey = in_data['extents']['y']
@@ -142,6 +151,30 @@ def main():
 
                     world.add_tile_entity(entity)
 
+    # Block entities the router placed itself, like the signs labelling nets
+    for entry in in_data.get('block_entities', []):
+        (x, y, z) = entry['pos']
+        xx = x + config.base_x
+        yy = y + config.base_y
+        zz = z + config.base_z
+
+        fqn = entry['nbt']['id']
+        i = fqn.find(':')
+        entity_data = json_to_nbt(entry['nbt'])
+        entity_data = amulet_nbt.NBTFile(amulet_nbt.TAG_Compound(entity_data))
+        entity_data['x'] = amulet_nbt.TAG_Int(xx)
+        entity_data['y'] = amulet_nbt.TAG_Int(yy)
+        entity_data['z'] = amulet_nbt.TAG_Int(zz)
+        entity_data['keepPacked'] = amulet_nbt.TAG_Int(0)
+
+        entity = amulet.api.block_entity.BlockEntity(
+            fqn[:i],
+            fqn[i+1:],
+            xx, yy, zz,
+            entity_data
+        )
+
+        world.add_tile_entity(entity)
 
     world.save()
 